@@ -0,0 +1,355 @@
+//! Benchmarks of the paths performance work tends to touch (scanning, rowid lookups, varint
+//! decoding, parsing and sorting) with a record of earlier runs to compare against, as a guardrail
+//! for redesigns.
+//!
+//! ```text
+//! cargo bench --bench regression -- --save-baseline main   # record a baseline
+//! cargo bench --bench regression -- --baseline main        # compare against it
+//! ```
+//!
+//! Each benchmark's time is the median of several runs, in nanoseconds. Baselines are kept as JSON
+//! in target/bench-baselines. Comparing reports every benchmark's change against the baseline and
+//! fails if any of them is more than 10% slower. The full scan's throughput is printed too, in MB
+//! of database read per second.
+
+use anyhow::{anyhow, bail, Context, Result};
+use sqlite_starter_rust::{
+    database::Database,
+    executor::execute,
+    planner::plan_query,
+    query_parser::{parse_query, Query},
+    record::encode_record,
+    value::Value,
+    varint::{encode_varint, parse_varint},
+};
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    hint::black_box,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+/// Large enough for the single interior page to point to every leaf
+const PAGE_SIZE: usize = 8192;
+/// Enough rows for a database of a few MB, well beyond what fits in the reader's buffers
+const ROWS: u32 = 100_000;
+/// Varints decoded by the varint benchmark
+const VARINTS: usize = 100_000;
+/// Runs of each benchmark, of which the median is kept
+const RUNS: usize = 15;
+/// How much slower than its baseline a benchmark can get before it's flagged
+const REGRESSION_THRESHOLD: f64 = 0.10;
+
+fn main() -> Result<()> {
+    let mut save_baseline = None;
+    let mut baseline = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--save-baseline" => save_baseline = args.next(),
+            "--baseline" => baseline = args.next(),
+            // cargo bench passes --bench along to every bench target
+            "--bench" => {}
+            other => bail!("unknown argument: {other}"),
+        }
+    }
+
+    let bytes = build_database(ROWS);
+    let database_size = bytes.len();
+    let database = Database::open_bytes(bytes)?;
+    let schema = database.schema()?;
+    let run_query = |sql: &str| -> Result<usize> {
+        let query = parse(sql)?;
+        let plan = plan_query(&database, &schema, &query)?;
+        let rows = execute(&database, &plan, &query)?.collect::<Result<Vec<_>>>()?;
+        Ok(rows.len())
+    };
+
+    let mut results = BTreeMap::new();
+    results.insert(
+        "scan",
+        time(|| run_query("SELECT name FROM t WHERE score = 42"))?,
+    );
+    results.insert("full_scan", time(|| run_query("SELECT count(*) FROM t"))?);
+    results.insert(
+        "lookup",
+        time(|| {
+            let mut found = 0;
+            for row_id in (1..=ROWS as i64).step_by(20) {
+                found += database.find_row(2, row_id)?.is_some() as usize;
+            }
+            Ok(found)
+        })?,
+    );
+    let varints = varint_buffer(VARINTS);
+    results.insert(
+        "varint",
+        time(|| {
+            let mut position = 0;
+            let mut sum = 0i64;
+            while position < varints.len() {
+                let (value, bytes_read) = parse_varint(&varints[position..]);
+                sum = sum.wrapping_add(value);
+                position += bytes_read;
+            }
+            Ok(sum as usize)
+        })?,
+    );
+    results.insert(
+        "parse",
+        time(|| {
+            for _ in 0..1000 {
+                parse(
+                    "SELECT DISTINCT name, score * 2 AS double FROM t \
+                     WHERE score > 10 AND name LIKE 'name 1%' ORDER BY double DESC LIMIT 10",
+                )?;
+            }
+            Ok(0)
+        })?,
+    );
+    results.insert(
+        "sort",
+        time(|| run_query("SELECT name, score FROM t ORDER BY name"))?,
+    );
+
+    for (name, nanos) in &results {
+        println!("{name:<10} {:>12.3} ms", *nanos as f64 / 1e6);
+    }
+    let scan_seconds = results["full_scan"] as f64 / 1e9;
+    println!(
+        "\nfull scan of {:.1} MB: {:.1} MB/s",
+        database_size as f64 / 1e6,
+        database_size as f64 / 1e6 / scan_seconds
+    );
+
+    if let Some(name) = baseline {
+        let path = baseline_path(&name);
+        let baseline = read_results(&path)
+            .with_context(|| format!("can't read baseline {}", path.display()))?;
+        let regressions = compare(&baseline, &results);
+        if !regressions.is_empty() {
+            bail!(
+                "{} benchmark(s) regressed by more than {:.0}% against baseline {name}: {}",
+                regressions.len(),
+                REGRESSION_THRESHOLD * 100.0,
+                regressions.join(", ")
+            );
+        }
+    }
+
+    if let Some(name) = save_baseline {
+        let path = baseline_path(&name);
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(&path, write_results(&results))?;
+        println!("saved baseline {}", path.display());
+    }
+
+    Ok(())
+}
+
+fn parse(sql: &str) -> Result<Query> {
+    let (_, query) = parse_query(sql).map_err(|e| anyhow!("can't parse {sql:?}: {e}"))?;
+    Ok(query)
+}
+
+/// The median time of RUNS runs of `run`, in nanoseconds, after a warm-up run
+fn time(mut run: impl FnMut() -> Result<usize>) -> Result<u64> {
+    black_box(run()?);
+
+    let mut times: Vec<Duration> = vec![];
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        black_box(run()?);
+        times.push(start.elapsed());
+    }
+    times.sort();
+
+    Ok(times[RUNS / 2].as_nanos() as u64)
+}
+
+/// Prints each benchmark's change from its baseline, returning the names of those that got more
+/// than REGRESSION_THRESHOLD slower
+fn compare(baseline: &BTreeMap<String, u64>, results: &BTreeMap<&str, u64>) -> Vec<String> {
+    let mut regressions = vec![];
+
+    println!();
+    for (name, &nanos) in results {
+        let Some(&before) = baseline.get(*name) else {
+            println!("{name:<10} (not in baseline)");
+            continue;
+        };
+        let change = nanos as f64 / before.max(1) as f64 - 1.0;
+        let flag = if change > REGRESSION_THRESHOLD {
+            regressions.push(name.to_string());
+            "  REGRESSED"
+        } else {
+            ""
+        };
+        println!("{name:<10} {:>+8.1}%{flag}", change * 100.0);
+    }
+
+    regressions
+}
+
+fn baseline_path(name: &str) -> PathBuf {
+    let target = env::var_os("CARGO_TARGET_DIR").unwrap_or_else(|| "target".into());
+    PathBuf::from(target)
+        .join("bench-baselines")
+        .join(format!("{name}.json"))
+}
+
+/// Results as a JSON object of benchmark names to nanoseconds
+fn write_results(results: &BTreeMap<&str, u64>) -> String {
+    let fields: Vec<_> = results
+        .iter()
+        .map(|(name, nanos)| format!("  \"{name}\": {nanos}"))
+        .collect();
+
+    format!("{{\n{}\n}}\n", fields.join(",\n"))
+}
+
+/// Reads back what write_results wrote: a flat JSON object of names to whole numbers
+fn read_results(path: &PathBuf) -> Result<BTreeMap<String, u64>> {
+    let json = fs::read_to_string(path)?;
+    let Some(fields) = json
+        .trim()
+        .strip_prefix('{')
+        .and_then(|json| json.strip_suffix('}'))
+    else {
+        bail!("expected a JSON object");
+    };
+
+    fields
+        .split(',')
+        .filter(|field| !field.trim().is_empty())
+        .map(|field| {
+            let (name, nanos) = field
+                .split_once(':')
+                .ok_or_else(|| anyhow!("expected \"name\": nanoseconds, found {field:?}"))?;
+            Ok((
+                name.trim().trim_matches('"').to_string(),
+                nanos.trim().parse()?,
+            ))
+        })
+        .collect()
+}
+
+/// `count` varints of every length from one byte to nine, one after another
+fn varint_buffer(count: usize) -> Vec<u8> {
+    (0..count)
+        .flat_map(|i| encode_varint(1i64.wrapping_shl((i % 64) as u32) - 1))
+        .collect()
+}
+
+/// A database with a single table, t(id INTEGER PRIMARY KEY, name TEXT, score INTEGER), of `rows`
+/// rows. Its root is an interior page, page 2, whose children are the leaves from page 3 on.
+fn build_database(rows: u32) -> Vec<u8> {
+    let mut leaves: Vec<Vec<(i64, Vec<u8>)>> = vec![vec![]];
+    let mut leaf_size = 8;
+    for row_id in 1..=rows as i64 {
+        let record = encode_record(
+            &[
+                Value::Null,
+                Value::Text(format!("name {}", row_id * 7919 % rows as i64)),
+                Value::Integer(row_id % 100),
+            ],
+            4,
+        );
+        let cell = leaf_cell(row_id, &record);
+        if leaf_size + 2 + cell.len() > PAGE_SIZE {
+            leaves.push(vec![]);
+            leaf_size = 8;
+        }
+        leaf_size += 2 + cell.len();
+        leaves.last_mut().unwrap().push((row_id, cell));
+    }
+
+    let page_count = leaves.len() + 2;
+    let mut bytes = vec![0; page_count * PAGE_SIZE];
+    bytes[0..16].copy_from_slice(b"SQLite format 3\0");
+    bytes[16..18].copy_from_slice(&(PAGE_SIZE as u16).to_be_bytes());
+    bytes[18..24].copy_from_slice(&[1, 1, 0, 64, 32, 32]);
+    bytes[28..32].copy_from_slice(&(page_count as u32).to_be_bytes());
+    bytes[44..48].copy_from_slice(&4u32.to_be_bytes());
+    bytes[56..60].copy_from_slice(&1u32.to_be_bytes());
+
+    let schema_row = encode_record(
+        &[
+            Value::Text("table".into()),
+            Value::Text("t".into()),
+            Value::Text("t".into()),
+            Value::Integer(2),
+            Value::Text("CREATE TABLE t(id INTEGER PRIMARY KEY, name TEXT, score INTEGER)".into()),
+        ],
+        4,
+    );
+    write_page(
+        &mut bytes[..PAGE_SIZE],
+        100,
+        13,
+        &[leaf_cell(1, &schema_row)],
+        None,
+    );
+
+    // Every leaf but the last is a cell of the root, keyed by its largest rowid
+    let interior_cells: Vec<_> = leaves[..leaves.len() - 1]
+        .iter()
+        .zip(3u32..)
+        .map(|(leaf, page)| {
+            let mut cell = page.to_be_bytes().to_vec();
+            cell.extend(encode_varint(leaf.last().unwrap().0));
+            cell
+        })
+        .collect();
+    write_page(
+        &mut bytes[PAGE_SIZE..2 * PAGE_SIZE],
+        0,
+        5,
+        &interior_cells,
+        Some(page_count as u32),
+    );
+
+    for (i, leaf) in leaves.iter().enumerate() {
+        let cells: Vec<_> = leaf.iter().map(|(_, cell)| cell.clone()).collect();
+        let start = (i + 2) * PAGE_SIZE;
+        write_page(&mut bytes[start..start + PAGE_SIZE], 0, 13, &cells, None);
+    }
+
+    bytes
+}
+
+fn leaf_cell(row_id: i64, record: &[u8]) -> Vec<u8> {
+    let mut cell = encode_varint(record.len() as i64);
+    cell.extend(encode_varint(row_id));
+    cell.extend(record);
+    cell
+}
+
+/// Writes a b-tree page of `page_type` holding `cells`, whose header starts at `header_start`
+fn write_page(
+    page: &mut [u8],
+    header_start: usize,
+    page_type: u8,
+    cells: &[Vec<u8>],
+    right_most_pointer: Option<u32>,
+) {
+    let mut pointer = header_start + 8;
+    page[header_start] = page_type;
+    page[header_start + 3..header_start + 5].copy_from_slice(&(cells.len() as u16).to_be_bytes());
+    if let Some(right_most_pointer) = right_most_pointer {
+        page[header_start + 8..header_start + 12]
+            .copy_from_slice(&right_most_pointer.to_be_bytes());
+        pointer += 4;
+    }
+
+    let mut content_start = page.len();
+    for cell in cells {
+        content_start -= cell.len();
+        page[content_start..content_start + cell.len()].copy_from_slice(cell);
+        page[pointer..pointer + 2].copy_from_slice(&(content_start as u16).to_be_bytes());
+        pointer += 2;
+    }
+    assert!(pointer <= content_start, "cells don't fit on the page");
+    page[header_start + 5..header_start + 7].copy_from_slice(&(content_start as u16).to_be_bytes());
+}
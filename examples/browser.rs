@@ -0,0 +1,119 @@
+//! Queries a database file in the browser. The page, browser.html, reads the file the user picks
+//! into this module's memory, where it's opened with Database::open_bytes, and shows what each
+//! query returns. There's no file system on the web, so it's built without the fs feature:
+//!
+//! ```text
+//! cargo build --release --example browser --target wasm32-unknown-unknown --no-default-features
+//! cp target/wasm32-unknown-unknown/release/examples/browser.wasm examples/
+//! python3 -m http.server --directory examples
+//! ```
+//!
+//! and then open http://localhost:8000/browser.html.
+//!
+//! The module's exports are plain functions on pointers into its memory, so that it needs no
+//! bindings generator: the page allocates a buffer, copies bytes into it, and hands it over. What
+//! open_database and run_query have to say is left in a buffer the page reads with output_ptr and
+//! output_len.
+
+use anyhow::{anyhow, Result};
+use sqlite_starter_rust::{
+    database::Database, executor::execute, planner::plan_query, query_parser::parse_query,
+    view::expand_views,
+};
+use std::{cell::RefCell, fmt::Write};
+
+thread_local! {
+    static DATABASE: RefCell<Option<Database>> = const { RefCell::new(None) };
+    static OUTPUT: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+/// A buffer of `len` bytes for the page to copy a database or a query into, which the function
+/// it's passed to takes ownership of
+#[no_mangle]
+pub extern "C" fn allocate(len: usize) -> *mut u8 {
+    Box::into_raw(vec![0; len].into_boxed_slice()).cast()
+}
+
+/// Opens the database in the buffer, replacing the one that was open. Returns 0 with the names of
+/// its tables in the output, one per line, or 1 with the error.
+///
+/// # Safety
+///
+/// `ptr` and `len` must be a buffer from allocate, which isn't used again.
+#[no_mangle]
+pub unsafe extern "C" fn open_database(ptr: *mut u8, len: usize) -> i32 {
+    let bytes = take_buffer(ptr, len);
+    respond(Database::open_bytes(bytes).and_then(|database| {
+        let mut tables = String::new();
+        for schema in database.schema()?.iter().filter(|schema| schema.is_table()) {
+            writeln!(tables, "{}", schema.name)?;
+        }
+        DATABASE.set(Some(database));
+
+        Ok(tables)
+    }))
+}
+
+/// Runs the query in the buffer on the open database. Returns 0 with its rows in the output, one
+/// per line with columns separated by "|", or 1 with the error.
+///
+/// # Safety
+///
+/// `ptr` and `len` must be a buffer from allocate, holding UTF-8, which isn't used again.
+#[no_mangle]
+pub unsafe extern "C" fn run_query(ptr: *mut u8, len: usize) -> i32 {
+    let sql = String::from_utf8(take_buffer(ptr, len)).map_err(Into::into);
+    respond(sql.and_then(|sql| {
+        DATABASE.with_borrow(|database| {
+            let database = database.as_ref().ok_or(anyhow!("no database is open"))?;
+            query(database, &sql)
+        })
+    }))
+}
+
+/// Where the output of the last call is
+#[no_mangle]
+pub extern "C" fn output_ptr() -> *const u8 {
+    OUTPUT.with_borrow(|output| output.as_ptr())
+}
+
+/// How many bytes of output the last call left
+#[no_mangle]
+pub extern "C" fn output_len() -> usize {
+    OUTPUT.with_borrow(|output| output.len())
+}
+
+fn query(database: &Database, sql: &str) -> Result<String> {
+    let (_, query) = parse_query(sql).map_err(|err| anyhow!("can't parse {sql:?}: {err}"))?;
+    let schema = database.schema()?;
+    let query = expand_views(&schema, &query)?;
+    let plan = plan_query(database, &schema, &query)?;
+
+    let mut output = String::new();
+    for row in execute(database, &plan, &query)? {
+        let row = row?;
+        for (i, value) in row.iter().enumerate() {
+            let separator = if i == 0 { "" } else { "|" };
+            write!(output, "{separator}{value}")?;
+        }
+        output.push('\n');
+    }
+
+    Ok(output)
+}
+
+/// Takes back a buffer made by allocate
+unsafe fn take_buffer(ptr: *mut u8, len: usize) -> Vec<u8> {
+    Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr, len)).into_vec()
+}
+
+/// Leaves the output of a call for the page to read, returning its status
+fn respond(result: Result<String>) -> i32 {
+    let (status, output) = match result {
+        Ok(output) => (0, output),
+        Err(err) => (1, format!("{err:#}")),
+    };
+    OUTPUT.set(output);
+
+    status
+}
@@ -0,0 +1,33 @@
+//! Opens arbitrary bytes as a database and reads everything in it. Malformed input should make
+//! reads fail with errors: never panic, hang or run out of memory.
+//!
+//! Run with `cargo +nightly fuzz run open_and_scan`, seeding the corpus with real databases.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use sqlite_starter_rust::{database::Database, value::Value};
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(database) = Database::open_bytes(data.to_vec()) else {
+        return;
+    };
+    let Ok(schema) = database.schema() else {
+        return;
+    };
+
+    for entry in schema {
+        if entry.is_table() {
+            for record in database.table_cursor(entry.root_page) {
+                if record.is_err() {
+                    break;
+                }
+            }
+            let _ = database.find_row(entry.root_page, 1);
+            let _ = database.count_estimate(&entry.name);
+        } else {
+            let _ = database.search_index(entry.root_page, &Value::Integer(1));
+        }
+        let _ = database.btree_size(entry.root_page);
+    }
+});
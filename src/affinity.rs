@@ -0,0 +1,264 @@
+use crate::value::Value;
+use std::fmt;
+
+/// A column's type affinity: the storage class it prefers, which SQLite converts values to when
+/// they're stored in the column or compared with it.
+/// [datatype3](https://www.sqlite.org/datatype3.html#type_affinity)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Affinity {
+    Text,
+    Numeric,
+    Integer,
+    Real,
+    /// No conversion at all, for a column declared without a type
+    Blob,
+}
+
+impl Affinity {
+    /// The affinity of a column with the declared type `type_name`, by the first of SQLite's rules
+    /// that matches: INT, then CHAR, CLOB or TEXT, then BLOB (or no type), then REAL, FLOA or DOUB
+    pub fn from_type_name(type_name: &str) -> Affinity {
+        let type_name = type_name.to_uppercase();
+        let contains_any = |parts: &[&str]| parts.iter().any(|part| type_name.contains(part));
+
+        if type_name.contains("INT") {
+            Affinity::Integer
+        } else if contains_any(&["CHAR", "CLOB", "TEXT"]) {
+            Affinity::Text
+        } else if type_name.contains("BLOB") || type_name.trim().is_empty() {
+            Affinity::Blob
+        } else if contains_any(&["REAL", "FLOA", "DOUB"]) {
+            Affinity::Real
+        } else {
+            Affinity::Numeric
+        }
+    }
+
+    /// Converts a value to suit the affinity. Numbers become text for TEXT, and text that's a
+    /// well-formed number becomes a number for the numeric affinities: an integer if it has an
+    /// integer's value, except for REAL, which always makes it a real. Anything else is left as it
+    /// is.
+    pub fn apply(self, value: Value) -> Value {
+        match (self, value) {
+            (Affinity::Text, value @ (Value::Integer(_) | Value::Real(_))) => {
+                Value::Text(value.to_string())
+            }
+            (Affinity::Numeric | Affinity::Integer, Value::Text(text)) => {
+                parse_number(&text).unwrap_or(Value::Text(text))
+            }
+            (Affinity::Real, Value::Text(text)) => match parse_number(&text) {
+                Some(number) => Value::Real(number.as_real().unwrap_or_default()),
+                None => Value::Text(text),
+            },
+            (Affinity::Real, Value::Integer(i)) => Value::Real(i as f64),
+            (_, value) => value,
+        }
+    }
+
+    /// Converts a value read from a column with the affinity, as SQLite does when it loads one. A
+    /// REAL column stores a real with no fractional part as an integer, to save space, so it's made
+    /// a real again. Anything else is left as it was stored.
+    pub fn load(self, value: Value) -> Value {
+        match (self, value) {
+            (Affinity::Real, Value::Integer(i)) => Value::Real(i as f64),
+            (_, value) => value,
+        }
+    }
+
+    /// Converts a value as CAST(value AS type) does for a type with the affinity. Unlike apply,
+    /// every value but NULL ends up with the affinity's storage class: text that isn't a number
+    /// becomes the number at its start, or 0, and reals are truncated to make integers. Blobs are
+    /// read as text, and BLOB makes text and numbers into their bytes.
+    /// [CAST](https://www.sqlite.org/lang_expr.html#castexpr)
+    pub fn cast(self, value: Value) -> Value {
+        match (self, value) {
+            (_, Value::Null) => Value::Null,
+            (Affinity::Text, Value::Blob(bytes)) => {
+                Value::Text(String::from_utf8_lossy(&bytes).into_owned())
+            }
+            (Affinity::Text, value) => Value::Text(value.to_string()),
+            (Affinity::Blob, Value::Text(text)) => Value::Blob(text.into_bytes()),
+            (Affinity::Blob, value @ (Value::Integer(_) | Value::Real(_))) => {
+                Value::Blob(value.to_string().into_bytes())
+            }
+            (Affinity::Integer, Value::Text(text)) => Value::Integer(integer_prefix(&text)),
+            (Affinity::Integer, Value::Blob(bytes)) => {
+                Value::Integer(integer_prefix(&String::from_utf8_lossy(&bytes)))
+            }
+            // Saturating at the ends of the range, as SQLite does
+            (Affinity::Integer, Value::Real(r)) => Value::Integer(r as i64),
+            (Affinity::Real, value) => {
+                Value::Real(value.to_numeric().as_real().unwrap_or_default())
+            }
+            // A real is left alone, but text that's a whole number becomes an integer
+            (Affinity::Numeric, value @ (Value::Text(_) | Value::Blob(_))) => {
+                match value.to_numeric() {
+                    Value::Real(r) if r.fract() == 0.0 && r.abs() < i64::MAX as f64 => {
+                        Value::Integer(r as i64)
+                    }
+                    number => number,
+                }
+            }
+            (_, value) => value,
+        }
+    }
+
+    /// The letter SQLite's EXPLAIN uses for the affinity
+    fn code(self) -> char {
+        match self {
+            Affinity::Blob => 'A',
+            Affinity::Text => 'B',
+            Affinity::Numeric => 'C',
+            Affinity::Integer => 'D',
+            Affinity::Real => 'E',
+        }
+    }
+}
+
+impl fmt::Display for Affinity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
+
+/// Parses text that's entirely a number, apart from surrounding spaces, like '42', ' 4.5 ' or
+/// '3.0e+5'. A real with an integer's value, like the last, becomes that integer.
+fn parse_number(text: &str) -> Option<Value> {
+    let text = text.trim_matches(|c: char| c.is_ascii_whitespace());
+    // Rust parses "inf" and "NaN" as reals, which SQLite doesn't
+    if !text
+        .chars()
+        .all(|c| c.is_ascii_digit() || matches!(c, '+' | '-' | '.' | 'e' | 'E'))
+        || !text.contains(|c: char| c.is_ascii_digit())
+    {
+        return None;
+    }
+
+    if let Ok(i) = text.parse::<i64>() {
+        return Some(Value::Integer(i));
+    }
+    let real = text.parse::<f64>().ok()?;
+    // Only reals that convert to an integer and back without loss are integers
+    if real.fract() == 0.0 && real.abs() < i64::MAX as f64 {
+        return Some(Value::Integer(real as i64));
+    }
+
+    Some(Value::Real(real))
+}
+
+/// The integer that `text` starts with, after any whitespace: an optional sign and digits,
+/// stopping at anything else, like a decimal point. Too many digits saturate rather than wrap.
+fn integer_prefix(text: &str) -> i64 {
+    let text = text.trim_start();
+    let (negative, digits) = match text.as_bytes().first() {
+        Some(b'-') => (true, &text[1..]),
+        Some(b'+') => (false, &text[1..]),
+        _ => (false, text),
+    };
+
+    let mut value: i64 = 0;
+    for digit in digits.bytes().take_while(u8::is_ascii_digit) {
+        let digit = (digit - b'0') as i64;
+        // Built up as a negative number, whose range reaches one further
+        value = value.saturating_mul(10).saturating_sub(digit);
+    }
+
+    if negative {
+        value
+    } else {
+        value.saturating_neg()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_type_name() {
+        assert_eq!(Affinity::from_type_name("INTEGER"), Affinity::Integer);
+        assert_eq!(Affinity::from_type_name("varchar(10)"), Affinity::Text);
+        // INT comes first, even inside another word
+        assert_eq!(
+            Affinity::from_type_name("FLOATING POINT"),
+            Affinity::Integer
+        );
+        assert_eq!(Affinity::from_type_name("CHARINT"), Affinity::Integer);
+        assert_eq!(Affinity::from_type_name(""), Affinity::Blob);
+        assert_eq!(Affinity::from_type_name("blob"), Affinity::Blob);
+        assert_eq!(Affinity::from_type_name("DOUBLE PRECISION"), Affinity::Real);
+        assert_eq!(Affinity::from_type_name("DECIMAL(10,5)"), Affinity::Numeric);
+        assert_eq!(Affinity::from_type_name("STRING"), Affinity::Numeric);
+    }
+
+    #[test]
+    fn test_apply() {
+        let text = |s: &str| Value::Text(s.to_string());
+
+        assert_eq!(Affinity::Integer.apply(text("42")), Value::Integer(42));
+        assert_eq!(Affinity::Numeric.apply(text(" 4.5 ")), Value::Real(4.5));
+        assert_eq!(
+            Affinity::Numeric.apply(text("3.0e+5")),
+            Value::Integer(300000)
+        );
+        assert_eq!(
+            Affinity::Integer.apply(text("42 apples")),
+            text("42 apples")
+        );
+        assert_eq!(Affinity::Integer.apply(text("inf")), text("inf"));
+        assert_eq!(Affinity::Real.apply(text("42")), Value::Real(42.0));
+        assert_eq!(Affinity::Real.apply(Value::Integer(2)), Value::Real(2.0));
+        assert_eq!(Affinity::Text.apply(Value::Integer(42)), text("42"));
+        assert_eq!(Affinity::Text.apply(Value::Real(2.0)), text("2.0"));
+        assert_eq!(Affinity::Blob.apply(text("42")), text("42"));
+        assert_eq!(Affinity::Blob.apply(Value::Real(4.5)), Value::Real(4.5));
+        assert_eq!(Affinity::Integer.apply(Value::Null), Value::Null);
+    }
+
+    #[test]
+    fn test_cast() {
+        let text = |s: &str| Value::Text(s.to_string());
+        let blob = |s: &str| Value::Blob(s.as_bytes().to_vec());
+
+        assert_eq!(
+            Affinity::Integer.cast(text(" -12.9abc")),
+            Value::Integer(-12)
+        );
+        assert_eq!(Affinity::Integer.cast(text("1e20")), Value::Integer(1));
+        assert_eq!(Affinity::Integer.cast(text("apple")), Value::Integer(0));
+        assert_eq!(
+            Affinity::Integer.cast(text("-99999999999999999999")),
+            Value::Integer(i64::MIN)
+        );
+        assert_eq!(
+            Affinity::Integer.cast(Value::Real(-1.9)),
+            Value::Integer(-1)
+        );
+        assert_eq!(
+            Affinity::Integer.cast(Value::Real(1e20)),
+            Value::Integer(i64::MAX)
+        );
+        assert_eq!(Affinity::Integer.cast(blob("42")), Value::Integer(42));
+        assert_eq!(Affinity::Real.cast(text("1.5kg")), Value::Real(1.5));
+        assert_eq!(Affinity::Real.cast(Value::Integer(3)), Value::Real(3.0));
+        assert_eq!(Affinity::Numeric.cast(text("3.0")), Value::Integer(3));
+        assert_eq!(Affinity::Numeric.cast(text("3.5e2")), Value::Integer(350));
+        assert_eq!(Affinity::Numeric.cast(text("2.5")), Value::Real(2.5));
+        assert_eq!(Affinity::Numeric.cast(Value::Real(5.0)), Value::Real(5.0));
+        assert_eq!(Affinity::Text.cast(Value::Integer(123)), text("123"));
+        assert_eq!(Affinity::Text.cast(blob("abc")), text("abc"));
+        assert_eq!(Affinity::Blob.cast(text("abc")), blob("abc"));
+        assert_eq!(Affinity::Blob.cast(Value::Real(1.5)), blob("1.5"));
+        assert_eq!(Affinity::Real.cast(Value::Null), Value::Null);
+    }
+
+    #[test]
+    fn test_load() {
+        let text = |s: &str| Value::Text(s.to_string());
+
+        assert_eq!(Affinity::Real.load(Value::Integer(2)), Value::Real(2.0));
+        assert_eq!(Affinity::Real.load(text("2")), text("2"));
+        assert_eq!(Affinity::Numeric.load(Value::Integer(2)), Value::Integer(2));
+        assert_eq!(Affinity::Real.load(Value::Null), Value::Null);
+    }
+}
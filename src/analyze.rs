@@ -0,0 +1,288 @@
+use crate::{
+    btree_writer::BtreeWriter,
+    create_index::compare_entries,
+    database::Database,
+    record::{encode_record, Record},
+    schema::Schema,
+    value::Value,
+};
+use anyhow::{bail, Result};
+use itertools::Itertools;
+use std::iter;
+
+/// The SQL SQLite makes sqlite_stat1 with
+const STAT1_SQL: &str = "CREATE TABLE sqlite_stat1(tbl,idx,stat)";
+
+/// A row of sqlite_stat1, which ANALYZE writes: how many rows a table has and, for an index on
+/// it, how many rows on average share each key.
+/// [sqlite_stat1](https://www.sqlite.org/fileformat2.html#stat1tab)
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexStat {
+    pub table_name: String,
+    /// None for a table without indexes, whose row has just its row count
+    pub index_name: Option<String>,
+    pub rows: u64,
+    /// For the index's first column, its first two and so on, how many rows on average have the
+    /// same values in them
+    pub rows_per_key: Vec<u64>,
+}
+
+impl IndexStat {
+    /// Reads a row of sqlite_stat1. Words that follow the numbers, like "unordered", are left out.
+    fn parse(record: &Record) -> Option<Self> {
+        let values = record.values();
+        let [Value::Text(table_name), index_name, Value::Text(stat)] = values.as_slice() else {
+            return None;
+        };
+        let mut numbers = stat
+            .split_whitespace()
+            .map_while(|number| number.parse().ok());
+
+        Some(IndexStat {
+            table_name: table_name.clone(),
+            index_name: match index_name {
+                Value::Text(name) => Some(name.clone()),
+                _ => None,
+            },
+            rows: numbers.next()?,
+            rows_per_key: numbers.collect(),
+        })
+    }
+
+    fn values(&self) -> Vec<Value> {
+        let stat = iter::once(&self.rows).chain(&self.rows_per_key).join(" ");
+
+        vec![
+            Value::Text(self.table_name.clone()),
+            self.index_name.clone().map_or(Value::Null, Value::Text),
+            Value::Text(stat),
+        ]
+    }
+
+    /// Whether this row is about the table, or the one index, that ANALYZE is running on
+    fn is_about(&self, table_name: &str, index_name: Option<&str>) -> bool {
+        self.table_name.eq_ignore_ascii_case(table_name)
+            && index_name.is_none_or(|index_name| {
+                self.index_name
+                    .as_deref()
+                    .is_some_and(|name| name.eq_ignore_ascii_case(index_name))
+            })
+    }
+}
+
+impl Database {
+    /// Runs ANALYZE on every table, or on the one table or index called `name`: counts how many
+    /// rows share each key of their indexes and writes the counts to sqlite_stat1, replacing the
+    /// ones there were. sqlite_stat1 is made the first time.
+    pub fn analyze(&mut self, name: Option<&str>) -> Result<()> {
+        self.write_locked(|database| database.write_stats(name))
+    }
+
+    /// The rows of sqlite_stat1, or none if ANALYZE hasn't made it
+    pub fn index_stats(&self, schema: &[Schema]) -> Result<Vec<IndexStat>> {
+        let Some(stat1) = find_stat1(schema) else {
+            return Ok(vec![]);
+        };
+
+        Ok(self
+            .read_table(stat1.root_page)?
+            .iter()
+            .filter_map(IndexStat::parse)
+            .collect())
+    }
+
+    fn write_stats(&self, name: Option<&str>) -> Result<()> {
+        let schema = self.schema()?;
+        let find = |is_kind: fn(&Schema) -> bool, name: &str| {
+            schema
+                .iter()
+                .find(|s| is_kind(s) && s.name.eq_ignore_ascii_case(name))
+        };
+        // "main" is the schema, which is every table
+        let (tables, index_name) = match name.filter(|name| !name.eq_ignore_ascii_case("main")) {
+            None => (
+                schema
+                    .iter()
+                    .filter(|s| s.is_table() && !s.name.to_ascii_lowercase().starts_with("sqlite_"))
+                    .collect_vec(),
+                None,
+            ),
+            Some(name) => match (find(Schema::is_table, name), find(Schema::is_index, name)) {
+                (Some(table), _) => (vec![table], None),
+                (None, Some(index)) => match find(Schema::is_table, &index.table_name) {
+                    Some(table) => (vec![table], Some(index.name.as_str())),
+                    None => bail!("no such table: {}", index.table_name),
+                },
+                (None, None) => bail!("no such table: {name}"),
+            },
+        };
+
+        let mut stats = vec![];
+        for table in &tables {
+            stats.extend(self.table_stats(&schema, table, index_name)?);
+        }
+
+        // The rows about other tables and indexes stay as they were
+        let stat1 = find_stat1(&schema);
+        let mut records = match stat1 {
+            Some(stat1) => self.read_table(stat1.root_page)?,
+            None => vec![],
+        };
+        records.retain(|record| {
+            !IndexStat::parse(record).is_some_and(|stat| {
+                tables
+                    .iter()
+                    .any(|table| stat.is_about(&table.name, index_name))
+            })
+        });
+
+        let (mut first_page, mut header) = self.first_page_for_writing()?;
+        let mut writer = BtreeWriter::new(self.page_size, self.page_count + 1);
+        let mut cells = vec![];
+        for record in &records {
+            let payload = encode_record(&record.values(), header.schema_format);
+            cells.push((record.row_id, writer.table_cell(record.row_id, &payload)?));
+        }
+        let first_row_id = records.last().map_or(1, |record| record.row_id + 1);
+        for (row_id, stat) in (first_row_id..).zip(&stats) {
+            let payload = encode_record(&stat.values(), header.schema_format);
+            cells.push((row_id, writer.table_cell(row_id, &payload)?));
+        }
+
+        match stat1 {
+            // Written again on its root page, which is all it can have had: any other pages
+            // would be left over, and there's no freeing pages yet
+            Some(stat1) => {
+                if self.btree_size(stat1.root_page)?.pages > 1 {
+                    bail!("Unhandled sqlite_stat1: it has outgrown its root page");
+                }
+                writer.write_table(cells, Some(stat1.root_page))?;
+            }
+            None => {
+                let root_page = writer.write_table(cells, None)?;
+                self.add_schema_row(
+                    &mut first_page,
+                    &header,
+                    &[
+                        Value::Text("table".to_string()),
+                        Value::Text("sqlite_stat1".to_string()),
+                        Value::Text("sqlite_stat1".to_string()),
+                        Value::Integer(root_page as i64),
+                        Value::Text(STAT1_SQL.to_string()),
+                    ],
+                )?;
+                header.schema_cookie = header.schema_cookie.wrapping_add(1);
+            }
+        }
+        header.page_count = writer.page_count();
+
+        self.write_pages(&writer.pages, first_page, header)
+    }
+
+    /// The rows of sqlite_stat1 for a table: one for each of its indexes, or for just the one
+    /// called `index_name`, or one with the row count if it has none. Like SQLite, there are none
+    /// for an empty table, and the newest index comes first.
+    fn table_stats(
+        &self,
+        schema: &[Schema],
+        table: &Schema,
+        index_name: Option<&str>,
+    ) -> Result<Vec<IndexStat>> {
+        let rows = self.btree_size(table.root_page)?.entries;
+        if rows == 0 {
+            return Ok(vec![]);
+        }
+        let create_table = table.create_table()?;
+
+        let indexes = schema
+            .iter()
+            .filter(|s| s.is_index() && s.table_name.eq_ignore_ascii_case(&table.name))
+            .collect_vec();
+        let mut stats = vec![];
+        for index in indexes.iter().rev() {
+            if index_name.is_some_and(|name| !index.name.eq_ignore_ascii_case(name)) {
+                continue;
+            }
+            // There's no counting the keys of an index on expressions
+            let create_index = index
+                .create_index()?
+                .or_else(|| create_table.autoindex(&index.name));
+            let Some(create_index) = create_index.filter(|index| index.simple_columns) else {
+                continue;
+            };
+
+            let (entries, collations) =
+                self.index_entries(table.root_page, &create_table, &create_index)?;
+            // How many different keys there are of each length, from the number of entries that
+            // differ from the one before in the first column, the first two and so on
+            let columns = create_index.columns.len();
+            let mut keys = vec![0u64; columns];
+            for (i, entry) in entries.iter().enumerate() {
+                let same = match i.checked_sub(1) {
+                    Some(previous) => (0..columns)
+                        .take_while(|&column| {
+                            let previous = &entries[previous][..=column];
+                            compare_entries(&collations, previous, &entry[..=column]).is_eq()
+                        })
+                        .count(),
+                    None => 0,
+                };
+                for count in &mut keys[same..] {
+                    *count += 1;
+                }
+            }
+
+            stats.push(IndexStat {
+                table_name: table.name.clone(),
+                index_name: Some(index.name.clone()),
+                rows,
+                rows_per_key: keys.iter().map(|&keys| rows_per_key(rows, keys)).collect(),
+            });
+        }
+
+        if indexes.is_empty() && index_name.is_none() {
+            stats.push(IndexStat {
+                table_name: table.name.clone(),
+                index_name: None,
+                rows,
+                rows_per_key: vec![],
+            });
+        }
+
+        Ok(stats)
+    }
+}
+
+fn find_stat1(schema: &[Schema]) -> Option<&Schema> {
+    schema
+        .iter()
+        .find(|s| s.is_table() && s.name.eq_ignore_ascii_case("sqlite_stat1"))
+}
+
+/// The average number of the `rows` that share each of `keys` different keys, rounded up, as
+/// SQLite works it out: except that when it rounds up to 2 but no more than one in ten keys is
+/// shared, it's 1, so that a nearly unique key is planned as a unique one
+fn rows_per_key(rows: u64, keys: u64) -> u64 {
+    let rows_per_key = rows.div_ceil(keys);
+    if rows_per_key == 2 && rows * 10 <= keys * 11 {
+        1
+    } else {
+        rows_per_key
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rows_per_key() {
+        assert_eq!(rows_per_key(2002, 2002), 1);
+        // sqlite3 writes "2002 1" for 1820 different keys, and "2002 2" for 1819
+        assert_eq!(rows_per_key(2002, 1820), 1);
+        assert_eq!(rows_per_key(2002, 1819), 2);
+        assert_eq!(rows_per_key(2002, 1001), 2);
+        assert_eq!(rows_per_key(2002, 1000), 3);
+        assert_eq!(rows_per_key(2002, 1), 2002);
+    }
+}
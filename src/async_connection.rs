@@ -0,0 +1,540 @@
+//! An async API for databases whose bytes take a while to arrive, like one served over HTTP range
+//! requests or kept in object storage. Queries run on a thread of their own, since the reader is
+//! synchronous, and whenever it needs bytes it asks the Connection, which fetches them with the
+//! source's futures as it's awaited. So nothing blocks the thread the futures are polled on, and
+//! only the pages a query reads are fetched. It works with any executor, tokio's included.
+
+use crate::{
+    database::{Database, SchemaChangedError},
+    executor::{execute, Rows},
+    page_source::PageSource,
+    planner::plan_query,
+    query_parser::parse_whole_query,
+    schema::Schema,
+    value::Value,
+    view::expand_views,
+};
+use anyhow::{anyhow, bail, Result};
+use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    future::{poll_fn, Future},
+    pin::Pin,
+    sync::{mpsc, Arc, Mutex, PoisonError},
+    task::{Poll, Waker},
+    thread,
+};
+
+/// A future that can be sent between threads, as AsyncPageSource returns
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Where a database's bytes come from when getting them means waiting, the async counterpart of
+/// a readonly PageSource. Like ReaderSource, the database mustn't change size while it's open.
+pub trait AsyncPageSource: Send + Sync + 'static {
+    /// The size of the database in bytes
+    fn size(&self) -> BoxFuture<'_, Result<u64>>;
+
+    /// The `len` bytes starting at `offset`
+    fn read_at(&self, offset: u64, len: usize) -> BoxFuture<'_, Result<Vec<u8>>>;
+}
+
+/// What the query thread tells the Connection
+enum Message {
+    /// The query thread needs these bytes, and waits for them
+    Read {
+        offset: u64,
+        len: usize,
+        reply: mpsc::Sender<Result<Vec<u8>>>,
+    },
+    /// The database is open, or couldn't be
+    Opened(Result<()>),
+    /// A query is ready to give rows with these columns, or couldn't be prepared
+    Prepared(Result<Vec<String>>),
+    /// The next row, or None once there are no more
+    Row(Option<Result<Vec<Value>>>),
+    /// The query that was running has been dropped
+    Finished,
+}
+
+/// What the Connection tells the query thread
+enum Command {
+    Query(String),
+    /// Produce the next row of the query
+    Next,
+    /// Drop the query
+    Finish,
+}
+
+/// The messages the query thread has sent that the Connection hasn't read yet
+#[derive(Default)]
+struct Mailbox {
+    messages: VecDeque<Message>,
+    /// Woken when a message arrives
+    waker: Option<Waker>,
+    /// Set once the Connection is gone, after which reads fail rather than wait forever
+    closed: bool,
+    /// Set once the query thread has stopped, which it only does early if it panics
+    stopped: bool,
+}
+
+/// The query thread's end of the mailbox
+#[derive(Clone)]
+struct Sender(Arc<Mutex<Mailbox>>);
+
+impl Sender {
+    fn send(&self, message: Message) {
+        let mut mailbox = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        if mailbox.closed {
+            return;
+        }
+        mailbox.messages.push_back(message);
+        if let Some(waker) = mailbox.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// Marks the query thread as stopped when it's dropped, however the thread ends
+struct StoppedGuard(Sender);
+
+impl Drop for StoppedGuard {
+    fn drop(&mut self) {
+        let mut mailbox = self.0 .0.lock().unwrap_or_else(PoisonError::into_inner);
+        mailbox.stopped = true;
+        if let Some(waker) = mailbox.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// The PageSource the query thread's Database reads through, which asks the Connection for the
+/// bytes and waits for them to be fetched
+struct BridgeSource {
+    sender: Sender,
+    size: u64,
+}
+
+impl PageSource for BridgeSource {
+    fn size(&self) -> Result<u64> {
+        Ok(self.size)
+    }
+
+    fn read_at(&self, offset: u64, len: usize) -> Result<Cow<'_, [u8]>> {
+        let (reply, bytes) = mpsc::channel();
+        self.sender.send(Message::Read { offset, len, reply });
+        match bytes.recv() {
+            Ok(bytes) => Ok(Cow::Owned(bytes?)),
+            Err(_) => bail!("the connection was closed"),
+        }
+    }
+}
+
+/// An open database, read from an AsyncPageSource. It runs one query at a time.
+pub struct Connection {
+    source: Box<dyn AsyncPageSource>,
+    mailbox: Arc<Mutex<Mailbox>>,
+    commands: mpsc::Sender<Command>,
+    /// Whether a dropped Statement's query is still to be finished by the query thread
+    unfinished: bool,
+}
+
+impl Connection {
+    /// Opens the database, reading its header from `source`
+    pub async fn open(source: impl AsyncPageSource) -> Result<Self> {
+        let size = source.size().await?;
+        let mailbox = Arc::new(Mutex::new(Mailbox::default()));
+        let (commands, command_receiver) = mpsc::channel();
+        let sender = Sender(mailbox.clone());
+        thread::Builder::new()
+            .name("sqlite-rust query".to_string())
+            .spawn(move || run_queries(BridgeSource { sender, size }, command_receiver))?;
+
+        let mut connection = Connection {
+            source: Box::new(source),
+            mailbox,
+            commands,
+            unfinished: false,
+        };
+        match connection.receive().await? {
+            Message::Opened(result) => result?,
+            _ => bail!("the query thread didn't open the database"),
+        }
+
+        Ok(connection)
+    }
+
+    /// Starts running a query, returning its rows as they're read. The query only reads what
+    /// it needs to for the rows that are asked for.
+    pub async fn query(&mut self, sql: &str) -> Result<Statement<'_>> {
+        // A dropped Statement may have left rows, and reads for them, on their way
+        while self.unfinished {
+            if let Message::Finished = self.receive().await? {
+                self.unfinished = false;
+            }
+        }
+
+        self.send(Command::Query(sql.to_string()))?;
+        let columns = match self.receive().await? {
+            Message::Prepared(columns) => columns?,
+            _ => bail!("the query thread didn't prepare the query"),
+        };
+
+        Ok(Statement {
+            connection: self,
+            columns,
+            done: false,
+        })
+    }
+
+    fn send(&self, command: Command) -> Result<()> {
+        self.commands
+            .send(command)
+            .map_err(|_| anyhow!("the query thread has stopped"))
+    }
+
+    /// The query thread's next message other than a read, fetching the bytes it reads in the
+    /// meantime
+    async fn receive(&mut self) -> Result<Message> {
+        loop {
+            let message = poll_fn(|cx| {
+                let mut mailbox = self.mailbox.lock().unwrap_or_else(PoisonError::into_inner);
+                match mailbox.messages.pop_front() {
+                    Some(message) => Poll::Ready(Some(message)),
+                    None if mailbox.stopped => Poll::Ready(None),
+                    None => {
+                        mailbox.waker = Some(cx.waker().clone());
+                        Poll::Pending
+                    }
+                }
+            })
+            .await;
+
+            match message {
+                Some(Message::Read { offset, len, reply }) => {
+                    // The query thread only goes away if it panicked
+                    let _ = reply.send(self.source.read_at(offset, len).await);
+                }
+                Some(message) => return Ok(message),
+                None => bail!("the query thread has stopped"),
+            }
+        }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        // Dropping the reads that are waiting lets the query thread see that it's been left
+        let mut mailbox = self.mailbox.lock().unwrap_or_else(PoisonError::into_inner);
+        mailbox.closed = true;
+        mailbox.messages.clear();
+    }
+}
+
+/// A query that's running on a Connection
+pub struct Statement<'a> {
+    connection: &'a mut Connection,
+    columns: Vec<String>,
+    /// Whether the last row has been read, or the query failed
+    done: bool,
+}
+
+impl Statement<'_> {
+    /// The name of each column, as in the result's headings
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    /// The next row, or None once there are no more
+    pub async fn next(&mut self) -> Option<Result<Vec<Value>>> {
+        if self.done {
+            return None;
+        }
+
+        let row = match self.connection.send(Command::Next) {
+            Ok(()) => match self.connection.receive().await {
+                Ok(Message::Row(row)) => row,
+                Ok(_) => Some(Err(anyhow!("the query thread didn't send a row"))),
+                Err(err) => Some(Err(err)),
+            },
+            Err(err) => Some(Err(err)),
+        };
+        self.done = !matches!(row, Some(Ok(_)));
+
+        row
+    }
+
+    /// Every row that's left
+    pub async fn collect(mut self) -> Result<Vec<Vec<Value>>> {
+        let mut rows = vec![];
+        while let Some(row) = self.next().await {
+            rows.push(row?);
+        }
+
+        Ok(rows)
+    }
+}
+
+impl Drop for Statement<'_> {
+    fn drop(&mut self) {
+        if !self.done && self.connection.send(Command::Finish).is_ok() {
+            self.connection.unfinished = true;
+        }
+    }
+}
+
+/// The query thread: opens the database, then runs queries as they come, a row at a time
+fn run_queries(source: BridgeSource, commands: mpsc::Receiver<Command>) {
+    let sender = source.sender.clone();
+    let _stopped = StoppedGuard(sender.clone());
+    let mut database = match Database::open(source) {
+        Ok(database) => {
+            sender.send(Message::Opened(Ok(())));
+            database
+        }
+        Err(err) => return sender.send(Message::Opened(Err(err))),
+    };
+
+    // The schema is read again only when the schema cookie says it has changed
+    let mut schema = None;
+    while let Ok(command) = commands.recv() {
+        let sql = match command {
+            Command::Query(sql) => sql,
+            Command::Next => {
+                sender.send(Message::Row(None));
+                continue;
+            }
+            Command::Finish => {
+                sender.send(Message::Finished);
+                continue;
+            }
+        };
+
+        if let Err(err) = reload_changed_schema(&mut database, &mut schema) {
+            sender.send(Message::Prepared(Err(err)));
+            continue;
+        }
+        let mut result = prepare(&database, &schema, &sql);
+        // Planned again if the schema changed between being read and the query running
+        if result
+            .as_ref()
+            .is_err_and(|err| err.is::<SchemaChangedError>())
+        {
+            drop(result);
+            result = reload_changed_schema(&mut database, &mut schema)
+                .and_then(|()| prepare(&database, &schema, &sql));
+        }
+        let mut rows = match result {
+            Ok(rows) => rows,
+            Err(err) => {
+                sender.send(Message::Prepared(Err(err)));
+                continue;
+            }
+        };
+        sender.send(Message::Prepared(Ok(rows.columns().to_vec())));
+
+        while let Ok(command) = commands.recv() {
+            match command {
+                Command::Next => {
+                    let row = rows.next();
+                    let done = !matches!(row, Some(Ok(_)));
+                    sender.send(Message::Row(row));
+                    if done {
+                        break;
+                    }
+                }
+                Command::Finish => {
+                    sender.send(Message::Finished);
+                    break;
+                }
+                Command::Query(_) => {
+                    sender.send(Message::Prepared(Err(anyhow!(
+                        "a query is already running"
+                    ))));
+                }
+            }
+        }
+    }
+}
+
+/// Plans the query in `sql` against `schema` and starts it running
+fn prepare<'a>(database: &'a Database, schema: &CachedSchema, sql: &str) -> Result<Rows<'a>> {
+    let (_, query) = parse_whole_query(sql).map_err(|err| anyhow!("can't parse query: {err}"))?;
+    let schema = schema.as_ref().map_or(&[][..], |(_, schema)| schema);
+    let query = expand_views(schema, &query)?;
+    let plan = plan_query(database, schema, &query)?;
+    execute(database, &plan, &query)
+}
+
+/// The schema as it was last read, with the schema cookie it was read at
+type CachedSchema = Option<(u32, Vec<Schema>)>;
+
+/// Reads the schema again if it hasn't been read yet, or if another connection has changed it
+/// since it was, going by the schema cookie in the header
+fn reload_changed_schema(database: &mut Database, schema: &mut CachedSchema) -> Result<()> {
+    let cookie = database.current_schema_cookie()?;
+    if schema.as_ref().is_none_or(|(cached, _)| *cached != cookie) {
+        database.refresh()?;
+        *schema = Some((database.schema_cookie, database.schema()?));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        task::{Context, Wake},
+    };
+
+    /// Bytes in memory, handed out a poll later than they're asked for, as if over a network
+    struct SlowBytes {
+        bytes: Vec<u8>,
+        reads: Arc<AtomicUsize>,
+    }
+
+    impl AsyncPageSource for SlowBytes {
+        fn size(&self) -> BoxFuture<'_, Result<u64>> {
+            Box::pin(async { Ok(self.bytes.len() as u64) })
+        }
+
+        fn read_at(&self, offset: u64, len: usize) -> BoxFuture<'_, Result<Vec<u8>>> {
+            self.reads.fetch_add(1, Ordering::Relaxed);
+            let mut polled = false;
+            Box::pin(poll_fn(move |cx| {
+                if !polled {
+                    polled = true;
+                    cx.waker().wake_by_ref();
+                    return Poll::Pending;
+                }
+                let start = offset as usize;
+                Poll::Ready(match self.bytes.get(start..start + len) {
+                    Some(bytes) => Ok(bytes.to_vec()),
+                    None => Err(anyhow!("{len} bytes at {offset} is past the end")),
+                })
+            }))
+        }
+    }
+
+    /// Runs a future to completion on this thread, parking it while the future waits
+    fn block_on<T>(future: impl Future<Output = T>) -> T {
+        struct Unparker(thread::Thread);
+        impl Wake for Unparker {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+
+        let waker = Waker::from(Arc::new(Unparker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut future = std::pin::pin!(future);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+            thread::park();
+        }
+    }
+
+    #[test]
+    fn test_query() {
+        let reads = Arc::new(AtomicUsize::new(0));
+        let source = SlowBytes {
+            bytes: include_bytes!("../tests/golden/types.db").to_vec(),
+            reads: reads.clone(),
+        };
+
+        block_on(async {
+            let mut connection = Connection::open(source).await.unwrap();
+            let statement = connection.query("SELECT count(*) FROM apples").await;
+            assert_eq!(
+                statement.err().unwrap().to_string(),
+                "no such table: apples"
+            );
+
+            let mut statement = connection
+                .query("SELECT id, name FROM things WHERE id IN (1, 2)")
+                .await
+                .unwrap();
+            assert_eq!(statement.columns(), ["id", "name"]);
+            assert_eq!(
+                statement.next().await.unwrap().unwrap(),
+                [Value::Integer(1), Value::Text("apple".to_string())]
+            );
+            assert_eq!(
+                statement.next().await.unwrap().unwrap(),
+                [Value::Integer(2), Value::Text("Banana".to_string())]
+            );
+            assert!(statement.next().await.is_none());
+            drop(statement);
+
+            // A query dropped part way through doesn't get in the way of the next one
+            let mut statement = connection.query("SELECT id FROM things").await.unwrap();
+            statement.next().await.unwrap().unwrap();
+            drop(statement);
+            let rows = connection
+                .query("SELECT count(*) FROM things")
+                .await
+                .unwrap()
+                .collect()
+                .await;
+            assert_eq!(rows.unwrap(), [[Value::Integer(7)]]);
+        });
+        assert!(reads.load(Ordering::Relaxed) > 0);
+    }
+
+    /// Bytes that another connection can change in between queries
+    struct SharedBytes(Arc<Mutex<Vec<u8>>>);
+
+    impl AsyncPageSource for SharedBytes {
+        fn size(&self) -> BoxFuture<'_, Result<u64>> {
+            Box::pin(async { Ok(self.0.lock().unwrap().len() as u64) })
+        }
+
+        fn read_at(&self, offset: u64, len: usize) -> BoxFuture<'_, Result<Vec<u8>>> {
+            let start = offset as usize;
+            let bytes = self.0.lock().unwrap()[start..start + len].to_vec();
+            Box::pin(async { Ok(bytes) })
+        }
+    }
+
+    #[test]
+    fn test_schema_changed() {
+        let bytes = Arc::new(Mutex::new(
+            include_bytes!("../tests/golden/types.db").to_vec(),
+        ));
+
+        block_on(async {
+            let mut connection = Connection::open(SharedBytes(bytes.clone())).await.unwrap();
+            let count = connection.query("SELECT count(*) FROM things").await;
+            assert_eq!(
+                count.unwrap().collect().await.unwrap(),
+                [[Value::Integer(7)]]
+            );
+
+            // Another database of the same size, as if the schema had been changed, which bumps
+            // the schema cookie
+            let mut changed = include_bytes!("../tests/golden/dates.db").to_vec();
+            changed[40..44].copy_from_slice(&2u32.to_be_bytes());
+            *bytes.lock().unwrap() = changed;
+
+            let count = connection.query("SELECT count(*) FROM things").await;
+            assert_eq!(count.err().unwrap().to_string(), "no such table: things");
+            let count = connection.query("SELECT count(*) FROM events").await;
+            assert_eq!(
+                count.unwrap().collect().await.unwrap(),
+                [[Value::Integer(6)]]
+            );
+        });
+    }
+
+    #[test]
+    fn test_open_fails() {
+        let source = SlowBytes {
+            bytes: vec![0; 512],
+            reads: Arc::default(),
+        };
+        assert!(block_on(Connection::open(source)).is_err());
+    }
+}
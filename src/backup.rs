@@ -0,0 +1,95 @@
+use crate::{database::Database, interrupt::InterruptedError};
+use anyhow::{bail, Result};
+use std::io::Write;
+#[cfg(feature = "fs")]
+use std::{
+    fs::{self, File},
+    io::BufWriter,
+    path::Path,
+};
+
+/// How many pages a backup copies between calls to its progress callback
+const BACKUP_STEP_PAGES: u32 = 100;
+
+impl Database {
+    /// Copies every page of the database to `output`, like sqlite3_backup_step() with -1 pages.
+    /// The read lock is held throughout, so the copy is a consistent database even while other
+    /// connections write to this one, and this connection can keep running statements after.
+    ///
+    /// `progress` is called as pages are copied, with the number copied so far and the number
+    /// there are in all. Returning true stops the backup, as an interrupt would.
+    pub fn backup_to(
+        &self,
+        output: &mut dyn Write,
+        mut progress: Option<&mut dyn FnMut(u32, u32) -> bool>,
+    ) -> Result<()> {
+        let _lock = self.read_lock()?;
+        // Other connections may have changed the file since the header was last read
+        let header = self.header()?;
+        let (page_count, _) = header.resolve_page_count(self.database_file.size()?);
+
+        let mut copied = 0;
+        while copied < page_count {
+            if self.interrupt.is_interrupted() {
+                bail!(InterruptedError);
+            }
+            let pages = BACKUP_STEP_PAGES.min(page_count - copied);
+            let offset = copied as u64 * self.page_size as u64;
+            let bytes = self
+                .database_file
+                .read_at(offset, pages as usize * self.page_size as usize)?;
+            output.write_all(&bytes)?;
+            copied += pages;
+
+            if let Some(progress) = progress.as_mut() {
+                if progress(copied, page_count) {
+                    bail!(InterruptedError);
+                }
+            }
+        }
+        output.flush()?;
+
+        Ok(())
+    }
+
+    /// Backs the database up to a file at `path`, replacing what's there. The copy is written
+    /// next to it first, and only renamed to `path` once it's complete.
+    #[cfg(feature = "fs")]
+    pub fn backup_to_file(
+        &self,
+        path: impl AsRef<Path>,
+        progress: Option<&mut dyn FnMut(u32, u32) -> bool>,
+    ) -> Result<()> {
+        let path = path.as_ref();
+        let same_file = fs::canonicalize(path)
+            .ok()
+            .is_some_and(|path| fs::canonicalize(&self.options.path).ok() == Some(path));
+        if same_file {
+            bail!("can't back up a database onto itself");
+        }
+
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push("-backup");
+        let result = self.write_backup(Path::new(&temp_path), progress);
+        if result.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+        result?;
+        fs::rename(&temp_path, path)?;
+
+        Ok(())
+    }
+
+    #[cfg(feature = "fs")]
+    fn write_backup(
+        &self,
+        path: &Path,
+        progress: Option<&mut dyn FnMut(u32, u32) -> bool>,
+    ) -> Result<()> {
+        let mut output = BufWriter::new(File::create(path)?);
+        self.backup_to(&mut output, progress)?;
+        output.into_inner()?.sync_all()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,171 @@
+use crate::{planner::ROWID_ALIASES, vm::ColumnRef};
+
+/// A table whose columns a query can refer to
+#[derive(Debug, Clone, Copy)]
+pub struct Source<'a> {
+    pub name: &'a str,
+    /// Column names, in record order
+    pub columns: &'a [String],
+    /// The index of the column that's an alias for the rowid, if any
+    pub rowid_alias: Option<usize>,
+}
+
+/// A column reference resolved to the source it's from and where in that source's rows it is
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundColumn {
+    pub source: usize,
+    pub column: ColumnRef,
+}
+
+/// A column reference that doesn't resolve to exactly one column. Each holds the reference as
+/// it was written, so that it can be pointed out in the SQL.
+#[derive(Debug, PartialEq, thiserror::Error)]
+pub enum BindError {
+    #[error("no such column: {0}")]
+    NoSuchColumn(String),
+    /// A bare name matching columns of more than one source
+    #[error("ambiguous column name: {0}")]
+    AmbiguousColumn(String),
+}
+
+impl BindError {
+    pub fn reference(&self) -> &str {
+        match self {
+            BindError::NoSuchColumn(reference) | BindError::AmbiguousColumn(reference) => reference,
+        }
+    }
+
+    /// Where the reference is in `sql`, as a byte offset: the first place it appears as a whole
+    /// word, ignoring case
+    pub fn offset_in(&self, sql: &str) -> Option<usize> {
+        let reference = self.reference().to_ascii_lowercase();
+        let lowercase = sql.to_ascii_lowercase();
+        let is_word_char = |c: char| c.is_alphanumeric() || c == '_' || c == '.';
+
+        lowercase
+            .match_indices(&reference)
+            .map(|(i, _)| i)
+            .find(|&i| {
+                let before = lowercase[..i].chars().next_back();
+                let after = lowercase[i + reference.len()..].chars().next();
+                !before.is_some_and(is_word_char) && !after.is_some_and(is_word_char)
+            })
+    }
+}
+
+/// Resolves the column references of a query against the tables it reads from. A reference is a
+/// bare column name, or one qualified with its table's name, like apples.color; a bare name has
+/// to belong to only one of the tables.
+pub struct Binder<'a> {
+    sources: Vec<Source<'a>>,
+}
+
+impl<'a> Binder<'a> {
+    pub fn new(sources: Vec<Source<'a>>) -> Self {
+        Binder { sources }
+    }
+
+    pub fn resolve(&self, reference: &str) -> Result<BoundColumn, BindError> {
+        let (qualifier, name) = match reference.split_once('.') {
+            Some((qualifier, name)) => (Some(qualifier), name),
+            None => (None, reference),
+        };
+
+        let mut matches = self
+            .sources
+            .iter()
+            .enumerate()
+            .filter(|(_, source)| qualifier.is_none_or(|q| source.name.eq_ignore_ascii_case(q)))
+            .filter_map(|(i, source)| {
+                Some(BoundColumn {
+                    source: i,
+                    column: resolve_in(source, name)?,
+                })
+            });
+
+        match (matches.next(), matches.next()) {
+            (Some(bound), None) => Ok(bound),
+            (Some(_), Some(_)) => Err(BindError::AmbiguousColumn(reference.to_string())),
+            (None, _) => Err(BindError::NoSuchColumn(reference.to_string())),
+        }
+    }
+}
+
+/// Finds a column of one source by name. A column named like the rowid hides the rowid itself.
+fn resolve_in(source: &Source, name: &str) -> Option<ColumnRef> {
+    if let Some(index) = source
+        .columns
+        .iter()
+        .position(|column| column.eq_ignore_ascii_case(name))
+    {
+        // The record holds NULL for an INTEGER PRIMARY KEY, whose value is the rowid
+        if source.rowid_alias == Some(index) {
+            return Some(ColumnRef::RowId);
+        }
+        return Some(ColumnRef::Index(index));
+    }
+
+    ROWID_ALIASES
+        .iter()
+        .any(|alias| alias.eq_ignore_ascii_case(name))
+        .then_some(ColumnRef::RowId)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let apples = ["id", "name", "color"].map(String::from);
+        let pears = ["name", "weight"].map(String::from);
+        let binder = Binder::new(vec![
+            Source {
+                name: "apples",
+                columns: &apples,
+                rowid_alias: Some(0),
+            },
+            Source {
+                name: "pears",
+                columns: &pears,
+                rowid_alias: None,
+            },
+        ]);
+        let bound = |reference| binder.resolve(reference).map(|b| (b.source, b.column));
+
+        assert_eq!(bound("color"), Ok((0, ColumnRef::Index(2))));
+        assert_eq!(bound("WEIGHT"), Ok((1, ColumnRef::Index(1))));
+        assert_eq!(bound("id"), Ok((0, ColumnRef::RowId)));
+        assert_eq!(bound("pears.name"), Ok((1, ColumnRef::Index(0))));
+        assert_eq!(bound("Apples.rowid"), Ok((0, ColumnRef::RowId)));
+        assert_eq!(
+            bound("name"),
+            Err(BindError::AmbiguousColumn("name".to_string()))
+        );
+        assert_eq!(
+            bound("rowid"),
+            Err(BindError::AmbiguousColumn("rowid".to_string()))
+        );
+        assert_eq!(
+            bound("plums.name"),
+            Err(BindError::NoSuchColumn("plums.name".to_string()))
+        );
+        assert_eq!(
+            bound("pears.color"),
+            Err(BindError::NoSuchColumn("pears.color".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_offset_in() {
+        let err = BindError::NoSuchColumn("nope.name".to_string());
+        assert_eq!(
+            err.offset_in("SELECT name, NOPE.NAME FROM apples"),
+            Some(13)
+        );
+
+        let err = BindError::AmbiguousColumn("name".to_string());
+        assert_eq!(err.offset_in("SELECT surname, name FROM t"), Some(16));
+        assert_eq!(err.offset_in("SELECT apples.name FROM t"), None);
+    }
+}
@@ -0,0 +1,422 @@
+use crate::{
+    header::{BTreePage, DATABASE_HEADER_SIZE},
+    lock::lock_byte_page,
+    overflow, varint,
+};
+use anyhow::{bail, Result};
+
+/// A cell's bytes, as they're stored on a page
+type Cell = Vec<u8>;
+
+/// Writes new b-trees bottom-up, from cells that are already in order, onto pages numbered from
+/// the first one it's given. The pages are kept in memory until they're written to the database.
+pub(crate) struct BtreeWriter {
+    page_size: u32,
+    next_page: u32,
+    /// The pages written so far, with their page numbers
+    pub pages: Vec<(u32, Vec<u8>)>,
+}
+
+impl BtreeWriter {
+    pub fn new(page_size: u32, first_page: u32) -> Self {
+        BtreeWriter {
+            page_size,
+            next_page: first_page,
+            pages: vec![],
+        }
+    }
+
+    /// The database's page count once the pages written so far are added to it
+    pub fn page_count(&self) -> u32 {
+        self.next_page - 1
+    }
+
+    fn allocate(&mut self) -> u32 {
+        // The page holding the bytes SQLite locks is never used
+        if self.next_page == lock_byte_page(self.page_size) {
+            self.next_page += 1;
+        }
+        self.next_page += 1;
+
+        self.next_page - 1
+    }
+
+    fn new_page(&self) -> Vec<u8> {
+        vec![0; self.page_size as usize]
+    }
+
+    /// Makes an index cell holding `payload`, writing the part of it that doesn't fit on the page
+    /// to overflow pages. The cell can go on a leaf page as it is, or on an interior page after a
+    /// child pointer.
+    pub fn index_cell(&mut self, payload: &[u8]) -> Result<Cell> {
+        let split =
+            overflow::thresholds(self.page_size, payload.len() as u64, BTreePage::LeafIndex)?;
+
+        let mut cell = varint::encode_varint(payload.len() as i64);
+        cell.extend(&payload[..split.local]);
+        if split.overflow > 0 {
+            let first_overflow_page = self.write_overflow(&payload[split.local..]);
+            cell.extend(first_overflow_page.to_be_bytes());
+        }
+
+        Ok(cell)
+    }
+
+    /// Makes a table leaf cell for the row with `row_id`, holding `payload`, writing the part of it
+    /// that doesn't fit on the page to overflow pages
+    pub fn table_cell(&mut self, row_id: i64, payload: &[u8]) -> Result<Cell> {
+        let split =
+            overflow::thresholds(self.page_size, payload.len() as u64, BTreePage::LeafTable)?;
+
+        let mut cell = varint::encode_varint(payload.len() as i64);
+        cell.extend(varint::encode_varint(row_id));
+        cell.extend(&payload[..split.local]);
+        if split.overflow > 0 {
+            let first_overflow_page = self.write_overflow(&payload[split.local..]);
+            cell.extend(first_overflow_page.to_be_bytes());
+        }
+
+        Ok(cell)
+    }
+
+    /// Writes `bytes` to a chain of overflow pages, returning the first
+    fn write_overflow(&mut self, bytes: &[u8]) -> u32 {
+        let chunks = bytes
+            .chunks(self.page_size as usize - 4)
+            .collect::<Vec<_>>();
+        let page_nums = chunks.iter().map(|_| self.allocate()).collect::<Vec<_>>();
+
+        for (i, chunk) in chunks.iter().enumerate() {
+            // Each overflow page starts with the number of the next, or 0 on the last one
+            let next = page_nums.get(i + 1).copied().unwrap_or(0);
+            let mut page = self.new_page();
+            page[..4].copy_from_slice(&next.to_be_bytes());
+            page[4..4 + chunk.len()].copy_from_slice(chunk);
+            self.pages.push((page_nums[i], page));
+        }
+
+        page_nums[0]
+    }
+
+    /// Writes an index b-tree holding `cells`, made by index_cell and sorted by key, returning
+    /// its root page
+    pub fn write_index(&mut self, cells: Vec<Cell>) -> Result<u32> {
+        let mut cells = cells;
+        // The last child of the level being written, which has none if it's the leaves
+        let mut right_most_child = None;
+
+        loop {
+            let page_type = match right_most_child {
+                Some(_) => BTreePage::InteriorIndex,
+                None => BTreePage::LeafIndex,
+            };
+            let capacity = self.page_size as usize - page_type.header_size();
+            let (runs, separators) = split_cells(cells, capacity)?;
+
+            let mut separators = separators.into_iter();
+            let mut parent_cells = vec![];
+            let mut page_num = 0;
+            for run in runs {
+                page_num = self.allocate();
+
+                // The cell after a run goes up a level, pointing to the run's page. On an interior
+                // level, the child it pointed to becomes the page's right-most child.
+                let (right_most_pointer, separator) = match (separators.next(), right_most_child) {
+                    (Some(cell), Some(_)) => {
+                        let child = u32::from_be_bytes(cell[..4].try_into()?);
+                        (Some(child), Some(cell[4..].to_vec()))
+                    }
+                    (separator, right_most_child) => (right_most_child, separator),
+                };
+
+                let mut page = self.new_page();
+                write_page(&mut page, 0, page_type, &run, right_most_pointer)?;
+                self.pages.push((page_num, page));
+
+                if let Some(separator) = separator {
+                    let mut cell = page_num.to_be_bytes().to_vec();
+                    cell.extend(separator);
+                    parent_cells.push(cell);
+                }
+            }
+
+            if parent_cells.is_empty() {
+                return Ok(page_num);
+            }
+            cells = parent_cells;
+            right_most_child = Some(page_num);
+        }
+    }
+
+    /// Writes a table b-tree holding `cells`, each made by table_cell and paired with its rowid, in
+    /// rowid order. The root goes on `root_page` if it's given, after the database header if
+    /// that's page 1, as sqlite_schema's does. Otherwise it's the last page written, and returned.
+    pub fn write_table(&mut self, cells: Vec<(i64, Cell)>, root_page: Option<u32>) -> Result<u32> {
+        let header_start = match root_page {
+            Some(1) => DATABASE_HEADER_SIZE,
+            _ => 0,
+        };
+        let page_size = self.page_size as usize;
+        let root_capacity =
+            |page_type: BTreePage| page_size - header_start - page_type.header_size();
+
+        // Rows are only on leaves. The level above has a cell for each leaf but the last, with
+        // the largest rowid on it.
+        let mut cells = cells;
+        if fits(
+            cells.iter().map(|(_, cell)| cell),
+            root_capacity(BTreePage::LeafTable),
+        ) {
+            let cells = cells.into_iter().map(|(_, cell)| cell).collect::<Vec<_>>();
+            return self.write_root(root_page, header_start, BTreePage::LeafTable, &cells, None);
+        }
+        let capacity = self.page_size as usize - BTreePage::LeafTable.header_size();
+        let mut parent_cells = vec![];
+        let mut right_most_child = 0;
+        while !cells.is_empty() {
+            let mut used = 0;
+            let run_length = cells
+                .iter()
+                .take_while(|(_, cell)| {
+                    used += cell.len() + 2;
+                    used <= capacity
+                })
+                .count();
+            if run_length == 0 {
+                bail!("a cell of {} bytes doesn't fit on a page", cells[0].1.len());
+            }
+            let run = cells.drain(..run_length).collect::<Vec<_>>();
+            let last_row_id = run.last().expect("runs aren't empty").0;
+            let run = run.into_iter().map(|(_, cell)| cell).collect::<Vec<_>>();
+
+            right_most_child = self.allocate();
+            let mut page = self.new_page();
+            write_page(&mut page, 0, BTreePage::LeafTable, &run, None)?;
+            self.pages.push((right_most_child, page));
+
+            if !cells.is_empty() {
+                let mut cell = right_most_child.to_be_bytes().to_vec();
+                cell.extend(varint::encode_varint(last_row_id));
+                parent_cells.push(cell);
+            }
+        }
+
+        // Each interior level is split like an index's, the cell after each run giving up its
+        // child to be the run's right-most child and its rowid to the level above
+        let mut cells = parent_cells;
+        loop {
+            let page_type = BTreePage::InteriorTable;
+            if fits(cells.iter(), root_capacity(page_type)) {
+                return self.write_root(
+                    root_page,
+                    header_start,
+                    page_type,
+                    &cells,
+                    Some(right_most_child),
+                );
+            }
+
+            let (runs, separators) = split_cells(cells, self.page_size as usize - 12)?;
+            let mut separators = separators.into_iter();
+            let mut parent_cells = vec![];
+            for run in runs {
+                let page_num = self.allocate();
+                let (right_most_pointer, separator) = match separators.next() {
+                    Some(cell) => {
+                        let child = u32::from_be_bytes(cell[..4].try_into()?);
+                        (child, Some(cell[4..].to_vec()))
+                    }
+                    None => (right_most_child, None),
+                };
+
+                let mut page = self.new_page();
+                write_page(&mut page, 0, page_type, &run, Some(right_most_pointer))?;
+                self.pages.push((page_num, page));
+
+                match separator {
+                    Some(row_id) => {
+                        let mut cell = page_num.to_be_bytes().to_vec();
+                        cell.extend(row_id);
+                        parent_cells.push(cell);
+                    }
+                    None => right_most_child = page_num,
+                }
+            }
+            cells = parent_cells;
+        }
+    }
+
+    /// Writes the root of a b-tree on `root_page`, or on a new page if it's None
+    fn write_root(
+        &mut self,
+        root_page: Option<u32>,
+        header_start: usize,
+        page_type: BTreePage,
+        cells: &[Cell],
+        right_most_pointer: Option<u32>,
+    ) -> Result<u32> {
+        let page_num = root_page.unwrap_or_else(|| self.allocate());
+        let mut page = self.new_page();
+        write_page(
+            &mut page,
+            header_start,
+            page_type,
+            cells,
+            right_most_pointer,
+        )?;
+        self.pages.push((page_num, page));
+
+        Ok(page_num)
+    }
+}
+
+/// Whether `cells`, with their cell pointers, fit in `capacity` bytes of a page
+fn fits<'a>(cells: impl Iterator<Item = &'a Cell>, capacity: usize) -> bool {
+    cells.map(|cell| cell.len() + 2).sum::<usize>() <= capacity
+}
+
+/// Splits `cells` into runs that each fit in `capacity` bytes of a page, separated by single
+/// cells that go up to the level above
+fn split_cells(cells: Vec<Cell>, capacity: usize) -> Result<(Vec<Vec<Cell>>, Vec<Cell>)> {
+    let mut runs = vec![vec![]];
+    let mut separators = vec![];
+    let mut used = 0;
+
+    for cell in cells {
+        // Each cell takes a two-byte cell pointer too
+        let size = cell.len() + 2;
+        if size > capacity {
+            bail!("a cell of {} bytes doesn't fit on a page", cell.len());
+        }
+
+        let run = runs.last_mut().expect("there's always a run");
+        if used + size <= capacity {
+            run.push(cell);
+            used += size;
+        } else {
+            separators.push(cell);
+            runs.push(vec![]);
+            used = 0;
+        }
+    }
+
+    // The last run can't be left empty: it takes the separator before it instead, and the run
+    // before that gives up its last cell to be the separator. That run is a full page of cells.
+    if runs.len() > 1 && runs.last().is_some_and(Vec::is_empty) {
+        let last_separator = separators.pop().expect("runs are separated");
+        let previous = runs.len() - 2;
+        let new_separator = runs[previous]
+            .pop()
+            .expect("only the last run can be empty");
+        separators.push(new_separator);
+        runs.last_mut()
+            .expect("there's always a run")
+            .push(last_separator);
+    }
+
+    Ok((runs, separators))
+}
+
+/// Writes a b-tree page of `page_type` holding `cells`, whose page header starts at
+/// `header_start`. Interior pages also have a right-most pointer.
+pub(crate) fn write_page(
+    page: &mut [u8],
+    header_start: usize,
+    page_type: BTreePage,
+    cells: &[Cell],
+    right_most_pointer: Option<u32>,
+) -> Result<()> {
+    let mut pointer = header_start + page_type.header_size();
+    page[header_start] = page_type as u8;
+    page[header_start + 3..header_start + 5].copy_from_slice(&(cells.len() as u16).to_be_bytes());
+    if let Some(right_most_pointer) = right_most_pointer {
+        page[header_start + 8..header_start + 12]
+            .copy_from_slice(&right_most_pointer.to_be_bytes());
+    }
+
+    let mut content_start = page.len();
+    for cell in cells {
+        if content_start < pointer + 2 + cell.len() {
+            bail!("{} cells don't fit on a page", cells.len());
+        }
+        content_start -= cell.len();
+        page[content_start..content_start + cell.len()].copy_from_slice(cell);
+        page[pointer..pointer + 2].copy_from_slice(&(content_start as u16).to_be_bytes());
+        pointer += 2;
+    }
+    // A 65536 byte page with no cells has its content area start at 65536, which is stored as 0
+    page[header_start + 5..header_start + 7].copy_from_slice(&(content_start as u16).to_be_bytes());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_cells() {
+        let cells = |sizes: &[usize]| sizes.iter().map(|&n| vec![n as u8; n]).collect::<Vec<_>>();
+
+        let (runs, separators) = split_cells(cells(&[8, 8, 8, 8, 8]), 20).unwrap();
+        assert_eq!(runs, [cells(&[8, 8]), cells(&[8, 8])]);
+        assert_eq!(separators, cells(&[8]));
+
+        // Rather than end with an empty run, the last separator moves into it
+        let (runs, separators) = split_cells(cells(&[1, 2, 3]), 10).unwrap();
+        assert_eq!(runs, [cells(&[1]), cells(&[3])]);
+        assert_eq!(separators, cells(&[2]));
+
+        let (runs, separators) = split_cells(vec![], 10).unwrap();
+        assert_eq!((runs.len(), separators.len()), (1, 0));
+        assert!(split_cells(cells(&[9]), 10).is_err());
+    }
+
+    #[test]
+    fn test_write_table() {
+        use crate::{
+            database::Database, header::DatabaseHeader, record::encode_record, value::Value,
+        };
+
+        let mut writer = BtreeWriter::new(512, 2);
+        let cells = (1..=2000)
+            .map(|row_id| {
+                let payload = encode_record(&[Value::Text("x".repeat(row_id as usize % 50))], 4);
+                Ok((row_id * 3, writer.table_cell(row_id * 3, &payload)?))
+            })
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        let root_page = writer.write_table(cells, None).unwrap();
+        writer.write_table(vec![], Some(1)).unwrap();
+
+        let mut header = DatabaseHeader::new(512);
+        header.page_count = writer.page_count();
+        let mut bytes = vec![0; header.page_count as usize * 512];
+        for (page_num, page) in &writer.pages {
+            let offset = (*page_num - 1) as usize * 512;
+            bytes[offset..offset + 512].copy_from_slice(page);
+        }
+        bytes[..DATABASE_HEADER_SIZE].copy_from_slice(&header.to_bytes());
+
+        let database = Database::open_bytes(bytes).unwrap();
+        assert!(database.btree_depth(root_page).unwrap() >= 3);
+        let records = database.read_table(root_page).unwrap();
+        assert_eq!(records.len(), 2000);
+        assert!(records
+            .iter()
+            .zip(1..)
+            .all(|(record, i)| record.row_id == i * 3));
+        assert_eq!(records[6].value(0), Value::Text("x".repeat(7)));
+        assert_eq!(
+            database
+                .find_row(root_page, 2001 * 3)
+                .unwrap()
+                .map(|r| r.row_id),
+            None
+        );
+        assert_eq!(
+            database.find_row(root_page, 999).unwrap().map(|r| r.row_id),
+            Some(999)
+        );
+    }
+}
@@ -0,0 +1,244 @@
+use crate::{header::BTreePage, overflow, varint::parse_varint_from_reader};
+use anyhow::{bail, Result};
+use std::io::{Read, Seek, SeekFrom};
+
+/// A cell from an index b-tree page, as described here:
+/// [b-tree_pages](https://www.sqlite.org/fileformat.html#b_tree_pages)
+///
+/// Index cells have no rowid of their own; the payload is a record whose last column is the
+/// rowid of the table row being indexed.
+#[derive(Debug, PartialEq)]
+pub struct IndexCell {
+    /// Only present on interior index pages
+    pub left_child_page: Option<u32>,
+    pub payload_size: usize,
+    pub payload: Vec<u8>,
+}
+
+/// A cell from an interior table b-tree page. Every rowid in the left child's subtree is less than
+/// or equal to `row_id`.
+#[derive(Debug, PartialEq)]
+pub struct TableInteriorCell {
+    pub left_child_page: u32,
+    pub row_id: i64,
+}
+
+/// Reads a cell from an InteriorTable page: (left-child pointer, rowid)
+pub fn read_table_interior_cell<R: Read>(reader: &mut R) -> Result<TableInteriorCell> {
+    let mut left_child_page = [0; 4];
+    reader.read_exact(&mut left_child_page)?;
+
+    let (row_id, _bytes_read) = parse_varint_from_reader(reader)?;
+
+    Ok(TableInteriorCell {
+        left_child_page: u32::from_be_bytes(left_child_page),
+        row_id,
+    })
+}
+
+/// Reads every cell of an interior table page, given its cell pointers (which are relative to the
+/// start of the page).
+pub fn read_table_interior_cells<R: Read + Seek>(
+    reader: &mut R,
+    page_start: u64,
+    cell_pointers: &[u16],
+) -> Result<Vec<TableInteriorCell>> {
+    let mut cells = Vec::with_capacity(cell_pointers.len());
+
+    for offset in cell_pointers {
+        reader.seek(SeekFrom::Start(page_start + *offset as u64))?;
+        cells.push(read_table_interior_cell(reader)?);
+    }
+
+    Ok(cells)
+}
+
+/// Reads a cell from a LeafIndex page: (payload-size, payload)
+pub fn read_index_leaf_cell<R: Read + Seek>(reader: &mut R, usable_size: u32) -> Result<IndexCell> {
+    let (payload_size, _bytes_read) = parse_varint_from_reader(reader)?;
+    let payload_size = checked_payload_size(payload_size)?;
+    let payload = read_index_payload(reader, payload_size, usable_size)?;
+
+    Ok(IndexCell {
+        left_child_page: None,
+        payload_size,
+        payload,
+    })
+}
+
+/// Reads a cell from an InteriorIndex page: (left-child pointer, payload-size, payload)
+pub fn read_index_interior_cell<R: Read + Seek>(
+    reader: &mut R,
+    usable_size: u32,
+) -> Result<IndexCell> {
+    let mut left_child_page = [0; 4];
+    reader.read_exact(&mut left_child_page)?;
+
+    let (payload_size, _bytes_read) = parse_varint_from_reader(reader)?;
+    let payload_size = checked_payload_size(payload_size)?;
+    let payload = read_index_payload(reader, payload_size, usable_size)?;
+
+    Ok(IndexCell {
+        left_child_page: Some(u32::from_be_bytes(left_child_page)),
+        payload_size,
+        payload,
+    })
+}
+
+/// Reads every cell of an index page, given its cell pointers (which are relative to the start of
+/// the page).
+pub fn read_index_cells<R: Read + Seek>(
+    reader: &mut R,
+    page_start: u64,
+    is_interior: bool,
+    cell_pointers: &[u16],
+    usable_size: u32,
+) -> Result<Vec<IndexCell>> {
+    let mut cells = Vec::with_capacity(cell_pointers.len());
+
+    for offset in cell_pointers {
+        reader.seek(SeekFrom::Start(page_start + *offset as u64))?;
+
+        let cell = if is_interior {
+            read_index_interior_cell(reader, usable_size)?
+        } else {
+            read_index_leaf_cell(reader, usable_size)?
+        };
+        if reader.stream_position()? > page_start + usable_size as u64 {
+            bail!("malformed cell: index cell at {offset} runs past the end of its page");
+        }
+        cells.push(cell);
+    }
+
+    Ok(cells)
+}
+
+/// A payload size read from a cell, which a corrupt cell can make negative
+pub(crate) fn checked_payload_size(payload_size: i64) -> Result<usize> {
+    match usize::try_from(payload_size) {
+        Ok(payload_size) => Ok(payload_size),
+        Err(_) => bail!("malformed cell: payload size {payload_size}"),
+    }
+}
+
+/// Reads the payload of an index cell, following its overflow chain if it has one. The reader is
+/// left at the end of the cell, as though the payload were all in it.
+fn read_index_payload<R: Read + Seek>(
+    reader: &mut R,
+    payload_size: usize,
+    usable_size: u32,
+) -> Result<Vec<u8>> {
+    // Leaf and interior index pages spill to overflow pages at the same size
+    let split = overflow::thresholds(usable_size, payload_size as u64, BTreePage::LeafIndex)?;
+
+    let mut payload = vec![0; split.local];
+    reader.read_exact(&mut payload)?;
+    if split.overflow > 0 {
+        let mut first_page = [0; 4];
+        reader.read_exact(&mut first_page)?;
+        let cell_end = reader.stream_position()?;
+        payload.reserve_exact(split.overflow);
+        overflow::read_overflow(
+            reader,
+            usable_size,
+            u32::from_be_bytes(first_page),
+            split.overflow,
+            &mut payload,
+        )?;
+        reader.seek(SeekFrom::Start(cell_end))?;
+    }
+
+    Ok(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn test_read_table_interior_cell() {
+        let bytes = vec![0, 0, 0, 5, 129, 3];
+        let mut reader = Cursor::new(bytes);
+
+        let cell = read_table_interior_cell(&mut reader).unwrap();
+        assert_eq!(
+            cell,
+            TableInteriorCell {
+                left_child_page: 5,
+                row_id: 131,
+            }
+        );
+    }
+
+    #[test]
+    fn test_read_index_leaf_cell() {
+        // Payload of 5 bytes: a record header (header size, Int8, Int8), followed by the indexed
+        // value 42 and rowid 7. The trailing byte belongs to whatever comes next on the page.
+        let bytes = vec![5, 3, 1, 1, 42, 7, 99];
+        let mut reader = Cursor::new(bytes);
+
+        let cell = read_index_leaf_cell(&mut reader, 4096).unwrap();
+        assert_eq!(
+            cell,
+            IndexCell {
+                left_child_page: None,
+                payload_size: 5,
+                payload: vec![3, 1, 1, 42, 7],
+            }
+        );
+    }
+
+    #[test]
+    fn test_read_index_interior_cell() {
+        let bytes = vec![0, 0, 1, 2, 3, 2, 1, 9];
+        let mut reader = Cursor::new(bytes);
+
+        let cell = read_index_interior_cell(&mut reader, 4096).unwrap();
+        assert_eq!(
+            cell,
+            IndexCell {
+                left_child_page: Some(258),
+                payload_size: 3,
+                payload: vec![2, 1, 9],
+            }
+        );
+    }
+
+    #[test]
+    fn test_read_index_cells_from_page() {
+        // A fake page starting at offset 2, with cells at page offsets 5 and 2
+        let bytes = vec![0xff, 0xff, 0xff, 0xff, 2, 1, 5, 2, 1, 6];
+        let mut reader = Cursor::new(bytes);
+
+        let cells = read_index_cells(&mut reader, 2, false, &[5, 2], 4096).unwrap();
+        assert_eq!(
+            cells.iter().map(|c| c.payload.clone()).collect::<Vec<_>>(),
+            vec![vec![1, 6], vec![1, 5]]
+        );
+    }
+
+    #[test]
+    fn test_read_index_leaf_cell_overflow() {
+        // With 512 byte pages, a 130 byte payload keeps 39 bytes in the cell, followed by the
+        // number of the overflow page that holds the other 91
+        let payload: Vec<u8> = (0..130).collect();
+        let mut bytes = vec![0x81, 0x02];
+        bytes.extend(&payload[..39]);
+        bytes.extend([0, 0, 0, 2]);
+        bytes.resize(512, 0xff);
+        bytes.extend([0, 0, 0, 0]);
+        bytes.extend(&payload[39..]);
+        let mut reader = Cursor::new(bytes.clone());
+
+        let cell = read_index_leaf_cell(&mut reader, 512).unwrap();
+        assert_eq!(cell.payload, payload);
+        assert_eq!(reader.position(), 45);
+
+        // A chain that ends too soon
+        bytes.truncate(512 + 50);
+        let mut reader = Cursor::new(bytes);
+        assert!(read_index_leaf_cell(&mut reader, 512).is_err());
+    }
+}
@@ -0,0 +1,233 @@
+use crate::{
+    database::{Database, MAX_BTREE_DEPTH},
+    header::{BTreePage, PageHeader, DATABASE_HEADER_SIZE},
+    lock::lock_byte_page,
+    overflow, varint,
+};
+use anyhow::{bail, Result};
+
+/// How many times each page has been reached so far, and what's been found wrong
+struct PageUsage {
+    /// Indexed by page number, so the first is unused
+    references: Vec<u32>,
+    problems: Vec<String>,
+}
+
+impl PageUsage {
+    /// Counts a reference to `page_num`, returning whether it's the first, and so whether the
+    /// page should be read. Pages past the end of the database, or reached again, are problems.
+    fn reference(&mut self, page_num: u32, from: &str) -> bool {
+        let Some(references) = self.references.get_mut(page_num as usize) else {
+            self.problems
+                .push(format!("{from}: invalid page number {page_num}"));
+            return false;
+        };
+        *references += 1;
+        if *references > 1 {
+            self.problems
+                .push(format!("{from}: 2nd reference to page {page_num}"));
+            return false;
+        }
+
+        true
+    }
+}
+
+impl Database {
+    /// Checks the database as PRAGMA integrity_check does in part: that every page is used by
+    /// exactly one b-tree, overflow chain or the freelist, and that every table's rows can be
+    /// read. Returns what's wrong, with nothing meaning that it's all fine.
+    pub fn check(&self) -> Result<Vec<String>> {
+        let _lock = self.read_lock()?;
+        let header = self.header()?;
+        if header.largest_root_page != 0 {
+            bail!("Unhandled auto-vacuum database: pointer map pages aren't checked");
+        }
+
+        let mut usage = PageUsage {
+            references: vec![0; self.page_count as usize + 1],
+            problems: vec![],
+        };
+        // The lock-byte page counts as used already, so that a pointer to it is a 2nd reference
+        if let Some(references) = usage
+            .references
+            .get_mut(lock_byte_page(self.page_size) as usize)
+        {
+            *references = 1;
+        }
+        self.check_btree(1, "sqlite_schema", &mut usage)?;
+        let mut progress = self.statement_progress();
+        for schema in self.schema()? {
+            // Views and triggers have no b-tree
+            if schema.root_page == 0 {
+                continue;
+            }
+            self.check_btree(schema.root_page, &schema.name, &mut usage)?;
+            if schema.is_table() {
+                for row in self.table_cursor(schema.root_page) {
+                    progress.row_read()?;
+                    if let Err(err) = row {
+                        usage.problems.push(format!("{}: {err:#}", schema.name));
+                        break;
+                    }
+                }
+            }
+        }
+        self.check_freelist(
+            header.first_freelist_trunk_page,
+            header.freelist_page_count,
+            &mut usage,
+        )?;
+
+        for (page_num, references) in usage.references.iter().enumerate().skip(1) {
+            if *references == 0 {
+                usage.problems.push(format!("Page {page_num}: never used"));
+            }
+        }
+
+        Ok(usage.problems)
+    }
+
+    /// Counts the pages of the b-tree rooted at `root_page`, and of its overflow chains
+    fn check_btree(&self, root_page: u32, name: &str, usage: &mut PageUsage) -> Result<()> {
+        let mut pages_to_visit = vec![(root_page, 0)];
+        while let Some((page_num, depth)) = pages_to_visit.pop() {
+            let from = format!("{name} page {page_num}");
+            if depth > MAX_BTREE_DEPTH {
+                usage.problems.push(format!("{from}: b-tree is too deep"));
+                continue;
+            }
+            if !usage.reference(page_num, name) {
+                continue;
+            }
+            match self.check_page(page_num, &from, usage) {
+                Ok(children) => {
+                    pages_to_visit.extend(children.into_iter().map(|child| (child, depth + 1)))
+                }
+                Err(err) => usage.problems.push(format!("{from}: {err:#}")),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Counts the overflow pages of each cell on the page, returning its child pages
+    fn check_page(&self, page_num: u32, from: &str, usage: &mut PageUsage) -> Result<Vec<u32>> {
+        let bytes = self.page_bytes(page_num)?;
+        let header_start = if page_num == 1 {
+            DATABASE_HEADER_SIZE
+        } else {
+            0
+        };
+        let header = PageHeader::parse(&bytes[header_start..])?;
+        let page_type = header.page_type;
+        let cell_pointers_start = header_start + page_type.header_size();
+        let cell_count = header.number_of_cells as usize;
+        let Some(cell_pointers) =
+            bytes.get(cell_pointers_start..cell_pointers_start + 2 * cell_count)
+        else {
+            bail!("more cell pointers than fit on the page");
+        };
+
+        let mut children = vec![];
+        for pointer in cell_pointers.chunks(2) {
+            let offset = u16::from_be_bytes([pointer[0], pointer[1]]) as usize;
+            let cell = bytes.get(offset..).unwrap_or_default();
+            if cell.len() < 4 {
+                bail!("cell at offset {offset} is past the end of the page");
+            }
+            if page_type.is_interior() {
+                children.push(u32::from_be_bytes(cell[..4].try_into()?));
+            }
+            if page_type == BTreePage::InteriorTable {
+                continue;
+            }
+
+            let mut payload_start = if page_type.is_interior() { 4 } else { 0 };
+            let (payload_size, length) = varint::parse_varint(&cell[payload_start..]);
+            payload_start += length;
+            if page_type == BTreePage::LeafTable {
+                payload_start += varint::parse_varint(&cell[payload_start..]).1;
+            }
+            let split = overflow::thresholds(self.page_size, payload_size as u64, page_type)?;
+            if split.overflow > 0 {
+                let pointer_start = payload_start + split.local;
+                let Some(pointer) = cell.get(pointer_start..pointer_start + 4) else {
+                    bail!("cell at offset {offset} is past the end of the page");
+                };
+                let first_page = u32::from_be_bytes(pointer.try_into()?);
+                let page_count = split.overflow.div_ceil(self.page_size as usize - 4);
+                self.check_overflow(first_page, page_count, from, usage)?;
+            }
+        }
+        children.extend(header.right_most_pointer);
+
+        Ok(children)
+    }
+
+    /// Counts the `page_count` pages of the overflow chain starting at `page_num`
+    fn check_overflow(
+        &self,
+        mut page_num: u32,
+        page_count: usize,
+        from: &str,
+        usage: &mut PageUsage,
+    ) -> Result<()> {
+        for remaining in (1..=page_count).rev() {
+            if page_num == 0 {
+                usage
+                    .problems
+                    .push(format!("{from}: overflow chain is {remaining} pages short"));
+                return Ok(());
+            }
+            if !usage.reference(page_num, from) {
+                return Ok(());
+            }
+            page_num = u32::from_be_bytes(self.page_bytes(page_num)?[..4].try_into()?);
+        }
+        if page_num != 0 {
+            usage
+                .problems
+                .push(format!("{from}: overflow chain continues past its end"));
+        }
+
+        Ok(())
+    }
+
+    /// Counts the trunk and leaf pages of the freelist
+    fn check_freelist(
+        &self,
+        mut trunk_page: u32,
+        expected: u32,
+        usage: &mut PageUsage,
+    ) -> Result<()> {
+        let mut pages = 0;
+        while trunk_page != 0 {
+            if !usage.reference(trunk_page, "freelist") {
+                break;
+            }
+            pages += 1;
+            let bytes = self.page_bytes(trunk_page)?;
+            let leaf_count = u32::from_be_bytes(bytes[4..8].try_into()?) as usize;
+            let Some(leaves) = bytes.get(8..8 + 4 * leaf_count) else {
+                usage.problems.push(format!(
+                    "freelist trunk page {trunk_page}: more leaves than fit on the page"
+                ));
+                break;
+            };
+            for leaf in leaves.chunks(4) {
+                if usage.reference(u32::from_be_bytes(leaf.try_into()?), "freelist") {
+                    pages += 1;
+                }
+            }
+            trunk_page = u32::from_be_bytes(bytes[..4].try_into()?);
+        }
+        if pages != expected {
+            usage.problems.push(format!(
+                "Freelist: size is {pages} but should be {expected}"
+            ));
+        }
+
+        Ok(())
+    }
+}
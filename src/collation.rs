@@ -0,0 +1,201 @@
+use crate::value::Value;
+use anyhow::{bail, Result};
+use std::{cmp::Ordering, fmt, sync::Arc};
+
+/// The implementation of a collation: how it orders two pieces of text
+pub type CollationFn = dyn Fn(&str, &str) -> Ordering + Send + Sync;
+
+/// A collating sequence, which decides how text compares when it's tested for equality, sorted or
+/// looked up in an index. Only text is collated: other values compare as they always do.
+pub struct Collation {
+    pub name: String,
+    compare: Box<CollationFn>,
+}
+
+impl fmt::Debug for Collation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "COLLATE {}", self.name)
+    }
+}
+
+impl PartialEq for Collation {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl Collation {
+    /// Orders two values like Value::compare, except that text is compared by the collation
+    pub fn compare_values(&self, a: &Value, b: &Value) -> Ordering {
+        match (a, b) {
+            (Value::Text(a), Value::Text(b)) => (self.compare)(a, b),
+            (a, b) => a.compare(b),
+        }
+    }
+}
+
+/// The collations SQL can name with COLLATE: the built-in BINARY, NOCASE and RTRIM, plus any
+/// registered by library users. A name registered again refers to the later collation.
+#[derive(Clone)]
+pub struct CollationRegistry {
+    collations: Vec<Arc<Collation>>,
+}
+
+impl Default for CollationRegistry {
+    fn default() -> Self {
+        let mut registry = CollationRegistry { collations: vec![] };
+
+        registry.register("BINARY", |a, b| a.cmp(b));
+        // Like SQLite, only ASCII letters are folded
+        registry.register("NOCASE", |a, b| {
+            a.bytes()
+                .map(|byte| byte.to_ascii_lowercase())
+                .cmp(b.bytes().map(|byte| byte.to_ascii_lowercase()))
+        });
+        registry.register("RTRIM", |a, b| {
+            a.trim_end_matches(' ').cmp(b.trim_end_matches(' '))
+        });
+
+        registry
+    }
+}
+
+impl CollationRegistry {
+    /// A registry holding just the built-in collations
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register<F>(&mut self, name: &str, compare: F)
+    where
+        F: Fn(&str, &str) -> Ordering + Send + Sync + 'static,
+    {
+        self.collations.push(Arc::new(Collation {
+            name: name.to_uppercase(),
+            compare: Box::new(compare),
+        }));
+    }
+
+    /// Registers `name` as a collation like NOCASE that folds the case of every letter, not just
+    /// ASCII ones, so that "ÉCOLE" and "école" are equal. Registered as NOCASE itself, it replaces
+    /// the built-in one, though an index SQLite built with NOCASE is then searched in a different
+    /// order from the one its entries are in.
+    #[cfg(feature = "unicode")]
+    pub fn register_unicode_nocase(&mut self, name: &str) {
+        self.register(name, |a, b| fold_case(a).cmp(fold_case(b)));
+    }
+
+    /// Looks up a collation by name, case-insensitively
+    pub fn find(&self, name: &str) -> Result<Arc<Collation>> {
+        match self
+            .collations
+            .iter()
+            .rev()
+            .find(|collation| collation.name.eq_ignore_ascii_case(name))
+        {
+            Some(collation) => Ok(Arc::clone(collation)),
+            None => bail!("no such collation sequence: {name}"),
+        }
+    }
+}
+
+/// The characters of `text` with their case folded, for comparing without regard to it: "ß" and
+/// "SS" both fold to "ss"
+#[cfg(feature = "unicode")]
+fn fold_case(text: &str) -> impl Iterator<Item = char> + '_ {
+    text.chars()
+        .flat_map(char::to_uppercase)
+        .flat_map(char::to_lowercase)
+}
+
+/// Whether two collation names, as declared on columns or written in queries, are the same
+/// collation. A column without one uses BINARY.
+pub fn same_collation(a: Option<&str>, b: Option<&str>) -> bool {
+    a.unwrap_or("BINARY")
+        .eq_ignore_ascii_case(b.unwrap_or("BINARY"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_collations() {
+        let registry = CollationRegistry::new();
+        let compare = |name: &str, a: &str, b: &str| {
+            registry
+                .find(name)
+                .unwrap()
+                .compare_values(&Value::Text(a.to_string()), &Value::Text(b.to_string()))
+        };
+
+        assert_eq!(compare("binary", "PIZZA", "pizza"), Ordering::Less);
+        assert_eq!(compare("NoCase", "PIZZA", "pizza"), Ordering::Equal);
+        assert_eq!(compare("nocase", "Apple", "banana"), Ordering::Less);
+        assert_eq!(compare("rtrim", "pizza  ", "pizza"), Ordering::Equal);
+        assert_eq!(compare("rtrim", " pizza", "pizza"), Ordering::Less);
+
+        let nocase = registry.find("NOCASE").unwrap();
+        assert_eq!(
+            nocase.compare_values(&Value::Integer(1), &Value::Text("a".to_string())),
+            Ordering::Less
+        );
+        assert!(registry.find("klingon").is_err());
+    }
+
+    #[test]
+    fn test_register() {
+        let mut registry = CollationRegistry::new();
+        registry.register("reverse", |a, b| b.cmp(a));
+        registry.register("nocase", |a, b| a.len().cmp(&b.len()));
+
+        let text = |s: &str| Value::Text(s.to_string());
+        let reverse = registry.find("REVERSE").unwrap();
+        assert_eq!(
+            reverse.compare_values(&text("a"), &text("b")),
+            Ordering::Greater
+        );
+        let nocase = registry.find("NOCASE").unwrap();
+        assert_eq!(
+            nocase.compare_values(&text("b"), &text("AA")),
+            Ordering::Less
+        );
+        assert!(same_collation(None, Some("binary")));
+        assert!(!same_collation(Some("NOCASE"), None));
+    }
+
+    #[cfg(feature = "unicode")]
+    #[test]
+    fn test_register_unicode_nocase() {
+        let mut registry = CollationRegistry::new();
+        registry.register_unicode_nocase("UNOCASE");
+
+        let text = |s: &str| Value::Text(s.to_string());
+        let compare = |registry: &CollationRegistry, name: &str, a: &str, b: &str| {
+            registry
+                .find(name)
+                .unwrap()
+                .compare_values(&text(a), &text(b))
+        };
+        assert_eq!(
+            compare(&registry, "unocase", "ÉCOLE", "école"),
+            Ordering::Equal
+        );
+        assert_eq!(
+            compare(&registry, "unocase", "Straße", "STRASSE"),
+            Ordering::Equal
+        );
+        assert_eq!(compare(&registry, "unocase", "éa", "ÉB"), Ordering::Less);
+        // The built-in NOCASE is left as it is, folding only ASCII letters
+        assert_eq!(
+            compare(&registry, "nocase", "ÉCOLE", "école"),
+            Ordering::Less
+        );
+
+        registry.register_unicode_nocase("NOCASE");
+        assert_eq!(
+            compare(&registry, "nocase", "ÉCOLE", "école"),
+            Ordering::Equal
+        );
+    }
+}
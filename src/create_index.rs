@@ -0,0 +1,354 @@
+use crate::{
+    btree_writer::{write_page, BtreeWriter},
+    collation::Collation,
+    database::Database,
+    header::{BTreePage, DatabaseHeader, DATABASE_HEADER_SIZE},
+    lock::LockLevel,
+    overflow,
+    query_parser::{CreateIndex, CreateTable},
+    record::encode_record,
+    value::Value,
+    varint,
+    vm::ColumnRef,
+};
+use anyhow::{bail, Context, Result};
+use itertools::Itertools;
+use std::{cmp::Ordering, iter, sync::Arc};
+
+/// The collation each column of an index compares its values by, if it has one
+pub(crate) type Collations = Vec<Option<Arc<Collation>>>;
+
+impl Database {
+    /// Runs CREATE INDEX: reads the table's rows, writes an index b-tree of their keys after the
+    /// last page of the database, and adds the index to sqlite_schema with `sql` as its SQL.
+    ///
+    /// Page 1, with the schema and the header, goes in the rollback journal before it's
+    /// overwritten, so a crash part way leaves the database to be rolled back to what it was.
+    pub fn create_index(&mut self, create_index: &CreateIndex, sql: &str) -> Result<()> {
+        self.write_locked(|database| database.write_index(create_index, sql))
+    }
+
+    /// Runs `write` with the database up to date and locked against other connections, which
+    /// write_pages makes an exclusive lock when it's time to write
+    pub(crate) fn write_locked(&mut self, write: impl FnOnce(&Self) -> Result<()>) -> Result<()> {
+        if self.options.read_only || self.options.immutable {
+            bail!("attempt to write a readonly database");
+        }
+
+        self.acquire_read_lock()?;
+        let result = self
+            .refresh()
+            .and_then(|()| self.check_no_wal())
+            .and_then(|()| write(self));
+        // Back to the read lock, which other statements may still be holding. A transaction
+        // keeps what it's written to itself until it's over.
+        let unlocked = if self.options.nolock || self.in_transaction {
+            Ok(())
+        } else {
+            self.database_file.unlock(LockLevel::Shared)
+        };
+        let released = self.release_read_lock();
+        result?;
+        unlocked?;
+        released?;
+
+        self.refresh()
+    }
+
+    /// Fails if SQLite reads the database through a write-ahead log: in WAL mode, or with a WAL
+    /// file left over that hasn't been checkpointed. Pages written straight to the database file
+    /// would be hidden by the log's newer copies of them, or be overwritten when it's
+    /// checkpointed.
+    pub(crate) fn check_no_wal(&self) -> Result<()> {
+        let header = self.header()?;
+        if header.write_version == 2 || header.read_version == 2 {
+            bail!("Unhandled write to a database in WAL mode");
+        }
+
+        #[cfg(feature = "fs")]
+        if !self.options.path.as_os_str().is_empty() {
+            let mut wal_path = self.options.path.clone().into_os_string();
+            wal_path.push("-wal");
+            if std::fs::metadata(&wal_path).is_ok_and(|metadata| metadata.len() > 0) {
+                bail!(
+                    "Unhandled write to a database with a WAL file: {}",
+                    wal_path.to_string_lossy()
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_index(&self, create_index: &CreateIndex, sql: &str) -> Result<()> {
+        let schema = self.schema()?;
+        let index_name = &create_index.index_name;
+        if index_name.to_ascii_lowercase().starts_with("sqlite_") {
+            bail!("object name reserved for internal use: {index_name}");
+        }
+        if let Some(existing) = schema
+            .iter()
+            .find(|s| s.name.eq_ignore_ascii_case(index_name))
+        {
+            if !existing.is_index() {
+                bail!("there is already a {} named {index_name}", existing.kind);
+            }
+            if create_index.if_not_exists {
+                return Ok(());
+            }
+            bail!("index {index_name} already exists");
+        }
+
+        let Some(table) = schema
+            .iter()
+            .find(|s| s.is_table() && s.name.eq_ignore_ascii_case(&create_index.table_name))
+        else {
+            bail!("no such table: {}", create_index.table_name);
+        };
+        if !create_index.simple_columns || create_index.descending.contains(&true) {
+            bail!(
+                "Unhandled index columns: only plain column names, in ascending order, can be \
+                 indexed"
+            );
+        }
+        let create_table = table.create_table()?;
+        let (entries, collations) =
+            self.index_entries(table.root_page, &create_table, create_index)?;
+
+        if create_index.unique {
+            let key_len = create_index.columns.len();
+            // NULLs are never equal to each other, so they don't make a key a duplicate
+            let duplicate = entries.iter().tuple_windows().any(|(a, b)| {
+                !a[..key_len].contains(&Value::Null)
+                    && compare_entries(&collations, &a[..key_len], &b[..key_len]) == Ordering::Equal
+            });
+            if duplicate {
+                let columns = create_index
+                    .columns
+                    .iter()
+                    .map(|column| format!("{}.{column}", table.name))
+                    .join(", ");
+                bail!("UNIQUE constraint failed: {columns}");
+            }
+        }
+
+        let (mut first_page, mut header) = self.first_page_for_writing()?;
+        let mut writer = BtreeWriter::new(self.page_size, self.page_count + 1);
+        let cells = entries
+            .iter()
+            .map(|entry| writer.index_cell(&encode_record(entry, header.schema_format)))
+            .collect::<Result<Vec<_>>>()?;
+        let root_page = writer.write_index(cells)?;
+
+        self.add_schema_row(
+            &mut first_page,
+            &header,
+            &[
+                Value::Text("index".to_string()),
+                Value::Text(index_name.clone()),
+                Value::Text(table.name.clone()),
+                Value::Integer(root_page as i64),
+                Value::Text(sql.to_string()),
+            ],
+        )?;
+        header.page_count = writer.page_count();
+        header.schema_cookie = header.schema_cookie.wrapping_add(1);
+
+        self.write_pages(&writer.pages, first_page, header)
+    }
+
+    /// The entries of an index on the table with its root on `table_root_page`: each row's
+    /// values of the indexed columns followed by its rowid, sorted like SQLite sorts them. The
+    /// collation each column is compared by comes with them.
+    pub(crate) fn index_entries(
+        &self,
+        table_root_page: u32,
+        create_table: &CreateTable,
+        create_index: &CreateIndex,
+    ) -> Result<(Vec<Vec<Value>>, Collations)> {
+        let mut columns = vec![];
+        // Each column is ordered by the collation the index names for it, or else its own
+        let mut collations = vec![];
+        for (name, collation) in create_index.columns.iter().zip(&create_index.collations) {
+            let Some(index) = create_table
+                .columns
+                .iter()
+                .position(|column| column.name.eq_ignore_ascii_case(name))
+            else {
+                bail!("no such column: {name}");
+            };
+            // The record holds NULL for an INTEGER PRIMARY KEY, whose value is the rowid
+            columns.push(if create_table.rowid_alias == Some(index) {
+                ColumnRef::RowId
+            } else {
+                ColumnRef::Index(index)
+            });
+            let collation = collation
+                .as_ref()
+                .or(create_table.columns[index].collation.as_ref());
+            collations.push(
+                collation
+                    .map(|name| self.collations.find(name))
+                    .transpose()?,
+            );
+        }
+
+        let mut entries = vec![];
+        let mut progress = self.statement_progress();
+        for record in self.table_rows(table_root_page, create_table)? {
+            // Nothing has been written yet, so an interrupt leaves the database as it was
+            progress.row_read()?;
+            let record = record?;
+            let mut entry = columns
+                .iter()
+                .map(|column| match column {
+                    ColumnRef::RowId => Value::Integer(record.row_id),
+                    ColumnRef::Index(index) => record.value(*index),
+                })
+                .collect::<Vec<_>>();
+            entry.push(Value::Integer(record.row_id));
+            entries.push(entry);
+        }
+        entries.sort_by(|a, b| compare_entries(&collations, a, b));
+
+        Ok((entries, collations))
+    }
+
+    /// Page 1, and the header on it, to be written again with a changed schema or header
+    pub(crate) fn first_page_for_writing(&self) -> Result<(Vec<u8>, DatabaseHeader)> {
+        let first_page = self.page_bytes(1)?.into_owned();
+        let header = DatabaseHeader::parse(&first_page[..DATABASE_HEADER_SIZE])?;
+        if header.largest_root_page != 0 {
+            bail!("Unhandled auto-vacuum database: new root pages would need pointer map entries");
+        }
+        if first_page[DATABASE_HEADER_SIZE] != BTreePage::LeafTable as u8 {
+            bail!("Unhandled sqlite_schema: it has outgrown page 1");
+        }
+
+        Ok((first_page, header))
+    }
+
+    /// Writes sqlite_schema again on `first_page`, with its existing rows and a new one
+    pub(crate) fn add_schema_row(
+        &self,
+        first_page: &mut [u8],
+        header: &DatabaseHeader,
+        row: &[Value],
+    ) -> Result<()> {
+        let schema_records = self.read_table(1)?;
+        let mut schema_cells = schema_records
+            .iter()
+            .map(|record| schema_cell(record.row_id, &record.values(), header))
+            .collect::<Result<Vec<_>>>()?;
+        let row_id = schema_records.last().map_or(1, |record| record.row_id + 1);
+        schema_cells.push(schema_cell(row_id, row, header)?);
+        first_page[DATABASE_HEADER_SIZE..].fill(0);
+        write_page(
+            first_page,
+            DATABASE_HEADER_SIZE,
+            BTreePage::LeafTable,
+            &schema_cells,
+            None,
+        )
+        .with_context(|| {
+            format!(
+                "Unhandled sqlite_schema: the new {} doesn't fit on page 1",
+                row[0]
+            )
+        })?;
+
+        Ok(())
+    }
+
+    /// Writes `pages`, and then page 1 with `header` on it, counting the change in the header.
+    /// What they overwrite is journaled first, so they're written in full or not at all.
+    pub(crate) fn write_pages(
+        &self,
+        pages: &[(u32, Vec<u8>)],
+        mut first_page: Vec<u8>,
+        mut header: DatabaseHeader,
+    ) -> Result<()> {
+        header.file_change_counter = header.file_change_counter.wrapping_add(1);
+        header.version_valid_for = header.file_change_counter;
+        first_page[..DATABASE_HEADER_SIZE].copy_from_slice(&header.to_bytes());
+
+        if !self.options.nolock {
+            self.database_file
+                .lock(LockLevel::Exclusive, self.options.busy_timeout)?;
+        }
+        self.write_atomically(|| {
+            self.journal_pages(iter::once(1).chain(pages.iter().map(|(page_num, _)| *page_num)))?;
+            for (page_num, bytes) in pages {
+                let offset = (*page_num - 1) as u64 * self.page_size as u64;
+                self.database_file.write_at(offset, bytes)?;
+            }
+            self.database_file.write_at(0, &first_page)
+        })
+    }
+}
+
+/// Orders index entries by their values in turn, comparing the text of each key column by its
+/// collation. The rowid at the end of an entry has none.
+pub(crate) fn compare_entries(
+    collations: &[Option<Arc<Collation>>],
+    a: &[Value],
+    b: &[Value],
+) -> Ordering {
+    a.iter()
+        .zip(b)
+        .enumerate()
+        .map(
+            |(i, (a, b))| match collations.get(i).and_then(Option::as_ref) {
+                Some(collation) => collation.compare_values(a, b),
+                None => a.compare(b),
+            },
+        )
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or_else(|| a.len().cmp(&b.len()))
+}
+
+/// A sqlite_schema cell, which has to fit on page 1 without overflowing
+fn schema_cell(row_id: i64, values: &[Value], header: &DatabaseHeader) -> Result<Vec<u8>> {
+    let record = encode_record(values, header.schema_format);
+    let split = overflow::thresholds(header.page_size, record.len() as u64, BTreePage::LeafTable)?;
+    if split.overflow > 0 {
+        bail!("Unhandled sqlite_schema: a row is too big for page 1");
+    }
+
+    let mut cell = varint::encode_varint(record.len() as i64);
+    cell.extend(varint::encode_varint(row_id));
+    cell.extend(record);
+    Ok(cell)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::new_database;
+
+    #[test]
+    fn test_no_writes_with_wal() {
+        let mut bytes = new_database(4096).unwrap();
+        bytes[18] = 2;
+        bytes[19] = 2;
+        let mut database = Database::open_bytes(bytes).unwrap();
+        let err = database.analyze(None).unwrap_err();
+        assert_eq!(err.to_string(), "Unhandled write to a database in WAL mode");
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_no_writes_with_wal_file() {
+        let path = std::env::temp_dir().join(format!("sqlite-rust-wal-{}.db", std::process::id()));
+        let wal_path = path.with_extension("db-wal");
+        let mut database = Database::create(&path).unwrap();
+        std::fs::write(&wal_path, b"not checkpointed").unwrap();
+
+        let err = database.analyze(None).unwrap_err();
+        std::fs::remove_file(&wal_path).unwrap();
+        assert!(err.to_string().contains("with a WAL file"), "{err}");
+        // Once it's gone, there's nothing in the way
+        database.analyze(None).unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+}
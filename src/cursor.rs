@@ -0,0 +1,155 @@
+use crate::{
+    cell::read_table_interior_cells,
+    database::{read_payload, Database, Page, MAX_BTREE_DEPTH},
+    header::BTreePage,
+    page_source::PageReader,
+    record::{self, Record},
+    trace_event,
+};
+use anyhow::{bail, Result};
+use std::vec;
+
+/// Walks a table b-tree in rowid order, yielding one record at a time.
+///
+/// Only the current leaf page's cell pointers, and the child pointers of the interior pages above
+/// it, are held in memory; cells are read and decoded as they're reached, so a scan can be
+/// abandoned at any point without having read the rest of the table. Cursors only share the
+/// Database, so any number of them can walk its tables at once, on as many threads.
+pub struct BtreeCursor<'a> {
+    database: &'a Database,
+    reader: PageReader<'a>,
+    /// For each interior page on the path to the current leaf, its children still to be visited
+    interior_stack: Vec<vec::IntoIter<u32>>,
+    /// The current leaf page, and its cell pointers still to be read
+    leaf: Option<(Page, vec::IntoIter<u16>)>,
+    /// The root page, until the cursor first moves
+    root_page: Option<u32>,
+    /// Set once an error has been returned, as the cursor's position can't be trusted after that
+    failed: bool,
+    /// Walk the b-tree backwards, from the largest rowid down
+    descending: bool,
+    /// Holds each cell's payload in turn, so that reading one doesn't allocate
+    payload: Vec<u8>,
+}
+
+impl<'a> BtreeCursor<'a> {
+    pub fn new(database: &'a Database, root_page: u32) -> Self {
+        BtreeCursor {
+            database,
+            reader: database.reader(),
+            interior_stack: vec![],
+            leaf: None,
+            root_page: Some(root_page),
+            failed: false,
+            descending: false,
+            payload: vec![],
+        }
+    }
+
+    /// Makes the cursor yield records in descending rowid order, so that the last rows of a table
+    /// can be read without reading the rest of it
+    pub fn descending(mut self) -> Self {
+        self.descending = true;
+        self
+    }
+
+    fn advance(&mut self) -> Result<Option<Record>> {
+        loop {
+            if let Some((page, cell_pointers)) = &mut self.leaf {
+                if let Some(offset) = cell_pointers.next() {
+                    let row_id = read_payload(
+                        self.database.page_size,
+                        page,
+                        offset,
+                        &mut self.reader,
+                        &mut self.payload,
+                    )?;
+                    let (serial_types, serial_values) = record::parse_record(&self.payload)?;
+
+                    return Ok(Some(Record {
+                        row_id,
+                        serial_types,
+                        serial_values,
+                    }));
+                }
+
+                self.leaf = None;
+            }
+
+            let next_page = match self.root_page.take() {
+                Some(root_page) => root_page,
+                None => match self.next_child() {
+                    Some(child) => child,
+                    None => return Ok(None),
+                },
+            };
+            self.enter_page(next_page)?;
+        }
+    }
+
+    /// Pops interior pages off the stack until one of them still has a child to visit
+    fn next_child(&mut self) -> Option<u32> {
+        while let Some(children) = self.interior_stack.last_mut() {
+            if let Some(child) = children.next() {
+                return Some(child);
+            }
+            self.interior_stack.pop();
+        }
+
+        None
+    }
+
+    fn enter_page(&mut self, page_number: u32) -> Result<()> {
+        let page = self.database.read_page(&mut self.reader, page_number)?;
+        let mut cell_pointers = page.fetch_cell_pointers(&mut self.reader)?;
+
+        match page.header.page_type {
+            BTreePage::LeafTable => {
+                if self.descending {
+                    cell_pointers.reverse();
+                }
+                self.leaf = Some((page, cell_pointers.into_iter()));
+            }
+            BTreePage::InteriorTable => {
+                if self.interior_stack.len() >= MAX_BTREE_DEPTH {
+                    bail!("b-tree is too deep at page {page_number}");
+                }
+
+                let cells =
+                    read_table_interior_cells(&mut self.reader, page.start_offset, &cell_pointers)?;
+
+                let mut children: Vec<u32> = cells.iter().map(|c| c.left_child_page).collect();
+                children.extend(page.header.right_most_pointer);
+                trace_event!(
+                    "btree",
+                    "descend into page {page_number}, with {} children",
+                    children.len()
+                );
+                if self.descending {
+                    children.reverse();
+                }
+                self.interior_stack.push(children.into_iter());
+            }
+            page_type => bail!("Expected a table b-tree page, found {page_type:?}"),
+        }
+
+        Ok(())
+    }
+}
+
+impl Iterator for BtreeCursor<'_> {
+    type Item = Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        let next = self.advance().transpose();
+        if matches!(next, Some(Err(_))) {
+            self.failed = true;
+        }
+
+        next
+    }
+}
@@ -0,0 +1,1433 @@
+use crate::{
+    cell::*,
+    collation::{Collation, CollationRegistry},
+    cursor::BtreeCursor,
+    functions::{quote, FunctionRegistry},
+    header::*,
+    interrupt::{InterruptHandle, ProgressHandler, StatementProgress},
+    journal::Journal,
+    lock::{lock_byte_page, LockLevel},
+    overflow,
+    page_source::{PageReader, PageSource, ReaderSource},
+    record::{self, Record},
+    schema::Schema,
+    trace, trace_event,
+    transaction::Savepoint,
+    uri::OpenOptions,
+    value::Value,
+    varint,
+    vfs::Vfs,
+};
+use anyhow::{bail, Result};
+use std::{
+    borrow::Cow,
+    cmp::Ordering,
+    io::{prelude::*, Cursor, SeekFrom},
+    path::{Path, PathBuf},
+    sync::{Mutex, PoisonError},
+};
+
+/// How big a b-tree is, as counted by Database::btree_size
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BtreeSize {
+    pub pages: u32,
+    /// The rows of a table b-tree, or the entries of an index b-tree
+    pub entries: u64,
+}
+
+/// A query was planned against a schema that has since changed, like SQLITE_SCHEMA. Reading the
+/// schema again and re-planning the query gets it running.
+#[derive(Debug, PartialEq, thiserror::Error)]
+#[error("database schema has changed")]
+pub struct SchemaChangedError;
+
+/// The page size of databases made with Database::create, as in SQLite
+pub const DEFAULT_PAGE_SIZE: u32 = 4096;
+
+/// How many leaf pages count_estimate reads the cell counts of
+const COUNT_ESTIMATE_SAMPLES: usize = 32;
+
+/// The most levels a b-tree can have, like SQLite's BTCURSOR_MAX_DEPTH. A b-tree that seems to be
+/// deeper is corrupt, most likely with a child pointer that leads back up the tree.
+pub(crate) const MAX_BTREE_DEPTH: usize = 20;
+
+/// An open database. Reading it only needs a shared reference, so one Database can be queried
+/// from many threads at once, e.g. behind an Arc: each query reads through a PageReader of its
+/// own.
+pub struct Database {
+    pub page_size: u32,
+    pub page_count: u32,
+    /// Changes whenever the schema does, as of when the header was last read
+    pub schema_cookie: u32,
+    /// How the database was opened, e.g. whether it's immutable or shouldn't be locked
+    pub options: OpenOptions,
+    /// The scalar functions queries can call
+    pub functions: FunctionRegistry,
+    /// The collations queries and the schema can name with COLLATE
+    pub collations: CollationRegistry,
+    /// Set to stop the statements that are running
+    pub(crate) interrupt: InterruptHandle,
+    /// Run as statements read rows, to report progress or stop them
+    progress_handler: Option<ProgressHandler>,
+    pub(crate) database_file: Box<dyn PageSource>,
+    /// How many read locks are held, by acquire_read_lock calls without a release_read_lock yet
+    readers: Mutex<usize>,
+    /// What was odd about the header when the database was opened
+    anomalies: Vec<HeaderAnomaly>,
+    /// Where the rollback journal goes, beside the database file, if it's a file on disk.
+    /// Otherwise the journal is kept in memory.
+    pub(crate) journal_path: Option<PathBuf>,
+    /// The rollback journal of the write going on, if there is one
+    pub(crate) journal: Mutex<Option<Journal>>,
+    /// Whether BEGIN has started a transaction, which keeps the journal until it's over
+    pub(crate) in_transaction: bool,
+    /// What the pages written since each savepoint in a transaction held when it was made,
+    /// oldest first, ending with the statement going on if there is one
+    pub(crate) savepoints: Mutex<Vec<Savepoint>>,
+    /// Whether the transaction was started by its first savepoint, rather than BEGIN, and so is
+    /// committed when that's released
+    pub(crate) savepoint_transaction: bool,
+}
+
+/// A read lock on a database, released when it's dropped
+pub(crate) struct ReadLock<'a> {
+    database: &'a Database,
+}
+
+impl Drop for ReadLock<'_> {
+    fn drop(&mut self) {
+        let _ = self.database.release_read_lock();
+    }
+}
+
+#[derive(Debug)]
+pub struct Page {
+    /// Offset of the start of the page in the database file
+    pub start_offset: u64,
+    /// Size of the page in bytes, which every cell has to fit within
+    pub size: u32,
+    pub header: PageHeader,
+}
+
+/// What an index search looks for: the entries whose first column equals `key`
+struct IndexSearch<'a> {
+    key: &'a Value,
+    /// The collation of the index's first column, or None for BINARY
+    collation: Option<&'a Collation>,
+    /// Whether the index's first column is sorted DESC
+    descending: bool,
+}
+
+impl IndexSearch<'_> {
+    /// Where the key sorts relative to `cell_key`, in the index's order
+    fn compare(&self, cell_key: &Value) -> Ordering {
+        let ordering = match self.collation {
+            Some(collation) => collation.compare_values(self.key, cell_key),
+            None => self.key.compare(cell_key),
+        };
+        if self.descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    }
+}
+
+impl Database {
+    /// Opens a database read from a file, or anything else that's a PageSource
+    pub fn open(database_file: impl PageSource + 'static) -> Result<Self> {
+        Database::open_source(Box::new(database_file))
+    }
+
+    fn open_source(database_file: Box<dyn PageSource>) -> Result<Self> {
+        let header = DatabaseHeader::parse(&database_file.read_at(0, DATABASE_HEADER_SIZE)?)?;
+
+        let reserved_end_of_page_space = header.reserved_space as u32;
+        if reserved_end_of_page_space > 0 {
+            bail!("Unhandled reserved_end_of_page_space: {reserved_end_of_page_space}");
+        }
+        // Text is only decoded as UTF-8, which UTF-16 text would be mistaken for rather than
+        // failing
+        if let 2 | 3 = header.text_encoding {
+            bail!("Unhandled text_encoding: {} (UTF-16)", header.text_encoding);
+        }
+
+        let file_size = database_file.size()?;
+        let (page_count, anomalies) = header.resolve_page_count(file_size);
+
+        Ok(Database {
+            page_size: header.page_size,
+            page_count,
+            schema_cookie: header.schema_cookie,
+            options: OpenOptions::default(),
+            functions: FunctionRegistry::new(),
+            collations: CollationRegistry::new(),
+            interrupt: InterruptHandle::new(),
+            progress_handler: None,
+            database_file,
+            readers: Mutex::new(0),
+            anomalies,
+            journal_path: None,
+            journal: Mutex::new(None),
+            in_transaction: false,
+            savepoints: Mutex::new(vec![]),
+            savepoint_transaction: false,
+        })
+    }
+
+    /// What was odd about the header when the database was opened, like a stale page count, for
+    /// the caller to report
+    pub fn anomalies(&self) -> &[HeaderAnomaly] {
+        &self.anomalies
+    }
+
+    /// Creates a database file at `path`, holding nothing but an empty sqlite_schema table, and
+    /// opens it. A file that's already there is left alone, and is an error.
+    #[cfg(feature = "fs")]
+    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let mut file = std::fs::File::options()
+            .write(true)
+            .create_new(true)
+            .open(path)?;
+        file.write_all(&new_database(DEFAULT_PAGE_SIZE)?)?;
+        file.sync_all()?;
+
+        let mut database = Database::open_vfs(&crate::vfs::FileVfs, path)?;
+        database.journal_path = Some(journal_path(path));
+
+        Ok(database)
+    }
+
+    /// Opens a database held in memory, e.g. one fetched over the network
+    pub fn open_bytes(bytes: Vec<u8>) -> Result<Self> {
+        Database::open(Cursor::new(bytes))
+    }
+
+    /// Opens a database read from anything that can Read and Seek
+    pub fn open_reader(reader: impl Read + Seek + Send + 'static) -> Result<Self> {
+        Database::open(ReaderSource::new(reader)?)
+    }
+
+    /// Opens a database by filename, which can be a plain path or a file: URI
+    #[cfg(feature = "fs")]
+    pub fn open_filename(filename: &str) -> Result<Self> {
+        Database::open_with_options(OpenOptions::parse(filename)?)
+    }
+
+    /// Opens the database at `options.path`, memory-mapping it if `options.mmap` is set
+    #[cfg(feature = "fs")]
+    pub fn open_with_options(options: OpenOptions) -> Result<Self> {
+        let mut database = Database::open_vfs(vfs(options.mmap)?, &options.path)?;
+        database.journal_path = Some(journal_path(&options.path));
+        database.options = options;
+
+        // A write that was cut short left its journal, to be rolled back before anything's read
+        if !database.options.immutable && database.journal_exists() {
+            database.acquire_read_lock()?;
+            database.release_read_lock()?;
+            database.refresh()?;
+        }
+
+        Ok(database)
+    }
+
+    /// Opens another database, by filename, to attach to this one: it's read with the same mmap,
+    /// memory and busy timeout options, can call the same functions, and is interrupted along
+    /// with this one
+    #[cfg(feature = "fs")]
+    pub fn open_attached(&self, filename: &str) -> Result<Self> {
+        let mut options = OpenOptions::parse(filename)?;
+        options.mmap = self.options.mmap;
+        options.max_memory = self.options.max_memory;
+        options.busy_timeout = self.options.busy_timeout;
+        let mut database = Database::open_with_options(options)?;
+        database.functions = self.functions.clone();
+        database.collations = self.collations.clone();
+        database.interrupt = self.interrupt.clone();
+
+        Ok(database)
+    }
+
+    /// Opens a database from a file that `vfs` provides
+    pub fn open_vfs(vfs: &dyn Vfs, path: &Path) -> Result<Self> {
+        let mut database = Database::open_source(vfs.open(path)?)?;
+        database.options.path = path.to_path_buf();
+
+        Ok(database)
+    }
+
+    /// Reads the database header again, picking up changes other connections have made to the
+    /// file since it was opened
+    pub fn refresh(&mut self) -> Result<()> {
+        let header = self.header()?;
+
+        // Anomalies were already reported when the database was opened
+        (self.page_count, _) = header.resolve_page_count(self.database_file.size()?);
+        self.schema_cookie = header.schema_cookie;
+
+        Ok(())
+    }
+
+    /// The schema cookie as it is in the file now, which differs from schema_cookie if another
+    /// connection has changed the schema since the header was last read
+    pub fn current_schema_cookie(&self) -> Result<u32> {
+        Ok(self.header()?.schema_cookie)
+    }
+
+    /// The database header, as it is in the file now
+    pub fn header(&self) -> Result<DatabaseHeader> {
+        DatabaseHeader::parse(&self.database_file.read_at(0, DATABASE_HEADER_SIZE)?)
+    }
+
+    /// Takes a shared lock on the database file, as SQLite does while it reads, so that other
+    /// processes can't write to it in the meantime. Locks are counted: the file is unlocked once
+    /// every acquire_read_lock has had a release_read_lock. Immutable databases, and those opened
+    /// with nolock, aren't locked.
+    pub fn acquire_read_lock(&self) -> Result<()> {
+        if self.options.immutable || self.options.nolock {
+            return Ok(());
+        }
+
+        let mut readers = self.readers.lock().unwrap_or_else(PoisonError::into_inner);
+        if *readers == 0 {
+            self.database_file
+                .lock(LockLevel::Shared, self.options.busy_timeout)?;
+            #[cfg(feature = "fs")]
+            if let Err(err) = self.roll_back_hot_journal() {
+                let _ = self.database_file.unlock(LockLevel::None);
+                return Err(err);
+            }
+        }
+        *readers += 1;
+
+        Ok(())
+    }
+
+    /// Releases a lock taken by acquire_read_lock
+    pub fn release_read_lock(&self) -> Result<()> {
+        if self.options.immutable || self.options.nolock {
+            return Ok(());
+        }
+
+        let mut readers = self.readers.lock().unwrap_or_else(PoisonError::into_inner);
+        match *readers {
+            0 => bail!("release_read_lock: no read lock is held"),
+            1 => self.database_file.unlock(LockLevel::None)?,
+            _ => {}
+        }
+        *readers -= 1;
+
+        Ok(())
+    }
+
+    /// A read lock held until it's dropped
+    pub(crate) fn read_lock(&self) -> Result<ReadLock<'_>> {
+        self.acquire_read_lock()?;
+
+        Ok(ReadLock { database: self })
+    }
+
+    /// A reader for walking pages, with its own position and read-ahead buffer
+    pub(crate) fn reader(&self) -> PageReader<'_> {
+        PageReader::new(&*self.database_file)
+    }
+
+    /// Reads a page's header
+    pub fn seek_to_page(&self, page_num: u32) -> Result<Page> {
+        self.read_page(&mut self.reader(), page_num)
+    }
+
+    /// Reads a page's header through `reader`, so that a walk of many pages can reuse its buffer
+    pub(crate) fn read_page(&self, reader: &mut PageReader, page_num: u32) -> Result<Page> {
+        if page_num < 1 || page_num > self.page_count {
+            bail!("seek_to_page: page_num out of bounds: {page_num}");
+        }
+        self.check_not_lock_byte_page(page_num)?;
+
+        let start_offset = (page_num - 1) as u64 * self.page_size as u64;
+        let mut seek_offset = start_offset;
+
+        if page_num == 1 {
+            // Skip first 100 bytes of page 1 to account for the database header.
+            seek_offset += DATABASE_HEADER_SIZE as u64;
+        }
+
+        // 12 bytes is enough for either kind of page header
+        let mut page_header_bytes = [0; 12];
+        if trace::enabled() && reader.is_buffered(seek_offset, page_header_bytes.len()) {
+            trace_event!("cache", "hit for page {page_num}");
+        }
+        reader.seek(SeekFrom::Start(seek_offset))?;
+        reader.read_exact(&mut page_header_bytes)?;
+        let header = PageHeader::parse(&page_header_bytes)?;
+        trace_event!("page", "read page {page_num} ({:?})", header.page_type);
+
+        Ok(Page {
+            start_offset,
+            size: self.page_size,
+            header,
+        })
+    }
+
+    /// A whole page's bytes, page 1's database header included. When the database is
+    /// memory-mapped, this is a slice of the map rather than a copy.
+    pub fn page_bytes(&self, page_num: u32) -> Result<Cow<'_, [u8]>> {
+        if page_num < 1 || page_num > self.page_count {
+            bail!("page_bytes: page_num out of bounds: {page_num}");
+        }
+        self.check_not_lock_byte_page(page_num)?;
+
+        let start_offset = (page_num - 1) as u64 * self.page_size as u64;
+        trace_event!("page", "read all of page {page_num}");
+        self.database_file
+            .read_at(start_offset, self.page_size as usize)
+    }
+
+    /// The lock-byte page holds nothing, so a pointer to it means the database is corrupt
+    fn check_not_lock_byte_page(&self, page_num: u32) -> Result<()> {
+        if page_num == lock_byte_page(self.page_size) {
+            bail!("malformed database: page {page_num} is the lock-byte page");
+        }
+
+        Ok(())
+    }
+
+    /// Registers a scalar function taking exactly `arg_count` arguments, which queries on this
+    /// database can then call, like sqlite3_create_function()
+    pub fn create_function<F>(&mut self, name: &str, arg_count: usize, function: F)
+    where
+        F: Fn(&[Value]) -> Result<Value> + Send + Sync + 'static,
+    {
+        self.functions
+            .register(name, arg_count, arg_count, function);
+    }
+
+    /// A handle for stopping this database's running statements, from another thread or a signal
+    /// handler
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+
+    /// Sets the callback statements run every so many rows they read, replacing any there was.
+    /// None removes it.
+    pub fn set_progress_handler(&mut self, handler: Option<ProgressHandler>) {
+        self.progress_handler = handler;
+    }
+
+    /// What a statement starting now checks for as it reads rows
+    pub(crate) fn statement_progress(&self) -> StatementProgress<'_> {
+        StatementProgress::new(self.interrupt.clone(), self.progress_handler.as_ref())
+    }
+
+    /// Registers a collation, which queries and the schema can then name with COLLATE, like
+    /// sqlite3_create_collation()
+    pub fn create_collation<F>(&mut self, name: &str, compare: F)
+    where
+        F: Fn(&str, &str) -> Ordering + Send + Sync + 'static,
+    {
+        self.collations.register(name, compare);
+    }
+
+    /// Reads a page's header and cell pointers without interpreting its cells, which works even
+    /// when the b-tree the page belongs to can't be walked
+    pub fn raw_page(&self, page_num: u32) -> Result<(Page, Vec<u16>)> {
+        let mut reader = self.reader();
+        let page = self.read_page(&mut reader, page_num)?;
+        let cell_pointers = page.fetch_cell_pointers(&mut reader)?;
+
+        Ok((page, cell_pointers))
+    }
+
+    /// Reads the sqlite_schema table, which always has its root on page 1
+    pub fn schema(&self) -> Result<Vec<Schema>> {
+        let _lock = self.read_lock()?;
+        let records = self.read_table(1)?;
+
+        records
+            .iter()
+            .map(|record| {
+                Schema::parse(record).ok_or_else(|| anyhow::anyhow!("Invalid sqlite_schema row"))
+            })
+            .collect()
+    }
+
+    /// Reads every record of the table b-tree rooted at `root_page`, in rowid order
+    pub fn read_table(&self, root_page: u32) -> Result<Vec<Record>> {
+        self.table_cursor(root_page).collect()
+    }
+
+    /// Walks the table b-tree rooted at `root_page` in rowid order, reading one record at a time
+    pub fn table_cursor(&self, root_page: u32) -> BtreeCursor<'_> {
+        BtreeCursor::new(self, root_page)
+    }
+
+    /// Looks up a single row by rowid, descending the table b-tree rooted at `root_page`
+    pub fn find_row(&self, root_page: u32, row_id: i64) -> Result<Option<Record>> {
+        let mut reader = self.reader();
+        let mut page_number = root_page;
+
+        for _ in 0..MAX_BTREE_DEPTH {
+            let page = self.read_page(&mut reader, page_number)?;
+            let cell_pointers = page.fetch_cell_pointers(&mut reader)?;
+
+            match page.header.page_type {
+                BTreePage::LeafTable => {
+                    return self.find_leaf_row(&page, &cell_pointers, &mut reader, row_id);
+                }
+                BTreePage::InteriorTable => {
+                    let cells =
+                        read_table_interior_cells(&mut reader, page.start_offset, &cell_pointers)?;
+
+                    // The first child whose largest rowid is at least the one being looked for
+                    let child = cells
+                        .get(cells.partition_point(|cell| cell.row_id < row_id))
+                        .map(|cell| cell.left_child_page)
+                        .or(page.header.right_most_pointer);
+
+                    match child {
+                        Some(child) => {
+                            trace_event!(
+                                "btree",
+                                "descend from page {page_number} to {child} for rowid {row_id}"
+                            );
+                            page_number = child;
+                        }
+                        None => return Ok(None),
+                    }
+                }
+                page_type => bail!("Expected a table b-tree page, found {page_type:?}"),
+            }
+        }
+
+        bail!("b-tree rooted at page {root_page} is too deep")
+    }
+
+    /// Looks up a row on a table leaf page. Cells are in rowid order, so a binary search finds it
+    /// reading only the rowids of a few cells, and then the payload of the one that matches.
+    fn find_leaf_row(
+        &self,
+        page: &Page,
+        cell_pointers: &[u16],
+        reader: &mut PageReader,
+        row_id: i64,
+    ) -> Result<Option<Record>> {
+        let (mut low, mut high) = (0, cell_pointers.len());
+
+        while low < high {
+            let middle = (low + high) / 2;
+            let offset = cell_pointers[middle];
+            match leaf_cell_row_id(page, offset, reader)?.cmp(&row_id) {
+                Ordering::Less => low = middle + 1,
+                Ordering::Greater => high = middle,
+                Ordering::Equal => {
+                    let mut payload = vec![];
+                    read_payload(self.page_size, page, offset, reader, &mut payload)?;
+                    let (serial_types, serial_values) = record::parse_record(&payload)?;
+
+                    return Ok(Some(Record {
+                        row_id,
+                        serial_types,
+                        serial_values,
+                    }));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// The largest rowid in the table b-tree rooted at `root_page`, found by following right-most
+    /// pointers down to the last leaf, or None if the table is empty
+    pub fn max_row_id(&self, root_page: u32) -> Result<Option<i64>> {
+        let mut reader = self.reader();
+        let mut page_number = root_page;
+
+        for _ in 0..MAX_BTREE_DEPTH {
+            let page = self.read_page(&mut reader, page_number)?;
+
+            match page.header.page_type {
+                BTreePage::LeafTable => {
+                    let cell_pointers = page.fetch_cell_pointers(&mut reader)?;
+                    let Some(&offset) = cell_pointers.last() else {
+                        return Ok(None);
+                    };
+                    return Ok(Some(leaf_cell_row_id(&page, offset, &mut reader)?));
+                }
+                BTreePage::InteriorTable => {
+                    let Some(right_most_pointer) = page.header.right_most_pointer else {
+                        bail!("Interior page {page_number} has no right-most pointer");
+                    };
+                    page_number = right_most_pointer;
+                }
+                page_type => bail!("Expected a table b-tree page, found {page_type:?}"),
+            }
+        }
+
+        bail!("b-tree rooted at page {root_page} is too deep")
+    }
+
+    /// The largest rowid an AUTOINCREMENT table has ever used, as kept in sqlite_sequence. None
+    /// if it's never had a row, or isn't an AUTOINCREMENT table.
+    pub fn sequence(&self, table_name: &str) -> Result<Option<i64>> {
+        let Some(sqlite_sequence) = self
+            .schema()?
+            .into_iter()
+            .find(|s| s.is_table() && s.name == "sqlite_sequence")
+        else {
+            return Ok(None);
+        };
+
+        for record in self.table_cursor(sqlite_sequence.root_page) {
+            let record = record?;
+            if let Value::Text(name) = record.value(0) {
+                if name.eq_ignore_ascii_case(table_name) {
+                    return Ok(record.value(1).as_integer());
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// The rowid SQLite gives a row inserted into `table_name` without one: one more than the
+    /// largest rowid in the table, or for an AUTOINCREMENT table, the largest it's ever had, so
+    /// that the rowids of deleted rows aren't used again. Storing a row with the new rowid in an
+    /// AUTOINCREMENT table means updating sqlite_sequence to match.
+    pub fn next_row_id(&self, table_name: &str) -> Result<i64> {
+        let Some(table) = self
+            .schema()?
+            .into_iter()
+            .find(|s| s.is_table() && s.name.eq_ignore_ascii_case(table_name))
+        else {
+            bail!("no such table: {table_name}");
+        };
+        let create_table = table.create_table()?;
+
+        let mut last_row_id = self.max_row_id(table.root_page)?.unwrap_or(0);
+        if create_table.autoincrement {
+            last_row_id = last_row_id.max(self.sequence(&table.name)?.unwrap_or(0));
+        }
+
+        match last_row_id.checked_add(1) {
+            Some(row_id) => Ok(row_id),
+            // SQLite fails an AUTOINCREMENT table with SQLITE_FULL here, and goes looking for an
+            // unused rowid at random in any other table
+            None => bail!("database or disk is full: {table_name} has used the largest rowid"),
+        }
+    }
+
+    /// Finds the rowids of every entry in the index b-tree rooted at `root_page` whose first
+    /// column equals `key`.
+    pub fn search_index(&self, root_page: u32, key: &Value) -> Result<Vec<i64>> {
+        self.search_index_using(root_page, key, None)
+    }
+
+    /// Like search_index, for an index whose first column has a collation other than BINARY,
+    /// which its entries are sorted by and which decides what equals `key`
+    pub fn search_index_using(
+        &self,
+        root_page: u32,
+        key: &Value,
+        collation: Option<&Collation>,
+    ) -> Result<Vec<i64>> {
+        let entries = self.search_index_entries(root_page, key, collation, false)?;
+
+        Ok(entries.into_iter().map(|entry| entry.row_id).collect())
+    }
+
+    /// Like search_index_using, returning the matching index entries themselves, each as a record
+    /// of the indexed columns' values with the rowid it points to. The entries of an index whose
+    /// first column is `descending` go from greatest to least, so the search is turned around.
+    pub fn search_index_entries(
+        &self,
+        root_page: u32,
+        key: &Value,
+        collation: Option<&Collation>,
+        descending: bool,
+    ) -> Result<Vec<Record>> {
+        let mut entries = vec![];
+        let search = IndexSearch {
+            key,
+            collation,
+            descending,
+        };
+        self.collect_index_matches(&mut self.reader(), root_page, &search, 1, &mut entries)?;
+
+        Ok(entries)
+    }
+
+    fn collect_index_matches(
+        &self,
+        reader: &mut PageReader,
+        page_number: u32,
+        search: &IndexSearch,
+        depth: usize,
+        entries: &mut Vec<Record>,
+    ) -> Result<()> {
+        if depth > MAX_BTREE_DEPTH {
+            bail!("index b-tree is too deep at page {page_number}");
+        }
+
+        let page = self.read_page(reader, page_number)?;
+        let cell_pointers = page.fetch_cell_pointers(reader)?;
+        let is_interior = match page.header.page_type {
+            BTreePage::LeafIndex => false,
+            BTreePage::InteriorIndex => true,
+            page_type => bail!("Expected an index b-tree page, found {page_type:?}"),
+        };
+
+        let cells = read_index_cells(
+            reader,
+            page.start_offset,
+            is_interior,
+            &cell_pointers,
+            self.page_size,
+        )?;
+        if is_interior {
+            trace_event!(
+                "btree",
+                "search index page {page_number}, with {} cells, for {}",
+                cells.len(),
+                quote(search.key)
+            );
+        }
+
+        for cell in cells {
+            let (mut serial_types, mut serial_values) = record::parse_record(&cell.payload)?;
+            let Some(cell_key) = serial_values.first().cloned().map(Value::from) else {
+                bail!("Empty index record on page {page_number}");
+            };
+            serial_types.pop();
+            let row_id = serial_values
+                .pop()
+                .map(Value::from)
+                .and_then(|v| v.as_integer());
+
+            match search.compare(&cell_key) {
+                Ordering::Less => {
+                    // Everything from here on is greater than the key, only the left child may
+                    // still hold matches.
+                    if let Some(left_child_page) = cell.left_child_page {
+                        self.collect_index_matches(
+                            reader,
+                            left_child_page,
+                            search,
+                            depth + 1,
+                            entries,
+                        )?;
+                    }
+                    return Ok(());
+                }
+                Ordering::Equal => {
+                    if let Some(left_child_page) = cell.left_child_page {
+                        self.collect_index_matches(
+                            reader,
+                            left_child_page,
+                            search,
+                            depth + 1,
+                            entries,
+                        )?;
+                    }
+                    if let Some(row_id) = row_id {
+                        entries.push(Record {
+                            row_id,
+                            serial_types,
+                            serial_values,
+                        });
+                    }
+                }
+                Ordering::Greater => {}
+            }
+        }
+
+        if let Some(right_most_pointer) = page.header.right_most_pointer {
+            self.collect_index_matches(reader, right_most_pointer, search, depth + 1, entries)?;
+        }
+
+        Ok(())
+    }
+
+    /// Number of levels in the b-tree rooted at `root_page` (1 for a single leaf page)
+    pub fn btree_depth(&self, root_page: u32) -> Result<u32> {
+        let (depth, _, _) = self.walk_leftmost_path(root_page)?;
+
+        Ok(depth)
+    }
+
+    /// Estimates how many pages the b-tree rooted at `root_page` has, by assuming every page on a
+    /// level has as many children as the left-most one.
+    pub fn estimate_btree_pages(&self, root_page: u32) -> Result<u32> {
+        let (_, fanouts, _) = self.walk_leftmost_path(root_page)?;
+
+        let mut pages_on_level: u32 = 1;
+        let mut total_pages: u32 = 1;
+        for fanout in fanouts {
+            pages_on_level = pages_on_level.saturating_mul(fanout);
+            total_pages = total_pages.saturating_add(pages_on_level);
+        }
+
+        Ok(total_pages)
+    }
+
+    /// Counts the pages and entries of the b-tree rooted at `root_page`. Every page is visited,
+    /// but only page headers and child pointers are read, so it's much faster than reading rows.
+    pub fn btree_size(&self, root_page: u32) -> Result<BtreeSize> {
+        let mut size = BtreeSize {
+            pages: 0,
+            entries: 0,
+        };
+        let mut reader = self.reader();
+        let mut pages_to_visit = vec![root_page];
+
+        while let Some(page_number) = pages_to_visit.pop() {
+            size.pages += 1;
+            if size.pages > self.page_count {
+                bail!("b-tree rooted at page {root_page} has more pages than the database");
+            }
+
+            let page = self.read_page(&mut reader, page_number)?;
+            let page_type = &page.header.page_type;
+            // Index b-trees keep entries on their interior pages too, table b-trees only on leaves
+            if !page_type.is_interior() || *page_type == BTreePage::InteriorIndex {
+                size.entries += page.header.number_of_cells as u64;
+            }
+            if !page_type.is_interior() {
+                continue;
+            }
+
+            pages_to_visit.extend(child_pages(&mut reader, &page)?);
+        }
+
+        Ok(size)
+    }
+
+    /// Estimates how many rows a table has from a sample of its leaf pages, which takes
+    /// milliseconds even when counting every row would take seconds. Every interior page is read
+    /// to find the leaves, but only COUNT_ESTIMATE_SAMPLES of them, spread evenly across the
+    /// table, are: the estimate is their average number of rows times the number of leaves.
+    pub fn count_estimate(&self, table_name: &str) -> Result<u64> {
+        let Some(table) = self
+            .schema()?
+            .into_iter()
+            .find(|s| s.is_table() && s.name.eq_ignore_ascii_case(table_name))
+        else {
+            bail!("no such table: {table_name}");
+        };
+
+        // Every page on a level of a b-tree is the same kind, so a level is all leaves as soon as
+        // its first page is a leaf
+        let mut reader = self.reader();
+        let mut level = vec![table.root_page];
+        let mut depth = 1;
+        while self
+            .read_page(&mut reader, level[0])?
+            .header
+            .page_type
+            .is_interior()
+        {
+            let mut next_level = vec![];
+            for page_number in level {
+                let page = self.read_page(&mut reader, page_number)?;
+                next_level.extend(child_pages(&mut reader, &page)?);
+            }
+            depth += 1;
+            if next_level.is_empty()
+                || next_level.len() > self.page_count as usize
+                || depth > MAX_BTREE_DEPTH
+            {
+                bail!("b-tree rooted at page {} is malformed", table.root_page);
+            }
+            level = next_level;
+        }
+
+        let samples = level.len().min(COUNT_ESTIMATE_SAMPLES);
+        let mut sampled_rows = 0;
+        for i in 0..samples {
+            let page = self.read_page(&mut reader, level[i * level.len() / samples])?;
+            sampled_rows += page.header.number_of_cells as u64;
+        }
+
+        Ok((sampled_rows * level.len() as u64).div_ceil(samples as u64))
+    }
+
+    /// Estimates how many rows the table b-tree rooted at `root_page` has, in the same way as
+    /// estimate_btree_pages, by assuming every leaf holds as many rows as the left-most one.
+    pub fn estimate_btree_rows(&self, root_page: u32) -> Result<u64> {
+        let (_, fanouts, leaf_cells) = self.walk_leftmost_path(root_page)?;
+
+        Ok(fanouts.into_iter().fold(leaf_cells as u64, |rows, fanout| {
+            rows.saturating_mul(fanout as u64)
+        }))
+    }
+
+    /// Descends to the left-most leaf, returning the depth, the number of children of every
+    /// interior page on the way and the number of cells on the leaf.
+    fn walk_leftmost_path(&self, root_page: u32) -> Result<(u32, Vec<u32>, u16)> {
+        let mut reader = self.reader();
+        let mut page_number = root_page;
+        let mut fanouts = vec![];
+
+        loop {
+            if fanouts.len() >= MAX_BTREE_DEPTH {
+                bail!("b-tree rooted at page {root_page} is too deep");
+            }
+
+            let page = self.read_page(&mut reader, page_number)?;
+            if !page.header.page_type.is_interior() {
+                return Ok((
+                    fanouts.len() as u32 + 1,
+                    fanouts,
+                    page.header.number_of_cells,
+                ));
+            }
+
+            let cell_pointers = page.fetch_cell_pointers(&mut reader)?;
+            fanouts.push(page.header.number_of_cells as u32 + 1);
+
+            page_number = match cell_pointers.first() {
+                Some(offset) => {
+                    reader.seek(SeekFrom::Start(page.start_offset + *offset as u64))?;
+                    let mut left_child_page = [0; 4];
+                    reader.read_exact(&mut left_child_page)?;
+                    u32::from_be_bytes(left_child_page)
+                }
+                None => match page.header.right_most_pointer {
+                    Some(right_most_pointer) => right_most_pointer,
+                    None => bail!("Interior page {page_number} has no children"),
+                },
+            };
+        }
+    }
+}
+
+impl Page {
+    /// Offset of the cell pointer array, which follows the page header (and on page 1, the
+    /// database header)
+    pub fn cell_pointers_offset(&self) -> u64 {
+        let mut offset = self.start_offset + self.header.page_type.header_size() as u64;
+        if self.start_offset == 0 {
+            offset += DATABASE_HEADER_SIZE as u64;
+        }
+
+        offset
+    }
+
+    /// Reads the page's cell pointers, which are offsets from the start of the page. Pointers
+    /// that don't leave room for a cell between the end of the pointer array and the end of the
+    /// page are errors.
+    pub fn fetch_cell_pointers<R: Read + std::io::Seek>(&self, reader: &mut R) -> Result<Vec<u16>> {
+        let cell_pointers_offset = self.cell_pointers_offset();
+        let cell_pointers_end =
+            cell_pointers_offset - self.start_offset + 2 * self.header.number_of_cells as u64;
+        if cell_pointers_end > self.size as u64 {
+            bail!(
+                "malformed page: {} cells don't fit on a {} byte page",
+                self.header.number_of_cells,
+                self.size
+            );
+        }
+
+        reader.seek(SeekFrom::Start(cell_pointers_offset))?;
+        let cell_pointers = Self::build_cell_pointers(&self.header, reader)?;
+
+        if let Some(offset) = cell_pointers
+            .iter()
+            .find(|&&offset| (offset as u64) < cell_pointers_end || offset as u32 >= self.size)
+        {
+            bail!("malformed page: cell pointer {offset} is outside the cell content area");
+        }
+
+        Ok(cell_pointers)
+    }
+
+    /// Checks that a cell's local payload, `len` bytes starting at `position` in the file, ends
+    /// on the page the cell is on
+    pub(crate) fn check_payload_fits(&self, position: u64, len: usize) -> Result<()> {
+        let end = position.checked_add(len as u64);
+        if end.is_none_or(|end| end > self.start_offset + self.size as u64) {
+            bail!("malformed cell: a {len} byte payload runs past the end of its page");
+        }
+
+        Ok(())
+    }
+
+    fn build_cell_pointers<R: Read>(page_header: &PageHeader, reader: &mut R) -> Result<Vec<u16>> {
+        let mut cell_pointers = Vec::with_capacity(page_header.number_of_cells.into());
+        let mut cell_pointer_buffer = [0; 2];
+        for _ in 0..page_header.number_of_cells {
+            reader.read_exact(&mut cell_pointer_buffer)?;
+
+            cell_pointers.push(u16::from_be_bytes([
+                cell_pointer_buffer[0],
+                cell_pointer_buffer[1],
+            ]))
+        }
+
+        Ok(cell_pointers)
+    }
+}
+
+/// The VFS that reads database files, through a memory map if `mmap` is set
+#[cfg(feature = "fs")]
+pub(crate) fn vfs(mmap: bool) -> Result<&'static dyn Vfs> {
+    if mmap {
+        #[cfg(all(feature = "mmap", unix))]
+        {
+            Ok(&crate::vfs::MmapVfs)
+        }
+        #[cfg(not(all(feature = "mmap", unix)))]
+        bail!("memory-mapped I/O needs the mmap feature, on a unix system")
+    } else {
+        Ok(&crate::vfs::FileVfs)
+    }
+}
+
+/// The children of an interior page, from left to right
+fn child_pages(reader: &mut PageReader, page: &Page) -> Result<Vec<u32>> {
+    let cell_pointers = page.fetch_cell_pointers(reader)?;
+
+    let mut children = Vec::with_capacity(cell_pointers.len() + 1);
+    for offset in cell_pointers {
+        reader.seek(SeekFrom::Start(page.start_offset + offset as u64))?;
+        let mut left_child_page = [0; 4];
+        reader.read_exact(&mut left_child_page)?;
+        children.push(u32::from_be_bytes(left_child_page));
+    }
+    children.extend(page.header.right_most_pointer);
+
+    Ok(children)
+}
+
+/// Reads the payload of the LeafTable cell at `offset` into `payload`, replacing what it held, and
+/// returns the cell's rowid. Scans pass the same buffer for every cell, so that reading a cell
+/// doesn't allocate once the buffer is big enough.
+pub(crate) fn read_payload<R: Read + std::io::Seek>(
+    database_page_size: u32,
+    page: &Page,
+    offset: u16,
+    reader: &mut R,
+    payload: &mut Vec<u8>,
+) -> Result<i64> {
+    reader.seek(SeekFrom::Start(page.start_offset + offset as u64))?;
+
+    let (payload_size, bytes_read_1) = varint::parse_varint_from_reader(reader)?;
+    let payload_size = checked_payload_size(payload_size)?;
+    let (row_id, bytes_read_2) = varint::parse_varint_from_reader(reader)?;
+
+    let split = overflow::thresholds(
+        database_page_size,
+        payload_size as u64,
+        BTreePage::LeafTable,
+    )?;
+    // A payload that spills to overflow pages ends with the number of the first of them
+    let pointer_size = if split.overflow > 0 { 4 } else { 0 };
+    let payload_start = page.start_offset + (offset as usize + bytes_read_1 + bytes_read_2) as u64;
+    page.check_payload_fits(payload_start, split.local + pointer_size)?;
+
+    payload.resize(split.local, 0);
+    reader.read_exact(payload)?;
+    if split.overflow > 0 {
+        let mut first_page = [0; 4];
+        reader.read_exact(&mut first_page)?;
+        overflow::read_overflow(
+            reader,
+            database_page_size,
+            u32::from_be_bytes(first_page),
+            split.overflow,
+            payload,
+        )?;
+    }
+
+    Ok(row_id)
+}
+
+/// The rowid of the LeafTable cell at `offset`, without reading its payload
+fn leaf_cell_row_id<R: Read + std::io::Seek>(
+    page: &Page,
+    offset: u16,
+    reader: &mut R,
+) -> Result<i64> {
+    reader.seek(SeekFrom::Start(page.start_offset + offset as u64))?;
+    varint::parse_varint_from_reader(reader)?;
+    let (row_id, _) = varint::parse_varint_from_reader(reader)?;
+
+    Ok(row_id)
+}
+
+/// Where the rollback journal of the database at `path` goes, as SQLite names it
+#[cfg(feature = "fs")]
+fn journal_path(path: &Path) -> PathBuf {
+    let mut journal_path = path.to_path_buf().into_os_string();
+    journal_path.push("-journal");
+
+    PathBuf::from(journal_path)
+}
+
+/// The bytes of a new database with `page_size` byte pages: the header, then an empty
+/// sqlite_schema table filling the rest of page 1
+pub fn new_database(page_size: u32) -> Result<Vec<u8>> {
+    if !(512..=65_536).contains(&page_size) || !page_size.is_power_of_two() {
+        bail!("invalid page size: {page_size} (expected a power of two from 512 to 65536)");
+    }
+
+    let mut bytes = vec![0; page_size as usize];
+    bytes[..DATABASE_HEADER_SIZE].copy_from_slice(&DatabaseHeader::new(page_size).to_bytes());
+    bytes[DATABASE_HEADER_SIZE] = BTreePage::LeafTable as u8;
+    // With no cells, the content area starts at the end of the page (stored as 0 for 65536)
+    let content_start = page_size as u16;
+    bytes[DATABASE_HEADER_SIZE + 5..DATABASE_HEADER_SIZE + 7]
+        .copy_from_slice(&content_start.to_be_bytes());
+
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{collections::HashMap, sync::Arc, thread};
+
+    /// A database with nothing in it: a header, then an empty sqlite_schema table on page 1
+    fn empty_database() -> Vec<u8> {
+        new_database(512).unwrap()
+    }
+
+    /// Writes a table leaf page holding `cells` of (rowid, record), whose page header starts at
+    /// `header_start`
+    fn write_leaf(page: &mut [u8], header_start: usize, cells: &[(i64, Vec<u8>)]) {
+        page[header_start] = 13;
+        page[header_start + 3..header_start + 5]
+            .copy_from_slice(&(cells.len() as u16).to_be_bytes());
+
+        let mut content_start = page.len();
+        for (i, (row_id, record)) in cells.iter().enumerate() {
+            let mut cell = varint::encode_varint(record.len() as i64);
+            cell.extend(varint::encode_varint(*row_id));
+            cell.extend(record);
+
+            content_start -= cell.len();
+            page[content_start..content_start + cell.len()].copy_from_slice(&cell);
+            let pointer = header_start + 8 + 2 * i;
+            page[pointer..pointer + 2].copy_from_slice(&(content_start as u16).to_be_bytes());
+        }
+        page[header_start + 5..header_start + 7]
+            .copy_from_slice(&(content_start as u16).to_be_bytes());
+    }
+
+    /// A database with a table t(a) of three rows, on page 2
+    fn database_with_table() -> Vec<u8> {
+        let mut bytes = empty_database();
+        bytes.resize(1024, 0);
+        bytes[28..32].copy_from_slice(&2u32.to_be_bytes());
+
+        let text = |s: &str| Value::Text(s.to_string());
+        let schema_row = record::encode_record(
+            &[
+                text("table"),
+                text("t"),
+                text("t"),
+                Value::Integer(2),
+                text("CREATE TABLE t(a)"),
+            ],
+            4,
+        );
+        write_leaf(&mut bytes[..512], DATABASE_HEADER_SIZE, &[(1, schema_row)]);
+
+        let rows: Vec<_> = ["apple", "banana", "cherry"]
+            .iter()
+            .zip(1..)
+            .map(|(fruit, row_id)| (row_id, record::encode_record(&[text(fruit)], 4)))
+            .collect();
+        write_leaf(&mut bytes[512..], 0, &rows);
+
+        bytes
+    }
+
+    #[test]
+    fn test_open_in_memory() {
+        let database = Database::open_bytes(empty_database()).unwrap();
+        assert_eq!(database.page_count, 1);
+        assert!(database.schema().unwrap().is_empty());
+        assert_eq!(database.page_bytes(1).unwrap().len(), 512);
+
+        let database = Database::open_reader(Cursor::new(empty_database())).unwrap();
+        assert!(database.schema().unwrap().is_empty());
+
+        assert!(Database::open_bytes(vec![0; 512]).is_err());
+        assert!(database.anomalies().is_empty());
+
+        // A zero page count is worked out from the file size instead
+        let mut bytes = empty_database();
+        bytes[28..32].fill(0);
+        let database = Database::open_bytes(bytes).unwrap();
+        assert_eq!(database.page_count, 1);
+        assert_eq!(database.anomalies(), [HeaderAnomaly::ZeroPageCount]);
+
+        let vfs = crate::vfs::MemoryVfs::new();
+        let path = Path::new("empty.db");
+        vfs.open(path)
+            .unwrap()
+            .write_at(0, &empty_database())
+            .unwrap();
+        let database = Database::open_vfs(&vfs, path).unwrap();
+        assert!(database.schema().unwrap().is_empty());
+
+        assert!(new_database(1000).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "fs")]
+    fn test_create() {
+        let path = std::env::temp_dir().join(format!("sqlite-rust-create-{}", std::process::id()));
+        let database = Database::create(&path).unwrap();
+        assert_eq!(database.page_size, DEFAULT_PAGE_SIZE);
+        assert_eq!(database.page_count, 1);
+        assert!(database.schema().unwrap().is_empty());
+
+        // An existing file isn't overwritten
+        assert!(Database::create(&path).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_malformed_databases() {
+        let bytes = database_with_table();
+        let database = Database::open_bytes(bytes.clone()).unwrap();
+        assert_eq!(database.read_table(2).unwrap().len(), 3);
+
+        // Too many cells for the page, a cell pointer past its end, and a cell claiming a payload
+        // bigger than the page
+        let mut corrupt = bytes.clone();
+        corrupt[515..517].copy_from_slice(&300u16.to_be_bytes());
+        assert!(Database::open_bytes(corrupt)
+            .unwrap()
+            .read_table(2)
+            .is_err());
+        let mut corrupt = bytes.clone();
+        corrupt[520..522].copy_from_slice(&600u16.to_be_bytes());
+        assert!(Database::open_bytes(corrupt)
+            .unwrap()
+            .read_table(2)
+            .is_err());
+        let mut corrupt = bytes.clone();
+        let cell = u16::from_be_bytes([bytes[520], bytes[521]]) as usize;
+        corrupt[512 + cell] = 0x7f;
+        assert!(Database::open_bytes(corrupt)
+            .unwrap()
+            .read_table(2)
+            .is_err());
+
+        // An interior page that's its own child
+        let mut corrupt = bytes.clone();
+        corrupt[512..524].copy_from_slice(&[5, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 2]);
+        assert!(Database::open_bytes(corrupt.clone())
+            .unwrap()
+            .read_table(2)
+            .is_err());
+        assert!(Database::open_bytes(corrupt)
+            .unwrap()
+            .find_row(2, 1)
+            .is_err());
+
+        // Whatever a byte is changed to, reading fails or succeeds, but never panics
+        for position in 0..bytes.len() {
+            for value in [0x00, 0x01, 0x7f, 0x80, 0xff] {
+                let mut corrupt = bytes.clone();
+                corrupt[position] = value;
+                let Ok(database) = Database::open_bytes(corrupt) else {
+                    continue;
+                };
+                let Ok(schema) = database.schema() else {
+                    continue;
+                };
+                for entry in schema {
+                    let _ = database.read_table(entry.root_page);
+                    let _ = database.btree_size(entry.root_page);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_next_row_id() {
+        let database = Database::open_bytes(database_with_table()).unwrap();
+        assert_eq!(database.max_row_id(2).unwrap(), Some(3));
+        assert_eq!(database.next_row_id("t").unwrap(), 4);
+        assert!(database.next_row_id("u").is_err());
+
+        // Make t AUTOINCREMENT, with sqlite_sequence on page 3 recording that it's had rowid 10
+        let mut bytes = database_with_table();
+        bytes.resize(1536, 0);
+        bytes[28..32].copy_from_slice(&3u32.to_be_bytes());
+        let text = |s: &str| Value::Text(s.to_string());
+        let schema_row = |name, root_page: i64, sql| {
+            record::encode_record(
+                &[
+                    text("table"),
+                    text(name),
+                    text(name),
+                    Value::Integer(root_page),
+                    text(sql),
+                ],
+                4,
+            )
+        };
+        write_leaf(
+            &mut bytes[..512],
+            DATABASE_HEADER_SIZE,
+            &[
+                (
+                    1,
+                    schema_row(
+                        "t",
+                        2,
+                        "CREATE TABLE t(id INTEGER PRIMARY KEY AUTOINCREMENT, a)",
+                    ),
+                ),
+                (
+                    2,
+                    schema_row(
+                        "sqlite_sequence",
+                        3,
+                        "CREATE TABLE sqlite_sequence(name,seq)",
+                    ),
+                ),
+            ],
+        );
+        write_leaf(
+            &mut bytes[1024..],
+            0,
+            &[(
+                1,
+                record::encode_record(&[text("t"), Value::Integer(10)], 4),
+            )],
+        );
+
+        let database = Database::open_bytes(bytes).unwrap();
+        assert_eq!(database.sequence("t").unwrap(), Some(10));
+        assert_eq!(database.next_row_id("t").unwrap(), 11);
+    }
+
+    #[test]
+    fn test_shared_between_threads() {
+        let database = Arc::new(Database::open_bytes(empty_database()).unwrap());
+
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let database = database.clone();
+                thread::spawn(move || database.schema().unwrap().len())
+            })
+            .collect();
+        for thread in threads {
+            assert_eq!(thread.join().unwrap(), 0);
+        }
+    }
+
+    /// A huge database that's mostly zeros, with only the pages in `pages` holding anything
+    struct SparsePages {
+        page_size: u32,
+        page_count: u32,
+        pages: HashMap<u32, Vec<u8>>,
+    }
+
+    impl PageSource for SparsePages {
+        fn size(&self) -> Result<u64> {
+            Ok(self.page_count as u64 * self.page_size as u64)
+        }
+
+        fn read_at(&self, offset: u64, len: usize) -> Result<Cow<'_, [u8]>> {
+            let page_size = self.page_size as u64;
+            let mut bytes = vec![0; len];
+            let mut position = offset;
+            while position < offset + len as u64 {
+                let page_num = (position / page_size) as u32 + 1;
+                let start = (position % page_size) as usize;
+                let n = (page_size as usize - start).min((offset + len as u64 - position) as usize);
+                if let Some(page) = self.pages.get(&page_num) {
+                    let at = (position - offset) as usize;
+                    bytes[at..at + n].copy_from_slice(&page[start..start + n]);
+                }
+                position += n as u64;
+            }
+
+            Ok(Cow::Owned(bytes))
+        }
+    }
+
+    #[test]
+    fn test_large_offsets() {
+        use crate::btree_writer::BtreeWriter;
+
+        let page_size = 65_536;
+        let text = |s: &str| Value::Text(s.to_string());
+        let write_table = |first_page, rows: i64, len| {
+            let mut writer = BtreeWriter::new(page_size, first_page);
+            let cells = (1..=rows)
+                .map(|row_id| {
+                    let payload = record::encode_record(&[text(&"x".repeat(len))], 4);
+                    Ok((row_id, writer.table_cell(row_id, &payload)?))
+                })
+                .collect::<Result<Vec<_>>>()
+                .unwrap();
+            let root_page = writer.write_table(cells, None).unwrap();
+            (writer, root_page)
+        };
+
+        // One table's pages go either side of the lock-byte page, 1 GiB in, and the other's
+        // are past 4 GiB, where offsets don't fit in a u32
+        let (low, low_root) = write_table(lock_byte_page(page_size) - 2, 10, 20_000);
+        let (high, high_root) = write_table(70_000, 3, 10);
+        assert!(low
+            .pages
+            .iter()
+            .all(|(page_num, _)| *page_num != lock_byte_page(page_size)));
+        assert!(high_root as u64 * page_size as u64 > u32::MAX as u64);
+
+        let mut schema = BtreeWriter::new(page_size, 2);
+        let cells = [("low", low_root), ("high", high_root)]
+            .iter()
+            .zip(1..)
+            .map(|((name, root_page), row_id)| {
+                let row = [
+                    text("table"),
+                    text(name),
+                    text(name),
+                    Value::Integer(*root_page as i64),
+                    text(&format!("CREATE TABLE {name}(x)")),
+                ];
+                let payload = record::encode_record(&row, 4);
+                Ok((row_id, schema.table_cell(row_id, &payload)?))
+            })
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        schema.write_table(cells, Some(1)).unwrap();
+
+        let mut header = DatabaseHeader::new(page_size);
+        header.page_count = high.page_count();
+        let mut pages: HashMap<_, _> = [schema, low, high]
+            .into_iter()
+            .flat_map(|writer| writer.pages)
+            .collect();
+        pages.get_mut(&1).unwrap()[..DATABASE_HEADER_SIZE].copy_from_slice(&header.to_bytes());
+
+        let database = Database::open(SparsePages {
+            page_size,
+            page_count: header.page_count,
+            pages,
+        })
+        .unwrap();
+        assert_eq!(database.read_table(low_root).unwrap().len(), 10);
+        let high_rows = database.read_table(high_root).unwrap();
+        assert_eq!(high_rows[2].value(0), text(&"x".repeat(10)));
+
+        // The lock-byte page is neither read nor reported as unused
+        let lock_byte_page = lock_byte_page(page_size);
+        assert!(database.page_bytes(lock_byte_page).is_err());
+        let problems = database.check().unwrap();
+        assert!(problems
+            .iter()
+            .all(|problem| problem.ends_with("never used")));
+        assert!(!problems.contains(&format!("Page {lock_byte_page}: never used")));
+        assert!(!problems.contains(&format!("Page {high_root}: never used")));
+    }
+}
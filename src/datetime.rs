@@ -0,0 +1,835 @@
+//! SQLite's date and time functions: date(), time(), datetime(), julianday(), unixepoch() and
+//! strftime(). [lang_datefunc](https://www.sqlite.org/lang_datefunc.html)
+//!
+//! Each takes a time value, which is ISO-8601 text like "2024-02-29 13:45", a Julian day number,
+//! or "now", followed by modifiers that are applied to it from left to right, like "+1 day" or
+//! "start of month". As in SQLite, a time value or a modifier that can't be understood makes the
+//! result NULL.
+//!
+//! Times are UTC unless the "localtime" modifier converts one to local time, which "utc" converts
+//! back. With the localtime feature, on unix systems, they go by the system's time zone, as
+//! sqlite3 does, by way of localtime_r(). Without it there's no time zone to go by, so local time
+//! is taken to be UTC and both modifiers leave the time as it is.
+
+use crate::value::Value;
+use anyhow::Result;
+
+const DAY_MS: i64 = 86_400_000;
+/// 1970-01-01 00:00:00 as a Julian day number, in milliseconds
+const UNIX_EPOCH_MS: i64 = 210_866_760_000_000;
+/// The last millisecond of 9999-12-31, the latest time these functions handle
+const MAX_MS: i64 = 464_269_060_799_999;
+
+/// A date and time of day in the proleptic Gregorian calendar
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Civil {
+    year: i64,
+    month: i64,
+    day: i64,
+    hour: i64,
+    minute: i64,
+    /// Seconds, with any milliseconds as the fraction
+    second: f64,
+}
+
+impl Civil {
+    /// The date and time of a moment, given in milliseconds since the start of the Julian day
+    /// count, noon on November 24, 4714 BC
+    fn from_ms(ms: i64) -> Civil {
+        // The same arithmetic as SQLite's computeYMD() and computeHMS()
+        let z = (ms + DAY_MS / 2) / DAY_MS;
+        let alpha = ((z as f64 + 32044.75) / 36524.25) as i64 - 52;
+        let a = z + 1 + alpha - ((alpha + 100) / 4) + 25;
+        let b = a + 1524;
+        let c = ((b as f64 - 122.1) / 365.25) as i64;
+        let d = (36525 * (c & 32767)) / 100;
+        let e = ((b - d) as f64 / 30.6001) as i64;
+        let month = if e < 14 { e - 1 } else { e - 13 };
+
+        let day_ms = (ms + DAY_MS / 2) % DAY_MS;
+        Civil {
+            year: if month > 2 { c - 4716 } else { c - 4715 },
+            month,
+            day: b - d - (30.6001 * e as f64) as i64,
+            hour: day_ms / 3_600_000,
+            minute: day_ms / 60_000 % 60,
+            second: (day_ms % 60_000) as f64 / 1000.0,
+        }
+    }
+
+    /// The moment in milliseconds. Days past the end of the month carry into the next one, so
+    /// February 31 is early in March.
+    fn to_ms(self) -> i64 {
+        let (mut year, mut month) = (self.year, self.month);
+        if month <= 2 {
+            year -= 1;
+            month += 12;
+        }
+        let a = (year + 4800) / 100;
+        let b = 38 - a + (a / 4);
+        let x1 = 36525 * (year + 4716) / 100;
+        let x2 = 306001 * (month + 1) / 10000;
+        let day_ms = ((x1 + x2 + self.day + b) as f64 - 1524.5) * DAY_MS as f64;
+
+        day_ms as i64
+            + self.hour * 3_600_000
+            + self.minute * 60_000
+            + (self.second * 1000.0 + 0.5) as i64
+    }
+
+    fn date(&self) -> String {
+        let sign = if self.year < 0 { "-" } else { "" };
+        format!(
+            "{sign}{:04}-{:02}-{:02}",
+            self.year.abs(),
+            self.month,
+            self.day
+        )
+    }
+
+    fn time(&self, subsec: bool) -> String {
+        if subsec {
+            format!("{:02}:{:02}:{:06.3}", self.hour, self.minute, self.second)
+        } else {
+            let second = self.second as i64;
+            format!("{:02}:{:02}:{second:02}", self.hour, self.minute)
+        }
+    }
+}
+
+/// A time value with its modifiers applied
+struct DateTime {
+    ms: i64,
+    /// Whether the "subsec" modifier asked for milliseconds in the result
+    subsec: bool,
+    /// Whether the time is known to be UTC, as "now" and a time with a time zone are, so that
+    /// "utc" leaves it alone
+    utc: bool,
+    /// Whether "localtime" has converted the time to local time, so that it isn't again
+    local: bool,
+}
+
+impl DateTime {
+    /// Evaluates a time value and its modifiers: a function's arguments after the format, if it
+    /// takes one. Without any, it's the current time. None means the result is NULL.
+    fn evaluate(args: &[Value]) -> Result<Option<DateTime>> {
+        let (time, modifiers) = match args.split_first() {
+            Some((time, modifiers)) => (time, modifiers),
+            None => return Ok(Some(DateTime::from_utc_ms(now()?))),
+        };
+
+        let number = match time {
+            Value::Integer(i) => Some(*i as f64),
+            Value::Real(r) => Some(*r),
+            Value::Text(text) if looks_numeric(text) => text.trim().parse().ok(),
+            _ => None,
+        };
+        let (ms, utc) = match (time, number) {
+            (_, Some(julian_day)) => (julian_day_ms(julian_day), false),
+            (Value::Text(text), None) if text.trim().eq_ignore_ascii_case("now") => {
+                (Some(now()?), true)
+            }
+            (Value::Text(text), None) => match parse_time_value(text.trim()) {
+                Some((ms, zoned)) => (Some(ms), zoned),
+                None => (None, false),
+            },
+            _ => (None, false),
+        };
+        // A number that's too big for a Julian day can still be seconds since 1970
+        let read_as_unix = matches!(
+            modifiers.first(),
+            Some(Value::Text(modifier)) if matches!(
+                modifier.trim().to_ascii_lowercase().as_str(),
+                "unixepoch" | "auto"
+            )
+        );
+        let mut datetime = match ms {
+            Some(ms) if utc => DateTime::from_utc_ms(ms),
+            Some(ms) => DateTime::from_ms(ms),
+            None if number.is_some() && read_as_unix => DateTime::from_ms(-1),
+            None => return Ok(None),
+        };
+        for (i, modifier) in modifiers.iter().enumerate() {
+            let Value::Text(modifier) = modifier else {
+                return Ok(None);
+            };
+            let modifier = modifier.trim().to_ascii_lowercase();
+
+            // These say how to read a number, so they have to come straight after it
+            let applied = match (modifier.as_str(), number) {
+                ("unixepoch", Some(seconds)) if i == 0 => unix_ms(seconds).map(|ms| {
+                    datetime.ms = ms;
+                }),
+                ("julianday", Some(_)) if i == 0 => Some(()),
+                ("auto", Some(number)) if i == 0 => {
+                    let ms = match julian_day_ms(number) {
+                        Some(ms) => Some(ms),
+                        None => unix_ms(number),
+                    };
+                    ms.map(|ms| datetime.ms = ms)
+                }
+                ("unixepoch" | "julianday" | "auto", _) => None,
+                (modifier, _) => datetime.modify(modifier),
+            };
+            if applied.is_none() {
+                return Ok(None);
+            }
+        }
+
+        Ok((0..=MAX_MS).contains(&datetime.ms).then_some(datetime))
+    }
+
+    fn from_ms(ms: i64) -> DateTime {
+        DateTime {
+            ms,
+            subsec: false,
+            utc: false,
+            local: false,
+        }
+    }
+
+    fn from_utc_ms(ms: i64) -> DateTime {
+        DateTime {
+            utc: true,
+            ..DateTime::from_ms(ms)
+        }
+    }
+
+    fn civil(&self) -> Civil {
+        Civil::from_ms(self.ms)
+    }
+
+    /// Applies a modifier, lowercased, other than the ones that say how to read a number.
+    /// Returns None if it isn't one.
+    fn modify(&mut self, modifier: &str) -> Option<()> {
+        let civil = self.civil();
+        match modifier {
+            "start of day" => {
+                self.ms = Civil {
+                    hour: 0,
+                    minute: 0,
+                    second: 0.0,
+                    ..civil
+                }
+                .to_ms()
+            }
+            "start of month" => {
+                self.ms = Civil {
+                    day: 1,
+                    hour: 0,
+                    minute: 0,
+                    second: 0.0,
+                    ..civil
+                }
+                .to_ms()
+            }
+            "start of year" => {
+                self.ms = Civil {
+                    month: 1,
+                    day: 1,
+                    hour: 0,
+                    minute: 0,
+                    second: 0.0,
+                    ..civil
+                }
+                .to_ms()
+            }
+            "subsec" | "subsecond" => self.subsec = true,
+            "localtime" => {
+                if !self.local {
+                    self.ms = to_local_ms(self.ms)?;
+                }
+                self.local = true;
+                self.utc = false;
+            }
+            "utc" => {
+                if !self.utc {
+                    self.ms = to_utc_ms(self.ms)?;
+                }
+                self.utc = true;
+                self.local = false;
+            }
+            _ => {
+                if let Some(weekday) = modifier.strip_prefix("weekday ") {
+                    // The next date that's the weekday, 0 for Sunday, unless this one is
+                    let weekday: i64 = weekday.trim().parse().ok()?;
+                    if !(0..7).contains(&weekday) {
+                        return None;
+                    }
+                    let mut today = (self.ms + DAY_MS * 3 / 2) / DAY_MS % 7;
+                    if today > weekday {
+                        today -= 7;
+                    }
+                    self.ms += (weekday - today) * DAY_MS;
+                } else {
+                    self.ms = add_interval(self.ms, modifier)?;
+                }
+            }
+        }
+
+        Some(())
+    }
+}
+
+/// Applies a modifier like "+1 day", "-2.5 hours", "3 months" or "+01:30" to a moment
+fn add_interval(ms: i64, modifier: &str) -> Option<i64> {
+    let sign_len = usize::from(modifier.starts_with(['+', '-']));
+    let negative = modifier.starts_with('-');
+
+    // ±HH:MM[:SS[.SSS]] adds a time of day
+    if let Some((hour, minute, second, "")) = parse_hms(&modifier[sign_len..]) {
+        let offset = hour * 3_600_000 + minute * 60_000 + (second * 1000.0 + 0.5) as i64;
+        return Some(if negative { ms - offset } else { ms + offset });
+    }
+
+    let (number, unit) = modifier.split_once(' ')?;
+    if !looks_numeric(number) {
+        return None;
+    }
+    let amount: f64 = number.parse().ok()?;
+    let unit = unit.trim();
+    let unit = unit.strip_suffix('s').unwrap_or(unit);
+
+    // Months and years move the date in the calendar, and any fraction is counted in days
+    let (ms, fraction, unit_ms) = match unit {
+        "second" => (ms, amount, 1000.0),
+        "minute" => (ms, amount, 60_000.0),
+        "hour" => (ms, amount, 3_600_000.0),
+        "day" => (ms, amount, DAY_MS as f64),
+        "month" | "year" => {
+            if amount.abs() >= 176_000.0 {
+                return None;
+            }
+            let mut civil = Civil::from_ms(ms);
+            let months = if unit == "month" {
+                amount as i64
+            } else {
+                amount as i64 * 12
+            };
+            let month = civil.month - 1 + months;
+            civil.year += month.div_euclid(12);
+            civil.month = month.rem_euclid(12) + 1;
+            let days_per_unit = if unit == "month" { 30.0 } else { 365.0 };
+            (civil.to_ms(), amount.fract(), days_per_unit * DAY_MS as f64)
+        }
+        _ => return None,
+    };
+
+    let rounder = if fraction < 0.0 { -0.5 } else { 0.5 };
+    let offset = fraction * unit_ms + rounder;
+    (offset.abs() < 1e17).then(|| ms + offset as i64)
+}
+
+/// Whether text is written as a number, rather than anything else Rust would parse as one, like
+/// "inf"
+fn looks_numeric(text: &str) -> bool {
+    let text = text.trim();
+    !text.is_empty()
+        && text.bytes().any(|b| b.is_ascii_digit())
+        && text
+            .bytes()
+            .all(|b| b.is_ascii_digit() || matches!(b, b'+' | b'-' | b'.' | b'e' | b'E'))
+}
+
+fn julian_day_ms(julian_day: f64) -> Option<i64> {
+    let ms = julian_day * DAY_MS as f64 + 0.5;
+    (0.0..=MAX_MS as f64).contains(&ms).then_some(ms as i64)
+}
+
+fn unix_ms(seconds: f64) -> Option<i64> {
+    let ms = seconds * 1000.0 + UNIX_EPOCH_MS as f64 + 0.5;
+    (0.0..=MAX_MS as f64).contains(&ms).then_some(ms as i64)
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+fn now() -> Result<i64> {
+    let elapsed = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?;
+    Ok(UNIX_EPOCH_MS + elapsed.as_millis() as i64)
+}
+
+/// A browser's clock can't be read without bindings to JavaScript
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+fn now() -> Result<i64> {
+    anyhow::bail!("the current time isn't available on this platform")
+}
+
+/// Converts a UTC moment to local time, the way SQLite's toLocaltime() does: the system's time
+/// zone rules are only asked about 1971 to 2037, so a year outside them is converted as though it
+/// were a year between 2000 and 2003 that's the same distance from a leap year
+#[cfg(all(feature = "localtime", unix))]
+fn to_local_ms(ms: i64) -> Option<i64> {
+    let civil = Civil::from_ms(ms);
+    let year_diff = if (1971..2038).contains(&civil.year) {
+        0
+    } else {
+        2000 + civil.year % 4 - civil.year
+    };
+    let shifted = Civil {
+        year: civil.year + year_diff,
+        ..civil
+    };
+    let seconds = shifted.to_ms() / 1000 - UNIX_EPOCH_MS / 1000;
+    let local = sys::localtime(seconds)?;
+
+    Some(
+        Civil {
+            year: local.tm_year as i64 + 1900 - year_diff,
+            month: local.tm_mon as i64 + 1,
+            day: local.tm_mday as i64,
+            hour: local.tm_hour as i64,
+            minute: local.tm_min as i64,
+            second: local.tm_sec as f64 + (ms % 1000) as f64 / 1000.0,
+        }
+        .to_ms(),
+    )
+}
+
+/// Without a time zone to go by, local time is UTC
+#[cfg(not(all(feature = "localtime", unix)))]
+fn to_local_ms(ms: i64) -> Option<i64> {
+    Some(ms)
+}
+
+/// Converts a local time to UTC, the way SQLite does: by guessing, converting the guess to local
+/// time and correcting the guess by how far off it was, a few times at most
+fn to_utc_ms(local_ms: i64) -> Option<i64> {
+    let mut guess = local_ms;
+    let mut error = 0;
+    for _ in 0..4 {
+        guess -= error;
+        error = to_local_ms(guess)? - local_ms;
+        if error == 0 {
+            break;
+        }
+    }
+
+    Some(guess)
+}
+
+#[cfg(all(feature = "localtime", unix))]
+mod sys {
+    use std::ffi::{c_char, c_int, c_long};
+
+    #[repr(C)]
+    pub struct Tm {
+        pub tm_sec: c_int,
+        pub tm_min: c_int,
+        pub tm_hour: c_int,
+        pub tm_mday: c_int,
+        pub tm_mon: c_int,
+        pub tm_year: c_int,
+        pub tm_wday: c_int,
+        pub tm_yday: c_int,
+        pub tm_isdst: c_int,
+        pub tm_gmtoff: c_long,
+        pub tm_zone: *const c_char,
+    }
+
+    extern "C" {
+        fn localtime_r(time: *const i64, result: *mut Tm) -> *mut Tm;
+    }
+
+    /// The local time at `seconds` since 1970, by the system's time zone, or None if it can't be
+    /// worked out
+    pub fn localtime(seconds: i64) -> Option<Tm> {
+        let mut tm = Tm {
+            tm_sec: 0,
+            tm_min: 0,
+            tm_hour: 0,
+            tm_mday: 0,
+            tm_mon: 0,
+            tm_year: 0,
+            tm_wday: 0,
+            tm_yday: 0,
+            tm_isdst: 0,
+            tm_gmtoff: 0,
+            tm_zone: std::ptr::null(),
+        };
+        // SAFETY: localtime_r only reads the time and writes the Tm it's given, both of which
+        // outlive the call
+        let result = unsafe { localtime_r(&seconds, &mut tm) };
+
+        (!result.is_null()).then_some(tm)
+    }
+}
+
+/// Parses ISO-8601 text: YYYY-MM-DD, optionally followed by a time of day after a space or a T,
+/// or a time of day alone, which is on 2000-01-01. A time can end with a time zone, Z or ±HH:MM,
+/// in which case it's converted to UTC and the second value returned is true.
+fn parse_time_value(text: &str) -> Option<(i64, bool)> {
+    let (civil, rest) = match parse_date(text) {
+        Some((year, month, day, rest)) => {
+            let date = Civil {
+                year,
+                month,
+                day,
+                hour: 0,
+                minute: 0,
+                second: 0.0,
+            };
+            match rest.strip_prefix([' ', 'T', 't']).map(str::trim_start) {
+                Some(time) if !time.is_empty() => (date, time),
+                _ if rest.trim().is_empty() => return Some((date.to_ms(), false)),
+                _ => return None,
+            }
+        }
+        None => (
+            Civil {
+                year: 2000,
+                month: 1,
+                day: 1,
+                hour: 0,
+                minute: 0,
+                second: 0.0,
+            },
+            text,
+        ),
+    };
+
+    let (hour, minute, second, rest) = parse_hms(rest)?;
+    let civil = Civil {
+        hour,
+        minute,
+        second,
+        ..civil
+    };
+
+    // A time zone says how far the time is ahead of UTC
+    let zone = rest.trim_start();
+    let offset_minutes = match zone {
+        "" | "Z" | "z" => 0,
+        zone => {
+            let negative = zone.starts_with('-');
+            let (hours, minutes, _, "") = parse_hms(zone.strip_prefix(['+', '-'])?)? else {
+                return None;
+            };
+            let minutes = hours * 60 + minutes;
+            if negative {
+                -minutes
+            } else {
+                minutes
+            }
+        }
+    };
+
+    Some((civil.to_ms() - offset_minutes * 60_000, !zone.is_empty()))
+}
+
+/// Parses YYYY-MM-DD from the start of the text, returning the year, month, day and the rest
+fn parse_date(text: &str) -> Option<(i64, i64, i64, &str)> {
+    let (year, rest) = parse_digits(text, 4)?;
+    let (month, rest) = parse_digits(rest.strip_prefix('-')?, 2)?;
+    let (day, rest) = parse_digits(rest.strip_prefix('-')?, 2)?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    Some((year, month, day, rest))
+}
+
+/// Parses HH:MM, HH:MM:SS or HH:MM:SS.SSS from the start of the text, returning the hours,
+/// minutes, seconds and the rest
+fn parse_hms(text: &str) -> Option<(i64, i64, f64, &str)> {
+    let (hour, rest) = parse_digits(text, 2)?;
+    let (minute, mut rest) = parse_digits(rest.strip_prefix(':')?, 2)?;
+    let mut second = 0.0;
+    if let Some((whole, after)) = rest.strip_prefix(':').and_then(|s| parse_digits(s, 2)) {
+        let fraction_len = after
+            .strip_prefix('.')
+            .map_or(0, |s| 1 + s.bytes().take_while(u8::is_ascii_digit).count());
+        let fraction = match fraction_len {
+            0 | 1 => 0.0,
+            len => after[..len].parse::<f64>().ok()?,
+        };
+        second = whole as f64 + fraction;
+        rest = &after[fraction_len..];
+    }
+    if hour > 24 || minute > 59 || second >= 60.0 {
+        return None;
+    }
+
+    Some((hour, minute, second, rest))
+}
+
+/// Parses exactly `count` decimal digits from the start of the text
+fn parse_digits(text: &str, count: usize) -> Option<(i64, &str)> {
+    let digits = text.get(..count)?;
+    if !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    Some((digits.parse().ok()?, &text[count..]))
+}
+
+/// [date(time, modifiers...)](https://www.sqlite.org/lang_datefunc.html): YYYY-MM-DD
+pub fn date(args: &[Value]) -> Result<Value> {
+    Ok(match DateTime::evaluate(args)? {
+        Some(datetime) => Value::Text(datetime.civil().date()),
+        None => Value::Null,
+    })
+}
+
+/// [time(time, modifiers...)](https://www.sqlite.org/lang_datefunc.html): HH:MM:SS, or
+/// HH:MM:SS.SSS with the subsec modifier
+pub fn time(args: &[Value]) -> Result<Value> {
+    Ok(match DateTime::evaluate(args)? {
+        Some(datetime) => Value::Text(datetime.civil().time(datetime.subsec)),
+        None => Value::Null,
+    })
+}
+
+/// [datetime(time, modifiers...)](https://www.sqlite.org/lang_datefunc.html): YYYY-MM-DD HH:MM:SS
+pub fn datetime(args: &[Value]) -> Result<Value> {
+    Ok(match DateTime::evaluate(args)? {
+        Some(datetime) => {
+            let civil = datetime.civil();
+            Value::Text(format!("{} {}", civil.date(), civil.time(datetime.subsec)))
+        }
+        None => Value::Null,
+    })
+}
+
+/// [julianday(time, modifiers...)](https://www.sqlite.org/lang_datefunc.html): the fractional
+/// number of days since noon on November 24, 4714 BC
+pub fn julianday(args: &[Value]) -> Result<Value> {
+    Ok(match DateTime::evaluate(args)? {
+        Some(datetime) => Value::Real(datetime.ms as f64 / DAY_MS as f64),
+        None => Value::Null,
+    })
+}
+
+/// [unixepoch(time, modifiers...)](https://www.sqlite.org/lang_datefunc.html): seconds since
+/// 1970-01-01, an integer unless the subsec modifier asks for milliseconds too
+pub fn unixepoch(args: &[Value]) -> Result<Value> {
+    Ok(match DateTime::evaluate(args)? {
+        Some(DateTime {
+            ms, subsec: true, ..
+        }) => Value::Real((ms - UNIX_EPOCH_MS) as f64 / 1000.0),
+        Some(DateTime {
+            ms, subsec: false, ..
+        }) => Value::Integer((ms - UNIX_EPOCH_MS) / 1000),
+        None => Value::Null,
+    })
+}
+
+/// [strftime(format, time, modifiers...)](https://www.sqlite.org/lang_datefunc.html#strftime):
+/// the time formatted by substitutions like %Y-%m-%d. An unknown substitution makes it NULL.
+pub fn strftime(args: &[Value]) -> Result<Value> {
+    let format = match &args[0] {
+        Value::Null => return Ok(Value::Null),
+        format => format.to_string(),
+    };
+    let Some(datetime) = DateTime::evaluate(&args[1..])? else {
+        return Ok(Value::Null);
+    };
+
+    let civil = datetime.civil();
+    let day_of_year = |civil: &Civil| {
+        let date = |month, day| {
+            Civil {
+                month,
+                day,
+                hour: 0,
+                minute: 0,
+                second: 0.0,
+                ..*civil
+            }
+            .to_ms()
+        };
+        (date(civil.month, civil.day) - date(1, 1)) / DAY_MS
+    };
+    // Days since the start of the count are Mondays when divisible by 7
+    let days = (datetime.ms + DAY_MS / 2) / DAY_MS;
+    let monday_based = days % 7;
+    let sunday_based = (days + 1) % 7;
+    let hour_12 = match civil.hour % 12 {
+        0 => 12,
+        hour => hour,
+    };
+    // ISO 8601 weeks belong to the year their Thursday is in
+    let thursday = Civil::from_ms(datetime.ms + (3 - monday_based) * DAY_MS);
+
+    let mut output = String::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            output.push(c);
+            continue;
+        }
+
+        let substitution = match chars.next() {
+            Some('d') => format!("{:02}", civil.day),
+            Some('e') => format!("{:2}", civil.day),
+            Some('f') => format!("{:06.3}", civil.second.min(59.999)),
+            Some('F') => civil.date(),
+            Some('G') => format!("{:04}", thursday.year),
+            Some('g') => format!("{:02}", thursday.year % 100),
+            Some('H') => format!("{:02}", civil.hour),
+            Some('I') => format!("{hour_12:02}"),
+            Some('j') => format!("{:03}", day_of_year(&civil) + 1),
+            Some('J') => {
+                // To 16 significant digits, like SQLite's %.16g
+                let julian_day = datetime.ms as f64 / DAY_MS as f64;
+                let whole_digits = (julian_day.max(1.0).log10() as usize) + 1;
+                let fixed = format!("{julian_day:.*}", 16usize.saturating_sub(whole_digits));
+                if fixed.contains('.') {
+                    fixed
+                        .trim_end_matches('0')
+                        .trim_end_matches('.')
+                        .to_string()
+                } else {
+                    fixed
+                }
+            }
+            Some('k') => format!("{:2}", civil.hour),
+            Some('l') => format!("{hour_12:2}"),
+            Some('m') => format!("{:02}", civil.month),
+            Some('M') => format!("{:02}", civil.minute),
+            Some('p') => (if civil.hour < 12 { "AM" } else { "PM" }).to_string(),
+            Some('P') => (if civil.hour < 12 { "am" } else { "pm" }).to_string(),
+            Some('R') => format!("{:02}:{:02}", civil.hour, civil.minute),
+            Some('s') => format!("{}", datetime.ms / 1000 - UNIX_EPOCH_MS / 1000),
+            Some('S') => format!("{:02}", civil.second as i64),
+            Some('T') => civil.time(false),
+            Some('u') => format!("{}", monday_based + 1),
+            Some('U') => format!("{:02}", (day_of_year(&civil) + 7 - sunday_based) / 7),
+            Some('V') => format!("{:02}", day_of_year(&thursday) / 7 + 1),
+            Some('w') => format!("{sunday_based}"),
+            Some('W') => format!("{:02}", (day_of_year(&civil) + 7 - monday_based) / 7),
+            Some('Y') => format!("{:04}", civil.year),
+            Some('%') => "%".to_string(),
+            _ => return Ok(Value::Null),
+        };
+        output.push_str(&substitution);
+    }
+
+    Ok(Value::Text(output))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.to_string())
+    }
+
+    fn call(f: fn(&[Value]) -> Result<Value>, args: &[&str]) -> Value {
+        f(&args.iter().map(|arg| text(arg)).collect::<Vec<_>>()).unwrap()
+    }
+
+    #[test]
+    fn test_civil_round_trip() {
+        for ms in [0, UNIX_EPOCH_MS, 211_813_488_000_000, MAX_MS] {
+            assert_eq!(Civil::from_ms(ms).to_ms(), ms);
+        }
+        assert_eq!(
+            Civil::from_ms(UNIX_EPOCH_MS),
+            Civil {
+                year: 1970,
+                month: 1,
+                day: 1,
+                hour: 0,
+                minute: 0,
+                second: 0.0
+            }
+        );
+    }
+
+    #[test]
+    fn test_time_values() {
+        assert_eq!(
+            call(datetime, &["2024-02-29T13:45:30.25"]),
+            text("2024-02-29 13:45:30")
+        );
+        assert_eq!(call(datetime, &["2024-02-29"]), text("2024-02-29 00:00:00"));
+        assert_eq!(call(datetime, &["13:45"]), text("2000-01-01 13:45:00"));
+        assert_eq!(
+            call(datetime, &["2024-02-29 23:30 -02:00"]),
+            text("2024-03-01 01:30:00")
+        );
+        assert_eq!(call(date, &["2460000.5"]), text("2023-02-25"));
+        assert_eq!(
+            julianday(&[text("2000-01-01 12:00")]).unwrap(),
+            Value::Real(2451545.0)
+        );
+        assert_eq!(
+            date(&[Value::Integer(1700000000), text("unixepoch")]).unwrap(),
+            text("2023-11-14")
+        );
+        assert_eq!(
+            unixepoch(&[text("2023-11-14 22:13:20")]).unwrap(),
+            Value::Integer(1700000000)
+        );
+
+        for bad in ["2024-13-01", "2024-2-1", "25:00", "yesterday", "inf"] {
+            assert_eq!(call(date, &[bad]), Value::Null, "{bad}");
+        }
+        assert_eq!(date(&[Value::Null]).unwrap(), Value::Null);
+    }
+
+    #[test]
+    fn test_modifiers() {
+        let modified = |modifiers: &[&str]| {
+            let mut args = vec!["2024-01-31 10:20:30"];
+            args.extend(modifiers);
+            call(datetime, &args)
+        };
+
+        assert_eq!(modified(&["+1 day"]), text("2024-02-01 10:20:30"));
+        assert_eq!(modified(&["-1.5 hours"]), text("2024-01-31 08:50:30"));
+        assert_eq!(modified(&["+01:30"]), text("2024-01-31 11:50:30"));
+        // February 31 is March 2 in a leap year
+        assert_eq!(modified(&["+1 month"]), text("2024-03-02 10:20:30"));
+        assert_eq!(modified(&["-13 months"]), text("2022-12-31 10:20:30"));
+        assert_eq!(
+            modified(&["start of month", "+1 month", "-1 day"]),
+            text("2024-01-31 00:00:00")
+        );
+        assert_eq!(modified(&["start of year"]), text("2024-01-01 00:00:00"));
+        assert_eq!(modified(&["START OF DAY"]), text("2024-01-31 00:00:00"));
+        // The 31st was a Wednesday
+        assert_eq!(modified(&["weekday 0"]), text("2024-02-04 10:20:30"));
+        assert_eq!(modified(&["weekday 3"]), text("2024-01-31 10:20:30"));
+        assert_eq!(
+            call(time, &["10:20:30.125", "subsec"]),
+            text("10:20:30.125")
+        );
+
+        for bad in ["+1 fortnight", "weekday 7", "unixepoch", "local time"] {
+            assert_eq!(modified(&[bad]), Value::Null, "{bad}");
+        }
+    }
+
+    #[test]
+    #[cfg(not(all(feature = "localtime", unix)))]
+    fn test_localtime_without_time_zone() {
+        let modified = |modifiers: &[&str]| {
+            let mut args = vec!["2024-07-01 12:00:00"];
+            args.extend(modifiers);
+            call(datetime, &args)
+        };
+
+        for modifiers in [&["localtime"][..], &["utc"], &["localtime", "utc"]] {
+            assert_eq!(
+                modified(modifiers),
+                text("2024-07-01 12:00:00"),
+                "{modifiers:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_strftime() {
+        let format = |format| call(strftime, &[format, "2024-12-30 15:04:05.678"]);
+
+        assert_eq!(format("%Y-%m-%d %H:%M:%S"), text("2024-12-30 15:04:05"));
+        assert_eq!(format("%j %w %u %W %U"), text("365 1 1 53 52"));
+        // Its Thursday is in 2025, so it's the first ISO week of 2025
+        assert_eq!(format("%G-W%V %g"), text("2025-W01 25"));
+        assert_eq!(format("%I:%M %p %P %l %e"), text("03:04 PM pm  3 30"));
+        assert_eq!(format("%f %s %%"), text("05.678 1735571045 %"));
+        assert_eq!(format("%F %T %R"), text("2024-12-30 15:04:05 15:04"));
+        assert_eq!(format("%Q"), Value::Null);
+    }
+}
@@ -0,0 +1,247 @@
+use crate::{
+    affinity::Affinity,
+    database::Database,
+    functions::{like, quote},
+    query_parser::{ColumnDefinition, CreateTable},
+    record::Record,
+    schema::Schema,
+    value::Value,
+};
+use anyhow::Result;
+use itertools::Itertools;
+use std::{cmp::Ordering, io::Write, iter::Peekable};
+
+/// Writes the SQL that would change `from` into `to`, table by table, as sqldiff does. Rows are
+/// matched by rowid, and a table whose columns changed is dropped and created again.
+pub fn write_diff(from: &Database, to: &Database, output: &mut dyn Write) -> Result<()> {
+    let _from_lock = from.read_lock()?;
+    let _to_lock = to.read_lock()?;
+    let from_schema = from.schema()?;
+    let to_schema = to.schema()?;
+
+    let from_tables = user_tables(&from_schema);
+    let to_tables = user_tables(&to_schema);
+    let names = from_tables
+        .iter()
+        .chain(&to_tables)
+        .map(|(name, _)| name)
+        .sorted()
+        .dedup();
+
+    for name in names {
+        match (find(&from_tables, name), find(&to_tables, name)) {
+            (Some(table), None) => writeln!(output, "DROP TABLE {};", identifier(&table.name))?,
+            (None, Some(table)) => write_new_table(to, table, output)?,
+            (Some(from_table), Some(to_table)) => {
+                let from_create = from_table.create_table()?;
+                let to_create = to_table.create_table()?;
+                if same_columns(&from_create, &to_create) {
+                    write_row_changes(from, from_table, to, to_table, &to_create, output)?;
+                } else {
+                    writeln!(output, "DROP TABLE {};", identifier(&from_table.name))?;
+                    write_new_table(to, to_table, output)?;
+                }
+            }
+            (None, None) => unreachable!("every name is from one of the schemas"),
+        }
+    }
+
+    Ok(())
+}
+
+/// The tables in the schema, leaving out SQLite's own, with their names lowercased to match them
+/// up by
+fn user_tables(schema: &[Schema]) -> Vec<(String, &Schema)> {
+    schema
+        .iter()
+        .filter(|s| s.is_table() && !like("sqlite_%", &s.name, None))
+        .map(|s| (s.name.to_lowercase(), s))
+        .collect()
+}
+
+/// The table named `name`, lowercased
+fn find<'a>(tables: &[(String, &'a Schema)], name: &str) -> Option<&'a Schema> {
+    tables
+        .iter()
+        .find(|(other, _)| other == name)
+        .map(|(_, table)| *table)
+}
+
+/// Whether rows of the two tables can be compared column by column, and matched by rowid
+fn same_columns(a: &CreateTable, b: &CreateTable) -> bool {
+    a.rowid_alias == b.rowid_alias
+        && a.columns.len() == b.columns.len()
+        && a.columns
+            .iter()
+            .zip(&b.columns)
+            .all(|(a, b)| a.name.eq_ignore_ascii_case(&b.name))
+}
+
+/// Writes the table's CREATE TABLE, followed by an INSERT for each of its rows
+fn write_new_table(database: &Database, table: &Schema, output: &mut dyn Write) -> Result<()> {
+    if let Some(sql) = &table.sql {
+        writeln!(output, "{sql};")?;
+    }
+    let create_table = table.create_table()?;
+    for record in database.table_rows(table.root_page, &create_table)? {
+        write_insert(table, &create_table, &record?, output)?;
+    }
+
+    Ok(())
+}
+
+/// Walks both tables in rowid order, writing a DELETE for each row that's only in `from`, an
+/// INSERT for each that's only in `to`, and an UPDATE of the changed columns of each that's in
+/// both but differs
+fn write_row_changes(
+    from: &Database,
+    from_table: &Schema,
+    to: &Database,
+    to_table: &Schema,
+    create_table: &CreateTable,
+    output: &mut dyn Write,
+) -> Result<()> {
+    let mut from_rows = from
+        .table_rows(from_table.root_page, create_table)?
+        .peekable();
+    let mut to_rows = to.table_rows(to_table.root_page, create_table)?.peekable();
+    let table_name = identifier(&to_table.name);
+
+    loop {
+        let ordering = match (from_rows.peek(), to_rows.peek()) {
+            (None, None) => return Ok(()),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (Some(Ok(from_row)), Some(Ok(to_row))) => from_row.row_id.cmp(&to_row.row_id),
+            // Taking the row that failed to read returns its error
+            (Some(Err(_)), _) => Ordering::Less,
+            (_, Some(Err(_))) => Ordering::Greater,
+        };
+
+        match ordering {
+            Ordering::Less => {
+                let row = next_row(&mut from_rows)?;
+                writeln!(
+                    output,
+                    "DELETE FROM {table_name} WHERE {};",
+                    row_key(create_table, &row)
+                )?;
+            }
+            Ordering::Greater => {
+                write_insert(to_table, create_table, &next_row(&mut to_rows)?, output)?
+            }
+            Ordering::Equal => {
+                let from_row = next_row(&mut from_rows)?;
+                let to_row = next_row(&mut to_rows)?;
+                let from_values = row_values(create_table, &from_row);
+                let changes = row_values(create_table, &to_row)
+                    .into_iter()
+                    .zip(inserted_columns(create_table))
+                    .zip(from_values)
+                    .filter(|((to_value, _), from_value)| to_value != from_value)
+                    .map(|((value, column), _)| {
+                        format!("{}={}", identifier(&column.name), quote(&value))
+                    })
+                    .collect_vec();
+                if !changes.is_empty() {
+                    writeln!(
+                        output,
+                        "UPDATE {table_name} SET {} WHERE {};",
+                        changes.join(", "),
+                        row_key(create_table, &to_row)
+                    )?;
+                }
+            }
+        }
+    }
+}
+
+/// Takes the row that was just peeked at
+fn next_row(rows: &mut Peekable<impl Iterator<Item = Result<Record>>>) -> Result<Record> {
+    rows.next().expect("the row was peeked at")
+}
+
+/// Writes an INSERT of the row, giving its rowid unless a column aliases it
+fn write_insert(
+    table: &Schema,
+    create_table: &CreateTable,
+    record: &Record,
+    output: &mut dyn Write,
+) -> Result<()> {
+    let mut columns = inserted_columns(create_table)
+        .map(|column| identifier(&column.name))
+        .collect_vec();
+    let mut values = row_values(create_table, record)
+        .iter()
+        .map(quote)
+        .collect_vec();
+    if create_table.rowid_alias.is_none() {
+        columns.insert(0, "rowid".to_string());
+        values.insert(0, record.row_id.to_string());
+    }
+
+    writeln!(
+        output,
+        "INSERT INTO {}({}) VALUES({});",
+        identifier(&table.name),
+        columns.join(","),
+        values.join(",")
+    )?;
+
+    Ok(())
+}
+
+/// The laid out row's value for each of the table's columns that an INSERT gives, with the rowid
+/// for the column aliasing it, and the integers a REAL column stores as reals
+pub(crate) fn row_values(create_table: &CreateTable, record: &Record) -> Vec<Value> {
+    (0..create_table.columns.len())
+        .filter(|&i| create_table.columns[i].generated.is_none())
+        .map(|i| match create_table.rowid_alias {
+            Some(alias) if alias == i => Value::Integer(record.row_id),
+            _ => Affinity::from_type_name(&create_table.columns[i].type_name).load(record.value(i)),
+        })
+        .collect()
+}
+
+/// The table's columns that an INSERT gives values for, which leaves out generated columns, as
+/// they're computed from the others
+fn inserted_columns(create_table: &CreateTable) -> impl Iterator<Item = &ColumnDefinition> {
+    create_table
+        .columns
+        .iter()
+        .filter(|column| column.generated.is_none())
+}
+
+/// The WHERE condition matching the row by its rowid, or the column aliasing it
+fn row_key(create_table: &CreateTable, record: &Record) -> String {
+    let column = match create_table.rowid_alias {
+        Some(alias) => identifier(&create_table.columns[alias].name),
+        None => "rowid".to_string(),
+    };
+
+    format!("{column}={}", record.row_id)
+}
+
+/// A table or column name as SQL, in double quotes unless it's a plain word
+pub(crate) fn identifier(name: &str) -> String {
+    let plain = name.chars().next().is_some_and(|c| !c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if plain {
+        name.to_string()
+    } else {
+        format!("\"{}\"", name.replace('"', "\"\""))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identifier() {
+        assert_eq!(identifier("apples"), "apples");
+        assert_eq!(identifier("my table"), "\"my table\"");
+        assert_eq!(identifier("1st"), "\"1st\"");
+        assert_eq!(identifier("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+}
@@ -0,0 +1,85 @@
+use crate::{database::Database, schema::Schema};
+use anyhow::Result;
+use std::io::Write;
+
+/// What a dot-command gets to work with: the open database, its schema, and the shell's output,
+/// which may be redirected by .output or .once
+pub struct DotCommandContext<'a> {
+    pub database: &'a Database,
+    pub schema: &'a [Schema],
+    pub output: &'a mut dyn Write,
+}
+
+/// The implementation of a dot-command, given its arguments split on whitespace
+pub type DotCommandFn = dyn Fn(&mut DotCommandContext, &[&str]) -> Result<()> + Send + Sync;
+
+/// A dot-command added to the shell by a library user or a plugin
+pub struct DotCommand {
+    pub name: String,
+    /// A line describing the command and its arguments, e.g. ".pii-scan TABLE  Look for PII"
+    pub help: String,
+    pub run: Box<DotCommandFn>,
+}
+
+/// Dot-commands that aren't built in, so that a shell can be extended without forking it. The
+/// shell's own commands take precedence over registered ones of the same name, and a command
+/// registered later takes precedence over an earlier one.
+#[derive(Default)]
+pub struct DotCommandRegistry {
+    commands: Vec<DotCommand>,
+}
+
+impl DotCommandRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `.name`, which the shell then runs with its arguments
+    pub fn register<F>(&mut self, name: &str, help: &str, run: F)
+    where
+        F: Fn(&mut DotCommandContext, &[&str]) -> Result<()> + Send + Sync + 'static,
+    {
+        self.commands.push(DotCommand {
+            name: name.trim_start_matches('.').to_string(),
+            help: help.to_string(),
+            run: Box::new(run),
+        });
+    }
+
+    /// Looks up a command by name, without its leading "."
+    pub fn find(&self, name: &str) -> Option<&DotCommand> {
+        self.commands
+            .iter()
+            .rev()
+            .find(|command| command.name == name)
+    }
+
+    /// Every registered command, in the order they were registered
+    pub fn commands(&self) -> impl Iterator<Item = &DotCommand> {
+        self.commands.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registry() {
+        let mut registry = DotCommandRegistry::new();
+        registry.register(".hello", ".hello NAME  Greet someone", |context, args| {
+            writeln!(context.output, "hello, {}", args.join(" "))?;
+            Ok(())
+        });
+        registry.register("hello", ".hello  Greet louder", |context, args| {
+            writeln!(context.output, "HELLO, {}", args.join(" "))?;
+            Ok(())
+        });
+
+        assert!(registry.find("goodbye").is_none());
+        assert_eq!(registry.commands().count(), 2);
+
+        let command = registry.find("hello").unwrap();
+        assert_eq!(command.help, ".hello  Greet louder");
+    }
+}
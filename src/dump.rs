@@ -0,0 +1,103 @@
+use crate::{
+    database::Database,
+    diff::{identifier, row_values},
+    functions::{like, quote},
+    schema::Schema,
+    value::Value,
+};
+use anyhow::Result;
+use itertools::Itertools;
+use std::io::Write;
+
+/// Writes the database as the SQL that would make it again, as sqlite3's .dump does: each table's
+/// CREATE TABLE and an INSERT per row, then its indexes, views and triggers, all in one
+/// transaction. With a `pattern`, only the tables whose names are LIKE it are written, with their
+/// indexes and triggers.
+pub fn write_dump(
+    database: &Database,
+    pattern: Option<&str>,
+    output: &mut dyn Write,
+) -> Result<()> {
+    let _lock = database.read_lock()?;
+    let schema = database.schema()?;
+    let included = |name: &str| pattern.is_none_or(|pattern| like(pattern, name, None));
+
+    writeln!(output, "PRAGMA foreign_keys=OFF;")?;
+    writeln!(output, "BEGIN TRANSACTION;")?;
+    let tables = schema
+        .iter()
+        .filter(|s| s.is_table() && included(&s.name))
+        .collect_vec();
+    for table in tables.iter().filter(|s| !like("sqlite_%", &s.name, None)) {
+        if let Some(sql) = &table.sql {
+            writeln!(output, "{sql};")?;
+        }
+        // Virtual tables have no b-tree, and their rows aren't the database's to write
+        if table.root_page > 0 {
+            write_rows(database, table, output)?;
+        }
+    }
+
+    // sqlite_sequence is made along with the first AUTOINCREMENT table, so it already exists
+    // when the dump is run, and only its rows are written
+    let sequence = tables.iter().find(|s| s.name == "sqlite_sequence");
+    if let Some(sequence) = sequence {
+        writeln!(output, "PRAGMA writable_schema=ON;")?;
+        writeln!(
+            output,
+            "CREATE TABLE IF NOT EXISTS sqlite_sequence(name,seq);"
+        )?;
+        writeln!(output, "DELETE FROM sqlite_sequence;")?;
+        write_rows(database, sequence, output)?;
+    }
+
+    // Views may select from any table, so they come after all of them
+    let others = schema
+        .iter()
+        .filter(|s| !s.is_table() && included(&s.table_name))
+        .sorted_by_key(|s| match s.kind.as_str() {
+            "view" => 0,
+            "trigger" => 1,
+            _ => 2,
+        });
+    for object in others {
+        // Indexes made for UNIQUE and PRIMARY KEY constraints have no SQL of their own
+        if let Some(sql) = &object.sql {
+            writeln!(output, "{sql};")?;
+        }
+    }
+    if sequence.is_some() {
+        writeln!(output, "PRAGMA writable_schema=OFF;")?;
+    }
+    writeln!(output, "COMMIT;")?;
+
+    Ok(())
+}
+
+/// Writes an INSERT for each of the table's rows, with the rowid as the value of a column that
+/// aliases it. Generated columns are left out, as SQLite computes them again.
+fn write_rows(database: &Database, table: &Schema, output: &mut dyn Write) -> Result<()> {
+    let create_table = table.create_table()?;
+    let name = identifier(&table.name);
+    for record in database.table_rows(table.root_page, &create_table)? {
+        let values = row_values(&create_table, &record?);
+        writeln!(
+            output,
+            "INSERT INTO {name} VALUES({});",
+            values.iter().map(literal).join(",")
+        )?;
+    }
+
+    Ok(())
+}
+
+/// A value as an INSERT gives it, as sqlite3's .dump writes it: like quote(), except that a real
+/// with an integer's value is written with all its digits, and ".0", rather than 15 of them
+fn literal(value: &Value) -> String {
+    match value {
+        Value::Real(r) if r.fract() == 0.0 && (i64::MIN as f64..i64::MAX as f64).contains(r) => {
+            format!("{}.0", *r as i64)
+        }
+        value => quote(value),
+    }
+}
@@ -0,0 +1,907 @@
+use crate::{
+    affinity::Affinity,
+    binder::{Binder, Source},
+    collation::Collation,
+    database::{Database, ReadLock, SchemaChangedError},
+    operator::Operator,
+    planner::{plan_query, CompoundMember, PlanNode, QueryPlan, RowCounts, RowOrder, ScanType},
+    query_parser::*,
+    record::Record,
+    row::{FromRow, Row},
+    row_layout::RowLayout,
+    sorter::Sorter,
+    types::{SerialType, SerialValue},
+    value::Value,
+    vm::{ColumnRef, Condition, Program, Vm},
+};
+use anyhow::{anyhow, bail, Result};
+use std::{
+    cell::Cell,
+    cmp::{Ordering, Reverse},
+    collections::BTreeSet,
+    rc::Rc,
+    sync::Arc,
+};
+
+/// Result rows of a query, produced lazily as they're pulled
+pub struct Rows<'a> {
+    rows: Box<dyn Iterator<Item = Result<Vec<Value>>> + 'a>,
+    /// The name of each column, as in the result's headings
+    columns: Vec<String>,
+    /// Keeps the database from being written to until the rows are dropped
+    _lock: ReadLock<'a>,
+}
+
+impl<'a> Rows<'a> {
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    /// Reads each row as a `T`, so that rows come out as the caller's own structs, with fields
+    /// read by column name rather than by matching on values
+    pub fn map_rows<T: FromRow>(self) -> impl Iterator<Item = Result<T>> + 'a {
+        let columns = self.columns.clone();
+        self.map(move |values| T::from_row(&Row::new(&columns, values?)))
+    }
+}
+
+impl Iterator for Rows<'_> {
+    type Item = Result<Vec<Value>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.rows.next()
+    }
+}
+
+/// Runs a planned query. Rows are read from the database as the returned iterator is advanced, so
+/// dropping it early (or hitting the query's LIMIT) stops the scan.
+pub fn execute<'a>(database: &'a Database, plan: &QueryPlan, query: &Query) -> Result<Rows<'a>> {
+    execute_counted(database, plan, query, Rc::default())
+}
+
+/// Runs a query to completion, for EXPLAIN ANALYZE, returning its plan tree with the number of
+/// rows each step produced
+pub fn analyze(database: &Database, plan: &QueryPlan, query: &Query) -> Result<PlanNode> {
+    let counts = Rc::new(RowCounts::default());
+    for row in execute_counted(database, plan, query, counts.clone())? {
+        row?;
+    }
+
+    Ok(plan.tree(query, Some(&counts)))
+}
+
+/// Like execute, counting the rows that come out of each step of the query as they go by
+fn execute_counted<'a>(
+    database: &'a Database,
+    plan: &QueryPlan,
+    query: &Query,
+    counts: Rc<RowCounts>,
+) -> Result<Rows<'a>> {
+    if let ScanType::Compound { members } = &plan.scan {
+        return execute_compound(database, plan, members, query, counts);
+    }
+
+    let lock = database.read_lock()?;
+    // The table and its indexes may have moved or gone since the query was planned
+    if database.current_schema_cookie()? != plan.schema_cookie {
+        return Err(SchemaChangedError.into());
+    }
+
+    let operator = build_operators(database, plan, query)?;
+    let columns = query
+        .selection_list
+        .iter()
+        .map(ResultColumn::name)
+        .collect();
+
+    Ok(Rows {
+        rows: Box::new(
+            open_rows(database, operator, &counts)?.inspect(count_into(&counts, |c| &c.output)),
+        ),
+        columns,
+        _lock: lock,
+    })
+}
+
+/// Builds the tree of operators that runs a planned query, from the operator that finds the
+/// table's rows up to the one producing the result. A compound SELECT has no tree of its own,
+/// as each of its SELECTs has one.
+pub fn build_operators(database: &Database, plan: &QueryPlan, query: &Query) -> Result<Operator> {
+    let mut operator = match &plan.scan {
+        ScanType::FullTableScan => Operator::Scan {
+            table_name: plan.table_name.clone(),
+            root_page: plan.table_root_page,
+            descending: plan.order == RowOrder::RowidDescending,
+            layout: plan.layout.clone(),
+        },
+        ScanType::RowidLookup { row_id } => Operator::RowidLookup {
+            table_name: plan.table_name.clone(),
+            root_page: plan.table_root_page,
+            row_id: *row_id,
+            layout: plan.layout.clone(),
+        },
+        ScanType::IndexScan {
+            index_name,
+            index_root_page,
+            keys,
+            collation,
+            descending,
+            covering,
+            ..
+        } => Operator::IndexSeek {
+            index_name: index_name.clone(),
+            index_root_page: *index_root_page,
+            table_root_page: plan.table_root_page,
+            keys: keys.clone(),
+            collation: collation.clone(),
+            descending: *descending,
+            covering: covering.clone(),
+            width: plan.columns.len(),
+            order: plan.order,
+            layout: plan.layout.clone(),
+        },
+        ScanType::Subquery {
+            plan: subquery_plan,
+            query: subquery,
+        } => Operator::Subquery {
+            table_name: plan.table_name.clone(),
+            plan: subquery_plan.clone(),
+            query: subquery.clone(),
+        },
+        ScanType::Compound { .. } => bail!(
+            "Unhandled operators for a compound SELECT: each of its SELECTs has operators of its \
+             own"
+        ),
+    };
+
+    let resolve = |name: &str| resolve_column(plan, name);
+    let collation = |column: ColumnRef| column_collation(database, plan, column);
+    let affinity = |column: ColumnRef| match column {
+        ColumnRef::Index(index) => plan.affinities[index],
+        ColumnRef::RowId => Affinity::Integer,
+    };
+    let conditions = where_conditions(database, plan, query)?;
+    let filter = query
+        .filter
+        .as_ref()
+        .map(|filter| with_comparison_affinities(plan, filter))
+        .transpose()?;
+    if !conditions.is_empty() || filter.is_some() {
+        operator = Operator::Filter {
+            input: Box::new(operator),
+            program: Program::compile_with_column_types(
+                &conditions,
+                filter.as_ref(),
+                &[],
+                &resolve,
+                &collation,
+                &affinity,
+                &database.functions,
+            )?,
+        };
+    }
+
+    // Aggregates are computed by Aggregate, so the program only has to produce any bare
+    // expressions
+    let mut result_columns: Vec<&Expression> = query
+        .selection_list
+        .iter()
+        .filter_map(|column| match &column.selection {
+            Selection::Expression(expression) => Some(expression),
+            Selection::AggregateFunction(_) => None,
+        })
+        .collect();
+    if plan.order == RowOrder::Sorted {
+        for term in &query.order_by {
+            result_columns.push(sort_key(query, &term.expression)?);
+        }
+    }
+    operator = Operator::Project {
+        input: Box::new(operator),
+        program: Program::compile_with_column_types(
+            &[],
+            None,
+            &result_columns,
+            &resolve,
+            &collation,
+            &affinity,
+            &database.functions,
+        )?,
+    };
+
+    if plan.order == RowOrder::Sorted {
+        // The program produces each row's sort keys after its result columns
+        operator = Operator::Sort {
+            input: Box::new(operator),
+            order_by: query.order_by.clone(),
+            collations: sort_collations(database, plan, query)?,
+            result_width: query.selection_list.len(),
+        };
+    }
+    let is_aggregate = query
+        .selection_list
+        .iter()
+        .any(|column| matches!(column.selection, Selection::AggregateFunction(_)));
+    if is_aggregate {
+        operator = Operator::Aggregate {
+            input: Box::new(operator),
+            selection_list: query.selection_list.clone(),
+        };
+    }
+    // After aggregating, so that rows are counted before duplicates are left out
+    if plan.distinct {
+        operator = Operator::Distinct {
+            input: Box::new(operator),
+            collations: result_collations(database, plan, query)?,
+        };
+    }
+    if let Some(limit) = query.limit {
+        operator = Operator::Limit {
+            input: Box::new(operator),
+            limit,
+        };
+    }
+
+    Ok(operator)
+}
+
+type Records<'a> = Box<dyn Iterator<Item = Result<Record>> + 'a>;
+type ResultRows<'a> = Box<dyn Iterator<Item = Result<Vec<Value>>> + 'a>;
+
+/// Starts an operator that finds or filters table records, and the operators below it
+fn open_records<'a>(
+    database: &'a Database,
+    operator: Operator,
+    counts: &Rc<RowCounts>,
+) -> Result<Records<'a>> {
+    let records: Records<'a> = match operator {
+        Operator::Filter { input, program } => {
+            let mut vm = Vm::new(program);
+            let records = open_records(database, *input, counts)?;
+            return Ok(Box::new(records.filter_map(move |record| {
+                record
+                    .and_then(|record| Ok(vm.run(&record)?.map(|_| record)))
+                    .transpose()
+            })));
+        }
+        Operator::Scan {
+            root_page,
+            descending: true,
+            layout,
+            ..
+        } => laid_out(database.table_cursor(root_page).descending(), layout),
+        Operator::Scan {
+            root_page, layout, ..
+        } => laid_out(database.table_cursor(root_page), layout),
+        Operator::RowidLookup {
+            root_page,
+            row_id,
+            layout,
+            ..
+        } => laid_out(
+            database.find_row(root_page, row_id).transpose().into_iter(),
+            layout,
+        ),
+        Operator::IndexSeek {
+            index_root_page,
+            table_root_page,
+            keys,
+            collation,
+            descending,
+            covering,
+            width,
+            order,
+            layout,
+            ..
+        } => {
+            let mut entries = vec![];
+            for key in &keys {
+                entries.extend(database.search_index_entries(
+                    index_root_page,
+                    key,
+                    collation.as_deref(),
+                    descending,
+                )?);
+            }
+            match order {
+                RowOrder::RowidAscending => entries.sort_unstable_by_key(|e| e.row_id),
+                RowOrder::RowidDescending => entries.sort_unstable_by_key(|e| Reverse(e.row_id)),
+                RowOrder::Any | RowOrder::Sorted => {}
+            }
+
+            match covering {
+                // The program only reads the columns the index holds, so the rest stay NULL
+                Some(positions) => Box::new(
+                    entries
+                        .into_iter()
+                        .map(move |entry| Ok(table_record(entry, &positions, width))),
+                ),
+                None => laid_out(
+                    entries.into_iter().filter_map(move |entry| {
+                        database.find_row(table_root_page, entry.row_id).transpose()
+                    }),
+                    layout,
+                ),
+            }
+        }
+        Operator::Subquery { plan, query, .. } => Box::new(
+            execute(database, &plan, &query)?
+                .zip(1..)
+                .map(|(row, row_id)| Ok(Record::from_values(row_id, &row?))),
+        ),
+        operator => bail!(
+            "Unhandled {} where table records are needed",
+            operator.label()
+        ),
+    };
+
+    // Checked as each row is read, so that a long scan or sort stops soon after an interrupt
+    let mut progress = database.statement_progress();
+    Ok(Box::new(
+        records
+            .map(move |record| {
+                progress.row_read()?;
+                record
+            })
+            .inspect(count_into(counts, |c| &c.scanned)),
+    ))
+}
+
+/// Starts an operator that produces result rows, and the operators below it
+fn open_rows<'a>(
+    database: &'a Database,
+    operator: Operator,
+    counts: &Rc<RowCounts>,
+) -> Result<ResultRows<'a>> {
+    let rows: ResultRows<'a> = match operator {
+        Operator::Project { input, program } => {
+            let mut vm = Vm::new(program);
+            Box::new(
+                open_records(database, *input, counts)?
+                    .filter_map(move |record| record.and_then(|record| vm.run(&record)).transpose())
+                    .inspect(count_into(counts, |c| &c.filtered)),
+            )
+        }
+        Operator::Sort {
+            input,
+            order_by,
+            collations,
+            result_width,
+        } => {
+            let mut sorter = Sorter::new(
+                move |a: &[Value], b: &[Value]| {
+                    compare_sort_keys(
+                        &order_by,
+                        &collations,
+                        &a[result_width..],
+                        &b[result_width..],
+                    )
+                },
+                database.options.max_memory,
+            );
+            for row in open_rows(database, *input, counts)? {
+                sorter.push(row?)?;
+            }
+
+            Box::new(
+                sorter
+                    .finish()?
+                    .map(move |row| {
+                        let mut row = row?;
+                        row.truncate(result_width);
+                        Ok(row)
+                    })
+                    .inspect(count_into(counts, |c| &c.sorted)),
+            )
+        }
+        Operator::Distinct { input, collations } => Box::new(
+            remove_duplicates(open_rows(database, *input, counts)?, collations.into())
+                .inspect(count_into(counts, |c| &c.distinct)),
+        ),
+        Operator::Aggregate {
+            input,
+            selection_list,
+        } => {
+            let mut count = 0;
+            let mut last_row = None;
+            for row in open_rows(database, *input, counts)? {
+                count += 1;
+                last_row = Some(row?);
+            }
+
+            // Bare expressions next to an aggregate are evaluated on the last row. That's what
+            // SQLite does next to count(), though next to min() or max() it uses the row holding
+            // the minimum or maximum, which would need those aggregates first.
+            let mut bare_values = last_row.unwrap_or_default().into_iter();
+            let row = selection_list
+                .iter()
+                .map(|column| match column.selection {
+                    Selection::AggregateFunction(Function::Count(_)) => Value::Integer(count),
+                    Selection::Expression(_) => bare_values.next().unwrap_or(Value::Null),
+                })
+                .collect();
+            Box::new(std::iter::once(Ok(row)))
+        }
+        Operator::Limit { input, limit } => {
+            Box::new(open_rows(database, *input, counts)?.take(limit))
+        }
+        operator => bail!(
+            "Unhandled {} where result rows are needed",
+            operator.label()
+        ),
+    };
+
+    Ok(rows)
+}
+
+/// Runs each SELECT of a compound SELECT in turn, combining its rows with those of the SELECTs
+/// before it, then sorts and limits the result. Rows are duplicates as they are for DISTINCT.
+fn execute_compound<'a>(
+    database: &'a Database,
+    plan: &QueryPlan,
+    members: &[CompoundMember],
+    query: &Query,
+    counts: Rc<RowCounts>,
+) -> Result<Rows<'a>> {
+    let lock = database.read_lock()?;
+
+    // Rows are compared by the collation the plan gives each column, as SQLite does
+    let collations: Rc<[Option<Arc<Collation>>]> = plan
+        .collations
+        .iter()
+        .map(|name| {
+            name.as_deref()
+                .map(|name| database.collations.find(name))
+                .transpose()
+        })
+        .collect::<Result<_>>()?;
+    let mut result_rows: Box<dyn Iterator<Item = Result<Vec<Value>>> + 'a> =
+        Box::new(std::iter::empty());
+    for member in members {
+        let member_rows = execute(database, &member.plan, &member.query)?;
+        result_rows = match member.operator {
+            None | Some(CompoundOperator::UnionAll) => Box::new(result_rows.chain(member_rows)),
+            Some(CompoundOperator::Union) => Box::new(remove_duplicates(
+                result_rows.chain(member_rows),
+                collations.clone(),
+            )),
+            // The right-hand rows are all needed before any row on the left can be decided on
+            Some(operator) => {
+                let right = member_rows
+                    .map(|row| Ok(DistinctRow::new(row?, collations.clone())))
+                    .collect::<Result<BTreeSet<_>>>()?;
+                let keep = operator == CompoundOperator::Intersect;
+                let left = remove_duplicates(result_rows, collations.clone());
+                let collations = collations.clone();
+                let probe = move |row: &[Value]| DistinctRow::new(row.to_vec(), collations.clone());
+                Box::new(left.filter(move |row| match row {
+                    Ok(row) => right.contains(&probe(row)) == keep,
+                    Err(_) => true,
+                }))
+            }
+        };
+    }
+    let result_rows = result_rows.inspect(count_into(&counts, |c| &c.scanned));
+
+    let result_rows: Box<dyn Iterator<Item = Result<Vec<Value>>> + 'a> =
+        if plan.order == RowOrder::Sorted {
+            // Each row is sorted with its sort keys after it, as for a single SELECT
+            let result_width = plan.columns.len();
+            let sort_columns = query
+                .order_by
+                .iter()
+                .enumerate()
+                .map(|(i, term)| compound_sort_column(query, &plan.columns, &term.expression, i))
+                .collect::<Result<Vec<_>>>()?;
+            let collations = query
+                .order_by
+                .iter()
+                .zip(&sort_columns)
+                .map(|(term, &column)| match &term.collation {
+                    Some(name) => Ok(Some(database.collations.find(name)?)),
+                    None => column_collation(database, plan, ColumnRef::Index(column)),
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let order_by = query.order_by.clone();
+            let mut sorter = Sorter::new(
+                move |a: &[Value], b: &[Value]| {
+                    compare_sort_keys(
+                        &order_by,
+                        &collations,
+                        &a[result_width..],
+                        &b[result_width..],
+                    )
+                },
+                database.options.max_memory,
+            );
+            for row in result_rows {
+                let mut row = row?;
+                let keys: Vec<Value> = sort_columns.iter().map(|&i| row[i].clone()).collect();
+                row.extend(keys);
+                sorter.push(row)?;
+            }
+
+            Box::new(
+                sorter
+                    .finish()?
+                    .map(move |row| {
+                        let mut row = row?;
+                        row.truncate(result_width);
+                        Ok(row)
+                    })
+                    .inspect(count_into(&counts, |c| &c.sorted)),
+            )
+        } else {
+            Box::new(result_rows)
+        };
+
+    Ok(Rows {
+        rows: Box::new(
+            result_rows
+                .take(query.limit.unwrap_or(usize::MAX))
+                .inspect(count_into(&counts, |c| &c.output)),
+        ),
+        columns: plan.columns.clone(),
+        _lock: lock,
+    })
+}
+
+/// The result column of a compound SELECT that its `position`th ORDER BY term sorts by: the one
+/// it gives the number or heading of, or else the left-most SELECT's column that it's the
+/// expression of
+fn compound_sort_column(
+    query: &Query,
+    columns: &[String],
+    term: &Expression,
+    position: usize,
+) -> Result<usize> {
+    if let Expression::Literal(Literal::Integer(number)) = term {
+        let column_count = columns.len();
+        if *number < 1 || *number as usize > column_count {
+            bail!("ORDER BY term out of range - should be between 1 and {column_count}");
+        }
+        return Ok(*number as usize - 1);
+    }
+
+    let heading = match term {
+        Expression::Column(name) => columns.iter().position(|c| c.eq_ignore_ascii_case(name)),
+        _ => None,
+    };
+    heading
+        .or_else(|| {
+            query.selection_list.iter().position(
+                |column| matches!(&column.selection, Selection::Expression(e) if e == term),
+            )
+        })
+        .ok_or_else(|| {
+            anyhow!(
+                "{} ORDER BY term does not match any column in the result set",
+                ordinal(position + 1)
+            )
+        })
+}
+
+/// A number as an English ordinal, like 1st or 12th
+fn ordinal(number: usize) -> String {
+    let suffix = match (number % 10, number % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    };
+
+    format!("{number}{suffix}")
+}
+
+/// Leaves out rows that duplicate one that came before, comparing each column's values by its
+/// collation, and keeping errors
+fn remove_duplicates<'a>(
+    rows: impl Iterator<Item = Result<Vec<Value>>> + 'a,
+    collations: Rc<[Option<Arc<Collation>>]>,
+) -> impl Iterator<Item = Result<Vec<Value>>> + 'a {
+    let mut seen = BTreeSet::new();
+    rows.filter(move |row| match row {
+        Ok(row) => seen.insert(DistinctRow::new(row.clone(), collations.clone())),
+        Err(_) => true,
+    })
+}
+
+/// A row as DISTINCT and compound SELECTs tell duplicates apart: by comparing each of its values
+/// with the collation of its column, or as BINARY past the last collation given. Comparing an
+/// integer with a real compares their values, so 1 and 1.0 are duplicates, as in SQLite.
+struct DistinctRow {
+    row: Vec<Value>,
+    collations: Rc<[Option<Arc<Collation>>]>,
+}
+
+impl DistinctRow {
+    fn new(row: Vec<Value>, collations: Rc<[Option<Arc<Collation>>]>) -> Self {
+        DistinctRow { row, collations }
+    }
+}
+
+impl Ord for DistinctRow {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.row
+            .iter()
+            .zip(&other.row)
+            .enumerate()
+            .map(
+                |(i, (a, b))| match self.collations.get(i).and_then(Option::as_ref) {
+                    Some(collation) => collation.compare_values(a, b),
+                    None => a.compare(b),
+                },
+            )
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or_else(|| self.row.len().cmp(&other.row.len()))
+    }
+}
+
+impl PartialOrd for DistinctRow {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for DistinctRow {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for DistinctRow {}
+
+/// Lays table records out as rows of the table with `layout`, if they need it
+fn laid_out<'a>(
+    records: impl Iterator<Item = Result<Record>> + 'a,
+    layout: Option<RowLayout>,
+) -> Records<'a> {
+    match layout {
+        Some(layout) => {
+            let mut lay_out = layout.into_mapper();
+            Box::new(records.map(move |record| record.and_then(&mut lay_out)))
+        }
+        None => Box::new(records),
+    }
+}
+
+/// Lays an index entry out as a row of the table with `width` columns, putting the value of each
+/// of the index's columns at the table position given for it in `positions`
+fn table_record(entry: Record, positions: &[usize], width: usize) -> Record {
+    let mut serial_types = vec![SerialType::Null; width];
+    let mut serial_values = vec![SerialValue::Null; width];
+    let columns = entry.serial_types.into_iter().zip(entry.serial_values);
+    for (&position, (serial_type, serial_value)) in positions.iter().zip(columns) {
+        serial_types[position] = serial_type;
+        serial_values[position] = serial_value;
+    }
+
+    Record {
+        row_id: entry.row_id,
+        serial_types,
+        serial_values,
+    }
+}
+
+/// Returns a closure for Iterator::inspect that adds one to a counter of `counts` per item
+fn count_into<T>(counts: &Rc<RowCounts>, counter: fn(&RowCounts) -> &Cell<u64>) -> impl FnMut(&T) {
+    let counts = counts.clone();
+    move |_| {
+        let counter = counter(&counts);
+        counter.set(counter.get() + 1);
+    }
+}
+
+/// Runs an IN condition's subquery, for the values it lists
+fn subquery_values(database: &Database, subquery: &Query) -> Result<Vec<Expression>> {
+    let columns = subquery.selection_list.len();
+    if columns != 1 {
+        bail!("sub-select returns {columns} columns - expected 1");
+    }
+
+    let plan = plan_query(database, &database.schema()?, subquery)?;
+    execute(database, &plan, subquery)?
+        .map(|row| {
+            let value = row?.into_iter().next().unwrap_or(Value::Null);
+            Ok(Expression::Literal(Literal::from(&value)))
+        })
+        .collect()
+}
+
+/// The WHERE conditions, with the columns they compare resolved, and the values of any IN
+/// subqueries found
+fn where_conditions(
+    database: &Database,
+    plan: &QueryPlan,
+    query: &Query,
+) -> Result<Vec<Condition>> {
+    query
+        .and_conditions
+        .as_deref()
+        .unwrap_or_default()
+        .iter()
+        .map(|condition| {
+            let column = resolve_column(plan, &condition.column_name)?;
+            let collation = match &condition.collation {
+                Some(name) => Some(database.collations.find(name)?),
+                None => column_collation(database, plan, column)?,
+            };
+
+            let affinity = match column {
+                ColumnRef::Index(index) => plan.affinities[index],
+                ColumnRef::RowId => Affinity::Integer,
+            };
+
+            let values = match &condition.subquery {
+                Some(subquery) => subquery_values(database, subquery)?,
+                None => condition.values.clone(),
+            };
+
+            Ok(Condition {
+                column,
+                operator: condition.operator,
+                values,
+                affinity,
+                collation,
+            })
+        })
+        .collect()
+}
+
+/// The filter with each literal that's compared with a column converted to suit the column's
+/// affinity, as SQLite does before comparing them, so that `id > '5'` compares numbers
+fn with_comparison_affinities(plan: &QueryPlan, expression: &Expression) -> Result<Expression> {
+    let affinity = |name: &str| -> Result<Affinity> {
+        Ok(match resolve_column(plan, name)? {
+            ColumnRef::Index(index) => plan.affinities[index],
+            ColumnRef::RowId => Affinity::Integer,
+        })
+    };
+    let convert = |literal: &Literal, affinity: Affinity| {
+        Expression::Literal(Literal::from(&affinity.apply(Value::from(literal))))
+    };
+
+    Ok(match expression {
+        Expression::Binary { operator, lhs, rhs } if operator.is_comparison() => {
+            match (lhs.as_ref(), rhs.as_ref()) {
+                (Expression::Column(name), Expression::Literal(literal)) => Expression::Binary {
+                    operator: *operator,
+                    lhs: lhs.clone(),
+                    rhs: Box::new(convert(literal, affinity(name)?)),
+                },
+                (Expression::Literal(literal), Expression::Column(name)) => Expression::Binary {
+                    operator: *operator,
+                    lhs: Box::new(convert(literal, affinity(name)?)),
+                    rhs: rhs.clone(),
+                },
+                _ => expression.clone(),
+            }
+        }
+        Expression::Binary { operator, lhs, rhs } => Expression::Binary {
+            operator: *operator,
+            lhs: Box::new(with_comparison_affinities(plan, lhs)?),
+            rhs: Box::new(with_comparison_affinities(plan, rhs)?),
+        },
+        Expression::Not(operand) => {
+            Expression::Not(Box::new(with_comparison_affinities(plan, operand)?))
+        }
+        expression => expression.clone(),
+    })
+}
+
+/// The collation of each result column: the one its column declares, if it's a column, or else
+/// None for BINARY
+fn result_collations(
+    database: &Database,
+    plan: &QueryPlan,
+    query: &Query,
+) -> Result<Vec<Option<Arc<Collation>>>> {
+    query
+        .selection_list
+        .iter()
+        .map(|column| match &column.selection {
+            Selection::Expression(Expression::Column(name)) => {
+                column_collation(database, plan, resolve_column(plan, name)?)
+            }
+            _ => Ok(None),
+        })
+        .collect()
+}
+
+/// The collation a column declares, or None if it uses BINARY
+fn column_collation(
+    database: &Database,
+    plan: &QueryPlan,
+    column: ColumnRef,
+) -> Result<Option<Arc<Collation>>> {
+    let name = match column {
+        ColumnRef::Index(index) => plan.collations.get(index).cloned().flatten(),
+        ColumnRef::RowId => None,
+    };
+
+    name.map(|name| database.collations.find(&name)).transpose()
+}
+
+/// The collation each ORDER BY term sorts text by: the one its COLLATE clause names, or else the
+/// one declared by the column it sorts by, if it's a column
+fn sort_collations(
+    database: &Database,
+    plan: &QueryPlan,
+    query: &Query,
+) -> Result<Vec<Option<Arc<Collation>>>> {
+    query
+        .order_by
+        .iter()
+        .map(
+            |term| match (&term.collation, sort_key(query, &term.expression)?) {
+                (Some(name), _) => Ok(Some(database.collations.find(name)?)),
+                (None, Expression::Column(name)) => {
+                    column_collation(database, plan, resolve_column(plan, name)?)
+                }
+                (None, _) => Ok(None),
+            },
+        )
+        .collect()
+}
+
+/// The expression an ORDER BY term sorts by: a result column, if the term is its alias or its
+/// number, or else the term itself
+fn sort_key<'q>(query: &'q Query, term: &'q Expression) -> Result<&'q Expression> {
+    let result_column = match term {
+        Expression::Literal(Literal::Integer(number)) => {
+            let column_count = query.selection_list.len();
+            if *number < 1 || *number as usize > column_count {
+                bail!("ORDER BY term out of range - should be between 1 and {column_count}");
+            }
+            Some(&query.selection_list[*number as usize - 1])
+        }
+        Expression::Column(name) => query
+            .selection_list
+            .iter()
+            .find(|column| column.alias.as_deref() == Some(name)),
+        _ => None,
+    };
+
+    match result_column.map(|column| &column.selection) {
+        Some(Selection::Expression(expression)) => Ok(expression),
+        Some(Selection::AggregateFunction(_)) => bail!("can't ORDER BY an aggregate"),
+        None => Ok(term),
+    }
+}
+
+/// Compares two rows' sort keys, term by term, for ORDER BY
+fn compare_sort_keys(
+    order_by: &[OrderingTerm],
+    collations: &[Option<Arc<Collation>>],
+    a: &[Value],
+    b: &[Value],
+) -> Ordering {
+    order_by
+        .iter()
+        .zip(collations)
+        .zip(a.iter().zip(b))
+        .map(|((term, collation), (a, b))| {
+            let ordering = match collation {
+                Some(collation) => collation.compare_values(a, b),
+                None => a.compare(b),
+            };
+            if term.descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        })
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+fn resolve_column(plan: &QueryPlan, name: &str) -> Result<ColumnRef> {
+    let binder = Binder::new(vec![Source {
+        name: &plan.table_name,
+        columns: &plan.columns,
+        rowid_alias: plan.rowid_alias,
+    }]);
+
+    Ok(binder.resolve(name)?.column)
+}
@@ -0,0 +1,382 @@
+use crate::{
+    btree_writer::write_page,
+    database::{Database, MAX_BTREE_DEPTH},
+    header::{BTreePage, PageHeader, DATABASE_HEADER_SIZE},
+    lock::lock_byte_page,
+    overflow,
+    record::encode_record,
+    value::Value,
+    varint,
+};
+use anyhow::{bail, Result};
+use std::{
+    collections::HashMap,
+    io::{Cursor, Write},
+};
+#[cfg(feature = "fs")]
+use std::{fs::File, io::BufWriter, path::Path};
+
+/// A page copied into an export, with the offsets of the page numbers in it that have to be
+/// renumbered: child pointers, overflow pointers, and the next page of an overflow chain
+struct ExportPage {
+    page_num: u32,
+    pointer_offsets: Vec<usize>,
+}
+
+impl Database {
+    /// Copies one table's b-tree, and those of its indexes, into a new database at `path` that
+    /// holds nothing else. Pages are copied as they are rather than rows being read and written
+    /// again, with page numbers changed to match where the pages end up. Returns the new
+    /// database's page count.
+    #[cfg(feature = "fs")]
+    pub fn export_subtree(&self, table_name: &str, path: impl AsRef<Path>) -> Result<u32> {
+        let mut output = BufWriter::new(File::create(path)?);
+        let page_count = self.write_subtree(table_name, &mut output)?;
+        output.flush()?;
+
+        Ok(page_count)
+    }
+
+    /// Like export_subtree, writing the new database to `output`
+    pub fn write_subtree(&self, table_name: &str, output: &mut impl Write) -> Result<u32> {
+        let schema = self.schema()?;
+        let Some(table) = schema
+            .iter()
+            .find(|s| s.is_table() && s.name.eq_ignore_ascii_case(table_name))
+        else {
+            bail!("no such table: {table_name}");
+        };
+        let objects = schema
+            .iter()
+            .filter(|s| s.name == table.name || (s.is_index() && s.table_name == table.name));
+
+        // Page 1 is the new sqlite_schema table, and the b-trees' pages follow it in the order
+        // they're walked
+        let mut pages = vec![];
+        let mut new_page_nums = HashMap::new();
+        let mut schema_rows = vec![];
+        for object in objects {
+            schema_rows.push((object, export_page_num(pages.len(), self.page_size)));
+            self.collect_export_pages(object.root_page, 1, &mut pages, &mut new_page_nums)?;
+        }
+        let page_count = match pages.len() {
+            0 => 1,
+            len => export_page_num(len - 1, self.page_size),
+        };
+
+        let mut first_page = self.page_bytes(1)?.into_owned();
+        let schema_format = u32::from_be_bytes(first_page[44..48].try_into()?);
+        let change_counter: [u8; 4] = first_page[24..28].try_into()?;
+        first_page[28..32].copy_from_slice(&page_count.to_be_bytes());
+        // There's no freelist, and with no pointer map pages it isn't an auto-vacuum database
+        first_page[32..40].fill(0);
+        first_page[52..56].fill(0);
+        first_page[64..68].fill(0);
+        first_page[92..96].copy_from_slice(&change_counter);
+
+        let cells = schema_rows
+            .iter()
+            .zip(1..)
+            .map(|((object, root_page), row_id)| {
+                let record = encode_record(
+                    &[
+                        Value::Text(object.kind.clone()),
+                        Value::Text(object.name.clone()),
+                        Value::Text(object.table_name.clone()),
+                        Value::Integer(*root_page as i64),
+                        object.sql.clone().map_or(Value::Null, Value::Text),
+                    ],
+                    schema_format,
+                );
+                let split = overflow::thresholds(
+                    self.page_size,
+                    record.len() as u64,
+                    BTreePage::LeafTable,
+                )?;
+                if split.overflow > 0 {
+                    bail!("the schema of {} is too big to export", object.name);
+                }
+
+                let mut cell = varint::encode_varint(record.len() as i64);
+                cell.extend(varint::encode_varint(row_id));
+                cell.extend(record);
+                Ok(cell)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        first_page[DATABASE_HEADER_SIZE..].fill(0);
+        write_page(
+            &mut first_page,
+            DATABASE_HEADER_SIZE,
+            BTreePage::LeafTable,
+            &cells,
+            None,
+        )?;
+        output.write_all(&first_page)?;
+
+        let lock_byte_page = lock_byte_page(self.page_size);
+        for (i, page) in pages.iter().enumerate() {
+            if export_page_num(i, self.page_size) == lock_byte_page + 1 {
+                output.write_all(&vec![0; self.page_size as usize])?;
+            }
+            let mut bytes = self.page_bytes(page.page_num)?.into_owned();
+            for &offset in &page.pointer_offsets {
+                let old = u32::from_be_bytes(bytes[offset..offset + 4].try_into()?);
+                bytes[offset..offset + 4].copy_from_slice(&new_page_nums[&old].to_be_bytes());
+            }
+            output.write_all(&bytes)?;
+        }
+
+        Ok(page_count)
+    }
+
+    /// Adds the b-tree page `page_num`, the pages below it and their overflow pages to `pages`,
+    /// numbering each from 2 in the order they're added
+    fn collect_export_pages(
+        &self,
+        page_num: u32,
+        depth: usize,
+        pages: &mut Vec<ExportPage>,
+        new_page_nums: &mut HashMap<u32, u32>,
+    ) -> Result<()> {
+        if depth > MAX_BTREE_DEPTH {
+            bail!("b-tree page {page_num} is too deep");
+        }
+        let mut add_page = |page_num: u32, pointer_offsets| {
+            if page_num == 1 || new_page_nums.contains_key(&page_num) {
+                bail!("malformed database: page {page_num} is used more than once");
+            }
+            new_page_nums.insert(page_num, export_page_num(pages.len(), self.page_size));
+            pages.push(ExportPage {
+                page_num,
+                pointer_offsets,
+            });
+            Ok(())
+        };
+
+        let bytes = self.page_bytes(page_num)?;
+        let (children, overflow_pages, pointer_offsets) = self.page_pointers(&bytes)?;
+        add_page(page_num, pointer_offsets)?;
+
+        for mut overflow_page in overflow_pages {
+            // Each overflow page starts with the number of the next, or 0 on the last one
+            while overflow_page != 0 {
+                let next = u32::from_be_bytes(self.page_bytes(overflow_page)?[..4].try_into()?);
+                add_page(overflow_page, if next == 0 { vec![] } else { vec![0] })?;
+                overflow_page = next;
+            }
+        }
+
+        for child in children {
+            self.collect_export_pages(child, depth + 1, pages, new_page_nums)?;
+        }
+
+        Ok(())
+    }
+
+    /// The child pages and first overflow pages a b-tree page points to, and the offsets of all
+    /// of those pointers in the page
+    fn page_pointers(&self, page: &[u8]) -> Result<(Vec<u32>, Vec<u32>, Vec<usize>)> {
+        let header = PageHeader::parse(page)?;
+        let page_type = header.page_type;
+        let pointers_start = page_type.header_size();
+        let pointers_end = pointers_start + 2 * header.number_of_cells as usize;
+        if pointers_end > page.len() {
+            bail!(
+                "malformed page: {} cells don't fit on the page",
+                header.number_of_cells
+            );
+        }
+
+        let read_pointer = |offset: usize| -> Result<u32> {
+            match page.get(offset..offset + 4) {
+                Some(pointer) => Ok(u32::from_be_bytes(pointer.try_into()?)),
+                None => bail!("malformed page: a pointer runs past the end of the page"),
+            }
+        };
+
+        let mut children = vec![];
+        let mut overflow_pages = vec![];
+        let mut pointer_offsets = vec![];
+        for pointer in page[pointers_start..pointers_end].chunks(2) {
+            let cell_start = u16::from_be_bytes([pointer[0], pointer[1]]) as usize;
+            if cell_start < pointers_end || cell_start >= page.len() {
+                bail!("malformed page: cell pointer {cell_start} is outside the cell content area");
+            }
+
+            let mut position = cell_start;
+            if page_type.is_interior() {
+                children.push(read_pointer(position)?);
+                pointer_offsets.push(position);
+                position += 4;
+            }
+            if page_type == BTreePage::InteriorTable {
+                continue;
+            }
+
+            let mut cell = Cursor::new(&page[position..]);
+            let (payload_size, _) = varint::parse_varint_from_reader(&mut cell)?;
+            if page_type == BTreePage::LeafTable {
+                varint::parse_varint_from_reader(&mut cell)?;
+            }
+            let Ok(payload_size) = u64::try_from(payload_size) else {
+                bail!("malformed cell: payload size {payload_size}");
+            };
+            let split = overflow::thresholds(self.page_size, payload_size, header.page_type)?;
+            if split.overflow > 0 {
+                let offset = position + cell.position() as usize + split.local;
+                overflow_pages.push(read_pointer(offset)?);
+                pointer_offsets.push(offset);
+            }
+        }
+
+        if let Some(right_most_pointer) = header.right_most_pointer {
+            children.push(right_most_pointer);
+            pointer_offsets.push(8);
+        }
+
+        Ok((children, overflow_pages, pointer_offsets))
+    }
+}
+
+/// The page number in an export of the `index`th page copied into it. They follow page 1 in the
+/// order they're copied, skipping the lock-byte page.
+fn export_page_num(index: usize, page_size: u32) -> u32 {
+    let page_num = index as u32 + 2;
+    if page_num >= lock_byte_page(page_size) {
+        page_num + 1
+    } else {
+        page_num
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE_SIZE: usize = 512;
+
+    fn table_cell(row_id: i64, values: &[Value]) -> Vec<u8> {
+        let record = encode_record(values, 4);
+        let mut cell = varint::encode_varint(record.len() as i64);
+        cell.extend(varint::encode_varint(row_id));
+        cell.extend(record);
+        cell
+    }
+
+    fn index_cell(values: &[Value]) -> Vec<u8> {
+        let record = encode_record(values, 4);
+        let mut cell = varint::encode_varint(record.len() as i64);
+        cell.extend(record);
+        cell
+    }
+
+    /// A database with tables a(x) on page 2 and b(x) on page 3, and an index on b(x) on page 4
+    fn database_with_tables() -> Vec<u8> {
+        let mut bytes = vec![0; 4 * PAGE_SIZE];
+        bytes[0..16].copy_from_slice(b"SQLite format 3\0");
+        bytes[16..18].copy_from_slice(&(PAGE_SIZE as u16).to_be_bytes());
+        bytes[18..24].copy_from_slice(&[1, 1, 0, 64, 32, 32]);
+        bytes[28..32].copy_from_slice(&4u32.to_be_bytes());
+        bytes[44..48].copy_from_slice(&4u32.to_be_bytes());
+        bytes[56..60].copy_from_slice(&1u32.to_be_bytes());
+
+        let text = |s: &str| Value::Text(s.to_string());
+        let schema_row = |row_id, kind, name, table, root_page, sql| {
+            table_cell(
+                row_id,
+                &[
+                    text(kind),
+                    text(name),
+                    text(table),
+                    Value::Integer(root_page),
+                    text(sql),
+                ],
+            )
+        };
+        let pages: Vec<&mut [u8]> = bytes.chunks_mut(PAGE_SIZE).collect();
+        let [schema_page, a_page, b_page, index_page] = <[_; 4]>::try_from(pages).unwrap();
+
+        write_page(
+            schema_page,
+            DATABASE_HEADER_SIZE,
+            BTreePage::LeafTable,
+            &[
+                schema_row(1, "table", "a", "a", 2, "CREATE TABLE a(x)"),
+                schema_row(2, "table", "b", "b", 3, "CREATE TABLE b(x)"),
+                schema_row(3, "index", "b_x", "b", 4, "CREATE INDEX b_x ON b(x)"),
+            ],
+            None,
+        )
+        .unwrap();
+        write_page(
+            a_page,
+            0,
+            BTreePage::LeafTable,
+            &[table_cell(1, &[text("apple")])],
+            None,
+        )
+        .unwrap();
+        write_page(
+            b_page,
+            0,
+            BTreePage::LeafTable,
+            &[
+                table_cell(1, &[text("banana")]),
+                table_cell(2, &[text("cherry")]),
+            ],
+            None,
+        )
+        .unwrap();
+        write_page(
+            index_page,
+            0,
+            BTreePage::LeafIndex,
+            &[
+                index_cell(&[text("banana"), Value::Integer(1)]),
+                index_cell(&[text("cherry"), Value::Integer(2)]),
+            ],
+            None,
+        )
+        .unwrap();
+
+        bytes
+    }
+
+    #[test]
+    fn test_write_subtree() {
+        let database = Database::open_bytes(database_with_tables()).unwrap();
+        assert!(database.write_subtree("c", &mut vec![]).is_err());
+
+        let mut bytes = vec![];
+        assert_eq!(database.write_subtree("B", &mut bytes).unwrap(), 3);
+        assert_eq!(bytes.len(), 3 * PAGE_SIZE);
+
+        let exported = Database::open_bytes(bytes).unwrap();
+        let schema = exported.schema().unwrap();
+        let names_and_roots: Vec<_> = schema
+            .iter()
+            .map(|s| (s.name.as_str(), s.root_page))
+            .collect();
+        assert_eq!(names_and_roots, [("b", 2), ("b_x", 3)]);
+
+        let rows: Vec<_> = exported
+            .read_table(2)
+            .unwrap()
+            .iter()
+            .map(|record| record.value(0))
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                Value::Text("banana".to_string()),
+                Value::Text("cherry".to_string())
+            ]
+        );
+        assert_eq!(
+            exported
+                .search_index(3, &Value::Text("cherry".to_string()))
+                .unwrap(),
+            [2]
+        );
+    }
+}
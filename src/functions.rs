@@ -0,0 +1,604 @@
+use crate::{datetime, printf, query_parser::Literal, value::Value};
+use anyhow::{bail, Result};
+use std::{fmt, sync::Arc};
+
+/// The implementation of a scalar function, given its evaluated arguments
+pub type ScalarFn = dyn Fn(&[Value]) -> Result<Value> + Send + Sync;
+
+/// A scalar SQL function, called once per row with its evaluated arguments
+pub struct ScalarFunction {
+    pub name: String,
+    pub min_args: usize,
+    pub max_args: usize,
+    pub call: Box<ScalarFn>,
+}
+
+impl fmt::Debug for ScalarFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}()", self.name)
+    }
+}
+
+impl PartialEq for ScalarFunction {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+            && self.min_args == other.min_args
+            && self.max_args == other.max_args
+    }
+}
+
+/// The scalar functions SQL can call: the built-in ones, plus any registered by library users.
+///
+/// A function registered later takes precedence over an earlier one of the same name that accepts
+/// the same number of arguments, so built-in functions can be overridden.
+#[derive(Clone)]
+pub struct FunctionRegistry {
+    functions: Vec<Arc<ScalarFunction>>,
+}
+
+impl Default for FunctionRegistry {
+    fn default() -> Self {
+        let mut registry = FunctionRegistry { functions: vec![] };
+
+        registry.register("abs", 1, 1, abs);
+        registry.register("date", 0, usize::MAX, datetime::date);
+        registry.register("datetime", 0, usize::MAX, datetime::datetime);
+        registry.register("format", 1, usize::MAX, printf_fn);
+        registry.register("hex", 1, 1, |args| Ok(Value::Text(hex(&args[0]))));
+        registry.register("julianday", 0, usize::MAX, datetime::julianday);
+        registry.register("length", 1, 1, length);
+        registry.register("levenshtein", 2, 2, |args| match args {
+            [Value::Null, _] | [_, Value::Null] => Ok(Value::Null),
+            [a, b] => Ok(Value::Integer(
+                levenshtein(&a.to_string(), &b.to_string()) as i64
+            )),
+            _ => unreachable!(),
+        });
+        // like(X, Y) is Y LIKE X, with an ESCAPE character as the third argument
+        registry.register("like", 2, 3, |args| {
+            if args.contains(&Value::Null) {
+                return Ok(Value::Null);
+            }
+            let escape = match args.get(2).map(|escape| escape.to_string()) {
+                Some(escape) if escape.chars().count() != 1 => {
+                    bail!("ESCAPE expression must be a single character")
+                }
+                escape => escape.and_then(|escape| escape.chars().next()),
+            };
+            let matches = like(&args[0].to_string(), &args[1].to_string(), escape);
+            Ok(Value::Integer(matches as i64))
+        });
+        registry.register("lower", 1, 1, |args| Ok(map_text(&args[0], to_lowercase)));
+        registry.register("printf", 1, usize::MAX, printf_fn);
+        registry.register("quote", 1, 1, |args| Ok(Value::Text(quote(&args[0]))));
+        registry.register("soundex", 1, 1, |args| {
+            Ok(Value::Text(soundex(&args[0].to_string())))
+        });
+        registry.register("strftime", 1, usize::MAX, datetime::strftime);
+        registry.register("substr", 2, 3, substr);
+        registry.register("time", 0, usize::MAX, datetime::time);
+        registry.register("typeof", 1, 1, |args| {
+            Ok(Value::Text(args[0].type_name().to_string()))
+        });
+        registry.register("unhex", 1, 2, unhex);
+        registry.register("unixepoch", 0, usize::MAX, datetime::unixepoch);
+        registry.register("upper", 1, 1, |args| Ok(map_text(&args[0], to_uppercase)));
+
+        registry
+    }
+}
+
+impl FunctionRegistry {
+    /// A registry holding just the built-in functions
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a function that takes between `min_args` and `max_args` arguments
+    pub fn register<F>(&mut self, name: &str, min_args: usize, max_args: usize, call: F)
+    where
+        F: Fn(&[Value]) -> Result<Value> + Send + Sync + 'static,
+    {
+        self.functions.push(Arc::new(ScalarFunction {
+            name: name.to_lowercase(),
+            min_args,
+            max_args,
+            call: Box::new(call),
+        }));
+    }
+
+    /// Looks up a function by name (case-insensitively) that accepts `arg_count` arguments
+    pub fn find(&self, name: &str, arg_count: usize) -> Result<Arc<ScalarFunction>> {
+        let mut candidates = self
+            .functions
+            .iter()
+            .rev()
+            .filter(|f| f.name.eq_ignore_ascii_case(name))
+            .peekable();
+
+        if candidates.peek().is_none() {
+            bail!("no such function: {name}");
+        }
+
+        match candidates.find(|f| (f.min_args..=f.max_args).contains(&arg_count)) {
+            Some(function) => Ok(Arc::clone(function)),
+            None => bail!("wrong number of arguments to function {name}()"),
+        }
+    }
+}
+
+/// Applies a text transformation to a value, leaving NULL as it is. Numbers and blobs are
+/// transformed as text, like SQLite does.
+fn map_text(value: &Value, transform: impl Fn(&str) -> String) -> Value {
+    match value {
+        Value::Null => Value::Null,
+        value => Value::Text(transform(&value.to_string())),
+    }
+}
+
+/// Like SQLite without ICU, only ASCII letters change case, unless the "unicode" feature is on
+#[cfg(not(feature = "unicode"))]
+fn to_uppercase(text: &str) -> String {
+    text.to_ascii_uppercase()
+}
+
+#[cfg(not(feature = "unicode"))]
+fn to_lowercase(text: &str) -> String {
+    text.to_ascii_lowercase()
+}
+
+#[cfg(feature = "unicode")]
+fn to_uppercase(text: &str) -> String {
+    text.to_uppercase()
+}
+
+#[cfg(feature = "unicode")]
+fn to_lowercase(text: &str) -> String {
+    text.to_lowercase()
+}
+
+/// Whether `text` matches the LIKE `pattern`, in which % matches any run of characters and _ any
+/// one character, and the `escape` character makes the one after it match only itself. As in
+/// SQLite, case doesn't matter for ASCII letters, but does for others.
+/// [like](https://www.sqlite.org/lang_expr.html#like)
+pub fn like(pattern: &str, text: &str, escape: Option<char>) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    like_chars(&pattern, &text, escape)
+}
+
+fn like_chars(pattern: &[char], text: &[char], escape: Option<char>) -> bool {
+    let matches_first = |c: char| text.first().is_some_and(|t| t.eq_ignore_ascii_case(&c));
+
+    match pattern {
+        [] => text.is_empty(),
+        [c, literal, rest @ ..] if Some(*c) == escape => {
+            matches_first(*literal) && like_chars(rest, &text[1..], escape)
+        }
+        [c] if Some(*c) == escape => false,
+        ['%', rest @ ..] => {
+            // A run of %s is the same as one
+            let rest = &rest[rest.iter().take_while(|&&c| c == '%').count()..];
+            (0..=text.len()).any(|skipped| like_chars(rest, &text[skipped..], escape))
+        }
+        ['_', rest @ ..] => !text.is_empty() && like_chars(rest, &text[1..], escape),
+        [c, rest @ ..] => matches_first(*c) && like_chars(rest, &text[1..], escape),
+    }
+}
+
+/// [abs(X)](https://www.sqlite.org/lang_corefunc.html#abs)
+fn abs(args: &[Value]) -> Result<Value> {
+    match &args[0] {
+        Value::Null => Ok(Value::Null),
+        Value::Integer(i) => match i.checked_abs() {
+            Some(i) => Ok(Value::Integer(i)),
+            None => bail!("integer overflow"),
+        },
+        Value::Real(r) => Ok(Value::Real(r.abs())),
+        // Text and blobs are always converted to reals, even when they hold an integer
+        value => Ok(Value::Real(
+            value.to_numeric().as_real().unwrap_or_default().abs(),
+        )),
+    }
+}
+
+/// [quote(X)](https://www.sqlite.org/lang_corefunc.html#quote): the value as an SQL literal,
+/// which reads back as the same value. Reals have all the digits that takes, so they can differ
+/// from SQLite's in digits past those that matter.
+pub fn quote(value: &Value) -> String {
+    match value {
+        Value::Real(r) => printf::round_trip_real(*r),
+        value => Literal::from(value).to_string(),
+    }
+}
+
+/// [hex(X)](https://www.sqlite.org/lang_corefunc.html#hex): the bytes of a blob, or of anything
+/// else as UTF-8 text, in upper case hexadecimal
+fn hex(value: &Value) -> String {
+    let bytes = match value {
+        Value::Null => return String::new(),
+        Value::Blob(b) => b.clone(),
+        value => value.to_string().into_bytes(),
+    };
+
+    bytes.iter().map(|byte| format!("{byte:02X}")).collect()
+}
+
+/// [unhex(X,Y)](https://www.sqlite.org/lang_corefunc.html#unhex): the blob that the hexadecimal
+/// text X spells, or NULL if it isn't hexadecimal. Characters in Y may come between the pairs of
+/// digits.
+fn unhex(args: &[Value]) -> Result<Value> {
+    if args.contains(&Value::Null) {
+        return Ok(Value::Null);
+    }
+    let ignored = args.get(1).map(Value::to_string).unwrap_or_default();
+
+    let text = args[0].to_string();
+    let mut chars = text.chars();
+    let mut blob = vec![];
+    while let Some(c) = chars.next() {
+        if ignored.contains(c) {
+            continue;
+        }
+        let high = c.to_digit(16);
+        let low = chars.next().and_then(|c| c.to_digit(16));
+        let (Some(high), Some(low)) = (high, low) else {
+            return Ok(Value::Null);
+        };
+        blob.push((high * 16 + low) as u8);
+    }
+
+    Ok(Value::Blob(blob))
+}
+
+/// [printf(FORMAT,...)](https://www.sqlite.org/lang_corefunc.html#printf), also called format()
+fn printf_fn(args: &[Value]) -> Result<Value> {
+    Ok(match &args[0] {
+        Value::Null => Value::Null,
+        format => Value::Text(printf::printf(&format.to_string(), &args[1..])),
+    })
+}
+
+/// [length(X)](https://www.sqlite.org/lang_corefunc.html#length): characters for text (and
+/// numbers, as text), bytes for blobs
+fn length(args: &[Value]) -> Result<Value> {
+    let length = match &args[0] {
+        Value::Null => return Ok(Value::Null),
+        Value::Blob(b) => b.len(),
+        value => value.to_string().chars().count(),
+    };
+
+    Ok(Value::Integer(length as i64))
+}
+
+/// [substr(X,Y,Z)](https://www.sqlite.org/lang_corefunc.html#substr): Z characters (or bytes, for
+/// blobs) of X starting at the Y-th, counting from 1. A negative Y counts from the end, and a
+/// negative Z takes the characters before Y instead.
+fn substr(args: &[Value]) -> Result<Value> {
+    if args.contains(&Value::Null) {
+        return Ok(Value::Null);
+    }
+
+    let as_integer = |value: &Value| match value.to_numeric() {
+        Value::Integer(i) => i,
+        Value::Real(r) => r as i64,
+        _ => 0,
+    };
+
+    let (length, chars) = match &args[0] {
+        Value::Blob(b) => (b.len() as i64, None),
+        value => {
+            let chars: Vec<char> = value.to_string().chars().collect();
+            (chars.len() as i64, Some(chars))
+        }
+    };
+
+    // The same arithmetic as SQLite's substrFunc(), quirks (like a start of 0) included
+    let mut start = as_integer(&args[1]);
+    let mut count = args.get(2).map(as_integer).unwrap_or(i64::MAX / 2);
+    let negative_count = count < 0;
+    if negative_count {
+        count = -count;
+    }
+
+    if start < 0 {
+        start += length;
+        if start < 0 {
+            count = if negative_count { 0 } else { count + start };
+            start = 0;
+        }
+    } else if start > 0 {
+        start -= 1;
+    } else if count > 0 {
+        count -= 1;
+    }
+
+    if negative_count {
+        start -= count;
+        if start < 0 {
+            count += start;
+            start = 0;
+        }
+    }
+    if start + count > length {
+        count = (length - start).max(0);
+    }
+
+    let (start, count) = (start as usize, count.max(0) as usize);
+    Ok(match (&args[0], chars) {
+        (Value::Blob(b), _) => Value::Blob(b[start.min(b.len())..][..count].to_vec()),
+        (_, Some(chars)) => Value::Text(chars.iter().skip(start).take(count).collect()),
+        (_, None) => unreachable!(),
+    })
+}
+
+/// Soundex codes for A-Z, 0 meaning the letter is dropped (vowels, H, W and Y)
+const SOUNDEX_CODES: [u8; 26] = [
+    0, 1, 2, 3, 0, 1, 2, 0, 0, 2, 2, 4, 5, 5, 0, 1, 2, 6, 2, 3, 0, 1, 0, 2, 0, 2,
+];
+
+fn soundex_code(c: char) -> u8 {
+    if c.is_ascii_alphabetic() {
+        SOUNDEX_CODES[(c.to_ascii_uppercase() as u8 - b'A') as usize]
+    } else {
+        0
+    }
+}
+
+/// Computes the soundex encoding of a string, matching SQLite's soundex() (available when it's
+/// built with SQLITE_SOUNDEX): [soundex](https://www.sqlite.org/lang_corefunc.html#soundex)
+///
+/// Leading non-letters are skipped, and strings without any letters encode to "?000".
+pub fn soundex(input: &str) -> String {
+    let mut chars = input.chars().skip_while(|c| !c.is_ascii_alphabetic());
+
+    let Some(first_letter) = chars.next() else {
+        return "?000".to_string();
+    };
+
+    let mut result = String::with_capacity(4);
+    result.push(first_letter.to_ascii_uppercase());
+
+    let mut previous_code = soundex_code(first_letter);
+    for c in chars {
+        if result.len() == 4 {
+            break;
+        }
+
+        let code = soundex_code(c);
+        if code > 0 && code != previous_code {
+            result.push((b'0' + code) as char);
+        }
+        previous_code = code;
+    }
+
+    while result.len() < 4 {
+        result.push('0');
+    }
+
+    result
+}
+
+/// Computes the Levenshtein edit distance between two strings: the minimum number of single
+/// character insertions, deletions and substitutions needed to turn one into the other.
+pub fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+
+    // Only the previous row of the edit distance matrix is needed to compute the next one
+    let mut previous_row: Vec<usize> = (0..=b.len()).collect();
+    let mut current_row = vec![0; b.len() + 1];
+
+    for (i, a_char) in a.chars().enumerate() {
+        current_row[0] = i + 1;
+
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution_cost = if a_char == *b_char { 0 } else { 1 };
+
+            current_row[j + 1] = (previous_row[j] + substitution_cost)
+                .min(previous_row[j + 1] + 1)
+                .min(current_row[j] + 1);
+        }
+
+        std::mem::swap(&mut previous_row, &mut current_row);
+    }
+
+    previous_row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_like() {
+        assert!(like("app%", "Apples", None));
+        assert!(like("%le_", "apples", None));
+        assert!(like("%%p%%", "apples", None));
+        assert!(!like("app_", "apples", None));
+        assert!(!like("apples", "applesauce", None));
+        assert!(like("10!%", "10%", Some('!')));
+        assert!(!like("10!%", "100", Some('!')));
+        assert!(!like("É", "é", None));
+
+        let like_fn = FunctionRegistry::new().find("like", 2).unwrap();
+        let text = |s: &str| Value::Text(s.to_string());
+        assert_eq!(
+            (like_fn.call)(&[text("a_c"), text("ABC")]).unwrap(),
+            Value::Integer(1)
+        );
+        assert_eq!(
+            (like_fn.call)(&[text("a_c"), Value::Null]).unwrap(),
+            Value::Null
+        );
+    }
+
+    #[test]
+    fn test_soundex() {
+        assert_eq!(soundex("Robert"), "R163");
+        assert_eq!(soundex("Rupert"), "R163");
+        assert_eq!(soundex("Tymczak"), "T522");
+        assert_eq!(soundex("Pfister"), "P236");
+        assert_eq!(soundex("Lee"), "L000");
+        // Unlike American soundex, H and W separate letters with the same code in SQLite's version
+        assert_eq!(soundex("Ashcraft"), "A226");
+        assert_eq!(soundex("  42 o'brien"), "O165");
+        assert_eq!(soundex(""), "?000");
+        assert_eq!(soundex("1234"), "?000");
+    }
+
+    fn call(name: &str, args: &[Value]) -> Value {
+        let function = FunctionRegistry::new().find(name, args.len()).unwrap();
+        (function.call)(args).unwrap()
+    }
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.to_string())
+    }
+
+    #[test]
+    fn test_find_function() {
+        let registry = FunctionRegistry::new();
+
+        assert_eq!(registry.find("SOUNDEX", 1).unwrap().name, "soundex");
+        assert!(registry.find("soundex", 2).is_err());
+        assert!(registry.find("pizza", 1).is_err());
+    }
+
+    #[test]
+    fn test_register_custom_function() {
+        let mut registry = FunctionRegistry::new();
+        registry.register("double", 1, 1, |args| match &args[0] {
+            Value::Integer(i) => Ok(Value::Integer(i * 2)),
+            _ => bail!("double() takes an integer"),
+        });
+        // Overrides the built-in upper(), but only for two arguments
+        registry.register("upper", 2, 2, |_| Ok(text("overridden")));
+
+        let double = registry.find("double", 1).unwrap();
+        assert_eq!(
+            (double.call)(&[Value::Integer(21)]).unwrap(),
+            Value::Integer(42)
+        );
+        assert!((double.call)(&[text("21")]).is_err());
+
+        let upper = registry.find("upper", 2).unwrap();
+        assert_eq!(
+            (upper.call)(&[text("a"), text("b")]).unwrap(),
+            text("overridden")
+        );
+        let upper = registry.find("upper", 1).unwrap();
+        assert_eq!((upper.call)(&[text("a")]).unwrap(), text("A"));
+    }
+
+    #[test]
+    fn test_core_functions() {
+        assert_eq!(call("abs", &[Value::Integer(-3)]), Value::Integer(3));
+        assert_eq!(call("abs", &[Value::Real(-2.5)]), Value::Real(2.5));
+        assert_eq!(call("abs", &[text("-4")]), Value::Real(4.0));
+        assert_eq!(call("abs", &[Value::Null]), Value::Null);
+
+        assert_eq!(call("length", &[text("café")]), Value::Integer(4));
+        assert_eq!(
+            call("length", &[Value::Blob(vec![0, 1, 2])]),
+            Value::Integer(3)
+        );
+        assert_eq!(call("length", &[Value::Real(4.5)]), Value::Integer(3));
+
+        assert_eq!(call("upper", &[text("Fuji")]), text("FUJI"));
+        assert_eq!(call("lower", &[text("Fuji")]), text("fuji"));
+        assert_eq!(call("lower", &[Value::Null]), Value::Null);
+
+        assert_eq!(call("typeof", &[Value::Integer(1)]), text("integer"));
+        assert_eq!(call("typeof", &[Value::Real(1.0)]), text("real"));
+        assert_eq!(call("typeof", &[text("1")]), text("text"));
+        assert_eq!(call("typeof", &[Value::Blob(vec![])]), text("blob"));
+        assert_eq!(call("typeof", &[Value::Null]), text("null"));
+    }
+
+    #[cfg(not(feature = "unicode"))]
+    #[test]
+    fn test_case_conversion_is_ascii_only() {
+        assert_eq!(call("upper", &[text("café")]), text("CAFé"));
+    }
+
+    #[cfg(feature = "unicode")]
+    #[test]
+    fn test_case_conversion_is_unicode_aware() {
+        assert_eq!(call("upper", &[text("café")]), text("CAFÉ"));
+        assert_eq!(call("lower", &[text("ÀÉÎ")]), text("àéî"));
+    }
+
+    #[test]
+    fn test_substr() {
+        let substr = |x: &str, args: &[i64]| {
+            let mut values = vec![text(x)];
+            values.extend(args.iter().map(|i| Value::Integer(*i)));
+            call("substr", &values)
+        };
+
+        assert_eq!(substr("Granny Smith", &[1, 6]), text("Granny"));
+        assert_eq!(substr("Granny Smith", &[8]), text("Smith"));
+        assert_eq!(substr("Granny Smith", &[-5]), text("Smith"));
+        assert_eq!(substr("Granny Smith", &[-5, 2]), text("Sm"));
+        assert_eq!(substr("Granny Smith", &[7, -3]), text("nny"));
+        assert_eq!(substr("Granny Smith", &[0, 2]), text("G"));
+        assert_eq!(substr("Granny Smith", &[20]), text(""));
+        assert_eq!(substr("Granny Smith", &[-20, 10]), text("Gr"));
+        assert_eq!(substr("café", &[4]), text("é"));
+        assert_eq!(
+            call(
+                "substr",
+                &[
+                    Value::Blob(vec![1, 2, 3]),
+                    Value::Integer(2),
+                    Value::Integer(1)
+                ]
+            ),
+            Value::Blob(vec![2])
+        );
+        assert_eq!(
+            call("substr", &[Value::Null, Value::Integer(1)]),
+            Value::Null
+        );
+    }
+
+    #[test]
+    fn test_hex_and_quote() {
+        assert_eq!(call("hex", &[text("é")]), text("C3A9"));
+        assert_eq!(call("hex", &[Value::Real(12.5)]), text("31322E35"));
+        assert_eq!(call("hex", &[Value::Null]), text(""));
+        assert_eq!(
+            call("unhex", &[text("414243")]),
+            Value::Blob(b"ABC".to_vec())
+        );
+        assert_eq!(
+            call("unhex", &[text("41 42"), text(" ")]),
+            Value::Blob(b"AB".to_vec())
+        );
+        assert_eq!(call("unhex", &[text("4g")]), Value::Null);
+        assert_eq!(call("unhex", &[text("414")]), Value::Null);
+
+        assert_eq!(quote(&Value::Blob(vec![0, 255])), "X'00FF'");
+        assert_eq!(quote(&text("it's")), "'it''s'");
+        assert_eq!(quote(&Value::Null), "NULL");
+        assert_eq!(quote(&Value::Integer(12)), "12");
+        assert_eq!(quote(&Value::Real(100.0)), "100.0");
+
+        assert_eq!(
+            call("format", &[text("%5.1f%%"), Value::Real(99.44)]),
+            text(" 99.4%")
+        );
+        assert_eq!(call("printf", &[Value::Null]), Value::Null);
+    }
+
+    #[test]
+    fn test_levenshtein() {
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+        assert_eq!(levenshtein("flaw", "lawn"), 2);
+        assert_eq!(levenshtein("", "abc"), 3);
+        assert_eq!(levenshtein("abc", ""), 3);
+        assert_eq!(levenshtein("pizza", "pizza"), 0);
+        assert_eq!(levenshtein("café", "cafe"), 1);
+    }
+}
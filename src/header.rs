@@ -1,7 +1,258 @@
 use anyhow::{bail, Result};
-use std::convert::TryInto;
+use std::{convert::TryInto, fmt};
 
+/// Size in bytes of the database header at the start of page 1.
+pub const DATABASE_HEADER_SIZE: usize = 100;
+
+/// Every valid SQLite 3 database file starts with these 16 bytes.
+const MAGIC_HEADER_STRING: &[u8; 16] = b"SQLite format 3\0";
+
+/// The version of SQLite whose file format new databases are written in, in the form of
+/// SQLITE_VERSION_NUMBER (3.40.1)
+const SQLITE_VERSION_NUMBER: u32 = 3_040_001;
+
+/// The 100-byte database file header, as described here:
+/// [database_header](https://www.sqlite.org/fileformat.html#the_database_header)
 #[derive(Debug)]
+pub struct DatabaseHeader {
+    pub page_size: u32,
+    pub write_version: u8,
+    pub read_version: u8,
+    pub reserved_space: u8,
+    pub max_payload_fraction: u8,
+    pub min_payload_fraction: u8,
+    pub leaf_payload_fraction: u8,
+    pub file_change_counter: u32,
+    pub page_count: u32,
+    pub first_freelist_trunk_page: u32,
+    pub freelist_page_count: u32,
+    pub schema_cookie: u32,
+    pub schema_format: u32,
+    pub default_page_cache_size: u32,
+    pub largest_root_page: u32,
+    pub text_encoding: u32,
+    pub user_version: u32,
+    pub incremental_vacuum: u32,
+    pub application_id: u32,
+    pub version_valid_for: u32,
+    pub sqlite_version_number: u32,
+}
+
+/// Oddities in a header that don't make the file unreadable, but that callers may want to report.
+#[derive(Debug, PartialEq)]
+pub enum HeaderAnomaly {
+    /// The in-header database size is zero, so it has to be derived from the file size instead.
+    ZeroPageCount,
+    /// The change counter doesn't match version-valid-for, meaning the file was last written by a
+    /// SQLite version (prior to 3.7.0) that didn't keep the in-header page count up to date.
+    StalePageCount {
+        file_change_counter: u32,
+        version_valid_for: u32,
+    },
+}
+
+impl fmt::Display for HeaderAnomaly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeaderAnomaly::ZeroPageCount => {
+                write!(f, "in-header page count is 0, using file size instead")
+            }
+            HeaderAnomaly::StalePageCount {
+                file_change_counter,
+                version_valid_for,
+            } => write!(
+                f,
+                "in-header page count is stale (change counter {file_change_counter} != version-valid-for {version_valid_for}), using file size instead"
+            ),
+        }
+    }
+}
+
+impl DatabaseHeader {
+    /// The header of a new database of one page, the empty sqlite_schema table, with UTF-8 text
+    /// and the current schema format
+    pub fn new(page_size: u32) -> Self {
+        DatabaseHeader {
+            page_size,
+            write_version: 1,
+            read_version: 1,
+            reserved_space: 0,
+            max_payload_fraction: 64,
+            min_payload_fraction: 32,
+            leaf_payload_fraction: 32,
+            file_change_counter: 1,
+            page_count: 1,
+            first_freelist_trunk_page: 0,
+            freelist_page_count: 0,
+            schema_cookie: 0,
+            schema_format: 4,
+            default_page_cache_size: 0,
+            largest_root_page: 0,
+            text_encoding: 1,
+            user_version: 0,
+            incremental_vacuum: 0,
+            application_id: 0,
+            version_valid_for: 1,
+            sqlite_version_number: SQLITE_VERSION_NUMBER,
+        }
+    }
+
+    /// Writes the header out as the first 100 bytes of a database file
+    pub fn to_bytes(&self) -> [u8; DATABASE_HEADER_SIZE] {
+        let mut bytes = [0; DATABASE_HEADER_SIZE];
+        bytes[0..16].copy_from_slice(MAGIC_HEADER_STRING);
+        // 65536 doesn't fit in two bytes, so it's written as 1
+        let page_size = if self.page_size == 65_536 {
+            1
+        } else {
+            self.page_size as u16
+        };
+        bytes[16..18].copy_from_slice(&page_size.to_be_bytes());
+        bytes[18..24].copy_from_slice(&[
+            self.write_version,
+            self.read_version,
+            self.reserved_space,
+            self.max_payload_fraction,
+            self.min_payload_fraction,
+            self.leaf_payload_fraction,
+        ]);
+
+        let mut write_u32 = |offset: usize, value: u32| {
+            bytes[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+        };
+        write_u32(24, self.file_change_counter);
+        write_u32(28, self.page_count);
+        write_u32(32, self.first_freelist_trunk_page);
+        write_u32(36, self.freelist_page_count);
+        write_u32(40, self.schema_cookie);
+        write_u32(44, self.schema_format);
+        write_u32(48, self.default_page_cache_size);
+        write_u32(52, self.largest_root_page);
+        write_u32(56, self.text_encoding);
+        write_u32(60, self.user_version);
+        write_u32(64, self.incremental_vacuum);
+        write_u32(68, self.application_id);
+        write_u32(92, self.version_valid_for);
+        write_u32(96, self.sqlite_version_number);
+
+        bytes
+    }
+
+    /// Parses and validates the first 100 bytes of a database file
+    pub fn parse(stream: &[u8]) -> Result<Self> {
+        if stream.len() < DATABASE_HEADER_SIZE {
+            bail!(
+                "Not a SQLite database: file is too short for a header ({} bytes)",
+                stream.len()
+            );
+        }
+        if &stream[0..16] != MAGIC_HEADER_STRING {
+            bail!("Not a SQLite database: missing \"SQLite format 3\" magic string");
+        }
+
+        let page_size = match u16::from_be_bytes(stream[16..18].try_into()?) {
+            // If page_size is 1, this should be interpreted as 65,536
+            1 => 65_536,
+            n => n as u32,
+        };
+        if !(512..=65_536).contains(&page_size) || !page_size.is_power_of_two() {
+            bail!("Invalid page size in header: {page_size}");
+        }
+
+        let write_version = stream[18];
+        let read_version = stream[19];
+        for version in [write_version, read_version] {
+            // 1 for legacy (rollback journal), 2 for WAL
+            if !(1..=2).contains(&version) {
+                bail!("Unsupported file format version in header: {version}");
+            }
+        }
+
+        let reserved_space = stream[20];
+        if page_size - (reserved_space as u32) < 480 {
+            bail!("Invalid reserved space in header: {reserved_space} (page size {page_size})");
+        }
+
+        // These are fixed by the file format, and must be exactly these values.
+        let max_payload_fraction = stream[21];
+        let min_payload_fraction = stream[22];
+        let leaf_payload_fraction = stream[23];
+        if (
+            max_payload_fraction,
+            min_payload_fraction,
+            leaf_payload_fraction,
+        ) != (64, 32, 32)
+        {
+            bail!(
+                "Invalid payload fractions in header: {max_payload_fraction}/{min_payload_fraction}/{leaf_payload_fraction} (expected 64/32/32)"
+            );
+        }
+
+        let read_u32 = |offset: usize| -> Result<u32> {
+            Ok(u32::from_be_bytes(stream[offset..offset + 4].try_into()?))
+        };
+
+        let schema_format = read_u32(44)?;
+        if schema_format > 4 {
+            bail!("Unsupported schema format number in header: {schema_format}");
+        }
+
+        let text_encoding = read_u32(56)?;
+        if text_encoding > 3 {
+            bail!("Invalid text encoding in header: {text_encoding}");
+        }
+
+        Ok(DatabaseHeader {
+            page_size,
+            write_version,
+            read_version,
+            reserved_space,
+            max_payload_fraction,
+            min_payload_fraction,
+            leaf_payload_fraction,
+            file_change_counter: read_u32(24)?,
+            page_count: read_u32(28)?,
+            first_freelist_trunk_page: read_u32(32)?,
+            freelist_page_count: read_u32(36)?,
+            schema_cookie: read_u32(40)?,
+            schema_format,
+            default_page_cache_size: read_u32(48)?,
+            largest_root_page: read_u32(52)?,
+            text_encoding,
+            user_version: read_u32(60)?,
+            incremental_vacuum: read_u32(64)?,
+            application_id: read_u32(68)?,
+            version_valid_for: read_u32(92)?,
+            sqlite_version_number: read_u32(96)?,
+        })
+    }
+
+    /// Works out how many pages the database has, falling back to the file size when the
+    /// in-header page count can't be trusted.
+    ///
+    /// The in-header database size is only valid if it is non-zero and the change counter matches
+    /// version-valid-for, see: [in_header_database_size](https://www.sqlite.org/fileformat.html#in_header_database_size)
+    pub fn resolve_page_count(&self, file_size: u64) -> (u32, Vec<HeaderAnomaly>) {
+        let mut anomalies = vec![];
+
+        if self.page_count == 0 {
+            anomalies.push(HeaderAnomaly::ZeroPageCount);
+        } else if self.file_change_counter != self.version_valid_for {
+            anomalies.push(HeaderAnomaly::StalePageCount {
+                file_change_counter: self.file_change_counter,
+                version_valid_for: self.version_valid_for,
+            });
+        }
+
+        if anomalies.is_empty() {
+            (self.page_count, anomalies)
+        } else {
+            ((file_size / self.page_size as u64) as u32, anomalies)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum BTreePage {
     InteriorIndex = 2,
     InteriorTable = 5,
@@ -9,6 +260,35 @@ pub enum BTreePage {
     LeafTable = 13,
 }
 
+impl TryFrom<u8> for BTreePage {
+    type Error = anyhow::Error;
+
+    fn try_from(page_type: u8) -> Result<Self> {
+        match page_type {
+            2 => Ok(BTreePage::InteriorIndex),
+            5 => Ok(BTreePage::InteriorTable),
+            10 => Ok(BTreePage::LeafIndex),
+            13 => Ok(BTreePage::LeafTable),
+            x => bail!("Invalid page value encountered: {}", x),
+        }
+    }
+}
+
+impl BTreePage {
+    pub fn is_interior(&self) -> bool {
+        matches!(self, BTreePage::InteriorIndex | BTreePage::InteriorTable)
+    }
+
+    /// Interior pages have a 12 byte header, leaf pages an 8 byte one
+    pub fn header_size(&self) -> usize {
+        if self.is_interior() {
+            12
+        } else {
+            8
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct PageHeader {
     pub page_type: BTreePage,
@@ -16,29 +296,154 @@ pub struct PageHeader {
     pub number_of_cells: u16,
     pub start_of_content_area: u16,
     pub fragmented_free_bytes: u8,
+    /// Only present on interior pages
+    pub right_most_pointer: Option<u32>,
 }
 
 impl PageHeader {
     /// Parses a page header stream into a page header
     pub fn parse(stream: &[u8]) -> Result<Self> {
-        let page_type = match stream[0] {
-            2 => BTreePage::InteriorIndex,
-            5 => BTreePage::InteriorTable,
-            10 => BTreePage::LeafIndex,
-            13 => BTreePage::LeafTable,
-            x => bail!("Invalid page value encountered: {}", x),
+        let Some(&first_byte) = stream.first() else {
+            bail!("Page header is empty");
         };
+        let page_type = BTreePage::try_from(first_byte)?;
+        if stream.len() < page_type.header_size() {
+            bail!(
+                "Page header is {} bytes, expected {}",
+                stream.len(),
+                page_type.header_size()
+            );
+        }
         let first_free_block_start = u16::from_be_bytes(stream[1..3].try_into()?);
         let number_of_cells = u16::from_be_bytes(stream[3..5].try_into()?);
         let start_of_content_area = u16::from_be_bytes(stream[5..7].try_into()?);
         let fragmented_free_bytes = stream[7];
+        let right_most_pointer = if page_type.is_interior() {
+            Some(u32::from_be_bytes(stream[8..12].try_into()?))
+        } else {
+            None
+        };
         let header = PageHeader {
             page_type,
             first_free_block_start,
             number_of_cells,
             start_of_content_area,
             fragmented_free_bytes,
+            right_most_pointer,
         };
         Ok(header)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn valid_header() -> [u8; DATABASE_HEADER_SIZE] {
+        let mut header = [0; DATABASE_HEADER_SIZE];
+        header[0..16].copy_from_slice(MAGIC_HEADER_STRING);
+        header[16..18].copy_from_slice(&4096u16.to_be_bytes());
+        header[18] = 1;
+        header[19] = 1;
+        header[21] = 64;
+        header[22] = 32;
+        header[23] = 32;
+        header[28..32].copy_from_slice(&2u32.to_be_bytes());
+        header[24..28].copy_from_slice(&7u32.to_be_bytes());
+        header[44..48].copy_from_slice(&4u32.to_be_bytes());
+        header[56..60].copy_from_slice(&1u32.to_be_bytes());
+        header[92..96].copy_from_slice(&7u32.to_be_bytes());
+        header
+    }
+
+    #[test]
+    fn test_parse_page_header() {
+        let leaf = PageHeader::parse(&[13, 0, 0, 0, 3, 15, 160, 0]).unwrap();
+        assert_eq!(leaf.page_type, BTreePage::LeafTable);
+        assert_eq!(leaf.number_of_cells, 3);
+        assert_eq!(leaf.start_of_content_area, 4000);
+        assert_eq!(leaf.right_most_pointer, None);
+
+        let interior = PageHeader::parse(&[2, 0, 0, 0, 1, 15, 160, 0, 0, 0, 1, 2]).unwrap();
+        assert_eq!(interior.page_type, BTreePage::InteriorIndex);
+        assert_eq!(interior.right_most_pointer, Some(258));
+    }
+
+    #[test]
+    fn test_parse_database_header() {
+        let header = DatabaseHeader::parse(&valid_header()).unwrap();
+
+        assert_eq!(header.page_size, 4096);
+        assert_eq!(header.page_count, 2);
+        assert_eq!(header.text_encoding, 1);
+        assert_eq!(header.resolve_page_count(8192), (2, vec![]));
+    }
+
+    #[test]
+    fn test_database_header_to_bytes() {
+        let bytes = valid_header();
+        assert_eq!(DatabaseHeader::parse(&bytes).unwrap().to_bytes(), bytes);
+
+        let header = DatabaseHeader::parse(&DatabaseHeader::new(65_536).to_bytes()).unwrap();
+        assert_eq!(header.page_size, 65_536);
+        assert_eq!(header.resolve_page_count(65_536), (1, vec![]));
+    }
+
+    #[test]
+    fn test_parse_database_header_page_size_one_means_65536() {
+        let mut bytes = valid_header();
+        bytes[16..18].copy_from_slice(&1u16.to_be_bytes());
+
+        let header = DatabaseHeader::parse(&bytes).unwrap();
+        assert_eq!(header.page_size, 65_536);
+    }
+
+    #[test]
+    fn test_parse_database_header_rejects_invalid_files() {
+        let mut bad_magic = valid_header();
+        bad_magic[0..6].copy_from_slice(b"Hello!");
+        assert!(DatabaseHeader::parse(&bad_magic).is_err());
+
+        let mut bad_page_size = valid_header();
+        bad_page_size[16..18].copy_from_slice(&1000u16.to_be_bytes());
+        assert!(DatabaseHeader::parse(&bad_page_size).is_err());
+
+        let mut bad_fractions = valid_header();
+        bad_fractions[21] = 100;
+        assert!(DatabaseHeader::parse(&bad_fractions).is_err());
+
+        assert!(DatabaseHeader::parse(&valid_header()[..50]).is_err());
+    }
+
+    #[test]
+    fn test_resolve_page_count_falls_back_to_file_size() {
+        let mut bytes = valid_header();
+        bytes[28..32].copy_from_slice(&0u32.to_be_bytes());
+
+        let header = DatabaseHeader::parse(&bytes).unwrap();
+        assert_eq!(
+            header.resolve_page_count(3 * 4096),
+            (3, vec![HeaderAnomaly::ZeroPageCount])
+        );
+    }
+
+    #[test]
+    fn test_resolve_page_count_ignores_stale_legacy_page_count() {
+        let mut bytes = valid_header();
+        // Written by an old version of SQLite, which bumped the change counter without updating
+        // version-valid-for (or the page count).
+        bytes[24..28].copy_from_slice(&9u32.to_be_bytes());
+
+        let header = DatabaseHeader::parse(&bytes).unwrap();
+        assert_eq!(
+            header.resolve_page_count(5 * 4096),
+            (
+                5,
+                vec![HeaderAnomaly::StalePageCount {
+                    file_change_counter: 9,
+                    version_valid_for: 7,
+                }]
+            )
+        );
+    }
+}
@@ -0,0 +1,180 @@
+use anyhow::Result;
+use std::{
+    env,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// A command entered at the prompt
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryEntry {
+    /// When the command was entered, in seconds since the Unix epoch
+    pub timestamp: u64,
+    /// The database the command was run against
+    pub database: String,
+    pub command: String,
+}
+
+/// The commands entered at the prompt, kept in a file so they outlive the session. Each line of the
+/// file is an entry: its timestamp, database and command, separated by tabs.
+#[derive(Default)]
+pub struct History {
+    entries: Vec<HistoryEntry>,
+    file: Option<PathBuf>,
+}
+
+impl History {
+    /// Where history is kept: $SQLITE_RUST_HISTORY, or else ~/.sqlite_rust_history
+    pub fn default_path() -> Option<PathBuf> {
+        match env::var_os("SQLITE_RUST_HISTORY") {
+            Some(path) => Some(PathBuf::from(path)),
+            None => {
+                env::var_os("HOME").map(|home| PathBuf::from(home).join(".sqlite_rust_history"))
+            }
+        }
+    }
+
+    /// Loads history from a file, which new entries are then appended to. A missing file is just
+    /// an empty history, and lines that can't be read are skipped.
+    pub fn load(path: PathBuf) -> Result<Self> {
+        let mut entries = vec![];
+
+        if path.exists() {
+            for line in BufReader::new(File::open(&path)?).lines() {
+                let line = line?;
+                let mut fields = line.splitn(3, '\t');
+                let (Some(timestamp), Some(database), Some(command)) =
+                    (fields.next(), fields.next(), fields.next())
+                else {
+                    continue;
+                };
+                let Ok(timestamp) = timestamp.parse() else {
+                    continue;
+                };
+
+                entries.push(HistoryEntry {
+                    timestamp,
+                    database: database.to_string(),
+                    command: command.to_string(),
+                });
+            }
+        }
+
+        Ok(History {
+            entries,
+            file: Some(path),
+        })
+    }
+
+    pub fn entries(&self) -> &[HistoryEntry] {
+        &self.entries
+    }
+
+    /// Records a command, appending it to the history file
+    pub fn add(&mut self, database: &str, command: &str) -> Result<()> {
+        let entry = HistoryEntry {
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            // Tabs and newlines would break the file's format
+            database: database.replace(['\t', '\n'], " "),
+            command: command.replace(['\t', '\n'], " "),
+        };
+
+        if let Some(path) = &self.file {
+            let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+            writeln!(
+                file,
+                "{}\t{}\t{}",
+                entry.timestamp, entry.database, entry.command
+            )?;
+        }
+        self.entries.push(entry);
+
+        Ok(())
+    }
+
+    /// The entries whose command contains `text`, most recent first, like a reverse search
+    pub fn search<'a>(&'a self, text: &'a str) -> impl Iterator<Item = (usize, &'a HistoryEntry)> {
+        self.entries
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(move |(_, entry)| entry.command.contains(text))
+    }
+}
+
+/// Formats seconds since the Unix epoch as a UTC date and time, "YYYY-MM-DD HH:MM:SS"
+pub fn format_timestamp(timestamp: u64) -> String {
+    let days = (timestamp / 86400) as i64;
+    let seconds = timestamp % 86400;
+
+    // Howard Hinnant's civil_from_days: https://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_history_file() {
+        let path = env::temp_dir().join(format!("sqlite_rust_history_test_{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut history = History::load(path.clone()).unwrap();
+        history.add("sample.db", "SELECT name FROM apples").unwrap();
+        history.add("sample.db", ".tables").unwrap();
+        history
+            .add("other.db", "SELECT\tcolor FROM apples")
+            .unwrap();
+
+        let history = History::load(path.clone()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let commands: Vec<_> = history
+            .entries()
+            .iter()
+            .map(|e| e.command.as_str())
+            .collect();
+        assert_eq!(
+            commands,
+            vec![
+                "SELECT name FROM apples",
+                ".tables",
+                "SELECT color FROM apples"
+            ]
+        );
+        assert_eq!(history.entries()[2].database, "other.db");
+
+        let found: Vec<_> = history.search("apples").map(|(i, _)| i).collect();
+        assert_eq!(found, vec![2, 0]);
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00:00");
+        assert_eq!(format_timestamp(951782400), "2000-02-29 00:00:00");
+        assert_eq!(format_timestamp(1791460263), "2026-10-08 11:51:03");
+    }
+}
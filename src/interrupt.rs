@@ -0,0 +1,151 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex, PoisonError,
+};
+
+/// A progress callback, which stops the statement that's running by returning true
+pub type ProgressFn = dyn FnMut() -> bool + Send;
+
+/// A statement was stopped by InterruptHandle::interrupt, like SQLITE_INTERRUPT
+#[derive(Debug, PartialEq, thiserror::Error)]
+#[error("interrupted")]
+pub struct InterruptedError;
+
+/// Stops the statements running on a database, like sqlite3_interrupt(). A clone can be handed to
+/// another thread, or to a signal handler, since interrupting is a single atomic store.
+///
+/// Statements check for an interrupt as they read each row and fail with InterruptedError once
+/// there's been one. It stays set until it's reset, which is up to whoever runs the next
+/// statement.
+#[derive(Debug, Clone, Default)]
+pub struct InterruptHandle {
+    interrupted: Arc<AtomicBool>,
+}
+
+impl InterruptHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn interrupt(&self) {
+        self.interrupted.store(true, Ordering::Relaxed);
+    }
+
+    pub fn reset(&self) {
+        self.interrupted.store(false, Ordering::Relaxed);
+    }
+
+    pub fn is_interrupted(&self) -> bool {
+        self.interrupted.load(Ordering::Relaxed)
+    }
+
+    /// Fails if there's been an interrupt, so that the statement checking stops there
+    pub fn check(&self) -> Result<(), InterruptedError> {
+        if self.is_interrupted() {
+            return Err(InterruptedError);
+        }
+
+        Ok(())
+    }
+}
+
+/// A callback that statements run every `period` rows they read, like sqlite3_progress_handler(),
+/// set with Database::set_progress_handler. It can report that a long statement is still going,
+/// or stop it, say once a deadline has passed, by returning true. The statement then fails with
+/// InterruptedError, as though it had been interrupted, though later statements aren't.
+pub struct ProgressHandler {
+    period: u64,
+    callback: Mutex<Box<ProgressFn>>,
+}
+
+impl ProgressHandler {
+    /// A handler that runs `callback` every `period` rows, or never if `period` is 0
+    pub fn new(period: u64, callback: impl FnMut() -> bool + Send + 'static) -> Self {
+        ProgressHandler {
+            period,
+            callback: Mutex::new(Box::new(callback)),
+        }
+    }
+}
+
+/// What a statement checks as it reads each row: whether it's been interrupted and, every so
+/// many rows, whether the progress handler wants it stopped
+pub(crate) struct StatementProgress<'a> {
+    interrupt: InterruptHandle,
+    handler: Option<&'a ProgressHandler>,
+    rows: u64,
+}
+
+impl<'a> StatementProgress<'a> {
+    pub(crate) fn new(interrupt: InterruptHandle, handler: Option<&'a ProgressHandler>) -> Self {
+        StatementProgress {
+            interrupt,
+            handler: handler.filter(|handler| handler.period > 0),
+            rows: 0,
+        }
+    }
+
+    /// Counts a row read, failing if the statement should stop there
+    pub(crate) fn row_read(&mut self) -> Result<(), InterruptedError> {
+        self.interrupt.check()?;
+
+        self.rows += 1;
+        if let Some(handler) = self.handler {
+            if self.rows.is_multiple_of(handler.period) {
+                let mut callback = handler
+                    .callback
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner);
+                if callback() {
+                    return Err(InterruptedError);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interrupt() {
+        let handle = InterruptHandle::new();
+        let clone = handle.clone();
+        assert_eq!(handle.check(), Ok(()));
+
+        clone.interrupt();
+        assert!(handle.is_interrupted());
+        assert_eq!(handle.check(), Err(InterruptedError));
+
+        handle.reset();
+        assert_eq!(clone.check(), Ok(()));
+    }
+
+    #[test]
+    fn test_statement_progress() {
+        let calls = Arc::new(Mutex::new(0));
+        let handler = ProgressHandler::new(3, {
+            let calls = calls.clone();
+            move || {
+                let mut calls = calls.lock().unwrap();
+                *calls += 1;
+                *calls == 2
+            }
+        });
+        let mut progress = StatementProgress::new(InterruptHandle::new(), Some(&handler));
+
+        // Called after the 3rd and 6th rows, stopping the statement on the second call
+        for _ in 0..5 {
+            assert_eq!(progress.row_read(), Ok(()));
+        }
+        assert_eq!(*calls.lock().unwrap(), 1);
+        assert_eq!(progress.row_read(), Err(InterruptedError));
+
+        let never = ProgressHandler::new(0, || true);
+        let mut progress = StatementProgress::new(InterruptHandle::new(), Some(&never));
+        assert_eq!(progress.row_read(), Ok(()));
+    }
+}
@@ -0,0 +1,406 @@
+#[cfg(feature = "fs")]
+use crate::lock::{DatabaseLockedError, LockLevel};
+use crate::{database::Database, lock::lock_byte_page, page_source::PageSource, vfs::MemoryFile};
+#[cfg(feature = "fs")]
+use anyhow::bail;
+use anyhow::{Context, Result};
+use std::{
+    collections::{hash_map::RandomState, HashSet},
+    hash::BuildHasher,
+    path::PathBuf,
+    sync::PoisonError,
+};
+#[cfg(feature = "fs")]
+use std::{fs, io, time::Duration};
+
+/// The bytes every journal header starts with
+const MAGIC: [u8; 8] = [0xd9, 0xd5, 0x05, 0xf9, 0x20, 0xa1, 0x63, 0xd7];
+
+/// The size of the header's fields. The header is padded out to a sector, after which the page
+/// records start.
+const HEADER_SIZE: u64 = 28;
+
+/// The sector size written in the header, SQLite's default on unix
+const SECTOR_SIZE: u32 = 4096;
+
+/// A rollback journal, in SQLite's format so that sqlite3 can roll it back too: the contents
+/// pages had before a write began, kept so that they can be put back if the write is cut short.
+/// A database file on disk has its journal beside it, as "<database>-journal", where a
+/// connection opening the database after a crash finds it and rolls the database back. Other
+/// databases keep their journal in memory, which can undo a write that fails but not one that a
+/// crash interrupts.
+/// [Rollback journal](https://www.sqlite.org/fileformat2.html#the_rollback_journal)
+pub(crate) struct Journal {
+    /// Where the journal is on disk, to be deleted when the write is over
+    path: Option<PathBuf>,
+    file: Box<dyn PageSource>,
+    /// Added to each page's checksum, so that records left over from an older journal don't pass
+    /// as this one's
+    nonce: u32,
+    /// The database's size in pages when the journal was started. Pages after it are new, so
+    /// instead of being journaled they're cut off when it's rolled back.
+    original_page_count: u32,
+    /// The pages the journal has the original contents of, which they're only needed once
+    journaled: HashSet<u32>,
+    /// Whether pages have been added since the journal was last synced
+    unsynced: bool,
+}
+
+impl Journal {
+    /// Starts a journal, at `path` or in memory, for a database of `page_count` pages
+    pub fn create(path: Option<PathBuf>, page_size: u32, page_count: u32) -> Result<Self> {
+        let file = open_file(&path)?;
+        file.set_len(0)?;
+        let nonce = RandomState::new().hash_one(page_count) as u32;
+
+        let mut header = MAGIC.to_vec();
+        // No records yet: the count is written as they're synced
+        header.extend(0u32.to_be_bytes());
+        header.extend(nonce.to_be_bytes());
+        header.extend(page_count.to_be_bytes());
+        header.extend(SECTOR_SIZE.to_be_bytes());
+        header.extend(page_size.to_be_bytes());
+        file.write_at(0, &header)?;
+
+        Ok(Journal {
+            path,
+            file,
+            nonce,
+            original_page_count: page_count,
+            journaled: HashSet::new(),
+            unsynced: false,
+        })
+    }
+
+    /// Whether the original contents of `page_number` have yet to be added, before it's written
+    pub fn needs(&self, page_number: u32) -> bool {
+        page_number <= self.original_page_count && !self.journaled.contains(&page_number)
+    }
+
+    /// Adds the original contents of `page_number`
+    pub fn add(&mut self, page_number: u32, bytes: &[u8]) -> Result<()> {
+        let mut record = page_number.to_be_bytes().to_vec();
+        record.extend(bytes);
+        record.extend(checksum(self.nonce, bytes).to_be_bytes());
+        let offset = SECTOR_SIZE as u64 + self.journaled.len() as u64 * record.len() as u64;
+        self.file.write_at(offset, &record)?;
+        self.journaled.insert(page_number);
+        self.unsynced = true;
+
+        Ok(())
+    }
+
+    /// Makes sure the pages added so far have reached storage, and then that the header counts
+    /// them, so that no page of the database is overwritten before its original is safe
+    pub fn sync(&mut self) -> Result<()> {
+        if self.unsynced {
+            self.file.sync()?;
+            let records = self.journaled.len() as u32;
+            self.file.write_at(8, &records.to_be_bytes())?;
+            self.file.sync()?;
+            self.unsynced = false;
+        }
+
+        Ok(())
+    }
+
+    /// Puts the journaled pages back in `database_file`, and cuts it back to its original size
+    pub fn roll_back(&mut self, database_file: &dyn PageSource) -> Result<()> {
+        self.sync()?;
+        play_back(&*self.file, database_file)
+    }
+
+    /// Deletes the journal, which is the moment the write it was kept for is committed
+    pub fn delete(self) -> Result<()> {
+        match &self.path {
+            #[cfg(feature = "fs")]
+            Some(path) => Ok(fs::remove_file(path)?),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(feature = "fs")]
+fn open_file(path: &Option<PathBuf>) -> Result<Box<dyn PageSource>> {
+    Ok(match path {
+        Some(path) => Box::new(
+            fs::File::options()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(path)?,
+        ),
+        None => Box::new(MemoryFile::default()),
+    })
+}
+
+#[cfg(not(feature = "fs"))]
+fn open_file(_path: &Option<PathBuf>) -> Result<Box<dyn PageSource>> {
+    Ok(Box::new(MemoryFile::default()))
+}
+
+/// SQLite's checksum of a journaled page: the nonce plus every 200th byte, counting back from
+/// 200 bytes before the end
+fn checksum(nonce: u32, bytes: &[u8]) -> u32 {
+    (1..)
+        .map(|i| bytes.len() as isize - 200 * i)
+        .take_while(|&i| i > 0)
+        .fold(nonce, |sum, i| sum.wrapping_add(bytes[i as usize] as u32))
+}
+
+/// Writes the pages in `journal` back to `database_file` and cuts it back to the size it was when
+/// the journal was started. A journal can have more than one segment, each with a header. The
+/// records stop at the first that doesn't have the right checksum, which was never synced.
+fn play_back(journal: &dyn PageSource, database_file: &dyn PageSource) -> Result<()> {
+    let size = journal.size()?;
+    let u32_at =
+        |bytes: &[u8], at: usize| u32::from_be_bytes(bytes[at..at + 4].try_into().unwrap());
+
+    let mut original_size = None;
+    let mut offset = 0;
+    'segments: while offset + HEADER_SIZE <= size {
+        let header = journal.read_at(offset, HEADER_SIZE as usize)?;
+        let (records, nonce, page_count, sector_size, page_size) = (
+            u32_at(&header, 8),
+            u32_at(&header, 12),
+            u32_at(&header, 16),
+            u32_at(&header, 20) as u64,
+            u32_at(&header, 24),
+        );
+        if header[..8] != MAGIC
+            || !sector_size.is_power_of_two()
+            || !(32..=65536).contains(&sector_size)
+            || !page_size.is_power_of_two()
+            || !(512..=65536).contains(&page_size)
+        {
+            break;
+        }
+        original_size.get_or_insert(page_count as u64 * page_size as u64);
+
+        let record_size = page_size as u64 + 8;
+        let start = offset + sector_size;
+        // SQLite writes -1 for a journal it doesn't sync, whose records go to the end of the file
+        let records = match records {
+            u32::MAX => size.saturating_sub(start) / record_size,
+            records => records as u64,
+        };
+        for i in 0..records {
+            let at = start + i * record_size;
+            if at + record_size > size {
+                break 'segments;
+            }
+            let record = journal.read_at(at, record_size as usize)?;
+            let page_number = u32_at(&record, 0);
+            let bytes = &record[4..4 + page_size as usize];
+            if page_number == 0
+                || page_number == lock_byte_page(page_size)
+                || u32_at(&record, 4 + page_size as usize) != checksum(nonce, bytes)
+            {
+                break 'segments;
+            }
+            database_file.write_at((page_number - 1) as u64 * page_size as u64, bytes)?;
+        }
+
+        offset = (start + records * record_size).div_ceil(sector_size) * sector_size;
+    }
+
+    if let Some(original_size) = original_size {
+        database_file.set_len(original_size)?;
+    }
+    database_file.sync()
+}
+
+impl Database {
+    /// Adds the contents `page_numbers` have now to the rollback journal, starting one if there
+    /// isn't one, and syncs it, so that the pages can be overwritten. Pages past the end of the
+    /// database when the journal was started, and pages already in it, are left out. Within a
+    /// transaction, the savepoints and the statement going on keep them too.
+    pub(crate) fn journal_pages(&self, page_numbers: impl IntoIterator<Item = u32>) -> Result<()> {
+        let mut savepoints = self
+            .savepoints
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let mut journal = self.journal.lock().unwrap_or_else(PoisonError::into_inner);
+        let journal = match &mut *journal {
+            Some(journal) => journal,
+            journal => journal.insert(Journal::create(
+                self.journal_path.clone(),
+                self.page_size,
+                self.page_count,
+            )?),
+        };
+
+        for page_number in page_numbers {
+            let needed = journal.needs(page_number)
+                || savepoints
+                    .iter()
+                    .any(|savepoint| savepoint.needs(page_number));
+            // The lock-byte page is never written
+            if !needed || page_number == lock_byte_page(self.page_size) {
+                continue;
+            }
+
+            let bytes = self.page_bytes(page_number)?;
+            if journal.needs(page_number) {
+                journal.add(page_number, &bytes)?;
+            }
+            for savepoint in savepoints.iter_mut() {
+                if savepoint.needs(page_number) {
+                    savepoint.add(page_number, &bytes);
+                }
+            }
+        }
+
+        journal.sync()
+    }
+
+    /// Runs `write`, which journals pages with journal_pages before writing over them, as a
+    /// change that's made in full or not at all: if it fails, the journaled pages are put back.
+    /// Within a transaction, it's only undone if it fails, and otherwise committed along with the
+    /// rest of the transaction.
+    pub(crate) fn write_atomically(&self, write: impl FnOnce() -> Result<()>) -> Result<()> {
+        if self.in_transaction() {
+            return self.write_statement(write);
+        }
+
+        match write() {
+            Ok(()) => self.commit_journal(),
+            Err(err) => match self.roll_back_journal() {
+                Ok(()) => Err(err),
+                Err(rollback_err) => Err(err).context(format!("{rollback_err:#}")),
+            },
+        }
+    }
+
+    /// Commits the writes the journal was kept for, by syncing the database and then deleting
+    /// the journal
+    pub(crate) fn commit_journal(&self) -> Result<()> {
+        let journal = self
+            .journal
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        match journal {
+            Some(journal) => {
+                self.database_file.sync()?;
+                journal.delete()
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Undoes the writes the journal was kept for, by putting back the pages in it
+    pub(crate) fn roll_back_journal(&self) -> Result<()> {
+        let journal = self
+            .journal
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        let Some(mut journal) = journal else {
+            return Ok(());
+        };
+
+        // A journal on disk is left to be rolled back when the database is next read
+        journal
+            .roll_back(&*self.database_file)
+            .context("rolling back failed, so the database needs recovering")?;
+        journal.delete()
+    }
+
+    /// Whether a journal has been left beside the database by a write that's still going on or
+    /// was cut short
+    #[cfg(feature = "fs")]
+    pub(crate) fn journal_exists(&self) -> bool {
+        self.journal_path
+            .as_ref()
+            .is_some_and(|path| fs::metadata(path).is_ok_and(|metadata| metadata.len() > 0))
+    }
+
+    /// Rolls back a hot journal, left beside the database by a write that was cut short, as
+    /// SQLite does before reading a database. A journal is only hot if no connection holds the
+    /// reserved lock, which a writer keeps until it's done. Called with the shared lock held.
+    #[cfg(feature = "fs")]
+    pub(crate) fn roll_back_hot_journal(&self) -> Result<()> {
+        let Some(path) = self.journal_path.as_ref().filter(|_| self.journal_exists()) else {
+            return Ok(());
+        };
+        match self.database_file.lock(LockLevel::Reserved, Duration::ZERO) {
+            Err(err) if err.is::<DatabaseLockedError>() => return Ok(()),
+            locked => locked?,
+        }
+        if self.options.read_only {
+            let _ = self.database_file.unlock(LockLevel::Shared);
+            bail!("attempt to write a readonly database: it has a journal to roll back");
+        }
+
+        let rolled_back = self
+            .database_file
+            .lock(LockLevel::Exclusive, self.options.busy_timeout)
+            .and_then(|()| match fs::File::open(path) {
+                Ok(journal) => {
+                    play_back(&journal, &*self.database_file)?;
+                    Ok(fs::remove_file(path)?)
+                }
+                // Another connection rolled it back first
+                Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+                Err(err) => Err(err.into()),
+            })
+            .context("can't roll back the journal left by a write that was cut short");
+        let unlocked = self.database_file.unlock(LockLevel::Shared);
+        rolled_back?;
+        unlocked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roll_back() {
+        let database_file = MemoryFile::default();
+        let pages = [[1u8; 512], [2; 512], [3; 512]];
+        database_file.write_at(0, &pages.concat()).unwrap();
+
+        let mut journal = Journal::create(None, 512, 3).unwrap();
+        for page_number in [2, 1] {
+            assert!(journal.needs(page_number));
+            let original = &pages[page_number as usize - 1];
+            journal.add(page_number, original).unwrap();
+            database_file
+                .write_at((page_number - 1) as u64 * 512, &[9; 512])
+                .unwrap();
+        }
+        assert!(!journal.needs(2));
+        // A page the database didn't have isn't journaled, and rolling back removes it
+        assert!(!journal.needs(4));
+        database_file.write_at(3 * 512, &[9; 512]).unwrap();
+
+        journal.roll_back(&database_file).unwrap();
+        assert_eq!(
+            &*database_file
+                .read_at(0, database_file.size().unwrap() as usize)
+                .unwrap(),
+            pages.concat()
+        );
+    }
+
+    #[test]
+    fn test_play_back_stops_at_a_bad_checksum() {
+        let database_file = MemoryFile::default();
+        database_file.write_at(0, &[7; 1024]).unwrap();
+
+        let mut journal = Journal::create(None, 512, 2).unwrap();
+        journal.add(1, &[1; 512]).unwrap();
+        journal.add(2, &[2; 512]).unwrap();
+        journal.sync().unwrap();
+        // The second record's checksum, after its page number and contents
+        let checksum_offset = SECTOR_SIZE as u64 + 520 + 4 + 512;
+        journal.file.write_at(checksum_offset, &[0; 4]).unwrap();
+
+        journal.roll_back(&database_file).unwrap();
+        let bytes = database_file.read_at(0, 1024).unwrap();
+        assert_eq!((bytes[0], bytes[512]), (1, 7));
+    }
+}
@@ -1,6 +1,50 @@
+pub mod affinity;
+pub mod analyze;
+pub mod async_connection;
+pub mod backup;
+pub mod binder;
+pub mod btree_writer;
+pub mod cell;
+pub mod check;
+pub mod collation;
+pub mod create_index;
+pub mod cursor;
+pub mod database;
+pub mod datetime;
+pub mod diff;
+pub mod dot_commands;
+pub mod dump;
+pub mod executor;
+pub mod export;
+pub mod functions;
 pub mod header;
+pub mod interrupt;
+pub mod journal;
+pub mod lock;
+pub mod operator;
+pub mod overflow;
+pub mod page_source;
+pub mod parameters;
+pub mod pipe;
+pub mod planner;
+pub mod pragma;
+pub mod printf;
 pub mod query_parser;
 pub mod record;
+pub mod regexp;
+pub mod row;
+pub mod row_layout;
 pub mod schema;
+pub mod script;
+pub mod sorter;
+pub mod stats;
+pub mod trace;
+pub mod transaction;
 pub mod types;
+pub mod uri;
+pub mod vacuum;
+pub mod value;
 pub mod varint;
+pub mod vfs;
+pub mod view;
+pub mod vm;
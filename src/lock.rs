@@ -0,0 +1,332 @@
+/// How much of a database a connection has locked, in SQLite's terms, as described here:
+/// [file locking](https://www.sqlite.org/lockingv3.html)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LockLevel {
+    #[default]
+    None,
+    /// Reading: any number of connections can hold a shared lock at once
+    Shared,
+    /// About to write: other connections can keep reading, but only one can hold this
+    Reserved,
+    /// Waiting for readers to finish so that it can write, while keeping new ones out
+    Pending,
+    /// Writing: nothing else can read or write
+    Exclusive,
+}
+
+/// A lock couldn't be taken before the busy timeout ran out, like SQLITE_BUSY
+#[derive(Debug, PartialEq, thiserror::Error)]
+#[error("database is locked")]
+pub struct DatabaseLockedError;
+
+// SQLite locks bytes of the 1 GiB "lock-byte page", which no database uses for data, rather than
+// the whole file
+pub(crate) const PENDING_BYTE: i64 = 0x4000_0000;
+
+/// The number of the lock-byte page, which databases of more than 1 GiB have to skip
+pub fn lock_byte_page(page_size: u32) -> u32 {
+    (PENDING_BYTE / page_size as i64) as u32 + 1
+}
+
+#[cfg(feature = "fs")]
+pub use self::file::LockedFile;
+
+#[cfg(feature = "fs")]
+mod file {
+    use super::*;
+    use crate::page_source::PageSource;
+    use anyhow::{bail, Result};
+    use std::{
+        borrow::Cow,
+        fs::File,
+        io,
+        sync::{Mutex, PoisonError},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    const RESERVED_BYTE: i64 = PENDING_BYTE + 1;
+    const SHARED_FIRST: i64 = PENDING_BYTE + 2;
+    const SHARED_SIZE: i64 = 510;
+
+    /// A database file that's locked the way SQLite locks it on unix, with POSIX advisory locks, so
+    /// that it can be shared with sqlite3 and other processes. Readers hold a read lock on one byte
+    /// of a range while writers need a write lock on all of it, and the pending and reserved bytes
+    /// let a writer queue up ahead of new readers.
+    ///
+    /// POSIX locks belong to the process, not the file handle, and closing any handle on the file
+    /// drops all of them, so a process should only open a database once while it's locked.
+    pub struct LockedFile {
+        file: File,
+        level: Mutex<LockLevel>,
+    }
+
+    impl LockedFile {
+        pub fn new(file: File) -> Self {
+            LockedFile {
+                file,
+                level: Mutex::new(LockLevel::None),
+            }
+        }
+
+        /// Tries to go from lock level `from` to `to`, returning false if another process's lock is
+        /// in the way. Like SQLite, a writer waiting for readers keeps its pending lock.
+        fn try_lock(&self, from: LockLevel, to: LockLevel) -> io::Result<bool> {
+            use fcntl::{set_lock, LockType::*};
+
+            match to {
+                LockLevel::None => Ok(true),
+                LockLevel::Shared => {
+                    // A pending writer's write lock on the pending byte keeps readers out
+                    if !set_lock(&self.file, Read, PENDING_BYTE, 1)? {
+                        return Ok(false);
+                    }
+                    let locked = set_lock(&self.file, Read, SHARED_FIRST, SHARED_SIZE)?;
+                    set_lock(&self.file, Unlock, PENDING_BYTE, 1)?;
+                    Ok(locked)
+                }
+                LockLevel::Reserved => set_lock(&self.file, Write, RESERVED_BYTE, 1),
+                LockLevel::Pending | LockLevel::Exclusive => {
+                    if from < LockLevel::Pending && !set_lock(&self.file, Write, PENDING_BYTE, 1)? {
+                        return Ok(false);
+                    }
+                    if to == LockLevel::Pending {
+                        return Ok(true);
+                    }
+                    set_lock(&self.file, Write, SHARED_FIRST, SHARED_SIZE)
+                }
+            }
+        }
+    }
+
+    impl PageSource for LockedFile {
+        fn size(&self) -> Result<u64> {
+            self.file.size()
+        }
+
+        fn read_at(&self, offset: u64, len: usize) -> Result<Cow<'_, [u8]>> {
+            self.file.read_at(offset, len)
+        }
+
+        fn write_at(&self, offset: u64, bytes: &[u8]) -> Result<()> {
+            self.file.write_at(offset, bytes)
+        }
+
+        fn set_len(&self, size: u64) -> Result<()> {
+            Ok(self.file.set_len(size)?)
+        }
+
+        fn sync(&self) -> Result<()> {
+            self.file.sync()
+        }
+
+        fn lock(&self, level: LockLevel, timeout: Duration) -> Result<()> {
+            let mut current = self.level.lock().unwrap_or_else(PoisonError::into_inner);
+            if level <= *current {
+                return Ok(());
+            }
+
+            let start = Instant::now();
+            let mut wait = Duration::from_millis(1);
+            loop {
+                if self.try_lock(*current, level)? {
+                    *current = level;
+                    return Ok(());
+                }
+                if level == LockLevel::Exclusive && *current < LockLevel::Pending {
+                    *current = LockLevel::Pending;
+                }
+
+                let waited = start.elapsed();
+                if waited >= timeout {
+                    return Err(DatabaseLockedError.into());
+                }
+                thread::sleep(wait.min(timeout - waited));
+                wait = (wait * 2).min(Duration::from_millis(100));
+            }
+        }
+
+        fn unlock(&self, level: LockLevel) -> Result<()> {
+            use fcntl::{set_lock, LockType::*};
+
+            let mut current = self.level.lock().unwrap_or_else(PoisonError::into_inner);
+            if level >= *current {
+                return Ok(());
+            }
+
+            match level {
+                LockLevel::None => {
+                    set_lock(&self.file, Unlock, PENDING_BYTE, 2 + SHARED_SIZE)?;
+                }
+                LockLevel::Shared => {
+                    if *current == LockLevel::Exclusive {
+                        set_lock(&self.file, Read, SHARED_FIRST, SHARED_SIZE)?;
+                    }
+                    set_lock(&self.file, Unlock, PENDING_BYTE, 2)?;
+                }
+                level => bail!("can't unlock to {level:?}, only to Shared or None"),
+            }
+            *current = level;
+
+            Ok(())
+        }
+    }
+
+    /// fcntl(F_SETLK), whose struct flock is laid out differently from one system to the next
+    #[cfg(any(
+        all(
+            any(target_os = "linux", target_os = "android"),
+            target_pointer_width = "64"
+        ),
+        target_os = "macos",
+        target_os = "ios"
+    ))]
+    mod fcntl {
+        use std::{
+            ffi::{c_int, c_short},
+            fs::File,
+            io,
+            os::fd::AsRawFd,
+        };
+
+        pub enum LockType {
+            Read,
+            Write,
+            Unlock,
+        }
+
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        mod sys {
+            use std::ffi::{c_int, c_short};
+
+            pub const F_SETLK: c_int = 6;
+            pub const F_RDLCK: c_short = 0;
+            pub const F_WRLCK: c_short = 1;
+            pub const F_UNLCK: c_short = 2;
+
+            #[repr(C)]
+            pub struct Flock {
+                pub l_type: c_short,
+                pub l_whence: c_short,
+                pub l_start: i64,
+                pub l_len: i64,
+                pub l_pid: c_int,
+            }
+        }
+
+        #[cfg(any(target_os = "macos", target_os = "ios"))]
+        mod sys {
+            use std::ffi::{c_int, c_short};
+
+            pub const F_SETLK: c_int = 8;
+            pub const F_RDLCK: c_short = 1;
+            pub const F_WRLCK: c_short = 3;
+            pub const F_UNLCK: c_short = 2;
+
+            #[repr(C)]
+            pub struct Flock {
+                pub l_start: i64,
+                pub l_len: i64,
+                pub l_pid: c_int,
+                pub l_type: c_short,
+                pub l_whence: c_short,
+            }
+        }
+
+        extern "C" {
+            fn fcntl(fd: c_int, cmd: c_int, ...) -> c_int;
+        }
+
+        /// Locks or unlocks `len` bytes from `start`, without waiting. Returns false if another
+        /// process holds a lock that conflicts.
+        pub fn set_lock(
+            file: &File,
+            lock_type: LockType,
+            start: i64,
+            len: i64,
+        ) -> io::Result<bool> {
+            let l_type: c_short = match lock_type {
+                LockType::Read => sys::F_RDLCK,
+                LockType::Write => sys::F_WRLCK,
+                LockType::Unlock => sys::F_UNLCK,
+            };
+            let flock = sys::Flock {
+                l_type,
+                // SEEK_SET
+                l_whence: 0,
+                l_start: start,
+                l_len: len,
+                l_pid: 0,
+            };
+
+            // SAFETY: F_SETLK reads the flock it's given, which outlives the call
+            if unsafe { fcntl(file.as_raw_fd(), sys::F_SETLK, &flock as *const sys::Flock) } == 0 {
+                return Ok(true);
+            }
+            let err = io::Error::last_os_error();
+            match err.kind() {
+                // EAGAIN or EACCES, depending on the system
+                io::ErrorKind::WouldBlock | io::ErrorKind::PermissionDenied => Ok(false),
+                _ => Err(err),
+            }
+        }
+    }
+
+    /// Elsewhere, files aren't locked
+    #[cfg(not(any(
+        all(
+            any(target_os = "linux", target_os = "android"),
+            target_pointer_width = "64"
+        ),
+        target_os = "macos",
+        target_os = "ios"
+    )))]
+    mod fcntl {
+        use std::{fs::File, io};
+
+        pub enum LockType {
+            Read,
+            Write,
+            Unlock,
+        }
+
+        pub fn set_lock(
+            _file: &File,
+            _lock_type: LockType,
+            _start: i64,
+            _len: i64,
+        ) -> io::Result<bool> {
+            Ok(true)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use std::{env, fs, process};
+
+        #[test]
+        fn test_lock_levels() {
+            let path = env::temp_dir().join(format!("sqlite-rust-lock-test-{}", process::id()));
+            fs::write(&path, b"").unwrap();
+            let file = LockedFile::new(File::options().read(true).write(true).open(&path).unwrap());
+            let level = || *file.level.lock().unwrap();
+
+            file.lock(LockLevel::Shared, Duration::ZERO).unwrap();
+            assert_eq!(level(), LockLevel::Shared);
+            file.lock(LockLevel::Exclusive, Duration::ZERO).unwrap();
+            assert_eq!(level(), LockLevel::Exclusive);
+            // Asking for a lower level than the one held leaves it as it is
+            file.lock(LockLevel::Reserved, Duration::ZERO).unwrap();
+            assert_eq!(level(), LockLevel::Exclusive);
+
+            file.unlock(LockLevel::Shared).unwrap();
+            assert_eq!(level(), LockLevel::Shared);
+            assert!(file.unlock(LockLevel::Reserved).is_ok());
+            file.unlock(LockLevel::None).unwrap();
+            assert_eq!(level(), LockLevel::None);
+
+            fs::remove_file(path).unwrap();
+        }
+    }
+}
@@ -1,373 +1,361 @@
-use anyhow::{bail, Result};
-use itertools::Itertools;
-use sqlite_starter_rust::{header::*, query_parser::*, record, types::*, varint};
+mod history;
+mod output;
+mod plugins;
+mod shell;
+mod signals;
+mod table;
+
+use anyhow::{anyhow, bail, Context, Result};
+use clap::{Parser, Subcommand};
+use history::History;
+use shell::{OutputMode, Shell};
+use sqlite_starter_rust::{
+    database::Database, interrupt::InterruptedError, lock::DatabaseLockedError, script, trace,
+    uri::OpenOptions,
+};
 use std::{
-    fs::File,
-    io::{prelude::*, Cursor, SeekFrom},
-    path::Path,
+    io::{self, IsTerminal, Write},
+    time::Duration,
 };
 
-struct Database {
-    page_size: u32,
-    page_count: u32,
-    database_file: File,
-}
-#[derive(Debug)]
-struct Page {
-    header: PageHeader,
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None, subcommand_value_name = "SUBCOMMAND")]
+struct Args {
+    /// Path to the SQLite database file, or a file: URI (e.g. file:data.db?immutable=1)
+    db_path: String,
+
+    #[command(subcommand)]
+    subcommand: Option<Command>,
+
+    /// A dot-command (.dbinfo, .tables, .pages, .page N) or SQL statements to run, separated by
+    /// semicolons. With "-", or without one or a subcommand, commands are read from stdin.
+    command: Option<String>,
+
+    /// Attach another database, as ATTACH 'FILE' AS NAME does, so that queries can read its
+    /// tables as NAME.table. May be given more than once.
+    #[arg(long, value_name = "NAME=FILE", value_parser = parse_attachment)]
+    attach: Vec<(String, String)>,
+
+    /// Run the dot-commands and SQL statements in this script first, as .read does
+    #[arg(long, value_name = "FILE")]
+    init: Option<String>,
+
+    /// Print the query plan before running the query
+    #[arg(long)]
+    explain: bool,
+
+    /// Open the database even if its schema can't be read, so that its pages can still be inspected
+    #[arg(long)]
+    degraded: bool,
+
+    /// Show wide values in full in the table and box modes, instead of truncating them
+    #[arg(long)]
+    full: bool,
+
+    /// Read the database through a memory map rather than with a system call per page (needs the
+    /// mmap feature)
+    #[arg(long)]
+    mmap: bool,
+
+    /// Sort rows for ORDER BY in memory only up to this many bytes (e.g. 64M or 1G), spilling the
+    /// rest to temporary files
+    #[arg(long, value_parser = parse_size)]
+    max_memory: Option<usize>,
+
+    /// Milliseconds to wait for other processes to unlock the database before failing with
+    /// "database is locked"
+    #[arg(long, default_value_t = 0)]
+    busy_timeout: u64,
+
+    /// Print what's read from the database and how queries are planned to stderr, as it happens
+    /// (needs the trace feature)
+    #[arg(long)]
+    verbose: bool,
 }
 
-#[allow(dead_code)] // TODO Remove
-#[derive(Debug)]
-struct Record {
-    row_id: usize,
-    serial_types: Vec<SerialType>,
-    serial_values: Vec<SerialValue>,
+// What to do with the database, as an alternative to giving a dot-command or SQL. A doc comment
+// here would become the description in --help.
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run SQL statements, separated by semicolons
+    Query {
+        sql: String,
+
+        /// How to print the rows
+        #[arg(long, value_enum, default_value = "list")]
+        format: OutputMode,
+
+        /// Leave out the column names, which are printed before the rows otherwise
+        #[arg(long)]
+        no_headers: bool,
+
+        /// Print at most this many rows of each query's result
+        #[arg(long)]
+        limit: Option<usize>,
+    },
+    /// List the tables and views, as .tables does
+    Tables {
+        /// Only list those whose names are LIKE this
+        pattern: Option<String>,
+    },
+    /// Show the page size and number of tables, as .dbinfo does
+    Dbinfo,
+    /// Write the database as SQL, as .dump does
+    Dump {
+        /// Only write the tables whose names are LIKE this
+        pattern: Option<String>,
+    },
+    /// Show the CREATE statements of the schema, as .schema does
+    Schema {
+        /// Only show those of the tables whose names are LIKE this
+        pattern: Option<String>,
+    },
+    /// Check that every page is used once and every row can be read, as .check does
+    Check,
 }
 
-#[derive(Debug)]
-struct TableInfo {
-    table_type: String,
-    name: String,
-    table_name: String,
-    root_page: u32,
-    sql: String,
+impl Command {
+    /// The dot-command or SQL to run
+    fn command(&self) -> String {
+        let with_pattern = |name: &str, pattern: &Option<String>| match pattern {
+            Some(pattern) => format!(".{name} {pattern}"),
+            None => format!(".{name}"),
+        };
+
+        match self {
+            Command::Query { sql, .. } => sql.clone(),
+            Command::Tables { pattern } => with_pattern("tables", pattern),
+            Command::Dbinfo => ".dbinfo".to_string(),
+            Command::Dump { pattern } => with_pattern("dump", pattern),
+            Command::Schema { pattern } => with_pattern("schema", pattern),
+            Command::Check => ".check".to_string(),
+        }
+    }
 }
 
-impl Database {
-    pub fn open(mut database_file: File) -> anyhow::Result<Self> {
-        let mut header = [0; 100];
-        database_file.read_exact(&mut header)?;
+fn main() -> Result<()> {
+    let args = Args::parse();
+    if args.verbose {
+        trace::set_subscriber(Box::new(|event| eprintln!("{event}")))?;
+    }
 
-        let mut page_size = u16::from_be_bytes([header[16], header[17]]) as u32;
+    let mut options = OpenOptions::parse(&args.db_path)?;
+    options.mmap = args.mmap;
+    options.max_memory = args.max_memory;
+    options.busy_timeout = Duration::from_millis(args.busy_timeout);
+    let command = match &args.subcommand {
+        Some(subcommand) => Some(subcommand.command()),
+        None => args.command,
+    };
+    if creates_database(&options, command.as_deref()) {
+        Database::create(&options.path)?;
+    }
+    let database = Database::open_with_options(options)?;
+    for anomaly in database.anomalies() {
+        eprintln!("warning: {anomaly}");
+    }
 
-        let reserved_end_of_page_space = u8::from_be_bytes([header[20]]) as u32;
-        if reserved_end_of_page_space > 0 {
-            bail!("Unhandled reserved_end_of_page_space: {reserved_end_of_page_space}");
+    let schema = match database.schema() {
+        Ok(schema) => schema,
+        Err(err) if args.degraded => {
+            eprintln!("warning: can't read the schema, continuing without it: {err}");
+            vec![]
         }
-
-        if page_size == 1 {
-            // If page_size is 1, this should be interpreted as 65,536
-            page_size = 65_536;
+        Err(err) if is_damaged(&err) => {
+            bail!("can't read the schema (use --degraded to open anyway): {err}")
         }
+        Err(err) => bail!("can't read the schema: {err}"),
+    };
 
-        let page_count = u32::from_be_bytes([header[28], header[29], header[30], header[31]]);
-
-        Ok(Database {
-            page_size,
-            page_count,
-            database_file,
-        })
+    if let Err(err) = signals::interrupt_on_ctrl_c(database.interrupt_handle()) {
+        eprintln!("warning: can't handle Ctrl-C: {err}");
     }
 
-    pub fn seek_to_page(&mut self, page_num: u32) -> anyhow::Result<Page> {
-        if page_num < 1 || page_num > self.page_count {
-            bail!("seek_to_page: page_num out of bounds: {page_num}");
-        }
-
-        let mut seek_offset = (page_num - 1) * self.page_size;
-
-        if page_num == 1 {
-            // Skip first 100 bytes of page 1 to account for the database header.
-            seek_offset += 100;
+    let mut shell = Shell::new(database, schema);
+    shell.explain = args.explain;
+    shell.full = args.full;
+    if let Some(Command::Query {
+        format,
+        no_headers,
+        limit,
+        ..
+    }) = args.subcommand
+    {
+        shell.mode = format;
+        shell.headers = Some(!no_headers);
+        shell.limit = limit;
+    }
+    if let Some(dir) = plugins::default_dir() {
+        if let Err(err) = plugins::load(&dir, &mut shell.dot_commands) {
+            eprintln!("warning: can't load plugins from {}: {err}", dir.display());
         }
-
-        self.database_file
-            .seek(SeekFrom::Start(seek_offset as u64))?;
-
-        let mut page_header_bytes = [0; 8];
-        self.database_file.read_exact(&mut page_header_bytes)?;
-        let header = PageHeader::parse(&page_header_bytes)?;
-
-        Ok(Page { header })
     }
-}
-
-impl Page {
-    pub fn fetch_cell_pointers<R: Read + std::io::Seek>(
-        &self,
-        reader: &mut R,
-    ) -> anyhow::Result<Vec<u16>> {
-        let cell_pointers = Self::build_cell_pointers(&self.header, reader)?;
 
-        Ok(cell_pointers)
+    for (name, filename) in &args.attach {
+        shell.attach(filename, name)?;
     }
 
-    fn build_cell_pointers<R: Read>(
-        page_header: &PageHeader,
-        reader: &mut R,
-    ) -> anyhow::Result<Vec<u16>> {
-        let mut cell_pointers = Vec::with_capacity(page_header.number_of_cells.into());
-        let mut cell_pointer_buffer = [0; 2];
-        for _ in 0..page_header.number_of_cells {
-            reader.read_exact(&mut cell_pointer_buffer)?;
-
-            cell_pointers.push(u16::from_be_bytes([
-                cell_pointer_buffer[0],
-                cell_pointer_buffer[1],
-            ]))
-        }
+    if let Some(path) = &args.init {
+        shell.run_script(path)?;
+    }
 
-        Ok(cell_pointers)
+    let result = match command.as_deref() {
+        // As with cat, "-" names stdin, so that queries can be piped in
+        Some("-") | None => repl(&mut shell),
+        Some(command) => shell.run_command(command),
+    };
+    // Whatever is reading the output, like head, has all it wants
+    match result {
+        Err(err) if shell::is_broken_pipe(&err) => Ok(()),
+        result => result,
     }
 }
 
-use std::path::PathBuf;
-
-use clap::{command, Parser};
-
-#[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
-struct Args {
-    /// Name of the person to greet
-    // #[arg(short, long)]
-    db_path: PathBuf,
-
-    /// Number of times to greet
-    // #[arg(short, long, default_value_t = 1)]
-    command: String,
+/// Whether to make a new database for `command`, as sqlite3 does: when there's no file to open,
+/// and the command is a CREATE or INSERT that would write one
+fn creates_database(options: &OpenOptions, command: Option<&str>) -> bool {
+    let Some(first_word) = command.and_then(|command| command.split_whitespace().next()) else {
+        return false;
+    };
+
+    !options.path.exists()
+        && !options.read_only
+        && !options.immutable
+        && ["CREATE", "INSERT"]
+            .iter()
+            .any(|keyword| first_word.eq_ignore_ascii_case(keyword))
 }
 
-// TODO:
-// * Detect which pages are root pages based on master table.
-fn main() -> Result<()> {
-    let args = Args::parse();
-
-    // TODO: Switch to clap
-    // let args = std::env::args().collect::<Vec<_>>();
-    // match args.len() {
-    //     0 | 1 => bail!("Missing <database path> and <command>"),
-    //     2 => bail!("Missing <command>"),
-    //     _ => {}
-    // }
-
-    let db_file_path = Path::new(&args.db_path);
-    let db_file = File::open(db_file_path)?;
-    let mut database = Database::open(db_file)?;
-
-    // Parse command and act accordingly
-    let command = args.command;
-
-    let (page_size, records) = read_records(&mut database, 1)?;
-    let master_tables = populate_master_tables(&records)?;
-
-    match command.as_ref() {
-        ".dbinfo" => {
-            println!("database page size: {}", page_size);
-            println!("number of tables: {}", records.len());
-        }
-        ".tables" => {
-            let table_names = master_tables
-                .iter()
-                .map(|t| t.name.clone())
-                .collect_vec()
-                .join(" ");
-
-            println!("{table_names}");
-        }
-        _ => {
-            // Parse query
-            // Plan lookups
-            // Execute
-            // Aggregate
-            // Present
-
-            let raw_query = command;
-
-            match parse_query(&raw_query) {
-                Ok((_, query)) => {
-                    println!("query: {:?}", query);
-
-                    let table_to_query = master_tables.iter().find(|t| t.name == query.from_table);
-
-                    if let Some(table) = table_to_query {
-                        println!("table to query is: {:?}", table);
-
-                        let (page_size, records) = read_records(&mut database, table.root_page)?;
-
-                        println!("queried records count: {}", records.len());
-                    } else {
-                        panic!("query.from_table not found ({})", query.from_table);
-                    }
-                    // query.from_table
-                    // for table in &master_tables {
-                    //     if table.name == query.from_table {
-
-                    //     }
-                    // }
-
-                    println!("master_tables: {:?}", master_tables);
-                }
-
-                Err(err) => {
-                    println!("Error: {:?}", err);
-                }
-            }
-        }
-    }
-
-    Ok(())
+/// Whether reading the schema failed because the file is damaged, which --degraded gets past,
+/// rather than because it's locked, the read was interrupted or the file couldn't be read at all
+fn is_damaged(err: &anyhow::Error) -> bool {
+    !(err.is::<DatabaseLockedError>()
+        || err.is::<InterruptedError>()
+        || err.chain().any(|cause| cause.is::<io::Error>()))
 }
 
-fn read_records(database: &mut Database, page_number: u32) -> anyhow::Result<(u32, Vec<Record>)> {
-    let page = database.seek_to_page(page_number)?;
-    let cell_pointers = page.fetch_cell_pointers(&mut database.database_file)?;
-
-    let payloads = build_payloads(
-        database.page_size,
-        &page,
-        &cell_pointers,
-        &mut database.database_file,
-    )?;
-
-    // TODO: Read other pages
-    // for page_i in 2..=database.page_count {
-    //     let next_page = database.seek_to_page(page_i)?;
-    //     println!("page #{page_i}: {:?}", next_page);
-    // }
-
-    match page.header.page_type {
-        sqlite_starter_rust::header::BTreePage::LeafTable => {
-            let records = build_records(payloads)?;
-
-            Ok((database.page_size, records))
+/// Parses an --attach argument, NAME=FILE, into the name and the filename
+fn parse_attachment(attachment: &str) -> Result<(String, String)> {
+    match attachment.split_once('=') {
+        Some((name, filename)) if !name.is_empty() && !filename.is_empty() => {
+            Ok((name.to_string(), filename.to_string()))
         }
-        _ => todo!(
-            "handle other page types ({:?}) in read_records",
-            page.header.page_type
-        ),
-    }
-}
-
-fn extract_string(string_serial_value: &SerialValue) -> String {
-    match string_serial_value {
-        SerialValue::String(s) => return s.to_string(),
-        _ => panic!("Unexpected value in extract_string"),
+        _ => bail!("invalid attachment: {attachment} (expected NAME=FILE, like aux=other.db)"),
     }
 }
 
-fn extract_i8(string_serial_value: &SerialValue) -> i8 {
-    match string_serial_value {
-        SerialValue::Int8(i) => return *i,
-        _ => panic!("Unexpected value in extract_i8"),
-    }
+/// Parses a number of bytes, which may have a K, M or G suffix for KiB, MiB or GiB
+fn parse_size(size: &str) -> Result<usize> {
+    let invalid = || anyhow!("invalid size: {size} (expected bytes, like 65536, 64K, 64M or 1G)");
+    let digits_end = size
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(size.len());
+    let (number, suffix) = size.split_at(digits_end);
+    let multiplier: usize = match suffix.to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" => 1 << 10,
+        "M" | "MB" => 1 << 20,
+        "G" | "GB" => 1 << 30,
+        _ => return Err(invalid()),
+    };
+
+    number
+        .parse::<usize>()
+        .ok()
+        .and_then(|number| number.checked_mul(multiplier))
+        .ok_or_else(invalid)
 }
 
-fn populate_master_tables(records: &Vec<Record>) -> anyhow::Result<Vec<TableInfo>> {
-    let mut result = vec![];
-
-    for record in records {
-        let table_type = extract_string(&record.serial_values[0]);
-        let name = extract_string(&record.serial_values[1]);
-        let table_name = extract_string(&record.serial_values[2]); // Awkward SQLite naming, I know...
-        let root_page = extract_i8(&record.serial_values[3]);
-        let sql = extract_string(&record.serial_values[4]);
-
-        result.push(TableInfo {
-            table_type,
-            name,
-            table_name,
-            root_page: root_page as u32,
-            sql,
-        });
+/// Reads commands from stdin until it's closed or .quit is entered. Errors are reported without
+/// stopping at the prompt, but a script piped in stops at one. A dot-command is a line of its
+/// own, while SQL statements end with a semicolon and can span lines or share one.
+fn repl(shell: &mut Shell) -> Result<()> {
+    let stdin = io::stdin();
+    let interactive = stdin.is_terminal();
+
+    // Like sqlite3, only commands typed at the prompt are kept in the history
+    if interactive {
+        if let Some(path) = History::default_path() {
+            match History::load(path) {
+                Ok(history) => shell.history = history,
+                Err(err) => eprintln!("warning: can't read the history file: {err}"),
+            }
+        }
     }
 
-    Ok(result)
-}
-
-fn build_records(payloads: Vec<(usize, usize, Vec<u8>)>) -> anyhow::Result<Vec<Record>> {
-    let mut records = vec![];
-
-    for (_payload_size, row_id, payload_bytes) in payloads {
-        let mut payload_cursor = Cursor::new(payload_bytes);
-
-        let mut serial_types: Vec<SerialType> = vec![];
-        let mut serial_values = vec![];
-
-        let (record_header_byte_count, bytes_read_3) =
-            varint::parse_varint_from_reader(&mut payload_cursor);
-
-        let mut record_header_bytes_remaining = record_header_byte_count - bytes_read_3;
-
-        loop {
-            let (column_serial_type, col_type_bytes_read) =
-                varint::parse_varint_from_reader(&mut payload_cursor);
-
-            let serial_type = SerialType::from(column_serial_type as u64);
-
-            serial_types.push(serial_type);
-
-            record_header_bytes_remaining -= col_type_bytes_read;
+    // The lines of SQL entered so far that don't yet end a statement, and the line it started on
+    let mut sql = String::new();
+    let mut sql_line = 0;
+    let mut line_number = 0;
+    let mut statement_number = 0;
+    let statement_context =
+        |number: usize, line: usize| format!("statement {number} of stdin, on line {line}");
+    loop {
+        if interactive {
+            print!(
+                "{}",
+                if sql.is_empty() {
+                    "sqlite> "
+                } else {
+                    "   ...> "
+                }
+            );
+            io::stdout().flush()?;
+        }
 
-            if record_header_bytes_remaining == 0 {
-                break;
+        let mut line = String::new();
+        if stdin.read_line(&mut line)? == 0 {
+            // Whatever is left is run as it is, without a semicolon to end it
+            if !sql.trim().is_empty() {
+                statement_number += 1;
+                run(shell, &sql, interactive)
+                    .with_context(|| statement_context(statement_number, sql_line))?;
             }
+            return Ok(());
         }
-
-        for column_serial_type in &serial_types {
-            let serial_value = SerialValue::parse(&mut payload_cursor, column_serial_type)?;
-
-            serial_values.push(serial_value);
+        line_number += 1;
+
+        if sql.is_empty() {
+            match line.trim() {
+                "" => continue,
+                ".quit" | ".exit" => return Ok(()),
+                command if command.starts_with('.') => {
+                    run(shell, command, interactive)
+                        .with_context(|| format!("line {line_number} of stdin"))?;
+                    continue;
+                }
+                _ => sql_line = line_number,
+            }
         }
 
-        records.push(Record {
-            row_id,
-            serial_types,
-            serial_values,
-        });
+        sql.push_str(&line);
+        if script::is_complete(&sql) {
+            if !sql.trim().is_empty() {
+                statement_number += 1;
+                run(shell, &sql, interactive)
+                    .with_context(|| statement_context(statement_number, sql_line))?;
+            }
+            sql.clear();
+        }
     }
-
-    Ok(records)
 }
 
-fn build_payloads<R: Read + std::io::Seek>(
-    database_page_size: u32,
-    page: &Page,
-    cell_pointers: &Vec<u16>,
-    reader: &mut R,
-) -> anyhow::Result<Vec<(usize, usize, Vec<u8>)>> {
-    match page.header.page_type {
-        sqlite_starter_rust::header::BTreePage::LeafTable => {
-            let mut payloads = vec![];
-
-            for offset in cell_pointers {
-                reader.seek(SeekFrom::Start(*offset as u64))?;
-
-                let (payload_size, _bytes_read_1) = varint::parse_varint_from_reader(reader);
-                let (row_id, _bytes_read_2) = varint::parse_varint_from_reader(reader);
-
-                let mut payload_bytes = vec![0; payload_size];
-                reader.read_exact(&mut payload_bytes)?;
-
-                // Calculate page content overflow
-                let u = database_page_size;
-                let p = payload_size as u32;
-
-                let x = u - 35;
-                let m = ((u - 12) * 32 / 255) - 23;
-                let _k = m + ((p - m) % (u - 4));
-
-                // If P<=X then all P bytes of payload are stored directly on the btree page without overflow.
-                // If P>X and K<=X then the first K bytes of P are stored on the btree page and the remaining P-K bytes are stored on overflow pages.
-                // If P>X and K>X then the first M bytes of P are stored on the btree page and the remaining P-M bytes are stored on overflow pages.
-                //
-                //   The overflow thresholds are designed to give a minimum fanout of 4 for index b-trees and to make sure enough of the payload is on
-                // the b-tree page that the record header can usually be accessed without consulting an overflow page. In hindsight, the designer of
-                // the SQLite b-tree logic realized that these thresholds could have been made much simpler. However, the computations cannot be changed
-                // without resulting in an incompatible file format. And the current computations work well, even if they are a little complex.
-
-                if p > x {
-                    bail!("Unhandled overflow");
-                }
-
-                payloads.push((payload_size, row_id, payload_bytes));
-            }
+/// Runs a command read from stdin. At the prompt an error is reported without stopping, unless
+/// it's that the output has gone, while from a script it's returned to stop there
+fn run(shell: &mut Shell, command: &str, interactive: bool) -> Result<()> {
+    let command = command.trim();
+    if interactive {
+        if let Err(err) = shell.remember(command) {
+            eprintln!("warning: can't save history: {err}");
+        }
+    }
 
-            Ok(payloads)
+    match shell.run_command(command) {
+        Err(err) if shell::is_broken_pipe(&err) || !interactive => Err(err),
+        Err(err) => {
+            eprintln!("Error: {err:#}");
+            Ok(())
         }
-        _ => todo!(
-            "handle other page types ({:?}) in build_payloads",
-            page.header.page_type
-        ),
+        Ok(()) => Ok(()),
     }
 }
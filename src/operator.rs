@@ -0,0 +1,228 @@
+use crate::{
+    collation::Collation,
+    functions::quote,
+    planner::{QueryPlan, RowOrder},
+    query_parser::{OrderingTerm, Query, ResultColumn},
+    row_layout::RowLayout,
+    value::Value,
+    vm::Program,
+};
+use itertools::Itertools;
+use std::{fmt, sync::Arc};
+
+/// A step of a query's execution. A query runs as a tree of them, built from its plan: each
+/// operator pulls the rows it needs from its input as its own rows are pulled from it.
+///
+/// The operators at the bottom find table records; Filter passes on those that meet the WHERE
+/// conditions, and Project turns them into result rows, which the operators above it work on.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operator {
+    /// Every row of a table b-tree, in rowid order
+    Scan {
+        table_name: String,
+        root_page: u32,
+        descending: bool,
+        layout: Option<RowLayout>,
+    },
+    /// The row with a given rowid, if there is one
+    RowidLookup {
+        table_name: String,
+        root_page: u32,
+        row_id: i64,
+        layout: Option<RowLayout>,
+    },
+    /// The rows whose index entries match any of `keys`, read from the table unless the index
+    /// covers the columns the query reads
+    IndexSeek {
+        index_name: String,
+        index_root_page: u32,
+        table_root_page: u32,
+        keys: Vec<Value>,
+        /// The collation of the index's first column, or None for BINARY
+        collation: Option<Arc<Collation>>,
+        /// Whether the index's first column is sorted DESC
+        descending: bool,
+        /// When the index covers the query, the position in the table of each of its columns
+        covering: Option<Vec<usize>>,
+        /// The number of columns the table has
+        width: usize,
+        /// Rowid order either way, or else index order
+        order: RowOrder,
+        /// How the table's records are laid out as rows, when they don't simply hold each column
+        /// in turn
+        layout: Option<RowLayout>,
+    },
+    /// The rows of the FROM clause's subquery, numbered from 1 as they come to stand in for
+    /// rowids
+    Subquery {
+        table_name: String,
+        plan: Box<QueryPlan>,
+        query: Box<Query>,
+    },
+    /// The records for which the program, which checks the WHERE conditions, produces a row
+    Filter {
+        input: Box<Operator>,
+        program: Program,
+    },
+    /// The result row the program produces from each record
+    Project {
+        input: Box<Operator>,
+        program: Program,
+    },
+    /// The rows in ORDER BY order. Each has its sort keys after its `result_width` result
+    /// columns, and comes out without them.
+    Sort {
+        input: Box<Operator>,
+        order_by: Vec<OrderingTerm>,
+        collations: Vec<Option<Arc<Collation>>>,
+        result_width: usize,
+    },
+    /// The rows, less any that are duplicates of one before them, comparing each column's values
+    /// by its collation
+    Distinct {
+        input: Box<Operator>,
+        collations: Vec<Option<Arc<Collation>>>,
+    },
+    /// A single row, with the aggregates of the SELECT list computed over every row, and the
+    /// values of its bare expressions taken from the last
+    Aggregate {
+        input: Box<Operator>,
+        selection_list: Vec<ResultColumn>,
+    },
+    /// The first `limit` rows
+    Limit { input: Box<Operator>, limit: usize },
+}
+
+impl Operator {
+    /// The operator this one pulls rows from, if it isn't one that finds them
+    pub fn input(&self) -> Option<&Operator> {
+        match self {
+            Operator::Scan { .. }
+            | Operator::RowidLookup { .. }
+            | Operator::IndexSeek { .. }
+            | Operator::Subquery { .. } => None,
+            Operator::Filter { input, .. }
+            | Operator::Project { input, .. }
+            | Operator::Sort { input, .. }
+            | Operator::Distinct { input, .. }
+            | Operator::Aggregate { input, .. }
+            | Operator::Limit { input, .. } => Some(input),
+        }
+    }
+
+    /// The programs of the operators in the tree, from the top down, with the name of the
+    /// operator each belongs to
+    pub fn programs(&self) -> Vec<(&'static str, &Program)> {
+        let mut programs = vec![];
+        let mut operator = Some(self);
+        while let Some(current) = operator {
+            match current {
+                Operator::Filter { program, .. } => programs.push(("FILTER", program)),
+                Operator::Project { program, .. } => programs.push(("PROJECT", program)),
+                _ => {}
+            }
+            operator = current.input();
+        }
+
+        programs
+    }
+
+    /// The operator's line in the tree EXPLAIN draws
+    pub fn label(&self) -> String {
+        match self {
+            Operator::Scan {
+                table_name,
+                descending,
+                ..
+            } => format!(
+                "SCAN {table_name}{}",
+                if *descending { " DESCENDING" } else { "" }
+            ),
+            Operator::RowidLookup {
+                table_name, row_id, ..
+            } => format!("SEEK {table_name} ROWID {row_id}"),
+            Operator::IndexSeek {
+                index_name,
+                keys,
+                covering,
+                ..
+            } => format!(
+                "SEEK {}INDEX {index_name} ({})",
+                if covering.is_some() { "COVERING " } else { "" },
+                keys.iter().map(quote).join(", ")
+            ),
+            Operator::Subquery { table_name, .. } => format!("SUBQUERY {table_name}"),
+            Operator::Filter { program, .. } => {
+                format!("FILTER ({} opcodes)", program.opcodes.len())
+            }
+            Operator::Project { program, .. } => {
+                format!("PROJECT ({} opcodes)", program.opcodes.len())
+            }
+            Operator::Sort { order_by, .. } => format!("SORT BY {}", order_by.iter().join(", ")),
+            Operator::Distinct { .. } => "DISTINCT".to_string(),
+            Operator::Aggregate { .. } => "AGGREGATE".to_string(),
+            Operator::Limit { limit, .. } => format!("LIMIT {limit}"),
+        }
+    }
+}
+
+/// Draws the tree with the operator producing the result at the top, and each operator's input
+/// below it
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.label())?;
+        let mut indent = String::new();
+        let mut operator = self.input();
+        while let Some(current) = operator {
+            write!(f, "\n{indent}`--{}", current.label())?;
+            indent.push_str("   ");
+            operator = current.input();
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let program = |opcode_count| Program {
+            opcodes: vec![crate::vm::Opcode::Halt; opcode_count],
+            register_count: 0,
+        };
+        let operator = Operator::Limit {
+            input: Box::new(Operator::Project {
+                input: Box::new(Operator::Filter {
+                    input: Box::new(Operator::Scan {
+                        table_name: "apples".to_string(),
+                        root_page: 2,
+                        descending: false,
+                        layout: None,
+                    }),
+                    program: program(4),
+                }),
+                program: program(3),
+            }),
+            limit: 10,
+        };
+
+        assert_eq!(
+            operator.to_string(),
+            "LIMIT 10\n\
+             `--PROJECT (3 opcodes)\n   \
+                `--FILTER (4 opcodes)\n      \
+                   `--SCAN apples"
+        );
+        assert_eq!(
+            operator
+                .programs()
+                .iter()
+                .map(|(name, program)| (*name, program.opcodes.len()))
+                .collect_vec(),
+            [("PROJECT", 3), ("FILTER", 4)]
+        );
+    }
+}
@@ -0,0 +1,249 @@
+use crate::table::Table;
+use itertools::Itertools;
+use sqlite_starter_rust::value::Value;
+use std::io::{self, Write};
+
+/// Writes query results in one of the .mode formats: the column headers first, then each row as
+/// it's produced, then whatever closes the output off. Formats that need every row before they
+/// can write any, like tables sizing their columns, hold on to rows until finish.
+pub trait OutputFormatter {
+    fn write_header(&mut self, output: &mut dyn Write, headers: &[String]) -> io::Result<()>;
+
+    fn write_row(&mut self, output: &mut dyn Write, row: &[Value]) -> io::Result<()>;
+
+    fn finish(&mut self, output: &mut dyn Write) -> io::Result<()> {
+        let _ = output;
+        Ok(())
+    }
+}
+
+/// Values joined by a separator, as in .mode list and .mode tabs, with a line of headers first
+/// if .headers is on
+pub struct Separated<'a> {
+    pub separator: &'a str,
+    pub headers: bool,
+}
+
+impl OutputFormatter for Separated<'_> {
+    fn write_header(&mut self, output: &mut dyn Write, headers: &[String]) -> io::Result<()> {
+        if self.headers {
+            writeln!(output, "{}", headers.join(self.separator))?;
+        }
+
+        Ok(())
+    }
+
+    fn write_row(&mut self, output: &mut dyn Write, row: &[Value]) -> io::Result<()> {
+        writeln!(output, "{}", row.iter().join(self.separator))
+    }
+}
+
+/// The rows of an HTML table, as in .mode html: a row of headers, then a row per result, with
+/// values escaped so they're never taken for markup. Like SQLite, it leaves out the <TABLE> tags
+/// around the rows, so that they can be added to a table that's already there.
+pub struct Html;
+
+impl Html {
+    fn write_cells<'a>(
+        output: &mut dyn Write,
+        tag: &str,
+        cells: impl Iterator<Item = &'a str>,
+    ) -> io::Result<()> {
+        write!(output, "<TR>")?;
+        for cell in cells {
+            writeln!(output, "<{tag}>{}</{tag}>", escape_html(cell))?;
+        }
+        writeln!(output, "</TR>")
+    }
+}
+
+impl OutputFormatter for Html {
+    fn write_header(&mut self, output: &mut dyn Write, headers: &[String]) -> io::Result<()> {
+        Html::write_cells(output, "TH", headers.iter().map(String::as_str))
+    }
+
+    fn write_row(&mut self, output: &mut dyn Write, row: &[Value]) -> io::Result<()> {
+        let values = row.iter().map(Value::to_string).collect_vec();
+        Html::write_cells(output, "TD", values.iter().map(String::as_str))
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '&' => escaped.push_str("&amp;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// A table drawn around the rows, as in .mode table, box and markdown. Column widths depend on
+/// every value, so nothing is written until finish.
+pub struct Tabulated<'a> {
+    table: Table<'a>,
+    headers: Vec<String>,
+    rows: Vec<Vec<Value>>,
+}
+
+impl<'a> Tabulated<'a> {
+    pub fn new(table: Table<'a>) -> Self {
+        Tabulated {
+            table,
+            headers: vec![],
+            rows: vec![],
+        }
+    }
+}
+
+impl OutputFormatter for Tabulated<'_> {
+    fn write_header(&mut self, _output: &mut dyn Write, headers: &[String]) -> io::Result<()> {
+        self.headers = headers.to_vec();
+        Ok(())
+    }
+
+    fn write_row(&mut self, _output: &mut dyn Write, row: &[Value]) -> io::Result<()> {
+        self.rows.push(row.to_vec());
+        Ok(())
+    }
+
+    fn finish(&mut self, output: &mut dyn Write) -> io::Result<()> {
+        self.table.write(output, &self.headers, &self.rows)
+    }
+}
+
+/// Writes names in columns across the screen, the way the sqlite3 shell's .tables does: each
+/// padded to the longest, filling each column before the next, with as many columns as fit in
+/// 80 characters
+pub fn write_in_columns(output: &mut dyn Write, names: &[String]) -> io::Result<()> {
+    let width = names
+        .iter()
+        .map(|name| name.chars().count())
+        .max()
+        .unwrap_or_default();
+    let columns = (80 / (width + 2)).max(1);
+    let rows = names.len().div_ceil(columns);
+
+    for row in 0..rows {
+        let line = names
+            .iter()
+            .skip(row)
+            .step_by(rows)
+            .map(|name| format!("{name:<width$}"))
+            .join("  ");
+        writeln!(output, "{line}")?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::table::Border;
+
+    fn render(
+        formatter: &mut dyn OutputFormatter,
+        headers: &[&str],
+        rows: &[Vec<Value>],
+    ) -> String {
+        let headers: Vec<String> = headers.iter().map(|h| h.to_string()).collect();
+        let mut output = Vec::new();
+        formatter.write_header(&mut output, &headers).unwrap();
+        for row in rows {
+            formatter.write_row(&mut output, row).unwrap();
+        }
+        formatter.finish(&mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    fn rows() -> Vec<Vec<Value>> {
+        vec![
+            vec![Value::Integer(1), Value::Text("Granny Smith".to_string())],
+            vec![
+                Value::Integer(2),
+                Value::Text("<b>Fuji</b> & 'co'".to_string()),
+            ],
+        ]
+    }
+
+    #[test]
+    fn test_write_in_columns() {
+        let write = |names: &[&str]| {
+            let names: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+            let mut output = Vec::new();
+            write_in_columns(&mut output, &names).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        assert_eq!(
+            write(&["agg", "apples", "oranges"]),
+            "agg      apples   oranges\n"
+        );
+        // 5 columns of 14 fit, so 12 names make 3 rows, filled a column at a time
+        let names: Vec<String> = (1..=12).map(|n| format!("t_{}", "0".repeat(n))).collect();
+        let names: Vec<&str> = names.iter().map(String::as_str).collect();
+        let output = write(&names);
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            lines[0],
+            "t_0             t_0000          t_0000000       t_0000000000  "
+        );
+        assert_eq!(write(&[]), "");
+    }
+
+    #[test]
+    fn test_html() {
+        assert_eq!(
+            render(&mut Html, &["id", "name"], &rows()),
+            "<TR><TH>id</TH>\n<TH>name</TH>\n</TR>\n\
+             <TR><TD>1</TD>\n<TD>Granny Smith</TD>\n</TR>\n\
+             <TR><TD>2</TD>\n<TD>&lt;b&gt;Fuji&lt;/b&gt; &amp; &#39;co&#39;</TD>\n</TR>\n"
+        );
+    }
+
+    #[test]
+    fn test_markdown() {
+        let mut rows = rows();
+        rows[1][1] = Value::Text("Fuji | Gala".to_string());
+        let mut formatter = Tabulated::new(Table {
+            border: Border::Markdown,
+            widths: &[],
+            full: false,
+            headers: true,
+        });
+
+        assert_eq!(
+            render(&mut formatter, &["id", "name"], &rows),
+            "| id |     name     |\n\
+             |----|--------------|\n\
+             | 1  | Granny Smith |\n\
+             | 2  | Fuji \\| Gala |\n"
+        );
+    }
+
+    #[test]
+    fn test_separated() {
+        let mut formatter = Separated {
+            separator: "|",
+            headers: false,
+        };
+        assert_eq!(
+            render(&mut formatter, &["id", "name"], &rows()[..1]),
+            "1|Granny Smith\n"
+        );
+
+        formatter.headers = true;
+        assert_eq!(
+            render(&mut formatter, &["id", "name"], &rows()[..1]),
+            "id|name\n1|Granny Smith\n"
+        );
+    }
+}
@@ -0,0 +1,145 @@
+use crate::header::BTreePage;
+use anyhow::{anyhow, bail, Result};
+use std::io::{Read, Seek, SeekFrom};
+
+/// How a cell's payload is split between the b-tree page and a chain of overflow pages
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PayloadSplit {
+    /// Bytes stored in the cell itself
+    pub local: usize,
+    /// Bytes stored on overflow pages
+    pub overflow: usize,
+}
+
+/// Works out how much of a `payload_size` byte payload is stored on a page of type `page_type`,
+/// with `usable_size` bytes per page, as described here:
+/// [cell payload overflow pages](https://www.sqlite.org/fileformat.html#cell_payload_overflow_pages)
+///
+/// Sizes a well-formed database can't have, like those of a corrupt header or cell, are errors
+/// rather than overflowing the arithmetic.
+pub fn thresholds(
+    usable_size: u32,
+    payload_size: u64,
+    page_type: BTreePage,
+) -> Result<PayloadSplit> {
+    if !(480..=65_536).contains(&usable_size) {
+        bail!("invalid usable page size: {usable_size}");
+    }
+    let u = usable_size as u64;
+    let p = payload_size;
+
+    // X, the most that's kept on the page. Table leaves can hold more of a payload than index
+    // pages, which have to fit at least four cells.
+    let max_local = match page_type {
+        BTreePage::LeafTable => u - 35,
+        BTreePage::LeafIndex | BTreePage::InteriorIndex => (u - 12) * 64 / 255 - 23,
+        BTreePage::InteriorTable => bail!("interior table cells have no payload"),
+    };
+    // M, the least that's kept on the page once the payload spills
+    let min_local = (u - 12) * 32 / 255 - 23;
+
+    let local = if p <= max_local {
+        p
+    } else {
+        // K: as much as makes the overflow fill its last page exactly, if that fits on the page
+        let k = min_local + (p - min_local) % (u - 4);
+        if k <= max_local {
+            k
+        } else {
+            min_local
+        }
+    };
+
+    let to_usize =
+        |n: u64| usize::try_from(n).map_err(|_| anyhow!("payload too large: {payload_size} bytes"));
+    Ok(PayloadSplit {
+        local: to_usize(local)?,
+        overflow: to_usize(p - local)?,
+    })
+}
+
+/// Reads the `len` bytes of a payload that didn't fit in its cell from the chain of overflow pages
+/// starting at `first_page`, adding them to the end of `payload`. Each overflow page starts with
+/// the number of the next, or 0 on the last one, followed by as much of the payload as fits.
+pub fn read_overflow<R: Read + Seek>(
+    reader: &mut R,
+    page_size: u32,
+    first_page: u32,
+    len: usize,
+    payload: &mut Vec<u8>,
+) -> Result<()> {
+    let mut page_num = first_page;
+    let mut remaining = len;
+    // Each page adds to the payload, so even a chain that loops back on itself ends
+    while remaining > 0 {
+        if page_num == 0 {
+            bail!("malformed overflow chain: it ends {remaining} bytes short of its payload");
+        }
+        reader.seek(SeekFrom::Start((page_num as u64 - 1) * page_size as u64))?;
+        let mut next_page = [0; 4];
+        reader.read_exact(&mut next_page)?;
+
+        let count = remaining.min(page_size as usize - 4);
+        let start = payload.len();
+        payload.resize(start + count, 0);
+        reader.read_exact(&mut payload[start..])?;
+        remaining -= count;
+        page_num = u32::from_be_bytes(next_page);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(local: usize, overflow: usize) -> PayloadSplit {
+        PayloadSplit { local, overflow }
+    }
+
+    #[test]
+    fn test_thresholds() {
+        // With 4096 byte pages, X is 4061 for table leaves and 1002 for index pages, and M is 489
+        assert_eq!(
+            thresholds(4096, 4061, BTreePage::LeafTable).unwrap(),
+            split(4061, 0)
+        );
+        assert_eq!(
+            thresholds(4096, 5000, BTreePage::LeafTable).unwrap(),
+            split(908, 4092)
+        );
+        assert_eq!(
+            thresholds(4096, 8181, BTreePage::LeafTable).unwrap(),
+            split(489, 7692)
+        );
+        assert_eq!(
+            thresholds(4096, 1002, BTreePage::LeafIndex).unwrap(),
+            split(1002, 0)
+        );
+        assert_eq!(
+            thresholds(4096, 1003, BTreePage::InteriorIndex).unwrap(),
+            split(489, 514)
+        );
+
+        // With 512 byte pages, X is 102 for index pages
+        assert_eq!(
+            thresholds(512, 130, BTreePage::LeafIndex).unwrap(),
+            split(39, 91)
+        );
+        assert_eq!(
+            thresholds(65_536, u32::MAX as u64, BTreePage::LeafTable)
+                .unwrap()
+                .overflow,
+            u32::MAX as usize - 8199
+        );
+    }
+
+    #[test]
+    fn test_thresholds_invalid() {
+        assert!(thresholds(0, 10, BTreePage::LeafTable).is_err());
+        assert!(thresholds(100, 10, BTreePage::LeafIndex).is_err());
+        assert!(thresholds(70_000, 10, BTreePage::LeafTable).is_err());
+        assert!(thresholds(4096, 10, BTreePage::InteriorTable).is_err());
+    }
+}
@@ -0,0 +1,498 @@
+use crate::{lock::LockLevel, trace_event};
+use anyhow::{bail, Result};
+#[cfg(feature = "fs")]
+use std::fs::File;
+use std::{
+    borrow::Cow,
+    io::{self, prelude::*, Cursor, SeekFrom},
+    sync::{Mutex, PoisonError},
+    time::Duration,
+};
+
+/// Where a database's bytes come from, and go to. Reads and writes are positioned, so a source
+/// has no file position that concurrent readers could fight over: one source can serve any
+/// number of threads, each reading its own pages. A memory map can answer read_at with a slice
+/// of the map instead of a copy.
+pub trait PageSource: Send + Sync {
+    /// The size of the database in bytes
+    fn size(&self) -> Result<u64>;
+
+    /// The `len` bytes starting at `offset`
+    fn read_at(&self, offset: u64, len: usize) -> Result<Cow<'_, [u8]>>;
+
+    /// Writes `bytes` starting at `offset`, growing the database if they go past its end
+    fn write_at(&self, offset: u64, bytes: &[u8]) -> Result<()> {
+        let _ = (offset, bytes);
+        bail!("attempt to write a readonly database")
+    }
+
+    /// Cuts the database off at `size` bytes, or grows it with zeros to that size
+    fn set_len(&self, size: u64) -> Result<()> {
+        let _ = size;
+        bail!("attempt to write a readonly database")
+    }
+
+    /// Makes sure everything written so far has reached storage
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Raises the lock held on the database to `level`, if it's lower, waiting up to `timeout`
+    /// for other processes' locks to get out of the way. Sources no other process can get at
+    /// don't need locking.
+    fn lock(&self, level: LockLevel, timeout: Duration) -> Result<()> {
+        let _ = (level, timeout);
+        Ok(())
+    }
+
+    /// Lowers the lock held on the database to `level`, Shared or None
+    fn unlock(&self, level: LockLevel) -> Result<()> {
+        let _ = level;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "fs")]
+impl PageSource for File {
+    fn size(&self) -> Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+
+    fn read_at(&self, offset: u64, len: usize) -> Result<Cow<'_, [u8]>> {
+        let mut bytes = vec![0; len];
+        file_read_exact_at(self, &mut bytes, offset)?;
+
+        Ok(Cow::Owned(bytes))
+    }
+
+    fn write_at(&self, offset: u64, bytes: &[u8]) -> Result<()> {
+        Ok(file_write_all_at(self, bytes, offset)?)
+    }
+
+    fn set_len(&self, size: u64) -> Result<()> {
+        Ok(File::set_len(self, size)?)
+    }
+
+    fn sync(&self) -> Result<()> {
+        Ok(self.sync_all()?)
+    }
+}
+
+#[cfg(all(feature = "fs", unix))]
+fn file_read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)
+}
+
+#[cfg(all(feature = "fs", unix))]
+fn file_write_all_at(file: &File, buf: &[u8], offset: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::write_all_at(file, buf, offset)
+}
+
+// Windows' positioned reads and writes move the file position as well, which nothing here relies
+// on, and may be short, so they're retried until they're done
+#[cfg(all(feature = "fs", windows))]
+fn file_read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset)? {
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            n => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(all(feature = "fs", windows))]
+fn file_write_all_at(file: &File, mut buf: &[u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_write(buf, offset)? {
+            0 => return Err(io::ErrorKind::WriteZero.into()),
+            n => {
+                buf = &buf[n..];
+                offset += n as u64;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// A database held in memory, like a Vec<u8> or the &'static [u8] of include_bytes!
+impl<T: AsRef<[u8]> + Send + Sync> PageSource for Cursor<T> {
+    fn size(&self) -> Result<u64> {
+        Ok(self.get_ref().as_ref().len() as u64)
+    }
+
+    fn read_at(&self, offset: u64, len: usize) -> Result<Cow<'_, [u8]>> {
+        let bytes = self.get_ref().as_ref();
+        let start = usize::try_from(offset)?;
+        match start.checked_add(len) {
+            Some(end) if end <= bytes.len() => Ok(Cow::Borrowed(&bytes[start..end])),
+            _ => bail!("read_at: {len} bytes at {offset} is past the end of the database"),
+        }
+    }
+}
+
+/// Any other reader, e.g. one that fetches its bytes over the network. Its size is found once, by
+/// seeking to its end, so it mustn't grow or shrink after that. Since the reader has a position,
+/// reads from different threads take turns.
+pub struct ReaderSource<R> {
+    reader: Mutex<R>,
+    size: u64,
+}
+
+impl<R: Read + Seek> ReaderSource<R> {
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let size = reader.seek(SeekFrom::End(0))?;
+        reader.rewind()?;
+
+        Ok(ReaderSource {
+            reader: Mutex::new(reader),
+            size,
+        })
+    }
+}
+
+impl<R: Read + Seek + Send> PageSource for ReaderSource<R> {
+    fn size(&self) -> Result<u64> {
+        Ok(self.size)
+    }
+
+    fn read_at(&self, offset: u64, len: usize) -> Result<Cow<'_, [u8]>> {
+        let mut bytes = vec![0; len];
+        // A reader that panicked mid-read is repositioned below anyway
+        let mut reader = self.reader.lock().unwrap_or_else(PoisonError::into_inner);
+        reader.seek(SeekFrom::Start(offset))?;
+        reader.read_exact(&mut bytes)?;
+
+        Ok(Cow::Owned(bytes))
+    }
+}
+
+/// Reads are rounded out to whole blocks of this size, a page at the default page size
+const BLOCK_SIZE: u64 = 4096;
+
+/// How much is read at once when reads carry on where the last one left off
+const READ_AHEAD_SIZE: u64 = 64 * 1024;
+
+/// Reads a source through Read and Seek, for the small reads of page headers, cell pointers and
+/// cells, buffering its bytes so they don't each cost a system call. A read outside the buffer
+/// reads the block around it, unless it runs on into the block just past the buffer, as a scan's
+/// reads usually do: then the next READ_AHEAD_SIZE bytes are read in one go.
+///
+/// Each scan has a reader of its own, so scans on different threads share the source but not a
+/// position or a buffer. When the source is in memory the buffer borrows from it rather than
+/// copying.
+pub struct PageReader<'a> {
+    source: &'a dyn PageSource,
+    buffer: Cow<'a, [u8]>,
+    /// Where in the source the buffer starts
+    buffer_start: u64,
+    position: u64,
+}
+
+impl<'a> PageReader<'a> {
+    pub fn new(source: &'a dyn PageSource) -> Self {
+        PageReader {
+            source,
+            buffer: Cow::Borrowed(&[]),
+            buffer_start: 0,
+            position: 0,
+        }
+    }
+
+    fn buffer_end(&self) -> u64 {
+        self.buffer_start + self.buffer.len() as u64
+    }
+
+    /// Whether `len` bytes at `offset` can be read from the buffer, without reading the source
+    pub(crate) fn is_buffered(&self, offset: u64, len: usize) -> bool {
+        offset >= self.buffer_start && offset + len as u64 <= self.buffer_end()
+    }
+
+    /// Fills the buffer so that it holds as much of `len` bytes at `offset` as the source has,
+    /// returning where they start in the buffer
+    fn fill(&mut self, offset: u64, len: usize) -> Result<usize> {
+        let end = offset + len as u64;
+        if offset < self.buffer_start || end > self.buffer_end() {
+            let sequential = end > self.buffer_end() && end <= self.buffer_end() + BLOCK_SIZE;
+            let read_size = if sequential {
+                READ_AHEAD_SIZE
+            } else {
+                BLOCK_SIZE
+            };
+
+            let start = offset - offset % BLOCK_SIZE;
+            let end = (start + read_size)
+                .max(end.next_multiple_of(BLOCK_SIZE))
+                .min(self.source.size()?);
+            trace_event!(
+                "cache",
+                "miss at {offset}, reading {} bytes at {start}",
+                end.saturating_sub(start)
+            );
+            self.buffer_start = start;
+            self.buffer = if start < end {
+                self.source.read_at(start, (end - start) as usize)?
+            } else {
+                Cow::Borrowed(&[])
+            };
+        }
+
+        Ok((offset.min(self.buffer_end()) - self.buffer_start) as usize)
+    }
+}
+
+impl Read for PageReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let start = self
+            .fill(self.position, buf.len().min(READ_AHEAD_SIZE as usize))
+            .map_err(io::Error::other)?;
+        let count = buf.len().min(self.buffer.len() - start);
+        buf[..count].copy_from_slice(&self.buffer[start..start + count]);
+        self.position += count as u64;
+
+        Ok(count)
+    }
+}
+
+impl Seek for PageReader<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self
+                .source
+                .size()
+                .map_err(io::Error::other)?
+                .checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+
+        match position {
+            Some(position) => {
+                self.position = position;
+                Ok(position)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
+
+#[cfg(all(feature = "mmap", unix))]
+pub use self::mmap::Mmap;
+
+#[cfg(all(feature = "mmap", unix))]
+mod mmap {
+    use super::*;
+    use std::{
+        ffi::{c_int, c_void},
+        os::fd::AsRawFd,
+        ptr, slice,
+    };
+
+    const PROT_READ: c_int = 1;
+    const MAP_PRIVATE: c_int = 2;
+
+    extern "C" {
+        fn mmap(
+            addr: *mut c_void,
+            len: usize,
+            prot: c_int,
+            flags: c_int,
+            fd: c_int,
+            offset: i64,
+        ) -> *mut c_void;
+        fn munmap(addr: *mut c_void, len: usize) -> c_int;
+    }
+
+    /// A database file mapped into memory, so pages are read without a system call each. Like
+    /// SQLite's mmap_size, this assumes the file isn't truncated while it's mapped.
+    pub struct Mmap {
+        /// Null when the file is empty, since an empty file can't be mapped
+        map: *mut c_void,
+        len: usize,
+        /// Kept open for as long as it's mapped
+        _file: File,
+    }
+
+    // SAFETY: the mapping is read-only and owned by the Mmap, so it's no different from a
+    // Box<[u8]> that's shared between threads
+    unsafe impl Send for Mmap {}
+    unsafe impl Sync for Mmap {}
+
+    impl Mmap {
+        pub fn new(file: File) -> Result<Self> {
+            let len = usize::try_from(file.metadata()?.len())?;
+            if len == 0 {
+                return Ok(Mmap {
+                    map: ptr::null_mut(),
+                    len,
+                    _file: file,
+                });
+            }
+
+            // SAFETY: a fresh read-only private mapping of a file we hold open
+            let map = unsafe {
+                mmap(
+                    ptr::null_mut(),
+                    len,
+                    PROT_READ,
+                    MAP_PRIVATE,
+                    file.as_raw_fd(),
+                    0,
+                )
+            };
+            // MAP_FAILED
+            if map as isize == -1 {
+                bail!(
+                    "can't map the database file: {}",
+                    io::Error::last_os_error()
+                );
+            }
+
+            Ok(Mmap {
+                map,
+                len,
+                _file: file,
+            })
+        }
+
+        pub fn as_slice(&self) -> &[u8] {
+            if self.map.is_null() {
+                return &[];
+            }
+            // SAFETY: the map is len bytes long and lives until self is dropped
+            unsafe { slice::from_raw_parts(self.map as *const u8, self.len) }
+        }
+    }
+
+    impl Drop for Mmap {
+        fn drop(&mut self) {
+            if !self.map.is_null() {
+                // SAFETY: unmapping exactly what new() mapped, after which it's never used again
+                unsafe { munmap(self.map, self.len) };
+            }
+        }
+    }
+
+    impl PageSource for Mmap {
+        fn size(&self) -> Result<u64> {
+            Ok(self.len as u64)
+        }
+
+        fn read_at(&self, offset: u64, len: usize) -> Result<Cow<'_, [u8]>> {
+            let start = usize::try_from(offset)?;
+            match start.checked_add(len) {
+                Some(end) if end <= self.len => Ok(Cow::Borrowed(&self.as_slice()[start..end])),
+                _ => bail!("read_at: {len} bytes at {offset} is past the end of the database"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "fs")]
+    fn temp_file(name: &str, contents: &[u8]) -> File {
+        let path = std::env::temp_dir().join(format!("{name}_{}", std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        let file = File::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        file
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_file_source() {
+        let file = temp_file("page_source_file", b"0123456789");
+
+        assert_eq!(file.size().unwrap(), 10);
+        assert_eq!(&*file.read_at(3, 4).unwrap(), b"3456");
+        assert!(file.read_at(8, 4).is_err());
+    }
+
+    #[test]
+    fn test_in_memory_sources() {
+        let bytes = Cursor::new(b"0123456789".to_vec());
+        assert_eq!(bytes.size().unwrap(), 10);
+        assert_eq!(bytes.read_at(3, 4).unwrap(), Cow::Borrowed(b"3456"));
+        assert!(bytes.read_at(8, 4).is_err());
+
+        let reader = ReaderSource::new(Cursor::new(b"0123456789")).unwrap();
+        assert_eq!(reader.size().unwrap(), 10);
+        assert_eq!(&*reader.read_at(3, 4).unwrap(), b"3456");
+        assert!(reader.read_at(8, 4).is_err());
+    }
+
+    #[test]
+    fn test_page_reader() {
+        let bytes: Vec<u8> = (0..20_000u32).map(|i| (i % 251) as u8).collect();
+        let source = Cursor::new(bytes.clone());
+        let mut reader = PageReader::new(&source);
+
+        let mut read = vec![0; 10];
+        reader.seek(SeekFrom::Start(5000)).unwrap();
+        reader.read_exact(&mut read).unwrap();
+        assert_eq!(read, &bytes[5000..5010]);
+        assert_eq!(reader.buffer_start, 4096);
+        assert_eq!(reader.buffer.len(), 4096);
+
+        // Carrying on past the end of the buffer reads ahead, up to the end of the source
+        reader.seek(SeekFrom::Start(8190)).unwrap();
+        reader.read_exact(&mut read[..4]).unwrap();
+        assert_eq!(&read[..4], &bytes[8190..8194]);
+        assert_eq!(reader.buffer_start, 4096);
+        assert_eq!(reader.buffer.len(), 20_000 - 4096);
+        // Borrowed from the source, not copied
+        assert!(matches!(reader.buffer, Cow::Borrowed(_)));
+
+        let mut read = vec![0; 300];
+        reader.seek(SeekFrom::End(-200)).unwrap();
+        assert_eq!(reader.read(&mut read).unwrap(), 200);
+        assert_eq!(&read[..200], &bytes[19_800..]);
+        assert_eq!(reader.read(&mut read).unwrap(), 0);
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_shared_between_threads() {
+        let file = temp_file("page_source_threads", &[7; 100_000]);
+
+        std::thread::scope(|scope| {
+            for offset in [0, 40_000, 99_000] {
+                let file = &file;
+                scope.spawn(move || {
+                    let mut reader = PageReader::new(file);
+                    reader.seek(SeekFrom::Start(offset)).unwrap();
+                    let mut bytes = vec![];
+                    reader.read_to_end(&mut bytes).unwrap();
+                    assert_eq!(bytes.len() as u64, 100_000 - offset);
+                    assert!(bytes.iter().all(|&b| b == 7));
+                });
+            }
+        });
+    }
+
+    #[cfg(all(feature = "mmap", unix))]
+    #[test]
+    fn test_mmap_source() {
+        let map = Mmap::new(temp_file("page_source_mmap", b"0123456789")).unwrap();
+
+        assert_eq!(map.size().unwrap(), 10);
+        assert_eq!(&*map.read_at(3, 4).unwrap(), b"3456");
+        assert!(map.read_at(8, 4).is_err());
+
+        let empty = Mmap::new(temp_file("page_source_empty", b"")).unwrap();
+        assert_eq!(empty.as_slice(), b"");
+    }
+}
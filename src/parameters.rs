@@ -0,0 +1,236 @@
+use crate::{query_parser::*, value::Value};
+use anyhow::{bail, Result};
+
+/// Numbers a statement's parameters the way SQLite does: ?NNN is parameter NNN, a plain ? is one
+/// more than the largest number used so far, and a named parameter gets the next number the first
+/// time it appears and keeps it after that.
+#[derive(Default)]
+struct Numbering {
+    /// The name of each parameter by number (from 1), empty for those that are only numbered
+    names: Vec<String>,
+}
+
+impl Numbering {
+    fn number(&mut self, parameter: &str) -> Result<usize> {
+        if parameter == "?" {
+            self.names.push(String::new());
+            return Ok(self.names.len());
+        }
+
+        if let Some(digits) = parameter.strip_prefix('?') {
+            let number: usize = digits.parse()?;
+            if number == 0 {
+                bail!("parameter numbers start at 1: {parameter}");
+            }
+            if number > self.names.len() {
+                self.names.resize(number, String::new());
+            }
+            return Ok(number);
+        }
+
+        match self.names.iter().position(|name| name == parameter) {
+            Some(index) => Ok(index + 1),
+            None => {
+                self.names.push(parameter.to_string());
+                Ok(self.names.len())
+            }
+        }
+    }
+}
+
+impl Statement {
+    /// The statement's parameters by number, starting from 1: the name of each one (like ":name",
+    /// prefix included), or an empty string for those that are only numbered
+    pub fn parameter_names(&self) -> Result<Vec<String>> {
+        let mut numbering = Numbering::default();
+        self.clone()
+            .visit_parameters(&mut |parameter| numbering.number(parameter).map(|_| None))?;
+
+        Ok(numbering.names)
+    }
+
+    /// Returns the statement with `values[i]` bound to parameter number i + 1, ready to be planned
+    /// and executed, so the same parsed statement can be run again and again with different
+    /// values. Bound values act just as if they'd been written into the SQL, and parameters left
+    /// without a value are NULL.
+    pub fn bind(&self, values: &[Value]) -> Result<Statement> {
+        let mut numbering = Numbering::default();
+        let mut statement = self.clone();
+        statement.visit_parameters(&mut |parameter| {
+            let number = numbering.number(parameter)?;
+            let value = values.get(number - 1).unwrap_or(&Value::Null);
+            Ok(Some(Expression::Literal(Literal::from(value))))
+        })?;
+
+        Ok(statement)
+    }
+
+    /// Like bind, but binds values to parameters by name (like ":name", prefix included)
+    pub fn bind_named(&self, values: &[(&str, Value)]) -> Result<Statement> {
+        let names = self.parameter_names()?;
+
+        let mut numbered_values = vec![Value::Null; names.len()];
+        for (name, value) in values {
+            let Some(index) = names.iter().position(|n| n == name) else {
+                bail!("no such parameter: {name}");
+            };
+            numbered_values[index] = value.clone();
+        }
+
+        self.bind(&numbered_values)
+    }
+
+    /// Calls `f` on each parameter in the order they appear in the SQL, and replaces the parameter
+    /// with whatever expression `f` returns
+    fn visit_parameters(
+        &mut self,
+        f: &mut dyn FnMut(&str) -> Result<Option<Expression>>,
+    ) -> Result<()> {
+        let query = match self {
+            Statement::Select(query)
+            | Statement::Explain(query)
+            | Statement::ExplainQueryPlan(query)
+            | Statement::ExplainAnalyze(query) => query,
+            // Transaction statements and CREATE INDEX have no parameters
+            Statement::Begin
+            | Statement::Commit
+            | Statement::Rollback
+            | Statement::Savepoint(_)
+            | Statement::Release(_)
+            | Statement::RollbackTo(_)
+            | Statement::CreateIndex { .. }
+            | Statement::Pragma(_)
+            | Statement::Analyze(_)
+            | Statement::Vacuum { .. }
+            | Statement::Attach { .. }
+            | Statement::Detach(_) => return Ok(()),
+        };
+
+        visit_query(query, f)
+    }
+}
+
+/// Visits the parameters of a query, subqueries included, in the order they appear in the SQL
+fn visit_query(
+    query: &mut Query,
+    f: &mut dyn FnMut(&str) -> Result<Option<Expression>>,
+) -> Result<()> {
+    for column in &mut query.selection_list {
+        if let Selection::Expression(expression) = &mut column.selection {
+            visit_expression(expression, f)?;
+        }
+    }
+    if let Some(subquery) = &mut query.from_subquery {
+        visit_query(subquery, f)?;
+    }
+    for condition in query.and_conditions.iter_mut().flatten() {
+        for value in &mut condition.values {
+            visit_expression(value, f)?;
+        }
+        if let Some(subquery) = &mut condition.subquery {
+            visit_query(subquery, f)?;
+        }
+    }
+    if let Some(filter) = &mut query.filter {
+        visit_expression(filter, f)?;
+    }
+    for member in &mut query.compound {
+        visit_query(&mut member.select, f)?;
+    }
+    for term in &mut query.order_by {
+        visit_expression(&mut term.expression, f)?;
+    }
+
+    Ok(())
+}
+
+fn visit_expression(
+    expression: &mut Expression,
+    f: &mut dyn FnMut(&str) -> Result<Option<Expression>>,
+) -> Result<()> {
+    match expression {
+        Expression::Parameter(parameter) => {
+            if let Some(replacement) = f(parameter)? {
+                *expression = replacement;
+            }
+        }
+        Expression::Negate(operand)
+        | Expression::Not(operand)
+        | Expression::Cast { operand, .. } => visit_expression(operand, f)?,
+        Expression::Binary { lhs, rhs, .. } => {
+            visit_expression(lhs, f)?;
+            visit_expression(rhs, f)?;
+        }
+        Expression::FunctionCall { arguments, .. } => {
+            for argument in arguments {
+                visit_expression(argument, f)?;
+            }
+        }
+        Expression::Literal(_) | Expression::Column(_) => {}
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn statement(sql: &str) -> Statement {
+        parse_statement(sql).unwrap().1
+    }
+
+    #[test]
+    fn test_parameter_names() {
+        assert_eq!(
+            statement("SELECT ?, :a, ?5, @b, :a, ? FROM apples WHERE color = $c")
+                .parameter_names()
+                .unwrap(),
+            vec!["", ":a", "", "", "", "@b", "", "$c"]
+        );
+    }
+
+    #[test]
+    fn test_bind() {
+        let select =
+            statement("SELECT name, ? + 1 FROM apples WHERE color = ? AND rowid IN (?, ?)");
+
+        let bound = select
+            .bind(&[
+                Value::Integer(41),
+                Value::Text("Red".to_string()),
+                Value::Integer(2),
+            ])
+            .unwrap();
+        assert_eq!(
+            bound,
+            statement("SELECT name, 41 + 1 FROM apples WHERE color = 'Red' AND rowid IN (2, NULL)")
+        );
+
+        // The original statement can be bound again
+        let bound = select.bind(&[]).unwrap();
+        let Statement::Select(query) = bound else {
+            panic!("expected a SELECT");
+        };
+        assert_eq!(
+            query.and_conditions.unwrap()[0].values,
+            vec![Expression::Literal(Literal::Null)]
+        );
+    }
+
+    #[test]
+    fn test_bind_named() {
+        let select = statement("SELECT :n * 2 FROM apples WHERE name = :name AND color = :name");
+
+        assert_eq!(
+            select
+                .bind_named(&[
+                    (":name", Value::Text("Fuji".to_string())),
+                    (":n", Value::Real(1.5))
+                ])
+                .unwrap(),
+            statement("SELECT 1.5 * 2 FROM apples WHERE name = 'Fuji' AND color = 'Fuji'")
+        );
+        assert!(select.bind_named(&[(":nope", Value::Null)]).is_err());
+    }
+}
@@ -0,0 +1,85 @@
+use std::{
+    io::{self, Write},
+    process::{Child, Command, Stdio},
+};
+
+/// Hands what's written to it to a closure a line at a time, so that query results can be
+/// transformed as they're formatted rather than collected first. A last line without a newline
+/// is handed over on flush.
+pub struct LineWriter<F: FnMut(&str) -> io::Result<()>> {
+    transform: F,
+    line: Vec<u8>,
+}
+
+impl<F: FnMut(&str) -> io::Result<()>> LineWriter<F> {
+    pub fn new(transform: F) -> Self {
+        LineWriter {
+            transform,
+            line: vec![],
+        }
+    }
+
+    fn send_line(&mut self) -> io::Result<()> {
+        let line = String::from_utf8_lossy(&self.line).into_owned();
+        self.line.clear();
+        (self.transform)(&line)
+    }
+}
+
+impl<F: FnMut(&str) -> io::Result<()>> Write for LineWriter<F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            if byte == b'\n' {
+                self.send_line()?;
+            } else {
+                self.line.push(byte);
+            }
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.line.is_empty() {
+            self.send_line()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Starts `command` in the shell (sh, or cmd on Windows) with its stdin piped, for output to be
+/// written to. Its own output goes to stdout.
+pub fn spawn_command(command: &str) -> io::Result<Child> {
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+
+    shell.arg(command).stdin(Stdio::piped()).spawn()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_writer() {
+        let mut lines = vec![];
+        let mut writer = LineWriter::new(|line| {
+            lines.push(line.to_uppercase());
+            Ok(())
+        });
+        write!(writer, "1|Granny Smith\n2|Fu").unwrap();
+        write!(writer, "ji\n3|Honeycrisp").unwrap();
+        writer.flush().unwrap();
+        drop(writer);
+
+        assert_eq!(lines, ["1|GRANNY SMITH", "2|FUJI", "3|HONEYCRISP"]);
+    }
+}
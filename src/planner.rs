@@ -0,0 +1,1128 @@
+use crate::{
+    affinity::Affinity,
+    analyze::IndexStat,
+    collation::{same_collation, Collation, CollationRegistry},
+    database::Database,
+    query_parser::*,
+    row_layout::RowLayout,
+    schema::Schema,
+    trace::{self, Span},
+    value::Value,
+};
+use anyhow::{anyhow, bail, Result};
+use itertools::Itertools;
+use std::{cell::Cell, fmt, sync::Arc};
+
+/// Column names that always refer to the rowid of a table
+pub const ROWID_ALIASES: [&str; 3] = ["rowid", "oid", "_rowid_"];
+
+/// How the rows of the queried table will be found
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScanType {
+    /// Visit every row of the table b-tree
+    FullTableScan,
+    /// Descend the table b-tree straight to a single rowid
+    RowidLookup { row_id: i64 },
+    /// Search an index b-tree for the rowids of matching rows, then look each of them up. An IN
+    /// list searches the index once per distinct key, in index order.
+    IndexScan {
+        index_name: String,
+        index_root_page: u32,
+        column_name: String,
+        keys: Vec<Value>,
+        unique: bool,
+        /// The collation of the index's first column, or None for BINARY
+        collation: Option<Arc<Collation>>,
+        /// Whether the index's first column is sorted DESC
+        descending: bool,
+        /// When the index holds every column the query uses, the position in the table of each
+        /// of the index's columns, so that rows can be read from the index without the table
+        covering: Option<Vec<usize>>,
+    },
+    /// Run the FROM clause's subquery, with its own plan, and read its rows as the table's
+    Subquery {
+        plan: Box<QueryPlan>,
+        query: Box<Query>,
+    },
+    /// Run each SELECT of a compound SELECT, with its own plan, and combine their rows
+    Compound { members: Vec<CompoundMember> },
+}
+
+/// One of the SELECTs of a compound SELECT
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompoundMember {
+    /// How its rows are combined with those of the SELECTs before it, or None for the first
+    pub operator: Option<CompoundOperator>,
+    pub plan: QueryPlan,
+    /// The SELECT on its own, without the compound's ORDER BY and LIMIT
+    pub query: Query,
+}
+
+impl CompoundMember {
+    /// The member's line in EXPLAIN QUERY PLAN, as SQLite words it
+    fn label(&self) -> String {
+        match self.operator {
+            None => "LEFT-MOST SUBQUERY".to_string(),
+            Some(CompoundOperator::UnionAll) => "UNION ALL".to_string(),
+            Some(operator) => format!("{operator} USING TEMP B-TREE"),
+        }
+    }
+}
+
+/// How rows are put in the order ORDER BY asks for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RowOrder {
+    /// No ORDER BY, so rows come in whatever order the scan finds them
+    Any,
+    /// ORDER BY rowid, the order the table is stored in
+    RowidAscending,
+    /// ORDER BY rowid DESC, by walking the table from its last row back
+    RowidDescending,
+    /// Rows are sorted once they've all been found
+    Sorted,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryPlan {
+    pub table_name: String,
+    pub table_root_page: u32,
+    /// Column names of the queried table, in table order
+    pub columns: Vec<String>,
+    /// The index of the column that's an alias for the rowid, if any
+    pub rowid_alias: Option<usize>,
+    /// The collation each column declares, in table order
+    pub collations: Vec<Option<String>>,
+    /// Each column's affinity, from its declared type, in table order
+    pub affinities: Vec<Affinity>,
+    /// How the table's records are laid out as rows, when they don't simply hold each column in
+    /// turn
+    pub layout: Option<RowLayout>,
+    pub scan: ScanType,
+    pub estimated_pages: u32,
+    /// How many rows the scan is expected to find, before the WHERE conditions are checked
+    pub estimated_rows: u64,
+    /// Duplicate result rows are removed as they're produced, by remembering the rows seen so far
+    pub distinct: bool,
+    pub order: RowOrder,
+    /// The database's schema cookie when the query was planned
+    pub schema_cookie: u32,
+}
+
+/// Chooses how to execute a query: a rowid lookup if the WHERE clause pins down the rowid, an
+/// index scan if an index covers one of the WHERE columns, or a full table scan otherwise.
+pub fn plan_query(database: &Database, schema: &[Schema], query: &Query) -> Result<QueryPlan> {
+    let span = Span::start();
+    let plan = choose_plan(database, schema, query)?;
+    // The steps are only worth describing when there's a subscriber to see them
+    if trace::enabled() {
+        span.end(
+            "plan",
+            format_args!(
+                "{}, est. {} pages and {} rows",
+                plan.steps().iter().map(|step| &step.label).join(", "),
+                plan.estimated_pages,
+                plan.estimated_rows
+            ),
+        );
+    }
+
+    Ok(plan)
+}
+
+fn choose_plan(database: &Database, schema: &[Schema], query: &Query) -> Result<QueryPlan> {
+    if !query.compound.is_empty() {
+        return plan_compound(database, schema, query);
+    }
+    if let Some(subquery) = &query.from_subquery {
+        return plan_subquery_scan(database, schema, query, subquery);
+    }
+
+    let table = schema
+        .iter()
+        .find(|s| s.is_table() && s.name.eq_ignore_ascii_case(&query.from_table))
+        .ok_or_else(|| anyhow!("no such table: {}", query.from_table))?;
+    let create_table = table.create_table()?;
+
+    let conditions = query.and_conditions.as_deref().unwrap_or_default();
+
+    let rowid_alias = create_table
+        .rowid_alias
+        .map(|i| create_table.columns[i].name.as_str());
+    let stats = database.index_stats(schema)?;
+    let scan = match find_rowid_lookup(conditions, rowid_alias) {
+        Some(row_id) => ScanType::RowidLookup { row_id },
+        None => match find_index_scan(
+            &database.collations,
+            schema,
+            &table.name,
+            &create_table,
+            query,
+        )? {
+            // ANALYZE's statistics can show that an index finds so many rows that looking each
+            // one up costs more than reading the whole table
+            Some(scan)
+                if rows_per_key(&stats, &scan).is_some()
+                    && index_scan_pages(database, table.root_page, &scan, &stats)?
+                        > database.estimate_btree_pages(table.root_page)? =>
+            {
+                ScanType::FullTableScan
+            }
+            Some(scan) => scan,
+            None => ScanType::FullTableScan,
+        },
+    };
+
+    let order = find_row_order(query, rowid_alias);
+
+    let estimated_pages = match &scan {
+        ScanType::FullTableScan => database.estimate_btree_pages(table.root_page)?,
+        ScanType::RowidLookup { .. } => database.btree_depth(table.root_page)?,
+        ScanType::IndexScan { .. } => index_scan_pages(database, table.root_page, &scan, &stats)?,
+        ScanType::Subquery { .. } | ScanType::Compound { .. } => {
+            unreachable!("subqueries and compounds are planned on their own")
+        }
+    };
+
+    let estimated_rows = match &scan {
+        ScanType::FullTableScan => match stats
+            .iter()
+            .find(|stat| stat.table_name.eq_ignore_ascii_case(&table.name))
+        {
+            Some(stat) => stat.rows,
+            None => database.estimate_btree_rows(table.root_page)?,
+        },
+        ScanType::RowidLookup { .. } => 1,
+        // Without statistics on the index, SQLite guesses that each key matches 10 rows
+        ScanType::IndexScan { keys, unique, .. } => {
+            let rows_per_key = rows_per_key(&stats, &scan);
+            keys.len() as u64 * rows_per_key.unwrap_or(if *unique { 1 } else { 10 })
+        }
+        ScanType::Subquery { .. } | ScanType::Compound { .. } => {
+            unreachable!("subqueries and compounds are planned on their own")
+        }
+    };
+
+    Ok(QueryPlan {
+        table_name: table.name.clone(),
+        table_root_page: table.root_page,
+        rowid_alias: create_table.rowid_alias,
+        layout: RowLayout::new(&create_table, &database.functions)?,
+        collations: create_table
+            .columns
+            .iter()
+            .map(|c| c.collation.clone())
+            .collect(),
+        affinities: create_table
+            .columns
+            .iter()
+            .map(|c| Affinity::from_type_name(&c.type_name))
+            .collect(),
+        columns: create_table.columns.into_iter().map(|c| c.name).collect(),
+        scan,
+        estimated_pages,
+        estimated_rows,
+        distinct: query.distinct,
+        order,
+        schema_cookie: database.schema_cookie,
+    })
+}
+
+/// How many rows share each key of an index scan's index, if ANALYZE has counted them
+fn rows_per_key(stats: &[IndexStat], scan: &ScanType) -> Option<u64> {
+    let ScanType::IndexScan { index_name, .. } = scan else {
+        return None;
+    };
+
+    stats
+        .iter()
+        .find(|stat| {
+            stat.index_name
+                .as_deref()
+                .is_some_and(|name| name.eq_ignore_ascii_case(index_name))
+        })
+        .and_then(|stat| stat.rows_per_key.first().copied())
+}
+
+/// How many pages an index scan reads: a descent of the index for each key, and one of the table
+/// for each row found, unless the index covers the query
+fn index_scan_pages(
+    database: &Database,
+    table_root_page: u32,
+    scan: &ScanType,
+    stats: &[IndexStat],
+) -> Result<u32> {
+    let ScanType::IndexScan {
+        index_root_page,
+        keys,
+        covering,
+        ..
+    } = scan
+    else {
+        unreachable!("only index scans read an index");
+    };
+    let table_depth = match covering {
+        Some(_) => 0,
+        None => database.btree_depth(table_root_page)?,
+    };
+    let rows_per_key = rows_per_key(stats, scan).unwrap_or(1);
+    let pages_per_key = (table_depth as u64)
+        .saturating_mul(rows_per_key)
+        .saturating_add(database.btree_depth(*index_root_page)? as u64);
+
+    Ok(pages_per_key
+        .saturating_mul(keys.len().max(1) as u64)
+        .min(u32::MAX as u64) as u32)
+}
+
+/// Plans a query on the rows of a subquery, which is run first, its rows taking the place of a
+/// table's. Columns that select a column of the subquery's table keep its collation and affinity.
+fn plan_subquery_scan(
+    database: &Database,
+    schema: &[Schema],
+    query: &Query,
+    subquery: &Query,
+) -> Result<QueryPlan> {
+    let plan = plan_query(database, schema, subquery)?;
+    let (columns, collations, affinities) = result_columns(&plan, subquery);
+
+    let estimated_rows = match subquery.limit {
+        Some(limit) => plan.estimated_rows.min(limit as u64),
+        None => plan.estimated_rows,
+    };
+    // The subquery's rows are numbered as they come, so they're already in "rowid" order, but
+    // there's no walking them backwards
+    let order = match find_row_order(query, None) {
+        RowOrder::RowidDescending => RowOrder::Sorted,
+        order => order,
+    };
+
+    Ok(QueryPlan {
+        table_name: query.from_table.clone(),
+        table_root_page: 0,
+        columns,
+        rowid_alias: None,
+        collations,
+        affinities,
+        layout: None,
+        estimated_pages: plan.estimated_pages,
+        estimated_rows,
+        distinct: query.distinct,
+        order,
+        schema_cookie: database.schema_cookie,
+        scan: ScanType::Subquery {
+            plan: Box::new(plan),
+            query: Box::new(subquery.clone()),
+        },
+    })
+}
+
+/// Plans a compound SELECT, each of whose SELECTs is planned on its own. Its result columns are
+/// those of the left-most SELECT, and sorting for its ORDER BY is always needed.
+fn plan_compound(database: &Database, schema: &[Schema], query: &Query) -> Result<QueryPlan> {
+    let left_most = query.left_most();
+    let mut members = vec![CompoundMember {
+        operator: None,
+        plan: plan_query(database, schema, &left_most)?,
+        query: left_most,
+    }];
+    for member in &query.compound {
+        if member.select.selection_list.len() != query.selection_list.len() {
+            bail!(
+                "SELECTs to the left and right of {} do not have the same number of result \
+                 columns",
+                member.operator
+            );
+        }
+        members.push(CompoundMember {
+            operator: Some(member.operator),
+            plan: plan_query(database, schema, &member.select)?,
+            query: member.select.clone(),
+        });
+    }
+
+    let (columns, mut collations, affinities) = result_columns(&members[0].plan, &members[0].query);
+    // As in SQLite, a column takes its collation from the left-most SELECT that has one for it
+    for member in &members[1..] {
+        let (_, member_collations, _) = result_columns(&member.plan, &member.query);
+        for (collation, member_collation) in collations.iter_mut().zip(member_collations) {
+            if collation.is_none() {
+                *collation = member_collation;
+            }
+        }
+    }
+    let estimated_rows = members.iter().fold(0, |rows, member| {
+        let member_rows = member.plan.estimated_rows;
+        match member.operator {
+            None | Some(CompoundOperator::UnionAll | CompoundOperator::Union) => rows + member_rows,
+            Some(CompoundOperator::Intersect) => rows.min(member_rows),
+            Some(CompoundOperator::Except) => rows,
+        }
+    });
+    let order = if query.order_by.is_empty() {
+        RowOrder::Any
+    } else {
+        RowOrder::Sorted
+    };
+
+    Ok(QueryPlan {
+        table_name: String::new(),
+        table_root_page: 0,
+        columns,
+        rowid_alias: None,
+        collations,
+        affinities,
+        layout: None,
+        estimated_pages: members
+            .iter()
+            .map(|member| member.plan.estimated_pages)
+            .sum(),
+        estimated_rows,
+        distinct: false,
+        order,
+        schema_cookie: database.schema_cookie,
+        scan: ScanType::Compound { members },
+    })
+}
+
+/// The names of the query's result columns, with the collation and affinity of each that selects
+/// a column of the plan's table. Other expressions have no collation and no affinity.
+fn result_columns(
+    plan: &QueryPlan,
+    query: &Query,
+) -> (Vec<String>, Vec<Option<String>>, Vec<Affinity>) {
+    let mut columns = vec![];
+    let mut collations = vec![];
+    let mut affinities = vec![];
+    for result_column in &query.selection_list {
+        let table_column = match &result_column.selection {
+            Selection::Expression(Expression::Column(name)) => plan.column_index(name),
+            _ => None,
+        };
+        columns.push(result_column.name());
+        collations.push(table_column.and_then(|i| plan.collations[i].clone()));
+        affinities.push(table_column.map_or(Affinity::Blob, |i| plan.affinities[i]));
+    }
+
+    (columns, collations, affinities)
+}
+
+/// Whether a column name refers to the rowid, by way of one of its built-in names or the table's
+/// INTEGER PRIMARY KEY column, `rowid_alias`
+fn is_rowid(column_name: &str, rowid_alias: Option<&str>) -> bool {
+    ROWID_ALIASES
+        .iter()
+        .copied()
+        .chain(rowid_alias)
+        .any(|alias| column_name.eq_ignore_ascii_case(alias))
+}
+
+/// Ordering by the rowid needs no sorting, since that's the order the table is stored in
+fn find_row_order(query: &Query, rowid_alias: Option<&str>) -> RowOrder {
+    let is_aggregate = query
+        .selection_list
+        .iter()
+        .any(|column| matches!(column.selection, Selection::AggregateFunction(_)));
+    // A result column's alias takes precedence over a table column of the same name
+    let is_alias = |name: &str| {
+        query
+            .selection_list
+            .iter()
+            .any(|column| column.alias.as_deref() == Some(name))
+    };
+
+    match query.order_by.as_slice() {
+        [] => RowOrder::Any,
+        // An aggregate query's single row doesn't need ordering
+        _ if is_aggregate => RowOrder::Any,
+        [OrderingTerm {
+            expression: Expression::Column(name),
+            descending,
+            ..
+        }] if is_rowid(name, rowid_alias) && !is_alias(name) => {
+            if *descending {
+                RowOrder::RowidDescending
+            } else {
+                RowOrder::RowidAscending
+            }
+        }
+        _ => RowOrder::Sorted,
+    }
+}
+
+/// Finds a condition that pins down the rowid
+fn find_rowid_lookup(conditions: &[AndCondition], rowid_alias: Option<&str>) -> Option<i64> {
+    conditions.iter().find_map(|condition| {
+        if condition.operator == ComparisonOperator::Equals
+            && is_rowid(&condition.column_name, rowid_alias)
+        {
+            match &condition.values[0] {
+                // The rowid has INTEGER affinity, so '42' and 42.0 find rowid 42 too
+                Expression::Literal(literal) => {
+                    Affinity::Integer.apply(Value::from(literal)).as_integer()
+                }
+                _ => None,
+            }
+        } else {
+            None
+        }
+    })
+}
+
+/// Finds an index whose first column a condition tests for equality, with the same collation, so
+/// that the index's order agrees with what the condition counts as equal
+fn find_index_scan(
+    collations: &CollationRegistry,
+    schema: &[Schema],
+    table_name: &str,
+    create_table: &CreateTable,
+    query: &Query,
+) -> Result<Option<ScanType>> {
+    let conditions = query.and_conditions.as_deref().unwrap_or_default();
+
+    for index in schema
+        .iter()
+        .filter(|s| s.is_index() && s.table_name.eq_ignore_ascii_case(table_name))
+    {
+        let Some(create_index) = index
+            .create_index()?
+            .or_else(|| create_table.autoindex(&index.name))
+        else {
+            continue;
+        };
+        let Some(first_column) = create_index.columns.first() else {
+            continue;
+        };
+
+        let column = create_table
+            .columns
+            .iter()
+            .find(|c| c.name.eq_ignore_ascii_case(first_column));
+        let column_collation = column.and_then(|c| c.collation.as_deref());
+        let index_collation = create_index
+            .collations
+            .first()
+            .and_then(Option::as_deref)
+            .or(column_collation);
+
+        // An IN subquery's values aren't known until it runs
+        let condition = conditions.iter().find(|condition| {
+            condition.subquery.is_none()
+                && matches!(
+                    condition.operator,
+                    ComparisonOperator::Equals | ComparisonOperator::In
+                )
+                && condition.column_name.eq_ignore_ascii_case(first_column)
+                && same_collation(
+                    condition.collation.as_deref().or(column_collation),
+                    index_collation,
+                )
+        });
+
+        if let Some(condition) = condition {
+            // Index entries are stored converted to the column's affinity, so the keys have to be
+            // converted the same way before they can be compared against them
+            let affinity =
+                Affinity::from_type_name(column.map(|c| c.type_name.as_str()).unwrap_or_default());
+            let collation = index_collation
+                .map(|name| collations.find(name))
+                .transpose()?;
+            let compare = |a: &Value, b: &Value| match &collation {
+                Some(collation) => collation.compare_values(a, b),
+                None => a.compare(b),
+            };
+
+            // NULL is never equal to anything, so it doesn't need looking up
+            let mut keys = condition
+                .values
+                .iter()
+                .filter_map(|value| match value {
+                    Expression::Literal(Literal::Null) => None,
+                    Expression::Literal(literal) => Some(affinity.apply(Value::from(literal))),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            let descending = create_index.descending.first() == Some(&true);
+            keys.sort_by(|a, b| match descending {
+                true => compare(b, a),
+                false => compare(a, b),
+            });
+            keys.dedup_by(|a, b| compare(a, b).is_eq());
+
+            return Ok(Some(ScanType::IndexScan {
+                index_name: index.name.clone(),
+                index_root_page: index.root_page,
+                column_name: first_column.clone(),
+                keys,
+                unique: create_index.unique,
+                collation,
+                descending,
+                covering: covering_columns(create_table, &create_index, query),
+            }));
+        }
+    }
+
+    Ok(None)
+}
+
+/// The position in the table of each of an index's columns, if the index holds every column the
+/// query uses. Every index entry ends with its row's rowid, so the rowid is always covered.
+fn covering_columns(
+    create_table: &CreateTable,
+    create_index: &CreateIndex,
+    query: &Query,
+) -> Option<Vec<usize>> {
+    // The entries of an index on expressions don't hold the columns' values
+    if !create_index.simple_columns {
+        return None;
+    }
+    let rowid_alias = create_table
+        .rowid_alias
+        .map(|i| create_table.columns[i].name.as_str());
+    let is_indexed = |name: &str| {
+        create_index
+            .columns
+            .iter()
+            .any(|column| column.eq_ignore_ascii_case(name))
+    };
+
+    let mut names = vec![];
+    for column in &query.selection_list {
+        if let Selection::Expression(expression) = &column.selection {
+            expression.visit_columns(&mut |name| names.push(name));
+        }
+    }
+    for condition in query.and_conditions.iter().flatten() {
+        names.push(&condition.column_name);
+    }
+    if let Some(filter) = &query.filter {
+        filter.visit_columns(&mut |name| names.push(name));
+    }
+    for term in &query.order_by {
+        term.expression.visit_columns(&mut |name| names.push(name));
+    }
+    let covered = names.into_iter().all(|name| {
+        let name = name.rsplit_once('.').map_or(name, |(_, column)| column);
+        is_rowid(name, rowid_alias) || is_indexed(name)
+    });
+    if !covered {
+        return None;
+    }
+
+    create_index
+        .columns
+        .iter()
+        .map(|name| {
+            create_table
+                .columns
+                .iter()
+                .position(|column| column.name.eq_ignore_ascii_case(name))
+        })
+        .collect()
+}
+
+impl QueryPlan {
+    /// How rows are found, as the first line of the plan says
+    fn scan_description(&self) -> String {
+        match &self.scan {
+            ScanType::FullTableScan | ScanType::Subquery { .. } => {
+                format!("SCAN {}", self.table_name)
+            }
+            ScanType::Compound { .. } => "COMPOUND QUERY".to_string(),
+            ScanType::RowidLookup { .. } => {
+                format!(
+                    "SEARCH {} USING INTEGER PRIMARY KEY (rowid=?)",
+                    self.table_name
+                )
+            }
+            ScanType::IndexScan {
+                index_name,
+                column_name,
+                covering,
+                ..
+            } => format!(
+                "SEARCH {} USING {}INDEX {index_name} ({column_name}=?)",
+                self.table_name,
+                if covering.is_some() { "COVERING " } else { "" }
+            ),
+        }
+    }
+
+    /// Lays the plan out as a tree of the steps rows go through, from the scan that finds them up
+    /// to the step that produces the result, each with the number of rows it's expected to
+    /// produce. With `counts` from running the query, each step also has the number of rows it
+    /// actually produced.
+    pub fn tree(&self, query: &Query, counts: Option<&RowCounts>) -> PlanNode {
+        let actual = |count: fn(&RowCounts) -> &Cell<u64>| counts.map(|c| count(c).get());
+
+        let mut node = PlanNode {
+            label: self.scan_description(),
+            estimated_rows: self.estimated_rows,
+            actual_rows: actual(|c| &c.scanned),
+            children: vec![],
+        };
+        // A subquery's rows are counted as they're scanned, but not within the subquery
+        match &self.scan {
+            ScanType::Subquery { plan, query } => node.children.push(plan.tree(query, None)),
+            ScanType::Compound { members } => {
+                node.children.extend(members.iter().map(|member| {
+                    let tree = member.plan.tree(&member.query, None);
+                    PlanNode {
+                        label: member.label(),
+                        estimated_rows: tree.estimated_rows,
+                        actual_rows: None,
+                        children: vec![tree],
+                    }
+                }));
+            }
+            _ => {}
+        }
+        // A compound's WHERE conditions and aggregates are its SELECTs' own
+        let is_compound = matches!(self.scan, ScanType::Compound { .. });
+        let mut add_step = |label: String, estimated_rows: u64, actual_rows: Option<u64>| {
+            let child = std::mem::replace(
+                &mut node,
+                PlanNode {
+                    label,
+                    estimated_rows,
+                    actual_rows,
+                    children: vec![],
+                },
+            );
+            node.children.push(child);
+        };
+
+        let mut conditions = vec![];
+        if !is_compound {
+            let and_conditions = query.and_conditions.iter().flatten();
+            conditions.extend(and_conditions.map(describe_condition));
+            conditions.extend(query.filter.iter().map(|filter| filter.to_string()));
+        }
+        let mut estimated_rows = self.estimated_rows;
+        if !conditions.is_empty() {
+            // Without statistics there's no telling, so guess that each condition lets a quarter of
+            // the rows through, apart from the one a search already used to find them
+            let searched = usize::from(matches!(
+                self.scan,
+                ScanType::RowidLookup { .. } | ScanType::IndexScan { .. }
+            ));
+            estimated_rows = conditions
+                .iter()
+                .skip(searched)
+                .fold(estimated_rows, |rows, _| rows.div_ceil(4));
+            let label = conditions.join(" AND ");
+            add_step(
+                format!("FILTER {label}"),
+                estimated_rows,
+                actual(|c| &c.filtered),
+            );
+        }
+
+        if self.order == RowOrder::Sorted {
+            add_step(
+                format!("ORDER BY {}", query.order_by.iter().join(", ")),
+                estimated_rows,
+                actual(|c| &c.sorted),
+            );
+        }
+
+        let is_aggregate = query
+            .selection_list
+            .iter()
+            .any(|column| matches!(column.selection, Selection::AggregateFunction(_)));
+        if is_aggregate && !is_compound {
+            estimated_rows = 1;
+            add_step("AGGREGATE".to_string(), estimated_rows, counts.map(|_| 1));
+        } else if self.distinct {
+            add_step(
+                "DISTINCT".to_string(),
+                estimated_rows,
+                actual(|c| &c.distinct),
+            );
+        }
+
+        if let Some(limit) = query.limit {
+            estimated_rows = estimated_rows.min(limit as u64);
+            add_step(
+                format!("LIMIT {limit}"),
+                estimated_rows,
+                actual(|c| &c.output),
+            );
+        }
+
+        node
+    }
+}
+
+fn describe_condition(condition: &AndCondition) -> String {
+    let column = &condition.column_name;
+    let values = condition.values.iter();
+    if condition.subquery.is_some() {
+        return format!("{column} IN (subquery)");
+    }
+
+    match condition.operator {
+        ComparisonOperator::Equals => format!("{column} = {}", values.format("")),
+        ComparisonOperator::Regexp => format!("{column} REGEXP {}", values.format("")),
+        ComparisonOperator::Between => format!("{column} BETWEEN {}", values.format(" AND ")),
+        ComparisonOperator::In => format!("{column} IN ({})", values.format(", ")),
+    }
+}
+
+impl QueryPlan {
+    /// The position of the column called `name` (which may be qualified by the table's name) in
+    /// the table's records
+    fn column_index(&self, name: &str) -> Option<usize> {
+        let name = match name.split_once('.') {
+            Some((table, column)) if table.eq_ignore_ascii_case(&self.table_name) => column,
+            _ => name,
+        };
+
+        self.columns
+            .iter()
+            .position(|column| column.eq_ignore_ascii_case(name))
+    }
+
+    /// The lines of EXPLAIN QUERY PLAN. A subquery's plan comes first, under the CO-ROUTINE that
+    /// runs it, and a compound's SELECTs each come under the operator that combines them.
+    fn steps(&self) -> Vec<Step> {
+        let step = |label: String| Step {
+            label,
+            children: vec![],
+        };
+
+        let mut steps = match &self.scan {
+            ScanType::Subquery { plan, .. } => vec![
+                Step {
+                    label: format!("CO-ROUTINE {}", self.table_name),
+                    children: plan.steps(),
+                },
+                step(self.scan_description()),
+            ],
+            ScanType::Compound { members } => vec![Step {
+                label: self.scan_description(),
+                children: members
+                    .iter()
+                    .map(|member| Step {
+                        label: member.label(),
+                        children: member.plan.steps(),
+                    })
+                    .collect(),
+            }],
+            _ => vec![step(format!(
+                "{} (~{} pages, ~{} rows)",
+                self.scan_description(),
+                self.estimated_pages,
+                self.estimated_rows
+            ))],
+        };
+        if self.distinct {
+            steps.push(step("USE TEMP B-TREE FOR DISTINCT".to_string()));
+        }
+        if self.order == RowOrder::Sorted {
+            steps.push(step("USE TEMP B-TREE FOR ORDER BY".to_string()));
+        }
+
+        steps
+    }
+}
+
+/// A line of EXPLAIN QUERY PLAN, with the lines of the steps under it
+struct Step {
+    label: String,
+    children: Vec<Step>,
+}
+
+/// Writes lines of EXPLAIN QUERY PLAN, each after `indent`, and their children after more
+fn fmt_steps(f: &mut fmt::Formatter<'_>, steps: &[Step], indent: &str) -> fmt::Result {
+    for (i, step) in steps.iter().enumerate() {
+        let is_last = i == steps.len() - 1;
+        let branch = if is_last { "`--" } else { "|--" };
+        write!(f, "\n{indent}{branch}{}", step.label)?;
+        let child_indent = if is_last { "   " } else { "|  " };
+        fmt_steps(f, &step.children, &format!("{indent}{child_indent}"))?;
+    }
+
+    Ok(())
+}
+
+impl fmt::Display for QueryPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "QUERY PLAN")?;
+        fmt_steps(f, &self.steps(), "")
+    }
+}
+
+/// The number of rows that came out of each step of a query as it ran, for EXPLAIN ANALYZE
+#[derive(Debug, Default)]
+pub struct RowCounts {
+    /// Rows found by the scan
+    pub scanned: Cell<u64>,
+    /// Rows that met the WHERE conditions
+    pub filtered: Cell<u64>,
+    /// Rows that came out of sorting for ORDER BY
+    pub sorted: Cell<u64>,
+    /// Rows left after removing duplicates
+    pub distinct: Cell<u64>,
+    /// Rows in the result
+    pub output: Cell<u64>,
+}
+
+/// A step of a query plan, fed by the steps that are its children
+#[derive(Debug, PartialEq)]
+pub struct PlanNode {
+    pub label: String,
+    pub estimated_rows: u64,
+    /// Set once the query has been run
+    pub actual_rows: Option<u64>,
+    pub children: Vec<PlanNode>,
+}
+
+impl PlanNode {
+    fn fmt_tree(&self, f: &mut fmt::Formatter<'_>, indent: &str, is_last: bool) -> fmt::Result {
+        let branch = if is_last { "`--" } else { "|--" };
+        write!(
+            f,
+            "\n{indent}{branch}{} (est. {} rows",
+            self.label, self.estimated_rows
+        )?;
+        if let Some(actual_rows) = self.actual_rows {
+            write!(f, ", actual {actual_rows} rows")?;
+        }
+        write!(f, ")")?;
+
+        let indent = format!("{indent}{}", if is_last { "   " } else { "|  " });
+        for (i, child) in self.children.iter().enumerate() {
+            child.fmt_tree(f, &indent, i == self.children.len() - 1)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Draws the tree the way SQLite draws EXPLAIN QUERY PLAN, with the root at the top
+impl fmt::Display for PlanNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "QUERY PLAN")?;
+        self.fmt_tree(f, "", true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn condition(column_name: &str, operator: ComparisonOperator, value: &str) -> AndCondition {
+        AndCondition {
+            column_name: column_name.to_string(),
+            operator,
+            values: vec![Expression::Literal(Literal::Text(value.to_string()))],
+            collation: None,
+            subquery: None,
+        }
+    }
+
+    #[test]
+    fn test_find_rowid_lookup() {
+        use ComparisonOperator::*;
+
+        assert_eq!(
+            find_rowid_lookup(&[condition("ROWID", Equals, "42")], None),
+            Some(42)
+        );
+        assert_eq!(
+            find_rowid_lookup(&[condition("rowid", Equals, " 4.2e1 ")], None),
+            Some(42)
+        );
+        assert_eq!(
+            find_rowid_lookup(&[condition("rowid", Equals, "pizza")], None),
+            None
+        );
+        assert_eq!(
+            find_rowid_lookup(&[condition("rowid", Regexp, "42")], None),
+            None
+        );
+        assert_eq!(
+            find_rowid_lookup(&[condition("name", Equals, "42")], None),
+            None
+        );
+        assert_eq!(
+            find_rowid_lookup(&[condition("ID", Equals, "42")], Some("id")),
+            Some(42)
+        );
+    }
+
+    #[test]
+    fn test_display_plan() {
+        let mut plan = QueryPlan {
+            table_name: "companies".to_string(),
+            table_root_page: 2,
+            columns: vec!["id".to_string(), "country".to_string()],
+            rowid_alias: Some(0),
+            collations: vec![None, None],
+            affinities: vec![Affinity::Integer, Affinity::Text],
+            layout: None,
+            scan: ScanType::IndexScan {
+                index_name: "idx_companies_country".to_string(),
+                index_root_page: 3,
+                column_name: "country".to_string(),
+                keys: vec![Value::Text("chad".to_string())],
+                unique: false,
+                collation: None,
+                descending: false,
+                covering: None,
+            },
+            estimated_pages: 4,
+            estimated_rows: 10,
+            distinct: false,
+            order: RowOrder::Any,
+            schema_cookie: 1,
+        };
+
+        assert_eq!(
+            plan.to_string(),
+            "QUERY PLAN\n`--SEARCH companies USING INDEX idx_companies_country (country=?) \
+             (~4 pages, ~10 rows)"
+        );
+
+        let QueryPlan {
+            scan: ScanType::IndexScan { covering, .. },
+            ..
+        } = &mut plan
+        else {
+            unreachable!();
+        };
+        *covering = Some(vec![1]);
+        assert_eq!(
+            plan.to_string(),
+            "QUERY PLAN\n`--SEARCH companies USING COVERING INDEX idx_companies_country (country=?) \
+             (~4 pages, ~10 rows)"
+        );
+
+        let plan = QueryPlan {
+            scan: ScanType::FullTableScan,
+            distinct: true,
+            ..plan
+        };
+        assert_eq!(
+            plan.to_string(),
+            "QUERY PLAN\n|--SCAN companies (~4 pages, ~10 rows)\n`--USE TEMP B-TREE FOR DISTINCT"
+        );
+    }
+
+    #[test]
+    fn test_display_plan_tree() {
+        let plan = QueryPlan {
+            table_name: "apples".to_string(),
+            table_root_page: 2,
+            columns: vec!["name".to_string(), "color".to_string()],
+            rowid_alias: None,
+            collations: vec![None, None],
+            affinities: vec![Affinity::Blob, Affinity::Blob],
+            layout: None,
+            scan: ScanType::FullTableScan,
+            estimated_pages: 1,
+            estimated_rows: 100,
+            distinct: true,
+            order: RowOrder::Any,
+            schema_cookie: 1,
+        };
+        let (_, query) = parse_query(
+            "SELECT DISTINCT name FROM apples WHERE color IN ('Red', 'Green') LIMIT 10",
+        )
+        .unwrap();
+
+        assert_eq!(
+            plan.tree(&query, None).to_string(),
+            "QUERY PLAN\n\
+             `--LIMIT 10 (est. 10 rows)\n   \
+                `--DISTINCT (est. 25 rows)\n      \
+                   `--FILTER color IN ('Red', 'Green') (est. 25 rows)\n         \
+                      `--SCAN apples (est. 100 rows)"
+        );
+
+        let counts = RowCounts::default();
+        counts.scanned.set(100);
+        counts.filtered.set(40);
+        counts.distinct.set(3);
+        counts.output.set(3);
+        let tree = plan.tree(&query, Some(&counts));
+        assert_eq!(tree.actual_rows, Some(3));
+        assert_eq!(tree.children[0].children[0].actual_rows, Some(40));
+    }
+
+    #[test]
+    fn test_covering_columns() {
+        let (_, create_table) = parse_create_table(
+            "CREATE TABLE companies (id INTEGER PRIMARY KEY, name TEXT, country TEXT, size INT)",
+        )
+        .unwrap();
+        let (_, create_index) =
+            parse_create_index("CREATE INDEX idx ON companies (country, name)").unwrap();
+        let covering = |sql| {
+            let (_, query) = parse_query(sql).unwrap();
+            covering_columns(&create_table, &create_index, &query)
+        };
+
+        assert_eq!(
+            covering("SELECT name, id FROM companies WHERE country = 'chad'"),
+            Some(vec![2, 1])
+        );
+        assert_eq!(
+            covering("SELECT count(*) FROM companies WHERE country = 'chad'"),
+            Some(vec![2, 1])
+        );
+        assert_eq!(
+            covering(
+                "SELECT upper(companies.name) FROM companies WHERE country = 'chad' \
+                 AND rowid IN (1, 2) ORDER BY Name"
+            ),
+            Some(vec![2, 1])
+        );
+        assert_eq!(
+            covering("SELECT size FROM companies WHERE country = 'chad'"),
+            None
+        );
+        assert_eq!(
+            covering("SELECT name FROM companies WHERE country = 'chad' ORDER BY size"),
+            None
+        );
+
+        let (_, create_index) =
+            parse_create_index("CREATE INDEX idx ON companies (country, lower(name))").unwrap();
+        let (_, query) =
+            parse_query("SELECT country FROM companies WHERE country = 'chad'").unwrap();
+        assert_eq!(covering_columns(&create_table, &create_index, &query), None);
+    }
+
+    #[test]
+    fn test_find_row_order() {
+        let order = |sql| find_row_order(&parse_query(sql).unwrap().1, Some("id"));
+
+        assert_eq!(order("SELECT name FROM apples"), RowOrder::Any);
+        assert_eq!(
+            order("SELECT name FROM apples ORDER BY rowid"),
+            RowOrder::RowidAscending
+        );
+        assert_eq!(
+            order("SELECT name FROM apples ORDER BY ID DESC LIMIT 5"),
+            RowOrder::RowidDescending
+        );
+        assert_eq!(
+            order("SELECT name FROM apples ORDER BY name"),
+            RowOrder::Sorted
+        );
+        assert_eq!(
+            order("SELECT name FROM apples ORDER BY rowid, name"),
+            RowOrder::Sorted
+        );
+        // "rowid" here is the alias of a result column, not the rowid
+        assert_eq!(
+            order("SELECT name AS rowid FROM apples ORDER BY rowid"),
+            RowOrder::Sorted
+        );
+        assert_eq!(
+            order("SELECT COUNT(*) FROM apples ORDER BY name"),
+            RowOrder::Any
+        );
+    }
+}
@@ -0,0 +1,70 @@
+use anyhow::{bail, Result};
+use sqlite_starter_rust::dot_commands::DotCommandRegistry;
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Where plugins are kept: $SQLITE_RUST_PLUGINS, or else ~/.sqlite_rust/plugins
+pub fn default_dir() -> Option<PathBuf> {
+    match env::var_os("SQLITE_RUST_PLUGINS") {
+        Some(dir) => Some(PathBuf::from(dir)),
+        None => env::var_os("HOME").map(|home| PathBuf::from(home).join(".sqlite_rust/plugins")),
+    }
+}
+
+/// Registers every executable in `dir` as a dot-command named after the file, so that
+/// `.pii-scan users` runs `DIR/pii-scan DATABASE users` and prints what it writes to stdout. A
+/// missing directory just has no plugins in it.
+pub fn load(dir: &Path, registry: &mut DotCommandRegistry) -> Result<()> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if !is_executable(&path) {
+            continue;
+        }
+        let Some(name) = path.file_stem().and_then(|name| name.to_str()) else {
+            continue;
+        };
+
+        let help = format!(".{name} ARGS...  Run the {} plugin", path.display());
+        let name = name.to_string();
+        registry.register(&name, &help, move |context, args| {
+            let result = Command::new(&path)
+                .arg(&context.database.options.path)
+                .args(args)
+                .output()?;
+            context.output.write_all(&result.stdout)?;
+
+            if !result.status.success() {
+                bail!(
+                    "{} failed ({}): {}",
+                    path.display(),
+                    result.status,
+                    String::from_utf8_lossy(&result.stderr).trim()
+                );
+            }
+
+            Ok(())
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::metadata(path)
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
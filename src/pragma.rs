@@ -0,0 +1,338 @@
+use crate::{
+    database::Database,
+    query_parser::{CreateIndex, CreateTable, Pragma},
+    schema::Schema,
+    value::Value,
+};
+use anyhow::{bail, Result};
+
+/// The rows a pragma returns, under the given column names
+#[derive(Debug, PartialEq)]
+pub struct PragmaResult {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+impl PragmaResult {
+    fn new(columns: &[&str], rows: Vec<Vec<Value>>) -> Self {
+        PragmaResult {
+            columns: columns.iter().map(|column| column.to_string()).collect(),
+            rows,
+        }
+    }
+
+    /// A single value, in a column named after the pragma, as the header pragmas return
+    fn value(name: &str, value: Value) -> Self {
+        Self::new(&[name], vec![vec![value]])
+    }
+}
+
+/// Runs one of the pragmas that read the database header or describe the schema, answering from
+/// `schema`. As in SQLite, a table or index that doesn't exist gives no rows rather than an
+/// error. Nothing here writes to the database, so setting a pragma's value is an error.
+/// [pragma](https://www.sqlite.org/pragma.html)
+pub fn run_pragma(database: &Database, schema: &[Schema], pragma: &Pragma) -> Result<PragmaResult> {
+    let name = pragma.name.as_str();
+    if let Some(value) = &pragma.value {
+        bail!("Unhandled PRAGMA {name} = {value}: pragmas can only be read");
+    }
+
+    let argument = || match &pragma.argument {
+        Some(argument) => Ok(argument.as_str()),
+        None => bail!("PRAGMA {name} needs an argument, as in PRAGMA {name}(name)"),
+    };
+    let integer = |value: u32| Value::Integer(value as i64);
+
+    let result = match name {
+        "table_info" => table_info(schema, argument()?)?,
+        "index_list" => index_list(schema, argument()?)?,
+        "index_info" => index_info(schema, argument()?)?,
+        "page_size" => PragmaResult::value(name, integer(database.page_size)),
+        "page_count" => PragmaResult::value(name, integer(database.page_count)),
+        "encoding" => PragmaResult::value(name, Value::Text("UTF-8".to_string())),
+        "freelist_count" => {
+            PragmaResult::value(name, integer(database.header()?.freelist_page_count))
+        }
+        "schema_version" => PragmaResult::value(name, integer(database.header()?.schema_cookie)),
+        // Stored as a signed integer, like the application's own values
+        "user_version" => PragmaResult::value(
+            name,
+            Value::Integer(database.header()?.user_version as i32 as i64),
+        ),
+        name => bail!("Unhandled PRAGMA {name}"),
+    };
+
+    Ok(result)
+}
+
+fn find_table<'a>(schema: &'a [Schema], table_name: &str) -> Option<&'a Schema> {
+    schema
+        .iter()
+        .find(|object| object.is_table() && object.name.eq_ignore_ascii_case(table_name))
+}
+
+/// A row per column: its position, name, declared type, whether it's NOT NULL, its default and
+/// its position in the primary key (counting from 1, or 0 if it isn't part of it). Generated
+/// columns are hidden, as they are from table_info in SQLite.
+fn table_info(schema: &[Schema], table_name: &str) -> Result<PragmaResult> {
+    let columns = ["cid", "name", "type", "notnull", "dflt_value", "pk"];
+    let Some(table) = find_table(schema, table_name) else {
+        return Ok(PragmaResult::new(&columns, vec![]));
+    };
+    let create_table = table.create_table()?;
+
+    let rows = create_table
+        .columns
+        .iter()
+        .filter(|column| column.generated.is_none())
+        .enumerate()
+        .map(|(cid, column)| {
+            let pk = create_table
+                .primary_key
+                .iter()
+                .position(|key_column| key_column.eq_ignore_ascii_case(&column.name))
+                .map_or(0, |position| position + 1);
+
+            vec![
+                Value::Integer(cid as i64),
+                Value::Text(column.name.clone()),
+                Value::Text(column.type_name.clone()),
+                Value::Integer(column.not_null as i64),
+                column.default.clone().map_or(Value::Null, Value::Text),
+                Value::Integer(pk as i64),
+            ]
+        })
+        .collect();
+
+    Ok(PragmaResult::new(&columns, rows))
+}
+
+/// A row per index on the table, the most recently created first: its name, whether it's
+/// UNIQUE, whether it was made by CREATE INDEX ("c"), for a UNIQUE constraint ("u") or for the
+/// primary key ("pk"), and whether it's a partial index
+fn index_list(schema: &[Schema], table_name: &str) -> Result<PragmaResult> {
+    let columns = ["seq", "name", "unique", "origin", "partial"];
+    let Some(table) = find_table(schema, table_name) else {
+        return Ok(PragmaResult::new(&columns, vec![]));
+    };
+    let create_table = table.create_table()?;
+
+    let mut rows = vec![];
+    let indexes = schema
+        .iter()
+        .filter(|object| object.is_index() && object.table_name.eq_ignore_ascii_case(&table.name));
+    for (seq, index) in indexes.rev().enumerate() {
+        let Some(create_index) = table_index(&create_table, index)? else {
+            continue;
+        };
+
+        let origin = match &index.sql {
+            Some(_) => "c",
+            None if is_primary_key(&create_table, &create_index) => "pk",
+            None => "u",
+        };
+        let partial = index.sql.as_deref().is_some_and(is_partial);
+
+        rows.push(vec![
+            Value::Integer(seq as i64),
+            Value::Text(index.name.clone()),
+            Value::Integer(create_index.unique as i64),
+            Value::Text(origin.to_string()),
+            Value::Integer(partial as i64),
+        ]);
+    }
+
+    Ok(PragmaResult::new(&columns, rows))
+}
+
+/// A row per indexed column: its position in the index, its position in the table and its name.
+/// A column that's the rowid is at position -1, and an expression at -2, with no name.
+fn index_info(schema: &[Schema], index_name: &str) -> Result<PragmaResult> {
+    let columns = ["seqno", "cid", "name"];
+    let Some(index) = schema
+        .iter()
+        .find(|object| object.is_index() && object.name.eq_ignore_ascii_case(index_name))
+    else {
+        return Ok(PragmaResult::new(&columns, vec![]));
+    };
+    let Some(table) = find_table(schema, &index.table_name) else {
+        bail!("no such table: {}", index.table_name);
+    };
+    let create_table = table.create_table()?;
+    let Some(create_index) = table_index(&create_table, index)? else {
+        return Ok(PragmaResult::new(&columns, vec![]));
+    };
+
+    let rows = create_index
+        .columns
+        .iter()
+        .enumerate()
+        .map(|(seqno, name)| {
+            let position = create_table
+                .columns
+                .iter()
+                .position(|column| column.name.eq_ignore_ascii_case(name));
+            let (cid, name) = match position {
+                Some(cid) => (
+                    cid as i64,
+                    Value::Text(create_table.columns[cid].name.clone()),
+                ),
+                None if is_rowid_name(name) => (-1, Value::Null),
+                None => (-2, Value::Null),
+            };
+
+            vec![Value::Integer(seqno as i64), Value::Integer(cid), name]
+        })
+        .collect();
+
+    Ok(PragmaResult::new(&columns, rows))
+}
+
+/// The definition of one of the table's indexes, from its CREATE INDEX statement or, for an
+/// index SQLite made for a constraint, from the table's
+fn table_index(create_table: &CreateTable, index: &Schema) -> Result<Option<CreateIndex>> {
+    match index.create_index()? {
+        Some(create_index) => Ok(Some(create_index)),
+        None => Ok(create_table.autoindex(&index.name)),
+    }
+}
+
+fn is_primary_key(create_table: &CreateTable, create_index: &CreateIndex) -> bool {
+    create_table.primary_key.len() == create_index.columns.len()
+        && create_table
+            .primary_key
+            .iter()
+            .zip(&create_index.columns)
+            .all(|(a, b)| a.eq_ignore_ascii_case(b))
+}
+
+/// Whether a CREATE INDEX statement has a WHERE clause after its columns
+fn is_partial(sql: &str) -> bool {
+    sql.rfind(')').is_some_and(|end| {
+        sql[end..]
+            .split(|c: char| !c.is_alphanumeric())
+            .any(|word| word.eq_ignore_ascii_case("WHERE"))
+    })
+}
+
+fn is_rowid_name(name: &str) -> bool {
+    ["rowid", "oid", "_rowid_"]
+        .iter()
+        .any(|rowid| name.eq_ignore_ascii_case(rowid))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema(objects: &[(&str, &str, &str, Option<&str>)]) -> Vec<Schema> {
+        objects
+            .iter()
+            .zip(2..)
+            .map(|(&(kind, name, table_name, sql), root_page)| Schema {
+                kind: kind.to_string(),
+                name: name.to_string(),
+                table_name: table_name.to_string(),
+                root_page,
+                sql: sql.map(str::to_string),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_table_info() {
+        let schema = schema(&[(
+            "table",
+            "t",
+            "t",
+            Some(
+                "CREATE TABLE t (a INT NOT NULL DEFAULT 'x''y', b TEXT DEFAULT (1 + 2), \
+                 c DEFAULT -1, d, PRIMARY KEY (d, b))",
+            ),
+        )]);
+        let text = |s: &str| Value::Text(s.to_string());
+        let int = Value::Integer;
+
+        let result = table_info(&schema, "T").unwrap();
+        assert_eq!(
+            result.columns,
+            ["cid", "name", "type", "notnull", "dflt_value", "pk"]
+        );
+        assert_eq!(
+            result.rows,
+            [
+                [
+                    int(0),
+                    text("a"),
+                    text("INT"),
+                    int(1),
+                    text("'x''y'"),
+                    int(0)
+                ],
+                [
+                    int(1),
+                    text("b"),
+                    text("TEXT"),
+                    int(0),
+                    text("1 + 2"),
+                    int(2)
+                ],
+                [int(2), text("c"), text(""), int(0), text("-1"), int(0)],
+                [int(3), text("d"), text(""), int(0), Value::Null, int(1)],
+            ]
+        );
+
+        assert!(table_info(&schema, "nosuch").unwrap().rows.is_empty());
+    }
+
+    #[test]
+    fn test_index_list_and_info() {
+        let schema = schema(&[
+            (
+                "table",
+                "t",
+                "t",
+                Some("CREATE TABLE t (a TEXT PRIMARY KEY, b, c, UNIQUE (c, b))"),
+            ),
+            ("index", "sqlite_autoindex_t_1", "t", None),
+            ("index", "sqlite_autoindex_t_2", "t", None),
+            (
+                "index",
+                "t_b",
+                "t",
+                Some("CREATE INDEX t_b ON t (b, rowid) WHERE b IS NOT NULL"),
+            ),
+        ]);
+        let text = |s: &str| Value::Text(s.to_string());
+        let int = Value::Integer;
+
+        assert_eq!(
+            index_list(&schema, "t").unwrap().rows,
+            [
+                [int(0), text("t_b"), int(0), text("c"), int(1)],
+                [
+                    int(1),
+                    text("sqlite_autoindex_t_2"),
+                    int(1),
+                    text("u"),
+                    int(0)
+                ],
+                [
+                    int(2),
+                    text("sqlite_autoindex_t_1"),
+                    int(1),
+                    text("pk"),
+                    int(0)
+                ],
+            ]
+        );
+        assert_eq!(
+            index_info(&schema, "sqlite_autoindex_t_2").unwrap().rows,
+            [[int(0), int(2), text("c")], [int(1), int(1), text("b")]]
+        );
+        assert_eq!(
+            index_info(&schema, "t_b").unwrap().rows,
+            [[int(0), int(1), text("b")], [int(1), int(-1), Value::Null]]
+        );
+    }
+}
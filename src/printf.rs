@@ -0,0 +1,538 @@
+//! SQLite's printf(), which the printf() and format() SQL functions and quote() use. Like C's,
+//! with SQLite's additions: %q, %Q and %w for quoting, the "," flag for thousands separators and
+//! the "!" flag for more digits (and a decimal point that's always shown) in reals.
+//! [printf](https://www.sqlite.org/printf.html)
+
+use crate::value::Value;
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// How many significant digits of a real are computed; the rest are zeros, as in SQLite
+const MAX_DIGITS: usize = 16;
+/// How many are computed with the "!" flag
+const MAX_DIGITS_ALTERNATE: usize = 26;
+
+/// A conversion's flags, width and precision
+#[derive(Debug, Default)]
+struct Spec {
+    left: bool,
+    plus: bool,
+    space: bool,
+    zero: bool,
+    alternate: bool,
+    bang: bool,
+    thousands: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+/// Formats `args` as `format` says. Arguments that are missing are taken to be 0 or an empty
+/// string, and the output stops at an unknown conversion, like SQLite's.
+pub fn printf(format: &str, args: &[Value]) -> String {
+    let mut args = args.iter();
+    let mut output = String::new();
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            output.push(c);
+            continue;
+        }
+
+        let mut spec = Spec::default();
+        while let Some(&flag) = chars.peek() {
+            match flag {
+                '-' => spec.left = true,
+                '+' => spec.plus = true,
+                ' ' => spec.space = true,
+                '0' => spec.zero = true,
+                '#' => spec.alternate = true,
+                '!' => spec.bang = true,
+                ',' => spec.thousands = true,
+                _ => break,
+            }
+            chars.next();
+        }
+        if chars.next_if_eq(&'*').is_some() {
+            let width = integer_arg(args.next());
+            spec.left |= width < 0;
+            spec.width = width.unsigned_abs() as usize;
+        } else {
+            spec.width = read_number(&mut chars);
+        }
+        if chars.next_if_eq(&'.').is_some() {
+            spec.precision = Some(if chars.next_if_eq(&'*').is_some() {
+                integer_arg(args.next()).max(0) as usize
+            } else {
+                read_number(&mut chars)
+            });
+        }
+        // Length modifiers mean nothing when every integer is 64 bits
+        while chars.next_if(|&c| c == 'l' || c == 'h').is_some() {}
+
+        let Some(conversion) = chars.next() else {
+            break;
+        };
+        match conversion {
+            'd' | 'i' => {
+                let i = integer_arg(args.next());
+                let digits = integer_digits(i.unsigned_abs(), 10, false, &spec);
+                write_number(&mut output, sign(i < 0, &spec), &digits, &spec);
+            }
+            'u' | 'x' | 'X' | 'o' => {
+                let i = integer_arg(args.next()) as u64;
+                let (radix, prefix) = match conversion {
+                    'x' => (16, "0x"),
+                    'X' => (16, "0X"),
+                    'o' => (8, "0"),
+                    _ => (10, ""),
+                };
+                let digits = integer_digits(i, radix, conversion == 'X', &spec);
+                let prefix = if spec.alternate && i != 0 { prefix } else { "" };
+                write_number(&mut output, prefix, &digits, &spec);
+            }
+            'f' | 'e' | 'E' | 'g' | 'G' => {
+                let r = real_arg(args.next());
+                let digits = real_digits(r.abs(), conversion, &spec);
+                write_number(&mut output, sign(r < 0.0, &spec), &digits, &spec);
+            }
+            'c' => {
+                let text = text_arg(args.next()).unwrap_or_default();
+                let c = text.chars().next().map(String::from).unwrap_or_default();
+                write_padded(&mut output, &c.repeat(spec.precision.unwrap_or(1)), &spec);
+            }
+            's' | 'z' => {
+                let text = text_arg(args.next()).unwrap_or_default();
+                write_padded(&mut output, truncate(&text, &spec), &spec);
+            }
+            'q' | 'Q' | 'w' => {
+                let quoted = match (text_arg(args.next()), conversion) {
+                    (None, 'Q') => "NULL".to_string(),
+                    (None, _) => "(NULL)".to_string(),
+                    (Some(text), 'w') => truncate(&text, &spec).replace('"', "\"\""),
+                    (Some(text), 'q') => truncate(&text, &spec).replace('\'', "''"),
+                    (Some(text), _) => format!("'{}'", truncate(&text, &spec).replace('\'', "''")),
+                };
+                write_padded(&mut output, &quoted, &spec);
+            }
+            '%' => output.push('%'),
+            _ => break,
+        }
+    }
+
+    output
+}
+
+/// Formats a real with the "!" flag's 15 significant digits, or all of them if 15 don't give the
+/// same number back, so that `text.parse()` returns `r`
+pub fn round_trip_real(r: f64) -> String {
+    if r.is_infinite() {
+        // Too large to be read back as anything but infinity
+        return if r > 0.0 { "9.0e+999" } else { "-9.0e+999" }.to_string();
+    }
+    let text = printf("%!.15g", &[Value::Real(r)]);
+    if text.parse() == Ok(r) {
+        return text;
+    }
+
+    // SQLite uses %!.20e, which gives digits past those that matter: the shortest form that reads
+    // back as the same number is used instead
+    let shortest = format!("{r:e}");
+    let (mantissa, exponent) = shortest.split_once('e').unwrap();
+    let exponent: i32 = exponent.parse().unwrap();
+    let point = if mantissa.contains('.') { "" } else { ".0" };
+    format!(
+        "{mantissa}{point}e{}{:02}",
+        if exponent < 0 { '-' } else { '+' },
+        exponent.abs()
+    )
+}
+
+fn read_number(chars: &mut Peekable<Chars>) -> usize {
+    let mut number = 0usize;
+    while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+        number = number.saturating_mul(10).saturating_add(digit as usize);
+        chars.next();
+    }
+
+    number
+}
+
+fn integer_arg(value: Option<&Value>) -> i64 {
+    match value.map(Value::to_numeric) {
+        Some(Value::Integer(i)) => i,
+        Some(Value::Real(r)) => r as i64,
+        _ => 0,
+    }
+}
+
+fn real_arg(value: Option<&Value>) -> f64 {
+    value
+        .and_then(|value| value.to_numeric().as_real())
+        .unwrap_or_default()
+}
+
+/// The argument as text, or None for NULL
+fn text_arg(value: Option<&Value>) -> Option<String> {
+    match value {
+        None => Some(String::new()),
+        Some(Value::Null) => None,
+        Some(value) => Some(value.to_string()),
+    }
+}
+
+fn sign(negative: bool, spec: &Spec) -> &'static str {
+    if negative {
+        "-"
+    } else if spec.plus {
+        "+"
+    } else if spec.space {
+        " "
+    } else {
+        ""
+    }
+}
+
+/// The precision cuts text to that many bytes, or characters with the "!" flag, never splitting
+/// a character
+fn truncate<'a>(text: &'a str, spec: &Spec) -> &'a str {
+    let Some(precision) = spec.precision else {
+        return text;
+    };
+    let end = if spec.bang {
+        text.char_indices().nth(precision).map(|(i, _)| i)
+    } else {
+        (0..=precision.min(text.len()))
+            .rev()
+            .find(|&i| text.is_char_boundary(i))
+    };
+
+    &text[..end.unwrap_or(text.len())]
+}
+
+/// The precision is the least number of digits
+fn integer_digits(i: u64, radix: u32, upper: bool, spec: &Spec) -> String {
+    let mut digits = match radix {
+        16 if upper => format!("{i:X}"),
+        16 => format!("{i:x}"),
+        8 => format!("{i:o}"),
+        _ => i.to_string(),
+    };
+    if let Some(precision) = spec.precision {
+        if digits.len() < precision {
+            digits.insert_str(0, &"0".repeat(precision - digits.len()));
+        }
+    }
+    if spec.thousands && radix == 10 {
+        let grouped: Vec<String> = digits
+            .as_bytes()
+            .rchunks(3)
+            .rev()
+            .map(|group| String::from_utf8_lossy(group).into_owned())
+            .collect();
+        digits = grouped.join(",");
+    }
+
+    digits
+}
+
+/// Formats `r` (not negative) for a %f, %e or %g conversion
+fn real_digits(r: f64, conversion: char, spec: &Spec) -> String {
+    if r.is_nan() {
+        return "NaN".to_string();
+    }
+    if r.is_infinite() {
+        return "Inf".to_string();
+    }
+
+    let max = if spec.bang {
+        MAX_DIGITS_ALTERNATE
+    } else {
+        MAX_DIGITS
+    };
+    let precision = spec.precision.unwrap_or(6);
+    let upper = conversion.is_ascii_uppercase();
+    let mut text = match conversion.to_ascii_lowercase() {
+        'f' => {
+            let (digits, exponent) = fixed_digits(r, precision, max);
+            render_fixed(&digits, exponent, precision)
+        }
+        'e' => {
+            let (digits, exponent) = decimal_digits(r, precision + 1, max);
+            render_exponent(&digits, exponent, precision, upper)
+        }
+        _ => {
+            let precision = precision.max(1);
+            let (digits, exponent) = decimal_digits(r, precision, max);
+            let mut text = if exponent < -4 || exponent >= precision as i32 {
+                render_exponent(&digits, exponent, precision - 1, upper)
+            } else {
+                render_fixed(
+                    &digits,
+                    exponent,
+                    (precision as i32 - 1 - exponent) as usize,
+                )
+            };
+            if !spec.alternate {
+                text = trim_zeros(&text);
+            }
+            if spec.bang && !text.contains('.') {
+                let end = text.find(['e', 'E']).unwrap_or(text.len());
+                text.insert_str(end, ".0");
+            }
+            text
+        }
+    };
+    if spec.alternate && !text.contains('.') {
+        let end = text.find(['e', 'E']).unwrap_or(text.len());
+        text.insert(end, '.');
+    }
+
+    text
+}
+
+/// The first `significant` decimal digits of `r` (not negative), and the power of ten of the
+/// first. Like SQLite, halves are rounded away from zero, and digits past the `max`th are zeros.
+fn decimal_digits(r: f64, significant: usize, max: usize) -> (Vec<u8>, i32) {
+    let computed = significant.clamp(1, max);
+    if r == 0.0 {
+        return (vec![b'0'; significant.max(1)], 0);
+    }
+
+    let (mut digits, mut exponent) = split_exponent(&format!("{r:.*e}", computed - 1));
+    // Rust rounds a half to even, which matters only when the half is exact: then the shortest
+    // form of the number has just the one digit more
+    let (shortest, shortest_exponent) = split_exponent(&format!("{r:e}"));
+    if shortest.len() == computed + 1 && shortest.last() == Some(&b'5') {
+        digits = shortest[..computed].to_vec();
+        exponent = shortest_exponent;
+        match digits.iter().rposition(|&digit| digit != b'9') {
+            Some(i) => {
+                digits[i] += 1;
+                digits[i + 1..].fill(b'0');
+            }
+            None => {
+                digits.fill(b'0');
+                digits[0] = b'1';
+                exponent += 1;
+            }
+        }
+    }
+    digits.resize(significant.max(1), b'0');
+
+    (digits, exponent)
+}
+
+/// The digits of `r` (not negative) to `precision` decimal places, as decimal_digits gives them
+fn fixed_digits(r: f64, precision: usize, max: usize) -> (Vec<u8>, i32) {
+    let (_, exponent) = split_exponent(&format!("{r:e}"));
+    let significant = exponent + 1 + precision as i32;
+    if r == 0.0 || significant > 0 {
+        return decimal_digits(r, significant.max(1) as usize, max);
+    }
+
+    // Less than a unit in the last place, so it rounds to that or to zero
+    let places = -(precision as i32);
+    if significant == 0 && r >= 0.5 * 10f64.powi(places) {
+        (vec![b'1'], places)
+    } else {
+        (vec![b'0'], 0)
+    }
+}
+
+/// Splits Rust's exponent form, "1.25e-3", into the digits and the exponent
+fn split_exponent(text: &str) -> (Vec<u8>, i32) {
+    let (mantissa, exponent) = text.split_once('e').unwrap();
+    let digits = mantissa.bytes().filter(|&b| b != b'.').collect();
+
+    (digits, exponent.parse().unwrap())
+}
+
+/// The digit for 10 to the power of `place`, given the digits and the power of the first
+fn digit_at(digits: &[u8], exponent: i32, place: i32) -> char {
+    let i = exponent - place;
+    match usize::try_from(i).ok().and_then(|i| digits.get(i)) {
+        Some(&digit) => digit as char,
+        None => '0',
+    }
+}
+
+fn render_fixed(digits: &[u8], exponent: i32, precision: usize) -> String {
+    let mut text: String = (0..=exponent.max(0))
+        .rev()
+        .map(|place| digit_at(digits, exponent, place))
+        .collect();
+    if precision > 0 {
+        text.push('.');
+        text.extend((1..=precision as i32).map(|place| digit_at(digits, exponent, -place)));
+    }
+
+    text
+}
+
+/// Like C, the exponent has a sign and at least two digits
+fn render_exponent(digits: &[u8], exponent: i32, precision: usize, upper: bool) -> String {
+    let mut text = String::from(digits[0] as char);
+    if precision > 0 {
+        text.push('.');
+        text.extend((1..=precision).map(|i| digits.get(i).map_or('0', |&digit| digit as char)));
+    }
+    let sign = if exponent < 0 { '-' } else { '+' };
+
+    format!(
+        "{text}{}{sign}{:02}",
+        if upper { 'E' } else { 'e' },
+        exponent.abs()
+    )
+}
+
+/// Removes the zeros that end a fraction, and the point if nothing's left after it
+fn trim_zeros(text: &str) -> String {
+    let (number, exponent) = text.split_at(text.find(['e', 'E']).unwrap_or(text.len()));
+    if !number.contains('.') {
+        return text.to_string();
+    }
+
+    format!(
+        "{}{exponent}",
+        number.trim_end_matches('0').trim_end_matches('.')
+    )
+}
+
+/// Writes a number's sign, or prefix, and digits, padded to the width with spaces or zeros
+fn write_number(output: &mut String, sign: &str, digits: &str, spec: &Spec) {
+    let length = sign.len() + digits.len();
+    if spec.zero && !spec.left && length < spec.width {
+        output.push_str(sign);
+        output.push_str(&"0".repeat(spec.width - length));
+        output.push_str(digits);
+    } else {
+        write_padded(output, &format!("{sign}{digits}"), spec);
+    }
+}
+
+/// Writes text padded with spaces to the width, which counts bytes, or characters with the "!"
+/// flag
+fn write_padded(output: &mut String, text: &str, spec: &Spec) {
+    let length = if spec.bang {
+        text.chars().count()
+    } else {
+        text.len()
+    };
+    let padding = " ".repeat(spec.width.saturating_sub(length));
+    if spec.left {
+        output.push_str(text);
+        output.push_str(&padding);
+    } else {
+        output.push_str(&padding);
+        output.push_str(text);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_integers() {
+        let args: Vec<Value> = [42, 42, 42, 42, 42, 1234567, 255, 255, 8, 255]
+            .into_iter()
+            .map(Value::Integer)
+            .collect();
+        assert_eq!(
+            printf("%d|%5d|%-5d|%05d|%+d|%,d|%x|%X|%o|%#x", &args),
+            "42|   42|42   |00042|+42|1,234,567|ff|FF|10|0xff"
+        );
+        let args = [Value::Integer(-5), Value::Integer(-5), Value::Real(3.9)];
+        assert_eq!(printf("%i|%u|%d", &args), "-5|18446744073709551611|3");
+        assert_eq!(
+            printf("%.3d|%,d|%,d", &[7, -1234, 999].map(Value::Integer)),
+            "007|-1,234|999"
+        );
+        // Missing and non-numeric arguments are 0
+        assert_eq!(
+            printf(
+                "%d %s",
+                &[Value::Text("abc".to_string()), Value::Integer(12)]
+            ),
+            "0 12"
+        );
+        assert_eq!(printf("%d", &[]), "0");
+        assert_eq!(
+            printf("%*d|%-*d|", &[3, 1, 3, 2].map(Value::Integer)),
+            "  1|2  |"
+        );
+    }
+
+    #[test]
+    fn test_reals() {
+        let args = [
+            1.23456,
+            2.5,
+            12345.678,
+            0.0001,
+            1e-5,
+            123456789.0,
+            1.23456,
+            1e-10,
+        ];
+        assert_eq!(
+            printf("%f|%.2f|%e|%g|%g|%g|%10.3f|%G", &args.map(Value::Real)),
+            "1.234560|2.50|1.234568e+04|0.0001|1e-05|1.23457e+08|     1.235|1E-10"
+        );
+        assert_eq!(
+            printf(
+                "%!.15g|%!g|%.3g|%#g",
+                &[100.0, 1e20, 1234.5, 1.0].map(Value::Real)
+            ),
+            "100.0|1.0e+20|1.23e+03|1.00000"
+        );
+        assert_eq!(
+            printf(
+                "% d|%+.2e|%.0f|%05.1f",
+                &[5.0, 12345.0, 2.5, -2.5].map(Value::Real)
+            ),
+            " 5|+1.23e+04|3|-02.5"
+        );
+        assert_eq!(printf("%5.1f%%", &[Value::Real(99.44)]), " 99.4%");
+        assert_eq!(
+            printf("%.20f", &[Value::Real(0.1)]),
+            "0.10000000000000000000"
+        );
+        assert_eq!(
+            printf("%.3f|%.2f", &[0.0006, 0.004].map(Value::Real)),
+            "0.001|0.00"
+        );
+        assert_eq!(printf("%f", &[Value::Real(f64::INFINITY)]), "Inf");
+    }
+
+    #[test]
+    fn test_text() {
+        let args = ["hi", "hi", "hi", "hello", "it's", "it's"].map(|s| Value::Text(s.to_string()));
+        let mut args = args.to_vec();
+        args.extend([Value::Null, Value::Text("a\"b".to_string())]);
+        args.push(Value::Text("xyz".to_string()));
+        assert_eq!(
+            printf("%s|%10s|%-10s|%.2s|%q|%Q|%Q|%w|%c|%%", &args),
+            "hi|        hi|hi        |he|it''s|'it''s'|NULL|a\"\"b|x|%"
+        );
+        assert_eq!(printf("%q|%s|", &[Value::Null, Value::Null]), "(NULL)||");
+        assert_eq!(
+            printf("%5s|%.1s", &["é", "é"].map(|s| Value::Text(s.into()))),
+            "   é|"
+        );
+        assert_eq!(printf("%.3c", &[Value::Text("x".to_string())]), "xxx");
+        // An unknown conversion ends the output
+        assert_eq!(printf("%z %y %d", &[Value::Text("a".to_string())]), "a ");
+    }
+
+    #[test]
+    fn test_round_trip_real() {
+        assert_eq!(round_trip_real(100.0), "100.0");
+        assert_eq!(round_trip_real(1e20), "1.0e+20");
+        assert_eq!(round_trip_real(0.1), "0.1");
+        assert_eq!(round_trip_real(-2.5e-7), "-2.5e-07");
+        assert_eq!(round_trip_real(1.0 / 3.0), "3.333333333333333e-01");
+        assert_eq!(round_trip_real(f64::INFINITY), "9.0e+999");
+        assert_eq!(round_trip_real(-0.0), "0.0");
+    }
+}
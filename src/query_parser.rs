@@ -1,172 +1,2029 @@
 // Ref: https://dzone.com/articles/the-internal-architecture-of-the-sqlite-database
 
-use itertools::Itertools;
+use crate::value::Value;
+use itertools::{Either, Itertools};
 use nom::{
     branch::alt,
-    bytes::complete::{tag, tag_no_case, take_till},
-    character::complete::{alphanumeric1, multispace0, multispace1},
-    combinator::opt,
-    multi::{many_till, separated_list1},
-    sequence::{delimited, pair, separated_pair},
+    bytes::complete::{tag_no_case, take_till, take_while, take_while1},
+    character::complete::{
+        char, digit0, digit1, hex_digit1, multispace0, multispace1, one_of, satisfy,
+    },
+    combinator::{consumed, cut, eof, map, map_res, not, opt, peek, recognize, verify},
+    multi::{many0, separated_list0, separated_list1},
+    sequence::{delimited, pair, preceded, terminated, tuple},
     IResult,
 };
+use std::fmt;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum FunctionArgument {
     All,
     Columns(Vec<String>),
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Function {
     Count(FunctionArgument),
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOperator {
+    /// `||`, string concatenation
+    Concat,
+    Multiply,
+    Divide,
+    Remainder,
+    Add,
+    Subtract,
+    /// `=` or `==`
+    Equals,
+    /// `!=` or `<>`
+    NotEquals,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    /// `IS`, which is like `=` except that NULL is equal to NULL, so it's never NULL itself
+    Is,
+    IsNot,
+    And,
+    Or,
+}
+
+impl BinaryOperator {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            BinaryOperator::Concat => "||",
+            BinaryOperator::Multiply => "*",
+            BinaryOperator::Divide => "/",
+            BinaryOperator::Remainder => "%",
+            BinaryOperator::Add => "+",
+            BinaryOperator::Subtract => "-",
+            BinaryOperator::Equals => "=",
+            BinaryOperator::NotEquals => "!=",
+            BinaryOperator::Less => "<",
+            BinaryOperator::LessOrEqual => "<=",
+            BinaryOperator::Greater => ">",
+            BinaryOperator::GreaterOrEqual => ">=",
+            BinaryOperator::Is => "IS",
+            BinaryOperator::IsNot => "IS NOT",
+            BinaryOperator::And => "AND",
+            BinaryOperator::Or => "OR",
+        }
+    }
+
+    /// Whether the operator compares its operands, rather than computing with them
+    pub fn is_comparison(&self) -> bool {
+        matches!(
+            self,
+            BinaryOperator::Equals
+                | BinaryOperator::NotEquals
+                | BinaryOperator::Less
+                | BinaryOperator::LessOrEqual
+                | BinaryOperator::Greater
+                | BinaryOperator::GreaterOrEqual
+                | BinaryOperator::Is
+                | BinaryOperator::IsNot
+        )
+    }
+
+    /// How tightly the operator binds: higher binds tighter. NOT comes between AND and the
+    /// comparisons.
+    fn precedence(&self) -> u8 {
+        match self {
+            BinaryOperator::Concat => 8,
+            BinaryOperator::Multiply | BinaryOperator::Divide | BinaryOperator::Remainder => 7,
+            BinaryOperator::Add | BinaryOperator::Subtract => 6,
+            BinaryOperator::Less
+            | BinaryOperator::LessOrEqual
+            | BinaryOperator::Greater
+            | BinaryOperator::GreaterOrEqual => 5,
+            BinaryOperator::Equals
+            | BinaryOperator::NotEquals
+            | BinaryOperator::Is
+            | BinaryOperator::IsNot => 4,
+            BinaryOperator::And => 2,
+            BinaryOperator::Or => 1,
+        }
+    }
+}
+
+/// How tightly NOT binds, compared with BinaryOperator::precedence
+const NOT_PRECEDENCE: u8 = 3;
+
+/// A constant written into the SQL, typed as it was written: 42 is an integer, '42' is text and
+/// X'2A' is a blob
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+impl From<&Literal> for Value {
+    fn from(literal: &Literal) -> Self {
+        match literal {
+            Literal::Null => Value::Null,
+            Literal::Integer(i) => Value::Integer(*i),
+            Literal::Real(r) => Value::Real(*r),
+            Literal::Text(text) => Value::Text(text.clone()),
+            Literal::Blob(blob) => Value::Blob(blob.clone()),
+        }
+    }
+}
+
+/// The literal a value stands for, of the same type
+impl From<&Value> for Literal {
+    fn from(value: &Value) -> Self {
+        match value {
+            Value::Null => Literal::Null,
+            Value::Integer(i) => Literal::Integer(*i),
+            Value::Real(r) => Literal::Real(*r),
+            Value::Text(text) => Literal::Text(text.clone()),
+            Value::Blob(blob) => Literal::Blob(blob.clone()),
+        }
+    }
+}
+
+/// Writes the literal back out as SQL
+impl fmt::Display for Literal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Literal::Null => write!(f, "NULL"),
+            Literal::Integer(value) => write!(f, "{value}"),
+            Literal::Real(value) => write!(f, "{value:?}"),
+            Literal::Text(text) => write!(f, "'{}'", text.replace('\'', "''")),
+            Literal::Blob(blob) => write!(f, "X'{:02X}'", blob.iter().format("")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expression {
+    Literal(Literal),
+    Column(String),
+    Negate(Box<Expression>),
+    /// `NOT operand`, which is NULL when the operand is
+    Not(Box<Expression>),
+    Binary {
+        operator: BinaryOperator,
+        lhs: Box<Expression>,
+        rhs: Box<Expression>,
+    },
+    FunctionCall {
+        name: String,
+        arguments: Vec<Expression>,
+    },
+    /// `CAST(operand AS type_name)`, which converts the operand to the storage class of the
+    /// affinity the type name has as a column type
+    Cast {
+        operand: Box<Expression>,
+        type_name: String,
+    },
+    /// A bind parameter as written: ?, ?NNN, :name, @name or $name. Until a value is bound to it,
+    /// it's NULL.
+    Parameter(String),
+}
+
+impl Expression {
+    /// Calls `f` with each column the expression refers to, named as it's written
+    pub fn visit_columns<'a>(&'a self, f: &mut impl FnMut(&'a str)) {
+        match self {
+            Expression::Column(name) => f(name),
+            Expression::Negate(operand)
+            | Expression::Not(operand)
+            | Expression::Cast { operand, .. } => operand.visit_columns(f),
+            Expression::Binary { lhs, rhs, .. } => {
+                lhs.visit_columns(f);
+                rhs.visit_columns(f);
+            }
+            Expression::FunctionCall { arguments, .. } => {
+                for argument in arguments {
+                    argument.visit_columns(f);
+                }
+            }
+            Expression::Literal(_) | Expression::Parameter(_) => {}
+        }
+    }
+
+    /// How tightly the expression's outermost operator binds, or None if it has none
+    fn precedence(&self) -> Option<u8> {
+        match self {
+            Expression::Binary { operator, .. } => Some(operator.precedence()),
+            Expression::Not(_) => Some(NOT_PRECEDENCE),
+            _ => None,
+        }
+    }
+
+    /// Writes an operand of an operator that binds as tightly as `parent`, in parentheses if it
+    /// wouldn't otherwise parse back as the same expression
+    fn fmt_operand(&self, f: &mut fmt::Formatter<'_>, parent: u8, rhs: bool) -> fmt::Result {
+        match self.precedence() {
+            Some(precedence) if precedence < parent || (rhs && precedence == parent) => {
+                write!(f, "({self})")
+            }
+            _ => write!(f, "{self}"),
+        }
+    }
+}
+
+/// Writes the expression back out as SQL, which is used to name unaliased result columns
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expression::Literal(literal) => write!(f, "{literal}"),
+            Expression::Column(name) => write!(f, "{name}"),
+            Expression::Negate(operand) => match operand.precedence() {
+                Some(_) => write!(f, "-({operand})"),
+                None => write!(f, "-{operand}"),
+            },
+            Expression::Not(operand) => {
+                write!(f, "NOT ")?;
+                operand.fmt_operand(f, NOT_PRECEDENCE, false)
+            }
+            Expression::Binary { operator, lhs, rhs } => {
+                lhs.fmt_operand(f, operator.precedence(), false)?;
+                write!(f, " {} ", operator.symbol())?;
+                rhs.fmt_operand(f, operator.precedence(), true)
+            }
+            Expression::FunctionCall { name, arguments } => {
+                write!(f, "{name}({})", arguments.iter().join(", "))
+            }
+            Expression::Cast { operand, type_name } => write!(f, "CAST({operand} AS {type_name})"),
+            Expression::Parameter(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Selection {
-    ColumnName(String),
+    Expression(Expression),
     AggregateFunction(Function),
 }
 
-#[derive(Debug, PartialEq)]
+/// An entry of the SELECT list, optionally named with "AS alias"
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResultColumn {
+    pub selection: Selection,
+    pub alias: Option<String>,
+}
+
+impl ResultColumn {
+    /// The column's heading: its alias if it has one, or else the expression itself
+    pub fn name(&self) -> String {
+        if let Some(alias) = &self.alias {
+            return alias.clone();
+        }
+
+        match &self.selection {
+            // Like SQLite, a qualified column is headed by its name alone
+            Selection::Expression(Expression::Column(name)) => match name.split_once('.') {
+                Some((_, column)) => column.to_string(),
+                None => name.clone(),
+            },
+            Selection::Expression(expression) => expression.to_string(),
+            Selection::AggregateFunction(Function::Count(FunctionArgument::All)) => {
+                "COUNT(*)".to_string()
+            }
+            Selection::AggregateFunction(Function::Count(FunctionArgument::Columns(columns))) => {
+                format!("COUNT({})", columns.join(", "))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ComparisonOperator {
+    Equals,
+    /// `X REGEXP Y`, true when the pattern Y matches somewhere in X
+    Regexp,
+    /// `X BETWEEN low AND high`, true when low <= X <= high
+    Between,
+    /// `X IN (v1, v2, ...)`, true when X equals any of the values
+    In,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct AndCondition {
     pub column_name: String,
-    pub value: String,
+    pub operator: ComparisonOperator,
+    /// The literals the column is compared against: one for = and REGEXP, the low and high bounds
+    /// for BETWEEN, or the whole list for IN. Each is a Literal, or a Parameter until a value is
+    /// bound to it.
+    pub values: Vec<Expression>,
+    /// The collation named by a COLLATE clause after the literals, which takes precedence over
+    /// the column's own
+    pub collation: Option<String>,
+    /// For `X IN (SELECT ...)`, the query whose single column gives the values, which is run when
+    /// the outer query is. Until then, `values` is empty.
+    pub subquery: Option<Box<Query>>,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Query {
-    pub selection_list: Vec<Selection>,
+    /// SELECT DISTINCT: leave out rows that duplicate an earlier one
+    pub distinct: bool,
+    pub selection_list: Vec<ResultColumn>,
+    /// The table selected from or, with a subquery, the name its rows go by
     pub from_table: String,
+    /// The database the table is in, when it's named before it, as in FROM aux.apples
+    pub from_schema: Option<String>,
+    /// For FROM (SELECT ...), the query whose result rows are selected from
+    pub from_subquery: Option<Box<Query>>,
     pub and_conditions: Option<Vec<AndCondition>>,
+    /// The rest of the WHERE clause, which isn't simple conditions on columns. Only the rows that
+    /// make it TRUE are kept, not those that make it FALSE or NULL.
+    pub filter: Option<Expression>,
+    /// The SELECTs after this one in a compound SELECT, combined with its rows from left to right
+    pub compound: Vec<CompoundSelect>,
+    /// For a compound SELECT, the ORDER BY and LIMIT of the whole compound
+    pub order_by: Vec<OrderingTerm>,
+    pub limit: Option<usize>,
+}
+
+impl Query {
+    /// The first SELECT of a compound SELECT on its own, without the compound's ORDER BY and LIMIT
+    pub fn left_most(&self) -> Query {
+        Query {
+            compound: vec![],
+            order_by: vec![],
+            limit: None,
+            ..self.clone()
+        }
+    }
+
+    /// The tables the query's SELECTs read from, subqueries and compound members included, each
+    /// with the database it's named as being in, if it is
+    pub fn tables(&self) -> Vec<(Option<&str>, &str)> {
+        let mut tables = vec![];
+        match &self.from_subquery {
+            Some(subquery) => tables.extend(subquery.tables()),
+            None => tables.push((self.from_schema.as_deref(), self.from_table.as_str())),
+        }
+        for condition in self.and_conditions.iter().flatten() {
+            tables.extend(
+                condition
+                    .subquery
+                    .iter()
+                    .flat_map(|subquery| subquery.tables()),
+            );
+        }
+        for member in &self.compound {
+            tables.extend(member.select.tables());
+        }
+
+        tables
+    }
+}
+
+/// How a compound SELECT combines the rows of the SELECTs to the left of the operator with those
+/// of the SELECT to its right. All but UNION ALL leave out duplicate rows.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompoundOperator {
+    UnionAll,
+    Union,
+    /// The rows on the left that are also on the right
+    Intersect,
+    /// The rows on the left that aren't on the right
+    Except,
+}
+
+impl fmt::Display for CompoundOperator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let keyword = match self {
+            CompoundOperator::UnionAll => "UNION ALL",
+            CompoundOperator::Union => "UNION",
+            CompoundOperator::Intersect => "INTERSECT",
+            CompoundOperator::Except => "EXCEPT",
+        };
+        write!(f, "{keyword}")
+    }
+}
+
+/// A SELECT after the first of a compound SELECT, with the operator before it
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompoundSelect {
+    pub operator: CompoundOperator,
+    pub select: Query,
+}
+
+/// A term of an ORDER BY clause
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderingTerm {
+    /// An expression, the alias of a result column, or a result column's number (from 1)
+    pub expression: Expression,
+    /// The collation named by a COLLATE clause, which text is sorted by instead of the column's
+    /// own
+    pub collation: Option<String>,
+    pub descending: bool,
+}
+
+impl fmt::Display for OrderingTerm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.expression)?;
+        if let Some(collation) = &self.collation {
+            write!(f, " COLLATE {collation}")?;
+        }
+        if self.descending {
+            write!(f, " DESC")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    Select(Query),
+    /// EXPLAIN: list the bytecode program the query compiles to
+    Explain(Query),
+    /// EXPLAIN QUERY PLAN: describe how the query's rows will be found
+    ExplainQueryPlan(Query),
+    /// EXPLAIN ANALYZE: run the query, then show its plan as a tree with the number of rows each
+    /// step was expected to produce and actually produced
+    ExplainAnalyze(Query),
+    /// BEGIN [DEFERRED | IMMEDIATE | EXCLUSIVE] [TRANSACTION]
+    Begin,
+    /// COMMIT or END [TRANSACTION]
+    Commit,
+    /// ROLLBACK [TRANSACTION]
+    Rollback,
+    /// SAVEPOINT name: mark a point in the transaction, starting one if there isn't one
+    Savepoint(String),
+    /// RELEASE [SAVEPOINT] name: forget the savepoint and those after it
+    Release(String),
+    /// ROLLBACK [TRANSACTION] TO [SAVEPOINT] name: go back to the savepoint, which is kept
+    RollbackTo(String),
+    /// CREATE INDEX, with the SQL to store in sqlite_schema: as SQLite stores it, with the
+    /// keywords before the index name normalized and IF NOT EXISTS left out
+    CreateIndex {
+        create_index: CreateIndex,
+        sql: String,
+    },
+    Pragma(Pragma),
+    /// ANALYZE [[schema.]table-or-index]: gather statistics on indexes for the planner
+    Analyze(Option<String>),
+    /// VACUUM [schema] [INTO 'file']: rebuild the database in place, or into a new file
+    Vacuum {
+        into: Option<String>,
+    },
+    /// ATTACH [DATABASE] 'file' AS name: open another database, whose tables are then named as
+    /// name.table
+    Attach {
+        path: String,
+        name: String,
+    },
+    /// DETACH [DATABASE] name: close a database opened with ATTACH
+    Detach(String),
+}
+
+/// PRAGMA [schema.]name, with an argument as in "PRAGMA table_info(t)" or a value to set as in
+/// "PRAGMA user_version = 1"
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pragma {
+    /// Lowercased, as pragma names aren't case sensitive
+    pub name: String,
+    pub argument: Option<String>,
+    pub value: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ColumnDefinition {
+    pub name: String,
+    /// The declared type, as written (may be empty)
+    pub type_name: String,
+    /// The collation named by the column's COLLATE constraint, if it has one
+    pub collation: Option<String>,
+    /// Whether the column has a NOT NULL constraint
+    pub not_null: bool,
+    /// The expression of the column's DEFAULT clause, as written, without the parentheses around
+    /// it if it has them
+    pub default: Option<String>,
+    /// The column's GENERATED ALWAYS AS clause, if it's a generated column
+    pub generated: Option<GeneratedColumn>,
+}
+
+/// A column whose value is computed from the others in its row. A VIRTUAL one, the default, isn't
+/// stored in the record but computed as it's read; a STORED one is computed as the row is written.
+/// [generated columns](https://www.sqlite.org/gencol.html)
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneratedColumn {
+    /// The expression, as written, without the parentheses around it
+    pub expression: String,
+    pub stored: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct CreateTable {
+    pub table_name: String,
+    pub columns: Vec<ColumnDefinition>,
+    /// The columns of the primary key, in the order the key lists them, if the table has one
+    pub primary_key: Vec<String>,
+    /// The index of the INTEGER PRIMARY KEY column, if there is one. It's an alias for the rowid,
+    /// so its value isn't stored in the record (which holds NULL instead) but as the rowid.
+    pub rowid_alias: Option<usize>,
+    /// The columns of each PRIMARY KEY and UNIQUE constraint that SQLite backs with an automatic
+    /// index, in the order they're numbered: sqlite_autoindex_TABLE_1, _2 and so on. The primary
+    /// key of a WITHOUT ROWID table takes a number too, though the table itself is its index.
+    pub unique_constraints: Vec<Vec<String>>,
+    /// Whether the rowid alias is declared AUTOINCREMENT, so that rowids are never reused, even
+    /// those of deleted rows. The largest rowid used so far is kept in sqlite_sequence.
+    pub autoincrement: bool,
+}
+
+impl CreateTable {
+    /// The index SQLite created for one of the table's constraints, which has a name like
+    /// sqlite_autoindex_TABLE_N and no CREATE INDEX statement of its own
+    pub fn autoindex(&self, index_name: &str) -> Option<CreateIndex> {
+        let number = index_name
+            .strip_prefix("sqlite_autoindex_")?
+            .strip_prefix(self.table_name.as_str())?
+            .strip_prefix('_')?;
+        let columns = self
+            .unique_constraints
+            .get(number.parse::<usize>().ok()?.checked_sub(1)?)?;
+
+        Some(CreateIndex {
+            index_name: index_name.to_string(),
+            table_name: self.table_name.clone(),
+            columns: columns.clone(),
+            collations: vec![None; columns.len()],
+            descending: vec![false; columns.len()],
+            unique: true,
+            if_not_exists: false,
+            simple_columns: true,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreateIndex {
+    pub index_name: String,
+    pub table_name: String,
+    pub columns: Vec<String>,
+    /// The collation each indexed column names with COLLATE, if any. Without one, the index
+    /// uses the column's own collation.
+    pub collations: Vec<Option<String>>,
+    pub unique: bool,
+    pub if_not_exists: bool,
+    /// Whether each indexed column is sorted DESC, so that its entries go from greatest to least
+    pub descending: Vec<bool>,
+    /// Whether every indexed column is just a column name, possibly with a COLLATE clause and
+    /// ASC or DESC, rather than an expression
+    pub simple_columns: bool,
+}
+
+/// A view: a named query, selected from as though it were a table
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreateView {
+    pub view_name: String,
+    /// The names given to the query's columns, if the view lists them
+    pub columns: Option<Vec<String>>,
+    pub query: Query,
 }
 
+/// Keywords that end a column's type name and begin its column constraints
+const COLUMN_CONSTRAINT_KEYWORDS: [&str; 11] = [
+    "CONSTRAINT",
+    "PRIMARY",
+    "NOT",
+    "NULL",
+    "UNIQUE",
+    "CHECK",
+    "DEFAULT",
+    "COLLATE",
+    "REFERENCES",
+    "GENERATED",
+    "AS",
+];
+
+/// Keywords that begin a table constraint, rather than a column definition
+const TABLE_CONSTRAINT_KEYWORDS: [&str; 5] =
+    ["CONSTRAINT", "PRIMARY", "UNIQUE", "CHECK", "FOREIGN"];
+
 // TODO: Use these nom functions:
 // map_opt: Maps a function returning an Option on the output of a parser
 // map_res: Maps a function returning a Result on the output of a parser
 
-fn parse_selection_list(input: &str) -> IResult<&str, Vec<Selection>> {
-    let (input, (raw_selections, _from)) = many_till(
+fn parse_selection_list(input: &str) -> IResult<&str, Vec<ResultColumn>> {
+    let (input, _) = multispace1(input)?;
+    let (input, result_columns) = separated_list1(
+        tuple((multispace0, char(','), multispace0)),
+        parse_result_column,
+    )(input)?;
+    let (input, _) = pair(multispace0, tag_no_case("FROM"))(input)?;
+
+    Ok((input, result_columns))
+}
+
+fn parse_result_column(input: &str) -> IResult<&str, ResultColumn> {
+    let (input, selection) = alt((
+        map(tag_no_case("COUNT(*)"), |_| {
+            Selection::AggregateFunction(Function::Count(FunctionArgument::All))
+        }),
+        map(parse_expression, Selection::Expression),
+    ))(input)?;
+    let (input, alias) = opt(parse_alias)(input)?;
+
+    Ok((input, ResultColumn { selection, alias }))
+}
+
+/// Parses "AS alias", or just "alias" as long as it isn't the FROM that ends the SELECT list
+fn parse_alias(input: &str) -> IResult<&str, String> {
+    alt((
+        preceded(
+            tuple((multispace1, tag_no_case("AS"), multispace1)),
+            parse_identifier,
+        ),
+        preceded(
+            multispace1,
+            verify(parse_identifier, |alias: &str| {
+                !alias.eq_ignore_ascii_case("FROM")
+            }),
+        ),
+    ))(input)
+}
+
+const OR_OPERATORS: &[(&str, BinaryOperator)] = &[("OR", BinaryOperator::Or)];
+const AND_OPERATORS: &[(&str, BinaryOperator)] = &[("AND", BinaryOperator::And)];
+const EQUALITY_OPERATORS: &[(&str, BinaryOperator)] = &[
+    ("==", BinaryOperator::Equals),
+    ("=", BinaryOperator::Equals),
+    ("!=", BinaryOperator::NotEquals),
+    ("<>", BinaryOperator::NotEquals),
+    ("IS NOT", BinaryOperator::IsNot),
+    ("IS", BinaryOperator::Is),
+];
+const RELATIONAL_OPERATORS: &[(&str, BinaryOperator)] = &[
+    ("<=", BinaryOperator::LessOrEqual),
+    ("<", BinaryOperator::Less),
+    (">=", BinaryOperator::GreaterOrEqual),
+    (">", BinaryOperator::Greater),
+];
+const ADDITIVE_OPERATORS: &[(&str, BinaryOperator)] =
+    &[("+", BinaryOperator::Add), ("-", BinaryOperator::Subtract)];
+const MULTIPLICATIVE_OPERATORS: &[(&str, BinaryOperator)] = &[
+    ("*", BinaryOperator::Multiply),
+    ("/", BinaryOperator::Divide),
+    ("%", BinaryOperator::Remainder),
+];
+const CONCAT_OPERATORS: &[(&str, BinaryOperator)] = &[("||", BinaryOperator::Concat)];
+
+/// Parses an expression: literals, columns and function calls, combined with arithmetic, string
+/// concatenation, comparisons and logic. Like in SQLite, `||` binds tightest, then `*`, `/` and
+/// `%`, then `+` and `-`, then `<`, `<=`, `>` and `>=`, then `=`, `!=`, IS and IS NOT, then NOT,
+/// AND and, loosest of all, OR.
+pub fn parse_expression(input: &str) -> IResult<&str, Expression> {
+    parse_binary_operators(input, parse_conjunction, OR_OPERATORS)
+}
+
+fn parse_conjunction(input: &str) -> IResult<&str, Expression> {
+    parse_binary_operators(input, parse_not, AND_OPERATORS)
+}
+
+fn parse_not(input: &str) -> IResult<&str, Expression> {
+    alt((
+        map(
+            preceded(pair(keyword("NOT"), multispace0), parse_not),
+            |e| Expression::Not(Box::new(e)),
+        ),
+        parse_equality,
+    ))(input)
+}
+
+fn parse_equality(input: &str) -> IResult<&str, Expression> {
+    parse_binary_operators(input, parse_relational, EQUALITY_OPERATORS)
+}
+
+fn parse_relational(input: &str) -> IResult<&str, Expression> {
+    parse_binary_operators(input, parse_additive, RELATIONAL_OPERATORS)
+}
+
+fn parse_additive(input: &str) -> IResult<&str, Expression> {
+    parse_binary_operators(input, parse_multiplicative, ADDITIVE_OPERATORS)
+}
+
+fn parse_multiplicative(input: &str) -> IResult<&str, Expression> {
+    parse_binary_operators(input, parse_concatenation, MULTIPLICATIVE_OPERATORS)
+}
+
+fn parse_concatenation(input: &str) -> IResult<&str, Expression> {
+    parse_binary_operators(input, parse_unary, CONCAT_OPERATORS)
+}
+
+/// Parses a keyword, which can't run on into a longer word: NOT, but not the start of NOTE
+fn keyword<'a>(word: &'static str) -> impl FnMut(&'a str) -> IResult<&'a str, &'a str> {
+    terminated(
+        tag_no_case(word),
+        not(satisfy(|c| c.is_alphanumeric() || c == '_')),
+    )
+}
+
+/// Finds which of `operators` the input starts with, returning the rest of the input after it. An
+/// operator written as words, like IS NOT, matches them whatever their case and spacing.
+fn match_operator<'a>(
+    input: &'a str,
+    operators: &[(&'static str, BinaryOperator)],
+) -> Option<(&'a str, BinaryOperator)> {
+    operators.iter().find_map(|&(symbol, operator)| {
+        if !symbol.starts_with(|c: char| c.is_alphabetic()) {
+            return input.strip_prefix(symbol).map(|rest| (rest, operator));
+        }
+
+        let mut rest = input;
+        for (i, word) in symbol.split(' ').enumerate() {
+            if i > 0 {
+                rest = multispace1::<_, nom::error::Error<_>>(rest).ok()?.0;
+            }
+            rest = keyword(word)(rest).ok()?.0;
+        }
+        Some((rest, operator))
+    })
+}
+
+/// Parses a left-associative chain of operands joined by operators of the same precedence
+fn parse_binary_operators<'a>(
+    input: &'a str,
+    parse_operand: fn(&str) -> IResult<&str, Expression>,
+    operators: &[(&'static str, BinaryOperator)],
+) -> IResult<&'a str, Expression> {
+    let (mut input, mut expression) = parse_operand(input)?;
+
+    loop {
+        let (rest, _) = multispace0(input)?;
+        let Some((rest, operator)) = match_operator(rest, operators) else {
+            break;
+        };
+
+        let (rest, _) = multispace0(rest)?;
+        match parse_operand(rest) {
+            Ok((rest, rhs)) => {
+                expression = Expression::Binary {
+                    operator,
+                    lhs: Box::new(expression),
+                    rhs: Box::new(rhs),
+                };
+                input = rest;
+            }
+            Err(nom::Err::Error(_)) => break,
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok((input, expression))
+}
+
+fn parse_unary(input: &str) -> IResult<&str, Expression> {
+    alt((
+        map(preceded(pair(char('-'), multispace0), parse_unary), |e| {
+            Expression::Negate(Box::new(e))
+        }),
+        preceded(pair(char('+'), multispace0), parse_unary),
+        parse_primary,
+    ))(input)
+}
+
+fn parse_primary(input: &str) -> IResult<&str, Expression> {
+    alt((
+        map(parse_numeric_literal, Expression::Literal),
+        map(parse_blob_literal, Expression::Literal),
+        map(parse_string_literal, |text| {
+            Expression::Literal(Literal::Text(text))
+        }),
+        delimited(
+            pair(char('('), multispace0),
+            parse_expression,
+            pair(multispace0, char(')')),
+        ),
+        map(parse_null, Expression::Literal),
+        parse_cast,
+        parse_function_call,
+        parse_parameter,
+        map(parse_column_reference, Expression::Column),
+    ))(input)
+}
+
+fn parse_null(input: &str) -> IResult<&str, Literal> {
+    map(
+        terminated(
+            tag_no_case("NULL"),
+            not(satisfy(|c| c.is_alphanumeric() || c == '_')),
+        ),
+        |_| Literal::Null,
+    )(input)
+}
+
+/// Parses a bind parameter: ?, ?NNN, or a name prefixed with :, @ or $
+fn parse_parameter(input: &str) -> IResult<&str, Expression> {
+    map(
+        alt((
+            recognize(pair(char('?'), digit0)),
+            recognize(pair(one_of(":@$"), parse_identifier)),
+        )),
+        |parameter: &str| Expression::Parameter(parameter.to_string()),
+    )(input)
+}
+
+/// Parses an unsigned number: an integer, a real, or a hex integer like 0x1F
+fn parse_numeric_literal(input: &str) -> IResult<&str, Literal> {
+    alt((parse_hex_literal, parse_decimal_literal))(input)
+}
+
+/// Parses a hex integer of up to 16 digits, whose 64 bits are a two's complement integer as in
+/// SQLite, so 0xFFFFFFFFFFFFFFFF is -1
+fn parse_hex_literal(input: &str) -> IResult<&str, Literal> {
+    // After 0x, it can't be anything else, so a bad hex integer is an error rather than a 0
+    preceded(
+        pair(char('0'), one_of("xX")),
+        cut(map_res(hex_digit1, |hex: &str| {
+            u64::from_str_radix(hex, 16).map(|i| Literal::Integer(i as i64))
+        })),
+    )(input)
+}
+
+fn parse_decimal_literal(input: &str) -> IResult<&str, Literal> {
+    let (rest, literal) = recognize(pair(
+        alt((
+            recognize(pair(digit1, opt(pair(char('.'), digit0)))),
+            recognize(pair(char('.'), digit1)),
+        )),
+        opt(tuple((one_of("eE"), opt(one_of("+-")), digit1))),
+    ))(input)?;
+
+    // Integers too big for 64 bits are read as reals, as SQLite does
+    let value = match literal.parse() {
+        Ok(i) => Literal::Integer(i),
+        Err(_) => match literal.parse() {
+            Ok(r) => Literal::Real(r),
+            Err(_) => {
+                return Err(nom::Err::Error(nom::error::Error::new(
+                    input,
+                    nom::error::ErrorKind::Float,
+                )))
+            }
+        },
+    };
+
+    Ok((rest, value))
+}
+
+/// Parses a blob literal: X'...' with an even number of hex digits, two for each byte
+fn parse_blob_literal(input: &str) -> IResult<&str, Literal> {
+    map_res(
         delimited(
-            alt((multispace1, tag(","))),
-            alt((tag_no_case("COUNT(*)"), alphanumeric1)),
-            alt((multispace1, tag(","))),
+            pair(one_of("xX"), char('\'')),
+            take_while(|c: char| c.is_ascii_hexdigit()),
+            char('\''),
         ),
-        tag_no_case("from"),
+        |hex: &str| {
+            if !hex.len().is_multiple_of(2) {
+                return Err("a blob literal needs two hex digits per byte");
+            }
+            (0..hex.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| "invalid hex digit"))
+                .collect::<Result<Vec<u8>, _>>()
+                .map(Literal::Blob)
+        },
+    )(input)
+}
+
+/// Parses a single-quoted string literal, in which '' stands for a single quote
+fn parse_string_literal(input: &str) -> IResult<&str, String> {
+    parse_quoted(input, '\'')
+}
+
+/// Parses text between two `quote`s, in which a doubled quote stands for a single one
+fn parse_quoted(input: &str, quote: char) -> IResult<&str, String> {
+    let (input, _) = char(quote)(input)?;
+
+    let mut value = String::new();
+    let mut chars = input.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if c != quote {
+            value.push(c);
+        } else if chars.next_if(|(_, c)| *c == quote).is_some() {
+            value.push(quote);
+        } else {
+            return Ok((&input[i + quote.len_utf8()..], value));
+        }
+    }
+
+    Err(nom::Err::Error(nom::error::Error::new(
+        input,
+        nom::error::ErrorKind::Char,
+    )))
+}
+
+fn parse_function_call(input: &str) -> IResult<&str, Expression> {
+    let (input, name) = take_while1(|c: char| c.is_alphanumeric() || c == '_')(input)?;
+    let (input, _) = tuple((multispace0, char('('), multispace0))(input)?;
+    let (input, arguments) = separated_list0(
+        tuple((multispace0, char(','), multispace0)),
+        parse_expression,
     )(input)?;
+    let (input, _) = pair(multispace0, char(')'))(input)?;
 
-    let selections = raw_selections
-        .iter()
-        .map(|raw_selection| raw_selection.to_lowercase())
-        .map(|raw_selection| match raw_selection.as_str() {
-            "count(*)" => Selection::AggregateFunction(Function::Count(FunctionArgument::All)),
-            s => Selection::ColumnName(s.to_string()),
-        })
-        .collect_vec();
+    Ok((
+        input,
+        Expression::FunctionCall {
+            name: name.to_lowercase(),
+            arguments,
+        },
+    ))
+}
+
+/// Parses CAST(expression AS type), where the type is written as a column's would be, like
+/// VARCHAR(10) or UNSIGNED BIG INT
+fn parse_cast(input: &str) -> IResult<&str, Expression> {
+    let (input, _) = tuple((tag_no_case("CAST"), multispace0, char('('), multispace0))(input)?;
+    let (input, operand) = parse_expression(input)?;
+    let (input, _) = tuple((multispace1, tag_no_case("AS"), multispace1))(input)?;
+    let (input, words) = separated_list1(
+        multispace1,
+        take_while1(|c: char| c.is_alphanumeric() || c == '_'),
+    )(input)?;
+    let (input, size) = opt(preceded(pair(multispace0, char('(')), parenthesized_body))(input)?;
+    let (input, _) = pair(multispace0, char(')'))(input)?;
+
+    let mut type_name = words.join(" ");
+    if let Some(size) = size {
+        type_name.push_str(&format!("({size})"));
+    }
 
-    Ok((input, selections))
+    Ok((
+        input,
+        Expression::Cast {
+            operand: Box::new(operand),
+            type_name,
+        },
+    ))
 }
 
-fn parse_where_conditions(input: &str) -> IResult<&str, Vec<AndCondition>> {
+/// Parses a WHERE clause into the simple conditions on columns that it ANDs together, which
+/// indexes can help with, and an expression for the rest of it, if there's more. Under an OR, no
+/// condition has to hold for every row, so the whole clause is the expression.
+fn parse_where(input: &str) -> IResult<&str, (Vec<AndCondition>, Option<Expression>)> {
     let (input, (_, _)) = pair(tag_no_case("WHERE"), multispace1)(input)?;
 
-    // TODO: Handle ORs?
-    let (input, raw_conditions) = separated_list1(
-        delimited(multispace0, tag_no_case("AND"), multispace0),
-        separated_pair(
-            take_till(|c| c == ' '),
-            delimited(
-                multispace0,
-                nom::character::complete::char('='),
-                multispace0,
+    let conjuncts = separated_list1(
+        delimited(multispace0, keyword("AND"), multispace0),
+        alt((
+            map(parse_condition, Either::Left),
+            map(parse_not, Either::Right),
+        )),
+    );
+    alt((
+        map(
+            terminated(conjuncts, not(preceded(multispace0, keyword("OR")))),
+            |terms| {
+                let (conditions, expressions): (Vec<_>, Vec<_>) =
+                    terms.into_iter().partition_map(|term| term);
+                let filter = expressions
+                    .into_iter()
+                    .reduce(|lhs, rhs| Expression::Binary {
+                        operator: BinaryOperator::And,
+                        lhs: Box::new(lhs),
+                        rhs: Box::new(rhs),
+                    });
+                (conditions, filter)
+            },
+        ),
+        map(parse_expression, |expression| (vec![], Some(expression))),
+    ))(input)
+}
+
+/// Parses a simple condition on a column, as long as it's a whole term of the WHERE clause and
+/// not the start of a bigger expression, as `a = 1` is in `a = 1 + b`
+fn parse_condition(input: &str) -> IResult<&str, AndCondition> {
+    let (rest, condition) = alt((
+        map(
+            tuple((
+                parse_column_reference,
+                preceded(
+                    tuple((multispace0, tag_no_case("IN"), multispace0)),
+                    parse_subquery,
+                ),
+            )),
+            |(column_name, subquery)| AndCondition {
+                column_name,
+                operator: ComparisonOperator::In,
+                values: vec![],
+                collation: None,
+                subquery: Some(Box::new(subquery)),
+            },
+        ),
+        map(
+            tuple((
+                parse_column_reference,
+                parse_predicate,
+                opt(preceded(multispace1, parse_collate)),
+            )),
+            |(column_name, (operator, values), collation)| AndCondition {
+                column_name,
+                operator,
+                values,
+                collation,
+                subquery: None,
+            },
+        ),
+    ))(input)?;
+
+    let (after, _) = multispace0(rest)?;
+    let continues = [
+        EQUALITY_OPERATORS,
+        RELATIONAL_OPERATORS,
+        ADDITIVE_OPERATORS,
+        MULTIPLICATIVE_OPERATORS,
+        CONCAT_OPERATORS,
+    ]
+    .iter()
+    .any(|operators| match_operator(after, operators).is_some());
+    if continues {
+        return Err(nom::Err::Error(nom::error::Error::new(
+            input,
+            nom::error::ErrorKind::Verify,
+        )));
+    }
+
+    Ok((rest, condition))
+}
+
+/// Parses what follows the column name in a WHERE condition: the operator and its literals
+fn parse_predicate(input: &str) -> IResult<&str, (ComparisonOperator, Vec<Expression>)> {
+    let single = |operator| {
+        move |input| {
+            map(preceded(multispace0, parse_literal), |value| {
+                (operator, vec![value])
+            })(input)
+        }
+    };
+
+    preceded(
+        multispace0,
+        alt((
+            preceded(char('='), single(ComparisonOperator::Equals)),
+            preceded(tag_no_case("REGEXP"), single(ComparisonOperator::Regexp)),
+            map(
+                tuple((
+                    tag_no_case("BETWEEN"),
+                    delimited(multispace1, parse_literal, multispace1),
+                    tag_no_case("AND"),
+                    preceded(multispace1, parse_literal),
+                )),
+                |(_, low, _, high)| (ComparisonOperator::Between, vec![low, high]),
             ),
-            delimited(
-                nom::character::complete::char('\''),
-                take_till(|c| c == '\''),
-                nom::character::complete::char('\''),
+            map(
+                preceded(
+                    pair(tag_no_case("IN"), multispace0),
+                    delimited(
+                        pair(char('('), multispace0),
+                        separated_list0(
+                            tuple((multispace0, char(','), multispace0)),
+                            parse_literal,
+                        ),
+                        pair(multispace0, char(')')),
+                    ),
+                ),
+                |values| (ComparisonOperator::In, values),
             ),
+        )),
+    )(input)
+}
+
+/// Parses a literal in a WHERE condition: a 'quoted string', a number with an optional sign, a
+/// blob, NULL or a bind parameter
+fn parse_literal(input: &str) -> IResult<&str, Expression> {
+    alt((
+        map(
+            alt((
+                parse_null,
+                parse_blob_literal,
+                map(parse_string_literal, Literal::Text),
+                map(
+                    preceded(char('-'), consumed(parse_numeric_literal)),
+                    |(text, literal)| match literal {
+                        Literal::Integer(i) => Literal::Integer(i.wrapping_neg()),
+                        // -9223372036854775808 is an integer, though its digits alone are too
+                        // big for one
+                        Literal::Real(r) => match format!("-{text}").parse() {
+                            Ok(i) => Literal::Integer(i),
+                            Err(_) => Literal::Real(-r),
+                        },
+                        literal => literal,
+                    },
+                ),
+                preceded(opt(char('+')), parse_numeric_literal),
+            )),
+            Expression::Literal,
         ),
-    )(input)?;
+        parse_parameter,
+    ))(input)
+}
 
-    let conditions = raw_conditions
-        .iter()
-        .map(|c| AndCondition {
-            column_name: c.0.to_string(),
-            value: c.1.to_string(),
-        })
-        .collect_vec();
+fn parse_order_by(input: &str) -> IResult<&str, Vec<OrderingTerm>> {
+    let direction = alt((
+        map(tag_no_case("ASC"), |_| false),
+        map(tag_no_case("DESC"), |_| true),
+    ));
+    let ordering_term = map(
+        tuple((
+            parse_expression,
+            opt(preceded(multispace1, parse_collate)),
+            opt(preceded(
+                multispace1,
+                terminated(direction, not(satisfy(|c| c.is_alphanumeric() || c == '_'))),
+            )),
+        )),
+        |(expression, collation, descending)| OrderingTerm {
+            expression,
+            collation,
+            descending: descending.unwrap_or(false),
+        },
+    );
+
+    preceded(
+        tuple((
+            multispace0,
+            tag_no_case("ORDER"),
+            multispace1,
+            tag_no_case("BY"),
+            multispace1,
+        )),
+        separated_list1(tuple((multispace0, char(','), multispace0)), ordering_term),
+    )(input)
+}
+
+/// Parses a COLLATE clause, giving the name of its collation
+fn parse_collate(input: &str) -> IResult<&str, String> {
+    preceded(pair(tag_no_case("COLLATE"), multispace1), parse_identifier)(input)
+}
 
-    Ok((input, conditions))
+fn parse_limit(input: &str) -> IResult<&str, usize> {
+    preceded(
+        tuple((multispace0, tag_no_case("LIMIT"), multispace1)),
+        map_res(digit1, str::parse),
+    )(input)
 }
 
+/// Parses a SELECT, or a compound SELECT, whose ORDER BY and LIMIT apply to the whole compound
 pub fn parse_query(input: &str) -> IResult<&str, Query> {
+    let (input, mut query) = parse_select_core(input)?;
+    let (input, compound) = many0(map(
+        pair(parse_compound_operator, parse_select_core),
+        |(operator, select)| CompoundSelect { operator, select },
+    ))(input)?;
+    let (input, order_by) = opt(parse_order_by)(input)?;
+    let (input, limit) = opt(parse_limit)(input)?;
+    let (input, _) = multispace0(input)?;
+
+    query.compound = compound;
+    query.order_by = order_by.unwrap_or_default();
+    query.limit = limit;
+
+    Ok((input, query))
+}
+
+fn parse_compound_operator(input: &str) -> IResult<&str, CompoundOperator> {
+    delimited(
+        multispace0,
+        alt((
+            map(
+                tuple((tag_no_case("UNION"), multispace1, tag_no_case("ALL"))),
+                |_| CompoundOperator::UnionAll,
+            ),
+            map(tag_no_case("UNION"), |_| CompoundOperator::Union),
+            map(tag_no_case("INTERSECT"), |_| CompoundOperator::Intersect),
+            map(tag_no_case("EXCEPT"), |_| CompoundOperator::Except),
+        )),
+        multispace1,
+    )(input)
+}
+
+/// Parses a SELECT up to its WHERE clause: all there is to each SELECT of a compound SELECT
+fn parse_select_core(input: &str) -> IResult<&str, Query> {
     let (input, _) = multispace0(input)?;
     let (input, _) = tag_no_case("SELECT")(input)?;
+    let (input, distinct) = opt(terminated(
+        preceded(
+            multispace1,
+            alt((
+                map(tag_no_case("DISTINCT"), |_| true),
+                map(tag_no_case("ALL"), |_| false),
+            )),
+        ),
+        peek(multispace1),
+    ))(input)?;
     let (input, selection_list) = parse_selection_list(input)?;
-    let (input, from_table) = delimited(multispace0, alphanumeric1, multispace0)(input)?;
-    let (input, conditions) = opt(parse_where_conditions)(input)?;
-    let (input, _) = multispace0(input)?;
+    let (input, ((from_table, from_subquery), from_schema)) = alt((
+        map(parse_subquery_source, |source| (source, None)),
+        map(
+            delimited(
+                multispace0,
+                pair(
+                    opt(terminated(parse_identifier, char('.'))),
+                    parse_identifier,
+                ),
+                multispace0,
+            ),
+            |(schema, table)| ((table, None), schema),
+        ),
+    ))(input)?;
+    let (input, where_clause) = opt(parse_where)(input)?;
+    let (conditions, filter) = where_clause.unwrap_or_default();
 
     Ok((
         input,
         Query {
+            distinct: distinct.unwrap_or(false),
             selection_list,
-            from_table: from_table.to_string(),
-            and_conditions: conditions,
+            from_table,
+            from_schema,
+            from_subquery,
+            and_conditions: (!conditions.is_empty()).then_some(conditions),
+            filter,
+            compound: vec![],
+            order_by: vec![],
+            limit: None,
         },
     ))
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Parses a parenthesized SELECT, as in "IN (SELECT ...)"
+fn parse_subquery(input: &str) -> IResult<&str, Query> {
+    delimited(
+        pair(char('('), multispace0),
+        parse_query,
+        pair(multispace0, char(')')),
+    )(input)
+}
 
-    #[test]
-    fn test_parse_query_count() {
-        let count_query = "SELECT COUNT(*) FROM apples";
+/// Parses "(SELECT ...) [AS] name" after FROM. Without a name, the subquery goes by "subquery".
+fn parse_subquery_source(input: &str) -> IResult<&str, (String, Option<Box<Query>>)> {
+    let (input, subquery) = preceded(multispace0, parse_subquery)(input)?;
+    let (input, name) = opt(preceded(
+        pair(multispace0, opt(pair(tag_no_case("AS"), multispace1))),
+        verify(parse_identifier, |name: &str| {
+            !["WHERE", "ORDER", "LIMIT", "UNION", "INTERSECT", "EXCEPT"]
+                .contains(&name.to_uppercase().as_str())
+        }),
+    ))(input)?;
+    let (input, _) = multispace0(input)?;
 
-        let (raw_query, query) = parse_query(count_query).unwrap();
+    Ok((
+        input,
+        (
+            name.unwrap_or_else(|| "subquery".to_string()),
+            Some(Box::new(subquery)),
+        ),
+    ))
+}
 
-        assert_eq!(
-            query.selection_list,
-            vec![Selection::AggregateFunction(Function::Count(
-                FunctionArgument::All
-            ))]
-        );
-        assert_eq!(query.from_table, "apples");
-        assert_eq!(raw_query, "");
-    }
+pub fn parse_statement(input: &str) -> IResult<&str, Statement> {
+    alt((
+        map(
+            preceded(
+                tuple((
+                    multispace0,
+                    tag_no_case("EXPLAIN"),
+                    multispace1,
+                    tag_no_case("QUERY"),
+                    multispace1,
+                    tag_no_case("PLAN"),
+                    multispace1,
+                )),
+                parse_whole_query,
+            ),
+            Statement::ExplainQueryPlan,
+        ),
+        map(
+            preceded(
+                tuple((
+                    multispace0,
+                    tag_no_case("EXPLAIN"),
+                    multispace1,
+                    tag_no_case("ANALYZE"),
+                    multispace1,
+                )),
+                parse_whole_query,
+            ),
+            Statement::ExplainAnalyze,
+        ),
+        map(
+            preceded(
+                tuple((multispace0, tag_no_case("EXPLAIN"), multispace1)),
+                parse_whole_query,
+            ),
+            Statement::Explain,
+        ),
+        map(parse_whole_query, Statement::Select),
+        parse_transaction_statement,
+        parse_savepoint_statement,
+        parse_create_index_statement,
+        parse_pragma,
+        parse_analyze,
+        parse_vacuum,
+        parse_attach,
+        parse_detach,
+    ))(input)
+}
 
-    #[test]
-    fn test_parse_query_multi_columns() {
-        let count_query = "SELECT name, color FROM carrots";
+/// Parses a query that's all there is of the statement, but for a semicolon. Anything else after
+/// it is a clause that isn't handled, or a mistake, and either way mustn't be left out.
+pub fn parse_whole_query(input: &str) -> IResult<&str, Query> {
+    terminated(
+        parse_query,
+        tuple((multispace0, opt(char(';')), multispace0, eof)),
+    )(input)
+}
 
-        let (raw_query, query) = parse_query(count_query).unwrap();
+/// The word a statement that doesn't parse goes wrong at, for an error message: the first after
+/// a query that parses but doesn't end there, or else the first of the statement
+pub fn syntax_error_near(sql: &str) -> &str {
+    let explain = tuple((
+        multispace0,
+        tag_no_case("EXPLAIN"),
+        multispace1,
+        opt(alt((
+            recognize(tuple((
+                tag_no_case("QUERY"),
+                multispace1,
+                tag_no_case("PLAN"),
+                multispace1,
+            ))),
+            recognize(pair(tag_no_case("ANALYZE"), multispace1)),
+        ))),
+    ));
+    let rest = match preceded(opt(explain), parse_query)(sql) {
+        Ok((rest, _)) => rest,
+        Err(_) => sql,
+    };
+    let rest = rest.trim_start();
 
-        assert_eq!(
-            query.selection_list,
-            vec![
-                Selection::ColumnName("name".to_string()),
-                Selection::ColumnName("color".to_string())
-            ]
-        );
-        assert_eq!(query.from_table, "carrots");
-        assert_eq!(raw_query, "");
-    }
+    &rest[..rest.find(char::is_whitespace).unwrap_or(rest.len())]
+}
+
+/// Parses VACUUM, with an optional schema name and INTO 'filename'
+fn parse_vacuum(input: &str) -> IResult<&str, Statement> {
+    let (input, _) = tuple((multispace0, tag_no_case("VACUUM")))(input)?;
+    let (input, _) = opt(preceded(
+        multispace1,
+        verify(parse_identifier, |name: &str| {
+            !name.eq_ignore_ascii_case("INTO")
+        }),
+    ))(input)?;
+    let (input, into) = opt(preceded(
+        tuple((multispace1, tag_no_case("INTO"), multispace1)),
+        parse_string_literal,
+    ))(input)?;
+    let (input, _) = tuple((multispace0, opt(char(';')), multispace0, eof))(input)?;
+
+    Ok((input, Statement::Vacuum { into }))
+}
+
+/// Parses ATTACH [DATABASE] 'file' AS name
+fn parse_attach(input: &str) -> IResult<&str, Statement> {
+    let (input, _) = tuple((
+        multispace0,
+        tag_no_case("ATTACH"),
+        opt(pair(multispace1, tag_no_case("DATABASE"))),
+        multispace1,
+    ))(input)?;
+    let (input, path) = parse_string_literal(input)?;
+    let (input, _) = tuple((multispace1, tag_no_case("AS"), multispace1))(input)?;
+    let (input, name) = parse_identifier(input)?;
+    let (input, _) = tuple((multispace0, opt(char(';')), multispace0, eof))(input)?;
+
+    Ok((input, Statement::Attach { path, name }))
+}
+
+/// Parses DETACH [DATABASE] name
+fn parse_detach(input: &str) -> IResult<&str, Statement> {
+    let (input, _) = tuple((multispace0, tag_no_case("DETACH"), multispace1))(input)?;
+    let (input, name) = alt((
+        preceded(pair(tag_no_case("DATABASE"), multispace1), parse_identifier),
+        parse_identifier,
+    ))(input)?;
+    let (input, _) = tuple((multispace0, opt(char(';')), multispace0, eof))(input)?;
+
+    Ok((input, Statement::Detach(name)))
+}
+
+/// Parses ANALYZE, with the name of a schema, table or index to analyze. The schema a table or
+/// index is in is left out.
+fn parse_analyze(input: &str) -> IResult<&str, Statement> {
+    let (input, _) = tuple((multispace0, tag_no_case("ANALYZE")))(input)?;
+    let (input, name) = opt(preceded(
+        multispace1,
+        preceded(opt(pair(parse_identifier, char('.'))), parse_identifier),
+    ))(input)?;
+    let (input, _) = tuple((multispace0, opt(char(';')), multispace0, eof))(input)?;
+
+    Ok((input, Statement::Analyze(name)))
+}
+
+/// Parses PRAGMA [schema.]name, PRAGMA name(argument) or PRAGMA name = value. Quotes around the
+/// argument or value are taken off.
+fn parse_pragma(input: &str) -> IResult<&str, Statement> {
+    let (input, _) = tuple((multispace0, tag_no_case("PRAGMA"), multispace1))(input)?;
+    let (input, _) = opt(pair(parse_identifier, char('.')))(input)?;
+    let (input, name) = parse_identifier(input)?;
+    let (input, _) = multispace0(input)?;
+    let (input, argument) = opt(preceded(char('('), parenthesized_body))(input)?;
+    let (input, value) = opt(preceded(
+        pair(char('='), multispace0),
+        take_till(|c| c == ';'),
+    ))(input)?;
+    let (input, _) = tuple((multispace0, opt(char(';')), multispace0, eof))(input)?;
+
+    let unquote = |text: &str| {
+        let text = text.trim();
+        match text.chars().next() {
+            Some(quote @ ('\'' | '"')) if text.len() > 1 && text.ends_with(quote) => {
+                text[1..text.len() - 1].to_string()
+            }
+            _ => text.to_string(),
+        }
+    };
+
+    Ok((
+        input,
+        Statement::Pragma(Pragma {
+            name: name.to_lowercase(),
+            argument: argument.map(unquote),
+            value: value.map(unquote),
+        }),
+    ))
+}
+
+/// Parses a CREATE INDEX statement to run
+fn parse_create_index_statement(input: &str) -> IResult<&str, Statement> {
+    let (rest, create_index) = parse_create_index(input)?;
+    let (rest, _) = tuple((multispace0, opt(char(';')), multispace0, eof))(rest)?;
+
+    // Everything from the index name on is stored as it was written
+    let (definition, _) = tuple((
+        multispace0,
+        tag_no_case("CREATE"),
+        multispace1,
+        opt(pair(tag_no_case("UNIQUE"), multispace1)),
+        tag_no_case("INDEX"),
+        multispace1,
+        if_not_exists,
+    ))(input)?;
+    let definition = definition.trim_end().trim_end_matches(';').trim_end();
+    let unique = if create_index.unique { "UNIQUE " } else { "" };
+    let sql = format!("CREATE {unique}INDEX {definition}");
+
+    Ok((rest, Statement::CreateIndex { create_index, sql }))
+}
+
+/// Parses BEGIN, COMMIT (or END) and ROLLBACK
+fn parse_transaction_statement(input: &str) -> IResult<&str, Statement> {
+    let mut transaction = opt(preceded(multispace1, tag_no_case("TRANSACTION")));
+    let (input, _) = multispace0(input)?;
+    let (input, statement) = alt((
+        map(
+            tuple((
+                tag_no_case("BEGIN"),
+                opt(preceded(
+                    multispace1,
+                    alt((
+                        tag_no_case("DEFERRED"),
+                        tag_no_case("IMMEDIATE"),
+                        tag_no_case("EXCLUSIVE"),
+                    )),
+                )),
+            )),
+            |_| Statement::Begin,
+        ),
+        map(alt((tag_no_case("COMMIT"), tag_no_case("END"))), |_| {
+            Statement::Commit
+        }),
+        map(tag_no_case("ROLLBACK"), |_| Statement::Rollback),
+    ))(input)?;
+    let (input, _) = transaction(input)?;
+    let (input, statement) = match statement {
+        Statement::Rollback => map(
+            opt(preceded(
+                tuple((multispace1, tag_no_case("TO"), multispace1)),
+                savepoint_name,
+            )),
+            |name| name.map_or(Statement::Rollback, Statement::RollbackTo),
+        )(input)?,
+        statement => (input, statement),
+    };
+    let (input, _) = tuple((multispace0, opt(char(';')), multispace0, eof))(input)?;
+
+    Ok((input, statement))
+}
+
+/// Parses SAVEPOINT and RELEASE
+fn parse_savepoint_statement(input: &str) -> IResult<&str, Statement> {
+    let (input, _) = multispace0(input)?;
+    let (input, statement) = alt((
+        map(
+            preceded(
+                pair(tag_no_case("SAVEPOINT"), multispace1),
+                parse_identifier,
+            ),
+            Statement::Savepoint,
+        ),
+        map(
+            preceded(pair(tag_no_case("RELEASE"), multispace1), savepoint_name),
+            Statement::Release,
+        ),
+    ))(input)?;
+    let (input, _) = tuple((multispace0, opt(char(';')), multispace0, eof))(input)?;
+
+    Ok((input, statement))
+}
+
+/// A savepoint's name, which the SAVEPOINT keyword may come before
+fn savepoint_name(input: &str) -> IResult<&str, String> {
+    preceded(
+        opt(terminated(tag_no_case("SAVEPOINT"), multispace1)),
+        parse_identifier,
+    )(input)
+}
+
+/// Parses a name: a plain word, or anything quoted as "name", `name` or [name]. Quoted names keep
+/// their case, spaces and punctuation, with "" or `` inside standing for one quote.
+fn parse_identifier(input: &str) -> IResult<&str, String> {
+    alt((
+        |input| parse_quoted(input, '"'),
+        |input| parse_quoted(input, '`'),
+        map(
+            delimited(char('['), take_till(|c| c == ']'), char(']')),
+            |s: &str| s.to_string(),
+        ),
+        map(
+            take_while1(|c: char| c.is_alphanumeric() || c == '_'),
+            |s: &str| s.to_string(),
+        ),
+    ))(input)
+}
+
+/// Parses a column name, which may be qualified with its table's name, as in apples.color
+fn parse_column_reference(input: &str) -> IResult<&str, String> {
+    map(
+        pair(parse_identifier, opt(preceded(char('.'), parse_identifier))),
+        |(table_or_column, column)| match column {
+            Some(column) => format!("{table_or_column}.{column}"),
+            None => table_or_column,
+        },
+    )(input)
+}
+
+/// Takes everything up to the parenthesis matching an already consumed opening one, skipping over
+/// nested parentheses and quoted strings/identifiers.
+fn parenthesized_body(input: &str) -> IResult<&str, &str> {
+    let mut depth = 0;
+    let mut quote = None;
+
+    for (i, c) in input.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"' | '`') => quote = Some(c),
+            (None, '[') => quote = Some(']'),
+            (None, '(') => depth += 1,
+            (None, ')') if depth == 0 => return Ok((&input[i + 1..], &input[..i])),
+            (None, ')') => depth -= 1,
+            _ => {}
+        }
+    }
+
+    Err(nom::Err::Error(nom::error::Error::new(
+        input,
+        nom::error::ErrorKind::Char,
+    )))
+}
+
+/// Splits a list on commas that aren't nested inside parentheses or quotes
+fn split_top_level_commas(input: &str) -> Vec<&str> {
+    let mut items = vec![];
+    let mut depth = 0;
+    let mut quote = None;
+    let mut item_start = 0;
+
+    for (i, c) in input.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"' | '`') => quote = Some(c),
+            (None, '[') => quote = Some(']'),
+            (None, '(') => depth += 1,
+            (None, ')') => depth -= 1,
+            (None, ',') if depth == 0 => {
+                items.push(input[item_start..i].trim());
+                item_start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    items.push(input[item_start..].trim());
+
+    items
+}
+
+fn first_keyword(input: &str) -> String {
+    input
+        .chars()
+        .take_while(|c| c.is_alphanumeric() || *c == '_')
+        .collect::<String>()
+        .to_uppercase()
+}
+
+fn parse_column_definition(input: &str) -> IResult<&str, ColumnDefinition> {
+    let (mut input, name) = parse_identifier(input)?;
+
+    let mut type_parts = vec![];
+    loop {
+        input = input.trim_start();
+
+        if input.is_empty() {
+            break;
+        } else if input.starts_with('(') {
+            // e.g. VARCHAR(255)
+            let (rest, size) = parenthesized_body(&input[1..])?;
+            type_parts.push(format!("({size})"));
+            input = rest;
+        } else {
+            let keyword = first_keyword(input);
+            if keyword.is_empty() || COLUMN_CONSTRAINT_KEYWORDS.contains(&keyword.as_str()) {
+                break;
+            }
+            type_parts.push(input[..keyword.len()].to_string());
+            input = &input[keyword.len()..];
+        }
+    }
+
+    Ok((
+        input,
+        ColumnDefinition {
+            name,
+            type_name: type_parts.join(" ").replace(" (", "("),
+            collation: None,
+            not_null: false,
+            default: None,
+            generated: None,
+        },
+    ))
+}
+
+fn if_not_exists(input: &str) -> IResult<&str, bool> {
+    map(
+        opt(tuple((
+            tag_no_case("IF"),
+            multispace1,
+            tag_no_case("NOT"),
+            multispace1,
+            tag_no_case("EXISTS"),
+            multispace1,
+        ))),
+        |if_not_exists| if_not_exists.is_some(),
+    )(input)
+}
+
+/// Parses the CREATE TABLE statements stored in sqlite_schema
+pub fn parse_create_table(input: &str) -> IResult<&str, CreateTable> {
+    let (input, _) = multispace0(input)?;
+    let (input, _) = tag_no_case("CREATE")(input)?;
+    let (input, _) = multispace1(input)?;
+    let (input, _) = opt(pair(
+        alt((tag_no_case("TEMPORARY"), tag_no_case("TEMP"))),
+        multispace1,
+    ))(input)?;
+    let (input, _) = tag_no_case("TABLE")(input)?;
+    let (input, _) = multispace1(input)?;
+    let (input, _) = if_not_exists(input)?;
+    let (input, table_name) = parse_identifier(input)?;
+    let (input, _) = multispace0(input)?;
+    let (input, _) = char('(')(input)?;
+    let (input, body) = parenthesized_body(input)?;
+
+    let mut columns = vec![];
+    let mut primary_key = vec![];
+    // Whether the primary key can alias the rowid, which "INTEGER PRIMARY KEY DESC" can't: a quirk
+    // SQLite keeps for compatibility
+    let mut primary_key_can_alias = true;
+    // Every PRIMARY KEY and UNIQUE constraint in the order they appear, the primary key's marked
+    let mut key_constraints: Vec<(bool, Vec<String>)> = vec![];
+    let mut autoincrement = false;
+    for definition in split_top_level_commas(body) {
+        if TABLE_CONSTRAINT_KEYWORDS.contains(&first_keyword(definition).as_str()) {
+            let words = keywords(definition);
+            if words.contains(&"PRIMARY".to_string()) {
+                if let Some(key_columns) = constraint_columns(definition, "PRIMARY") {
+                    primary_key = key_columns.clone();
+                    primary_key_can_alias = true;
+                    key_constraints.push((true, key_columns));
+                }
+            } else if words.contains(&"UNIQUE".to_string()) {
+                if let Some(key_columns) = constraint_columns(definition, "UNIQUE") {
+                    key_constraints.push((false, key_columns));
+                }
+            }
+            continue;
+        }
+
+        let (constraints, mut column) =
+            parse_column_definition(definition).map_err(|e| e.map_input(|_| input))?;
+        let words = keywords(constraints);
+        for (i, word) in words.iter().enumerate() {
+            if word == "PRIMARY" && words.get(i + 1).map(String::as_str) == Some("KEY") {
+                primary_key = vec![column.name.clone()];
+                primary_key_can_alias = words.get(i + 2).map(String::as_str) != Some("DESC");
+                key_constraints.push((true, vec![column.name.clone()]));
+            } else if word == "UNIQUE" {
+                key_constraints.push((false, vec![column.name.clone()]));
+            } else if word == "AUTOINCREMENT" {
+                autoincrement = true;
+            } else if word == "COLLATE" {
+                column.collation = words.get(i + 1).cloned();
+            } else if word == "NOT" && words.get(i + 1).map(String::as_str) == Some("NULL") {
+                column.not_null = true;
+            }
+        }
+        column.default = default_expression(constraints);
+        column.generated = generated_column(constraints);
+        columns.push(column);
+    }
+
+    // A WITHOUT ROWID table has no rowid for a column to alias
+    let without_rowid = keywords(input)
+        .windows(2)
+        .any(|w| w == ["WITHOUT", "ROWID"]);
+    let rowid_alias = match primary_key.as_slice() {
+        [key_column] if !without_rowid && primary_key_can_alias => {
+            columns.iter().position(|column| {
+                column.name.eq_ignore_ascii_case(key_column)
+                    && column.type_name.eq_ignore_ascii_case("INTEGER")
+            })
+        }
+        _ => None,
+    };
+
+    // A primary key that aliases the rowid needs no index, and constraints on the same columns as
+    // an earlier one share its index
+    let mut unique_constraints: Vec<Vec<String>> = vec![];
+    for (is_primary_key, key_columns) in key_constraints {
+        if is_primary_key && rowid_alias.is_some() {
+            continue;
+        }
+        let same_columns = |other: &Vec<String>| {
+            other.len() == key_columns.len()
+                && other
+                    .iter()
+                    .zip(&key_columns)
+                    .all(|(a, b)| a.eq_ignore_ascii_case(b))
+        };
+        if !unique_constraints.iter().any(same_columns) {
+            unique_constraints.push(key_columns);
+        }
+    }
+
+    Ok((
+        input,
+        CreateTable {
+            table_name,
+            columns,
+            primary_key,
+            autoincrement: autoincrement && rowid_alias.is_some(),
+            rowid_alias,
+            unique_constraints,
+        },
+    ))
+}
+
+/// What follows `keyword` in a column's constraints, where it isn't inside parentheses, like
+/// those of a CHECK constraint
+fn after_constraint_keyword<'a>(constraints: &'a str, keyword: &str) -> Option<&'a str> {
+    let mut rest = constraints.trim_start();
+    loop {
+        let word = first_keyword(rest);
+        if word == keyword {
+            return Some(rest[word.len()..].trim_start());
+        }
+        // Skip the word, a parenthesized expression, a quoted string, or the one character
+        // that's none of those
+        let skip = match rest.chars().next()? {
+            '(' => rest.len() - parenthesized_body(&rest[1..]).ok()?.0.len(),
+            quote @ ('\'' | '"' | '`' | '[') => {
+                let close = if quote == '[' { ']' } else { quote };
+                rest[1..].find(close)? + 2
+            }
+            c => word.len().max(c.len_utf8()),
+        };
+        rest = rest[skip..].trim_start();
+    }
+}
+
+/// The GENERATED ALWAYS AS clause in a column's constraints, which can be just AS: a
+/// parenthesized expression, then STORED or VIRTUAL
+fn generated_column(constraints: &str) -> Option<GeneratedColumn> {
+    let rest = after_constraint_keyword(constraints, "AS")?;
+    let (rest, expression) = parenthesized_body(rest.strip_prefix('(')?).ok()?;
+
+    Some(GeneratedColumn {
+        expression: expression.trim().to_string(),
+        stored: first_keyword(rest.trim_start()) == "STORED",
+    })
+}
+
+/// The expression of a DEFAULT clause in a column's constraints: a parenthesized expression
+/// (without its parentheses), a quoted string, or a single word or number, which may be signed
+fn default_expression(constraints: &str) -> Option<String> {
+    let rest = after_constraint_keyword(constraints, "DEFAULT")?;
+
+    let expression = match rest.chars().next()? {
+        '(' => parenthesized_body(&rest[1..]).ok()?.1.trim(),
+        quote @ ('\'' | '"') => {
+            // A doubled quote, as in 'it''s', doesn't end the string
+            let mut end = 1;
+            while let Some(i) = rest[end..].find(quote) {
+                end += i + 1;
+                if !rest[end..].starts_with(quote) {
+                    break;
+                }
+                end += 1;
+            }
+            &rest[..end]
+        }
+        _ => {
+            let end = rest
+                .char_indices()
+                .skip(1)
+                .find(|&(_, c)| !(c.is_alphanumeric() || matches!(c, '_' | '.' | '+' | '-')))
+                .map_or(rest.len(), |(i, _)| i);
+            &rest[..end]
+        }
+    };
+
+    Some(expression.to_string())
+}
+
+/// The columns of a "PRIMARY KEY (...)" or "UNIQUE (...)" table constraint, given the keyword it
+/// starts with
+fn constraint_columns(constraint: &str, keyword: &str) -> Option<Vec<String>> {
+    let upper = constraint.to_uppercase();
+    let start = upper.find(keyword)?;
+    let open = start + upper[start..].find('(')?;
+    let (_, body) = parenthesized_body(&constraint[open + 1..]).ok()?;
+
+    split_top_level_commas(body)
+        .into_iter()
+        .map(|column| parse_identifier(column.trim()).ok().map(|(_, name)| name))
+        .collect()
+}
+
+/// The words of a column or table constraint, uppercased
+fn keywords(input: &str) -> Vec<String> {
+    input
+        .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .filter(|word| !word.is_empty())
+        .map(str::to_uppercase)
+        .collect()
+}
+
+/// Parses the CREATE VIEW statements stored in sqlite_schema
+pub fn parse_create_view(input: &str) -> IResult<&str, CreateView> {
+    let (input, _) = multispace0(input)?;
+    let (input, _) = tag_no_case("CREATE")(input)?;
+    let (input, _) = multispace1(input)?;
+    let (input, _) = opt(pair(
+        alt((tag_no_case("TEMPORARY"), tag_no_case("TEMP"))),
+        multispace1,
+    ))(input)?;
+    let (input, _) = tag_no_case("VIEW")(input)?;
+    let (input, _) = multispace1(input)?;
+    let (input, _) = if_not_exists(input)?;
+    let (input, view_name) = parse_identifier(input)?;
+    let (input, _) = multispace0(input)?;
+    let (input, columns) = opt(preceded(char('('), parenthesized_body))(input)?;
+    let (input, _) = tuple((multispace0, tag_no_case("AS"), multispace1))(input)?;
+    let (input, query) = parse_query(input)?;
+
+    let columns = columns
+        .map(|body| {
+            split_top_level_commas(body)
+                .into_iter()
+                .map(|column| parse_identifier(column.trim()).map(|(_, name)| name))
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .transpose()
+        .map_err(|e| e.map_input(|_| input))?;
+
+    Ok((
+        input,
+        CreateView {
+            view_name,
+            columns,
+            query,
+        },
+    ))
+}
+
+/// Parses the CREATE INDEX statements stored in sqlite_schema
+pub fn parse_create_index(input: &str) -> IResult<&str, CreateIndex> {
+    let (input, _) = multispace0(input)?;
+    let (input, _) = tag_no_case("CREATE")(input)?;
+    let (input, _) = multispace1(input)?;
+    let (input, unique) = opt(pair(tag_no_case("UNIQUE"), multispace1))(input)?;
+    let (input, _) = tag_no_case("INDEX")(input)?;
+    let (input, _) = multispace1(input)?;
+    let (input, if_not_exists) = if_not_exists(input)?;
+    let (input, index_name) = parse_identifier(input)?;
+    let (input, _) = delimited(multispace1, tag_no_case("ON"), multispace1)(input)?;
+    let (input, table_name) = parse_identifier(input)?;
+    let (input, _) = multispace0(input)?;
+    let (input, _) = char('(')(input)?;
+    let (input, body) = parenthesized_body(input)?;
+
+    let mut columns = vec![];
+    let mut collations = vec![];
+    let mut descending = vec![];
+    let mut simple_columns = true;
+    for indexed_column in split_top_level_commas(body) {
+        let (rest, column) =
+            parse_identifier(indexed_column).map_err(|e| e.map_input(|_| input))?;
+        let (rest, collation) = opt(preceded(multispace1, parse_collate))(rest)?;
+        let rest = rest.trim();
+        let desc = rest.eq_ignore_ascii_case("DESC");
+        simple_columns &= rest.is_empty() || rest.eq_ignore_ascii_case("ASC") || desc;
+        columns.push(column);
+        collations.push(collation);
+        descending.push(desc);
+    }
+
+    Ok((
+        input,
+        CreateIndex {
+            index_name,
+            table_name,
+            columns,
+            collations,
+            descending,
+            unique: unique.is_some(),
+            if_not_exists,
+            simple_columns,
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(text: &str) -> Expression {
+        Expression::Literal(Literal::Text(text.to_string()))
+    }
+
+    fn column(name: &str) -> ResultColumn {
+        ResultColumn {
+            selection: Selection::Expression(Expression::Column(name.to_string())),
+            alias: None,
+        }
+    }
 
     #[test]
-    fn test_parse_query_multiple_where_condition() {
-        let count_query = "  SELECT    id,    name    FROM    superheroes   WHERE     eye_color   =   'Pink Eyes'   AND  favourite_food   =    'pizza'      ";
+    fn test_parse_query_count() {
+        let count_query = "SELECT COUNT(*) FROM apples";
 
         let (raw_query, query) = parse_query(count_query).unwrap();
 
         assert_eq!(
             query.selection_list,
-            vec![
-                Selection::ColumnName("id".to_string()),
-                Selection::ColumnName("name".to_string())
-            ]
+            vec![ResultColumn {
+                selection: Selection::AggregateFunction(Function::Count(FunctionArgument::All)),
+                alias: None,
+            }]
         );
+        assert_eq!(query.from_table, "apples");
+        assert_eq!(raw_query, "");
+    }
+
+    #[test]
+    fn test_parse_query_multi_columns() {
+        let count_query = "SELECT name, color FROM carrots";
+
+        let (raw_query, query) = parse_query(count_query).unwrap();
+
+        assert_eq!(query.selection_list, vec![column("name"), column("color")]);
+        assert_eq!(query.from_table, "carrots");
+        assert_eq!(raw_query, "");
+    }
+
+    #[test]
+    fn test_parse_query_multiple_where_condition() {
+        let count_query = "  SELECT    id,    name    FROM    superheroes   WHERE     eye_color   =   'Pink Eyes'   AND  favourite_food   =    'pizza'      ";
+
+        let (raw_query, query) = parse_query(count_query).unwrap();
+
+        assert_eq!(query.selection_list, vec![column("id"), column("name")]);
         assert_eq!(query.from_table, "superheroes");
 
         assert_eq!(
@@ -174,11 +2031,17 @@ mod tests {
             Some(vec![
                 AndCondition {
                     column_name: "eye_color".to_string(),
-                    value: "Pink Eyes".to_string()
+                    operator: ComparisonOperator::Equals,
+                    values: vec![text("Pink Eyes")],
+                    collation: None,
+                    subquery: None
                 },
                 AndCondition {
                     column_name: "favourite_food".to_string(),
-                    value: "pizza".to_string()
+                    operator: ComparisonOperator::Equals,
+                    values: vec![text("pizza")],
+                    collation: None,
+                    subquery: None
                 }
             ])
         );
@@ -192,23 +2055,992 @@ mod tests {
 
         let (raw_query, query) = parse_query(count_query).unwrap();
 
+        assert_eq!(query.selection_list, vec![column("id"), column("name")]);
+        assert_eq!(query.from_table, "superheroes");
+
+        assert_eq!(
+            query.and_conditions,
+            Some(vec![AndCondition {
+                column_name: "eye_color".to_string(),
+                operator: ComparisonOperator::Equals,
+                values: vec![text("Pink Eyes")],
+                collation: None,
+                subquery: None
+            }])
+        );
+
+        assert_eq!(raw_query, "");
+    }
+
+    #[test]
+    fn test_parse_query_where_filter() {
+        let filter = |sql| parse_expression(sql).unwrap().1;
+        let name_condition = AndCondition {
+            column_name: "name".to_string(),
+            operator: ComparisonOperator::Equals,
+            values: vec![text("x")],
+            collation: None,
+            subquery: None,
+        };
+
+        // Simple conditions are kept apart from the rest, which is ANDed together
+        let (_, query) =
+            parse_query("SELECT id FROM t WHERE id > 5 AND name = 'x' AND note IS NULL").unwrap();
+        assert_eq!(query.and_conditions, Some(vec![name_condition.clone()]));
+        assert_eq!(query.filter, Some(filter("id > 5 AND note IS NULL")));
+
+        // Under an OR, none of them is a condition every row has to meet
+        let (_, query) = parse_query("SELECT id FROM t WHERE name = 'x' OR id > 5").unwrap();
+        assert_eq!(query.and_conditions, None);
+        assert_eq!(query.filter, Some(filter("name = 'x' OR id > 5")));
+
+        // A condition can't be just the start of an expression
+        let (_, query) = parse_query("SELECT id FROM t WHERE name = 'x' || id").unwrap();
+        assert_eq!(query.and_conditions, None);
+        assert_eq!(query.filter, Some(filter("name = 'x' || id")));
+
+        let (rest, query) = parse_query("SELECT id FROM t WHERE name = 'x' ORDER BY id").unwrap();
+        assert_eq!(query.and_conditions, Some(vec![name_condition]));
+        assert_eq!(query.filter, None);
+        assert_eq!(query.order_by.len(), 1);
+        assert_eq!(rest, "");
+    }
+
+    #[test]
+    fn test_parse_query_expressions_and_aliases() {
+        let (raw_query, query) = parse_query(
+            "SELECT price * 2 AS doubled, 'x' || name label, -1.5, count(*) FROM items",
+        )
+        .unwrap();
+
         assert_eq!(
             query.selection_list,
             vec![
-                Selection::ColumnName("id".to_string()),
-                Selection::ColumnName("name".to_string())
+                ResultColumn {
+                    selection: Selection::Expression(Expression::Binary {
+                        operator: BinaryOperator::Multiply,
+                        lhs: Box::new(Expression::Column("price".to_string())),
+                        rhs: Box::new(Expression::Literal(Literal::Integer(2))),
+                    }),
+                    alias: Some("doubled".to_string()),
+                },
+                ResultColumn {
+                    selection: Selection::Expression(Expression::Binary {
+                        operator: BinaryOperator::Concat,
+                        lhs: Box::new(Expression::Literal(Literal::Text("x".to_string()))),
+                        rhs: Box::new(Expression::Column("name".to_string())),
+                    }),
+                    alias: Some("label".to_string()),
+                },
+                ResultColumn {
+                    selection: Selection::Expression(Expression::Negate(Box::new(
+                        Expression::Literal(Literal::Real(1.5))
+                    ))),
+                    alias: None,
+                },
+                ResultColumn {
+                    selection: Selection::AggregateFunction(Function::Count(FunctionArgument::All)),
+                    alias: None,
+                },
             ]
         );
-        assert_eq!(query.from_table, "superheroes");
+        assert_eq!(query.from_table, "items");
+        assert_eq!(raw_query, "");
+    }
+
+    #[test]
+    fn test_parse_expression() {
+        use BinaryOperator::*;
+
+        let binary = |operator, lhs, rhs| Expression::Binary {
+            operator,
+            lhs: Box::new(lhs),
+            rhs: Box::new(rhs),
+        };
+
+        // * binds tighter than +, and operators of the same precedence group to the left
+        assert_eq!(
+            parse_expression("1 + 2 * 3 - 4").unwrap().1,
+            binary(
+                Subtract,
+                binary(
+                    Add,
+                    Expression::Literal(Literal::Integer(1)),
+                    binary(
+                        Multiply,
+                        Expression::Literal(Literal::Integer(2)),
+                        Expression::Literal(Literal::Integer(3))
+                    )
+                ),
+                Expression::Literal(Literal::Integer(4))
+            )
+        );
+        assert_eq!(
+            parse_expression("(1 + 2) * 3").unwrap().1,
+            binary(
+                Multiply,
+                binary(
+                    Add,
+                    Expression::Literal(Literal::Integer(1)),
+                    Expression::Literal(Literal::Integer(2))
+                ),
+                Expression::Literal(Literal::Integer(3))
+            )
+        );
+        assert_eq!(
+            parse_expression("soundex( name ) || 'it''s'").unwrap().1,
+            binary(
+                Concat,
+                Expression::FunctionCall {
+                    name: "soundex".to_string(),
+                    arguments: vec![Expression::Column("name".to_string())]
+                },
+                Expression::Literal(Literal::Text("it's".to_string()))
+            )
+        );
+        assert_eq!(
+            parse_expression("NULL").unwrap().1,
+            Expression::Literal(Literal::Null)
+        );
+        assert_eq!(
+            parse_expression("nullable").unwrap().1,
+            Expression::Column("nullable".to_string())
+        );
+        assert_eq!(
+            parse_expression("1e3").unwrap().1,
+            Expression::Literal(Literal::Real(1000.0))
+        );
+        assert_eq!(
+            parse_expression("cast ( weight + 1 as decimal (10, 2) )")
+                .unwrap()
+                .1,
+            Expression::Cast {
+                operand: Box::new(binary(
+                    Add,
+                    Expression::Column("weight".to_string()),
+                    Expression::Literal(Literal::Integer(1))
+                )),
+                type_name: "decimal(10, 2)".to_string()
+            }
+        );
+        // A column that happens to be called cast
+        assert_eq!(
+            parse_expression("cast").unwrap().1,
+            Expression::Column("cast".to_string())
+        );
+
+        // OR binds loosest, then AND, then NOT, then the comparisons
+        assert_eq!(
+            parse_expression("a = 1 or not b is  not NULL and c < 2")
+                .unwrap()
+                .1,
+            binary(
+                Or,
+                binary(
+                    Equals,
+                    Expression::Column("a".to_string()),
+                    Expression::Literal(Literal::Integer(1))
+                ),
+                binary(
+                    And,
+                    Expression::Not(Box::new(binary(
+                        IsNot,
+                        Expression::Column("b".to_string()),
+                        Expression::Literal(Literal::Null)
+                    ))),
+                    binary(
+                        Less,
+                        Expression::Column("c".to_string()),
+                        Expression::Literal(Literal::Integer(2))
+                    )
+                )
+            )
+        );
+        // Keywords don't match the start of a longer word
+        assert_eq!(
+            parse_expression("notes ORDER BY notes").unwrap(),
+            (" ORDER BY notes", Expression::Column("notes".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_display_expression() {
+        for sql in [
+            "1 + 2 * 3 - 4",
+            "(1 + 2) * 3",
+            "1 - (2 - 3)",
+            "soundex(name) || 'it''s'",
+            "-(a + 1.0)",
+            "substr(name, 1, 3)",
+            "CAST(a || 'b' AS UNSIGNED BIG INT)",
+            "a = 1 OR NOT b IS NOT NULL AND c < 2",
+            "(a OR b) AND NOT (c AND d)",
+            "(NOT a) = b",
+            "-(NOT a)",
+        ] {
+            assert_eq!(parse_expression(sql).unwrap().1.to_string(), sql);
+        }
+    }
+
+    #[test]
+    fn test_parse_query_limit() {
+        let (raw_query, query) = parse_query("SELECT name FROM apples LIMIT 2").unwrap();
+        assert_eq!(query.limit, Some(2));
+        assert_eq!(raw_query, "");
+
+        let (raw_query, query) =
+            parse_query("SELECT name FROM apples WHERE color = 'Red' limit 10 ").unwrap();
+        assert_eq!(query.limit, Some(10));
+        assert_eq!(raw_query, "");
+
+        let (_, query) = parse_query("SELECT name FROM apples").unwrap();
+        assert_eq!(query.limit, None);
+    }
+
+    #[test]
+    fn test_parse_query_order_by() {
+        let (raw_query, query) = parse_query(
+            "SELECT name FROM apples WHERE color = 'Red' ORDER BY rowid desc, length(name), 1 ASC LIMIT 3",
+        )
+        .unwrap();
+        assert_eq!(
+            query.order_by,
+            vec![
+                OrderingTerm {
+                    expression: Expression::Column("rowid".to_string()),
+                    collation: None,
+                    descending: true
+                },
+                OrderingTerm {
+                    expression: Expression::FunctionCall {
+                        name: "length".to_string(),
+                        arguments: vec![Expression::Column("name".to_string())]
+                    },
+                    collation: None,
+                    descending: false
+                },
+                OrderingTerm {
+                    expression: Expression::Literal(Literal::Integer(1)),
+                    collation: None,
+                    descending: false
+                },
+            ]
+        );
+        assert_eq!(query.limit, Some(3));
+        assert_eq!(raw_query, "");
+
+        // A column whose name starts with "desc" isn't the keyword
+        let (raw_query, query) =
+            parse_query("SELECT name FROM apples ORDER BY name descr").unwrap();
+        assert!(!query.order_by[0].descending);
+        assert_eq!(raw_query, "descr");
+    }
+
+    #[test]
+    fn test_parse_query_between_and_in() {
+        let query = "SELECT name FROM apples WHERE rowid BETWEEN 2 AND '3' AND color IN ('Red', 'Blush Red')";
+
+        let (raw_query, query) = parse_query(query).unwrap();
 
         assert_eq!(
             query.and_conditions,
-            Some(vec![AndCondition {
-                column_name: "eye_color".to_string(),
-                value: "Pink Eyes".to_string()
-            }])
+            Some(vec![
+                AndCondition {
+                    column_name: "rowid".to_string(),
+                    operator: ComparisonOperator::Between,
+                    values: vec![Expression::Literal(Literal::Integer(2)), text("3")],
+                    collation: None,
+                    subquery: None
+                },
+                AndCondition {
+                    column_name: "color".to_string(),
+                    operator: ComparisonOperator::In,
+                    values: vec![text("Red"), text("Blush Red")],
+                    collation: None,
+                    subquery: None
+                }
+            ])
+        );
+        assert_eq!(raw_query, "");
+
+        let (_, query) = parse_query("SELECT name FROM apples WHERE rowid in(-1)").unwrap();
+        assert_eq!(
+            query.and_conditions.unwrap()[0].values,
+            vec![Expression::Literal(Literal::Integer(-1))]
+        );
+    }
+
+    #[test]
+    fn test_parse_query_typed_literals() {
+        let (_, query) = parse_query(
+            "SELECT name FROM apples WHERE a = -4.5 AND b = NULL AND c = x'CAFE' AND d = 'it''s'",
+        )
+        .unwrap();
+        let values: Vec<_> = query
+            .and_conditions
+            .unwrap()
+            .into_iter()
+            .flat_map(|condition| condition.values)
+            .collect();
+        assert_eq!(
+            values,
+            [
+                Literal::Real(-4.5),
+                Literal::Null,
+                Literal::Blob(vec![0xca, 0xfe]),
+                Literal::Text("it's".to_string()),
+            ]
+            .map(Expression::Literal)
+        );
+        assert_eq!(values[2].to_string(), "X'CAFE'");
+
+        assert!(parse_literal("X'CAF'").is_err());
+    }
+
+    #[test]
+    fn test_parse_literal_numbers() {
+        let literal = |sql| match parse_literal(sql) {
+            Ok(("", Expression::Literal(literal))) => literal,
+            result => panic!("{sql}: {result:?}"),
+        };
+
+        assert_eq!(literal("42"), Literal::Integer(42));
+        assert_eq!(literal("+42"), Literal::Integer(42));
+        assert_eq!(literal("-42"), Literal::Integer(-42));
+        assert_eq!(literal("1."), Literal::Real(1.0));
+        assert_eq!(literal(".5e1"), Literal::Real(5.0));
+        assert_eq!(literal("-2.5E-1"), Literal::Real(-0.25));
+        assert_eq!(literal("0x1F"), Literal::Integer(31));
+        assert_eq!(literal("-0X1f"), Literal::Integer(-31));
+        assert_eq!(literal("0xFFFFFFFFFFFFFFFF"), Literal::Integer(-1));
+        assert_eq!(literal("-9223372036854775808"), Literal::Integer(i64::MIN));
+        assert_eq!(
+            literal("9223372036854775808"),
+            Literal::Real(-(i64::MIN as f64))
+        );
+        assert_eq!(
+            literal("-9223372036854775809"),
+            Literal::Real(i64::MIN as f64)
+        );
+        assert!(parse_literal("0x10000000000000000").is_err());
+    }
+
+    #[test]
+    fn test_parse_query_quoted_identifiers() {
+        assert_eq!(
+            parse_identifier("\"my table\" x"),
+            Ok((" x", "my table".to_string()))
+        );
+        assert_eq!(parse_identifier("`order`"), Ok(("", "order".to_string())));
+        assert_eq!(
+            parse_identifier("[a \"b\"]"),
+            Ok(("", "a \"b\"".to_string()))
+        );
+        assert_eq!(
+            parse_identifier("\"say \"\"hi\"\"\""),
+            Ok(("", "say \"hi\"".to_string()))
+        );
+        assert_eq!(parse_identifier("`a``b`"), Ok(("", "a`b".to_string())));
+        assert!(parse_identifier("\"unterminated").is_err());
+
+        let (rest, query) = parse_query(
+            "SELECT \"First Name\", [order] AS `the order` FROM \"my table\" \
+             WHERE \"my table\".`order`=1 AND [First Name] IN ('Ann')",
+        )
+        .unwrap();
+        assert_eq!(rest, "");
+        assert_eq!(query.from_table, "my table");
+        assert_eq!(query.selection_list[0], column("First Name"));
+        assert_eq!(query.selection_list[1].alias.as_deref(), Some("the order"));
+        let conditions = query.and_conditions.unwrap();
+        assert_eq!(conditions[0].column_name, "my table.order");
+        assert_eq!(
+            conditions[0].values,
+            [Expression::Literal(Literal::Integer(1))]
+        );
+        assert_eq!(conditions[1].column_name, "First Name");
+    }
+
+    #[test]
+    fn test_parse_query_qualified_column() {
+        let (_, query) = parse_query("SELECT apples.name, \"apples\".color FROM apples").unwrap();
+        assert_eq!(
+            query.selection_list,
+            vec![column("apples.name"), column("apples.color")]
         );
+        assert_eq!(query.selection_list[0].name(), "name");
+    }
+
+    #[test]
+    fn test_parse_query_distinct() {
+        let (_, query) = parse_query("SELECT DISTINCT color FROM apples").unwrap();
+        assert!(query.distinct);
+        assert_eq!(query.selection_list, vec![column("color")]);
+
+        let (_, query) = parse_query("select all color from apples").unwrap();
+        assert!(!query.distinct);
+
+        // A column whose name starts with "distinct" isn't the keyword
+        let (_, query) = parse_query("SELECT distinctness FROM apples").unwrap();
+        assert!(!query.distinct);
+        assert_eq!(query.selection_list, vec![column("distinctness")]);
+    }
 
+    #[test]
+    fn test_parse_query_regexp_condition() {
+        let query = "SELECT name FROM apples WHERE name regexp '^G.*h$' AND color = 'Light Green'";
+
+        let (raw_query, query) = parse_query(query).unwrap();
+
+        assert_eq!(
+            query.and_conditions,
+            Some(vec![
+                AndCondition {
+                    column_name: "name".to_string(),
+                    operator: ComparisonOperator::Regexp,
+                    values: vec![text("^G.*h$")],
+                    collation: None,
+                    subquery: None
+                },
+                AndCondition {
+                    column_name: "color".to_string(),
+                    operator: ComparisonOperator::Equals,
+                    values: vec![text("Light Green")],
+                    collation: None,
+                    subquery: None
+                }
+            ])
+        );
         assert_eq!(raw_query, "");
     }
+
+    #[test]
+    fn test_parse_query_subqueries() {
+        let (_, query) =
+            parse_query("SELECT n FROM (SELECT name AS n FROM apples WHERE id = 1) AS a LIMIT 2")
+                .unwrap();
+        assert_eq!(query.from_table, "a");
+        assert_eq!(query.limit, Some(2));
+        let subquery = query.from_subquery.unwrap();
+        assert_eq!(subquery.from_table, "apples");
+        assert_eq!(subquery.and_conditions.unwrap().len(), 1);
+
+        // Without a name, and with a WHERE clause that isn't one
+        let (_, query) =
+            parse_query("SELECT name FROM ( SELECT name FROM apples ) WHERE name = 'Fuji'")
+                .unwrap();
+        assert_eq!(query.from_table, "subquery");
+        assert!(query.from_subquery.is_some());
+        assert_eq!(query.and_conditions.unwrap()[0].column_name, "name");
+
+        let (_, query) = parse_query(
+            "SELECT name FROM apples WHERE id IN (SELECT id FROM apples WHERE color = 'Red') \
+             AND color = 'Red'",
+        )
+        .unwrap();
+        let conditions = query.and_conditions.unwrap();
+        assert_eq!(conditions[0].column_name, "id");
+        assert_eq!(conditions[0].operator, ComparisonOperator::In);
+        assert!(conditions[0].values.is_empty());
+        assert_eq!(
+            conditions[0]
+                .subquery
+                .as_ref()
+                .unwrap()
+                .selection_list
+                .len(),
+            1
+        );
+        assert!(conditions[1].subquery.is_none());
+    }
+
+    #[test]
+    fn test_parse_query_compound() {
+        let (rest, query) = parse_query(
+            "SELECT name FROM apples WHERE color = 'Red' UNION ALL SELECT name FROM pears \
+             intersect SELECT name FROM (SELECT name FROM plums) EXCEPT SELECT name FROM figs \
+             ORDER BY 1 DESC LIMIT 3",
+        )
+        .unwrap();
+        assert_eq!(rest, "");
+        assert_eq!(query.from_table, "apples");
+        assert_eq!(query.and_conditions.as_ref().unwrap().len(), 1);
+        assert_eq!(
+            query
+                .compound
+                .iter()
+                .map(|member| (member.operator, member.select.from_table.as_str()))
+                .collect::<Vec<_>>(),
+            [
+                (CompoundOperator::UnionAll, "pears"),
+                (CompoundOperator::Intersect, "subquery"),
+                (CompoundOperator::Except, "figs"),
+            ]
+        );
+        // ORDER BY and LIMIT belong to the compound, not to its last SELECT
+        assert_eq!(query.order_by.len(), 1);
+        assert_eq!(query.limit, Some(3));
+        assert!(query.compound[2].select.order_by.is_empty());
+
+        let left_most = query.left_most();
+        assert!(left_most.compound.is_empty() && left_most.order_by.is_empty());
+        assert_eq!(left_most.limit, None);
+
+        // Only the whole compound can have an ORDER BY
+        let (rest, _) = parse_query("SELECT a FROM t ORDER BY a UNION SELECT a FROM u").unwrap();
+        assert_eq!(rest, "UNION SELECT a FROM u");
+    }
+
+    #[test]
+    fn test_parse_statement_explain() {
+        let (_, statement) = parse_statement("EXPLAIN QUERY PLAN SELECT name FROM apples").unwrap();
+        assert!(matches!(statement, Statement::ExplainQueryPlan(q) if q.from_table == "apples"));
+
+        let (_, statement) = parse_statement("explain SELECT name FROM apples").unwrap();
+        assert!(matches!(statement, Statement::Explain(_)));
+
+        let (_, statement) = parse_statement("EXPLAIN ANALYZE SELECT name FROM apples").unwrap();
+        assert!(matches!(statement, Statement::ExplainAnalyze(_)));
+
+        let (_, statement) = parse_statement("SELECT name FROM apples").unwrap();
+        assert!(matches!(statement, Statement::Select(_)));
+    }
+
+    #[test]
+    fn test_parse_statement_trailing_clauses() {
+        let (rest, _) = parse_statement("SELECT name FROM apples LIMIT 2 ; ").unwrap();
+        assert_eq!(rest, "");
+
+        // Clauses that aren't handled are errors, rather than being left out of the query
+        for sql in [
+            "SELECT name FROM apples LIMIT 1 OFFSET 2",
+            "SELECT name FROM apples LIMIT 2, 1",
+            "SELECT name FROM apples GROUP BY name",
+            "SELECT name FROM apples WHERE name LIKE 'F%'",
+            "SELECT name FROM apples WHERE name GLOB 'F*'",
+            "SELECT name FROM apples WHERE id = 1 garbage here",
+            "EXPLAIN QUERY PLAN SELECT name FROM apples GROUP BY name",
+            "SELECT name FROM apples; SELECT name FROM pears",
+        ] {
+            assert!(parse_statement(sql).is_err(), "{sql}");
+        }
+
+        assert_eq!(
+            syntax_error_near("SELECT name FROM apples LIMIT 1 OFFSET 2"),
+            "OFFSET"
+        );
+        assert_eq!(
+            syntax_error_near("EXPLAIN QUERY PLAN SELECT name FROM apples GROUP BY name"),
+            "GROUP"
+        );
+        assert_eq!(syntax_error_near("  SELEC name FROM apples"), "SELEC");
+    }
+
+    #[test]
+    fn test_parse_statement_transaction() {
+        let statement = |sql| parse_statement(sql).unwrap();
+
+        assert_eq!(statement("BEGIN"), ("", Statement::Begin));
+        assert_eq!(
+            statement("begin immediate transaction;"),
+            ("", Statement::Begin)
+        );
+        assert_eq!(statement("COMMIT"), ("", Statement::Commit));
+        assert_eq!(statement("END TRANSACTION"), ("", Statement::Commit));
+        assert_eq!(statement("ROLLBACK;"), ("", Statement::Rollback));
+        assert!(parse_statement("BEGIN LATER").is_err());
+    }
+
+    #[test]
+    fn test_parse_statement_savepoint() {
+        let statement = |sql| parse_statement(sql).unwrap();
+        let name = |name: &str| name.to_string();
+
+        assert_eq!(
+            statement("SAVEPOINT a"),
+            ("", Statement::Savepoint(name("a")))
+        );
+        assert_eq!(statement("release a;"), ("", Statement::Release(name("a"))));
+        assert_eq!(
+            statement("RELEASE SAVEPOINT \"my point\""),
+            ("", Statement::Release(name("my point")))
+        );
+        // A savepoint can be called "savepoint"
+        assert_eq!(
+            statement("RELEASE savepoint"),
+            ("", Statement::Release(name("savepoint")))
+        );
+        assert_eq!(
+            statement("ROLLBACK TRANSACTION TO SAVEPOINT a"),
+            ("", Statement::RollbackTo(name("a")))
+        );
+        assert_eq!(
+            statement("rollback to a"),
+            ("", Statement::RollbackTo(name("a")))
+        );
+        assert!(parse_statement("SAVEPOINT").is_err());
+        assert!(parse_statement("ROLLBACK TO").is_err());
+    }
+
+    #[test]
+    fn test_parse_statement_analyze() {
+        let statement = |sql| parse_statement(sql).unwrap();
+
+        assert_eq!(statement("ANALYZE"), ("", Statement::Analyze(None)));
+        assert_eq!(
+            statement("analyze apples;"),
+            ("", Statement::Analyze(Some("apples".to_string())))
+        );
+        assert_eq!(
+            statement("ANALYZE main.\"idx apples\""),
+            ("", Statement::Analyze(Some("idx apples".to_string())))
+        );
+        assert!(parse_statement("ANALYZE apples oranges").is_err());
+    }
+
+    #[test]
+    fn test_parse_statement_vacuum() {
+        let statement = |sql| parse_statement(sql).unwrap();
+
+        assert_eq!(statement("VACUUM"), ("", Statement::Vacuum { into: None }));
+        assert_eq!(
+            statement("vacuum main;"),
+            ("", Statement::Vacuum { into: None })
+        );
+        assert_eq!(
+            statement("VACUUM main INTO 'copy.db'"),
+            (
+                "",
+                Statement::Vacuum {
+                    into: Some("copy.db".to_string())
+                }
+            )
+        );
+        assert_eq!(
+            statement("VACUUM INTO 'it''s.db'"),
+            (
+                "",
+                Statement::Vacuum {
+                    into: Some("it's.db".to_string())
+                }
+            )
+        );
+        assert!(parse_statement("VACUUM INTO").is_err());
+    }
+
+    #[test]
+    fn test_parse_statement_attach() {
+        let statement = |sql| parse_statement(sql).unwrap();
+        let attach = |path: &str, name: &str| Statement::Attach {
+            path: path.to_string(),
+            name: name.to_string(),
+        };
+
+        assert_eq!(
+            statement("ATTACH 'other.db' AS aux"),
+            ("", attach("other.db", "aux"))
+        );
+        assert_eq!(
+            statement("attach database 'it''s.db' as \"my db\";"),
+            ("", attach("it's.db", "my db"))
+        );
+        assert_eq!(
+            statement("DETACH DATABASE aux"),
+            ("", Statement::Detach("aux".to_string()))
+        );
+        assert!(parse_statement("ATTACH 'other.db'").is_err());
+
+        let (_, Statement::Select(query)) = statement(
+            "SELECT name FROM aux.apples WHERE id IN (SELECT id FROM pears) \
+             UNION SELECT name FROM main.\"green apples\"",
+        ) else {
+            panic!("not a SELECT");
+        };
+        assert_eq!(
+            query.tables(),
+            [
+                (Some("aux"), "apples"),
+                (None, "pears"),
+                (Some("main"), "green apples")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_create_table() {
+        let sql = "CREATE TABLE superheroes (id integer primary key autoincrement, name text not null, \"size range\" VARCHAR (10), eye_color COLLATE nocase, first_appearance_year integer DEFAULT (1900), PRIMARY KEY (id))";
+
+        let (_, create_table) = parse_create_table(sql).unwrap();
+
+        assert_eq!(create_table.table_name, "superheroes");
+        assert_eq!(
+            create_table.columns,
+            vec![
+                ColumnDefinition {
+                    name: "id".to_string(),
+                    type_name: "integer".to_string(),
+                    collation: None,
+                    not_null: false,
+                    default: None,
+                    generated: None
+                },
+                ColumnDefinition {
+                    name: "name".to_string(),
+                    type_name: "text".to_string(),
+                    collation: None,
+                    not_null: true,
+                    default: None,
+                    generated: None
+                },
+                ColumnDefinition {
+                    name: "size range".to_string(),
+                    type_name: "VARCHAR(10)".to_string(),
+                    collation: None,
+                    not_null: false,
+                    default: None,
+                    generated: None
+                },
+                ColumnDefinition {
+                    name: "eye_color".to_string(),
+                    type_name: "".to_string(),
+                    collation: Some("NOCASE".to_string()),
+                    not_null: false,
+                    default: None,
+                    generated: None
+                },
+                ColumnDefinition {
+                    name: "first_appearance_year".to_string(),
+                    type_name: "integer".to_string(),
+                    collation: None,
+                    not_null: false,
+                    default: Some("1900".to_string()),
+                    generated: None
+                },
+            ]
+        );
+        assert_eq!(create_table.rowid_alias, Some(0));
+    }
+
+    #[test]
+    fn test_parse_create_table_generated_columns() {
+        let (_, create_table) = parse_create_table(
+            "CREATE TABLE boxes (w INT CHECK (CAST(w AS INT) > 0), h INT DEFAULT 'AS (1)', \
+             area INT GENERATED ALWAYS AS (w * h) VIRTUAL, label AS (upper(name)) STORED)",
+        )
+        .unwrap();
+        let generated = create_table
+            .columns
+            .iter()
+            .map(|column| column.generated.clone())
+            .collect_vec();
+
+        assert_eq!(
+            generated,
+            [
+                None,
+                None,
+                Some(GeneratedColumn {
+                    expression: "w * h".to_string(),
+                    stored: false
+                }),
+                Some(GeneratedColumn {
+                    expression: "upper(name)".to_string(),
+                    stored: true
+                }),
+            ]
+        );
+        assert_eq!(create_table.columns[1].default.as_deref(), Some("'AS (1)'"));
+        assert_eq!(create_table.columns[2].type_name, "INT");
+    }
+
+    #[test]
+    fn test_parse_create_table_quoted_names() {
+        let (_, create_table) = parse_create_table(
+            "CREATE TABLE \"my table\" ([Id] INTEGER PRIMARY KEY, `order` INT, \"a, b\" TEXT, \
+             UNIQUE (\"a, b\", `order`))",
+        )
+        .unwrap();
+
+        assert_eq!(create_table.table_name, "my table");
+        let names: Vec<_> = create_table
+            .columns
+            .iter()
+            .map(|c| c.name.as_str())
+            .collect();
+        assert_eq!(names, ["Id", "order", "a, b"]);
+        assert_eq!(create_table.rowid_alias, Some(0));
+        assert_eq!(
+            create_table.unique_constraints,
+            [vec!["a, b".to_string(), "order".to_string()]]
+        );
+    }
+
+    #[test]
+    fn test_parse_create_table_rowid_alias() {
+        let rowid_alias = |sql| parse_create_table(sql).unwrap().1.rowid_alias;
+
+        assert_eq!(
+            rowid_alias("CREATE TABLE t (name text, id INTEGER PRIMARY KEY)"),
+            Some(1)
+        );
+        assert_eq!(
+            rowid_alias("CREATE TABLE t (id integer, name, PRIMARY KEY (id))"),
+            Some(0)
+        );
+        assert_eq!(
+            rowid_alias("CREATE TABLE t (id INTEGER PRIMARY KEY DESC)"),
+            None
+        );
+        assert_eq!(rowid_alias("CREATE TABLE t (id INT PRIMARY KEY)"), None);
+        assert_eq!(
+            rowid_alias("CREATE TABLE t (id integer, b, PRIMARY KEY (id, b))"),
+            None
+        );
+        assert_eq!(
+            rowid_alias("CREATE TABLE t (id INTEGER PRIMARY KEY) WITHOUT ROWID"),
+            None
+        );
+        assert_eq!(rowid_alias("CREATE TABLE t (id integer, name text)"), None);
+    }
+
+    #[test]
+    fn test_parse_create_table_autoincrement() {
+        let autoincrement = |sql| parse_create_table(sql).unwrap().1.autoincrement;
+
+        assert!(autoincrement(
+            "CREATE TABLE t (id INTEGER PRIMARY KEY AUTOINCREMENT, name)"
+        ));
+        assert!(!autoincrement(
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, name)"
+        ));
+    }
+
+    #[test]
+    fn test_parse_create_table_unique_constraints() {
+        let unique_constraints = |sql| parse_create_table(sql).unwrap().1.unique_constraints;
+        let names = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(
+            unique_constraints(
+                "CREATE TABLE t (id INTEGER PRIMARY KEY, email TEXT UNIQUE, a, b, UNIQUE (a, b DESC), CONSTRAINT e UNIQUE (Email))"
+            ),
+            vec![names(&["email"]), names(&["a", "b"])]
+        );
+        assert_eq!(
+            unique_constraints("CREATE TABLE p (code TEXT UNIQUE, name TEXT PRIMARY KEY)"),
+            vec![names(&["code"]), names(&["name"])]
+        );
+        assert_eq!(
+            unique_constraints("CREATE TABLE w (k TEXT, v UNIQUE, PRIMARY KEY (k)) WITHOUT ROWID"),
+            vec![names(&["v"]), names(&["k"])]
+        );
+        assert_eq!(
+            unique_constraints("CREATE TABLE d (id INTEGER PRIMARY KEY DESC)"),
+            vec![names(&["id"])]
+        );
+
+        let (_, create_table) =
+            parse_create_table("CREATE TABLE p (code TEXT UNIQUE, name TEXT PRIMARY KEY)").unwrap();
+        assert_eq!(
+            create_table.autoindex("sqlite_autoindex_p_2"),
+            Some(CreateIndex {
+                index_name: "sqlite_autoindex_p_2".to_string(),
+                table_name: "p".to_string(),
+                columns: names(&["name"]),
+                collations: vec![None],
+                descending: vec![false],
+                unique: true,
+                if_not_exists: false,
+                simple_columns: true,
+            })
+        );
+        assert_eq!(create_table.autoindex("sqlite_autoindex_p_3"), None);
+        assert_eq!(create_table.autoindex("sqlite_autoindex_q_1"), None);
+    }
+
+    #[test]
+    fn test_parse_create_index() {
+        let sql = "CREATE UNIQUE INDEX IF NOT EXISTS idx_companies_country\n\ton companies (country COLLATE nocase, name DESC)";
+
+        let (_, create_index) = parse_create_index(sql).unwrap();
+
+        assert_eq!(
+            create_index,
+            CreateIndex {
+                index_name: "idx_companies_country".to_string(),
+                table_name: "companies".to_string(),
+                columns: vec!["country".to_string(), "name".to_string()],
+                collations: vec![Some("nocase".to_string()), None],
+                descending: vec![false, true],
+                unique: true,
+                if_not_exists: true,
+                simple_columns: true,
+            }
+        );
+
+        // An index on an expression doesn't hold the column's values
+        let (_, create_index) = parse_create_index("CREATE INDEX i ON t (lower(name))").unwrap();
+        assert!(!create_index.simple_columns);
+    }
+
+    #[test]
+    fn test_parse_create_view() {
+        let (_, create_view) = parse_create_view(
+            "CREATE VIEW IF NOT EXISTS red (id, \"apple name\") AS SELECT id, name FROM apples \
+             WHERE color = 'Red'",
+        )
+        .unwrap();
+
+        assert_eq!(create_view.view_name, "red");
+        assert_eq!(
+            create_view.columns,
+            Some(vec!["id".to_string(), "apple name".to_string()])
+        );
+        assert_eq!(create_view.query.from_table, "apples");
+        assert_eq!(create_view.query.and_conditions.unwrap().len(), 1);
+
+        let (_, create_view) = parse_create_view("create temp view v as select 1 from t").unwrap();
+        assert_eq!(create_view.columns, None);
+    }
+
+    #[test]
+    fn test_parse_statement_pragma() {
+        let pragma = |sql| match parse_statement(sql) {
+            Ok(("", Statement::Pragma(pragma))) => pragma,
+            other => panic!("{sql}: {other:?}"),
+        };
+        let argument = |s: &str| Some(s.to_string());
+
+        assert_eq!(
+            pragma("PRAGMA Page_Size"),
+            Pragma {
+                name: "page_size".to_string(),
+                argument: None,
+                value: None,
+            }
+        );
+        assert_eq!(
+            pragma("pragma main.table_info('my table');").argument,
+            argument("my table")
+        );
+        assert_eq!(pragma("PRAGMA index_info (idx)").argument, argument("idx"));
+        assert_eq!(pragma("PRAGMA user_version = 7").value, argument("7"));
+        assert!(parse_statement("PRAGMA table_info(t) x").is_err());
+    }
+
+    #[test]
+    fn test_parse_statement_create_index() {
+        let (rest, statement) =
+            parse_statement("create index if not exists  idx ON apples (color ASC, name);\n")
+                .unwrap();
+        assert_eq!(rest, "");
+        let Statement::CreateIndex { create_index, sql } = statement else {
+            panic!("expected CREATE INDEX, got {statement:?}");
+        };
+        assert_eq!(sql, "CREATE INDEX idx ON apples (color ASC, name)");
+        assert!(create_index.if_not_exists && create_index.simple_columns);
+
+        // Partial indexes aren't supported
+        assert!(parse_statement("CREATE INDEX idx ON apples (color) WHERE id > 1").is_err());
+    }
 }
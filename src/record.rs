@@ -1,42 +1,234 @@
-use crate::varint::parse_varint;
+use crate::{types::*, value::Value, varint};
 use anyhow::{bail, Result};
+use std::io::Cursor;
+
+/// A row from a table b-tree leaf: its rowid, plus the columns of its record
+#[derive(Debug)]
+pub struct Record {
+    pub row_id: i64,
+    pub serial_types: Vec<SerialType>,
+    pub serial_values: Vec<SerialValue>,
+}
+
+impl Record {
+    /// Returns the value of the column at `index`
+    pub fn value(&self, index: usize) -> Value {
+        match self.serial_values.get(index) {
+            Some(serial_value) => serial_value.clone().into(),
+            None => Value::Null,
+        }
+    }
+
+    pub fn values(&self) -> Vec<Value> {
+        (0..self.serial_values.len())
+            .map(|i| self.value(i))
+            .collect()
+    }
+
+    /// A record holding `values`, as though it were a table row with the given rowid
+    pub fn from_values(row_id: i64, values: &[Value]) -> Self {
+        let serial_values: Vec<SerialValue> = values
+            .iter()
+            .map(|value| serial_value_for(value, true))
+            .collect();
+
+        Record {
+            row_id,
+            serial_types: serial_values.iter().map(SerialValue::serial_type).collect(),
+            serial_values,
+        }
+    }
+
+    /// Encodes the record's columns as a record payload, the way a current SQLite would write it
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encode_record(&self.values(), 4)
+    }
+}
 
 /// Reads SQLite's "Record Format" as mentioned here:
 /// [record_format](https://www.sqlite.org/fileformat.html#record_format)
-pub fn parse_record(stream: &[u8], column_count: usize) -> Result<Vec<Vec<u8>>> {
-    // Parse number of bytes in header, and use bytes_read as offset
-    let (_, mut offset) = parse_varint(stream);
+pub fn parse_record(payload_bytes: &[u8]) -> Result<(Vec<SerialType>, Vec<SerialValue>)> {
+    let payload_size = payload_bytes.len();
+
+    let Some((record_header_byte_count, bytes_read)) = varint::parse_complete_varint(payload_bytes)
+    else {
+        bail!("malformed record: the payload ends in the header size");
+    };
+
+    // The header size counts its own varint, and the header has to fit in the payload
+    let header_size = match usize::try_from(record_header_byte_count) {
+        Ok(count) if count >= bytes_read && count <= payload_size => count,
+        _ => bail!("malformed record: header size {record_header_byte_count} is out of range"),
+    };
 
-    // Read each varint into serial types and modify the offset
-    let mut serial_types = vec![];
-    for _ in 0..column_count {
-        let (varint, read_bytes) = parse_varint(&stream[offset..]);
-        offset += read_bytes;
-        serial_types.push(varint);
+    // The serial types are read straight from the header's bytes, which stops the last of them
+    // running on past its end
+    let mut serial_types: Vec<SerialType> = vec![];
+    let mut position = bytes_read;
+    while position < header_size {
+        let Some((column_serial_type, col_type_bytes_read)) =
+            varint::parse_complete_varint(&payload_bytes[position..header_size])
+        else {
+            bail!("malformed record: serial type runs past the end of the header");
+        };
+
+        serial_types.push(SerialType::from(column_serial_type as u64));
+        position += col_type_bytes_read;
     }
 
-    // Parse each serial type as column into record and modify the offset
-    let mut record = vec![];
-    for serial_type in serial_types {
-        let column = parse_column_value(&stream[offset..], serial_type)?;
-        offset += column.len();
-        record.push(column);
+    let mut payload_cursor = Cursor::new(&payload_bytes[header_size..]);
+    let mut serial_values = Vec::with_capacity(serial_types.len());
+    for column_serial_type in &serial_types {
+        let serial_value = SerialValue::parse(&mut payload_cursor, column_serial_type)?;
+
+        serial_values.push(serial_value);
     }
 
-    Ok(record)
+    Ok((serial_types, serial_values))
 }
 
-fn parse_column_value(stream: &[u8], serial_type: usize) -> Result<Vec<u8>> {
-    let column_value = match serial_type {
-        // 8 bit twos-complement integer
-        1 => vec![stream[0]],
-        // Text encoding
-        n if serial_type >= 13 && serial_type % 2 == 1 => {
-            let n_bytes = (n - 13) / 2;
-            
-            stream[0..n_bytes].to_vec()
-        }
-        _ => bail!("Invalid serial_type: {}", serial_type),
-    };
-    Ok(column_value)
+/// Encodes values in SQLite's "Record Format", the inverse of parse_record. Integers use the
+/// smallest serial type that holds them, and with a `schema_format` of 4 or more (which is what
+/// SQLite uses by default) 0 and 1 are stored as serial types 8 and 9, without any body bytes.
+pub fn encode_record(values: &[Value], schema_format: u32) -> Vec<u8> {
+    let serial_values: Vec<SerialValue> = values
+        .iter()
+        .map(|value| serial_value_for(value, schema_format >= 4))
+        .collect();
+
+    let mut header_body = vec![];
+    let mut body = vec![];
+    for serial_value in &serial_values {
+        header_body.extend(varint::encode_varint(
+            serial_value.serial_type().to_raw() as i64
+        ));
+        body.extend(serial_value.to_bytes());
+    }
+
+    // The header size counts its own varint, whose length depends on the size it encodes
+    let mut header_size = header_body.len() + 1;
+    while varint::encode_varint(header_size as i64).len() + header_body.len() != header_size {
+        header_size = varint::encode_varint(header_size as i64).len() + header_body.len();
+    }
+
+    let mut record = varint::encode_varint(header_size as i64);
+    record.extend(header_body);
+    record.extend(body);
+
+    record
+}
+
+fn serial_value_for(value: &Value, use_zero_and_one: bool) -> SerialValue {
+    match value {
+        Value::Null => SerialValue::Null,
+        Value::Integer(0) if use_zero_and_one => SerialValue::Zero,
+        Value::Integer(1) if use_zero_and_one => SerialValue::One,
+        Value::Integer(i) => match *i {
+            i if i8::try_from(i).is_ok() => SerialValue::Int8(i as i8),
+            i if i16::try_from(i).is_ok() => SerialValue::Int16(i as i16),
+            i if (-(1 << 23)..(1 << 23)).contains(&i) => SerialValue::Int24(i as i32),
+            i if i32::try_from(i).is_ok() => SerialValue::Int32(i as i32),
+            i if (-(1 << 47)..(1 << 47)).contains(&i) => SerialValue::Int48(i),
+            i => SerialValue::Int64(i),
+        },
+        Value::Real(r) => SerialValue::Float(*r),
+        Value::Text(s) => SerialValue::String(s.clone()),
+        Value::Blob(b) => SerialValue::Blob(b.clone()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_record() {
+        // Header: size 4, Null, Int8, String(5). Body: 42, "pizza"
+        let payload = vec![4, 0, 1, 23, 42, b'p', b'i', b'z', b'z', b'a'];
+
+        let (serial_types, serial_values) = parse_record(&payload).unwrap();
+
+        assert_eq!(
+            serial_types,
+            vec![SerialType::Null, SerialType::Int8, SerialType::String(5)]
+        );
+        assert_eq!(
+            serial_values,
+            vec![
+                SerialValue::Null,
+                SerialValue::Int8(42),
+                SerialValue::String("pizza".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_malformed_record() {
+        // A header size smaller than its own varint, and one bigger than the payload
+        assert!(parse_record(&[0, 1]).is_err());
+        assert!(parse_record(&[9, 1, 1]).is_err());
+        // The last serial type's varint runs past the end of the header
+        assert!(parse_record(&[2, 0x81, 0x01]).is_err());
+        // A string far longer than the payload
+        assert!(parse_record(&[6, 0x8f, 0xff, 0xff, 0xff, 0x7f]).is_err());
+        // The payload ends mid-varint
+        assert!(parse_record(&[0x81]).is_err());
+    }
+
+    #[test]
+    fn test_encode_record() {
+        let values = vec![
+            Value::Null,
+            Value::Integer(42),
+            Value::Text("pizza".to_string()),
+        ];
+        assert_eq!(
+            encode_record(&values, 4),
+            vec![4, 0, 1, 23, 42, b'p', b'i', b'z', b'z', b'a']
+        );
+
+        // 0 and 1 only get their own serial types from schema format 4
+        let values = vec![Value::Integer(0), Value::Integer(1)];
+        assert_eq!(encode_record(&values, 4), vec![3, 8, 9]);
+        assert_eq!(encode_record(&values, 1), vec![3, 1, 1, 0, 1]);
+
+        // With 127 serial types, the header's size (129, including itself) needs a 2-byte varint
+        let values = vec![Value::Null; 127];
+        let record = encode_record(&values, 4);
+        assert_eq!(&record[..2], &[0x81, 0x01]);
+        assert_eq!(record.len(), 129);
+    }
+
+    #[test]
+    fn test_encode_record_round_trip() {
+        let values = vec![
+            Value::Null,
+            Value::Integer(-1),
+            Value::Integer(-300),
+            Value::Integer(-8_000_000),
+            Value::Integer(2_000_000_000),
+            Value::Integer(-100_000_000_000_000),
+            Value::Integer(i64::MIN),
+            Value::Real(4.5),
+            Value::Text("Granny Smith".to_string()),
+            Value::Blob(vec![0xde, 0xad]),
+        ];
+
+        let (serial_types, serial_values) = parse_record(&encode_record(&values, 4)).unwrap();
+
+        assert_eq!(
+            serial_types
+                .iter()
+                .map(SerialType::to_raw)
+                .collect::<Vec<_>>(),
+            vec![0, 1, 2, 3, 4, 5, 6, 7, 37, 16]
+        );
+        assert_eq!(
+            serial_values
+                .into_iter()
+                .map(Value::from)
+                .collect::<Vec<_>>(),
+            values
+        );
+    }
 }
@@ -0,0 +1,65 @@
+use anyhow::{bail, Result};
+#[cfg(feature = "regexp")]
+use std::collections::HashMap;
+
+/// Compiled REGEXP patterns, keyed by pattern text, so that a pattern is only compiled once no
+/// matter how many rows it gets matched against.
+///
+/// Without the "regexp" feature there's no regex engine to match with, so every match is an error,
+/// just like in a SQLite build that has no regexp() function registered.
+#[derive(Default)]
+pub struct RegexCache {
+    #[cfg(feature = "regexp")]
+    patterns: HashMap<String, regex::Regex>,
+}
+
+impl RegexCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether `pattern` matches anywhere in `text`
+    #[cfg(feature = "regexp")]
+    pub fn is_match(&mut self, pattern: &str, text: &str) -> Result<bool> {
+        if !self.patterns.contains_key(pattern) {
+            let regex = match regex::Regex::new(pattern) {
+                Ok(regex) => regex,
+                Err(e) => bail!("invalid REGEXP pattern {pattern:?}: {e}"),
+            };
+            self.patterns.insert(pattern.to_string(), regex);
+        }
+
+        Ok(self.patterns[pattern].is_match(text))
+    }
+
+    #[cfg(not(feature = "regexp"))]
+    pub fn is_match(&mut self, _pattern: &str, _text: &str) -> Result<bool> {
+        bail!("no such function: REGEXP")
+    }
+}
+
+#[cfg(all(test, feature = "regexp"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_match() {
+        let mut cache = RegexCache::new();
+
+        assert!(cache.is_match("^Gr", "Granny Smith").unwrap());
+        assert!(cache.is_match("Smith$", "Granny Smith").unwrap());
+        assert!(!cache.is_match("^Smith", "Granny Smith").unwrap());
+        assert!(cache.is_match("(?i)granny", "Granny Smith").unwrap());
+        assert_eq!(cache.patterns.len(), 4);
+
+        assert!(cache.is_match("^Gr", "Grapefruit").unwrap());
+        assert_eq!(cache.patterns.len(), 4);
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        let mut cache = RegexCache::new();
+
+        assert!(cache.is_match("(unclosed", "text").is_err());
+    }
+}
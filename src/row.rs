@@ -0,0 +1,191 @@
+use crate::value::Value;
+use anyhow::{anyhow, bail, Context, Result};
+use std::any;
+
+/// A Rust type that a result value can be read as, with Row::get
+pub trait FromValue: Sized {
+    fn from_value(value: &Value) -> Result<Self>;
+}
+
+/// The error for a value that can't be read as `T`
+fn mismatch<T>(value: &Value) -> anyhow::Error {
+    anyhow!(
+        "can't read a {} value as {}",
+        value.type_name(),
+        any::type_name::<T>()
+    )
+}
+
+impl FromValue for Value {
+    fn from_value(value: &Value) -> Result<Self> {
+        Ok(value.clone())
+    }
+}
+
+impl FromValue for i64 {
+    fn from_value(value: &Value) -> Result<Self> {
+        value.as_integer().ok_or_else(|| mismatch::<Self>(value))
+    }
+}
+
+impl FromValue for i32 {
+    fn from_value(value: &Value) -> Result<Self> {
+        let i = i64::from_value(value)?;
+        i32::try_from(i).map_err(|_| anyhow!("{i} is out of range for i32"))
+    }
+}
+
+/// Integers are read as reals too, since a REAL column stores whole numbers as integers
+impl FromValue for f64 {
+    fn from_value(value: &Value) -> Result<Self> {
+        value.as_real().ok_or_else(|| mismatch::<Self>(value))
+    }
+}
+
+/// SQLite has no booleans: false is 0 and true is any other integer
+impl FromValue for bool {
+    fn from_value(value: &Value) -> Result<Self> {
+        Ok(i64::from_value(value)? != 0)
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: &Value) -> Result<Self> {
+        match value {
+            Value::Text(text) => Ok(text.clone()),
+            value => Err(mismatch::<Self>(value)),
+        }
+    }
+}
+
+impl FromValue for Vec<u8> {
+    fn from_value(value: &Value) -> Result<Self> {
+        match value {
+            Value::Blob(bytes) => Ok(bytes.clone()),
+            Value::Text(text) => Ok(text.as_bytes().to_vec()),
+            value => Err(mismatch::<Self>(value)),
+        }
+    }
+}
+
+/// NULL is None, for a column that may hold NULLs
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: &Value) -> Result<Self> {
+        match value {
+            Value::Null => Ok(None),
+            value => T::from_value(value).map(Some),
+        }
+    }
+}
+
+/// A result row, whose values can be read by column name as Rust types
+#[derive(Debug)]
+pub struct Row<'a> {
+    columns: &'a [String],
+    values: Vec<Value>,
+}
+
+impl<'a> Row<'a> {
+    /// A row of `values`, in the order of the result's `columns`
+    pub fn new(columns: &'a [String], values: Vec<Value>) -> Self {
+        Row { columns, values }
+    }
+
+    /// The value of the column named `column`, as a `T`. Columns are named as in the result's
+    /// headings, by their alias if they have one, and case doesn't matter.
+    pub fn get<T: FromValue>(&self, column: &str) -> Result<T> {
+        let Some(index) = self
+            .columns
+            .iter()
+            .position(|name| name.eq_ignore_ascii_case(column))
+        else {
+            bail!("no such column in the result: {column}");
+        };
+
+        self.get_index(index)
+            .with_context(|| format!("can't read column {column}"))
+    }
+
+    /// The value of the column at `index`, counting from 0, as a `T`
+    pub fn get_index<T: FromValue>(&self, index: usize) -> Result<T> {
+        match self.values.get(index) {
+            Some(value) => T::from_value(value),
+            None => bail!("no column {index} in a result of {}", self.values.len()),
+        }
+    }
+
+    pub fn values(&self) -> &[Value] {
+        &self.values
+    }
+}
+
+/// A Rust type that a whole result row can be read as, with Rows::map_rows. Implementations
+/// usually read each field from the column of the same name with Row::get.
+pub trait FromRow: Sized {
+    fn from_row(row: &Row) -> Result<Self>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Apple {
+        id: i64,
+        name: String,
+        weight: Option<f64>,
+    }
+
+    impl FromRow for Apple {
+        fn from_row(row: &Row) -> Result<Self> {
+            Ok(Apple {
+                id: row.get("id")?,
+                name: row.get("name")?,
+                weight: row.get("weight")?,
+            })
+        }
+    }
+
+    #[test]
+    fn test_from_row() {
+        let columns = ["ID", "name", "weight"].map(String::from);
+        let row = Row::new(
+            &columns,
+            vec![
+                Value::Integer(1),
+                Value::Text("Fuji".to_string()),
+                Value::Null,
+            ],
+        );
+        assert_eq!(
+            Apple::from_row(&row).unwrap(),
+            Apple {
+                id: 1,
+                name: "Fuji".to_string(),
+                weight: None,
+            }
+        );
+
+        assert_eq!(row.get::<f64>("id").unwrap(), 1.0);
+        assert!(row.get::<i64>("name").is_err());
+        assert!(row.get::<i64>("weight").is_err());
+        assert!(row.get::<i64>("color").is_err());
+        assert!(row.get_index::<i64>(3).is_err());
+    }
+
+    #[test]
+    fn test_from_value() {
+        assert!(bool::from_value(&Value::Integer(2)).unwrap());
+        assert_eq!(i32::from_value(&Value::Integer(-7)).unwrap(), -7);
+        assert!(i32::from_value(&Value::Integer(i64::MAX)).is_err());
+        assert!(i64::from_value(&Value::Real(1.0)).is_err());
+        assert_eq!(
+            Vec::<u8>::from_value(&Value::Text("hi".to_string())).unwrap(),
+            b"hi"
+        );
+        assert_eq!(
+            Option::<String>::from_value(&Value::Text("hi".to_string())).unwrap(),
+            Some("hi".to_string())
+        );
+    }
+}
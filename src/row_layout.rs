@@ -0,0 +1,260 @@
+use crate::{
+    affinity::Affinity,
+    binder::{Binder, Source},
+    database::Database,
+    functions::FunctionRegistry,
+    query_parser::{parse_expression, CreateTable, Expression},
+    record::Record,
+    value::Value,
+    vm::{Program, Vm},
+};
+use anyhow::{anyhow, bail, Result};
+use itertools::{Either, Itertools};
+
+/// How a table's columns are found in its records, for a table whose records don't simply hold
+/// each of its columns in turn. A virtual generated column isn't stored, so the columns after it
+/// are a place earlier in the record, and its value is computed from the others. A record written
+/// before ALTER TABLE ADD COLUMN is short of the columns added since, which take their DEFAULT
+/// values.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RowLayout {
+    /// Where each column's value comes from, in table order
+    columns: Vec<ColumnSource>,
+    /// The virtual columns, each after the others its expression reads
+    virtual_order: Vec<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ColumnSource {
+    /// The value at `position` in the record, or `default` if the record is too short to have it
+    Stored { position: usize, default: Value },
+    /// The value the program computes from the rest of the row, with the column's affinity
+    Virtual {
+        program: Program,
+        affinity: Affinity,
+    },
+}
+
+impl RowLayout {
+    /// The layout of the table's records, or None if there's nothing to lay out: no column is
+    /// virtual or has a default other than NULL, which is what a record too short to hold the
+    /// column reads as anyway
+    pub fn new(create_table: &CreateTable, functions: &FunctionRegistry) -> Result<Option<Self>> {
+        let needed = create_table.columns.iter().any(|column| {
+            column.generated.as_ref().is_some_and(|g| !g.stored) || column.default.is_some()
+        });
+        if !needed {
+            return Ok(None);
+        }
+
+        let names = create_table
+            .columns
+            .iter()
+            .map(|column| column.name.clone())
+            .collect_vec();
+        let binder = Binder::new(vec![Source {
+            name: &create_table.table_name,
+            columns: &names,
+            rowid_alias: create_table.rowid_alias,
+        }]);
+        let resolve = |name: &str| Ok(binder.resolve(name)?.column);
+
+        let mut columns = vec![];
+        let mut expressions = vec![];
+        let mut position = 0;
+        for column in &create_table.columns {
+            let affinity = Affinity::from_type_name(&column.type_name);
+            match &column.generated {
+                Some(generated) if !generated.stored => {
+                    let expression = expression(&generated.expression)?;
+                    let program = Program::compile(&[], None, &[&expression], &resolve, functions)?;
+                    columns.push(ColumnSource::Virtual { program, affinity });
+                    expressions.push(Some(expression));
+                }
+                _ => {
+                    // A DEFAULT that isn't constant, like CURRENT_TIMESTAMP, can't have been
+                    // given to a column added with ALTER TABLE ADD COLUMN, so no record is short
+                    // of a column that has one
+                    let default = match &column.default {
+                        Some(default) => constant(default, functions).unwrap_or(Value::Null),
+                        None => Value::Null,
+                    };
+                    columns.push(ColumnSource::Stored {
+                        position,
+                        default: affinity.apply(default),
+                    });
+                    expressions.push(None);
+                    position += 1;
+                }
+            }
+        }
+
+        Ok(Some(RowLayout {
+            virtual_order: virtual_order(&names, &expressions)?,
+            columns,
+        }))
+    }
+
+    /// Consumes the layout for a function that lays out each record as a row of the table, with
+    /// every column's value in table order
+    pub fn into_mapper(self) -> impl FnMut(Record) -> Result<Record> {
+        let mut columns = self.columns.into_iter().map(Some).collect_vec();
+        let mut vms = vec![];
+        for i in self.virtual_order {
+            if let Some(ColumnSource::Virtual { program, affinity }) = columns[i].take() {
+                vms.push((i, Vm::new(program), affinity));
+            }
+        }
+        let stored = columns
+            .into_iter()
+            .map(|column| match column {
+                Some(ColumnSource::Stored { position, default }) => Some((position, default)),
+                _ => None,
+            })
+            .collect_vec();
+
+        move |record| {
+            if vms.is_empty() && record.serial_values.len() >= stored.len() {
+                return Ok(record);
+            }
+
+            let mut values = stored
+                .iter()
+                .map(|column| match column {
+                    Some((position, _)) if *position < record.serial_values.len() => {
+                        record.value(*position)
+                    }
+                    Some((_, default)) => default.clone(),
+                    None => Value::Null,
+                })
+                .collect_vec();
+            for (i, vm, affinity) in &mut vms {
+                let row = Record::from_values(record.row_id, &values);
+                let value = vm.run(&row)?.and_then(|row| row.into_iter().next());
+                values[*i] = affinity.apply(value.unwrap_or(Value::Null));
+            }
+
+            Ok(Record::from_values(record.row_id, &values))
+        }
+    }
+}
+
+impl Database {
+    /// The rows of the table with its root on `root_page`, in rowid order, each laid out with
+    /// every column's value in table order
+    pub fn table_rows(
+        &self,
+        root_page: u32,
+        create_table: &CreateTable,
+    ) -> Result<impl Iterator<Item = Result<Record>> + '_> {
+        let records = self.table_cursor(root_page);
+
+        Ok(match RowLayout::new(create_table, &self.functions)? {
+            Some(layout) => {
+                let mut lay_out = layout.into_mapper();
+                Either::Left(records.map(move |record| record.and_then(&mut lay_out)))
+            }
+            None => Either::Right(records),
+        })
+    }
+}
+
+/// Parses a generated column's expression or a default, which has to be all there is of `sql`
+fn expression(sql: &str) -> Result<Expression> {
+    match parse_expression(sql) {
+        Ok((rest, expression)) if rest.trim().is_empty() => Ok(expression),
+        _ => bail!("malformed database schema: can't parse expression: {sql}"),
+    }
+}
+
+/// The value of a DEFAULT expression, which can't read any column
+fn constant(sql: &str, functions: &FunctionRegistry) -> Result<Value> {
+    let resolve = |name: &str| Err(anyhow!("default value of column is not constant: {name}"));
+    let program = Program::compile(&[], None, &[&expression(sql)?], &resolve, functions)?;
+    let row = Vm::new(program).run(&Record::from_values(0, &[]))?;
+
+    Ok(row
+        .and_then(|row| row.into_iter().next())
+        .unwrap_or(Value::Null))
+}
+
+/// The virtual columns, given by the expressions of those that are, in an order in which each
+/// comes after the other virtual columns its expression reads
+fn virtual_order(names: &[String], expressions: &[Option<Expression>]) -> Result<Vec<usize>> {
+    let reads = expressions
+        .iter()
+        .map(|expression| {
+            let mut reads = vec![];
+            if let Some(expression) = expression {
+                expression.visit_columns(&mut |name| {
+                    let name = name.rsplit('.').next().unwrap_or(name);
+                    reads.extend(names.iter().position(|n| n.eq_ignore_ascii_case(name)));
+                });
+            }
+            reads
+        })
+        .collect_vec();
+
+    let mut order = vec![];
+    let mut remaining = (0..names.len())
+        .filter(|&i| expressions[i].is_some())
+        .collect_vec();
+    while !remaining.is_empty() {
+        let ready = |i: &usize| {
+            reads[*i]
+                .iter()
+                .all(|read| expressions[*read].is_none() || order.contains(read))
+        };
+        let Some(next) = remaining.iter().position(ready) else {
+            bail!("generated column loop on \"{}\"", names[remaining[0]]);
+        };
+        order.push(remaining.remove(next));
+    }
+
+    Ok(order)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query_parser::parse_create_table;
+
+    fn layout(sql: &str) -> Result<Option<RowLayout>> {
+        let (_, create_table) = parse_create_table(sql).unwrap();
+        RowLayout::new(&create_table, &FunctionRegistry::default())
+    }
+
+    #[test]
+    fn test_row_layout() {
+        assert_eq!(layout("CREATE TABLE t (a, b AS (a) STORED)").unwrap(), None);
+
+        let layout = layout(
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, a INT, d AS (c * 2), b TEXT AS (a + 1) \
+             STORED, c AS (b || '!'), e REAL DEFAULT (1 + 1))",
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(layout.virtual_order, [4, 2]);
+
+        let mut lay_out = layout.into_mapper();
+        let text = |s: &str| Value::Text(s.to_string());
+        let record = Record::from_values(7, &[Value::Null, Value::Integer(2), text("3")]);
+        assert_eq!(
+            lay_out(record).unwrap().values(),
+            [
+                Value::Null,
+                Value::Integer(2),
+                Value::Integer(6),
+                text("3"),
+                text("3!"),
+                Value::Real(2.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_row_layout_loop() {
+        let err = layout("CREATE TABLE t (a AS (b), b AS (a))").unwrap_err();
+        assert_eq!(err.to_string(), "generated column loop on \"a\"");
+    }
+}
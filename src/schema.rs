@@ -1,41 +1,97 @@
+use crate::{query_parser::*, record::Record, value::Value};
+use anyhow::{anyhow, Result};
+
+/// A row of the sqlite_schema table, as described here:
+/// [schema_table](https://www.sqlite.org/schematab.html)
 #[derive(Debug)]
-#[allow(dead_code)]
 pub struct Schema {
-    kind: String,
-    name: String,
-    table_name: String,
-    root_page: usize,
-    sql: String,
+    pub kind: String,
+    pub name: String,
+    pub table_name: String, // Awkward SQLite naming, I know...
+    pub root_page: u32,
+    /// NULL for internal indexes, e.g. those created for UNIQUE constraints
+    pub sql: Option<String>,
 }
 
 impl Schema {
-    /// Parses a record into a schema
-    pub fn parse(record: Vec<Vec<u8>>) -> Option<Self> {
-        let mut items = record.into_iter();
-        let kind = items.next()?;
-        let name = items.next()?;
-        let table_name = items.next()?;
-        let root_page = items.next()?;
-        let sql = items.next()?;
+    /// Parses a sqlite_schema record into a schema
+    pub fn parse(record: &Record) -> Option<Self> {
+        let text = |value: Value| match value {
+            Value::Text(s) => Some(s),
+            _ => None,
+        };
 
         let schema = Self {
-            kind: String::from_utf8_lossy(&kind).to_string(),
-            name: String::from_utf8_lossy(&name).to_string(),
-            table_name: String::from_utf8_lossy(&table_name).to_string(),
-            root_page: parse_number(&root_page),
-            sql: String::from_utf8_lossy(&sql).to_string(),
+            kind: text(record.value(0))?,
+            name: text(record.value(1))?,
+            table_name: text(record.value(2))?,
+            root_page: record.value(3).as_integer()? as u32,
+            sql: text(record.value(4)),
         };
         Some(schema)
     }
-}
 
-/// parses variable size unsigned integer encoded as bytes to number.
-fn parse_number(bytes: &[u8]) -> usize {
-    let mut result: usize = 0;
-    let num_bytes = bytes.len();
-    for i in 0..num_bytes {
-        let shift = (num_bytes - i - 1) * 8;
-        result += (bytes[i] as usize) << shift;
+    pub fn is_table(&self) -> bool {
+        self.kind == "table"
+    }
+
+    pub fn is_index(&self) -> bool {
+        self.kind == "index"
+    }
+
+    pub fn is_view(&self) -> bool {
+        self.kind == "view"
+    }
+
+    /// Parses the CREATE TABLE statement of a table
+    pub fn create_table(&self) -> Result<CreateTable> {
+        let sql = self
+            .sql
+            .as_deref()
+            .ok_or_else(|| anyhow!("table {} has no sql", self.name))?;
+
+        let (_, create_table) = parse_create_table(sql)
+            .map_err(|e| anyhow!("Could not parse schema of table {}: {e}", self.name))?;
+
+        Ok(create_table)
+    }
+
+    /// Parses the CREATE INDEX statement of an index, if it has one
+    pub fn create_index(&self) -> Result<Option<CreateIndex>> {
+        let Some(sql) = self.sql.as_deref() else {
+            return Ok(None);
+        };
+
+        let (_, create_index) = parse_create_index(sql)
+            .map_err(|e| anyhow!("Could not parse schema of index {}: {e}", self.name))?;
+
+        Ok(Some(create_index))
+    }
+
+    /// Parses the CREATE VIEW statement of a view
+    pub fn create_view(&self) -> Result<CreateView> {
+        let sql = self
+            .sql
+            .as_deref()
+            .ok_or_else(|| anyhow!("view {} has no sql", self.name))?;
+
+        let (_, create_view) = parse_create_view(sql)
+            .map_err(|e| anyhow!("Could not parse schema of view {}: {e}", self.name))?;
+
+        Ok(create_view)
+    }
+
+    /// The names the object is known by: its own name, the name of its table and, for a table, the
+    /// names of its columns
+    pub fn names(&self) -> Vec<String> {
+        let mut names = vec![self.name.clone(), self.table_name.clone()];
+
+        if self.is_table() {
+            if let Ok(create_table) = self.create_table() {
+                names.extend(create_table.columns.into_iter().map(|c| c.name));
+            }
+        }
+
+        names
     }
-    result
 }
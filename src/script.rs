@@ -0,0 +1,220 @@
+use std::mem;
+
+/// Where the scanner is in SQL text: in code, in a quoted string or identifier (ending with the
+/// given character), or in a comment
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Code,
+    Quoted(char),
+    LineComment,
+    BlockComment,
+}
+
+/// SQL text split at the semicolons that end its statements
+struct Scan {
+    /// The statements, without their semicolons, each with the line it starts on, counting from 0
+    statements: Vec<(usize, String)>,
+    /// Whether the last statement is missing its semicolon
+    unterminated: bool,
+    state: State,
+}
+
+/// A command in a script: a dot-command or an SQL statement
+#[derive(Debug, PartialEq)]
+pub enum ScriptCommand {
+    Dot(String),
+    Sql(String),
+}
+
+/// Splits `sql` at semicolons, except those in quotes or comments. Comments are replaced by a
+/// space, since the parser doesn't expect them.
+fn scan(sql: &str) -> Scan {
+    let mut statements = vec![];
+    let mut statement = String::new();
+    let mut state = State::Code;
+    let mut line = 0;
+    // The line the statement being scanned starts on, once it's more than space and comments
+    let mut start_line = None;
+    let mut chars = sql.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match state {
+            State::Code => match c {
+                ';' => {
+                    let statement = mem::take(&mut statement).trim().to_string();
+                    if let Some(start_line) = start_line.take() {
+                        statements.push((start_line, statement));
+                    }
+                }
+                '\'' | '"' | '`' => {
+                    state = State::Quoted(c);
+                    statement.push(c);
+                }
+                '[' => {
+                    state = State::Quoted(']');
+                    statement.push(c);
+                }
+                '-' if chars.peek() == Some(&'-') => {
+                    chars.next();
+                    state = State::LineComment;
+                    statement.push(' ');
+                }
+                '/' if chars.peek() == Some(&'*') => {
+                    chars.next();
+                    state = State::BlockComment;
+                    statement.push(' ');
+                }
+                c => statement.push(c),
+            },
+            // A doubled quote, as in 'it''s', ends the string and starts it again
+            State::Quoted(end) => {
+                statement.push(c);
+                if c == end {
+                    state = State::Code;
+                }
+            }
+            State::LineComment => {
+                if c == '\n' {
+                    state = State::Code;
+                    statement.push(c);
+                }
+            }
+            State::BlockComment => {
+                if c == '*' && chars.peek() == Some(&'/') {
+                    chars.next();
+                    state = State::Code;
+                }
+            }
+        }
+
+        if start_line.is_none() && !c.is_whitespace() && !statement.trim().is_empty() {
+            start_line = Some(line);
+        }
+        if c == '\n' {
+            line += 1;
+        }
+    }
+
+    let unterminated = start_line.is_some();
+    if let Some(start_line) = start_line {
+        statements.push((start_line, statement.trim().to_string()));
+    }
+
+    Scan {
+        statements,
+        unterminated,
+        state,
+    }
+}
+
+/// The statements in `sql`, as typed at the prompt or read from a script, without the semicolons
+/// between them. The last needn't end with one. Empty statements and comments are left out.
+pub fn split_statements(sql: &str) -> Vec<String> {
+    scan(sql)
+        .statements
+        .into_iter()
+        .map(|(_, statement)| statement)
+        .collect()
+}
+
+/// Whether every statement in `sql` has been ended with a semicolon, so that it can be run, like
+/// sqlite3_complete(). Until then, the prompt asks for more lines. Text that's blank, or only
+/// comments, is complete, having nothing to run.
+pub fn is_complete(sql: &str) -> bool {
+    let scan = scan(sql);
+
+    !scan.unterminated && matches!(scan.state, State::Code | State::LineComment)
+}
+
+/// The commands in a script, each with the line it starts on, counting from 1. As at the prompt,
+/// a dot-command is a line of its own, which can't come in the middle of a statement.
+pub fn script_commands(script: &str) -> Vec<(usize, ScriptCommand)> {
+    let mut commands = vec![];
+    // The lines of the statements being read, and the index of the first
+    let mut sql = String::new();
+    let mut sql_start = 0;
+
+    for (i, line) in script.lines().enumerate() {
+        if sql.is_empty() {
+            if line.trim_start().starts_with('.') {
+                commands.push((i + 1, ScriptCommand::Dot(line.trim().to_string())));
+                continue;
+            }
+            sql_start = i;
+        }
+
+        sql.push_str(line);
+        sql.push('\n');
+        if is_complete(&sql) {
+            commands.extend(numbered_statements(&sql, sql_start));
+            sql.clear();
+        }
+    }
+    commands.extend(numbered_statements(&sql, sql_start));
+
+    commands
+}
+
+/// The statements in `sql`, which starts on the line with index `sql_start`
+fn numbered_statements(sql: &str, sql_start: usize) -> Vec<(usize, ScriptCommand)> {
+    scan(sql)
+        .statements
+        .into_iter()
+        .map(|(line, statement)| (sql_start + line + 1, ScriptCommand::Sql(statement)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_statements() {
+        assert_eq!(
+            split_statements("SELECT a FROM t; SELECT b FROM t;\n"),
+            ["SELECT a FROM t", "SELECT b FROM t"]
+        );
+        assert_eq!(split_statements("BEGIN;;COMMIT"), ["BEGIN", "COMMIT"]);
+        // Semicolons in strings, identifiers and comments don't end statements
+        assert_eq!(
+            split_statements("SELECT a FROM t WHERE b = 'x;''y' -- c;\n;SELECT \"d;\" FROM t"),
+            ["SELECT a FROM t WHERE b = 'x;''y'", "SELECT \"d;\" FROM t"]
+        );
+        assert_eq!(
+            split_statements("/* a; */ SELECT [b;] FROM t /* c */;"),
+            ["SELECT [b;] FROM t"]
+        );
+        assert!(split_statements(" ; -- nothing\n").is_empty());
+    }
+
+    #[test]
+    fn test_is_complete() {
+        assert!(is_complete("SELECT a FROM t;"));
+        assert!(is_complete("SELECT a\nFROM t; -- done"));
+        assert!(is_complete(""));
+        assert!(is_complete("-- just a comment\n"));
+        assert!(!is_complete("SELECT a FROM t"));
+        assert!(!is_complete("SELECT a FROM t WHERE b = 'x;"));
+        assert!(!is_complete("SELECT a FROM t; /* c;"));
+        assert!(!is_complete("SELECT a FROM t; SELECT"));
+    }
+
+    #[test]
+    fn test_script_commands() {
+        let script = "-- setup\n.mode box\n\nSELECT a\n  FROM t; SELECT b FROM t;\n\n\
+                      /* first */ SELECT c\n.headers on\n FROM t;\n  .tables\nSELECT d FROM t";
+        let sql = |sql: &str| ScriptCommand::Sql(sql.to_string());
+
+        assert_eq!(
+            script_commands(script),
+            [
+                (2, ScriptCommand::Dot(".mode box".to_string())),
+                (4, sql("SELECT a\n  FROM t")),
+                (5, sql("SELECT b FROM t")),
+                (7, sql("SELECT c\n.headers on\n FROM t")),
+                (10, ScriptCommand::Dot(".tables".to_string())),
+                (11, sql("SELECT d FROM t")),
+            ]
+        );
+    }
+}
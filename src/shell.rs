@@ -0,0 +1,1081 @@
+use crate::{
+    history::{format_timestamp, History},
+    output::{write_in_columns, Html, OutputFormatter, Separated, Tabulated},
+    table::{Border, Table},
+};
+use anyhow::{anyhow, bail, Context, Result};
+use clap::ValueEnum;
+use itertools::Itertools;
+use sqlite_starter_rust::{
+    binder::BindError,
+    database::{Database, SchemaChangedError},
+    diff::write_diff,
+    dot_commands::{DotCommandContext, DotCommandRegistry},
+    dump::write_dump,
+    executor::{analyze, build_operators, execute},
+    functions::like,
+    interrupt::InterruptedError,
+    lock::lock_byte_page,
+    pipe::spawn_command,
+    planner::plan_query,
+    pragma::run_pragma,
+    query_parser::*,
+    regexp::RegexCache,
+    schema::Schema,
+    script::{script_commands, split_statements, ScriptCommand},
+    value::Value,
+    view::expand_views,
+};
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    iter, mem,
+    path::Path,
+    process::Child,
+};
+
+/// How many rows .describe shows
+const SAMPLE_ROWS: usize = 5;
+
+/// How much output is gathered before it's written to stdout. Rows are written a buffer at a
+/// time rather than a line at a time, and a reader that falls behind holds the query up once
+/// its pipe is full.
+const STDOUT_BUFFER_SIZE: usize = 64 * 1024;
+
+/// How result rows are printed, as chosen with .mode
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum OutputMode {
+    /// Values separated by "|"
+    List,
+    /// Values separated by tabs, which spreadsheets accept when pasted
+    Tabs,
+    /// A table drawn with ASCII characters
+    Table,
+    /// A table drawn with Unicode box-drawing characters
+    Box,
+    /// Rows of an HTML table
+    Html,
+    /// A Markdown pipe table
+    Markdown,
+    /// Columns padded to line up, with a heading unless .headers is off
+    Column,
+}
+
+/// The formatter for `mode`, with the settings of .width, .headers and the like
+fn formatter<'a>(
+    mode: OutputMode,
+    widths: &'a [usize],
+    full: bool,
+    headers: Option<bool>,
+) -> Box<dyn OutputFormatter + 'a> {
+    let table = |border| {
+        Tabulated::new(Table {
+            border,
+            widths,
+            full,
+            headers: headers.unwrap_or(true),
+        })
+    };
+    let separated = |separator| Separated {
+        separator,
+        headers: headers.unwrap_or(false),
+    };
+
+    match mode {
+        OutputMode::List => Box::new(separated("|")),
+        OutputMode::Tabs => Box::new(separated("\t")),
+        OutputMode::Table => Box::new(table(Border::Ascii)),
+        OutputMode::Box => Box::new(table(Border::Box)),
+        OutputMode::Html => Box::new(Html),
+        OutputMode::Markdown => Box::new(table(Border::Markdown)),
+        OutputMode::Column => Box::new(table(Border::Column)),
+    }
+}
+
+/// A database opened with ATTACH, whose tables are named as `name`.table
+struct Attached {
+    name: String,
+    database: Database,
+    schema: Vec<Schema>,
+    /// The database's schema cookie when the schema was read
+    schema_cookie: u32,
+}
+
+/// Runs dot-commands and SQL statements against an open database, keeping the settings that
+/// dot-commands change (like the output mode) from one command to the next
+pub struct Shell {
+    database: Database,
+    schema: Vec<Schema>,
+    /// The database's schema cookie when the schema was read
+    schema_cookie: u32,
+    /// The databases opened with ATTACH, in the order they were
+    attached: Vec<Attached>,
+    /// Print the query plan before running each query
+    pub explain: bool,
+    /// How result rows are printed, as set with .mode
+    pub mode: OutputMode,
+    /// Whether to write column headers, as set with .headers. Until it's set, only the modes that
+    /// lay out a table do.
+    pub headers: Option<bool>,
+    /// The most rows of a query's result that are printed, leaving out the rest
+    pub limit: Option<usize>,
+    /// Column widths for the table modes, set with .width
+    widths: Vec<usize>,
+    /// Show wide values in full in the table modes, rather than truncating them
+    pub full: bool,
+    output: Box<dyn Write>,
+    /// Set by .once: the output goes back to stdout after the next command
+    output_once: bool,
+    /// Set by .pipe: a command that the output of each command is piped through
+    pipe: Option<String>,
+    /// The process the current command's output is piped to, and the output to go back to
+    /// when it's done
+    piped: Option<(Child, Box<dyn Write>)>,
+    /// Commands entered at the prompt, listed by .history
+    pub history: History,
+    /// Dot-commands added by library users and plugins
+    pub dot_commands: DotCommandRegistry,
+}
+
+impl Shell {
+    pub fn new(database: Database, schema: Vec<Schema>) -> Self {
+        Shell {
+            schema_cookie: database.schema_cookie,
+            database,
+            schema,
+            attached: vec![],
+            explain: false,
+            mode: OutputMode::List,
+            headers: None,
+            limit: None,
+            widths: vec![],
+            full: false,
+            output: stdout(),
+            output_once: false,
+            pipe: None,
+            piped: None,
+            history: History::default(),
+            dot_commands: DotCommandRegistry::new(),
+        }
+    }
+
+    /// Runs a dot-command, or one or more SQL statements separated by semicolons
+    pub fn run_command(&mut self, command: &str) -> Result<()> {
+        let command = command.trim();
+
+        if let Some(dot_command) = command.strip_prefix('.') {
+            // .once and .pipe redirect the commands after them, so they mustn't start or finish
+            // the output themselves
+            let name = dot_command.split_whitespace().next().unwrap_or_default();
+            if name == "once" || name == "pipe" {
+                return self.run_dot_command(dot_command);
+            }
+
+            return self.run_whole_command(|shell| shell.run_dot_command(dot_command));
+        }
+
+        self.run_whole_command(|shell| shell.run_statements(command))
+    }
+
+    /// Runs the script at `path` as a command of its own, as .read does
+    pub fn run_script(&mut self, path: &str) -> Result<()> {
+        self.run_whole_command(|shell| shell.read_script(path))
+    }
+
+    /// Opens the database at `filename` alongside the main one, as ATTACH does, so that queries
+    /// can read its tables as `name`.table
+    pub fn attach(&mut self, filename: &str, name: &str) -> Result<()> {
+        if self.database.in_transaction() {
+            bail!("cannot ATTACH database within transaction");
+        }
+        let in_use = ["main", "temp"]
+            .into_iter()
+            .chain(self.attached.iter().map(|attached| attached.name.as_str()))
+            .any(|in_use| in_use.eq_ignore_ascii_case(name));
+        if in_use {
+            bail!("database {name} is already in use");
+        }
+
+        let database = self.database.open_attached(filename)?;
+        self.attached.push(Attached {
+            name: name.to_string(),
+            schema: database.schema()?,
+            schema_cookie: database.schema_cookie,
+            database,
+        });
+
+        Ok(())
+    }
+
+    /// Runs a command entered by the user, with `run`, against the current schema, and with its
+    /// output going where it's been redirected for the command
+    fn run_whole_command(&mut self, run: impl FnOnce(&mut Self) -> Result<()>) -> Result<()> {
+        // A Ctrl-C from before the command, like one at the prompt, doesn't interrupt it
+        self.database.interrupt_handle().reset();
+        self.reload_changed_schema()?;
+
+        self.start_pipe()?;
+        let result = run(self);
+        let result = self.end_interrupted_transaction(result);
+        self.finish_command(result)
+    }
+
+    /// Runs the statements in `sql` one after another, writing each one's rows in turn, and
+    /// stops at the first that fails
+    fn run_statements(&mut self, sql: &str) -> Result<()> {
+        for statement in split_statements(sql) {
+            self.run_statement(&statement)
+                .map_err(|err| point_out(err, &statement))?;
+        }
+
+        Ok(())
+    }
+
+    /// Runs a statement against the schema as it is now: another connection may have changed it
+    /// since the last statement. One that finds it changed between being planned and run is
+    /// planned again, as SQLite prepares a statement again.
+    fn run_statement(&mut self, statement: &str) -> Result<()> {
+        self.reload_changed_schema()?;
+        let result = self.run_sql(statement);
+        if result
+            .as_ref()
+            .is_err_and(|err| err.is::<SchemaChangedError>())
+        {
+            self.reload_changed_schema()?;
+            return self.run_sql(statement);
+        }
+
+        result
+    }
+
+    /// Rolls back the transaction a command was part of if the command was interrupted, as SQLite
+    /// does, so that the next one starts afresh
+    fn end_interrupted_transaction(&mut self, result: Result<()>) -> Result<()> {
+        let interrupted = result
+            .as_ref()
+            .is_err_and(|err| err.is::<InterruptedError>());
+        if interrupted && self.database.in_transaction() {
+            self.end_transaction(false)?;
+        }
+
+        result
+    }
+
+    /// Runs `statement` on the main database and then those attached, stopping at the first
+    /// that fails
+    fn on_each_database(
+        &mut self,
+        mut statement: impl FnMut(&mut Database) -> Result<()>,
+    ) -> Result<()> {
+        statement(&mut self.database)?;
+        for attached in &mut self.attached {
+            statement(&mut attached.database)?;
+        }
+
+        Ok(())
+    }
+
+    /// Starts a transaction on the main database and those attached with `begin`, which reads
+    /// each of them as it was when the transaction began. The schema isn't read again until it
+    /// ends, so that every statement in it is planned against the same tables and indexes.
+    fn begin_transaction(&mut self, begin: impl FnMut(&mut Database) -> Result<()>) -> Result<()> {
+        let begun = self.on_each_database(begin);
+        if begun.is_err() {
+            // Those it was started on are left as they were
+            let _ = self.on_each_database(|database| {
+                if database.in_transaction() {
+                    let _ = database.rollback();
+                }
+                Ok(())
+            });
+        }
+
+        begun
+    }
+
+    /// Ends the transaction, and its savepoints with it, committing what it wrote or rolling it
+    /// back
+    fn end_transaction(&mut self, commit: bool) -> Result<()> {
+        self.on_each_database(|database| match commit {
+            true => database.commit(),
+            false => database.rollback(),
+        })
+    }
+
+    /// The database called `name`: main, or one that's attached
+    fn find_database(&self, name: &str) -> Result<&Database> {
+        if name.eq_ignore_ascii_case("main") {
+            return Ok(&self.database);
+        }
+
+        match self
+            .attached
+            .iter()
+            .find(|a| a.name.eq_ignore_ascii_case(name))
+        {
+            Some(attached) => Ok(&attached.database),
+            None => bail!("no such database: {name}"),
+        }
+    }
+
+    /// Runs a dot-command, given without its dot
+    fn run_dot_command(&mut self, dot_command: &str) -> Result<()> {
+        let mut words = dot_command.split_whitespace();
+        let name = words.next().unwrap_or_default();
+        let args = words.collect_vec();
+        let args = args.as_slice();
+
+        match name {
+            "dbinfo" => {
+                writeln!(
+                    self.output,
+                    "database page size: {}",
+                    self.database.page_size
+                )?;
+                // Counted as sqlite3 does, so indexes, triggers and views are left out, but
+                // SQLite's own tables, like sqlite_sequence, are counted
+                let tables = self.schema.iter().filter(|s| s.is_table()).count();
+                writeln!(self.output, "number of tables: {tables}")?;
+            }
+            "tables" => {
+                // The pattern may be quoted, as in .tables 'app%'
+                let pattern = match args {
+                    [] => None,
+                    [pattern] => Some(pattern.trim_matches(|c| c == '\'' || c == '"')),
+                    _ => bail!("Usage: .tables ?PATTERN?"),
+                };
+                // SQLite's own tables, like sqlite_sequence, are left out
+                let mut table_names = self
+                    .schema
+                    .iter()
+                    .filter(|s| s.is_table() || s.is_view())
+                    .map(|s| s.name.clone())
+                    .filter(|name| !like("sqlite_%", name, None))
+                    .filter(|name| pattern.is_none_or(|pattern| like(pattern, name, None)))
+                    .sorted()
+                    .collect_vec();
+                // Those of attached databases come after, named as queries name them
+                for attached in &self.attached {
+                    table_names.extend(
+                        attached
+                            .schema
+                            .iter()
+                            .filter(|s| s.is_table() || s.is_view())
+                            .filter(|s| !like("sqlite_%", &s.name, None))
+                            .filter(|s| pattern.is_none_or(|pattern| like(pattern, &s.name, None)))
+                            .map(|s| format!("{}.{}", attached.name, s.name))
+                            .sorted(),
+                    );
+                }
+
+                write_in_columns(&mut self.output, &table_names)?;
+            }
+            "stats" => {
+                let pattern = match args {
+                    [] => None,
+                    [pattern] => Some(pattern.trim_matches(|c| c == '\'' || c == '"')),
+                    _ => bail!("Usage: .stats ?PATTERN?"),
+                };
+                self.write_stats(pattern)?;
+            }
+            "backup" => {
+                let (database, path) = match args {
+                    [path] => (&self.database, path),
+                    [name, path] => (self.find_database(name)?, path),
+                    _ => bail!("Usage: .backup ?DB? FILE"),
+                };
+                database.backup_to_file(path, None)?;
+            }
+            "databases" => {
+                let path = |database: &Database| database.options.path.display().to_string();
+                writeln!(self.output, "main: {}", path(&self.database))?;
+                for attached in &self.attached {
+                    writeln!(
+                        self.output,
+                        "{}: {}",
+                        attached.name,
+                        path(&attached.database)
+                    )?;
+                }
+            }
+            "diff" => {
+                let [filename] = args else {
+                    bail!("Usage: .diff FILE");
+                };
+                let other = Database::open_filename(filename)?;
+                write_diff(&self.database, &other, &mut self.output)?;
+            }
+            "schema" => {
+                let pattern = match args {
+                    [] => None,
+                    [pattern] => Some(pattern.trim_matches(|c| c == '\'' || c == '"')),
+                    _ => bail!("Usage: .schema ?PATTERN?"),
+                };
+                // A table's indexes and triggers are shown along with it
+                let sqls = self
+                    .schema
+                    .iter()
+                    .filter(|s| pattern.is_none_or(|pattern| like(pattern, &s.table_name, None)))
+                    .filter_map(|s| s.sql.as_ref());
+                for sql in sqls {
+                    writeln!(self.output, "{sql};")?;
+                }
+            }
+            "dump" => {
+                let pattern = match args {
+                    [] => None,
+                    [pattern] => Some(pattern.trim_matches(|c| c == '\'' || c == '"')),
+                    _ => bail!("Usage: .dump ?PATTERN?"),
+                };
+                write_dump(&self.database, pattern, &mut self.output)?;
+            }
+            "check" => {
+                let problems = self.database.check()?;
+                if problems.is_empty() {
+                    writeln!(self.output, "ok")?;
+                }
+                for problem in problems {
+                    writeln!(self.output, "{problem}")?;
+                }
+            }
+            "pages" => {
+                for page_num in 1..=self.database.page_count {
+                    if page_num == lock_byte_page(self.database.page_size) {
+                        writeln!(self.output, "{page_num}: lock-byte page")?;
+                        continue;
+                    }
+                    match self.database.raw_page(page_num) {
+                        Ok((page, _)) => writeln!(
+                            self.output,
+                            "{page_num}: {:?} ({} cells)",
+                            page.header.page_type, page.header.number_of_cells
+                        )?,
+                        Err(err) => writeln!(self.output, "{page_num}: unreadable ({err})")?,
+                    }
+                }
+            }
+            "page" => {
+                let [page_num] = args else {
+                    bail!("Usage: .page N");
+                };
+                let (page, cell_pointers) = self.database.raw_page(page_num.parse()?)?;
+                let header = &page.header;
+
+                writeln!(self.output, "page type: {:?}", header.page_type)?;
+                writeln!(self.output, "number of cells: {}", header.number_of_cells)?;
+                writeln!(
+                    self.output,
+                    "first freeblock: {}",
+                    header.first_free_block_start
+                )?;
+                writeln!(
+                    self.output,
+                    "cell content area: {}",
+                    header.start_of_content_area
+                )?;
+                writeln!(
+                    self.output,
+                    "fragmented free bytes: {}",
+                    header.fragmented_free_bytes
+                )?;
+                if let Some(right_most_pointer) = header.right_most_pointer {
+                    writeln!(self.output, "right-most pointer: {right_most_pointer}")?;
+                }
+                writeln!(
+                    self.output,
+                    "cell pointers: {}",
+                    cell_pointers.iter().join(" ")
+                )?;
+            }
+            "mode" => match args {
+                [] => writeln!(self.output, "current output mode: {:?}", self.mode)?,
+                ["list"] => self.mode = OutputMode::List,
+                ["tabs"] => self.mode = OutputMode::Tabs,
+                ["table"] => self.mode = OutputMode::Table,
+                ["box"] => self.mode = OutputMode::Box,
+                ["html"] => self.mode = OutputMode::Html,
+                ["markdown"] => self.mode = OutputMode::Markdown,
+                ["column"] => self.mode = OutputMode::Column,
+                [mode] => bail!(
+                    "unknown mode: {mode} (use list, tabs, table, box, html, markdown or column)"
+                ),
+                _ => bail!("Usage: .mode [list|tabs|table|box|html|markdown|column]"),
+            },
+            "headers" => match args {
+                ["on"] => self.headers = Some(true),
+                ["off"] => self.headers = Some(false),
+                _ => bail!("Usage: .headers on|off"),
+            },
+            "width" => {
+                self.widths = args
+                    .iter()
+                    .map(|width| width.parse())
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| anyhow!("Usage: .width [NUM ...]"))?;
+            }
+            "output" => self.redirect_output(args, false)?,
+            "once" => self.redirect_output(args, true)?,
+            // The command to pipe through is taken as written, spaces and all
+            "pipe" => self.set_pipe(dot_command[name.len()..].trim())?,
+            "read" => {
+                let [path] = args else {
+                    bail!("Usage: .read FILE");
+                };
+                self.read_script(path)?;
+            }
+            "history" => match args {
+                [] => self.write_history(usize::MAX)?,
+                [count] => self.write_history(count.parse()?)?,
+                ["search", text @ ..] if !text.is_empty() => {
+                    let text = text.join(" ");
+                    for (i, entry) in self.history.search(&text) {
+                        writeln!(
+                            self.output,
+                            "{:>5}  {}  {}  ({})",
+                            i + 1,
+                            format_timestamp(entry.timestamp),
+                            entry.command,
+                            entry.database
+                        )?;
+                    }
+                }
+                _ => bail!("Usage: .history [N | search TEXT]"),
+            },
+            "count~" => {
+                let [table_name] = args else {
+                    bail!("Usage: .count~ TABLE");
+                };
+                let estimate = self.database.count_estimate(table_name)?;
+
+                writeln!(self.output, "{estimate}")?;
+            }
+            "export" => {
+                let [table_name, path] = args else {
+                    bail!("Usage: .export TABLE FILE");
+                };
+                let page_count = self.database.export_subtree(table_name, path)?;
+
+                writeln!(self.output, "exported {page_count} pages to {path}")?;
+            }
+            "describe" => {
+                let [table_name] = args else {
+                    bail!("Usage: .describe TABLE");
+                };
+                self.describe(table_name)?;
+            }
+            "grep" => {
+                let (search_names, pattern) = match args {
+                    ["--names", pattern @ ..] => (true, pattern),
+                    pattern => (false, pattern),
+                };
+                if pattern.is_empty() {
+                    bail!("Usage: .grep [--names] PATTERN");
+                }
+                // The pattern may contain spaces, which split it into several arguments
+                let pattern = pattern.join(" ");
+
+                let mut regex_cache = RegexCache::new();
+                for object in &self.schema {
+                    let mut texts = object.sql.iter().cloned().collect_vec();
+                    if search_names {
+                        texts.extend(object.names());
+                    }
+
+                    let mut is_match = false;
+                    for text in &texts {
+                        is_match = is_match || regex_cache.is_match(&pattern, text)?;
+                    }
+
+                    if is_match {
+                        let sql = object.sql.as_deref().unwrap_or_default();
+                        writeln!(self.output, "{} {}: {sql}", object.kind, object.name)?;
+                    }
+                }
+            }
+            name => {
+                let Some(command) = self.dot_commands.find(name) else {
+                    bail!("unknown command: .{name}");
+                };
+                let mut context = DotCommandContext {
+                    database: &self.database,
+                    schema: &self.schema,
+                    output: &mut self.output,
+                };
+                (command.run)(&mut context, args)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn run_sql(&mut self, sql: &str) -> Result<()> {
+        match parse_statement(sql) {
+            Ok((_, Statement::Explain(query))) => {
+                let (database, schema) =
+                    query_database(&self.database, &self.schema, &self.attached, &query)?;
+                let query = expand_views(schema, &query)?;
+                let plan = plan_query(database, schema, &query)?;
+
+                let operator = build_operators(database, &plan, &query)?;
+                writeln!(self.output, "{operator}")?;
+                for (name, program) in operator.programs() {
+                    writeln!(self.output, "\n{name}\n{program}")?;
+                }
+            }
+            Ok((_, Statement::ExplainAnalyze(query))) => {
+                let (database, schema) =
+                    query_database(&self.database, &self.schema, &self.attached, &query)?;
+                let query = expand_views(schema, &query)?;
+                let plan = plan_query(database, schema, &query)?;
+
+                writeln!(self.output, "{}", analyze(database, &plan, &query)?)?;
+            }
+            Ok((_, Statement::ExplainQueryPlan(query))) => {
+                let (database, schema) =
+                    query_database(&self.database, &self.schema, &self.attached, &query)?;
+                let query = expand_views(schema, &query)?;
+                let plan = plan_query(database, schema, &query)?;
+
+                writeln!(self.output, "{plan}")?;
+            }
+            Ok((_, Statement::Begin)) => self.begin_transaction(Database::begin)?,
+            Ok((_, Statement::Commit)) => self.end_transaction(true)?,
+            Ok((_, Statement::Rollback)) => self.end_transaction(false)?,
+            // Like a transaction, a savepoint spans the attached databases too
+            Ok((_, Statement::Savepoint(name))) => {
+                let savepoint = |database: &mut Database| database.savepoint(&name);
+                if self.database.in_transaction() {
+                    self.on_each_database(savepoint)?;
+                } else {
+                    self.begin_transaction(savepoint)?;
+                }
+            }
+            Ok((_, Statement::Release(name))) => {
+                self.on_each_database(|database| database.release(&name))?;
+            }
+            Ok((_, Statement::RollbackTo(name))) => {
+                self.on_each_database(|database| database.rollback_to(&name))?;
+                self.reload_schema()?;
+            }
+            Ok((_, Statement::CreateIndex { create_index, sql })) => {
+                self.database.create_index(&create_index, &sql)?;
+                self.schema = self.database.schema()?;
+                self.schema_cookie = self.database.schema_cookie;
+            }
+            Ok((_, Statement::Analyze(name))) => {
+                self.database.analyze(name.as_deref())?;
+                self.schema = self.database.schema()?;
+                self.schema_cookie = self.database.schema_cookie;
+            }
+            Ok((_, Statement::Vacuum { into: None })) => {
+                self.database.vacuum()?;
+                self.schema = self.database.schema()?;
+                self.schema_cookie = self.database.schema_cookie;
+            }
+            Ok((_, Statement::Vacuum { into: Some(path) })) => {
+                self.database.vacuum_into(Path::new(&path))?;
+            }
+            Ok((_, Statement::Attach { path, name })) => self.attach(&path, &name)?,
+            Ok((_, Statement::Detach(name))) => {
+                if self.database.in_transaction() {
+                    bail!("cannot DETACH database within transaction");
+                }
+                let Some(i) = self
+                    .attached
+                    .iter()
+                    .position(|attached| attached.name.eq_ignore_ascii_case(&name))
+                else {
+                    bail!("no such database: {name}");
+                };
+                self.attached.remove(i);
+            }
+            Ok((_, Statement::Pragma(pragma))) => {
+                let result = run_pragma(&self.database, &self.schema, &pragma)?;
+
+                let mut formatter = formatter(self.mode, &self.widths, self.full, self.headers);
+                formatter.write_header(&mut self.output, &result.columns)?;
+                for row in &result.rows {
+                    formatter.write_row(&mut self.output, row)?;
+                }
+                formatter.finish(&mut self.output)?;
+            }
+            Ok((_, Statement::Select(query))) => {
+                let (database, schema) =
+                    query_database(&self.database, &self.schema, &self.attached, &query)?;
+                let query = expand_views(schema, &query)?;
+                let plan = plan_query(database, schema, &query)?;
+                if self.explain {
+                    writeln!(self.output, "{plan}")?;
+                }
+
+                let mut formatter = formatter(self.mode, &self.widths, self.full, self.headers);
+                let rows = execute(database, &plan, &query)?;
+                formatter.write_header(&mut self.output, rows.columns())?;
+                for row in rows.take(self.limit.unwrap_or(usize::MAX)) {
+                    formatter.write_row(&mut self.output, &row?)?;
+                }
+                formatter.finish(&mut self.output)?;
+            }
+
+            Err(_) => bail!("near \"{}\": syntax error", syntax_error_near(sql)),
+        }
+
+        Ok(())
+    }
+
+    /// Runs the dot-commands and SQL statements in the script at `path` in turn, stopping at the
+    /// first that fails, with an error saying where it is
+    fn read_script(&mut self, path: &str) -> Result<()> {
+        let script = fs::read_to_string(path).with_context(|| format!("can't read {path}"))?;
+
+        let mut statement_number = 0;
+        for (line, command) in script_commands(&script) {
+            match command {
+                ScriptCommand::Dot(command) => {
+                    let dot_command = command.strip_prefix('.').unwrap_or(&command);
+                    self.run_dot_command(dot_command)
+                        .with_context(|| format!("line {line} of {path}"))?;
+                }
+                ScriptCommand::Sql(statement) => {
+                    statement_number += 1;
+                    self.run_statement(&statement)
+                        .map_err(|err| point_out(err, &statement))
+                        .with_context(|| {
+                            format!("statement {statement_number} of {path}, on line {line}")
+                        })?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads the schema again if another connection has changed it since it was last read, so
+    /// that queries are planned against the tables and indexes that are there now
+    fn reload_changed_schema(&mut self) -> Result<()> {
+        if self.database.in_transaction() {
+            return Ok(());
+        }
+        self.on_each_database(Database::refresh)?;
+
+        self.reload_schema()
+    }
+
+    /// Reads the schema of each database again if its cookie has changed since it was read
+    fn reload_schema(&mut self) -> Result<()> {
+        if self.database.schema_cookie != self.schema_cookie {
+            self.schema = self.database.schema()?;
+            self.schema_cookie = self.database.schema_cookie;
+        }
+        for attached in &mut self.attached {
+            if attached.database.schema_cookie != attached.schema_cookie {
+                attached.schema = attached.database.schema()?;
+                attached.schema_cookie = attached.database.schema_cookie;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Adds a command entered at the prompt to the history
+    pub fn remember(&mut self, command: &str) -> Result<()> {
+        let database = self.database.options.path.display().to_string();
+
+        self.history.add(&database, command)
+    }
+
+    /// Lists the last `count` commands run against this database
+    fn write_history(&mut self, count: usize) -> Result<()> {
+        let database = self.database.options.path.display().to_string();
+        let entries = self
+            .history
+            .entries()
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| entry.database == database)
+            .collect_vec();
+
+        for (i, entry) in &entries[entries.len().saturating_sub(count)..] {
+            writeln!(
+                self.output,
+                "{:>5}  {}  {}",
+                i + 1,
+                format_timestamp(entry.timestamp),
+                entry.command
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Summarizes a table: its columns, indexes, size and first few rows
+    fn describe(&mut self, table_name: &str) -> Result<()> {
+        let Some(table) = self
+            .schema
+            .iter()
+            .find(|s| s.is_table() && s.name.eq_ignore_ascii_case(table_name))
+        else {
+            bail!("no such table: {table_name}");
+        };
+        let create_table = table.create_table()?;
+        let size = self.database.btree_size(table.root_page)?;
+        let page_size = self.database.page_size as u64;
+
+        writeln!(self.output, "table: {}", table.name)?;
+        writeln!(self.output, "columns:")?;
+        for (i, column) in create_table.columns.iter().enumerate() {
+            let rowid_alias = if create_table.rowid_alias == Some(i) {
+                " (rowid)"
+            } else {
+                ""
+            };
+            writeln!(
+                self.output,
+                "  {} {}{rowid_alias}",
+                column.name, column.type_name
+            )?;
+        }
+
+        writeln!(self.output, "indexes:")?;
+        for index in self
+            .schema
+            .iter()
+            .filter(|s| s.is_index() && s.table_name == table.name)
+        {
+            let create_index = index
+                .create_index()?
+                .or_else(|| create_table.autoindex(&index.name));
+            let columns = match create_index {
+                Some(create_index) if create_index.unique => {
+                    format!("UNIQUE ({})", create_index.columns.join(", "))
+                }
+                Some(create_index) => format!("({})", create_index.columns.join(", ")),
+                None => "(internal)".to_string(),
+            };
+            let index_size = self.database.btree_size(index.root_page)?;
+            writeln!(
+                self.output,
+                "  {} {columns}, {} pages",
+                index.name, index_size.pages
+            )?;
+        }
+
+        writeln!(self.output, "rows: {}", size.entries)?;
+        writeln!(
+            self.output,
+            "size: {} pages ({} bytes)",
+            size.pages,
+            size.pages as u64 * page_size
+        )?;
+
+        writeln!(self.output, "sample rows:")?;
+        let root_page = table.root_page;
+        let separator = match self.mode {
+            OutputMode::Tabs => "\t",
+            _ => "|",
+        };
+        let rows = self.database.table_rows(root_page, &create_table)?;
+        for record in rows.take(SAMPLE_ROWS) {
+            let record = record?;
+            let mut values = record.values();
+            if let Some(value) = create_table.rowid_alias.and_then(|i| values.get_mut(i)) {
+                *value = Value::Integer(record.row_id);
+            }
+            writeln!(self.output, "  {}", values.iter().join(separator))?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes a row for each b-tree in the schema whose name matches `pattern`, sqlite_schema's
+    /// first, with what its pages hold
+    fn write_stats(&mut self, pattern: Option<&str>) -> Result<()> {
+        let columns = ["name", "pages", "cells", "payload", "unused", "overflow"];
+        let objects = [("sqlite_schema".to_string(), 1)].into_iter().chain(
+            self.schema
+                .iter()
+                .filter(|s| s.root_page > 0)
+                .map(|s| (s.name.clone(), s.root_page)),
+        );
+
+        let mut formatter = formatter(self.mode, &self.widths, self.full, self.headers);
+        formatter.write_header(&mut self.output, &columns.map(String::from))?;
+        for (name, root_page) in objects {
+            if pattern.is_some_and(|pattern| !like(pattern, &name, None)) {
+                continue;
+            }
+            let stats = self.database.btree_stats(root_page)?;
+            let row = [
+                Value::Text(name),
+                Value::Integer(stats.pages as i64),
+                Value::Integer(stats.cells as i64),
+                Value::Integer(stats.payload_bytes as i64),
+                Value::Integer(stats.unused_bytes as i64),
+                Value::Integer(stats.overflow_pages as i64),
+            ];
+            formatter.write_row(&mut self.output, &row)?;
+        }
+        formatter.finish(&mut self.output)?;
+
+        Ok(())
+    }
+
+    /// Sends output to a file, or back to stdout when no file is given
+    fn redirect_output(&mut self, args: &[&str], once: bool) -> Result<()> {
+        self.output.flush()?;
+
+        match args {
+            [] if !once => self.output = stdout(),
+            [path] => self.output = Box::new(BufWriter::new(File::create(path)?)),
+            _ if once => bail!("Usage: .once FILE"),
+            _ => bail!("Usage: .output [FILE]"),
+        }
+        self.output_once = once;
+
+        Ok(())
+    }
+
+    /// Pipes the output of the commands after this one through `command`, which may be quoted,
+    /// or stops piping when it's empty
+    fn set_pipe(&mut self, command: &str) -> Result<()> {
+        let command = ['\'', '"']
+            .into_iter()
+            .find_map(|quote| command.strip_prefix(quote)?.strip_suffix(quote))
+            .unwrap_or(command);
+        self.pipe = (!command.is_empty()).then(|| command.to_string());
+
+        Ok(())
+    }
+
+    /// Starts the .pipe command, if there is one, and sends the output to it until the command
+    /// that's about to run finishes. Output redirected by .once goes to its file instead.
+    fn start_pipe(&mut self) -> Result<()> {
+        let Some(command) = &self.pipe else {
+            return Ok(());
+        };
+        if self.output_once {
+            return Ok(());
+        }
+
+        self.output.flush()?;
+        let mut child = spawn_command(command).with_context(|| format!("can't run {command}"))?;
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let output = mem::replace(&mut self.output, Box::new(BufWriter::new(stdin)));
+        self.piped = Some((child, output));
+
+        Ok(())
+    }
+
+    /// Flushes the output of a command that's finished with `result`, and puts the output back
+    /// if it was only redirected for that command
+    fn finish_command(&mut self, result: Result<()>) -> Result<()> {
+        let flushed = self.output.flush();
+
+        if let Some((mut child, output)) = self.piped.take() {
+            // Dropping the pipe closes it, so that the command sees the end of its input
+            self.output = output;
+            let status = child.wait()?;
+            if !status.success() {
+                bail!(
+                    "{} failed ({status})",
+                    self.pipe.as_deref().unwrap_or_default()
+                );
+            }
+            // A command that stops reading early, like head, closes the pipe on purpose
+            return match result.and(flushed.map_err(Into::into)) {
+                Err(err) if is_broken_pipe(&err) => Ok(()),
+                result => result,
+            };
+        }
+        flushed?;
+
+        if self.output_once {
+            self.output = stdout();
+            self.output_once = false;
+        }
+
+        result
+    }
+}
+
+/// The database a query reads from, and its schema. A table is in the database it's named as
+/// being in, or else the first that has it, looking in the main database before those attached
+/// in turn. Every table a query reads has to be in the same database.
+fn query_database<'a>(
+    database: &'a Database,
+    schema: &'a [Schema],
+    attached: &'a [Attached],
+    query: &Query,
+) -> Result<(&'a Database, &'a [Schema])> {
+    let databases = iter::once(("main", database, schema))
+        .chain(
+            attached
+                .iter()
+                .map(|a| (a.name.as_str(), &a.database, a.schema.as_slice())),
+        )
+        .collect_vec();
+    let mut found = vec![];
+    for (schema_name, table) in query.tables() {
+        let has_table = |schema: &[Schema]| {
+            schema
+                .iter()
+                .any(|s| (s.is_table() || s.is_view()) && s.name.eq_ignore_ascii_case(table))
+        };
+        let i = match schema_name {
+            Some(name) => match databases
+                .iter()
+                .position(|d| d.0.eq_ignore_ascii_case(name))
+            {
+                Some(i) => i,
+                None => bail!("no such table: {name}.{table}"),
+            },
+            // A table that's nowhere is left for planning against main to say so
+            None => databases.iter().position(|d| has_table(d.2)).unwrap_or(0),
+        };
+        if !found.contains(&i) {
+            found.push(i);
+        }
+    }
+
+    match found[..] {
+        [] => Ok((database, schema)),
+        [i] => Ok((databases[i].1, databases[i].2)),
+        [i, j, ..] => bail!(
+            "Unhandled query reading from more than one database: {} and {}",
+            databases[i].0,
+            databases[j].0
+        ),
+    }
+}
+
+fn stdout() -> Box<dyn Write> {
+    Box::new(BufWriter::with_capacity(STDOUT_BUFFER_SIZE, io::stdout()))
+}
+
+/// Whether writing output failed because its reader has gone, like head once it has read
+/// enough. The command that was writing stops there, having nobody to write to.
+pub fn is_broken_pipe(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<io::Error>()
+        .is_some_and(|err| err.kind() == io::ErrorKind::BrokenPipe)
+}
+
+/// Adds the line of SQL to an error about one of its column references, with a caret under the
+/// reference, as sqlite3 does
+fn point_out(err: anyhow::Error, sql: &str) -> anyhow::Error {
+    let Some(offset) = err
+        .downcast_ref::<BindError>()
+        .and_then(|bind_error| bind_error.offset_in(sql))
+    else {
+        return err;
+    };
+
+    // Only the line the reference is on is shown, for a statement that spans lines
+    let line_start = sql[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = sql[offset..].find('\n').map_or(sql.len(), |i| offset + i);
+    let indent = sql[line_start..offset].chars().count();
+    anyhow!(
+        "{err}\n  {}\n  {}^--- error here",
+        &sql[line_start..line_end],
+        " ".repeat(indent)
+    )
+}
@@ -0,0 +1,55 @@
+use sqlite_starter_rust::interrupt::InterruptHandle;
+use std::{io, sync::OnceLock};
+
+/// The handle Ctrl-C interrupts, set once the handler is installed
+static HANDLE: OnceLock<InterruptHandle> = OnceLock::new();
+
+/// Makes Ctrl-C interrupt the running statement through `handle`, instead of ending the process,
+/// which could stop it in the middle of writing the database. At the prompt, Ctrl-C does nothing.
+pub fn interrupt_on_ctrl_c(handle: InterruptHandle) -> io::Result<()> {
+    if HANDLE.set(handle).is_err() {
+        return Err(io::Error::other("Ctrl-C is already handled"));
+    }
+
+    sys::install()
+}
+
+#[cfg(unix)]
+mod sys {
+    use super::HANDLE;
+    use std::{ffi::c_int, io};
+
+    const SIGINT: c_int = 2;
+    const SIG_ERR: usize = usize::MAX;
+
+    extern "C" {
+        fn signal(signum: c_int, handler: usize) -> usize;
+    }
+
+    /// Runs in the signal handler, so it does no more than an atomic store
+    extern "C" fn on_interrupt(_signum: c_int) {
+        if let Some(handle) = HANDLE.get() {
+            handle.interrupt();
+        }
+    }
+
+    pub fn install() -> io::Result<()> {
+        // SAFETY: the handler only touches an atomic, which is safe to do in a signal handler
+        let previous = unsafe { signal(SIGINT, on_interrupt as extern "C" fn(c_int) as usize) };
+        if previous == SIG_ERR {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+}
+
+/// Elsewhere, Ctrl-C ends the process as usual
+#[cfg(not(unix))]
+mod sys {
+    use std::io;
+
+    pub fn install() -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,240 @@
+use crate::{
+    record::{encode_record, parse_record},
+    value::Value,
+    varint,
+};
+use anyhow::Result;
+use std::{
+    cmp::Ordering,
+    env,
+    fs::{self, File},
+    io::{BufReader, BufWriter, ErrorKind, Read, Write},
+    iter, mem,
+    path::PathBuf,
+    process,
+    sync::atomic::{AtomicUsize, Ordering as AtomicOrdering},
+};
+
+/// The most runs a Sorter keeps at once, each with a file open while they're merged. Past this
+/// many, they're merged into one.
+const MAX_RUNS: usize = 16;
+
+/// Numbers the temporary files of every Sorter in the process, so that none share a name
+static NEXT_RUN: AtomicUsize = AtomicUsize::new(0);
+
+/// Sorts rows for ORDER BY. Rows are kept in memory until they take up more than `max_memory`
+/// bytes, at which point they're sorted and written to a temporary file, a run. With runs on
+/// disk, the sorted rows come from merging them. Without the fs feature, rows are only ever
+/// kept in memory.
+pub struct Sorter<F: Fn(&[Value], &[Value]) -> Ordering> {
+    compare: F,
+    max_memory: Option<usize>,
+    rows: Vec<Vec<Value>>,
+    /// Roughly how much memory `rows` takes up
+    memory: usize,
+    runs: Vec<Run>,
+}
+
+impl<F: Fn(&[Value], &[Value]) -> Ordering + 'static> Sorter<F> {
+    /// A sorter that orders rows with `compare`, using as much memory as it needs if there's no
+    /// `max_memory`
+    pub fn new(compare: F, max_memory: Option<usize>) -> Self {
+        Sorter {
+            compare,
+            max_memory,
+            rows: vec![],
+            memory: 0,
+            runs: vec![],
+        }
+    }
+
+    pub fn push(&mut self, row: Vec<Value>) -> Result<()> {
+        self.memory += row_memory(&row);
+        self.rows.push(row);
+
+        // Without the file system, there's nowhere to spill to
+        if cfg!(feature = "fs")
+            && self
+                .max_memory
+                .is_some_and(|max_memory| self.memory > max_memory)
+        {
+            self.spill()?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the rows in memory to a new run, first merging the runs there are into one if
+    /// there are MAX_RUNS of them
+    fn spill(&mut self) -> Result<()> {
+        if self.runs.len() >= MAX_RUNS {
+            let runs = mem::take(&mut self.runs);
+            let run = write_run(merge(runs, &self.compare)?)?;
+            self.runs.push(run);
+        }
+
+        let mut rows = mem::take(&mut self.rows);
+        rows.sort_by(|a, b| (self.compare)(a, b));
+        self.memory = 0;
+        self.runs.push(write_run(rows.into_iter().map(Ok))?);
+
+        Ok(())
+    }
+
+    /// The rows in order
+    pub fn finish(mut self) -> Result<Box<dyn Iterator<Item = Result<Vec<Value>>>>> {
+        if self.runs.is_empty() {
+            self.rows.sort_by(|a, b| (self.compare)(a, b));
+            return Ok(Box::new(self.rows.into_iter().map(Ok)));
+        }
+
+        if !self.rows.is_empty() {
+            self.spill()?;
+        }
+        let runs = mem::take(&mut self.runs);
+
+        Ok(Box::new(merge(runs, self.compare)?))
+    }
+}
+
+/// Writes rows, which are already in order, to a new run
+fn write_run(rows: impl Iterator<Item = Result<Vec<Value>>>) -> Result<Run> {
+    let path = env::temp_dir().join(format!(
+        "sqlite-rust-sort-{}-{}",
+        process::id(),
+        NEXT_RUN.fetch_add(1, AtomicOrdering::Relaxed)
+    ));
+    let run = Run { path };
+    let mut file = BufWriter::new(
+        File::options()
+            .write(true)
+            .create_new(true)
+            .open(&run.path)?,
+    );
+    for row in rows {
+        let record = encode_record(&row?, 4);
+        varint::write_varint(&mut file, record.len() as i64)?;
+        file.write_all(&record)?;
+    }
+    file.flush()?;
+
+    Ok(run)
+}
+
+/// The rows of `runs` in order, read from each run as they're needed
+fn merge(
+    runs: Vec<Run>,
+    compare: impl Fn(&[Value], &[Value]) -> Ordering,
+) -> Result<impl Iterator<Item = Result<Vec<Value>>>> {
+    let mut readers = vec![];
+    for run in runs {
+        let mut reader = RunReader {
+            file: BufReader::new(File::open(&run.path)?),
+            next: None,
+            _run: run,
+        };
+        reader.advance()?;
+        readers.push(reader);
+    }
+
+    Ok(iter::from_fn(move || {
+        // There are at most MAX_RUNS runs, few enough that looking at each one's next row to
+        // find the smallest is fine
+        let smallest = readers
+            .iter()
+            .enumerate()
+            .filter_map(|(i, reader)| Some((i, reader.next.as_ref()?)))
+            .min_by(|(_, a), (_, b)| compare(a, b))
+            .map(|(i, _)| i)?;
+
+        let row = readers[smallest].next.take()?;
+        Some(readers[smallest].advance().map(|_| row))
+    }))
+}
+
+/// A file of sorted rows, removed when it's dropped
+struct Run {
+    path: PathBuf,
+}
+
+impl Drop for Run {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Reads a run's rows back, one at a time: each is a varint length, then a record
+struct RunReader {
+    file: BufReader<File>,
+    /// The run's next row, or None once it's all been read
+    next: Option<Vec<Value>>,
+    _run: Run,
+}
+
+impl RunReader {
+    fn advance(&mut self) -> Result<()> {
+        let length = match varint::parse_varint_from_reader(&mut self.file) {
+            Ok((length, _)) => length,
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => {
+                self.next = None;
+                return Ok(());
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut record = vec![0; length as usize];
+        self.file.read_exact(&mut record)?;
+        let (_, serial_values) = parse_record(&record)?;
+        self.next = Some(serial_values.into_iter().map(Value::from).collect());
+
+        Ok(())
+    }
+}
+
+/// Roughly how many bytes a row takes up in memory
+fn row_memory(row: &[Value]) -> usize {
+    let heap: usize = row
+        .iter()
+        .map(|value| match value {
+            Value::Text(text) => text.len(),
+            Value::Blob(blob) => blob.len(),
+            _ => 0,
+        })
+        .sum();
+
+    mem::size_of::<Vec<Value>>() + mem::size_of_val(row) + heap
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sort(values: &[i64], max_memory: Option<usize>) -> Vec<Value> {
+        let mut sorter = Sorter::new(|a: &[Value], b: &[Value]| b[0].compare(&a[0]), max_memory);
+        for &value in values {
+            sorter
+                .push(vec![
+                    Value::Integer(value),
+                    Value::Text(format!("row {value}")),
+                ])
+                .unwrap();
+        }
+
+        sorter
+            .finish()
+            .unwrap()
+            .map(|row| row.unwrap().remove(0))
+            .collect()
+    }
+
+    #[test]
+    fn test_sorter() {
+        let values: Vec<i64> = (0..1000).map(|i| i * 7919 % 1000).collect();
+        let descending: Vec<_> = (0..1000).rev().map(Value::Integer).collect();
+
+        assert_eq!(sort(&values, None), descending);
+        // Small enough that the rows are spread across dozens of runs, which get merged
+        assert_eq!(sort(&values, Some(2000)), descending);
+        assert!(sort(&[], Some(0)).is_empty());
+    }
+}
@@ -0,0 +1,117 @@
+use crate::{
+    cell::checked_payload_size,
+    database::Database,
+    header::{BTreePage, PageHeader, DATABASE_HEADER_SIZE},
+    overflow, varint,
+};
+use anyhow::{bail, Result};
+
+/// What the pages of a b-tree hold, adding up to the totals of SQLite's dbstat table for it
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BtreeStats {
+    /// Interior and leaf pages, not counting overflow pages
+    pub pages: u32,
+    /// Cells on every page, interior ones included
+    pub cells: u64,
+    /// Bytes of payload, both in cells and on overflow pages
+    pub payload_bytes: u64,
+    /// Bytes holding nothing: space between the cell pointers and the cells, free blocks,
+    /// fragments, and what's left over on the last overflow page of each payload
+    pub unused_bytes: u64,
+    pub overflow_pages: u32,
+}
+
+impl Database {
+    /// Reads every page of the b-tree rooted at `root_page` to add up what's on them. Overflow
+    /// pages aren't read, as their number and contents follow from the payload sizes.
+    pub fn btree_stats(&self, root_page: u32) -> Result<BtreeStats> {
+        let mut stats = BtreeStats::default();
+        let mut pages_to_visit = vec![root_page];
+
+        while let Some(page_num) = pages_to_visit.pop() {
+            stats.pages += 1;
+            if stats.pages > self.page_count {
+                bail!("b-tree rooted at page {root_page} has more pages than the database");
+            }
+            self.add_page_stats(page_num, &mut stats, &mut pages_to_visit)?;
+        }
+
+        Ok(stats)
+    }
+
+    fn add_page_stats(
+        &self,
+        page_num: u32,
+        stats: &mut BtreeStats,
+        children: &mut Vec<u32>,
+    ) -> Result<()> {
+        let bytes = self.page_bytes(page_num)?;
+        let header_start = if page_num == 1 {
+            DATABASE_HEADER_SIZE
+        } else {
+            0
+        };
+        let header = PageHeader::parse(&bytes[header_start..])?;
+        let page_type = header.page_type;
+        let cell_pointers_start = header_start + page_type.header_size();
+        let cell_count = header.number_of_cells as usize;
+        let Some(cell_pointers) =
+            bytes.get(cell_pointers_start..cell_pointers_start + 2 * cell_count)
+        else {
+            bail!("page {page_num} has more cell pointers than fit on it");
+        };
+
+        // A content area starting at 0 starts at 65536, the end of the biggest page
+        let content_start = match header.start_of_content_area {
+            0 => 65_536,
+            start => start as usize,
+        };
+        let mut unused = content_start.saturating_sub(cell_pointers_start + 2 * cell_count)
+            + header.fragmented_free_bytes as usize;
+        let mut free_block = header.first_free_block_start as usize;
+        while free_block != 0 {
+            let Some(block) = bytes.get(free_block..free_block + 4) else {
+                bail!("page {page_num} has a free block past its end");
+            };
+            let next = u16::from_be_bytes([block[0], block[1]]) as usize;
+            unused += u16::from_be_bytes([block[2], block[3]]) as usize;
+            // Free blocks are in order of their offsets, so anything else is a loop
+            if next != 0 && next <= free_block {
+                bail!("page {page_num} has free blocks out of order");
+            }
+            free_block = next;
+        }
+
+        stats.cells += cell_count as u64;
+        stats.unused_bytes += unused as u64;
+        for pointer in cell_pointers.chunks(2) {
+            let offset = u16::from_be_bytes([pointer[0], pointer[1]]) as usize;
+            let cell = bytes.get(offset..).unwrap_or_default();
+            if cell.is_empty() || page_type.is_interior() && cell.len() < 4 {
+                bail!("page {page_num} has a cell past its end");
+            }
+            if page_type.is_interior() {
+                children.push(u32::from_be_bytes(cell[..4].try_into()?));
+            }
+            // An interior table cell is a child pointer and a rowid, with no payload
+            if page_type == BTreePage::InteriorTable {
+                continue;
+            }
+
+            let payload_start = if page_type.is_interior() { 4 } else { 0 };
+            let (payload_size, _) = varint::parse_varint(&cell[payload_start..]);
+            let payload_size = checked_payload_size(payload_size)?;
+            let split = overflow::thresholds(self.page_size, payload_size as u64, page_type)?;
+            stats.payload_bytes += payload_size as u64;
+            if split.overflow > 0 {
+                let overflow_page_size = self.page_size as usize - 4;
+                let overflow_pages = split.overflow.div_ceil(overflow_page_size);
+                stats.overflow_pages += overflow_pages as u32;
+                stats.unused_bytes += (overflow_pages * overflow_page_size - split.overflow) as u64;
+            }
+        }
+        children.extend(header.right_most_pointer);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,322 @@
+use sqlite_starter_rust::value::Value;
+use std::io::{self, Write};
+
+/// The widest a column grows to fit its values before they're cut short with an ellipsis
+pub const MAX_AUTO_WIDTH: usize = 40;
+
+/// The characters a table is drawn with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Border {
+    /// +, - and |, as in .mode table
+    Ascii,
+    /// Unicode box-drawing characters, as in .mode box
+    Box,
+    /// A Markdown pipe table, as in .mode markdown, which has no top or bottom line
+    Markdown,
+    /// No lines at all, just dashes under the heading and two spaces between columns, as in
+    /// .mode column. Numeric columns are aligned to the right.
+    Column,
+}
+
+struct BorderChars {
+    horizontal: char,
+    vertical: char,
+    /// The left, middle and right corners of the top, header separator and bottom lines
+    top: Option<[char; 3]>,
+    separator: [char; 3],
+    bottom: Option<[char; 3]>,
+}
+
+impl Border {
+    fn chars(&self) -> BorderChars {
+        match self {
+            Border::Ascii => BorderChars {
+                horizontal: '-',
+                vertical: '|',
+                top: Some(['+'; 3]),
+                separator: ['+'; 3],
+                bottom: Some(['+'; 3]),
+            },
+            Border::Box => BorderChars {
+                horizontal: '─',
+                vertical: '│',
+                top: Some(['┌', '┬', '┐']),
+                separator: ['├', '┼', '┤'],
+                bottom: Some(['└', '┴', '┘']),
+            },
+            Border::Markdown => BorderChars {
+                horizontal: '-',
+                vertical: '|',
+                top: None,
+                separator: ['|'; 3],
+                bottom: None,
+            },
+            Border::Column => BorderChars {
+                horizontal: '-',
+                vertical: ' ',
+                top: None,
+                separator: [' '; 3],
+                bottom: None,
+            },
+        }
+    }
+}
+
+/// Lays out result rows as a table with a heading, sizing each column to fit
+pub struct Table<'a> {
+    pub border: Border,
+    /// Widths set with .width. A missing entry or 0 sizes the column to fit, up to MAX_AUTO_WIDTH.
+    pub widths: &'a [usize],
+    /// Never cut values short, so that columns are as wide as their widest value
+    pub full: bool,
+    /// Write the heading and the line under it, which .headers off leaves out of .mode column
+    pub headers: bool,
+}
+
+impl Table<'_> {
+    pub fn write(
+        &self,
+        output: &mut dyn Write,
+        headers: &[String],
+        rows: &[Vec<Value>],
+    ) -> io::Result<()> {
+        // Columns of numbers line up on their last digit, when no border runs down between them
+        let right_aligned: Vec<bool> = (0..headers.len())
+            .map(|i| {
+                self.border == Border::Column
+                    && rows.iter().any(|row| row[i] != Value::Null)
+                    && rows.iter().all(|row| {
+                        matches!(row[i], Value::Null | Value::Integer(_) | Value::Real(_))
+                    })
+            })
+            .collect();
+        let rows: Vec<Vec<String>> = rows
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|value| match self.border {
+                        // A pipe would end the cell early
+                        Border::Markdown => value.to_string().replace('|', "\\|"),
+                        _ => value.to_string(),
+                    })
+                    .collect()
+            })
+            .collect();
+        let widths: Vec<usize> = (0..headers.len())
+            .map(|i| {
+                let natural = rows
+                    .iter()
+                    .map(|row| char_count(&row[i]))
+                    .chain([char_count(&headers[i])])
+                    .max()
+                    .unwrap_or_default();
+
+                match self.widths.get(i).copied().unwrap_or_default() {
+                    0 if self.full => natural,
+                    0 => natural.min(MAX_AUTO_WIDTH),
+                    width if self.full => width.max(natural),
+                    width => width,
+                }
+                .max(1)
+            })
+            .collect();
+
+        let chars = self.border.chars();
+        let column = self.border == Border::Column;
+        let line = |corners: [char; 3]| {
+            if column {
+                return widths
+                    .iter()
+                    .map(|&width| chars.horizontal.to_string().repeat(width))
+                    .collect::<Vec<_>>()
+                    .join("  ");
+            }
+
+            let segments: Vec<String> = widths
+                .iter()
+                .map(|width| chars.horizontal.to_string().repeat(width + 2))
+                .collect();
+            format!(
+                "{}{}{}",
+                corners[0],
+                segments.join(&corners[1].to_string()),
+                corners[2]
+            )
+        };
+        let cells = |cells: Vec<String>| {
+            if column {
+                return cells.join("  ").trim_end().to_string();
+            }
+            let vertical = chars.vertical;
+            format!(
+                "{vertical} {} {vertical}",
+                cells.join(&format!(" {vertical} "))
+            )
+        };
+
+        if let Some(top) = chars.top {
+            writeln!(output, "{}", line(top))?;
+        }
+        let align = |text: &str, width: usize, i: usize| {
+            if right_aligned[i] {
+                format!("{:>width$}", truncate(text, width))
+            } else {
+                format!("{:width$}", truncate(text, width))
+            }
+        };
+        if self.headers {
+            let heading = headers
+                .iter()
+                .zip(&widths)
+                .enumerate()
+                .map(|(i, (header, &width))| {
+                    if column {
+                        align(header, width, i)
+                    } else {
+                        center(&truncate(header, width), width)
+                    }
+                })
+                .collect();
+            writeln!(output, "{}", cells(heading))?;
+            writeln!(output, "{}", line(chars.separator))?;
+        }
+        for row in &rows {
+            let row = row
+                .iter()
+                .zip(&widths)
+                .enumerate()
+                .map(|(i, (value, &width))| align(value, width, i))
+                .collect();
+            writeln!(output, "{}", cells(row))?;
+        }
+        if let Some(bottom) = chars.bottom {
+            writeln!(output, "{}", line(bottom))?;
+        }
+
+        Ok(())
+    }
+}
+
+fn char_count(text: &str) -> usize {
+    text.chars().count()
+}
+
+/// Cuts text that doesn't fit in width characters short, ending it with an ellipsis
+fn truncate(text: &str, width: usize) -> String {
+    if char_count(text) <= width {
+        return text.to_string();
+    }
+
+    let mut truncated: String = text.chars().take(width - 1).collect();
+    truncated.push('…');
+    truncated
+}
+
+fn center(text: &str, width: usize) -> String {
+    let padding = width - char_count(text);
+    let left = padding / 2;
+
+    format!("{}{text}{}", " ".repeat(left), " ".repeat(padding - left))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(table: Table, headers: &[&str], rows: &[Vec<Value>]) -> String {
+        let headers: Vec<String> = headers.iter().map(|h| h.to_string()).collect();
+        let mut output = Vec::new();
+        table.write(&mut output, &headers, rows).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_table() {
+        let rows = vec![
+            vec![Value::Integer(1), Value::Text("Granny Smith".to_string())],
+            vec![Value::Integer(2), Value::Text("Fuji".to_string())],
+        ];
+        let table = Table {
+            border: Border::Ascii,
+            widths: &[],
+            full: false,
+            headers: true,
+        };
+
+        assert_eq!(
+            render(table, &["id", "name"], &rows),
+            "+----+--------------+\n\
+             | id |     name     |\n\
+             +----+--------------+\n\
+             | 1  | Granny Smith |\n\
+             | 2  | Fuji         |\n\
+             +----+--------------+\n"
+        );
+
+        let table = Table {
+            border: Border::Box,
+            widths: &[0, 6],
+            full: false,
+            headers: true,
+        };
+        assert_eq!(
+            render(table, &["id", "name"], &rows[..1]),
+            "┌────┬────────┐\n\
+             │ id │  name  │\n\
+             ├────┼────────┤\n\
+             │ 1  │ Grann… │\n\
+             └────┴────────┘\n"
+        );
+    }
+
+    #[test]
+    fn test_column() {
+        let rows = vec![
+            vec![Value::Integer(1), Value::Text("Granny Smith".to_string())],
+            vec![Value::Integer(20), Value::Null],
+        ];
+        let table = |headers| Table {
+            border: Border::Column,
+            widths: &[],
+            full: false,
+            headers,
+        };
+
+        assert_eq!(
+            render(table(true), &["id", "name"], &rows),
+            "id  name\n\
+             --  ------------\n\
+             \x201  Granny Smith\n\
+             20\n"
+        );
+        assert_eq!(
+            render(table(false), &["id", "name"], &rows[..1]),
+            " 1  Granny Smith\n"
+        );
+    }
+
+    #[test]
+    fn test_truncate_wide_columns() {
+        let rows = vec![vec![Value::Text("x".repeat(100))]];
+
+        let table = Table {
+            border: Border::Ascii,
+            widths: &[],
+            full: false,
+            headers: true,
+        };
+        let output = render(table, &["json"], &rows);
+        let value_line = output.lines().nth(3).unwrap();
+        assert_eq!(char_count(value_line), MAX_AUTO_WIDTH + 4);
+        assert!(value_line.ends_with("x… |"));
+
+        let table = Table {
+            border: Border::Ascii,
+            widths: &[10],
+            full: true,
+            headers: true,
+        };
+        let output = render(table, &["json"], &rows);
+        assert!(output.contains(&"x".repeat(100)));
+    }
+}
@@ -0,0 +1,121 @@
+//! Events describing what the library does, like which pages it reads and which plans it picks,
+//! for diagnosing slow queries on real databases. They're only compiled in with the trace
+//! feature, and only made once a subscriber is set, so otherwise they cost nothing.
+
+use anyhow::{bail, Result};
+use std::{
+    fmt,
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+/// Something that happened, as passed to the subscriber
+#[derive(Debug)]
+pub struct Event<'a> {
+    /// What the event is about: "page", "cache", "btree" or "plan"
+    pub target: &'static str,
+    pub message: fmt::Arguments<'a>,
+    /// How long it took, for events that end a span of work
+    pub elapsed: Option<Duration>,
+}
+
+impl fmt::Display for Event<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.target, self.message)?;
+        if let Some(elapsed) = self.elapsed {
+            write!(f, " ({elapsed:?})")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Receives every event, from whichever thread made it
+pub type Subscriber = dyn Fn(&Event) + Send + Sync;
+
+static SUBSCRIBER: OnceLock<Box<Subscriber>> = OnceLock::new();
+
+/// Sets the subscriber that events go to from now on. There can only be one, set once.
+pub fn set_subscriber(subscriber: Box<Subscriber>) -> Result<()> {
+    if !cfg!(feature = "trace") {
+        bail!("tracing needs the trace feature");
+    }
+    if SUBSCRIBER.set(subscriber).is_err() {
+        bail!("a trace subscriber is already set");
+    }
+
+    Ok(())
+}
+
+/// Whether events are being made, which is worth checking before any work to describe one
+#[inline]
+pub fn enabled() -> bool {
+    cfg!(feature = "trace") && SUBSCRIBER.get().is_some()
+}
+
+/// Passes an event to the subscriber. Use the trace_event! macro rather than calling this.
+#[doc(hidden)]
+pub fn dispatch(target: &'static str, message: fmt::Arguments, elapsed: Option<Duration>) {
+    if let Some(subscriber) = SUBSCRIBER.get() {
+        subscriber(&Event {
+            target,
+            message,
+            elapsed,
+        });
+    }
+}
+
+/// Makes an event, formatting its message as format! does, if there's a subscriber
+#[macro_export]
+macro_rules! trace_event {
+    ($target:literal, $($arg:tt)+) => {
+        if $crate::trace::enabled() {
+            $crate::trace::dispatch($target, format_args!($($arg)+), None);
+        }
+    };
+}
+
+/// Times a span of work, to be ended with an event saying how long it took
+pub struct Span {
+    start: Option<Instant>,
+}
+
+impl Span {
+    /// Starts timing, unless events aren't being made
+    pub fn start() -> Self {
+        Span {
+            start: enabled().then(Instant::now),
+        }
+    }
+
+    /// Makes an event with the time since the span started
+    pub fn end(self, target: &'static str, message: fmt::Arguments) {
+        if let Some(start) = self.start {
+            dispatch(target, message, Some(start.elapsed()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_display() {
+        let event = Event {
+            target: "page",
+            message: format_args!("read page {}", 2),
+            elapsed: Some(Duration::from_millis(3)),
+        };
+        assert_eq!(event.to_string(), "page: read page 2 (3ms)");
+    }
+
+    #[test]
+    fn test_set_subscriber() {
+        let result = set_subscriber(Box::new(|_| {}));
+        assert_eq!(result.is_ok(), cfg!(feature = "trace"));
+        assert_eq!(enabled(), cfg!(feature = "trace"));
+        // There can only be one
+        assert!(set_subscriber(Box::new(|_| {})).is_err());
+    }
+}
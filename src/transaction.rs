@@ -0,0 +1,215 @@
+use crate::{database::Database, lock::LockLevel, page_source::PageSource};
+use anyhow::{bail, Context, Result};
+use std::{collections::HashMap, sync::PoisonError};
+
+/// The pages as they were at a SAVEPOINT, or at the start of a statement within a transaction,
+/// so that what's written after it can be undone without undoing the rest of the transaction, as
+/// SQLite's statement journal does. They're kept in memory: they're only needed until the
+/// transaction ends.
+pub(crate) struct Savepoint {
+    /// What SAVEPOINT called it, or None for a statement's
+    name: Option<String>,
+    /// The database's size in pages when the statement began. Pages after it are new, so instead
+    /// of being kept they're cut off when it's undone.
+    page_count: u32,
+    /// What the pages the statement has written held before it wrote them
+    pages: HashMap<u32, Vec<u8>>,
+}
+
+impl Savepoint {
+    fn new(name: Option<String>, page_count: u32) -> Self {
+        Savepoint {
+            name,
+            page_count,
+            pages: HashMap::new(),
+        }
+    }
+
+    /// Whether what `page_number` holds has yet to be kept, before it's written
+    pub fn needs(&self, page_number: u32) -> bool {
+        page_number <= self.page_count && !self.pages.contains_key(&page_number)
+    }
+
+    /// Keeps what `page_number` holds, before it's written
+    pub fn add(&mut self, page_number: u32, bytes: &[u8]) {
+        self.pages.insert(page_number, bytes.to_vec());
+    }
+
+    /// Puts the pages back in `database_file` as they were, and cuts it back to its size then.
+    /// The pages are kept, as they're what the savepoint goes back to if it's rolled back again.
+    fn roll_back(&self, database_file: &dyn PageSource, page_size: u32) -> Result<()> {
+        for (page_number, bytes) in &self.pages {
+            database_file.write_at((page_number - 1) as u64 * page_size as u64, bytes)?;
+        }
+
+        database_file.set_len(self.page_count as u64 * page_size as u64)
+    }
+}
+
+impl Database {
+    /// Whether BEGIN has started a transaction that hasn't been committed or rolled back yet
+    pub fn in_transaction(&self) -> bool {
+        self.in_transaction
+    }
+
+    /// Runs BEGIN: starts a transaction, in which the database is read as it is now, and what's
+    /// written is only committed by COMMIT. Like SQLite's BEGIN DEFERRED, it takes a read lock,
+    /// and the exclusive lock once something's written, holding it until the transaction ends.
+    pub fn begin(&mut self) -> Result<()> {
+        if self.in_transaction {
+            bail!("cannot start a transaction within a transaction");
+        }
+        self.acquire_read_lock()?;
+        self.in_transaction = true;
+
+        Ok(())
+    }
+
+    /// Runs SAVEPOINT: marks where the transaction has got to, so that ROLLBACK TO can undo what's
+    /// written after it. Outside a transaction, it starts one, which releasing it commits.
+    pub fn savepoint(&mut self, name: &str) -> Result<()> {
+        if !self.in_transaction {
+            self.begin()?;
+            self.savepoint_transaction = true;
+        }
+        // What's been written so far may have added pages
+        self.refresh()?;
+        let savepoint = Savepoint::new(Some(name.to_string()), self.page_count);
+        self.savepoints
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(savepoint);
+
+        Ok(())
+    }
+
+    /// Runs RELEASE: forgets the savepoint and those after it, keeping what's been written since.
+    /// Releasing the savepoint that started the transaction commits it.
+    pub fn release(&mut self, name: &str) -> Result<()> {
+        let i = self.find_savepoint(name)?;
+        if i == 0 && self.savepoint_transaction {
+            return self.commit();
+        }
+        self.savepoints
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .truncate(i);
+
+        Ok(())
+    }
+
+    /// Runs ROLLBACK TO: undoes what's been written since the savepoint, which is kept, and
+    /// forgets those after it
+    pub fn rollback_to(&mut self, name: &str) -> Result<()> {
+        let i = self.find_savepoint(name)?;
+        let savepoints = self
+            .savepoints
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner);
+        savepoints.truncate(i + 1);
+        savepoints[i].roll_back(&*self.database_file, self.page_size)?;
+
+        self.refresh()
+    }
+
+    /// Where the most recent savepoint called `name` is in the list of them
+    fn find_savepoint(&mut self, name: &str) -> Result<usize> {
+        let savepoints = self
+            .savepoints
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner);
+        match savepoints.iter().rposition(|savepoint| {
+            savepoint
+                .name
+                .as_ref()
+                .is_some_and(|savepoint| savepoint.eq_ignore_ascii_case(name))
+        }) {
+            Some(i) => Ok(i),
+            None => bail!("no such savepoint: {name}"),
+        }
+    }
+
+    /// Runs COMMIT: keeps what the transaction wrote, by syncing the database and deleting the
+    /// journal
+    pub fn commit(&mut self) -> Result<()> {
+        if !self.in_transaction {
+            bail!("cannot commit - no transaction is active");
+        }
+        let committed = self.commit_journal();
+        // A failed commit leaves the journal to roll the transaction back
+        let ended = self.end_transaction();
+        committed?;
+
+        ended
+    }
+
+    /// Runs ROLLBACK: undoes what the transaction wrote, by putting back the pages in the journal
+    pub fn rollback(&mut self) -> Result<()> {
+        if !self.in_transaction {
+            bail!("cannot rollback - no transaction is active");
+        }
+        let rolled_back = self.roll_back_journal();
+        let ended = self.end_transaction();
+        rolled_back?;
+
+        ended
+    }
+
+    /// Forgets the transaction's savepoints, lets go of its locks, and reads the header again for
+    /// what it's left
+    fn end_transaction(&mut self) -> Result<()> {
+        self.in_transaction = false;
+        self.savepoint_transaction = false;
+        self.savepoints
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+        let unlocked = if self.options.nolock {
+            Ok(())
+        } else {
+            self.database_file.unlock(LockLevel::Shared)
+        };
+        let released = self.release_read_lock();
+        unlocked?;
+        released?;
+
+        self.refresh()
+    }
+
+    /// Runs `write` as a statement within the transaction: the pages it writes are kept as they
+    /// were until it's done, so that if it fails, it's undone and the transaction carries on
+    pub(crate) fn write_statement(&self, write: impl FnOnce() -> Result<()>) -> Result<()> {
+        self.savepoints
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(Savepoint::new(None, self.page_count));
+
+        let written = write();
+        let statement = self
+            .savepoints
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .pop()
+            .expect("the statement's savepoint was pushed");
+        match written {
+            Ok(()) => Ok(()),
+            Err(err) => match statement.roll_back(&*self.database_file, self.page_size) {
+                Ok(()) => Err(err),
+                Err(rollback_err) => Err(err).context(format!(
+                    "undoing the statement failed too, so the transaction needs rolling back: \
+                     {rollback_err:#}"
+                )),
+            },
+        }
+    }
+}
+
+/// Closing the database rolls back a transaction it's left in, as closing a connection does in
+/// SQLite
+impl Drop for Database {
+    fn drop(&mut self) {
+        if self.in_transaction {
+            let _ = self.rollback();
+        }
+    }
+}
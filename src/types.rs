@@ -1,7 +1,7 @@
 use anyhow::Error;
-use std::io::Read;
+use std::io::{self, Read};
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum SerialType {
     Null,
     Int8,
@@ -15,7 +15,16 @@ pub enum SerialType {
     One,
     Blob(u64),
     String(u64),
+    /// Serial types 10 and 11, which are reserved for SQLite's internal use and never appear in a
+    /// well-formed database file
+    Reserved(u64),
 }
+
+/// Returned when a record holds a value of a reserved serial type, so that callers can tell a
+/// corrupt record apart from an I/O error
+#[derive(Debug, PartialEq, thiserror::Error)]
+#[error("reserved serial type {0} in record")]
+pub struct ReservedSerialTypeError(pub u64);
 impl SerialType {
     pub fn from(raw_serial_type: u64) -> SerialType {
         match raw_serial_type {
@@ -29,7 +38,7 @@ impl SerialType {
             7 => SerialType::Float,
             8 => SerialType::Zero,
             9 => SerialType::One,
-            10 | 11 => todo!(),
+            10 | 11 => SerialType::Reserved(raw_serial_type),
             n => {
                 if n % 2 == 0 {
                     SerialType::Blob((n - 12) / 2)
@@ -39,8 +48,27 @@ impl SerialType {
             }
         }
     }
+
+    /// The serial type number stored in a record header, the inverse of SerialType::from
+    pub fn to_raw(&self) -> u64 {
+        match self {
+            SerialType::Null => 0,
+            SerialType::Int8 => 1,
+            SerialType::Int16 => 2,
+            SerialType::Int24 => 3,
+            SerialType::Int32 => 4,
+            SerialType::Int48 => 5,
+            SerialType::Int64 => 6,
+            SerialType::Float => 7,
+            SerialType::Zero => 8,
+            SerialType::One => 9,
+            SerialType::Blob(size) => size * 2 + 12,
+            SerialType::String(size) => size * 2 + 13,
+            SerialType::Reserved(raw) => *raw,
+        }
+    }
 }
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum SerialValue {
     Null,
     Int8(i8),
@@ -56,6 +84,39 @@ pub enum SerialValue {
     String(String),
 }
 impl SerialValue {
+    pub fn serial_type(&self) -> SerialType {
+        match self {
+            SerialValue::Null => SerialType::Null,
+            SerialValue::Int8(_) => SerialType::Int8,
+            SerialValue::Int16(_) => SerialType::Int16,
+            SerialValue::Int24(_) => SerialType::Int24,
+            SerialValue::Int32(_) => SerialType::Int32,
+            SerialValue::Int48(_) => SerialType::Int48,
+            SerialValue::Int64(_) => SerialType::Int64,
+            SerialValue::Float(_) => SerialType::Float,
+            SerialValue::Zero => SerialType::Zero,
+            SerialValue::One => SerialType::One,
+            SerialValue::Blob(b) => SerialType::Blob(b.len() as u64),
+            SerialValue::String(s) => SerialType::String(s.len() as u64),
+        }
+    }
+
+    /// Encodes the value as it's stored in a record body, the inverse of SerialValue::parse
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            SerialValue::Null | SerialValue::Zero | SerialValue::One => vec![],
+            SerialValue::Int8(i) => i.to_be_bytes().to_vec(),
+            SerialValue::Int16(i) => i.to_be_bytes().to_vec(),
+            SerialValue::Int24(i) => i.to_be_bytes()[1..].to_vec(),
+            SerialValue::Int32(i) => i.to_be_bytes().to_vec(),
+            SerialValue::Int48(i) => i.to_be_bytes()[2..].to_vec(),
+            SerialValue::Int64(i) => i.to_be_bytes().to_vec(),
+            SerialValue::Float(f) => f.to_be_bytes().to_vec(),
+            SerialValue::Blob(b) => b.clone(),
+            SerialValue::String(s) => s.as_bytes().to_vec(),
+        }
+    }
+
     pub fn parse<R: Read>(
         reader: &mut R,
         serial_type: &SerialType,
@@ -78,9 +139,10 @@ impl SerialValue {
                 let mut buf = [0; 3];
                 reader.read_exact(&mut buf)?;
 
-                Ok(SerialValue::Int24(i32::from_be_bytes([
-                    0, buf[0], buf[1], buf[2],
-                ])))
+                // Shift the three bytes to the top and back down again, to sign-extend them
+                Ok(SerialValue::Int24(
+                    i32::from_be_bytes([buf[0], buf[1], buf[2], 0]) >> 8,
+                ))
             }
             SerialType::Int32 => {
                 let mut buf = [0; 4];
@@ -92,9 +154,10 @@ impl SerialValue {
                 let mut buf = [0; 6];
                 reader.read_exact(&mut buf)?;
 
-                Ok(SerialValue::Int48(i64::from_be_bytes([
-                    0, 0, buf[0], buf[1], buf[2], buf[3], buf[4], buf[5],
-                ])))
+                Ok(SerialValue::Int48(
+                    i64::from_be_bytes([buf[0], buf[1], buf[2], buf[3], buf[4], buf[5], 0, 0])
+                        >> 16,
+                ))
             }
             SerialType::Int64 => {
                 let mut buf = [0; 8];
@@ -111,18 +174,58 @@ impl SerialValue {
             SerialType::Zero => Ok(SerialValue::Zero),
             SerialType::One => Ok(SerialValue::One),
             SerialType::Blob(size) => {
-                let mut buf = vec![0; *size as usize];
-                reader.read_exact(&mut buf)?;
+                let buf = read_bytes(reader, *size)?;
 
                 Ok(SerialValue::Blob(buf))
             }
             SerialType::String(size) => {
-                let mut buf = vec![0; *size as usize];
-                reader.read_exact(&mut buf)?;
+                let buf = read_bytes(reader, *size)?;
                 let value = String::from_utf8(buf)?;
 
                 Ok(SerialValue::String(value))
             }
+            SerialType::Reserved(raw) => Err(ReservedSerialTypeError(*raw).into()),
         }
     }
 }
+
+/// Reads `size` bytes, without trusting `size` enough to allocate it all up front: a corrupt
+/// record can claim a value far bigger than the record itself
+fn read_bytes<R: Read>(reader: &mut R, size: u64) -> io::Result<Vec<u8>> {
+    let mut buf = vec![];
+    reader.take(size).read_to_end(&mut buf)?;
+    if (buf.len() as u64) < size {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serial_type_from() {
+        assert_eq!(SerialType::from(0), SerialType::Null);
+        assert_eq!(SerialType::from(9), SerialType::One);
+        assert_eq!(SerialType::from(10), SerialType::Reserved(10));
+        assert_eq!(SerialType::from(11), SerialType::Reserved(11));
+        assert_eq!(SerialType::from(12), SerialType::Blob(0));
+        assert_eq!(SerialType::from(23), SerialType::String(5));
+        assert_eq!(
+            SerialType::from(u64::MAX),
+            SerialType::String((u64::MAX - 13) / 2)
+        );
+    }
+
+    #[test]
+    fn test_parse_reserved_serial_type() {
+        let error = SerialValue::parse(&mut &[][..], &SerialType::Reserved(10)).unwrap_err();
+
+        assert_eq!(
+            error.downcast_ref::<ReservedSerialTypeError>(),
+            Some(&ReservedSerialTypeError(10))
+        );
+    }
+}
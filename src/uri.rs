@@ -0,0 +1,189 @@
+use anyhow::{bail, Result};
+use std::{path::PathBuf, time::Duration};
+
+/// The VFS names a URI's vfs parameter may select, from SQLite's built-in unix and windows VFSes
+const KNOWN_VFS_NAMES: [&str; 6] = [
+    "unix",
+    "unix-dotfile",
+    "unix-excl",
+    "unix-none",
+    "unix-namedsem",
+    "win32",
+];
+
+/// How a database file should be opened, as given by its filename: either a plain path, or a
+/// [URI filename](https://www.sqlite.org/uri.html) like `file:data.db?immutable=1&mode=ro`
+#[derive(Debug, Default, PartialEq)]
+pub struct OpenOptions {
+    pub path: PathBuf,
+    /// vfs=NAME. Files are always read through std::fs, so this only checks the name is valid.
+    pub vfs: Option<String>,
+    /// mode=ro. Databases are only ever read anyway, so this just records what was asked for.
+    pub read_only: bool,
+    /// cache=shared
+    pub shared_cache: bool,
+    /// immutable=1: the file is on read-only media and will never change, so no locks are needed
+    /// and there's no point checking whether another connection has modified it.
+    pub immutable: bool,
+    /// nolock=1: don't take any file locks, leaving other processes to keep out of the way
+    pub nolock: bool,
+    /// Read the file through a memory map instead of seeking and reading (--mmap, not a URI
+    /// parameter). Needs the mmap feature.
+    pub mmap: bool,
+    /// The most memory, in bytes, that a query's ORDER BY keeps rows in before sorting them in
+    /// batches written to temporary files (--max-memory, not a URI parameter). Unlimited if None.
+    pub max_memory: Option<usize>,
+    /// How long to wait for another process's lock on the database to go before giving up with
+    /// "database is locked", like PRAGMA busy_timeout (--busy-timeout, not a URI parameter)
+    pub busy_timeout: Duration,
+}
+
+impl OpenOptions {
+    /// Parses a database filename. Anything that doesn't start with "file:" is a plain path.
+    ///
+    /// Like SQLite, query parameters that aren't recognized are ignored.
+    pub fn parse(filename: &str) -> Result<Self> {
+        let Some(uri) = filename.strip_prefix("file:") else {
+            return Ok(OpenOptions {
+                path: PathBuf::from(filename),
+                ..Default::default()
+            });
+        };
+
+        let uri = uri.split('#').next().unwrap_or_default();
+        let (path, query) = uri.split_once('?').unwrap_or((uri, ""));
+
+        // An authority, if present, has to be empty or "localhost"
+        let path = match path.strip_prefix("//") {
+            Some(rest) => {
+                let authority_end = rest.find('/').unwrap_or(rest.len());
+                let authority = &rest[..authority_end];
+                if !authority.is_empty() && authority != "localhost" {
+                    bail!("invalid uri authority: {authority}");
+                }
+                &rest[authority_end..]
+            }
+            None => path,
+        };
+
+        let mut options = OpenOptions {
+            path: PathBuf::from(percent_decode(path)?),
+            ..Default::default()
+        };
+
+        for parameter in query.split('&').filter(|p| !p.is_empty()) {
+            let (key, value) = parameter.split_once('=').unwrap_or((parameter, ""));
+            let value = percent_decode(value)?;
+
+            match percent_decode(key)?.as_str() {
+                "mode" => match value.as_str() {
+                    "ro" => options.read_only = true,
+                    "rw" | "rwc" => options.read_only = false,
+                    "memory" => bail!("in-memory databases aren't supported"),
+                    _ => bail!("no such access mode: {value}"),
+                },
+                "vfs" => {
+                    if !KNOWN_VFS_NAMES.contains(&value.as_str()) {
+                        bail!("no such vfs: {value}");
+                    }
+                    options.vfs = Some(value);
+                }
+                "cache" => match value.as_str() {
+                    "shared" => options.shared_cache = true,
+                    "private" => options.shared_cache = false,
+                    _ => bail!("no such cache mode: {value}"),
+                },
+                "immutable" => options.immutable = is_true(&value),
+                "nolock" => options.nolock = is_true(&value),
+                _ => {}
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+/// Boolean query parameters accept the same values as SQLite's sqlite3_uri_boolean()
+fn is_true(value: &str) -> bool {
+    ["1", "yes", "true", "on"]
+        .iter()
+        .any(|t| value.eq_ignore_ascii_case(t))
+}
+
+fn percent_decode(input: &str) -> Result<String> {
+    let mut bytes = Vec::with_capacity(input.len());
+    let mut input_bytes = input.bytes();
+
+    while let Some(byte) = input_bytes.next() {
+        if byte != b'%' {
+            bytes.push(byte);
+            continue;
+        }
+
+        let hex = [input_bytes.next(), input_bytes.next()];
+        let decoded = match hex {
+            [Some(high), Some(low)] => std::str::from_utf8(&[high, low])
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+            _ => None,
+        };
+        match decoded {
+            Some(decoded) => bytes.push(decoded),
+            None => bail!("invalid percent-encoding in uri: {input}"),
+        }
+    }
+
+    Ok(String::from_utf8(bytes)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_plain_path() {
+        assert_eq!(
+            OpenOptions::parse("sample.db").unwrap(),
+            OpenOptions {
+                path: PathBuf::from("sample.db"),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_uri() {
+        assert_eq!(
+            OpenOptions::parse("file:data.db?immutable=1&mode=ro").unwrap(),
+            OpenOptions {
+                path: PathBuf::from("data.db"),
+                read_only: true,
+                immutable: true,
+                ..Default::default()
+            }
+        );
+
+        let options =
+            OpenOptions::parse("file://localhost/tmp/my%20data.db?cache=shared#x").unwrap();
+        assert_eq!(options.path, PathBuf::from("/tmp/my data.db"));
+        assert!(!options.immutable);
+        assert!(options.shared_cache);
+
+        assert_eq!(
+            OpenOptions::parse("file:data.db?vfs=unix-none&nolock=yes&cache=private&psow=1")
+                .unwrap(),
+            OpenOptions {
+                path: PathBuf::from("data.db"),
+                vfs: Some("unix-none".to_string()),
+                nolock: true,
+                ..Default::default()
+            }
+        );
+
+        assert!(OpenOptions::parse("file://example.com/data.db").is_err());
+        assert!(OpenOptions::parse("file:data.db?mode=bogus").is_err());
+        assert!(OpenOptions::parse("file:data.db?vfs=bogus").is_err());
+        assert!(OpenOptions::parse("file:data.db?cache=bogus").is_err());
+        assert!(OpenOptions::parse("file:data%2.db").is_err());
+    }
+}
@@ -0,0 +1,258 @@
+use crate::{
+    btree_writer::BtreeWriter,
+    cell::read_index_cells,
+    database::{Database, MAX_BTREE_DEPTH},
+    header::{BTreePage, DatabaseHeader, DATABASE_HEADER_SIZE},
+    page_source::PageReader,
+    record::encode_record,
+    value::Value,
+};
+#[cfg(feature = "fs")]
+use crate::{lock::LockLevel, page_source::PageSource};
+use anyhow::{bail, Result};
+use std::io::{Seek, SeekFrom, Write};
+#[cfg(feature = "fs")]
+use std::{
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+};
+
+impl Database {
+    /// Runs VACUUM: rebuilds the database into a new file next to it, then copies that back over
+    /// the database's own file. The rebuilt database has no free pages, and every page's cells
+    /// are packed together.
+    ///
+    /// As SQLite does, the file is written in place rather than replaced, so that other
+    /// connections' handles and locks stay on the file the database is in. Its pages go in the
+    /// rollback journal first, so a crash while they're copied back leaves the database to be
+    /// rolled back to what it was.
+    #[cfg(feature = "fs")]
+    pub fn vacuum(&mut self) -> Result<()> {
+        if self.options.read_only || self.options.immutable {
+            bail!("attempt to write a readonly database");
+        }
+        if self.in_transaction {
+            bail!("cannot VACUUM from within a transaction");
+        }
+        if self.options.path.as_os_str().is_empty() {
+            bail!("Unhandled VACUUM of a database that isn't a file");
+        }
+        let mut temp_path = self.options.path.clone().into_os_string();
+        temp_path.push("-vacuum");
+        let temp_path = PathBuf::from(temp_path);
+
+        self.acquire_read_lock()?;
+        let result = self
+            .refresh()
+            .and_then(|()| self.check_no_wal())
+            .and_then(|()| self.reserve())
+            // Holding the reserved lock, no other connection can be vacuuming, so a rebuilt
+            // database that's already there was left by one that was cut short
+            .and_then(|()| match fs::remove_file(&temp_path) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err.into()),
+                _ => Ok(()),
+            })
+            .and_then(|()| self.vacuum_into(&temp_path))
+            .and_then(|()| self.copy_back(&temp_path));
+        let unlocked = if self.options.nolock {
+            Ok(())
+        } else {
+            self.database_file.unlock(LockLevel::Shared)
+        };
+        let released = self.release_read_lock();
+        result?;
+        unlocked?;
+        released?;
+
+        self.refresh()
+    }
+
+    /// Takes the reserved lock, which only one connection can hold, as it's about to write
+    #[cfg(feature = "fs")]
+    fn reserve(&self) -> Result<()> {
+        if self.options.nolock {
+            return Ok(());
+        }
+
+        self.database_file
+            .lock(LockLevel::Reserved, self.options.busy_timeout)
+    }
+
+    /// Writes the rebuilt database at `temp_path` over the database's file, a page at a time,
+    /// once other connections have stopped reading it, and removes it
+    #[cfg(feature = "fs")]
+    fn copy_back(&self, temp_path: &Path) -> Result<()> {
+        let locked = if self.options.nolock {
+            Ok(())
+        } else {
+            self.database_file
+                .lock(LockLevel::Exclusive, self.options.busy_timeout)
+        };
+        let copied = locked.and_then(|()| {
+            let rebuilt = File::open(temp_path)?;
+            let page_count = (rebuilt.size()? / self.page_size as u64) as u32;
+
+            // Every page is overwritten or cut off, so they all need journaling
+            self.write_atomically(|| {
+                self.journal_pages(1..=self.page_count)?;
+                for page_num in 1..=page_count {
+                    let offset = (page_num - 1) as u64 * self.page_size as u64;
+                    let page = rebuilt.read_at(offset, self.page_size as usize)?;
+                    self.database_file.write_at(offset, &page)?;
+                }
+                self.database_file
+                    .set_len(page_count as u64 * self.page_size as u64)
+            })
+        });
+        let removed = fs::remove_file(temp_path);
+        copied?;
+
+        Ok(removed?)
+    }
+
+    /// Runs VACUUM INTO: writes a copy of the database to a new file at `path`, holding only the
+    /// schema and the live rows, with b-trees rebuilt from them. If it fails, the file is removed
+    /// again rather than left part written.
+    #[cfg(feature = "fs")]
+    pub fn vacuum_into(&self, path: &Path) -> Result<()> {
+        let mut file = File::options().write(true).create_new(true).open(path)?;
+        let written = self
+            .vacuum_to(&mut file)
+            .and_then(|()| Ok(file.sync_all()?));
+        if written.is_err() {
+            let _ = fs::remove_file(path);
+        }
+
+        written
+    }
+
+    /// Like vacuum_into, writing the copy to `output`. Each b-tree's pages are written as soon
+    /// as it's been rebuilt, and page 1 last of all.
+    pub fn vacuum_to(&self, output: &mut (impl Write + Seek)) -> Result<()> {
+        let _lock = self.read_lock()?;
+
+        let mut header = DatabaseHeader::parse(&self.page_bytes(1)?[..DATABASE_HEADER_SIZE])?;
+        if header.largest_root_page != 0 {
+            bail!("Unhandled auto-vacuum database: root pages would need pointer map entries");
+        }
+
+        // Page 1 is written last, when the root pages of everything in the schema are known
+        let mut writer = BtreeWriter::new(self.page_size, 2);
+        let mut reader = self.reader();
+        let mut progress = self.statement_progress();
+        let mut schema_cells = vec![];
+        for record in self.table_cursor(1) {
+            let record = record?;
+            let mut values = record.values();
+            let root_page = record.value(3).as_integer().unwrap_or(0) as u32;
+
+            // Virtual tables, views and triggers have no b-tree, and a root page of 0
+            if root_page > 0 {
+                let page = self.read_page(&mut reader, root_page)?;
+                let new_root_page = match page.header.page_type {
+                    BTreePage::LeafTable | BTreePage::InteriorTable => {
+                        let mut cells = vec![];
+                        for row in self.table_cursor(root_page) {
+                            progress.row_read()?;
+                            let row = row?;
+                            let payload = encode_record(&row.values(), header.schema_format);
+                            cells.push((row.row_id, writer.table_cell(row.row_id, &payload)?));
+                        }
+                        writer.write_table(cells, None)?
+                    }
+                    BTreePage::LeafIndex | BTreePage::InteriorIndex => {
+                        let mut payloads = vec![];
+                        self.index_payloads(&mut reader, root_page, 0, &mut payloads)?;
+                        let cells = payloads
+                            .iter()
+                            .map(|payload| {
+                                progress.row_read()?;
+                                writer.index_cell(payload)
+                            })
+                            .collect::<Result<Vec<_>>>()?;
+                        writer.write_index(cells)?
+                    }
+                };
+                values[3] = Value::Integer(new_root_page as i64);
+                self.write_vacuumed_pages(&mut writer, output)?;
+            }
+
+            let payload = encode_record(&values, header.schema_format);
+            schema_cells.push((record.row_id, writer.table_cell(record.row_id, &payload)?));
+        }
+        writer.write_table(schema_cells, Some(1))?;
+
+        header.page_count = writer.page_count();
+        header.first_freelist_trunk_page = 0;
+        header.freelist_page_count = 0;
+        header.file_change_counter = header.file_change_counter.wrapping_add(1);
+        header.version_valid_for = header.file_change_counter;
+        header.schema_cookie = header.schema_cookie.wrapping_add(1);
+
+        for (page_num, page) in &mut writer.pages {
+            if *page_num == 1 {
+                page[..DATABASE_HEADER_SIZE].copy_from_slice(&header.to_bytes());
+            }
+        }
+        self.write_vacuumed_pages(&mut writer, output)?;
+
+        Ok(())
+    }
+
+    /// Writes the pages `writer` has written so far to `output`, and lets go of them
+    fn write_vacuumed_pages(
+        &self,
+        writer: &mut BtreeWriter,
+        output: &mut (impl Write + Seek),
+    ) -> Result<()> {
+        for (page_num, page) in writer.pages.drain(..) {
+            let offset = (page_num - 1) as u64 * self.page_size as u64;
+            output.seek(SeekFrom::Start(offset))?;
+            output.write_all(&page)?;
+        }
+
+        Ok(())
+    }
+
+    /// Adds the payloads of the index b-tree rooted at `page_number` to `payloads`, in order
+    fn index_payloads(
+        &self,
+        reader: &mut PageReader,
+        page_number: u32,
+        depth: usize,
+        payloads: &mut Vec<Vec<u8>>,
+    ) -> Result<()> {
+        if depth > MAX_BTREE_DEPTH {
+            bail!("index b-tree is too deep at page {page_number}");
+        }
+
+        let page = self.read_page(reader, page_number)?;
+        let cell_pointers = page.fetch_cell_pointers(reader)?;
+        let is_interior = match page.header.page_type {
+            BTreePage::LeafIndex => false,
+            BTreePage::InteriorIndex => true,
+            page_type => bail!("Expected an index b-tree page, found {page_type:?}"),
+        };
+        let cells = read_index_cells(
+            reader,
+            page.start_offset,
+            is_interior,
+            &cell_pointers,
+            self.page_size,
+        )?;
+
+        // An interior cell's entry comes after everything in its left child
+        for cell in cells {
+            if let Some(left_child_page) = cell.left_child_page {
+                self.index_payloads(reader, left_child_page, depth + 1, payloads)?;
+            }
+            payloads.push(cell.payload);
+        }
+        if let Some(right_most_pointer) = page.header.right_most_pointer {
+            self.index_payloads(reader, right_most_pointer, depth + 1, payloads)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,211 @@
+use crate::{printf::printf, types::SerialValue};
+use std::{cmp::Ordering, fmt};
+
+/// A SQL value, with the five storage classes SQLite uses:
+/// [datatype3](https://www.sqlite.org/datatype3.html)
+///
+/// Unlike SerialValue, this doesn't care how the value was encoded on disk.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+impl From<SerialValue> for Value {
+    fn from(serial_value: SerialValue) -> Self {
+        match serial_value {
+            SerialValue::Null => Value::Null,
+            SerialValue::Int8(i) => Value::Integer(i as i64),
+            SerialValue::Int16(i) => Value::Integer(i as i64),
+            SerialValue::Int24(i) => Value::Integer(i as i64),
+            SerialValue::Int32(i) => Value::Integer(i as i64),
+            SerialValue::Int48(i) => Value::Integer(i),
+            SerialValue::Int64(i) => Value::Integer(i),
+            SerialValue::Float(f) => Value::Real(f),
+            SerialValue::Zero => Value::Integer(0),
+            SerialValue::One => Value::Integer(1),
+            SerialValue::Blob(b) => Value::Blob(b),
+            SerialValue::String(s) => Value::Text(s),
+        }
+    }
+}
+
+impl Value {
+    /// The name of the value's storage class, as returned by typeof()
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Null => "null",
+            Value::Integer(_) => "integer",
+            Value::Real(_) => "real",
+            Value::Text(_) => "text",
+            Value::Blob(_) => "blob",
+        }
+    }
+
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            Value::Integer(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    pub fn as_real(&self) -> Option<f64> {
+        match self {
+            Value::Integer(i) => Some(*i as f64),
+            Value::Real(r) => Some(*r),
+            _ => None,
+        }
+    }
+
+    /// Converts the value to a number the way SQLite does for arithmetic: text (and blobs, read as
+    /// text) becomes the number at its start, or 0 if it doesn't start with one. NULL stays NULL.
+    pub fn to_numeric(&self) -> Value {
+        match self {
+            Value::Null | Value::Integer(_) | Value::Real(_) => self.clone(),
+            Value::Text(s) => numeric_prefix(s),
+            Value::Blob(b) => numeric_prefix(&String::from_utf8_lossy(b)),
+        }
+    }
+
+    /// Orders values the way SQLite does when comparing or sorting them: NULLs first, then
+    /// numbers (integers and reals compared numerically), then text, then blobs.
+    pub fn compare(&self, other: &Value) -> Ordering {
+        match (self, other) {
+            (Value::Null, Value::Null) => Ordering::Equal,
+            (Value::Null, _) => Ordering::Less,
+            (_, Value::Null) => Ordering::Greater,
+
+            (Value::Integer(a), Value::Integer(b)) => a.cmp(b),
+            (Value::Integer(a), Value::Real(b)) => (*a as f64).total_cmp(b),
+            (Value::Real(a), Value::Integer(b)) => a.total_cmp(&(*b as f64)),
+            (Value::Real(a), Value::Real(b)) => a.total_cmp(b),
+            (Value::Integer(_) | Value::Real(_), _) => Ordering::Less,
+            (_, Value::Integer(_) | Value::Real(_)) => Ordering::Greater,
+
+            (Value::Text(a), Value::Text(b)) => a.cmp(b),
+            (Value::Text(_), _) => Ordering::Less,
+            (_, Value::Text(_)) => Ordering::Greater,
+
+            (Value::Blob(a), Value::Blob(b)) => a.cmp(b),
+        }
+    }
+}
+
+/// Parses the longest prefix of `text` that looks like a number, ignoring leading whitespace
+fn numeric_prefix(text: &str) -> Value {
+    let text = text.trim_start();
+    let bytes = text.as_bytes();
+    let digits_from = |start: usize| {
+        start
+            + bytes[start.min(bytes.len())..]
+                .iter()
+                .take_while(|b| b.is_ascii_digit())
+                .count()
+    };
+
+    let mut end = digits_from(usize::from(matches!(bytes.first(), Some(b'+' | b'-'))));
+    let mut is_real = false;
+
+    if bytes.get(end) == Some(&b'.') {
+        end = digits_from(end + 1);
+        is_real = true;
+    }
+    if matches!(bytes.get(end), Some(b'e' | b'E')) {
+        let sign = usize::from(matches!(bytes.get(end + 1), Some(b'+' | b'-')));
+        let exponent_end = digits_from(end + 1 + sign);
+        if exponent_end > end + 1 + sign {
+            end = exponent_end;
+            is_real = true;
+        }
+    }
+
+    let prefix = &text[..end];
+    if !is_real {
+        if let Ok(i) = prefix.parse() {
+            return Value::Integer(i);
+        }
+    }
+    match prefix.parse() {
+        Ok(r) => Value::Real(r),
+        Err(_) => Value::Integer(0),
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => Ok(()),
+            Value::Integer(i) => write!(f, "{i}"),
+            // As sqlite3 prints them: 15 significant digits, and a ".0" if there's no point
+            Value::Real(r) => write!(f, "{}", printf("%!.15g", &[Value::Real(*r)])),
+            Value::Text(s) => write!(f, "{s}"),
+            Value::Blob(b) => write!(f, "{}", String::from_utf8_lossy(b)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare_follows_sqlite_sort_order() {
+        let mut values = vec![
+            Value::Blob(vec![1]),
+            Value::Text("b".to_string()),
+            Value::Real(2.5),
+            Value::Null,
+            Value::Integer(3),
+            Value::Text("a".to_string()),
+            Value::Integer(-1),
+        ];
+        values.sort_by(|a, b| a.compare(b));
+
+        assert_eq!(
+            values,
+            vec![
+                Value::Null,
+                Value::Integer(-1),
+                Value::Real(2.5),
+                Value::Integer(3),
+                Value::Text("a".to_string()),
+                Value::Text("b".to_string()),
+                Value::Blob(vec![1]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_to_numeric() {
+        let numeric = |s: &str| Value::Text(s.to_string()).to_numeric();
+
+        assert_eq!(numeric("42"), Value::Integer(42));
+        assert_eq!(numeric("  -7 apples"), Value::Integer(-7));
+        assert_eq!(numeric("4.5"), Value::Real(4.5));
+        assert_eq!(numeric("1e3x"), Value::Real(1000.0));
+        assert_eq!(numeric("3e"), Value::Integer(3));
+        assert_eq!(numeric(".5"), Value::Real(0.5));
+        assert_eq!(numeric("pizza"), Value::Integer(0));
+        assert_eq!(numeric("99999999999999999999"), Value::Real(1e20));
+        assert_eq!(Value::Null.to_numeric(), Value::Null);
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(Value::Null.to_string(), "");
+        assert_eq!(Value::Integer(42).to_string(), "42");
+        assert_eq!(Value::Real(2.0).to_string(), "2.0");
+        assert_eq!(Value::Real(2.5).to_string(), "2.5");
+        // Rounded to 15 significant digits, as sqlite3 prints them
+        assert_eq!(Value::Real(1e20).to_string(), "1.0e+20");
+        assert_eq!(Value::Real(0.1 + 0.2).to_string(), "0.3");
+        assert_eq!(Value::Real(1.0 / 3.0).to_string(), "0.333333333333333");
+        assert_eq!(Value::Real(1e15).to_string(), "1.0e+15");
+        assert_eq!(Value::Real(-2.5e-7).to_string(), "-2.5e-07");
+        assert_eq!(Value::Real(f64::INFINITY).to_string(), "Inf");
+        assert_eq!(Value::Text("pizza".to_string()).to_string(), "pizza");
+    }
+}
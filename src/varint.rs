@@ -1,4 +1,4 @@
-use std::io::Read;
+use std::io::{self, Read, Write};
 
 const IS_FIRST_BIT_ZERO_MASK: u8 = 0b10000000;
 const LAST_SEVEN_BITS_MASK: u8 = 0b01111111;
@@ -6,74 +6,83 @@ const LAST_SEVEN_BITS_MASK: u8 = 0b01111111;
 /// Parses SQLite's "varint" (short for variable-length integer) as mentioned here:
 /// [varint](https://www.sqlite.org/fileformat2.html#varint)
 ///
-/// Returns (varint, bytes_read)
-pub fn parse_varint(stream: &[u8]) -> (usize, usize) {
-    let usable_bytes = read_usable_bytes(stream);
-    let bytes_read = usable_bytes.len();
-
-    let varint = usable_bytes
-        .into_iter()
-        .enumerate()
-        .fold(0, |value, (i, usable_byte)| {
-            let usable_size = if i == 8 { 8 } else { 7 };
-            (value << usable_size) + usable_value(usable_size, usable_byte) as usize
-        });
-    (varint, bytes_read)
-}
-
-pub fn parse_varint_from_reader<R: Read>(reader: &mut R) -> (usize, usize) {
-    let usable_bytes = read_usable_bytes_from_reader(reader);
-
-    let bytes_read = usable_bytes.len();
-    let varint = usable_bytes
-        .into_iter()
-        .enumerate()
-        .fold(0, |value, (i, usable_byte)| {
-            let usable_size = if i == 8 { 8 } else { 7 };
-
-            (value << usable_size) + usable_value(usable_size, usable_byte) as usize
-        });
+/// Returns (varint, bytes_read). A 9-byte varint uses all 64 bits, so large values come back
+/// negative, just as SQLite stores negative integers.
+pub fn parse_varint(stream: &[u8]) -> (i64, usize) {
+    let mut value = 0u64;
+
+    for (i, &byte) in stream.iter().take(9).enumerate() {
+        if i == 8 {
+            return (((value << 8) | byte as u64) as i64, 9);
+        }
+        value = (value << 7) | (byte & LAST_SEVEN_BITS_MASK) as u64;
+        if starts_with_zero(byte) {
+            return (value as i64, i + 1);
+        }
+    }
 
-    (varint, bytes_read)
+    // The stream ended part way through the varint
+    (value as i64, stream.len())
 }
 
-/// Usable size is either 8 or 7
-fn usable_value(usable_size: u8, byte: u8) -> u8 {
-    if usable_size == 8 {
-        usable_size
-    } else {
-        byte & LAST_SEVEN_BITS_MASK
-    }
+/// Like parse_varint, but None if `stream` ends part way through the varint
+pub fn parse_complete_varint(stream: &[u8]) -> Option<(i64, usize)> {
+    let (value, bytes_read) = parse_varint(stream);
+    let complete = bytes_read == 9 || bytes_read > 0 && starts_with_zero(stream[bytes_read - 1]);
+
+    complete.then_some((value, bytes_read))
 }
 
-fn read_usable_bytes(stream: &[u8]) -> Vec<u8> {
-    let mut usable_bytes = vec![];
+/// Like parse_varint, reading from `reader`. Running out of bytes part way through a varint is an
+/// error.
+pub fn parse_varint_from_reader<R: Read>(reader: &mut R) -> io::Result<(i64, usize)> {
+    let mut bytes = [0u8; 9];
 
     for i in 0..9 {
-        let byte = stream[i];
-        usable_bytes.push(byte);
-        if starts_with_zero(byte) {
-            break;
+        reader.read_exact(&mut bytes[i..i + 1])?;
+        if starts_with_zero(bytes[i]) {
+            return Ok(parse_varint(&bytes[..i + 1]));
         }
     }
 
-    usable_bytes
+    Ok(parse_varint(&bytes))
 }
 
-fn read_usable_bytes_from_reader<R: Read>(reader: &mut R) -> Vec<u8> {
-    let mut usable_bytes = vec![];
+/// Encodes a value as a varint, using as few bytes as SQLite would: values needing more than 56
+/// bits (including every negative value) take all nine bytes.
+pub fn encode_varint(value: i64) -> Vec<u8> {
+    let value = value as u64;
 
-    for _i in 0..9 {
-        let mut byte = [0u8; 1];
-        reader.read_exact(&mut byte).unwrap();
+    if value >> 56 != 0 {
+        let mut bytes = [0; 9];
+        bytes[8] = value as u8;
 
-        usable_bytes.push(byte[0]);
-        if starts_with_zero(byte[0]) {
-            break;
+        let mut rest = value >> 8;
+        for byte in bytes[..8].iter_mut().rev() {
+            *byte = (rest as u8 & LAST_SEVEN_BITS_MASK) | IS_FIRST_BIT_ZERO_MASK;
+            rest >>= 7;
         }
+
+        return bytes.to_vec();
+    }
+
+    let mut bytes = vec![value as u8 & LAST_SEVEN_BITS_MASK];
+    let mut rest = value >> 7;
+    while rest != 0 {
+        bytes.push((rest as u8 & LAST_SEVEN_BITS_MASK) | IS_FIRST_BIT_ZERO_MASK);
+        rest >>= 7;
     }
+    bytes.reverse();
+
+    bytes
+}
+
+/// Writes a value as a varint, returning the number of bytes written
+pub fn write_varint<W: Write>(writer: &mut W, value: i64) -> io::Result<usize> {
+    let bytes = encode_varint(value);
+    writer.write_all(&bytes)?;
 
-    usable_bytes
+    Ok(bytes.len())
 }
 
 fn starts_with_zero(byte: u8) -> bool {
@@ -116,6 +125,87 @@ mod tests {
         let (num, bytes_read) = parse_varint(&a[total_bytes_read..]);
         assert_eq!(num, 116);
         assert_eq!(bytes_read, 1);
+
+        // The stream ends mid-varint
+        assert_eq!(parse_varint(&[129, 130]), (130, 2));
+        assert_eq!(parse_complete_varint(&[129, 130]), None);
+        assert_eq!(parse_complete_varint(&[129, 3, 116]), Some((131, 2)));
+        assert_eq!(parse_complete_varint(&[]), None);
+    }
+
+    #[test]
+    fn test_parse_varint_multi_byte() {
+        // 240 takes two bytes, while 2^56 and anything negative need all nine
+        assert_eq!(parse_varint(&[0x81, 0x70]), (240, 2));
+        assert_eq!(parse_varint(&[0xff; 9]), (-1, 9));
+        assert_eq!(
+            parse_varint(&[0x80, 0xc0, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x00]),
+            (1 << 56, 9)
+        );
+        assert_eq!(
+            parse_varint(&[0xc0, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x00]),
+            (i64::MIN, 9)
+        );
+    }
+
+    #[test]
+    fn test_encode_varint() {
+        assert_eq!(encode_varint(0), vec![0x00]);
+        assert_eq!(encode_varint(127), vec![0x7f]);
+        assert_eq!(encode_varint(128), vec![0x81, 0x00]);
+        assert_eq!(encode_varint(240), vec![0x81, 0x70]);
+        assert_eq!(
+            encode_varint((1 << 56) - 1),
+            vec![0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f]
+        );
+        assert_eq!(
+            encode_varint(1 << 56),
+            vec![0x80, 0xc0, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x00]
+        );
+        assert_eq!(encode_varint(-1), vec![0xff; 9]);
+
+        let mut buffer = vec![];
+        assert_eq!(write_varint(&mut buffer, 131).unwrap(), 2);
+        assert_eq!(buffer, vec![129, 3]);
+    }
+
+    #[test]
+    fn test_varint_round_trip() {
+        let mut values = vec![0, 1, 127, 128, 16383, 16384, i64::MAX, i64::MIN, -1, -2];
+        for shift in 0..64 {
+            values.push(1 << shift);
+            values.push((1i64 << shift).wrapping_sub(1));
+            values.push((1i64 << shift).wrapping_neg());
+        }
+
+        // A simple LCG, to cover a spread of values without pulling in a property testing crate
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        for _ in 0..1000 {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            values.push(state as i64 >> (state % 64));
+        }
+
+        for value in values {
+            let encoding = encode_varint(value);
+
+            // Minimal length: seven bits per byte, until the ninth byte takes a full eight
+            let significant_bits = 64 - (value as u64).leading_zeros() as usize;
+            let minimal_length = if significant_bits > 56 {
+                9
+            } else {
+                significant_bits.div_ceil(7).max(1)
+            };
+            assert_eq!(encoding.len(), minimal_length, "{value}");
+
+            assert_eq!(parse_varint(&encoding), (value, encoding.len()), "{value}");
+            assert_eq!(
+                parse_varint_from_reader(&mut Cursor::new(&encoding)).unwrap(),
+                (value, encoding.len()),
+                "{value}"
+            );
+        }
     }
 
     #[test]
@@ -124,25 +214,29 @@ mod tests {
 
         let mut c = Cursor::new(a);
 
-        let (num, bytes_read) = parse_varint_from_reader(&mut c);
+        let (num, bytes_read) = parse_varint_from_reader(&mut c).unwrap();
         assert_eq!(num, 92);
         assert_eq!(bytes_read, 1);
 
-        let (num, bytes_read) = parse_varint_from_reader(&mut c);
+        let (num, bytes_read) = parse_varint_from_reader(&mut c).unwrap();
         assert_eq!(num, 4);
         assert_eq!(bytes_read, 1);
 
         for _ in 0..5 {
-            parse_varint_from_reader(&mut c);
+            parse_varint_from_reader(&mut c).unwrap();
         }
 
         // NOTE: Consecutive bytes `129, 3` are read as 131.
-        let (num, bytes_read) = parse_varint_from_reader(&mut c);
+        let (num, bytes_read) = parse_varint_from_reader(&mut c).unwrap();
         assert_eq!(num, 131);
         assert_eq!(bytes_read, 2);
 
-        let (num, bytes_read) = parse_varint_from_reader(&mut c);
+        let (num, bytes_read) = parse_varint_from_reader(&mut c).unwrap();
         assert_eq!(num, 116);
         assert_eq!(bytes_read, 1);
+
+        // The stream ends mid-varint
+        let mut c = Cursor::new(vec![129, 130]);
+        assert!(parse_varint_from_reader(&mut c).is_err());
     }
 }
@@ -0,0 +1,142 @@
+#[cfg(feature = "fs")]
+use crate::lock::LockedFile;
+use crate::page_source::PageSource;
+use anyhow::{bail, Result};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError, RwLock},
+};
+#[cfg(feature = "fs")]
+use std::{fs::File, io};
+
+/// Opens the files databases are stored in, like SQLite's VFS layer, so that storage can be
+/// swapped out: for an in-memory fake in tests, or for a backend that fetches pages over HTTP or
+/// decrypts them
+pub trait Vfs {
+    fn open(&self, path: &Path) -> Result<Box<dyn PageSource>>;
+}
+
+/// Files on disk, locked like SQLite locks them. Files are opened to be written to as well as
+/// read, unless they can only be read.
+#[cfg(feature = "fs")]
+pub struct FileVfs;
+
+#[cfg(feature = "fs")]
+impl Vfs for FileVfs {
+    fn open(&self, path: &Path) -> Result<Box<dyn PageSource>> {
+        let file = match File::options().read(true).write(true).open(path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::PermissionDenied => File::open(path)?,
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok(Box::new(LockedFile::new(file)))
+    }
+}
+
+/// Files on disk, mapped into memory
+#[cfg(all(feature = "mmap", unix))]
+pub struct MmapVfs;
+
+#[cfg(all(feature = "mmap", unix))]
+impl Vfs for MmapVfs {
+    fn open(&self, path: &Path) -> Result<Box<dyn PageSource>> {
+        Ok(Box::new(crate::page_source::Mmap::new(File::open(path)?)?))
+    }
+}
+
+/// Files that only exist in memory, for as long as the MemoryVfs does. Opening the same path
+/// twice gives two handles on the same bytes, and a path that's never been opened is an empty
+/// file.
+#[derive(Default)]
+pub struct MemoryVfs {
+    files: Mutex<HashMap<PathBuf, Arc<RwLock<Vec<u8>>>>>,
+}
+
+impl MemoryVfs {
+    pub fn new() -> Self {
+        MemoryVfs::default()
+    }
+}
+
+impl Vfs for MemoryVfs {
+    fn open(&self, path: &Path) -> Result<Box<dyn PageSource>> {
+        let bytes = self
+            .files
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(path.to_path_buf())
+            .or_default()
+            .clone();
+
+        Ok(Box::new(MemoryFile { bytes }))
+    }
+}
+
+/// A file opened by a MemoryVfs, or one on its own that only its owner can get at
+#[derive(Default)]
+pub(crate) struct MemoryFile {
+    bytes: Arc<RwLock<Vec<u8>>>,
+}
+
+impl PageSource for MemoryFile {
+    fn size(&self) -> Result<u64> {
+        let bytes = self.bytes.read().unwrap_or_else(PoisonError::into_inner);
+        Ok(bytes.len() as u64)
+    }
+
+    fn read_at(&self, offset: u64, len: usize) -> Result<Cow<'_, [u8]>> {
+        let bytes = self.bytes.read().unwrap_or_else(PoisonError::into_inner);
+        let start = usize::try_from(offset)?;
+        match start.checked_add(len) {
+            Some(end) if end <= bytes.len() => Ok(Cow::Owned(bytes[start..end].to_vec())),
+            _ => bail!("read_at: {len} bytes at {offset} is past the end of the database"),
+        }
+    }
+
+    fn write_at(&self, offset: u64, data: &[u8]) -> Result<()> {
+        let mut bytes = self.bytes.write().unwrap_or_else(PoisonError::into_inner);
+        let start = usize::try_from(offset)?;
+        let end = start + data.len();
+        if end > bytes.len() {
+            bytes.resize(end, 0);
+        }
+        bytes[start..end].copy_from_slice(data);
+
+        Ok(())
+    }
+
+    fn set_len(&self, size: u64) -> Result<()> {
+        let mut bytes = self.bytes.write().unwrap_or_else(PoisonError::into_inner);
+        bytes.resize(usize::try_from(size)?, 0);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_vfs() {
+        let vfs = MemoryVfs::new();
+        let path = Path::new("test.db");
+
+        let file = vfs.open(path).unwrap();
+        assert_eq!(file.size().unwrap(), 0);
+        file.write_at(4, b"4567").unwrap();
+        file.write_at(0, b"0123").unwrap();
+        file.sync().unwrap();
+
+        // Another handle on the same file sees what was written
+        let other = vfs.open(path).unwrap();
+        assert_eq!(other.size().unwrap(), 8);
+        assert_eq!(&*other.read_at(2, 4).unwrap(), b"2345");
+        assert_eq!(&*other.read_at(0, 8).unwrap(), b"01234567");
+
+        assert_eq!(vfs.open(Path::new("other.db")).unwrap().size().unwrap(), 0);
+    }
+}
@@ -0,0 +1,301 @@
+use crate::{
+    query_parser::{
+        AndCondition, BinaryOperator, CompoundSelect, CreateView, Expression, Function,
+        FunctionArgument, Query, ResultColumn, Selection,
+    },
+    schema::Schema,
+};
+use anyhow::{bail, Result};
+
+/// How deep views defined on other views can go, which also catches views defined on themselves
+const MAX_VIEW_DEPTH: usize = 32;
+
+/// Rewrites a query that selects from a view into one that selects from the view's table, by
+/// substituting the view's query for it: the view's expressions take the place of its columns,
+/// and its WHERE conditions are added to the query's. A query on a table comes back unchanged.
+///
+/// Only views that select columns and expressions from a table, with a WHERE clause or without,
+/// can be substituted like this. Views with DISTINCT, aggregates, ORDER BY or LIMIT can't yet.
+pub fn expand_views(schema: &[Schema], query: &Query) -> Result<Query> {
+    // Each SELECT of a compound is expanded on its own, leaving the compound's ORDER BY and LIMIT
+    if !query.compound.is_empty() {
+        let mut expanded = expand_views(schema, &query.left_most())?;
+        for member in &query.compound {
+            expanded.compound.push(CompoundSelect {
+                operator: member.operator,
+                select: expand_views(schema, &member.select)?,
+            });
+        }
+        expanded.order_by.clone_from(&query.order_by);
+        expanded.limit = query.limit;
+        return Ok(expanded);
+    }
+
+    let mut query = query.clone();
+
+    let mut depth = 0;
+    // A subquery's name isn't a view's, even if it's the same
+    while query.from_subquery.is_none() {
+        let Some(view) = schema
+            .iter()
+            .find(|s| s.is_view() && s.name.eq_ignore_ascii_case(&query.from_table))
+        else {
+            break;
+        };
+        if depth == MAX_VIEW_DEPTH {
+            bail!("views on {} are nested too deeply", query.from_table);
+        }
+        query = substitute_view(&view.create_view()?, query)?;
+        depth += 1;
+    }
+
+    // Subqueries can select from views too
+    if let Some(subquery) = &mut query.from_subquery {
+        **subquery = expand_views(schema, subquery)?;
+    }
+    for condition in query.and_conditions.iter_mut().flatten() {
+        if let Some(subquery) = &mut condition.subquery {
+            **subquery = expand_views(schema, subquery)?;
+        }
+    }
+
+    Ok(query)
+}
+
+/// A column of a view: its name, and the expression it's the result of
+type ViewColumn = (String, Expression);
+
+fn substitute_view(view: &CreateView, outer: Query) -> Result<Query> {
+    let name = &view.view_name;
+    let inner = &view.query;
+    if inner.distinct || !inner.order_by.is_empty() || inner.limit.is_some() {
+        bail!(
+            "Unhandled view {name}: views with DISTINCT, ORDER BY or LIMIT can't be selected from"
+        );
+    }
+    if !inner.compound.is_empty() {
+        bail!("Unhandled view {name}: views of compound SELECTs can't be selected from");
+    }
+
+    let mut columns: Vec<ViewColumn> = vec![];
+    for result_column in &inner.selection_list {
+        let Selection::Expression(expression) = &result_column.selection else {
+            bail!("Unhandled view {name}: views with aggregates can't be selected from");
+        };
+        columns.push((result_column.name(), expression.clone()));
+    }
+    if let Some(names) = &view.columns {
+        if names.len() != columns.len() {
+            bail!(
+                "expected {} columns for '{name}' but got {}",
+                names.len(),
+                columns.len()
+            );
+        }
+        for ((column_name, _), name) in columns.iter_mut().zip(names) {
+            column_name.clone_from(name);
+        }
+    }
+
+    let view_column = |column: &str| -> Result<Expression> {
+        // A column may be qualified with the view's name
+        let column = match column.split_once('.') {
+            Some((qualifier, column)) if qualifier.eq_ignore_ascii_case(name) => column,
+            _ => column,
+        };
+        match columns
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(column))
+        {
+            Some((_, expression)) => Ok(expression.clone()),
+            None => bail!("no such column: {column}"),
+        }
+    };
+    // Conditions and count() can only name the table's own columns
+    let table_column = |column: &str| -> Result<String> {
+        match view_column(column)? {
+            Expression::Column(table_column) => Ok(table_column),
+            expression => {
+                bail!("Unhandled condition on view column {column}, which is {expression}")
+            }
+        }
+    };
+
+    let mut selection_list = vec![];
+    for result_column in &outer.selection_list {
+        let selection = match &result_column.selection {
+            Selection::Expression(expression) => {
+                Selection::Expression(substitute_columns(expression, &view_column)?)
+            }
+            Selection::AggregateFunction(Function::Count(FunctionArgument::Columns(names))) => {
+                let names = names
+                    .iter()
+                    .map(|name| table_column(name))
+                    .collect::<Result<_>>()?;
+                Selection::AggregateFunction(Function::Count(FunctionArgument::Columns(names)))
+            }
+            selection => selection.clone(),
+        };
+        // Headed as they were, rather than by the expressions that replace the view's columns
+        let alias = match selection {
+            Selection::Expression(_) => Some(result_column.name()),
+            Selection::AggregateFunction(_) => result_column.alias.clone(),
+        };
+        selection_list.push(ResultColumn { selection, alias });
+    }
+
+    let mut and_conditions = inner.and_conditions.clone().unwrap_or_default();
+    for condition in outer.and_conditions.iter().flatten() {
+        and_conditions.push(AndCondition {
+            column_name: table_column(&condition.column_name)?,
+            ..condition.clone()
+        });
+    }
+
+    let outer_filter = outer
+        .filter
+        .as_ref()
+        .map(|filter| substitute_columns(filter, &view_column))
+        .transpose()?;
+    let filter = match (inner.filter.clone(), outer_filter) {
+        (Some(lhs), Some(rhs)) => Some(Expression::Binary {
+            operator: BinaryOperator::And,
+            lhs: Box::new(lhs),
+            rhs: Box::new(rhs),
+        }),
+        (lhs, rhs) => lhs.or(rhs),
+    };
+
+    // Terms that name a result column by its heading are left for the executor to resolve
+    let mut order_by = outer.order_by;
+    for term in &mut order_by {
+        let is_heading = matches!(&term.expression, Expression::Column(name)
+            if selection_list
+                .iter()
+                .any(|column| column.alias.as_ref().is_some_and(|alias| alias == name)));
+        if !is_heading {
+            term.expression = substitute_columns(&term.expression, &view_column)?;
+        }
+    }
+
+    Ok(Query {
+        distinct: outer.distinct,
+        selection_list,
+        from_table: inner.from_table.clone(),
+        from_schema: outer.from_schema.clone(),
+        from_subquery: inner.from_subquery.clone(),
+        and_conditions: (!and_conditions.is_empty()).then_some(and_conditions),
+        filter,
+        compound: vec![],
+        order_by,
+        limit: outer.limit,
+    })
+}
+
+/// The expression with each column replaced by `column`'s expression for it
+fn substitute_columns(
+    expression: &Expression,
+    column: &impl Fn(&str) -> Result<Expression>,
+) -> Result<Expression> {
+    let substitute = |operand: &Expression| substitute_columns(operand, column).map(Box::new);
+
+    let expression = match expression {
+        Expression::Column(name) => column(name)?,
+        Expression::Negate(operand) => Expression::Negate(substitute(operand)?),
+        Expression::Not(operand) => Expression::Not(substitute(operand)?),
+        Expression::Binary { operator, lhs, rhs } => Expression::Binary {
+            operator: *operator,
+            lhs: substitute(lhs)?,
+            rhs: substitute(rhs)?,
+        },
+        Expression::FunctionCall { name, arguments } => Expression::FunctionCall {
+            name: name.clone(),
+            arguments: arguments
+                .iter()
+                .map(|argument| substitute_columns(argument, column))
+                .collect::<Result<_>>()?,
+        },
+        Expression::Cast { operand, type_name } => Expression::Cast {
+            operand: substitute(operand)?,
+            type_name: type_name.clone(),
+        },
+        Expression::Literal(_) | Expression::Parameter(_) => expression.clone(),
+    };
+
+    Ok(expression)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query_parser::parse_query;
+
+    fn view(name: &str, sql: &str) -> Schema {
+        Schema {
+            kind: "view".to_string(),
+            name: name.to_string(),
+            table_name: name.to_string(),
+            root_page: 0,
+            sql: Some(sql.to_string()),
+        }
+    }
+
+    fn expand(schema: &[Schema], sql: &str) -> Result<Query> {
+        expand_views(schema, &parse_query(sql).unwrap().1)
+    }
+
+    #[test]
+    fn test_expand_views() {
+        let schema = [
+            view(
+                "red",
+                "CREATE VIEW red (id, label) AS SELECT id, upper(name) FROM apples \
+                 WHERE color = 'Red'",
+            ),
+            view("bigred", "CREATE VIEW bigred AS SELECT id, label FROM red"),
+        ];
+
+        let query = expand(
+            &schema,
+            "SELECT label, red.id + 1 FROM red WHERE id = 3 ORDER BY label DESC, id",
+        )
+        .unwrap();
+        let expected = parse_query(
+            "SELECT upper(name) AS label, id + 1 AS \"red.id + 1\" FROM apples \
+             WHERE color = 'Red' AND id = 3 ORDER BY label DESC, id",
+        )
+        .unwrap()
+        .1;
+        assert_eq!(query, expected);
+
+        // A view on a view is expanded all the way down to the table
+        let query = expand(&schema, "SELECT label FROM bigred").unwrap();
+        assert_eq!(query.from_table, "apples");
+        assert_eq!(
+            query.selection_list,
+            parse_query("SELECT upper(name) AS label FROM apples")
+                .unwrap()
+                .1
+                .selection_list
+        );
+
+        let query = expand(&schema, "SELECT name FROM apples").unwrap();
+        assert_eq!(query, parse_query("SELECT name FROM apples").unwrap().1);
+
+        assert!(expand(&schema, "SELECT color FROM red").is_err());
+        assert!(expand(&schema, "SELECT id FROM red WHERE label = 'FUJI'").is_err());
+    }
+
+    #[test]
+    fn test_expand_views_unhandled() {
+        let schema = [
+            view("a", "CREATE VIEW a AS SELECT DISTINCT color FROM apples"),
+            view("b", "CREATE VIEW b (x, y) AS SELECT color FROM apples"),
+            view("c", "CREATE VIEW c AS SELECT id FROM c"),
+        ];
+
+        assert!(expand(&schema, "SELECT color FROM a").is_err());
+        assert!(expand(&schema, "SELECT x FROM b").is_err());
+        assert!(expand(&schema, "SELECT id FROM c").is_err());
+    }
+}
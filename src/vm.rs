@@ -0,0 +1,1513 @@
+use crate::{
+    affinity::Affinity,
+    collation::Collation,
+    functions::{FunctionRegistry, ScalarFunction},
+    query_parser::{BinaryOperator, ComparisonOperator, Expression, Literal},
+    record::Record,
+    regexp::RegexCache,
+    value::Value,
+};
+use anyhow::{bail, Result};
+use std::{cmp::Ordering, fmt, mem, sync::Arc};
+
+/// Where a column's value comes from in a table row
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColumnRef {
+    RowId,
+    Index(usize),
+}
+
+/// A single instruction of a Program, loosely modelled on SQLite's VDBE opcodes:
+/// [opcode](https://www.sqlite.org/opcode.html)
+#[derive(Debug, Clone, PartialEq)]
+pub enum Opcode {
+    /// r[dest] = the column at `column` of the current row
+    Column { column: usize, dest: usize },
+    /// r[register] = r[register] as a real, if it's an integer. A REAL column stores a real with
+    /// no fractional part as an integer, to save space, so this follows a Column that loads one.
+    RealAffinity { register: usize },
+    /// r[dest] = the rowid of the current row
+    Rowid { dest: usize },
+    /// r[dest] = a string constant
+    String8 { value: String, dest: usize },
+    /// r[dest] = a blob constant
+    Blob { value: Vec<u8>, dest: usize },
+    /// r[dest] = an integer constant
+    Integer { value: i64, dest: usize },
+    /// r[dest] = a real constant
+    Real { value: f64, dest: usize },
+    /// r[dest] = NULL
+    Null { dest: usize },
+    /// r[dest] = r[lhs] <operator> r[rhs]
+    Binary {
+        operator: BinaryOperator,
+        lhs: usize,
+        rhs: usize,
+        dest: usize,
+        /// The collation a comparison compares text with, or None for BINARY
+        collation: Option<Arc<Collation>>,
+    },
+    /// r[dest] = NOT r[register]: 1 if it's false, 0 if it's true, or NULL if it's NULL
+    Not { register: usize, dest: usize },
+    /// r[dest] = function(r[args..args + arg_count])
+    Function {
+        function: Arc<ScalarFunction>,
+        args: usize,
+        arg_count: usize,
+        dest: usize,
+    },
+    /// Jump to `target` unless r[lhs] equals r[rhs]. NULL is never equal to anything.
+    Ne {
+        lhs: usize,
+        rhs: usize,
+        target: usize,
+        collation: Option<Arc<Collation>>,
+    },
+    /// Jump to `target` if r[lhs] equals r[rhs]
+    Eq {
+        lhs: usize,
+        rhs: usize,
+        target: usize,
+        collation: Option<Arc<Collation>>,
+    },
+    /// Jump to `target` if r[lhs] < r[rhs], or if either is NULL
+    Lt {
+        lhs: usize,
+        rhs: usize,
+        target: usize,
+        collation: Option<Arc<Collation>>,
+    },
+    /// Jump to `target` if r[lhs] > r[rhs], or if either is NULL
+    Gt {
+        lhs: usize,
+        rhs: usize,
+        target: usize,
+        collation: Option<Arc<Collation>>,
+    },
+    /// r[dest] = 1 if the pattern in r[pattern] matches r[text], 0 if not, or NULL if r[text] is
+    /// NULL
+    Regexp {
+        pattern: usize,
+        text: usize,
+        dest: usize,
+    },
+    /// Converts r[register] to suit `affinity`, as a constant is before it's compared with a
+    /// column
+    Affinity { register: usize, affinity: Affinity },
+    /// Converts r[register] as CAST to a type with `affinity` does
+    Cast { register: usize, affinity: Affinity },
+    /// Jump to `target` if r[register] is false (0) or NULL
+    IfNot { register: usize, target: usize },
+    /// Jump to `target`
+    Goto { target: usize },
+    /// Emit r[start..start + count] as a result row
+    ResultRow { start: usize, count: usize },
+    /// Stop running the program for the current row
+    Halt,
+}
+
+impl Opcode {
+    /// The opcode's name and its P1, P2, P3 and P4 operands, laid out the way SQLite's EXPLAIN
+    /// lists them, with registers and jump targets in P1-P3 and constants in P4
+    fn operands(&self) -> (&'static str, usize, usize, usize, String) {
+        match self {
+            Opcode::Column { column, dest } => ("Column", 0, *column, *dest, String::new()),
+            Opcode::RealAffinity { register } => ("RealAffinity", *register, 0, 0, String::new()),
+            Opcode::Rowid { dest } => ("Rowid", 0, *dest, 0, String::new()),
+            Opcode::String8 { value, dest } => ("String8", 0, *dest, 0, value.clone()),
+            Opcode::Blob { value, dest } => ("Blob", value.len(), *dest, 0, hex(value)),
+            Opcode::Integer { value, dest } => {
+                ("Integer", *value as usize, *dest, 0, String::new())
+            }
+            Opcode::Real { value, dest } => ("Real", 0, *dest, 0, value.to_string()),
+            Opcode::Null { dest } => ("Null", 0, *dest, 0, String::new()),
+            Opcode::Binary {
+                operator,
+                lhs,
+                rhs,
+                dest,
+                collation,
+            } => (
+                opcode_name(*operator),
+                *rhs,
+                *lhs,
+                *dest,
+                collation_name(collation),
+            ),
+            Opcode::Not { register, dest } => ("Not", *register, *dest, 0, String::new()),
+            Opcode::Function {
+                function,
+                args,
+                arg_count,
+                dest,
+            } => (
+                "Function",
+                0,
+                *args,
+                *dest,
+                format!("{}({arg_count})", function.name),
+            ),
+            Opcode::Ne {
+                lhs,
+                rhs,
+                target,
+                collation,
+            } => ("Ne", *rhs, *target, *lhs, collation_name(collation)),
+            Opcode::Eq {
+                lhs,
+                rhs,
+                target,
+                collation,
+            } => ("Eq", *rhs, *target, *lhs, collation_name(collation)),
+            Opcode::Lt {
+                lhs,
+                rhs,
+                target,
+                collation,
+            } => ("Lt", *rhs, *target, *lhs, collation_name(collation)),
+            Opcode::Gt {
+                lhs,
+                rhs,
+                target,
+                collation,
+            } => ("Gt", *rhs, *target, *lhs, collation_name(collation)),
+            Opcode::Regexp {
+                pattern,
+                text,
+                dest,
+            } => ("Regexp", *pattern, *text, *dest, String::new()),
+            Opcode::Affinity { register, affinity } => {
+                ("Affinity", *register, 1, 0, affinity.to_string())
+            }
+            Opcode::Cast { register, affinity } => ("Cast", *register, 0, 0, affinity.to_string()),
+            Opcode::IfNot { register, target } => ("IfNot", *register, *target, 0, String::new()),
+            Opcode::Goto { target } => ("Goto", 0, *target, 0, String::new()),
+            Opcode::ResultRow { start, count } => ("ResultRow", *start, *count, 0, String::new()),
+            Opcode::Halt => ("Halt", 0, 0, 0, String::new()),
+        }
+    }
+
+    /// A short description of what the opcode does, like the comments in SQLite's EXPLAIN output
+    fn comment(&self) -> String {
+        match self {
+            Opcode::Column { column, dest } => format!("r[{dest}]=column {column}"),
+            Opcode::RealAffinity { .. } => String::new(),
+            Opcode::Rowid { dest } => format!("r[{dest}]=rowid"),
+            Opcode::String8 { value, dest } => format!("r[{dest}]='{value}'"),
+            Opcode::Blob { value, dest } => format!("r[{dest}]=X'{}'", hex(value)),
+            Opcode::Integer { value, dest } => format!("r[{dest}]={value}"),
+            Opcode::Real { value, dest } => format!("r[{dest}]={value}"),
+            Opcode::Null { dest } => format!("r[{dest}]=NULL"),
+            Opcode::Binary {
+                operator,
+                lhs,
+                rhs,
+                dest,
+                ..
+            } if operator.is_comparison()
+                || matches!(operator, BinaryOperator::And | BinaryOperator::Or) =>
+            {
+                format!("r[{dest}]=(r[{lhs}] {} r[{rhs}])", operator.symbol())
+            }
+            Opcode::Binary {
+                operator,
+                lhs,
+                rhs,
+                dest,
+                ..
+            } => format!("r[{dest}]=r[{lhs}]{}r[{rhs}]", operator.symbol()),
+            Opcode::Not { register, dest } => format!("r[{dest}]=!r[{register}]"),
+            Opcode::Function {
+                args,
+                arg_count,
+                dest,
+                ..
+            } => format!("r[{dest}]=func(r[{args}..{}])", args + arg_count),
+            Opcode::Ne { lhs, rhs, .. } => format!("if r[{lhs}]!=r[{rhs}] goto P2"),
+            Opcode::Eq { lhs, rhs, .. } => format!("if r[{lhs}]==r[{rhs}] goto P2"),
+            Opcode::Lt { lhs, rhs, .. } => format!("if r[{lhs}]<r[{rhs}] goto P2"),
+            Opcode::Gt { lhs, rhs, .. } => format!("if r[{lhs}]>r[{rhs}] goto P2"),
+            Opcode::Regexp {
+                pattern,
+                text,
+                dest,
+            } => format!("r[{dest}]=r[{text}] REGEXP r[{pattern}]"),
+            Opcode::Affinity { register, .. } => format!("affinity(r[{register}])"),
+            Opcode::Cast { register, .. } => format!("cast(r[{register}])"),
+            Opcode::IfNot { register, .. } => format!("if !r[{register}] goto P2"),
+            Opcode::Goto { .. } => String::new(),
+            Opcode::ResultRow { start, count } => {
+                format!("output=r[{start}..{}]", start + count)
+            }
+            Opcode::Halt => String::new(),
+        }
+    }
+}
+
+fn opcode_name(operator: BinaryOperator) -> &'static str {
+    match operator {
+        BinaryOperator::Concat => "Concat",
+        BinaryOperator::Multiply => "Multiply",
+        BinaryOperator::Divide => "Divide",
+        BinaryOperator::Remainder => "Remainder",
+        BinaryOperator::Add => "Add",
+        BinaryOperator::Subtract => "Subtract",
+        BinaryOperator::Equals => "Eq",
+        BinaryOperator::NotEquals => "Ne",
+        BinaryOperator::Less => "Lt",
+        BinaryOperator::LessOrEqual => "Le",
+        BinaryOperator::Greater => "Gt",
+        BinaryOperator::GreaterOrEqual => "Ge",
+        BinaryOperator::Is => "Is",
+        BinaryOperator::IsNot => "IsNot",
+        BinaryOperator::And => "And",
+        BinaryOperator::Or => "Or",
+    }
+}
+
+/// A comparison's collation as SQLite's EXPLAIN shows it in P4, e.g. (NOCASE), or nothing for
+/// BINARY
+fn collation_name(collation: &Option<Arc<Collation>>) -> String {
+    collation
+        .as_ref()
+        .map(|collation| format!("({})", collation.name))
+        .unwrap_or_default()
+}
+
+/// A blob constant's bytes in hex, as SQLite's EXPLAIN shows them
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02X}")).collect()
+}
+
+/// A WHERE condition, with its column resolved and its collation looked up
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+    pub column: ColumnRef,
+    pub operator: ComparisonOperator,
+    /// The literals the column is compared against, as in AndCondition
+    pub values: Vec<Expression>,
+    /// The column's affinity, which the literals are converted to suit before they're compared
+    pub affinity: Affinity,
+    /// The collation text is compared with, or None for BINARY
+    pub collation: Option<Arc<Collation>>,
+}
+
+/// A compiled, register-based program that's run once per row: it filters the row according to the
+/// WHERE conditions and, if it passes, produces the projected result row.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub opcodes: Vec<Opcode>,
+    pub register_count: usize,
+}
+
+/// Builds a Program, handing out registers and patching jump targets
+struct ProgramBuilder<'a> {
+    opcodes: Vec<Opcode>,
+    register_count: usize,
+    /// Addresses of jumps to patch to the final Halt, once its address is known
+    jumps_to_halt: Vec<usize>,
+    resolve_column: &'a dyn Fn(&str) -> Result<ColumnRef>,
+    /// The collation a column declares, or None for BINARY
+    column_collation: &'a dyn Fn(ColumnRef) -> Result<Option<Arc<Collation>>>,
+    /// The affinity a column declares
+    column_affinity: &'a dyn Fn(ColumnRef) -> Affinity,
+    functions: &'a FunctionRegistry,
+}
+
+impl ProgramBuilder<'_> {
+    fn allocate_register(&mut self) -> usize {
+        self.register_count += 1;
+        self.register_count - 1
+    }
+
+    /// The collation a comparison compares text with: as SQLite picks it, the collation of its
+    /// left operand if that's a column, or else of its right operand if that is
+    fn comparison_collation(
+        &self,
+        lhs: &Expression,
+        rhs: &Expression,
+    ) -> Result<Option<Arc<Collation>>> {
+        for operand in [lhs, rhs] {
+            if let Expression::Column(name) = operand {
+                return (self.column_collation)((self.resolve_column)(name)?);
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn load_column(&mut self, column: ColumnRef, dest: usize) {
+        match column {
+            ColumnRef::RowId => self.opcodes.push(Opcode::Rowid { dest }),
+            ColumnRef::Index(index) => {
+                self.opcodes.push(Opcode::Column {
+                    column: index,
+                    dest,
+                });
+                if (self.column_affinity)(column) == Affinity::Real {
+                    self.opcodes.push(Opcode::RealAffinity { register: dest });
+                }
+            }
+        }
+    }
+
+    /// Emits the opcodes that evaluate an expression into r[dest]
+    fn compile_expression(&mut self, expression: &Expression, dest: usize) -> Result<()> {
+        match expression {
+            Expression::Literal(Literal::Null) => self.opcodes.push(Opcode::Null { dest }),
+            Expression::Literal(Literal::Integer(value)) => self.opcodes.push(Opcode::Integer {
+                value: *value,
+                dest,
+            }),
+            Expression::Literal(Literal::Real(value)) => self.opcodes.push(Opcode::Real {
+                value: *value,
+                dest,
+            }),
+            Expression::Literal(Literal::Text(value)) => self.opcodes.push(Opcode::String8 {
+                value: value.clone(),
+                dest,
+            }),
+            Expression::Literal(Literal::Blob(value)) => self.opcodes.push(Opcode::Blob {
+                value: value.clone(),
+                dest,
+            }),
+            Expression::Column(name) => self.load_column((self.resolve_column)(name)?, dest),
+            // Parameters are replaced when values are bound, so any left over are unbound
+            Expression::Parameter(_) => self.opcodes.push(Opcode::Null { dest }),
+            Expression::Negate(operand) => match operand.as_ref() {
+                Expression::Literal(Literal::Integer(value)) if *value != i64::MIN => {
+                    self.opcodes.push(Opcode::Integer {
+                        value: -value,
+                        dest,
+                    })
+                }
+                Expression::Literal(Literal::Real(value)) => self.opcodes.push(Opcode::Real {
+                    value: -value,
+                    dest,
+                }),
+                // -x is evaluated as 0 - x, which also turns text into a number
+                operand => {
+                    let zero = self.allocate_register();
+                    self.opcodes.push(Opcode::Integer {
+                        value: 0,
+                        dest: zero,
+                    });
+                    let value = self.allocate_register();
+                    self.compile_expression(operand, value)?;
+                    self.opcodes.push(Opcode::Binary {
+                        operator: BinaryOperator::Subtract,
+                        lhs: zero,
+                        rhs: value,
+                        dest,
+                        collation: None,
+                    });
+                }
+            },
+            Expression::Binary { operator, lhs, rhs } => {
+                let lhs_register = self.allocate_register();
+                self.compile_expression(lhs, lhs_register)?;
+                let rhs_register = self.allocate_register();
+                self.compile_expression(rhs, rhs_register)?;
+
+                let collation = match operator.is_comparison() {
+                    true => self.comparison_collation(lhs, rhs)?,
+                    false => None,
+                };
+                self.opcodes.push(Opcode::Binary {
+                    operator: *operator,
+                    lhs: lhs_register,
+                    rhs: rhs_register,
+                    dest,
+                    collation,
+                });
+            }
+            Expression::Not(operand) => {
+                let register = self.allocate_register();
+                self.compile_expression(operand, register)?;
+                self.opcodes.push(Opcode::Not { register, dest });
+            }
+            Expression::Cast { operand, type_name } => {
+                self.compile_expression(operand, dest)?;
+                self.opcodes.push(Opcode::Cast {
+                    register: dest,
+                    affinity: Affinity::from_type_name(type_name),
+                });
+            }
+            Expression::FunctionCall { name, arguments } => {
+                let function = self.functions.find(name, arguments.len())?;
+
+                // Arguments go into consecutive registers, like result columns
+                let args = self.register_count;
+                self.register_count += arguments.len();
+                for (i, argument) in arguments.iter().enumerate() {
+                    self.compile_expression(argument, args + i)?;
+                }
+
+                self.opcodes.push(Opcode::Function {
+                    function,
+                    args,
+                    arg_count: arguments.len(),
+                    dest,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Emits a jump that skips the rest of the row, to be patched once the program is finished
+    fn push_jump_to_halt(&mut self, opcode: Opcode) {
+        self.jumps_to_halt.push(self.opcodes.len());
+        self.opcodes.push(opcode);
+    }
+}
+
+impl Program {
+    /// Compiles the WHERE conditions (all of which must hold) and filter (which must be TRUE, not
+    /// FALSE or NULL) and the expressions to output for each matching row, using `resolve_column`
+    /// to find the columns the expressions refer to and `functions` to find the functions they
+    /// call.
+    pub fn compile(
+        conditions: &[Condition],
+        filter: Option<&Expression>,
+        result_columns: &[&Expression],
+        resolve_column: &dyn Fn(&str) -> Result<ColumnRef>,
+        functions: &FunctionRegistry,
+    ) -> Result<Program> {
+        Program::compile_with_column_types(
+            conditions,
+            filter,
+            result_columns,
+            resolve_column,
+            &|_| Ok(None),
+            &|_| Affinity::Blob,
+            functions,
+        )
+    }
+
+    /// Like compile, with `column_collation` and `column_affinity` to find the collation and
+    /// affinity each column declares. The comparisons in the filter and expressions compare the
+    /// column's text with its collation, and a REAL column's integers are loaded as reals.
+    pub fn compile_with_column_types(
+        conditions: &[Condition],
+        filter: Option<&Expression>,
+        result_columns: &[&Expression],
+        resolve_column: &dyn Fn(&str) -> Result<ColumnRef>,
+        column_collation: &dyn Fn(ColumnRef) -> Result<Option<Arc<Collation>>>,
+        column_affinity: &dyn Fn(ColumnRef) -> Affinity,
+        functions: &FunctionRegistry,
+    ) -> Result<Program> {
+        let mut builder = ProgramBuilder {
+            opcodes: vec![],
+            register_count: 0,
+            jumps_to_halt: vec![],
+            resolve_column,
+            column_collation,
+            column_affinity,
+            functions,
+        };
+
+        for condition in conditions {
+            let value = builder.allocate_register();
+            builder.load_column(condition.column, value);
+
+            let collation = &condition.collation;
+            let constants = condition
+                .values
+                .iter()
+                .map(|literal| {
+                    let constant = builder.allocate_register();
+                    builder.compile_expression(literal, constant)?;
+                    Ok(constant)
+                })
+                .collect::<Result<Vec<_>>>()?;
+            // REGEXP is a function call rather than a comparison, so its pattern is left as it is
+            if condition.operator != ComparisonOperator::Regexp
+                && condition.affinity != Affinity::Blob
+            {
+                for &register in &constants {
+                    builder.opcodes.push(Opcode::Affinity {
+                        register,
+                        affinity: condition.affinity,
+                    });
+                }
+            }
+
+            match (condition.operator, constants.as_slice()) {
+                (ComparisonOperator::Equals, &[constant]) => {
+                    builder.push_jump_to_halt(Opcode::Ne {
+                        lhs: value,
+                        rhs: constant,
+                        target: 0,
+                        collation: collation.clone(),
+                    })
+                }
+                (ComparisonOperator::Regexp, &[constant]) => {
+                    let is_match = builder.allocate_register();
+                    builder.opcodes.push(Opcode::Regexp {
+                        pattern: constant,
+                        text: value,
+                        dest: is_match,
+                    });
+                    builder.push_jump_to_halt(Opcode::IfNot {
+                        register: is_match,
+                        target: 0,
+                    });
+                }
+                (ComparisonOperator::Between, &[low, high]) => {
+                    builder.push_jump_to_halt(Opcode::Lt {
+                        lhs: value,
+                        rhs: low,
+                        target: 0,
+                        collation: collation.clone(),
+                    });
+                    builder.push_jump_to_halt(Opcode::Gt {
+                        lhs: value,
+                        rhs: high,
+                        target: 0,
+                        collation: collation.clone(),
+                    });
+                }
+                (ComparisonOperator::In, constants) => {
+                    // Each match jumps past the Goto that skips the row when nothing matched
+                    let first_match = builder.opcodes.len();
+                    for &constant in constants {
+                        builder.opcodes.push(Opcode::Eq {
+                            lhs: value,
+                            rhs: constant,
+                            target: first_match + constants.len() + 1,
+                            collation: collation.clone(),
+                        });
+                    }
+                    builder.push_jump_to_halt(Opcode::Goto { target: 0 });
+                }
+                (operator, constants) => {
+                    bail!("{operator:?} can't take {} values", constants.len())
+                }
+            }
+        }
+
+        if let Some(filter) = filter {
+            let register = builder.allocate_register();
+            builder.compile_expression(filter, register)?;
+            builder.push_jump_to_halt(Opcode::IfNot {
+                register,
+                target: 0,
+            });
+        }
+
+        // Result columns go into consecutive registers, so ResultRow can emit them as a range
+        let start = builder.register_count;
+        builder.register_count += result_columns.len();
+        for (i, expression) in result_columns.iter().enumerate() {
+            builder.compile_expression(expression, start + i)?;
+        }
+        builder.opcodes.push(Opcode::ResultRow {
+            start,
+            count: result_columns.len(),
+        });
+
+        let halt = builder.opcodes.len();
+        builder.opcodes.push(Opcode::Halt);
+        for address in builder.jumps_to_halt {
+            match &mut builder.opcodes[address] {
+                Opcode::Ne { target, .. }
+                | Opcode::Lt { target, .. }
+                | Opcode::Gt { target, .. }
+                | Opcode::IfNot { target, .. }
+                | Opcode::Goto { target } => *target = halt,
+                opcode => unreachable!("{opcode:?} is not a jump"),
+            }
+        }
+
+        Ok(Program {
+            opcodes: builder.opcodes,
+            register_count: builder.register_count,
+        })
+    }
+}
+
+/// Lists the program one opcode per line, in the same columns as SQLite's
+/// [EXPLAIN](https://www.sqlite.org/lang_explain.html)
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "addr  opcode         p1    p2    p3    p4             p5  comment"
+        )?;
+        write!(
+            f,
+            "----  -------------  ----  ----  ----  -------------  --  -------------"
+        )?;
+
+        for (address, opcode) in self.opcodes.iter().enumerate() {
+            let (name, p1, p2, p3, p4) = opcode.operands();
+            let line = format!(
+                "{address:<4}  {name:<13}  {p1:<4}  {p2:<4}  {p3:<4}  {p4:<13}  0   {}",
+                opcode.comment()
+            );
+            write!(f, "\n{}", line.trim_end())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Runs a Program against rows, reusing its registers from one row to the next
+pub struct Vm {
+    program: Program,
+    registers: Vec<Value>,
+    regex_cache: RegexCache,
+}
+
+impl Vm {
+    pub fn new(program: Program) -> Self {
+        Vm {
+            registers: vec![Value::Null; program.register_count],
+            program,
+            regex_cache: RegexCache::new(),
+        }
+    }
+
+    /// Runs the program for a row, returning its result row, or None if the row was filtered out
+    pub fn run(&mut self, record: &Record) -> Result<Option<Vec<Value>>> {
+        let mut result_row = None;
+        let mut pc = 0;
+
+        while let Some(opcode) = self.program.opcodes.get(pc) {
+            pc += 1;
+
+            match opcode {
+                Opcode::Column { column, dest } => self.registers[*dest] = record.value(*column),
+                Opcode::RealAffinity { register } => {
+                    let value = mem::replace(&mut self.registers[*register], Value::Null);
+                    self.registers[*register] = Affinity::Real.load(value);
+                }
+                Opcode::Rowid { dest } => self.registers[*dest] = Value::Integer(record.row_id),
+                Opcode::String8 { value, dest } => {
+                    self.registers[*dest] = Value::Text(value.clone())
+                }
+                Opcode::Blob { value, dest } => self.registers[*dest] = Value::Blob(value.clone()),
+                Opcode::Integer { value, dest } => self.registers[*dest] = Value::Integer(*value),
+                Opcode::Real { value, dest } => self.registers[*dest] = Value::Real(*value),
+                Opcode::Null { dest } => self.registers[*dest] = Value::Null,
+                Opcode::Binary {
+                    operator,
+                    lhs,
+                    rhs,
+                    dest,
+                    collation,
+                } => {
+                    let (lhs, rhs) = (&self.registers[*lhs], &self.registers[*rhs]);
+                    self.registers[*dest] = binary(*operator, lhs, rhs, collation.as_deref());
+                }
+                Opcode::Not { register, dest } => {
+                    self.registers[*dest] = match truth(&self.registers[*register]) {
+                        Some(is_true) => Value::Integer(!is_true as i64),
+                        None => Value::Null,
+                    };
+                }
+                Opcode::Function {
+                    function,
+                    args,
+                    arg_count,
+                    dest,
+                } => {
+                    self.registers[*dest] =
+                        (function.call)(&self.registers[*args..*args + *arg_count])?;
+                }
+                Opcode::Ne {
+                    lhs,
+                    rhs,
+                    target,
+                    collation,
+                } => {
+                    let (lhs, rhs) = (&self.registers[*lhs], &self.registers[*rhs]);
+                    if !values_equal(lhs, rhs, collation.as_deref()) {
+                        pc = *target;
+                    }
+                }
+                Opcode::Eq {
+                    lhs,
+                    rhs,
+                    target,
+                    collation,
+                } => {
+                    let (lhs, rhs) = (&self.registers[*lhs], &self.registers[*rhs]);
+                    if values_equal(lhs, rhs, collation.as_deref()) {
+                        pc = *target;
+                    }
+                }
+                Opcode::Lt {
+                    lhs,
+                    rhs,
+                    target,
+                    collation,
+                } => {
+                    let (lhs, rhs) = (&self.registers[*lhs], &self.registers[*rhs]);
+                    let ordering = compare_values(lhs, rhs, collation.as_deref());
+                    if ordering.is_none_or(|ordering| ordering == Ordering::Less) {
+                        pc = *target;
+                    }
+                }
+                Opcode::Gt {
+                    lhs,
+                    rhs,
+                    target,
+                    collation,
+                } => {
+                    let (lhs, rhs) = (&self.registers[*lhs], &self.registers[*rhs]);
+                    let ordering = compare_values(lhs, rhs, collation.as_deref());
+                    if ordering.is_none_or(|ordering| ordering == Ordering::Greater) {
+                        pc = *target;
+                    }
+                }
+                Opcode::Regexp {
+                    pattern,
+                    text,
+                    dest,
+                } => {
+                    self.registers[*dest] =
+                        match (&self.registers[*pattern], &self.registers[*text]) {
+                            (Value::Null, _) | (_, Value::Null) => Value::Null,
+                            (pattern, text) => {
+                                let is_match = self
+                                    .regex_cache
+                                    .is_match(&pattern.to_string(), &text.to_string())?;
+                                Value::Integer(is_match as i64)
+                            }
+                        };
+                }
+                Opcode::Affinity { register, affinity } => {
+                    let value = mem::replace(&mut self.registers[*register], Value::Null);
+                    self.registers[*register] = affinity.apply(value);
+                }
+                Opcode::Cast { register, affinity } => {
+                    let value = mem::replace(&mut self.registers[*register], Value::Null);
+                    self.registers[*register] = affinity.cast(value);
+                }
+                Opcode::IfNot { register, target } => {
+                    if !is_true(&self.registers[*register]) {
+                        pc = *target;
+                    }
+                }
+                Opcode::Goto { target } => pc = *target,
+                Opcode::ResultRow { start, count } => {
+                    result_row = Some(self.registers[*start..*start + *count].to_vec());
+                }
+                Opcode::Halt => return Ok(result_row),
+            }
+        }
+
+        bail!("Program ended without a Halt")
+    }
+}
+
+/// Applies a binary operator the way SQLite does: operands are converted to numbers (or to text,
+/// for ||), NULL operands give NULL, integer results that would overflow become reals, and
+/// dividing by zero gives NULL. Comparisons give 1 or 0, or NULL if either operand is NULL, except
+/// for IS and IS NOT, to which NULL is just another value. AND and OR follow SQL's three-valued
+/// logic, where NULL is unknown: FALSE AND NULL is FALSE, but TRUE AND NULL is NULL. Comparisons
+/// compare text by `collation`, or by BINARY without one.
+fn binary(
+    operator: BinaryOperator,
+    lhs: &Value,
+    rhs: &Value,
+    collation: Option<&Collation>,
+) -> Value {
+    let boolean = |b: bool| Value::Integer(b as i64);
+    match operator {
+        BinaryOperator::Concat => {
+            return match (lhs, rhs) {
+                (Value::Null, _) | (_, Value::Null) => Value::Null,
+                (lhs, rhs) => Value::Text(format!("{lhs}{rhs}")),
+            };
+        }
+        BinaryOperator::And => {
+            return match (truth(lhs), truth(rhs)) {
+                (Some(false), _) | (_, Some(false)) => boolean(false),
+                (Some(true), Some(true)) => boolean(true),
+                _ => Value::Null,
+            };
+        }
+        BinaryOperator::Or => {
+            return match (truth(lhs), truth(rhs)) {
+                (Some(true), _) | (_, Some(true)) => boolean(true),
+                (Some(false), Some(false)) => boolean(false),
+                _ => Value::Null,
+            };
+        }
+        BinaryOperator::Is | BinaryOperator::IsNot => {
+            let is = match (lhs, rhs) {
+                (Value::Null, Value::Null) => true,
+                (lhs, rhs) => values_equal(lhs, rhs, collation),
+            };
+            return boolean(is == (operator == BinaryOperator::Is));
+        }
+        operator if operator.is_comparison() => {
+            let Some(ordering) = compare_values(lhs, rhs, collation) else {
+                return Value::Null;
+            };
+            return boolean(match operator {
+                BinaryOperator::Equals => ordering.is_eq(),
+                BinaryOperator::NotEquals => ordering.is_ne(),
+                BinaryOperator::Less => ordering.is_lt(),
+                BinaryOperator::LessOrEqual => ordering.is_le(),
+                BinaryOperator::Greater => ordering.is_gt(),
+                _ => ordering.is_ge(),
+            });
+        }
+        _ => {}
+    }
+
+    match (lhs.to_numeric(), rhs.to_numeric()) {
+        (Value::Null, _) | (_, Value::Null) => Value::Null,
+        (Value::Integer(a), Value::Integer(b)) => {
+            let result = match operator {
+                BinaryOperator::Add => a.checked_add(b),
+                BinaryOperator::Subtract => a.checked_sub(b),
+                BinaryOperator::Multiply => a.checked_mul(b),
+                BinaryOperator::Divide if b == 0 => return Value::Null,
+                BinaryOperator::Divide => a.checked_div(b),
+                BinaryOperator::Remainder if b == 0 => return Value::Null,
+                BinaryOperator::Remainder => Some(a.wrapping_rem(b)),
+                operator => unreachable!("{operator:?} isn't arithmetic"),
+            };
+
+            match result {
+                Some(i) => Value::Integer(i),
+                None => real_binary(operator, a as f64, b as f64),
+            }
+        }
+        (a, b) => real_binary(
+            operator,
+            a.as_real().unwrap_or_default(),
+            b.as_real().unwrap_or_default(),
+        ),
+    }
+}
+
+fn real_binary(operator: BinaryOperator, a: f64, b: f64) -> Value {
+    match operator {
+        BinaryOperator::Add => Value::Real(a + b),
+        BinaryOperator::Subtract => Value::Real(a - b),
+        BinaryOperator::Multiply => Value::Real(a * b),
+        BinaryOperator::Divide if b == 0.0 => Value::Null,
+        BinaryOperator::Divide => Value::Real(a / b),
+        // Like SQLite, the remainder of reals is taken after truncating them to integers
+        BinaryOperator::Remainder => match (a as i64, b as i64) {
+            (_, 0) => Value::Null,
+            (a, b) => Value::Real(a.wrapping_rem(b) as f64),
+        },
+        operator => unreachable!("{operator:?} isn't arithmetic"),
+    }
+}
+
+/// Whether a value is true or false as a condition, or None if it's NULL, which is neither
+fn truth(value: &Value) -> Option<bool> {
+    match value {
+        Value::Null => None,
+        value => Some(is_true(value)),
+    }
+}
+
+fn is_true(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Integer(i) => *i != 0,
+        Value::Real(r) => *r != 0.0,
+        Value::Text(s) => s.parse::<f64>().is_ok_and(|f| f != 0.0),
+        Value::Blob(_) => false,
+    }
+}
+
+/// Whether two values are equal. NULL is never equal to anything.
+fn values_equal(value: &Value, other: &Value, collation: Option<&Collation>) -> bool {
+    compare_values(value, other, collation) == Some(Ordering::Equal)
+}
+
+/// Orders two values as they are, or gives None if either is NULL: a constant compared with a
+/// column has already been converted by an Affinity opcode. Text is compared by `collation`, or
+/// by BINARY without one.
+fn compare_values(value: &Value, other: &Value, collation: Option<&Collation>) -> Option<Ordering> {
+    match (value, other, collation) {
+        (Value::Null, _, _) | (_, Value::Null, _) => None,
+        (value, other, Some(collation)) => Some(collation.compare_values(value, other)),
+        (value, other, None) => Some(value.compare(other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        collation::CollationRegistry,
+        types::{SerialType, SerialValue},
+    };
+
+    fn record(row_id: i64, serial_values: Vec<SerialValue>) -> Record {
+        Record {
+            row_id,
+            serial_types: serial_values.iter().map(|_| SerialType::Null).collect(),
+            serial_values,
+        }
+    }
+
+    /// Resolves columns of a table created with "CREATE TABLE apples (name, color)"
+    fn resolve_apples_column(name: &str) -> Result<ColumnRef> {
+        match name {
+            "rowid" => Ok(ColumnRef::RowId),
+            "name" => Ok(ColumnRef::Index(0)),
+            "color" => Ok(ColumnRef::Index(1)),
+            _ => bail!("no such column: {name}"),
+        }
+    }
+
+    fn column(name: &str) -> Expression {
+        Expression::Column(name.to_string())
+    }
+
+    fn condition(
+        column: ColumnRef,
+        operator: ComparisonOperator,
+        values: Vec<Expression>,
+    ) -> Condition {
+        // Like the columns of "CREATE TABLE apples (name, color)", which have no affinity
+        let affinity = match column {
+            ColumnRef::RowId => Affinity::Integer,
+            ColumnRef::Index(_) => Affinity::Blob,
+        };
+        Condition {
+            column,
+            operator,
+            values,
+            affinity,
+            collation: None,
+        }
+    }
+
+    #[test]
+    fn test_compile() {
+        let program = Program::compile(
+            &[condition(
+                ColumnRef::Index(1),
+                ComparisonOperator::Equals,
+                vec![Expression::Literal(Literal::Text("Red".to_string()))],
+            )],
+            None,
+            &[&column("rowid"), &column("name")],
+            &resolve_apples_column,
+            &FunctionRegistry::new(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            program.opcodes,
+            vec![
+                Opcode::Column { column: 1, dest: 0 },
+                Opcode::String8 {
+                    value: "Red".to_string(),
+                    dest: 1
+                },
+                Opcode::Ne {
+                    lhs: 0,
+                    rhs: 1,
+                    target: 6,
+                    collation: None
+                },
+                Opcode::Rowid { dest: 2 },
+                Opcode::Column { column: 0, dest: 3 },
+                Opcode::ResultRow { start: 2, count: 2 },
+                Opcode::Halt,
+            ]
+        );
+        assert_eq!(program.register_count, 4);
+    }
+
+    #[test]
+    fn test_display_program() {
+        let program = Program::compile(
+            &[condition(
+                ColumnRef::Index(1),
+                ComparisonOperator::Equals,
+                vec![Expression::Literal(Literal::Text("Red".to_string()))],
+            )],
+            None,
+            &[&column("name")],
+            &resolve_apples_column,
+            &FunctionRegistry::new(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            program.to_string().lines().collect::<Vec<_>>(),
+            vec![
+                "addr  opcode         p1    p2    p3    p4             p5  comment",
+                "----  -------------  ----  ----  ----  -------------  --  -------------",
+                "0     Column         0     1     0                    0   r[0]=column 1",
+                "1     String8        0     1     0     Red            0   r[1]='Red'",
+                "2     Ne             1     5     0                    0   if r[0]!=r[1] goto P2",
+                "3     Column         0     0     2                    0   r[2]=column 0",
+                "4     ResultRow      2     1     0                    0   output=r[2..3]",
+                "5     Halt           0     0     0                    0",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_run_filters_and_projects() {
+        let program = Program::compile(
+            &[condition(
+                ColumnRef::Index(1),
+                ComparisonOperator::Equals,
+                vec![Expression::Literal(Literal::Text("Red".to_string()))],
+            )],
+            None,
+            &[&column("rowid"), &column("name")],
+            &resolve_apples_column,
+            &FunctionRegistry::new(),
+        )
+        .unwrap();
+        let mut vm = Vm::new(program);
+
+        let fuji = record(
+            2,
+            vec![
+                SerialValue::String("Fuji".to_string()),
+                SerialValue::String("Red".to_string()),
+            ],
+        );
+        assert_eq!(
+            vm.run(&fuji).unwrap(),
+            Some(vec![Value::Integer(2), Value::Text("Fuji".to_string())])
+        );
+
+        let granny_smith = record(
+            1,
+            vec![
+                SerialValue::String("Granny Smith".to_string()),
+                SerialValue::String("Light Green".to_string()),
+            ],
+        );
+        assert_eq!(vm.run(&granny_smith).unwrap(), None);
+    }
+
+    #[test]
+    fn test_run_between_and_in() {
+        let program = Program::compile(
+            &[
+                condition(
+                    ColumnRef::RowId,
+                    ComparisonOperator::Between,
+                    vec![
+                        Expression::Literal(Literal::Text("2".to_string())),
+                        Expression::Literal(Literal::Text("3.5".to_string())),
+                    ],
+                ),
+                condition(
+                    ColumnRef::Index(1),
+                    ComparisonOperator::In,
+                    vec![
+                        Expression::Literal(Literal::Text("Red".to_string())),
+                        Expression::Literal(Literal::Text("Blush Red".to_string())),
+                    ],
+                ),
+            ],
+            None,
+            &[&column("name")],
+            &resolve_apples_column,
+            &FunctionRegistry::new(),
+        )
+        .unwrap();
+        let mut vm = Vm::new(program);
+
+        let apple = |row_id, color: &str| {
+            record(
+                row_id,
+                vec![
+                    SerialValue::String("apple".to_string()),
+                    SerialValue::String(color.to_string()),
+                ],
+            )
+        };
+        let name = Some(vec![Value::Text("apple".to_string())]);
+
+        assert_eq!(vm.run(&apple(2, "Red")).unwrap(), name);
+        assert_eq!(vm.run(&apple(3, "Blush Red")).unwrap(), name);
+        assert_eq!(vm.run(&apple(3, "Yellow")).unwrap(), None);
+        assert_eq!(vm.run(&apple(1, "Red")).unwrap(), None);
+        assert_eq!(vm.run(&apple(4, "Red")).unwrap(), None);
+        assert_eq!(
+            vm.run(&record(2, vec![SerialValue::Null, SerialValue::Null]))
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_run_expressions() {
+        let doubled = Expression::Binary {
+            operator: BinaryOperator::Multiply,
+            lhs: Box::new(column("rowid")),
+            rhs: Box::new(Expression::Literal(Literal::Integer(2))),
+        };
+        let soundex = Expression::FunctionCall {
+            name: "soundex".to_string(),
+            arguments: vec![column("name")],
+        };
+        let negated = Expression::Negate(Box::new(column("color")));
+        let program = Program::compile(
+            &[],
+            None,
+            &[&doubled, &soundex, &negated],
+            &resolve_apples_column,
+            &FunctionRegistry::new(),
+        )
+        .unwrap();
+        let mut vm = Vm::new(program);
+
+        let row = record(
+            21,
+            vec![
+                SerialValue::String("Robert".to_string()),
+                SerialValue::String("4.5 Red".to_string()),
+            ],
+        );
+        assert_eq!(
+            vm.run(&row).unwrap(),
+            Some(vec![
+                Value::Integer(42),
+                Value::Text("R163".to_string()),
+                Value::Real(-4.5),
+            ])
+        );
+
+        let unknown_column = Program::compile(
+            &[],
+            None,
+            &[&column("size")],
+            &resolve_apples_column,
+            &FunctionRegistry::new(),
+        );
+        assert!(unknown_column.is_err());
+    }
+
+    #[test]
+    fn test_binary() {
+        use BinaryOperator::*;
+
+        let text = |s: &str| Value::Text(s.to_string());
+
+        assert_eq!(
+            binary(Add, &Value::Integer(2), &Value::Integer(3), None),
+            Value::Integer(5)
+        );
+        assert_eq!(
+            binary(Add, &Value::Integer(2), &Value::Real(0.5), None),
+            Value::Real(2.5)
+        );
+        assert_eq!(
+            binary(Add, &text("2 apples"), &text("x"), None),
+            Value::Integer(2)
+        );
+        assert_eq!(
+            binary(Divide, &Value::Integer(7), &Value::Integer(2), None),
+            Value::Integer(3)
+        );
+        assert_eq!(
+            binary(Divide, &Value::Integer(7), &Value::Integer(0), None),
+            Value::Null
+        );
+        assert_eq!(
+            binary(Remainder, &Value::Real(7.5), &Value::Integer(2), None),
+            Value::Real(1.0)
+        );
+        assert_eq!(
+            binary(
+                Multiply,
+                &Value::Integer(i64::MAX),
+                &Value::Integer(2),
+                None
+            ),
+            Value::Real(i64::MAX as f64 * 2.0)
+        );
+        assert_eq!(
+            binary(Subtract, &Value::Null, &Value::Integer(1), None),
+            Value::Null
+        );
+        assert_eq!(
+            binary(Concat, &text("a"), &Value::Real(2.0), None),
+            text("a2.0")
+        );
+        assert_eq!(binary(Concat, &text("a"), &Value::Null, None), Value::Null);
+    }
+
+    #[test]
+    fn test_three_valued_logic() {
+        use BinaryOperator::*;
+
+        let (t, f, null) = (Value::Integer(1), Value::Integer(0), Value::Null);
+        // Each operand against TRUE, FALSE and NULL in turn
+        for (operator, lhs, results) in [
+            (And, &t, [&t, &f, &null]),
+            (And, &f, [&f, &f, &f]),
+            (And, &null, [&null, &f, &null]),
+            (Or, &t, [&t, &t, &t]),
+            (Or, &f, [&t, &f, &null]),
+            (Or, &null, [&t, &null, &null]),
+            (Equals, &null, [&null, &null, &null]),
+            (NotEquals, &t, [&f, &t, &null]),
+            (Is, &null, [&f, &f, &t]),
+            (IsNot, &null, [&t, &t, &f]),
+        ] {
+            for (rhs, result) in [&t, &f, &null].into_iter().zip(results) {
+                assert_eq!(
+                    &binary(operator, lhs, rhs, None),
+                    result,
+                    "{lhs} {operator:?} {rhs}"
+                );
+            }
+        }
+
+        assert_eq!(binary(Less, &Value::Integer(1), &Value::Real(1.5), None), t);
+        assert_eq!(
+            binary(GreaterOrEqual, &Value::Integer(1), &null, None),
+            null
+        );
+        // Numbers sort before text
+        assert_eq!(
+            binary(
+                Less,
+                &Value::Integer(9),
+                &Value::Text("1".to_string()),
+                None
+            ),
+            t
+        );
+    }
+
+    #[test]
+    fn test_run_filter() {
+        let red = Expression::Binary {
+            operator: BinaryOperator::Equals,
+            lhs: Box::new(column("color")),
+            rhs: Box::new(Expression::Literal(Literal::Text("Red".to_string()))),
+        };
+        let not_red = Expression::Not(Box::new(red));
+        let program = Program::compile(
+            &[],
+            Some(&not_red),
+            &[&column("name")],
+            &resolve_apples_column,
+            &FunctionRegistry::new(),
+        )
+        .unwrap();
+        let mut vm = Vm::new(program);
+
+        let apple = |color| record(1, vec![SerialValue::String("Fuji".to_string()), color]);
+        assert_eq!(
+            vm.run(&apple(SerialValue::String("Green".to_string())))
+                .unwrap(),
+            Some(vec![Value::Text("Fuji".to_string())])
+        );
+        assert_eq!(
+            vm.run(&apple(SerialValue::String("Red".to_string())))
+                .unwrap(),
+            None
+        );
+        // NOT NULL is NULL, which doesn't pass either
+        assert_eq!(vm.run(&apple(SerialValue::Null)).unwrap(), None);
+    }
+
+    #[test]
+    fn test_run_comparison_collations() {
+        let text = |s: &str| Box::new(Expression::Literal(Literal::Text(s.to_string())));
+        let compare = |operator, lhs, rhs| Expression::Binary { operator, lhs, rhs };
+        let name = || Box::new(column("name"));
+        let color = || Box::new(column("color"));
+        // By the left operand's collation if it's a column, or else the right one's: 'C' sorts
+        // before 'carol' by NOCASE
+        let expressions = [
+            compare(BinaryOperator::Greater, name(), text("b")),
+            compare(BinaryOperator::GreaterOrEqual, text("C"), name()),
+            compare(BinaryOperator::LessOrEqual, color(), text("red")),
+            compare(BinaryOperator::Equals, name(), text("carol")),
+        ];
+        let collations = CollationRegistry::new();
+        let program = Program::compile_with_column_types(
+            &[],
+            None,
+            &expressions.iter().collect::<Vec<_>>(),
+            &resolve_apples_column,
+            &|column| {
+                Ok(match column {
+                    ColumnRef::Index(0) => collations.find("NOCASE").ok(),
+                    ColumnRef::Index(1) => collations.find("RTRIM").ok(),
+                    _ => None,
+                })
+            },
+            &|_| Affinity::Blob,
+            &FunctionRegistry::new(),
+        )
+        .unwrap();
+        assert!(program.to_string().contains("(NOCASE)"), "{program}");
+        let mut vm = Vm::new(program);
+
+        let row = record(
+            1,
+            vec![
+                SerialValue::String("CAROL".to_string()),
+                SerialValue::String("red  ".to_string()),
+            ],
+        );
+        let [t, f] = [Value::Integer(1), Value::Integer(0)];
+        assert_eq!(
+            vm.run(&row).unwrap(),
+            Some(vec![t.clone(), f, t.clone(), t])
+        );
+    }
+
+    #[test]
+    fn test_run_real_affinity() {
+        // As though color were declared REAL, which stores 2.0 as the integer 2
+        let program = Program::compile_with_column_types(
+            &[],
+            None,
+            &[&column("name"), &column("color"), &column("rowid")],
+            &resolve_apples_column,
+            &|_| Ok(None),
+            &|column| match column {
+                ColumnRef::Index(1) => Affinity::Real,
+                _ => Affinity::Integer,
+            },
+            &FunctionRegistry::new(),
+        )
+        .unwrap();
+        assert!(program.to_string().contains("RealAffinity"), "{program}");
+        let mut vm = Vm::new(program);
+
+        let row = record(3, vec![SerialValue::Int8(1), SerialValue::Int8(2)]);
+        assert_eq!(
+            vm.run(&row).unwrap(),
+            Some(vec![Value::Integer(1), Value::Real(2.0), Value::Integer(3)])
+        );
+        let row = record(
+            4,
+            vec![SerialValue::One, SerialValue::String("x".to_string())],
+        );
+        assert_eq!(
+            vm.run(&row).unwrap(),
+            Some(vec![
+                Value::Integer(1),
+                Value::Text("x".to_string()),
+                Value::Integer(4)
+            ])
+        );
+    }
+
+    #[cfg(feature = "regexp")]
+    #[test]
+    fn test_run_regexp() {
+        let program = Program::compile(
+            &[condition(
+                ColumnRef::Index(0),
+                ComparisonOperator::Regexp,
+                vec![Expression::Literal(Literal::Text("^[0-9]+$".to_string()))],
+            )],
+            None,
+            &[],
+            &resolve_apples_column,
+            &FunctionRegistry::new(),
+        )
+        .unwrap();
+        let mut vm = Vm::new(program);
+
+        assert_eq!(
+            vm.run(&record(1, vec![SerialValue::Int8(42)])).unwrap(),
+            Some(vec![])
+        );
+        assert_eq!(
+            vm.run(&record(2, vec![SerialValue::String("4x2".to_string())]))
+                .unwrap(),
+            None
+        );
+        assert_eq!(vm.run(&record(3, vec![SerialValue::Null])).unwrap(), None);
+    }
+
+    #[test]
+    fn test_run_affinity() {
+        let equals = |affinity, literal| Condition {
+            column: ColumnRef::Index(0),
+            operator: ComparisonOperator::Equals,
+            values: vec![Expression::Literal(literal)],
+            affinity,
+            collation: None,
+        };
+        let matches = |condition: Condition, value: SerialValue| {
+            let program = Program::compile(
+                &[condition],
+                None,
+                &[],
+                &resolve_apples_column,
+                &FunctionRegistry::new(),
+            )
+            .unwrap();
+            Vm::new(program)
+                .run(&record(1, vec![value]))
+                .unwrap()
+                .is_some()
+        };
+        let text = |text: &str| Literal::Text(text.to_string());
+
+        assert!(matches(
+            equals(Affinity::Integer, text("42")),
+            SerialValue::Int8(42)
+        ));
+        assert!(matches(
+            equals(Affinity::Numeric, text("4.2e1")),
+            SerialValue::Int8(42)
+        ));
+        assert!(matches(
+            equals(Affinity::Text, Literal::Integer(42)),
+            SerialValue::String("42".to_string())
+        ));
+        // A column without a type converts nothing
+        assert!(!matches(
+            equals(Affinity::Blob, text("42")),
+            SerialValue::Int8(42)
+        ));
+        assert!(!matches(
+            equals(Affinity::Integer, text("42 apples")),
+            SerialValue::Int8(42)
+        ));
+    }
+
+    #[test]
+    fn test_values_equal() {
+        let text = |text: &str| Value::Text(text.to_string());
+        assert!(values_equal(&text("Pink Eyes"), &text("Pink Eyes"), None));
+        assert!(!values_equal(&text("Pink Eyes"), &text("pink eyes"), None));
+        assert!(values_equal(&Value::Integer(3), &Value::Real(3.0), None));
+        // Text and numbers are only equal once an Affinity opcode has converted one of them
+        assert!(!values_equal(&Value::Integer(42), &text("42"), None));
+        assert!(!values_equal(
+            &Value::Blob(b"ab".to_vec()),
+            &text("ab"),
+            None
+        ));
+        assert!(!values_equal(&Value::Null, &text(""), None));
+        assert!(!values_equal(&Value::Null, &Value::Null, None));
+
+        let nocase = CollationRegistry::new().find("NOCASE").unwrap();
+        assert!(values_equal(
+            &text("Pink Eyes"),
+            &text("pink eyes"),
+            Some(&nocase)
+        ));
+        assert!(!values_equal(
+            &Value::Integer(42),
+            &text("42"),
+            Some(&nocase)
+        ));
+    }
+}
@@ -0,0 +1,62 @@
+//! ANALYZE, and the statistics it leaves for the planner
+
+// These run the shell, which needs the file system
+#![cfg(feature = "fs")]
+
+mod fixtures;
+
+use fixtures::{with_apples, DatabaseBuilder, Fixture};
+
+#[test]
+fn test_analyze() {
+    let bytes = with_apples(DatabaseBuilder::new(4096), 1000).build();
+    let fixture = Fixture::new("analyze", &bytes);
+    let plan = |sql: &str| fixture.run(&format!("EXPLAIN QUERY PLAN {sql}")).unwrap();
+    let red_names = "SELECT name FROM apples WHERE color = 'Red'";
+
+    // Without statistics, each key of an index is guessed to match 10 rows
+    let red_plan = plan(red_names);
+    assert!(
+        red_plan.contains("SEARCH apples USING INDEX idx_apples_color (color=?)")
+            && red_plan.ends_with(", ~10 rows)\n"),
+        "{red_plan}"
+    );
+
+    assert_eq!(fixture.run("ANALYZE"), Ok(String::new()));
+    assert_eq!(
+        fixture
+            .run("SELECT tbl, idx, stat FROM sqlite_stat1")
+            .unwrap(),
+        "apples|idx_apples_color|1000 250\n"
+    );
+    // A quarter of the rows are too many to look up one at a time
+    let red_plan = plan(red_names);
+    assert!(
+        red_plan.contains("SCAN apples") && red_plan.ends_with(", ~1000 rows)\n"),
+        "{red_plan}"
+    );
+
+    // Analyzing an index leaves the other rows as they were
+    fixture
+        .run("CREATE UNIQUE INDEX idx_apples_name ON apples (name)")
+        .unwrap();
+    assert_eq!(fixture.run("ANALYZE idx_apples_name"), Ok(String::new()));
+    assert_eq!(
+        fixture
+            .run("SELECT rowid, tbl, idx, stat FROM sqlite_stat1")
+            .unwrap(),
+        "1|apples|idx_apples_color|1000 250\n2|apples|idx_apples_name|1000 1\n"
+    );
+    let name_plan = plan("SELECT color FROM apples WHERE name = 'Apple 00042'");
+    assert!(
+        name_plan.contains("SEARCH apples USING INDEX idx_apples_name (name=?)")
+            && name_plan.ends_with(", ~1 rows)\n"),
+        "{name_plan}"
+    );
+
+    assert!(fixture
+        .run("ANALYZE pears")
+        .unwrap_err()
+        .contains("no such table: pears"));
+    assert_eq!(fixture.run(".check").unwrap(), "ok\n");
+}
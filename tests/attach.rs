@@ -0,0 +1,70 @@
+//! ATTACH and DETACH, and queries that name a schema
+
+// These run the shell, which needs the file system
+#![cfg(feature = "fs")]
+
+mod fixtures;
+
+use fixtures::{with_apples, DatabaseBuilder, Fixture};
+
+#[test]
+fn test_attach() {
+    let main = with_apples(DatabaseBuilder::new(4096), 2)
+        .table("pears", "CREATE TABLE pears (name)", vec![])
+        .build();
+    let other = with_apples(DatabaseBuilder::new(1024), 3)
+        .table("plums", "CREATE TABLE plums (name)", vec![])
+        .build();
+    let main = Fixture::new("attach-main", &main);
+    let other = Fixture::new("attach-other", &other);
+    let attach = format!("ATTACH '{}' AS aux", other.path.display());
+
+    // A table that isn't named with its database is the first that has it, main before aux
+    let output = main
+        .run(&format!(
+            "{attach}; SELECT count(*) FROM aux.apples; SELECT count(*) FROM main.apples; \
+             SELECT count(*) FROM apples; SELECT count(*) FROM plums; \
+             SELECT name FROM aux.apples WHERE color IN (SELECT color FROM aux.apples WHERE id = 1)"
+        ))
+        .unwrap();
+    assert_eq!(output, "3\n2\n2\n0\nApple 00001\n");
+
+    let err = main
+        .run(&format!(
+            "{attach}; SELECT name FROM aux.apples UNION SELECT name FROM pears"
+        ))
+        .unwrap_err();
+    assert!(
+        err.contains("more than one database: aux and main"),
+        "{err}"
+    );
+    let err = main.run(&format!("{attach}; {attach}")).unwrap_err();
+    assert!(err.contains("database aux is already in use"), "{err}");
+    let err = main
+        .run(&format!(
+            "{attach}; DETACH aux; SELECT name FROM aux.apples"
+        ))
+        .unwrap_err();
+    assert!(err.contains("no such table: aux.apples"), "{err}");
+
+    // Or attached from the command line
+    let attach = format!("--attach=aux={}", other.path.display());
+    let output = main
+        .run_args(&[&attach, "SELECT name FROM aux.apples WHERE id = 3"])
+        .unwrap();
+    assert_eq!(output, "Apple 00003\n");
+    let output = main.run_args(&[&attach, ".databases"]).unwrap();
+    assert_eq!(
+        output,
+        format!(
+            "main: {}\naux: {}\n",
+            main.path.display(),
+            other.path.display()
+        )
+    );
+    let output = main.run_args(&[&attach, ".tables"]).unwrap();
+    assert!(
+        output.contains("aux.apples") && output.contains("aux.plums"),
+        "{output}"
+    );
+}
@@ -0,0 +1,52 @@
+//! `.backup` and `.restore`, and the online backup they use
+
+// These run the shell, which needs the file system
+#![cfg(feature = "fs")]
+
+mod fixtures;
+
+use fixtures::{query, with_apples, DatabaseBuilder, Fixture};
+use sqlite_starter_rust::{database::Database, interrupt::InterruptedError, value::Value};
+use std::fs;
+
+#[test]
+fn test_backup() {
+    let bytes = with_apples(DatabaseBuilder::new(1024), 3000).build();
+    let database = Database::open_bytes(bytes.clone()).unwrap();
+    let page_count = database.page_count;
+    assert!(page_count > 100, "{page_count} pages is one step");
+
+    let mut copy = vec![];
+    let mut steps = vec![];
+    let mut progress = |copied, total| {
+        steps.push((copied, total));
+        false
+    };
+    database.backup_to(&mut copy, Some(&mut progress)).unwrap();
+    assert_eq!(copy, bytes);
+    assert_eq!(steps[0], (100, page_count));
+    assert_eq!(steps.last(), Some(&(page_count, page_count)));
+
+    // Stopped part way, by the callback
+    let mut stop = |copied, _| copied >= 100;
+    let err = database
+        .backup_to(&mut vec![], Some(&mut stop))
+        .unwrap_err();
+    assert!(err.is::<InterruptedError>(), "{err}");
+
+    // The source stays open, and usable, throughout
+    let fixture = Fixture::new("backup", &bytes);
+    let source = Database::open_filename(fixture.path.to_str().unwrap()).unwrap();
+    let backup = Fixture::new("backup-copy", b"stale");
+    source.backup_to_file(&backup.path, None).unwrap();
+    assert_eq!(fs::read(&backup.path).unwrap(), bytes);
+    assert_eq!(
+        query(&source, "SELECT count(*) FROM apples").unwrap(),
+        [[Value::Integer(3000)]]
+    );
+    assert!(source
+        .backup_to_file(&fixture.path, None)
+        .unwrap_err()
+        .to_string()
+        .contains("can't back up a database onto itself"));
+}
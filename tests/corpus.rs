@@ -0,0 +1,235 @@
+//! Every command run against databases with the unusual layouts real ones turn up with, built by
+//! the fixtures module. Each test checks for the results sqlite3 gives, and one for a layout the
+//! reader doesn't handle yet is marked #[ignore] with the reason.
+
+// These run the shell, which needs the file system
+#![cfg(feature = "fs")]
+
+mod fixtures;
+
+use fixtures::{check_apples, query, with_apples, DatabaseBuilder, Encoding, Fixture};
+use sqlite_starter_rust::{database::Database, value::Value};
+
+#[test]
+fn test_zero_tables() {
+    let bytes = DatabaseBuilder::new(4096).build();
+    let database = Database::open_bytes(bytes.clone()).unwrap();
+    assert!(database.schema().unwrap().is_empty());
+    let err = query(&database, "SELECT count(*) FROM apples").unwrap_err();
+    assert!(err.to_string().contains("no such table"), "{err}");
+
+    let fixture = Fixture::new("zero-tables", &bytes);
+    assert_eq!(
+        fixture.run(".dbinfo").unwrap(),
+        "database page size: 4096\nnumber of tables: 0\n"
+    );
+    assert_eq!(fixture.run(".tables").unwrap(), "");
+    assert_eq!(fixture.run(".pages").unwrap(), "1: LeafTable (0 cells)\n");
+    assert!(fixture
+        .run("SELECT count(*) FROM apples")
+        .unwrap_err()
+        .contains("no such table: apples"));
+}
+
+#[test]
+fn test_page_size_65536() {
+    let bytes = with_apples(DatabaseBuilder::new(65536), 2000).build();
+    let database = check_apples("page-size-65536", &bytes, 2000, 65536);
+    assert_eq!(database.page_count, 3);
+}
+
+#[test]
+fn test_deep_trees() {
+    let bytes = with_apples(DatabaseBuilder::new(512), 20_000).build();
+    let database = check_apples("deep-trees", &bytes, 20_000, 512);
+    let schema = database.schema().unwrap();
+    assert_eq!(database.btree_depth(schema[0].root_page).unwrap(), 3);
+    assert_eq!(database.btree_depth(schema[1].root_page).unwrap(), 4);
+}
+
+#[test]
+fn test_auto_vacuum() {
+    // Page 2 is a pointer map, which isn't a b-tree page
+    let bytes = with_apples(DatabaseBuilder::new(1024).auto_vacuum(), 500).build();
+    let database = check_apples("auto-vacuum", &bytes, 500, 1024);
+    let schema = database.schema().unwrap();
+    assert_eq!((schema[0].root_page, schema[1].root_page), (3, 4));
+}
+
+#[test]
+#[ignore = "WITHOUT ROWID tables, which are index b-trees, aren't read as tables yet"]
+fn test_without_rowid() {
+    let rows = (0..2000)
+        .map(|i| {
+            vec![
+                Value::Text(format!("key {:04}", i * 7919 % 2000)),
+                Value::Integer(i),
+            ]
+        })
+        .collect();
+    let bytes = DatabaseBuilder::new(4096)
+        .table(
+            "kv",
+            "CREATE TABLE kv (k TEXT PRIMARY KEY, v INTEGER) WITHOUT ROWID",
+            rows,
+        )
+        .build();
+    let database = Database::open_bytes(bytes.clone()).unwrap();
+    let schema = database.schema().unwrap();
+    assert_eq!(
+        database.btree_size(schema[0].root_page).unwrap().entries,
+        2000
+    );
+    assert_eq!(
+        query(&database, "SELECT count(*) FROM kv").unwrap(),
+        [[Value::Integer(2000)]]
+    );
+
+    let fixture = Fixture::new("without-rowid", &bytes);
+    assert_eq!(fixture.run(".tables").unwrap(), "kv\n");
+    let v = (0..2000).find(|i| i * 7919 % 2000 == 42).unwrap();
+    assert_eq!(
+        fixture
+            .run("SELECT v FROM kv WHERE k = 'key 0042'")
+            .unwrap(),
+        format!("{v}\n")
+    );
+    assert_eq!(
+        fixture.run("SELECT k FROM kv LIMIT 2").unwrap(),
+        "key 0000\nkey 0001\n"
+    );
+}
+
+#[test]
+fn test_overflow() {
+    let rows = (1..=50)
+        .map(|i| vec![Value::Null, Value::Text("x".repeat(i * 300))])
+        .collect();
+    let bytes = DatabaseBuilder::new(1024)
+        .table(
+            "docs",
+            "CREATE TABLE docs (id INTEGER PRIMARY KEY, body TEXT)",
+            rows,
+        )
+        .build();
+    let database = Database::open_bytes(bytes.clone()).unwrap();
+    let root = database.schema().unwrap()[0].root_page;
+    assert_eq!(database.btree_size(root).unwrap().entries, 50);
+
+    // Overflow chains are followed when exporting, and when reading rows
+    let mut exported = vec![];
+    let page_count = database.write_subtree("docs", &mut exported).unwrap();
+    assert_eq!(page_count, database.page_count);
+    assert_eq!(
+        query(&database, "SELECT count(*) FROM docs").unwrap(),
+        [[Value::Integer(50)]]
+    );
+    let lengths = (1..=50)
+        .map(|i| vec![Value::Integer(i), Value::Integer(i * 300)])
+        .collect::<Vec<_>>();
+    assert_eq!(
+        query(&database, "SELECT id, length(body) FROM docs").unwrap(),
+        lengths
+    );
+
+    // Every page is either a b-tree page or an overflow page of one of the two b-trees
+    let schema_stats = database.btree_stats(1).unwrap();
+    let stats = database.btree_stats(root).unwrap();
+    assert_eq!(stats.pages, database.btree_size(root).unwrap().pages);
+    assert_eq!(
+        schema_stats.pages + stats.pages + stats.overflow_pages,
+        database.page_count
+    );
+    assert!(stats.payload_bytes > (1..=50).map(|i| i * 300).sum::<u64>());
+    assert_eq!(schema_stats.overflow_pages, 0);
+
+    let fixture = Fixture::new("overflow", &bytes);
+    assert_eq!(fixture.run(".tables").unwrap(), "docs\n");
+    let output = fixture.run(".stats 'd%'").unwrap();
+    assert_eq!(
+        output,
+        format!(
+            "docs|{}|{}|{}|{}|{}\n",
+            stats.pages, stats.cells, stats.payload_bytes, stats.unused_bytes, stats.overflow_pages
+        )
+    );
+    assert_eq!(
+        fixture
+            .run("SELECT body = printf('%.*c', 12000, 'x') FROM docs WHERE id = 40")
+            .unwrap(),
+        "1\n"
+    );
+}
+
+#[test]
+#[ignore = "reserved bytes at the end of each page aren't handled yet"]
+fn test_reserved_bytes() {
+    let bytes = with_apples(DatabaseBuilder::new(4096).reserved(32), 500).build();
+    check_apples("reserved-bytes", &bytes, 500, 4096);
+}
+
+#[test]
+#[ignore = "UTF-16 text encodings aren't handled yet"]
+fn test_utf16() {
+    for (name, encoding) in [
+        ("utf-16le", Encoding::Utf16le),
+        ("utf-16be", Encoding::Utf16be),
+    ] {
+        let bytes = with_apples(DatabaseBuilder::new(4096).encoding(encoding), 500).build();
+        check_apples(name, &bytes, 500, 4096);
+    }
+}
+
+#[test]
+fn test_short_records() {
+    // The first row is from before legs was added with ALTER TABLE ADD COLUMN
+    let rows = vec![
+        vec![Value::Null, Value::Text("cat".to_string())],
+        vec![
+            Value::Null,
+            Value::Text("bird".to_string()),
+            Value::Integer(2),
+        ],
+    ];
+    let bytes = DatabaseBuilder::new(4096)
+        .table(
+            "pets",
+            "CREATE TABLE pets (id INTEGER PRIMARY KEY, name TEXT, sound AS (name || '!'), \
+             legs INTEGER DEFAULT '4')",
+            rows,
+        )
+        .build();
+    let fixture = Fixture::new("short-records", &bytes);
+
+    assert_eq!(
+        fixture.run("SELECT name, sound, legs FROM pets").unwrap(),
+        "cat|cat!|4\nbird|bird!|2\n"
+    );
+    assert!(fixture
+        .run(".dump")
+        .unwrap()
+        .contains("INSERT INTO pets VALUES(1,'cat',4);\nINSERT INTO pets VALUES(2,'bird',2);\n"));
+
+    // The index has the default for the short row
+    fixture
+        .run("CREATE INDEX idx_pets_legs ON pets (legs)")
+        .unwrap();
+    assert_eq!(
+        fixture.run("SELECT id FROM pets WHERE legs = 4").unwrap(),
+        "1\n"
+    );
+    assert_eq!(fixture.run(".check").unwrap(), "ok\n");
+}
+
+#[test]
+fn test_check_corrupt_freelist() {
+    let mut bytes = with_apples(DatabaseBuilder::new(4096), 3).build();
+    // The header claims a free page that isn't on the freelist
+    bytes[36..40].copy_from_slice(&1u32.to_be_bytes());
+    let fixture = Fixture::new("corrupt-freelist", &bytes);
+
+    assert_eq!(
+        fixture.run(".check").unwrap(),
+        "Freelist: size is 0 but should be 1\n"
+    );
+}
@@ -0,0 +1,99 @@
+//! CREATE INDEX on a database file, and queries that use the index it builds
+
+// These run the shell, which needs the file system
+#![cfg(feature = "fs")]
+
+mod fixtures;
+
+use fixtures::{query, DatabaseBuilder, Fixture};
+use sqlite_starter_rust::{database::Database, value::Value};
+
+#[test]
+fn test_create_index() {
+    // Keys this long only fit a few to a page, so the index is several levels deep
+    let body = |i: i64| format!("{:0>200}", i * 7919 % 3000);
+    let rows = (1..=3000)
+        .map(|i| vec![Value::Null, Value::Text(body(i))])
+        .collect();
+    let bytes = DatabaseBuilder::new(1024)
+        .table(
+            "docs",
+            "CREATE TABLE docs (id INTEGER PRIMARY KEY, body TEXT)",
+            rows,
+        )
+        .build();
+    let fixture = Fixture::new("create-index", &bytes);
+
+    let sql = "CREATE INDEX idx_docs_body ON docs (body)";
+    assert_eq!(fixture.run(&format!("{sql};")).unwrap(), "");
+    assert!(fixture
+        .run(sql)
+        .unwrap_err()
+        .contains("index idx_docs_body already exists"));
+    assert!(fixture
+        .run("CREATE UNIQUE INDEX idx_docs_id_body ON docs (body, id)")
+        .is_ok());
+    assert!(fixture
+        .run("CREATE UNIQUE INDEX idx_docs_first ON docs (substr(body, 1, 1))")
+        .unwrap_err()
+        .contains("only plain column names, in ascending order, can be indexed"));
+    assert!(fixture
+        .run("CREATE INDEX idx_docs_body_desc ON docs (body DESC)")
+        .unwrap_err()
+        .contains("only plain column names, in ascending order, can be indexed"));
+
+    let database = Database::open_filename(fixture.path.to_str().unwrap()).unwrap();
+    let schema = database.schema().unwrap();
+    let names: Vec<_> = schema.iter().map(|s| s.name.as_str()).collect();
+    assert_eq!(names, ["docs", "idx_docs_body", "idx_docs_id_body"]);
+    let index = &schema[1];
+    assert_eq!(index.sql.as_deref(), Some(sql));
+    assert_eq!(database.btree_size(index.root_page).unwrap().entries, 3000);
+    assert!(database.btree_depth(index.root_page).unwrap() >= 3);
+
+    let id = (1..=3000).find(|&i| body(i) == body(42)).unwrap();
+    assert_eq!(
+        database
+            .search_index(index.root_page, &Value::Text(body(42)))
+            .unwrap(),
+        [id]
+    );
+    assert!(fixture
+        .run(&format!(
+            "EXPLAIN QUERY PLAN SELECT id FROM docs WHERE body = '{}'",
+            body(42)
+        ))
+        .unwrap()
+        .contains("USING COVERING INDEX idx_docs_body"));
+    assert_eq!(
+        query(
+            &database,
+            &format!("SELECT id FROM docs WHERE body = '{}'", body(42))
+        )
+        .unwrap(),
+        [[Value::Integer(id)]]
+    );
+
+    // Keys too long for an index page spill to overflow pages, and are read back from them
+    let long_rows = (1..=10)
+        .map(|i| vec![Value::Null, Value::Text(format!("{i:0>300}"))])
+        .collect();
+    let bytes = DatabaseBuilder::new(1024)
+        .table(
+            "notes",
+            "CREATE TABLE notes (id INTEGER PRIMARY KEY, body TEXT)",
+            long_rows,
+        )
+        .build();
+    let fixture = Fixture::new("create-index-overflow", &bytes);
+    fixture
+        .run("CREATE INDEX idx_notes_body ON notes (body)")
+        .unwrap();
+    let sql = format!("SELECT id FROM notes WHERE body = '{:0>300}'", 7);
+    assert!(fixture
+        .run(&format!("EXPLAIN QUERY PLAN {sql}"))
+        .unwrap()
+        .contains("USING COVERING INDEX idx_notes_body"));
+    assert_eq!(fixture.run(&sql).unwrap(), "7\n");
+    assert_eq!(fixture.run(".check").unwrap(), "ok\n");
+}
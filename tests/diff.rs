@@ -0,0 +1,50 @@
+//! The diff subcommand, which compares the rows of two databases
+
+// These run the shell, which needs the file system
+#![cfg(feature = "fs")]
+
+mod fixtures;
+
+use fixtures::{apples, with_apples, DatabaseBuilder, Fixture, APPLES_SQL};
+use sqlite_starter_rust::value::Value;
+
+#[test]
+fn test_diff() {
+    let pears_sql = "CREATE TABLE pears (name)";
+    let pears = |names: &[i64]| {
+        names
+            .iter()
+            .map(|&name| vec![Value::Integer(name)])
+            .collect()
+    };
+    let from = with_apples(DatabaseBuilder::new(4096), 5)
+        .table("pears", pears_sql, pears(&[1]))
+        .table("plums", "CREATE TABLE plums (name)", vec![])
+        .build();
+    // Rowids are given out in order, so the last apple is the one to delete
+    let mut changed = apples(4);
+    changed[1][1] = Value::Text("Apple 'two'".to_string());
+    let to = DatabaseBuilder::new(4096)
+        .table("apples", APPLES_SQL, changed)
+        .table("pears", pears_sql, pears(&[1, 3]))
+        .table("Figs", "CREATE TABLE \"Figs\" (name)", pears(&[7]))
+        .build();
+    let from = Fixture::new("diff-from", &from);
+    let to = Fixture::new("diff-to", &to);
+
+    let diff = from.run(&format!(".diff {}", to.path.display())).unwrap();
+    assert_eq!(
+        diff,
+        "UPDATE apples SET name='Apple ''two''' WHERE id=2;\n\
+         DELETE FROM apples WHERE id=5;\n\
+         CREATE TABLE \"Figs\" (name);\n\
+         INSERT INTO Figs(rowid,name) VALUES(1,7);\n\
+         INSERT INTO pears(rowid,name) VALUES(2,3);\n\
+         DROP TABLE plums;\n"
+    );
+    assert_eq!(
+        from.run(&format!(".diff {}", from.path.display())).unwrap(),
+        ""
+    );
+    assert!(from.run(".diff").unwrap_err().contains("Usage: .diff FILE"));
+}
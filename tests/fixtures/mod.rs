@@ -0,0 +1,783 @@
+//! Databases built byte by byte for the integration tests, covering the parts of the file format
+//! that databases in the wild use and a quick sample wouldn't: page sizes at either end of the
+//! range, reserved bytes, UTF-16 text, WITHOUT ROWID tables, overflow pages, deep b-trees and
+//! auto_vacuum.
+//!
+//! Pages are laid out the way SQLite lays them out, so each fixture passes sqlite3's
+//! `PRAGMA integrity_check`. The apples database most tests start from, and a way to run the
+//! shell on a database file, are here too.
+
+// Each test file uses some of these
+#![allow(dead_code)]
+
+use anyhow::{anyhow, Result};
+use sqlite_starter_rust::{
+    database::Database, executor::execute, header::BTreePage, overflow::thresholds,
+    planner::plan_query, query_parser::parse_query, value::Value, varint::encode_varint,
+};
+use std::{env, fs, path::PathBuf, process::Command};
+
+/// The text encodings a database header can name
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Utf8 = 1,
+    Utf16le = 2,
+    Utf16be = 3,
+}
+
+/// A table, stored as rows keyed by rowid, or by its first column if it's WITHOUT ROWID
+pub struct Table {
+    pub name: String,
+    pub sql: String,
+    pub without_rowid: bool,
+    pub rows: Vec<Vec<Value>>,
+    pub indexes: Vec<Index>,
+}
+
+/// An index on one column of its table
+pub struct Index {
+    pub name: String,
+    pub sql: String,
+    pub column: usize,
+}
+
+/// Describes a database, which build turns into the bytes of its file
+pub struct DatabaseBuilder {
+    page_size: usize,
+    reserved: usize,
+    encoding: Encoding,
+    auto_vacuum: bool,
+    tables: Vec<Table>,
+}
+
+impl DatabaseBuilder {
+    pub fn new(page_size: usize) -> Self {
+        DatabaseBuilder {
+            page_size,
+            reserved: 0,
+            encoding: Encoding::Utf8,
+            auto_vacuum: false,
+            tables: vec![],
+        }
+    }
+
+    /// Leaves `reserved` bytes unused at the end of every page, as extensions like checksums and
+    /// encryption do
+    pub fn reserved(mut self, reserved: usize) -> Self {
+        self.reserved = reserved;
+        self
+    }
+
+    pub fn encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Adds pointer map pages, as PRAGMA auto_vacuum = FULL does
+    pub fn auto_vacuum(mut self) -> Self {
+        self.auto_vacuum = true;
+        self
+    }
+
+    /// Adds a table whose rows get rowids from 1. An INTEGER PRIMARY KEY column's value is the
+    /// rowid, so it should be Null in `rows`.
+    pub fn table(mut self, name: &str, sql: &str, rows: Vec<Vec<Value>>) -> Self {
+        self.tables.push(Table {
+            name: name.to_string(),
+            sql: sql.to_string(),
+            without_rowid: sql.to_uppercase().contains("WITHOUT ROWID"),
+            rows,
+            indexes: vec![],
+        });
+        self
+    }
+
+    /// Adds an index on `column` of the table added last
+    pub fn index(mut self, name: &str, sql: &str, column: usize) -> Self {
+        let table = self.tables.last_mut().expect("an index needs a table");
+        table.indexes.push(Index {
+            name: name.to_string(),
+            sql: sql.to_string(),
+            column,
+        });
+        self
+    }
+
+    pub fn build(&self) -> Vec<u8> {
+        let mut pager = Pager {
+            page_size: self.page_size,
+            usable_size: self.page_size - self.reserved,
+            pages: vec![vec![0; self.page_size]],
+            ptrmap: vec![],
+        };
+        if self.auto_vacuum {
+            pager.allocate();
+        }
+
+        // Like SQLite with auto_vacuum on, every root page comes before the rest of the b-trees
+        let mut trees = vec![];
+        let mut schema_rows = vec![];
+        for table in &self.tables {
+            let root = pager.allocate();
+            pager.set_ptrmap(root, 1, 0);
+            schema_rows.push(vec![
+                Value::Text("table".into()),
+                Value::Text(table.name.clone()),
+                Value::Text(table.name.clone()),
+                Value::Integer(root as i64),
+                Value::Text(table.sql.clone()),
+            ]);
+            trees.push((root, self.table_entries(table), !table.without_rowid));
+
+            for index in &table.indexes {
+                let root = pager.allocate();
+                pager.set_ptrmap(root, 1, 0);
+                schema_rows.push(vec![
+                    Value::Text("index".into()),
+                    Value::Text(index.name.clone()),
+                    Value::Text(table.name.clone()),
+                    Value::Integer(root as i64),
+                    Value::Text(index.sql.clone()),
+                ]);
+                trees.push((root, self.index_entries(table, index), false));
+            }
+        }
+        let largest_root = pager.pages.len() as u32;
+
+        for (root, entries, is_table) in trees {
+            pager.write_tree(root, entries, is_table);
+        }
+        let schema_entries = schema_rows
+            .iter()
+            .zip(1..)
+            .map(|(row, row_id)| Entry {
+                key: Some(row_id),
+                payload: encode_record(row, self.encoding),
+            })
+            .collect();
+        pager.write_tree(1, schema_entries, true);
+
+        let page_count = pager.pages.len() as u32;
+        if self.auto_vacuum {
+            assert!(
+                pager.ptrmap.len() <= pager.usable_size / 5,
+                "the pointer map doesn't fit on one page"
+            );
+            for &(page, page_type, parent) in &pager.ptrmap {
+                let offset = (page as usize - 3) * 5;
+                pager.pages[1][offset] = page_type;
+                pager.pages[1][offset + 1..offset + 5].copy_from_slice(&parent.to_be_bytes());
+            }
+        }
+
+        let header = &mut pager.pages[0];
+        header[0..16].copy_from_slice(b"SQLite format 3\0");
+        // 65536 doesn't fit in two bytes, so it's stored as 1
+        let page_size_field = if self.page_size == 65536 {
+            1
+        } else {
+            self.page_size as u16
+        };
+        header[16..18].copy_from_slice(&page_size_field.to_be_bytes());
+        header[18..24].copy_from_slice(&[1, 1, self.reserved as u8, 64, 32, 32]);
+        // The change counter, page count, schema cookie and schema format
+        header[24..28].copy_from_slice(&1u32.to_be_bytes());
+        header[28..32].copy_from_slice(&page_count.to_be_bytes());
+        header[40..44].copy_from_slice(&1u32.to_be_bytes());
+        header[44..48].copy_from_slice(&4u32.to_be_bytes());
+        if self.auto_vacuum {
+            header[52..56].copy_from_slice(&largest_root.to_be_bytes());
+        }
+        header[56..60].copy_from_slice(&(self.encoding as u32).to_be_bytes());
+        // The change counter the page count is valid for, and the version of SQLite 3.40.1
+        header[92..96].copy_from_slice(&1u32.to_be_bytes());
+        header[96..100].copy_from_slice(&3_040_001u32.to_be_bytes());
+
+        pager.pages.concat()
+    }
+
+    /// A table's rows in rowid order, or in key order without rowids
+    fn table_entries(&self, table: &Table) -> Vec<Entry> {
+        if table.without_rowid {
+            let mut rows: Vec<_> = table.rows.iter().collect();
+            rows.sort_by(|a, b| a[0].compare(&b[0]));
+            return rows
+                .into_iter()
+                .map(|row| Entry {
+                    key: None,
+                    payload: encode_record(row, self.encoding),
+                })
+                .collect();
+        }
+
+        table
+            .rows
+            .iter()
+            .zip(1..)
+            .map(|(row, row_id)| Entry {
+                key: Some(row_id),
+                payload: encode_record(row, self.encoding),
+            })
+            .collect()
+    }
+
+    /// An index's entries, each the indexed value then the rowid, in order
+    fn index_entries(&self, table: &Table, index: &Index) -> Vec<Entry> {
+        let mut keys: Vec<(Value, i64)> = table
+            .rows
+            .iter()
+            .zip(1..)
+            .map(|(row, row_id)| {
+                let value = row[index.column].clone();
+                // An INTEGER PRIMARY KEY column is stored as Null, but indexed as the rowid
+                match value {
+                    Value::Null if index.column == 0 => (Value::Integer(row_id), row_id),
+                    value => (value, row_id),
+                }
+            })
+            .collect();
+        keys.sort_by(|(a, a_id), (b, b_id)| a.compare(b).then(a_id.cmp(b_id)));
+
+        keys.into_iter()
+            .map(|(value, row_id)| Entry {
+                key: None,
+                payload: encode_record(&[value, Value::Integer(row_id)], self.encoding),
+            })
+            .collect()
+    }
+}
+
+/// A b-tree entry: a row with its rowid, or an index (or WITHOUT ROWID) record, which is its own
+/// key
+struct Entry {
+    key: Option<i64>,
+    payload: Vec<u8>,
+}
+
+/// A b-tree page before it has a page number
+enum Node {
+    Leaf(Vec<Entry>),
+    /// Children, with the entry (or, in a table, the rowid) that separates each from the next
+    Interior(Vec<Node>, Vec<Entry>),
+}
+
+struct Pager {
+    page_size: usize,
+    usable_size: usize,
+    pages: Vec<Vec<u8>>,
+    /// The type and parent of each page from page 3 on, for auto_vacuum's pointer map
+    ptrmap: Vec<(u32, u8, u32)>,
+}
+
+impl Pager {
+    fn allocate(&mut self) -> u32 {
+        self.pages.push(vec![0; self.page_size]);
+        self.pages.len() as u32
+    }
+
+    fn set_ptrmap(&mut self, page: u32, page_type: u8, parent: u32) {
+        if page >= 3 {
+            self.ptrmap.push((page, page_type, parent));
+        }
+    }
+
+    /// Packs `entries`, which are in order, into a b-tree whose root is page `root`
+    fn write_tree(&mut self, root: u32, entries: Vec<Entry>, is_table: bool) {
+        let mut level = self.pack_leaves(entries, is_table);
+        while level.len() > 1 {
+            level = self.pack_interior(level, is_table);
+        }
+        let (node, _) = level.pop().unwrap();
+        self.write_node(root, node, is_table);
+    }
+
+    /// The space a cell takes up on a page, including its pointer
+    fn cell_size(&self, entry: &Entry, page_type: BTreePage) -> usize {
+        let child_pointer = match page_type {
+            BTreePage::InteriorIndex | BTreePage::InteriorTable => 4,
+            _ => 0,
+        };
+        if page_type == BTreePage::InteriorTable {
+            return 2 + child_pointer + encode_varint(entry.key.unwrap()).len();
+        }
+
+        let split = thresholds(
+            self.usable_size as u32,
+            entry.payload.len() as u64,
+            page_type,
+        )
+        .unwrap();
+        let overflow_pointer = if split.overflow > 0 { 4 } else { 0 };
+        let row_id = entry.key.map_or(0, |key| encode_varint(key).len());
+        2 + child_pointer
+            + encode_varint(entry.payload.len() as i64).len()
+            + row_id
+            + split.local
+            + overflow_pointer
+    }
+
+    /// Fills leaves with as many entries as fit, returning each with the entry that separates it
+    /// from the next: its last rowid in a table, or the entry after it in an index, which then
+    /// isn't in a leaf
+    fn pack_leaves(&self, entries: Vec<Entry>, is_table: bool) -> Vec<(Node, Option<Entry>)> {
+        let page_type = if is_table {
+            BTreePage::LeafTable
+        } else {
+            BTreePage::LeafIndex
+        };
+        // Pages aren't filled completely, leaving room for page 1's header
+        let capacity = self.usable_size - 100 - 8;
+
+        let mut leaves = vec![];
+        let mut leaf: Vec<Entry> = vec![];
+        let mut size = 0;
+        let mut entries = entries.into_iter().peekable();
+        while let Some(entry) = entries.next() {
+            let cell_size = self.cell_size(&entry, page_type);
+            if size + cell_size <= capacity || leaf.is_empty() {
+                size += cell_size;
+                leaf.push(entry);
+                continue;
+            }
+
+            let separator = if is_table {
+                let key = leaf.last().unwrap().key;
+                leaves.push((
+                    Node::Leaf(leaf),
+                    Some(Entry {
+                        key,
+                        payload: vec![],
+                    }),
+                ));
+                leaf = vec![entry];
+                size = cell_size;
+                continue;
+            } else if entries.peek().is_some() {
+                entry
+            } else {
+                // The last entry can't separate this leaf from an empty one, so the one before
+                // it does instead
+                let separator = leaf.pop().unwrap();
+                leaves.push((Node::Leaf(leaf), Some(separator)));
+                leaf = vec![entry];
+                size = cell_size;
+                continue;
+            };
+            leaves.push((Node::Leaf(leaf), Some(separator)));
+            leaf = vec![];
+            size = 0;
+        }
+        leaves.push((Node::Leaf(leaf), None));
+
+        leaves
+    }
+
+    /// Groups a level of nodes under interior pages, returning the level above it
+    fn pack_interior(
+        &self,
+        level: Vec<(Node, Option<Entry>)>,
+        is_table: bool,
+    ) -> Vec<(Node, Option<Entry>)> {
+        let page_type = if is_table {
+            BTreePage::InteriorTable
+        } else {
+            BTreePage::InteriorIndex
+        };
+        let capacity = self.usable_size - 100 - 12;
+
+        let mut parents = vec![];
+        let mut children = vec![];
+        let mut separators = vec![];
+        let mut size = 0;
+        let count = level.len();
+        for (i, (node, separator)) in level.into_iter().enumerate() {
+            children.push(node);
+            let Some(separator) = separator else {
+                break;
+            };
+            let cell_size = self.cell_size(&separator, page_type);
+            // A parent needs a separator, so the last one always stays with the last two children
+            let promote = size + cell_size > capacity && !separators.is_empty() && i + 2 < count;
+            if promote {
+                parents.push((Node::Interior(children, separators), Some(separator)));
+                children = vec![];
+                separators = vec![];
+                size = 0;
+            } else {
+                size += cell_size;
+                separators.push(separator);
+            }
+        }
+        parents.push((Node::Interior(children, separators), None));
+
+        parents
+    }
+
+    fn write_node(&mut self, page: u32, node: Node, is_table: bool) {
+        let mut cells = vec![];
+        let (page_type, right_most_pointer) = match node {
+            Node::Leaf(entries) => {
+                let page_type = if is_table {
+                    BTreePage::LeafTable
+                } else {
+                    BTreePage::LeafIndex
+                };
+                for entry in entries {
+                    cells.push(self.cell(page, &entry, page_type, None));
+                }
+                (page_type, None)
+            }
+            Node::Interior(children, separators) => {
+                let page_type = if is_table {
+                    BTreePage::InteriorTable
+                } else {
+                    BTreePage::InteriorIndex
+                };
+                let mut child_pages = vec![];
+                for child in children {
+                    let child_page = self.allocate();
+                    self.set_ptrmap(child_page, 5, page);
+                    self.write_node(child_page, child, is_table);
+                    child_pages.push(child_page);
+                }
+                for (separator, &child_page) in separators.iter().zip(&child_pages) {
+                    cells.push(self.cell(page, separator, page_type, Some(child_page)));
+                }
+                (page_type, child_pages.last().copied())
+            }
+        };
+
+        let usable_size = self.usable_size;
+        let header_start = if page == 1 { 100 } else { 0 };
+        let bytes = &mut self.pages[page as usize - 1];
+        write_page(
+            &mut bytes[..usable_size],
+            header_start,
+            page_type,
+            &cells,
+            right_most_pointer,
+        );
+    }
+
+    /// A cell for `entry` on `page`, with the part of its payload that doesn't fit written to
+    /// overflow pages
+    fn cell(
+        &mut self,
+        page: u32,
+        entry: &Entry,
+        page_type: BTreePage,
+        child: Option<u32>,
+    ) -> Vec<u8> {
+        let mut cell = child.map_or(vec![], |child| child.to_be_bytes().to_vec());
+        if page_type == BTreePage::InteriorTable {
+            cell.extend(encode_varint(entry.key.unwrap()));
+            return cell;
+        }
+
+        cell.extend(encode_varint(entry.payload.len() as i64));
+        if let Some(key) = entry.key {
+            cell.extend(encode_varint(key));
+        }
+        let split = thresholds(
+            self.usable_size as u32,
+            entry.payload.len() as u64,
+            page_type,
+        )
+        .unwrap();
+        cell.extend(&entry.payload[..split.local]);
+        if split.overflow > 0 {
+            cell.extend(
+                self.write_overflow(page, &entry.payload[split.local..])
+                    .to_be_bytes(),
+            );
+        }
+
+        cell
+    }
+
+    /// Writes `payload` to a chain of overflow pages, returning the first
+    fn write_overflow(&mut self, page: u32, payload: &[u8]) -> u32 {
+        let chunks: Vec<_> = payload.chunks(self.usable_size - 4).collect();
+        let pages: Vec<u32> = chunks.iter().map(|_| self.allocate()).collect();
+        for (i, chunk) in chunks.iter().enumerate() {
+            let (page_type, parent) = if i == 0 { (3, page) } else { (4, pages[i - 1]) };
+            self.set_ptrmap(pages[i], page_type, parent);
+
+            let next = pages.get(i + 1).copied().unwrap_or(0);
+            let bytes = &mut self.pages[pages[i] as usize - 1];
+            bytes[..4].copy_from_slice(&next.to_be_bytes());
+            bytes[4..4 + chunk.len()].copy_from_slice(chunk);
+        }
+
+        pages[0]
+    }
+}
+
+/// Writes a b-tree page holding `cells`, whose header starts at `header_start`
+fn write_page(
+    page: &mut [u8],
+    header_start: usize,
+    page_type: BTreePage,
+    cells: &[Vec<u8>],
+    right_most_pointer: Option<u32>,
+) {
+    let mut pointer = header_start + 8;
+    page[header_start] = match page_type {
+        BTreePage::InteriorIndex => 2,
+        BTreePage::InteriorTable => 5,
+        BTreePage::LeafIndex => 10,
+        BTreePage::LeafTable => 13,
+    };
+    page[header_start + 3..header_start + 5].copy_from_slice(&(cells.len() as u16).to_be_bytes());
+    if let Some(right_most_pointer) = right_most_pointer {
+        page[header_start + 8..header_start + 12]
+            .copy_from_slice(&right_most_pointer.to_be_bytes());
+        pointer += 4;
+    }
+
+    let mut content_start = page.len();
+    for cell in cells {
+        content_start -= cell.len();
+        page[content_start..content_start + cell.len()].copy_from_slice(cell);
+        page[pointer..pointer + 2].copy_from_slice(&(content_start as u16).to_be_bytes());
+        pointer += 2;
+    }
+    assert!(pointer <= content_start, "cells don't fit on the page");
+    // A content area starting at 65536 is stored as 0
+    page[header_start + 5..header_start + 7]
+        .copy_from_slice(&(content_start as u32 as u16).to_be_bytes());
+}
+
+/// Encodes a record, with text in `encoding`
+pub fn encode_record(values: &[Value], encoding: Encoding) -> Vec<u8> {
+    let mut header = vec![];
+    let mut body = vec![];
+    for value in values {
+        let serial_type = match value {
+            Value::Null => 0,
+            Value::Integer(0) => 8,
+            Value::Integer(1) => 9,
+            Value::Integer(i) => {
+                let (serial_type, len) = match i {
+                    -0x80..=0x7f => (1, 1),
+                    -0x8000..=0x7fff => (2, 2),
+                    -0x80_0000..=0x7f_ffff => (3, 3),
+                    -0x8000_0000..=0x7fff_ffff => (4, 4),
+                    -0x8000_0000_0000..=0x7fff_ffff_ffff => (5, 6),
+                    _ => (6, 8),
+                };
+                body.extend(&i.to_be_bytes()[8 - len..]);
+                serial_type
+            }
+            Value::Real(r) => {
+                body.extend(r.to_be_bytes());
+                7
+            }
+            Value::Text(text) => {
+                let bytes: Vec<u8> = match encoding {
+                    Encoding::Utf8 => text.as_bytes().to_vec(),
+                    Encoding::Utf16le => text.encode_utf16().flat_map(u16::to_le_bytes).collect(),
+                    Encoding::Utf16be => text.encode_utf16().flat_map(u16::to_be_bytes).collect(),
+                };
+                let serial_type = bytes.len() as i64 * 2 + 13;
+                body.extend(bytes);
+                serial_type
+            }
+            Value::Blob(blob) => {
+                body.extend(blob);
+                blob.len() as i64 * 2 + 12
+            }
+        };
+        header.extend(encode_varint(serial_type));
+    }
+
+    // The header's size includes the varint it's stored in, which is one byte for any record
+    // with fewer than 127 bytes of serial types
+    let mut record = encode_varint(header.len() as i64 + 1);
+    assert!(record.len() == 1 && header.len() < 127);
+    record.extend(header);
+    record.extend(body);
+    record
+}
+
+pub const APPLES_SQL: &str = "CREATE TABLE apples (id INTEGER PRIMARY KEY, name TEXT, color TEXT)";
+pub const APPLES_INDEX_SQL: &str = "CREATE INDEX idx_apples_color ON apples (color)";
+
+pub fn apples(count: i64) -> Vec<Vec<Value>> {
+    let colors = ["Light Green", "Red", "Yellow", "Blush Red"];
+    (1..=count)
+        .map(|i| {
+            vec![
+                Value::Null,
+                Value::Text(format!("Apple {i:05}")),
+                Value::Text(colors[i as usize % colors.len()].to_string()),
+            ]
+        })
+        .collect()
+}
+
+pub fn with_apples(builder: DatabaseBuilder, count: i64) -> DatabaseBuilder {
+    builder.table("apples", APPLES_SQL, apples(count)).index(
+        "idx_apples_color",
+        APPLES_INDEX_SQL,
+        2,
+    )
+}
+
+/// The rows `sql` returns
+pub fn query(database: &Database, sql: &str) -> Result<Vec<Vec<Value>>> {
+    let schema = database.schema()?;
+    let (_, query) = parse_query(sql).map_err(|e| anyhow!("can't parse {sql:?}: {e}"))?;
+    let plan = plan_query(database, &schema, &query)?;
+    let rows = execute(database, &plan, &query)?.collect::<Result<Vec<_>>>()?;
+    Ok(rows)
+}
+
+/// A database file in the temporary directory, removed when it's dropped
+pub struct Fixture {
+    pub path: PathBuf,
+}
+
+impl Fixture {
+    pub fn new(name: &str, bytes: &[u8]) -> Self {
+        let path =
+            env::temp_dir().join(format!("sqlite-rust-test-{}-{name}.db", std::process::id()));
+        fs::write(&path, bytes).unwrap();
+        Fixture { path }
+    }
+
+    /// Runs the shell on the database with `command`, returning what it wrote to stdout if it
+    /// succeeded or to stderr if it didn't
+    pub fn run(&self, command: &str) -> Result<String, String> {
+        self.run_args(&[command])
+    }
+
+    /// Runs the shell on the database with `args` after its path, like a subcommand and its
+    /// options
+    pub fn run_args(&self, args: &[&str]) -> Result<String, String> {
+        let output = Command::new(env!("CARGO_BIN_EXE_sqlite-starter-rust"))
+            .arg(&self.path)
+            .args(args)
+            .env("HOME", env::temp_dir())
+            .output()
+            .unwrap();
+        if output.status.success() {
+            Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+        } else {
+            Err(String::from_utf8_lossy(&output.stderr).into_owned())
+        }
+    }
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Opens a database that the reader handles, and runs what's common to every apples fixture
+/// against it: queries through the library, the b-tree walks, and the shell's commands
+pub fn check_apples(name: &str, bytes: &[u8], count: i64, page_size: u32) -> Database {
+    let database = Database::open_bytes(bytes.to_vec()).unwrap();
+    assert_eq!(database.page_size, page_size);
+    let schema = database.schema().unwrap();
+    let names: Vec<_> = schema.iter().map(|s| s.name.as_str()).collect();
+    assert_eq!(names, ["apples", "idx_apples_color"]);
+    let (table_root, index_root) = (schema[0].root_page, schema[1].root_page);
+
+    assert_eq!(
+        query(&database, "SELECT count(*) FROM apples").unwrap(),
+        [[Value::Integer(count)]]
+    );
+    let reds: Vec<_> = (1..=count)
+        .filter(|i| i % 4 == 1)
+        .map(|i| vec![Value::Integer(i), Value::Text(format!("Apple {i:05}"))])
+        .collect();
+    assert_eq!(
+        query(&database, "SELECT id, name FROM apples WHERE color = 'Red'").unwrap(),
+        reds
+    );
+    // Answered from the index alone, which holds the color and the rowid
+    let red_colors: Vec<_> = reds
+        .iter()
+        .map(|row| vec![row[0].clone(), Value::Text("Red".into())])
+        .collect();
+    assert_eq!(
+        query(
+            &database,
+            "SELECT id, color FROM apples WHERE color = 'Red'"
+        )
+        .unwrap(),
+        red_colors
+    );
+    assert_eq!(
+        query(&database, "SELECT name FROM apples WHERE id = 7").unwrap(),
+        [[Value::Text("Apple 00007".into())]]
+    );
+
+    let red_ids = database
+        .search_index(index_root, &Value::Text("Red".into()))
+        .unwrap();
+    assert_eq!(
+        red_ids,
+        reds.iter()
+            .map(|row| row[0].as_integer().unwrap())
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(
+        database.btree_size(table_root).unwrap().entries,
+        count as u64
+    );
+    assert_eq!(
+        database.btree_size(index_root).unwrap().entries,
+        count as u64
+    );
+    assert_eq!(database.max_row_id(table_root).unwrap(), Some(count));
+    assert!(database.find_row(table_root, count).unwrap().is_some());
+    assert!(database.find_row(table_root, count + 1).unwrap().is_none());
+    assert!(database.find_row(table_root, 0).unwrap().is_none());
+    for row_id in (1..=count).step_by(7) {
+        let row = database.find_row(table_root, row_id).unwrap().unwrap();
+        assert_eq!(row.row_id, row_id);
+    }
+
+    // The exported copy holds the same rows
+    let mut exported = vec![];
+    database.write_subtree("apples", &mut exported).unwrap();
+    let copy = Database::open_bytes(exported).unwrap();
+    assert_eq!(
+        query(&copy, "SELECT count(*) FROM apples WHERE color = 'Red'").unwrap(),
+        [[Value::Integer(reds.len() as i64)]]
+    );
+
+    let fixture = Fixture::new(name, bytes);
+    assert_eq!(
+        fixture.run(".dbinfo").unwrap(),
+        format!("database page size: {page_size}\nnumber of tables: 1\n")
+    );
+    assert_eq!(fixture.run(".tables").unwrap(), "apples\n");
+    let pages = fixture.run(".pages").unwrap();
+    assert_eq!(pages.lines().count(), database.page_count as usize);
+    assert!(pages.starts_with("1: LeafTable (2 cells)\n"));
+    assert!(fixture
+        .run(".page 1")
+        .unwrap()
+        .contains("number of cells: 2\n"));
+    assert_eq!(
+        fixture.run("SELECT count(*) FROM apples").unwrap(),
+        format!("{count}\n")
+    );
+    assert!(fixture
+        .run("EXPLAIN QUERY PLAN SELECT rowid FROM apples WHERE color = 'Red'")
+        .unwrap()
+        .contains("SEARCH apples USING COVERING INDEX idx_apples_color (color=?)"));
+    assert_eq!(
+        fixture
+            .run("SELECT name FROM apples WHERE color = 'Red'")
+            .unwrap()
+            .lines()
+            .count(),
+        reds.len()
+    );
+    database
+}
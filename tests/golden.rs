@@ -0,0 +1,144 @@
+//! Queries run on databases that sqlite3 made, checked against what sqlite3 printed for them. Each
+//! case in tests/golden has the script that made its database, the database itself, and its
+//! .expected file: each query, on a line starting with "> ", followed by sqlite3's output. See
+//! generate.sh for how they're made. Queries this crate doesn't yet get right are listed in
+//! known_differences.
+
+// These run the shell, which needs the file system
+#![cfg(feature = "fs")]
+
+use std::{borrow::Cow, collections::HashSet, fs, path::Path, process::Command};
+
+/// A query from an .expected file, and what sqlite3 printed for it
+struct Golden {
+    query: String,
+    output: String,
+}
+
+fn read_expected(path: &Path) -> Vec<Golden> {
+    let expected = fs::read_to_string(path).unwrap();
+    let mut goldens: Vec<Golden> = vec![];
+    for line in expected.split_inclusive('\n') {
+        match line.strip_prefix("> ") {
+            Some(query) => goldens.push(Golden {
+                query: query.trim_end().to_string(),
+                output: String::new(),
+            }),
+            None => goldens
+                .last_mut()
+                .expect("an .expected file starts with a query")
+                .output
+                .push_str(line),
+        }
+    }
+
+    goldens
+}
+
+/// What queries print with the unicode feature, as "case: query" and the output, where its upper()
+/// and lower() convert more than sqlite3's ASCII-only ones
+const UNICODE_OUTPUTS: &[(&str, &str)] = if cfg!(feature = "unicode") {
+    &[(
+        "types: SELECT name, length(name), upper(name) FROM things ORDER BY name",
+        "|0|\nBanana|6|BANANA\napple|5|APPLE\ncafé|4|CAFÉ\ncherry|6|CHERRY\nit's|4|IT'S\n\
+         no id|5|NO ID\n",
+    )]
+} else {
+    &[]
+};
+
+/// The lines of sqlite3's .dbinfo that the shell prints too. sqlite3 goes on to show the rest of
+/// the header and pads each value into a column, where the shell puts a single space.
+const DBINFO_LABELS: &[&str] = &["database page size:", "number of tables:"];
+
+/// What a query should print, given as "case: query": what sqlite3 printed, unless a feature
+/// changes it, or only the part of it the shell prints
+fn expected_output<'a>(case_query: &str, golden: &'a Golden) -> Cow<'a, str> {
+    if golden.query == ".dbinfo" {
+        return golden
+            .output
+            .lines()
+            .filter_map(|line| {
+                let label = DBINFO_LABELS
+                    .iter()
+                    .find(|label| line.starts_with(**label))?;
+                Some(format!("{label} {}\n", line[label.len()..].trim()))
+            })
+            .collect();
+    }
+    let output = UNICODE_OUTPUTS
+        .iter()
+        .find(|(query, _)| *query == case_query)
+        .map_or(golden.output.as_str(), |(_, output)| output);
+    Cow::Borrowed(output)
+}
+
+/// The "case: query" lines of known_differences
+fn read_known_differences(dir: &Path) -> HashSet<String> {
+    fs::read_to_string(dir.join("known_differences"))
+        .unwrap()
+        .lines()
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect()
+}
+
+#[test]
+fn test_golden() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let mut cases = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "expected")
+        })
+        .collect::<Vec<_>>();
+    cases.sort();
+    assert!(!cases.is_empty(), "no cases in {}", dir.display());
+
+    let mut known_differences = read_known_differences(&dir);
+    let mut failures = vec![];
+    for case in &cases {
+        let name = case.file_stem().unwrap().to_string_lossy();
+        // Opened immutable, so that nothing is written next to the checked-in databases
+        let uri = format!("file:{}?immutable=1", case.with_extension("db").display());
+        for golden in read_expected(case) {
+            let output = Command::new(env!("CARGO_BIN_EXE_sqlite-starter-rust"))
+                .arg(&uri)
+                .arg(&golden.query)
+                .env("HOME", std::env::temp_dir())
+                .output()
+                .unwrap();
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let case_query = format!("{name}: {}", golden.query);
+            let expected = expected_output(&case_query, &golden);
+            let differs = !output.status.success() || stdout != expected;
+            let known = known_differences.remove(&case_query);
+            if known && !differs {
+                failures.push(format!(
+                    "{name}: {}\nnow matches sqlite3; remove it from known_differences\n",
+                    golden.query
+                ));
+            } else if differs && !known {
+                failures.push(format!(
+                    "{name}: {}\n--- sqlite3\n{expected}--- this crate\n{stdout}{}",
+                    golden.query,
+                    String::from_utf8_lossy(&output.stderr)
+                ));
+            }
+        }
+    }
+
+    // A stale entry would otherwise hide a difference if its query were put back
+    for difference in known_differences {
+        failures.push(format!("{difference}\nisn't a query of any case\n"));
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{} queries differ from sqlite3 unexpectedly:\n\n{}",
+        failures.len(),
+        failures.join("\n")
+    );
+}
@@ -0,0 +1,6 @@
+PRAGMA page_size = 1024;
+CREATE TABLE docs (id INTEGER PRIMARY KEY, title TEXT, body TEXT);
+CREATE INDEX idx_docs_body ON docs (body);
+WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 30)
+INSERT INTO docs SELECT i, 'doc ' || i, substr(replace(printf('%.*c', i * 200, 'x'), 'x', 'abcdefghij'), 1, i * 200) FROM n;
+INSERT INTO docs VALUES (100, 'huge', replace(printf('%.*c', 6000, 'x'), 'x', 'lorem ipsum '));
@@ -0,0 +1,7 @@
+CREATE TABLE events (id INTEGER PRIMARY KEY, name TEXT, at TEXT, day REAL, stamp INTEGER);
+INSERT INTO events VALUES (1, 'launch', '2024-02-29 13:45:30', 2460370.0729, 1709214330);
+INSERT INTO events VALUES (2, 'new year', '1999-12-31T23:59:59.999', 2451544.5, 946684799);
+INSERT INTO events VALUES (3, 'lunch', '12:30', 2460000.5, 0);
+INSERT INTO events VALUES (4, 'offset', '2023-06-15 08:00:00+05:30', 2440587.5, -86400);
+INSERT INTO events VALUES (5, 'month end', '2023-01-31', 1721425.5, 4102444800);
+INSERT INTO events VALUES (6, 'bad', 'next tuesday', NULL, NULL);
@@ -0,0 +1,6 @@
+PRAGMA page_size = 512;
+CREATE TABLE numbers (n INTEGER PRIMARY KEY, square INTEGER, word TEXT);
+CREATE INDEX idx_numbers_word ON numbers (word);
+WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 6000)
+INSERT INTO numbers SELECT i * 3, i * i, 'number ' || (i % 1000) FROM n;
+DELETE FROM numbers WHERE n % 7 = 0;
@@ -0,0 +1,28 @@
+#!/bin/sh
+# Builds each golden database from its .sql script with sqlite3, then records what sqlite3 prints
+# for each line of its .queries file in its .expected file, for tests/golden.rs to compare
+# against. Run it again after changing a script or a query, and check the results in.
+#
+#     tests/golden/generate.sh [CASE...]
+
+set -eu
+cd "$(dirname "$0")"
+
+if [ $# -eq 0 ]; then
+    set -- $(ls *.sql | sed 's/\.sql$//')
+fi
+
+for name in "$@"; do
+    rm -f "$name.db"
+    sqlite3 "$name.db" < "$name.sql"
+
+    : > "$name.expected"
+    while IFS= read -r query; do
+        case "$query" in
+        "" | --*) continue ;;
+        esac
+        printf '> %s\n' "$query" >> "$name.expected"
+        sqlite3 "$name.db" "$query" >> "$name.expected"
+    done < "$name.queries"
+    echo "$name: $(grep -c '^> ' "$name.expected") queries"
+done
@@ -0,0 +1,15 @@
+CREATE TABLE fruit (id INTEGER PRIMARY KEY, name TEXT UNIQUE, color TEXT, weight REAL, tag TEXT COLLATE NOCASE);
+CREATE INDEX idx_fruit_color ON fruit (color);
+CREATE INDEX idx_fruit_weight ON fruit (weight DESC);
+CREATE INDEX idx_fruit_tag ON fruit (tag);
+WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 3000)
+INSERT INTO fruit SELECT i, 'fruit ' || i, CASE i % 5 WHEN 0 THEN 'red' WHEN 1 THEN 'green' WHEN 2 THEN 'yellow' WHEN 3 THEN 'Red' ELSE NULL END, (i % 97) / 4.0, CASE i % 3 WHEN 0 THEN 'Sweet' WHEN 1 THEN 'SOUR' ELSE 'bitter' END FROM n;
+CREATE TABLE notes (id INTEGER PRIMARY KEY, body TEXT COLLATE RTRIM);
+INSERT INTO notes (body) VALUES ('a '), ('b'), ('b  '), ('c');
+CREATE TABLE readings (a INTEGER, b TEXT);
+CREATE INDEX idx_readings_a ON readings (a DESC);
+CREATE INDEX idx_readings_b ON readings (b COLLATE NOCASE DESC, a);
+WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 2000)
+INSERT INTO readings SELECT i % 50, CASE i % 2 WHEN 0 THEN 'v' ELSE 'V' END || (i % 7) FROM n;
+CREATE TABLE paints (id INTEGER PRIMARY KEY, color TEXT COLLATE NOCASE, shade TEXT);
+INSERT INTO paints (color, shade) VALUES ('Red', 'dark'), ('red', 'Dark'), ('Green', 'light'), ('RED', 'dark'), ('blue', 'pale'), ('Blue', 'Pale');
@@ -0,0 +1,11 @@
+CREATE TABLE readings (id INTEGER PRIMARY KEY, value REAL, label TEXT, raw);
+INSERT INTO readings VALUES (1, 2, 'stored as an integer', 2.0);
+INSERT INTO readings VALUES (2, 2.5, 'half', 2.5);
+INSERT INTO readings VALUES (3, -0.25, 'negative', -0.25);
+INSERT INTO readings VALUES (4, 1e20, 'huge', 1e20);
+INSERT INTO readings VALUES (5, 100, 'hundred', 100);
+INSERT INTO readings VALUES (6, '7', 'text that converts', '7');
+INSERT INTO readings VALUES (7, 'n/a', 'text that does not', 'n/a');
+INSERT INTO readings VALUES (8, NULL, 'none', NULL);
+INSERT INTO readings VALUES (9, 123456789012345, 'fifteen digits', 123456789012345.0);
+INSERT INTO readings VALUES (10, 1e15, 'sixteen digits', 1e15);
@@ -0,0 +1,10 @@
+CREATE TABLE "order items" ("item id" INTEGER PRIMARY KEY, [unit price] REAL, `quantity` INTEGER);
+CREATE TABLE customers (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT NOT NULL, email TEXT UNIQUE);
+CREATE VIEW big_orders AS SELECT "item id", quantity FROM "order items" WHERE quantity > 10;
+INSERT INTO "order items" VALUES (1, 2.5, 4), (2, 10.0, 12), (3, 0.99, 100);
+INSERT INTO customers (name, email) VALUES ('Ada', 'ada@example.com'), ('Grace', NULL);
+CREATE TABLE boxes (id INTEGER PRIMARY KEY, width INTEGER, height INTEGER, area INTEGER GENERATED ALWAYS AS (width * height) VIRTUAL, label TEXT AS (upper(name)) STORED, name TEXT, perimeter AS (2 * (width + height)));
+INSERT INTO boxes (width, height, name) VALUES (2, 3, 'small'), (10, 4, 'wide');
+ALTER TABLE boxes ADD COLUMN color TEXT DEFAULT 'red';
+ALTER TABLE boxes ADD COLUMN weight INTEGER DEFAULT '2';
+INSERT INTO boxes (width, height, name, color, weight) VALUES (5, 5, 'square', 'blue', 9);
@@ -0,0 +1,8 @@
+CREATE TABLE things (id INTEGER PRIMARY KEY, name TEXT, amount REAL, count INTEGER, data BLOB, note);
+INSERT INTO things VALUES (1, 'apple', 1.5, 3, x'0102', NULL);
+INSERT INTO things VALUES (2, 'Banana', -0.25, -7, x'', 'ripe');
+INSERT INTO things VALUES (3, 'cherry', 100.0, 9223372036854775807, NULL, 42);
+INSERT INTO things VALUES (4, '', 0.1, -9223372036854775808, x'ff', 4.75);
+INSERT INTO things VALUES (5, 'it''s', 1e20, 0, NULL, x'00');
+INSERT INTO things VALUES (6, 'café', 12345.678, 255, NULL, 'naïve');
+INSERT INTO things (name) VALUES ('no id');
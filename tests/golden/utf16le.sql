@@ -0,0 +1,5 @@
+PRAGMA encoding = 'UTF-16le';
+CREATE TABLE words (id INTEGER PRIMARY KEY, word TEXT, language TEXT);
+INSERT INTO words VALUES (1, 'hello', 'en'), (2, 'héllo wörld', 'de'), (3, 'こんにちは', 'ja');
+INSERT INTO words VALUES (4, '😀 emoji', 'xx'), (5, '', 'none');
+CREATE INDEX idx_words_language ON words (language);
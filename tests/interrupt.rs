@@ -0,0 +1,70 @@
+//! Interrupting a long query or index build, from another thread or a progress handler
+
+mod fixtures;
+
+use fixtures::{query, with_apples, DatabaseBuilder};
+use sqlite_starter_rust::{
+    database::Database,
+    interrupt::{InterruptedError, ProgressHandler},
+    query_parser::parse_create_index,
+    value::Value,
+};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+#[test]
+fn test_interrupt() {
+    let bytes = with_apples(DatabaseBuilder::new(4096), 100).build();
+    let mut database = Database::open_bytes(bytes).unwrap();
+    let handle = database.interrupt_handle();
+
+    handle.interrupt();
+    let err = query(&database, "SELECT name FROM apples").unwrap_err();
+    assert!(err.is::<InterruptedError>(), "{err}");
+    let (_, create_index) = parse_create_index("CREATE INDEX idx_name ON apples (name)").unwrap();
+    let err = database
+        .create_index(&create_index, "CREATE INDEX idx_name ON apples (name)")
+        .unwrap_err();
+    assert!(err.is::<InterruptedError>(), "{err}");
+    assert_eq!(database.schema().unwrap().len(), 2);
+
+    handle.reset();
+    assert_eq!(
+        query(&database, "SELECT count(*) FROM apples").unwrap(),
+        [[Value::Integer(100)]]
+    );
+}
+
+#[test]
+fn test_progress_handler() {
+    let bytes = with_apples(DatabaseBuilder::new(4096), 100).build();
+    let mut database = Database::open_bytes(bytes).unwrap();
+
+    // Stops any statement that reads more than 50 rows, as a timeout would a slow one
+    let calls = Arc::new(AtomicU64::new(0));
+    let handler_calls = calls.clone();
+    database.set_progress_handler(Some(ProgressHandler::new(10, move || {
+        handler_calls.fetch_add(1, Ordering::Relaxed) == 4
+    })));
+    let err = query(&database, "SELECT name FROM apples").unwrap_err();
+    assert!(err.is::<InterruptedError>(), "{err}");
+    assert_eq!(calls.load(Ordering::Relaxed), 5);
+
+    // Later statements aren't interrupted
+    assert_eq!(
+        query(
+            &database,
+            "SELECT count(*) FROM apples WHERE id BETWEEN 1 AND 30"
+        )
+        .unwrap(),
+        [[Value::Integer(30)]]
+    );
+
+    database.set_progress_handler(None);
+    assert_eq!(
+        query(&database, "SELECT name FROM apples").unwrap().len(),
+        100
+    );
+}
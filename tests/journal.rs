@@ -0,0 +1,121 @@
+//! Rolling back the journal a write leaves beside the database when it's cut short
+
+// These run the shell, which needs the file system
+#![cfg(all(feature = "fs", unix))]
+
+mod fixtures;
+
+use fixtures::{check_apples, with_apples, DatabaseBuilder, Fixture};
+use sqlite_starter_rust::{
+    database::Database,
+    lock::{LockLevel, LockedFile},
+    page_source::PageSource,
+};
+use std::{fs, path::PathBuf, time::Duration};
+
+/// A journal like the one SQLite leaves when it's killed part way through a write: a header
+/// padded to a 512-byte sector, then the number, original contents and checksum of each of
+/// `page_numbers`, from `original`
+fn hot_journal(original: &[u8], page_size: usize, page_numbers: &[u32]) -> Vec<u8> {
+    let nonce = 0x1234_5678u32;
+    let mut journal = vec![0xd9, 0xd5, 0x05, 0xf9, 0x20, 0xa1, 0x63, 0xd7];
+    journal.extend((page_numbers.len() as u32).to_be_bytes());
+    journal.extend(nonce.to_be_bytes());
+    journal.extend(((original.len() / page_size) as u32).to_be_bytes());
+    journal.extend(512u32.to_be_bytes());
+    journal.extend((page_size as u32).to_be_bytes());
+    journal.resize(512, 0);
+
+    for &page_number in page_numbers {
+        let start = (page_number as usize - 1) * page_size;
+        let page = &original[start..start + page_size];
+        let checksum = (1..page_size / 200 + 1)
+            .map(|i| page_size as isize - 200 * i as isize)
+            .filter(|&i| i > 0)
+            .fold(nonce, |sum, i| sum.wrapping_add(page[i as usize] as u32));
+        journal.extend(page_number.to_be_bytes());
+        journal.extend(page);
+        journal.extend(checksum.to_be_bytes());
+    }
+
+    journal
+}
+
+fn journal_path(fixture: &Fixture) -> PathBuf {
+    let mut path = fixture.path.clone().into_os_string();
+    path.push("-journal");
+
+    PathBuf::from(path)
+}
+
+#[test]
+fn test_hot_journal() {
+    let original = with_apples(DatabaseBuilder::new(1024), 500).build();
+    // Cut short after overwriting page 1 and the first of the table's pages, and adding a page
+    let mut written = original.clone();
+    written[100..1024].fill(0);
+    written[1024..2048].fill(0xff);
+    written.extend(vec![0; 1024]);
+    let fixture = Fixture::new("hot-journal", &written);
+    fs::write(
+        journal_path(&fixture),
+        hot_journal(&original, 1024, &[1, 2]),
+    )
+    .unwrap();
+
+    // Opening the database rolls it back, before the schema on page 1 is read
+    assert_eq!(fixture.run(".tables").unwrap(), "apples\n");
+    assert_eq!(fs::read(&fixture.path).unwrap(), original);
+    assert!(!journal_path(&fixture).exists());
+    check_apples("hot-journal-rolled-back", &original, 500, 1024);
+
+    // As does reading it, if it's already open
+    let database = Database::open_filename(fixture.path.to_str().unwrap()).unwrap();
+    fs::write(&fixture.path, &written).unwrap();
+    fs::write(
+        journal_path(&fixture),
+        hot_journal(&original, 1024, &[1, 2]),
+    )
+    .unwrap();
+    assert_eq!(database.schema().unwrap().len(), 2);
+    assert_eq!(fs::read(&fixture.path).unwrap(), original);
+    assert!(!journal_path(&fixture).exists());
+}
+
+#[test]
+fn test_journal_of_a_write_going_on() {
+    let original = with_apples(DatabaseBuilder::new(1024), 500).build();
+    let fixture = Fixture::new("live-journal", &original);
+    let mut journal = hot_journal(&original, 1024, &[2]);
+    fs::write(journal_path(&fixture), &journal).unwrap();
+
+    // The writer holds the reserved lock, so its journal is left alone
+    let writer = LockedFile::new(
+        fs::File::options()
+            .read(true)
+            .write(true)
+            .open(&fixture.path)
+            .unwrap(),
+    );
+    writer.lock(LockLevel::Reserved, Duration::ZERO).unwrap();
+    assert_eq!(fixture.run(".tables").unwrap(), "apples\n");
+    assert!(journal_path(&fixture).exists());
+    writer.unlock(LockLevel::None).unwrap();
+
+    // A journal whose records were never synced has nothing to put back, and one that isn't a
+    // journal at all is deleted
+    journal[8..12].fill(0);
+    fs::write(journal_path(&fixture), &journal).unwrap();
+    assert_eq!(fixture.run(".tables").unwrap(), "apples\n");
+    assert_eq!(fs::read(&fixture.path).unwrap(), original);
+    assert!(!journal_path(&fixture).exists());
+    fs::write(journal_path(&fixture), b"not a journal").unwrap();
+    assert_eq!(fixture.run(".tables").unwrap(), "apples\n");
+    assert!(!journal_path(&fixture).exists());
+
+    // A read-only connection can't roll the database back
+    fs::write(journal_path(&fixture), hot_journal(&original, 1024, &[2])).unwrap();
+    let uri = format!("file:{}?mode=ro", fixture.path.display());
+    let err = Database::open_filename(&uri).err().unwrap();
+    assert!(err.to_string().contains("readonly"), "{err}");
+}
@@ -0,0 +1,102 @@
+//! The 'localtime' and 'utc' modifiers, converting by the time zone in TZ. Each test checks for the
+//! results sqlite3 gives with the same TZ.
+
+// These run the shell, which needs the file system, and the system's time zone rules
+#![cfg(all(feature = "fs", feature = "localtime", unix))]
+
+mod fixtures;
+
+use fixtures::{with_apples, DatabaseBuilder, Fixture};
+use std::{env, process::Command};
+
+/// US Eastern time, spelt out so as not to need the system's time zone database
+const EASTERN: &str = "EST5EDT,M3.2.0,M11.1.0";
+
+/// Evaluates `expression` in the shell with TZ set to US Eastern time
+fn eastern(fixture: &Fixture, expression: &str) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_sqlite-starter-rust"))
+        .arg(&fixture.path)
+        .arg(format!("SELECT {expression} FROM apples LIMIT 1"))
+        .env("HOME", env::temp_dir())
+        .env("TZ", EASTERN)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8_lossy(&output.stdout)
+        .trim_end()
+        .to_string()
+}
+
+#[test]
+fn test_localtime() {
+    let fixture = Fixture::new(
+        "localtime",
+        &with_apples(DatabaseBuilder::new(4096), 1).build(),
+    );
+
+    for (expression, expected) in [
+        (
+            "datetime('2024-07-01 12:00:00', 'localtime')",
+            "2024-07-01 08:00:00",
+        ),
+        // Converting twice is the same as converting once
+        (
+            "datetime('2024-07-01 12:00:00', 'localtime', 'localtime')",
+            "2024-07-01 08:00:00",
+        ),
+        (
+            "datetime('2024-01-15 12:00:00', 'localtime')",
+            "2024-01-15 07:00:00",
+        ),
+        // Outside 1971 to 2037, as a year the same distance from a leap year
+        (
+            "datetime('1960-01-15 12:00:00.250', 'localtime', 'subsec')",
+            "1960-01-15 07:00:00.250",
+        ),
+        (
+            "datetime('2100-07-01 12:00', 'localtime')",
+            "2100-07-01 08:00:00",
+        ),
+        (
+            "datetime('2024-07-01 12:00:00', 'localtime', 'utc')",
+            "2024-07-01 12:00:00",
+        ),
+    ] {
+        assert_eq!(eastern(&fixture, expression), expected, "{expression}");
+    }
+}
+
+#[test]
+fn test_utc() {
+    let fixture = Fixture::new("utc", &with_apples(DatabaseBuilder::new(4096), 1).build());
+
+    for (expression, expected) in [
+        (
+            "datetime('2024-07-01 12:00:00', 'utc')",
+            "2024-07-01 16:00:00",
+        ),
+        (
+            "datetime('2024-07-01 12:00:00', 'utc', 'utc')",
+            "2024-07-01 16:00:00",
+        ),
+        // A time with a time zone is already UTC, but one from a number of seconds isn't
+        (
+            "datetime('2024-07-01 12:00:00Z', 'utc')",
+            "2024-07-01 12:00:00",
+        ),
+        (
+            "datetime(1719835200, 'unixepoch', 'utc')",
+            "2024-07-01 16:00:00",
+        ),
+        // Times skipped and repeated by daylight saving time changes
+        ("datetime('2024-03-10 02:30', 'utc')", "2024-03-10 07:30:00"),
+        ("datetime('2024-11-03 01:30', 'utc')", "2024-11-03 05:30:00"),
+        ("datetime('now', 'utc') = datetime('now')", "1"),
+    ] {
+        assert_eq!(eastern(&fixture, expression), expected, "{expression}");
+    }
+}
@@ -0,0 +1,261 @@
+//! Subqueries, compound queries, collations and mapping rows to structs, run on the apples
+//! database
+
+// These run the shell, which needs the file system
+#![cfg(feature = "fs")]
+
+mod fixtures;
+
+use anyhow::Result;
+use fixtures::{query, with_apples, DatabaseBuilder, Fixture};
+use sqlite_starter_rust::{
+    database::Database,
+    executor::execute,
+    planner::plan_query,
+    query_parser::parse_query,
+    row::{FromRow, Row},
+    value::Value,
+};
+
+#[test]
+fn test_subqueries() {
+    let bytes = with_apples(DatabaseBuilder::new(4096), 100).build();
+    let database = Database::open_bytes(bytes).unwrap();
+
+    let rows = query(
+        &database,
+        "SELECT n FROM (SELECT name AS n, color FROM apples WHERE color = 'Red') \
+         WHERE n = 'Apple 00005'",
+    )
+    .unwrap();
+    assert_eq!(rows, [[Value::Text("Apple 00005".to_string())]]);
+
+    // The subquery's rows come out in its own order, for the outer query to sort again
+    let rows = query(
+        &database,
+        "SELECT id FROM (SELECT id FROM apples WHERE id BETWEEN 3 AND 5 ORDER BY id DESC) \
+         ORDER BY id LIMIT 2",
+    )
+    .unwrap();
+    assert_eq!(rows, [[Value::Integer(3)], [Value::Integer(4)]]);
+
+    let rows = query(
+        &database,
+        "SELECT count(*) FROM apples WHERE id IN (SELECT id FROM apples WHERE color = 'Yellow')",
+    )
+    .unwrap();
+    assert_eq!(rows, [[Value::Integer(25)]]);
+
+    let err = query(
+        &database,
+        "SELECT name FROM apples WHERE id IN (SELECT id, name FROM apples)",
+    )
+    .unwrap_err();
+    assert_eq!(err.to_string(), "sub-select returns 2 columns - expected 1");
+}
+
+#[test]
+fn test_compound_queries() {
+    let bytes = with_apples(DatabaseBuilder::new(4096), 100).build();
+    let database = Database::open_bytes(bytes).unwrap();
+    let count = |sql| query(&database, sql).unwrap().len();
+
+    assert_eq!(
+        count("SELECT color FROM apples UNION ALL SELECT color FROM apples WHERE id = 1"),
+        101
+    );
+    assert_eq!(
+        count("SELECT color FROM apples UNION SELECT color FROM apples"),
+        4
+    );
+    assert_eq!(
+        count(
+            "SELECT id FROM apples WHERE color = 'Red' INTERSECT \
+             SELECT id FROM apples WHERE id BETWEEN 1 AND 10"
+        ),
+        3
+    );
+    // Left to right: the 12 Red rows after 50, then rows 1 and 2
+    assert_eq!(
+        count(
+            "SELECT id FROM apples WHERE color = 'Red' EXCEPT \
+             SELECT id FROM apples WHERE id BETWEEN 1 AND 50 UNION \
+             SELECT id FROM apples WHERE id IN (1, 2)"
+        ),
+        14
+    );
+
+    // ORDER BY and LIMIT apply to the whole compound, by heading, number or expression
+    let rows = query(
+        &database,
+        "SELECT id AS n, color FROM apples WHERE id IN (1, 2, 3) UNION \
+         SELECT id, color FROM apples WHERE id IN (3, 98, 99) ORDER BY id DESC, 2 LIMIT 4",
+    )
+    .unwrap();
+    let ids: Vec<_> = rows.iter().map(|row| row[0].clone()).collect();
+    assert_eq!(ids, [99, 98, 3, 2].map(Value::Integer));
+
+    let err = query(
+        &database,
+        "SELECT id FROM apples UNION SELECT id FROM apples ORDER BY name",
+    )
+    .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "1st ORDER BY term does not match any column in the result set"
+    );
+    let err = query(
+        &database,
+        "SELECT id FROM apples EXCEPT SELECT id, name FROM apples",
+    )
+    .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "SELECTs to the left and right of EXCEPT do not have the same number of result columns"
+    );
+}
+
+#[test]
+fn test_collations() {
+    let names = ["PIZZA", "pizza", "Salad", "salad  ", "soup"];
+    let rows = names
+        .iter()
+        .map(|name| vec![Value::Null, Value::Text(name.to_string())])
+        .collect();
+    let bytes = DatabaseBuilder::new(4096)
+        .table(
+            "foods",
+            "CREATE TABLE foods (id INTEGER PRIMARY KEY, name TEXT COLLATE NOCASE)",
+            rows,
+        )
+        .build();
+    let fixture = Fixture::new("collations", &bytes);
+    let ids = |database: &Database, sql: &str| {
+        query(database, sql)
+            .unwrap()
+            .into_iter()
+            .map(|row| row[0].as_integer().unwrap())
+            .collect::<Vec<_>>()
+    };
+
+    let database = Database::open_filename(fixture.path.to_str().unwrap()).unwrap();
+    assert_eq!(
+        ids(&database, "SELECT id FROM foods WHERE name = 'pizza'"),
+        [1, 2]
+    );
+    assert_eq!(
+        ids(
+            &database,
+            "SELECT id FROM foods WHERE name = 'salad' COLLATE RTRIM"
+        ),
+        [4]
+    );
+    assert_eq!(
+        ids(
+            &database,
+            "SELECT id FROM foods ORDER BY name COLLATE BINARY DESC, id LIMIT 3"
+        ),
+        [5, 4, 2]
+    );
+    assert!(query(
+        &database,
+        "SELECT id FROM foods WHERE name = 'x' COLLATE klingon"
+    )
+    .unwrap_err()
+    .to_string()
+    .contains("no such collation sequence: klingon"));
+
+    // The index takes the column's collation, so it's searched with NOCASE too
+    assert!(fixture
+        .run("CREATE UNIQUE INDEX idx_foods_name ON foods (name)")
+        .unwrap_err()
+        .contains("UNIQUE constraint failed: foods.name"));
+    assert!(fixture
+        .run("CREATE INDEX idx_foods_name ON foods (name)")
+        .is_ok());
+    let database = Database::open_filename(fixture.path.to_str().unwrap()).unwrap();
+    assert!(fixture
+        .run("EXPLAIN QUERY PLAN SELECT id FROM foods WHERE name = 'PIZZA'")
+        .unwrap()
+        .contains("USING COVERING INDEX idx_foods_name"));
+    assert_eq!(
+        ids(
+            &database,
+            "SELECT id FROM foods WHERE name IN ('Pizza', 'SOUP')"
+        ),
+        [1, 2, 5]
+    );
+    // A comparison with another collation can't use the index
+    assert!(!fixture
+        .run("EXPLAIN QUERY PLAN SELECT id FROM foods WHERE name = 'PIZZA' COLLATE BINARY")
+        .unwrap()
+        .contains("USING INDEX"));
+
+    let mut database = database;
+    database.create_collation("LENGTH", |a, b| a.len().cmp(&b.len()));
+    assert_eq!(
+        ids(
+            &database,
+            "SELECT id FROM foods WHERE name = 'abcde' COLLATE length"
+        ),
+        [1, 2, 3]
+    );
+}
+
+#[test]
+fn test_map_rows() {
+    #[derive(Debug, PartialEq)]
+    struct Apple {
+        id: i64,
+        name: String,
+        color: Option<String>,
+    }
+
+    impl FromRow for Apple {
+        fn from_row(row: &Row) -> Result<Self> {
+            Ok(Apple {
+                id: row.get("id")?,
+                name: row.get("name")?,
+                color: row.get("color")?,
+            })
+        }
+    }
+
+    let bytes = with_apples(DatabaseBuilder::new(4096), 10).build();
+    let database = Database::open_bytes(bytes).unwrap();
+    let schema = database.schema().unwrap();
+    let (_, query) =
+        parse_query("SELECT color, name, id FROM apples WHERE id BETWEEN 1 AND 2").unwrap();
+    let plan = plan_query(&database, &schema, &query).unwrap();
+    let rows = execute(&database, &plan, &query).unwrap();
+    assert_eq!(rows.columns(), ["color", "name", "id"]);
+
+    let apples = rows
+        .map_rows::<Apple>()
+        .collect::<Result<Vec<_>>>()
+        .unwrap();
+    assert_eq!(
+        apples,
+        [
+            Apple {
+                id: 1,
+                name: "Apple 00001".to_string(),
+                color: Some("Red".to_string()),
+            },
+            Apple {
+                id: 2,
+                name: "Apple 00002".to_string(),
+                color: Some("Yellow".to_string()),
+            },
+        ]
+    );
+
+    // A column missing from the result is an error, rather than a default
+    let (_, query) = parse_query("SELECT name, id FROM apples").unwrap();
+    let plan = plan_query(&database, &schema, &query).unwrap();
+    let mut apples = execute(&database, &plan, &query)
+        .unwrap()
+        .map_rows::<Apple>();
+    let err = apples.next().unwrap().unwrap_err();
+    assert_eq!(err.to_string(), "no such column in the result: color");
+}
@@ -0,0 +1,83 @@
+//! SAVEPOINT, RELEASE and ROLLBACK TO in the shell
+
+// These run the shell, which needs the file system
+#![cfg(feature = "fs")]
+
+mod fixtures;
+
+use fixtures::{with_apples, DatabaseBuilder, Fixture};
+use std::fs;
+
+#[test]
+fn test_savepoints() {
+    let bytes = with_apples(DatabaseBuilder::new(4096), 10).build();
+    let fixture = Fixture::new("savepoints", &bytes);
+
+    // A savepoint outside a transaction starts one, which releasing it ends
+    assert_eq!(
+        fixture.run("SAVEPOINT a; SAVEPOINT b; RELEASE b; SELECT count(*) FROM apples; COMMIT"),
+        Ok("10\n".to_string())
+    );
+    assert!(fixture
+        .run("SAVEPOINT a; SAVEPOINT b; RELEASE a; COMMIT")
+        .unwrap_err()
+        .contains("cannot commit - no transaction is active"));
+    assert_eq!(
+        fixture.run("BEGIN; SAVEPOINT a; RELEASE a; COMMIT"),
+        Ok(String::new())
+    );
+    assert!(fixture
+        .run("SAVEPOINT a; VACUUM")
+        .unwrap_err()
+        .contains("cannot VACUUM from within a transaction"));
+
+    // Rolling back to a savepoint keeps it, but not those after it
+    assert_eq!(
+        fixture.run("BEGIN; SAVEPOINT a; SAVEPOINT b; ROLLBACK TO A; RELEASE a; COMMIT"),
+        Ok(String::new())
+    );
+    assert!(fixture
+        .run("BEGIN; SAVEPOINT a; SAVEPOINT b; ROLLBACK TO a; RELEASE b")
+        .unwrap_err()
+        .contains("no such savepoint: b"));
+    assert!(fixture
+        .run("BEGIN; SAVEPOINT a; ROLLBACK; RELEASE a")
+        .unwrap_err()
+        .contains("no such savepoint: a"));
+}
+
+#[test]
+fn test_rollback_to() {
+    let bytes = with_apples(DatabaseBuilder::new(1024), 500).build();
+    let fixture = Fixture::new("rollback-to", &bytes);
+    let plan = "EXPLAIN QUERY PLAN SELECT id FROM apples WHERE name = 'Apple 00001'";
+
+    // Only what's written after the savepoint is undone, by as many savepoints as it's nested in
+    assert_eq!(
+        fixture.run(
+            "BEGIN; CREATE INDEX idx_apples_name ON apples (name); SAVEPOINT a; ANALYZE; \
+             SAVEPOINT b; CREATE INDEX idx_apples_name_id ON apples (name, id); ROLLBACK TO a; \
+             RELEASE a; COMMIT"
+        ),
+        Ok(String::new())
+    );
+    assert!(fixture
+        .run(plan)
+        .unwrap()
+        .contains("USING COVERING INDEX idx_apples_name"));
+    assert!(fixture
+        .run("SELECT count(*) FROM sqlite_stat1")
+        .unwrap_err()
+        .contains("no such table: sqlite_stat1"));
+
+    // A savepoint can be rolled back to again, and the statements after it see what's left
+    let fixture = Fixture::new("rollback-to-again", &bytes);
+    let output = fixture
+        .run(&format!(
+            "SAVEPOINT a; CREATE INDEX idx_apples_name ON apples (name); ROLLBACK TO a; {plan}; \
+             CREATE INDEX idx_apples_name ON apples (name); ROLLBACK TO a; RELEASE a"
+        ))
+        .unwrap();
+    assert!(!output.contains("idx_apples_name"), "{output}");
+    assert_eq!(fs::read(&fixture.path).unwrap(), bytes);
+}
@@ -0,0 +1,307 @@
+//! The shell itself: its subcommands, scripts read from stdin or `.read`, and what it does
+//! when a statement fails or its output is closed
+
+// These run the shell, which needs the file system
+#![cfg(feature = "fs")]
+
+mod fixtures;
+
+use fixtures::{with_apples, DatabaseBuilder, Fixture, APPLES_INDEX_SQL, APPLES_SQL};
+use sqlite_starter_rust::{
+    database::Database,
+    lock::{LockLevel, LockedFile},
+    page_source::PageSource,
+};
+use std::{
+    env, fs,
+    io::{BufRead, BufReader, Write},
+    process::{Command, Stdio},
+    time::Duration,
+};
+
+#[test]
+fn test_subcommands() {
+    let bytes = with_apples(DatabaseBuilder::new(4096), 3).build();
+    let fixture = Fixture::new("subcommands", &bytes);
+
+    assert_eq!(
+        fixture.run_args(&["tables"]).unwrap(),
+        fixture.run(".tables").unwrap()
+    );
+    assert_eq!(
+        fixture.run_args(&["dbinfo"]).unwrap(),
+        fixture.run(".dbinfo").unwrap()
+    );
+    assert_eq!(
+        fixture
+            .run_args(&["query", "SELECT id, name FROM apples", "--limit", "2"])
+            .unwrap(),
+        "id|name\n1|Apple 00001\n2|Apple 00002\n"
+    );
+    assert_eq!(
+        fixture
+            .run_args(&[
+                "query",
+                "SELECT id FROM apples",
+                "--format",
+                "tabs",
+                "--no-headers"
+            ])
+            .unwrap(),
+        "1\n2\n3\n"
+    );
+    assert_eq!(
+        fixture.run_args(&["schema", "apples"]).unwrap(),
+        format!("{APPLES_SQL};\n{APPLES_INDEX_SQL};\n")
+    );
+    assert_eq!(
+        fixture.run_args(&["dump"]).unwrap(),
+        format!(
+            "PRAGMA foreign_keys=OFF;\n\
+             BEGIN TRANSACTION;\n\
+             {APPLES_SQL};\n\
+             INSERT INTO apples VALUES(1,'Apple 00001','Red');\n\
+             INSERT INTO apples VALUES(2,'Apple 00002','Yellow');\n\
+             INSERT INTO apples VALUES(3,'Apple 00003','Blush Red');\n\
+             {APPLES_INDEX_SQL};\n\
+             COMMIT;\n"
+        )
+    );
+    assert_eq!(fixture.run_args(&["check"]).unwrap(), "ok\n");
+}
+
+#[test]
+fn test_output_closed_early() {
+    let bytes = with_apples(DatabaseBuilder::new(4096), 20_000).build();
+    let fixture = Fixture::new("output-closed-early", &bytes);
+
+    // Like piping to head: read the first row, then stop reading
+    let mut child = Command::new(env!("CARGO_BIN_EXE_sqlite-starter-rust"))
+        .arg(&fixture.path)
+        .arg("SELECT id, name, color FROM apples")
+        .env("HOME", env::temp_dir())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut first_row = String::new();
+    BufReader::new(child.stdout.take().unwrap())
+        .read_line(&mut first_row)
+        .unwrap();
+    assert_eq!(first_row, "1|Apple 00001|Red\n");
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+}
+
+#[test]
+fn test_multiple_statements() {
+    let bytes = with_apples(DatabaseBuilder::new(4096), 10).build();
+    let fixture = Fixture::new("multiple-statements", &bytes);
+    let run = |command: Option<&str>, stdin: &str| {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_sqlite-starter-rust"))
+            .arg(&fixture.path)
+            .args(command)
+            .env("HOME", env::temp_dir())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(stdin.as_bytes())
+            .unwrap();
+        let output = child.wait_with_output().unwrap();
+        (
+            String::from_utf8_lossy(&output.stdout).into_owned(),
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        )
+    };
+
+    let (stdout, stderr) = run(
+        Some("SELECT count(*) FROM apples; SELECT name FROM apples WHERE id = 3;"),
+        "",
+    );
+    assert_eq!(
+        (stdout.as_str(), stderr.as_str()),
+        ("10\nApple 00003\n", "")
+    );
+
+    // A script's statements can span lines, and the semicolon in the string doesn't end one
+    let script = "SELECT name -- the name\n  FROM apples\n  WHERE id = 1;\n\
+                  SELECT count(*) FROM apples WHERE name = 'a;b';\n.headers on\n\
+                  SELECT id FROM apples WHERE id = 2";
+    let (stdout, stderr) = run(None, script);
+    assert_eq!(
+        (stdout.as_str(), stderr.as_str()),
+        ("Apple 00001\n0\nid\n2\n", "")
+    );
+
+    // It stops at an error, saying where it was
+    let (stdout, stderr) = run(
+        None,
+        "SELECT id FROM apples LIMIT 1;\n\
+                                      SELECT nope FROM apples; SELECT id FROM apples LIMIT 1;",
+    );
+    assert_eq!(stdout, "1\n");
+    assert!(
+        stderr.starts_with("Error: statement 2 of stdin, on line 2"),
+        "{stderr}"
+    );
+    assert!(stderr.contains("no such column: nope"), "{stderr}");
+
+    // "-" reads stdin too, as when the command is left out
+    assert_eq!(
+        run(Some("-"), script),
+        ("Apple 00001\n0\nid\n2\n".to_string(), String::new())
+    );
+}
+
+#[test]
+fn test_syntax_error_mid_script() {
+    let bytes = with_apples(DatabaseBuilder::new(4096), 10).build();
+    let fixture = Fixture::new("syntax-error-mid-script", &bytes);
+    let script = "SELECT count(*) FROM apples;\n\
+                  SELECT name FROM apples\n  LIMIT 1 OFFSET 2;\n\
+                  CREATE INDEX idx_name ON apples (name);\n\
+                  SELECT id FROM apples WHERE id = 3;\n";
+    let indexed = || {
+        Database::open_filename(fixture.path.to_str().unwrap())
+            .unwrap()
+            .schema()
+            .unwrap()
+            .iter()
+            .any(|entry| entry.name == "idx_name")
+    };
+
+    // From the command line, the statements after the error aren't run
+    let err = fixture.run(&script.replace('\n', " ")).unwrap_err();
+    assert!(err.contains("near \"OFFSET\": syntax error"), "{err}");
+    assert!(!indexed());
+
+    // Nor from stdin, which exits non-zero after the output of the statements before it
+    let mut child = Command::new(env!("CARGO_BIN_EXE_sqlite-starter-rust"))
+        .args([fixture.path.to_str().unwrap(), "-"])
+        .env("HOME", env::temp_dir())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(script.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(!output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "10\n");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "Error: statement 2 of stdin, on line 2\n\n\
+         Caused by:\n    near \"OFFSET\": syntax error\n"
+    );
+    assert!(!indexed());
+
+    // Nor from .read, which says where in the script it was
+    let mut script_path = fixture.path.clone().into_os_string();
+    script_path.push(".sql");
+    fs::write(&script_path, script).unwrap();
+    let script_path = script_path.to_str().unwrap();
+    let err = fixture.run(&format!(".read {script_path}")).unwrap_err();
+    fs::remove_file(script_path).unwrap();
+    assert!(
+        err.starts_with(&format!("Error: statement 2 of {script_path}, on line 2")),
+        "{err}"
+    );
+    assert!(err.contains("near \"OFFSET\": syntax error"), "{err}");
+    assert!(!indexed());
+
+    // Without the error, they all run
+    fixture
+        .run(&script.replace(" OFFSET 2", "").replace('\n', " "))
+        .unwrap();
+    assert!(indexed());
+}
+
+#[test]
+fn test_read_script() {
+    let bytes = with_apples(DatabaseBuilder::new(4096), 10).build();
+    let fixture = Fixture::new("read-script", &bytes);
+    let script = env::temp_dir().join(format!("sqlite-rust-corpus-{}.sql", std::process::id()));
+    let read = format!(".read {}", script.display());
+
+    fs::write(
+        &script,
+        "-- setup\n.headers on\nSELECT count(*)\n  FROM apples;\nSELECT name FROM apples LIMIT 1;\n",
+    )
+    .unwrap();
+    assert_eq!(
+        fixture.run(&read),
+        Ok("COUNT(*)\n10\nname\nApple 00001\n".to_string())
+    );
+
+    // Nothing after the failing statement runs, and the error says which one it was
+    fs::write(
+        &script,
+        "SELECT count(*) FROM apples;\n\nSELECT name\n  FROM apples WHERE nope = 1;\n\
+         SELECT id FROM apples;\n",
+    )
+    .unwrap();
+    let err = fixture.run(&read).unwrap_err();
+    assert!(
+        err.contains(&format!("statement 2 of {}, on line 3", script.display())),
+        "{err}"
+    );
+    assert!(err.contains("no such column: nope"), "{err}");
+
+    fs::write(&script, ".mode nope\n").unwrap();
+    let err = fixture.run(&read).unwrap_err();
+    assert!(
+        err.contains(&format!("line 1 of {}", script.display())),
+        "{err}"
+    );
+
+    fs::write(&script, ".headers on\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_sqlite-starter-rust"))
+        .arg("--init")
+        .arg(&script)
+        .arg(&fixture.path)
+        .arg("SELECT count(*) FROM apples")
+        .env("HOME", env::temp_dir())
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "COUNT(*)\n10\n");
+    fs::remove_file(&script).unwrap();
+}
+
+#[test]
+fn test_unreadable_schema() {
+    let mut bytes = with_apples(DatabaseBuilder::new(4096), 3).build();
+    // Page 1 is no longer a table b-tree page, so sqlite_schema can't be read
+    bytes[100] = 0;
+    let fixture = Fixture::new("unreadable-schema", &bytes);
+    let err = fixture.run(".tables").unwrap_err();
+    assert!(err.contains("use --degraded to open anyway"), "{err}");
+    assert_eq!(fixture.run_args(&["--degraded", ".tables"]).unwrap(), "");
+
+    // Skipping the schema doesn't get past a lock
+    let bytes = with_apples(DatabaseBuilder::new(4096), 3).build();
+    let fixture = Fixture::new("locked-schema", &bytes);
+    let file = LockedFile::new(
+        fs::File::options()
+            .read(true)
+            .write(true)
+            .open(&fixture.path)
+            .unwrap(),
+    );
+    file.lock(LockLevel::Exclusive, Duration::ZERO).unwrap();
+    let err = fixture.run(".tables").unwrap_err();
+    assert!(err.contains("database is locked"), "{err}");
+    assert!(!err.contains("--degraded"), "{err}");
+}
@@ -0,0 +1,95 @@
+//! BEGIN, COMMIT and ROLLBACK in the shell, and statements that write within a transaction
+
+// These run the shell, which needs the file system
+#![cfg(feature = "fs")]
+
+mod fixtures;
+
+use fixtures::{with_apples, DatabaseBuilder, Fixture};
+use sqlite_starter_rust::{database::Database, query_parser::parse_create_index};
+use std::{fs, path::Path};
+
+fn journal_exists(path: &Path) -> bool {
+    let mut journal = path.to_path_buf().into_os_string();
+    journal.push("-journal");
+    Path::new(&journal).exists()
+}
+
+#[test]
+fn test_rollback() {
+    let bytes = with_apples(DatabaseBuilder::new(1024), 500).build();
+    let fixture = Fixture::new("rollback", &bytes);
+
+    // What the transaction wrote is seen within it, and undone by ROLLBACK
+    let output = fixture
+        .run(
+            "BEGIN; CREATE INDEX idx_apples_name ON apples (name); ANALYZE; \
+             EXPLAIN QUERY PLAN SELECT id FROM apples WHERE name = 'Apple 00001'; \
+             SELECT count(*) FROM sqlite_stat1; ROLLBACK",
+        )
+        .unwrap();
+    assert!(
+        output.contains("USING COVERING INDEX idx_apples_name"),
+        "{output}"
+    );
+    assert!(output.ends_with("\n2\n"), "{output}");
+    assert_eq!(fs::read(&fixture.path).unwrap(), bytes);
+    assert!(!journal_exists(&fixture.path));
+    assert!(fixture
+        .run("SELECT count(*) FROM sqlite_stat1")
+        .unwrap_err()
+        .contains("no such table: sqlite_stat1"));
+    assert!(fixture
+        .run("ROLLBACK")
+        .unwrap_err()
+        .contains("cannot rollback - no transaction is active"));
+    assert!(fixture
+        .run("BEGIN; BEGIN")
+        .unwrap_err()
+        .contains("cannot start a transaction within a transaction"));
+}
+
+#[test]
+fn test_commit() {
+    let bytes = with_apples(DatabaseBuilder::new(1024), 500).build();
+    let fixture = Fixture::new("commit", &bytes);
+    let mut database = Database::open_filename(fixture.path.to_str().unwrap()).unwrap();
+    let create_index = |database: &mut Database, sql: &str| {
+        let (_, create_index) = parse_create_index(sql).unwrap();
+        database.create_index(&create_index, sql)
+    };
+
+    // A statement that fails is undone, but what the transaction wrote before it is kept
+    database.begin().unwrap();
+    create_index(
+        &mut database,
+        "CREATE INDEX idx_apples_name ON apples (name)",
+    )
+    .unwrap();
+    assert!(create_index(
+        &mut database,
+        "CREATE UNIQUE INDEX idx_colors ON apples (color)"
+    )
+    .is_err());
+    assert!(journal_exists(&fixture.path));
+    database.commit().unwrap();
+    assert!(!journal_exists(&fixture.path));
+    assert!(!database.in_transaction());
+
+    let names: Vec<_> = database
+        .schema()
+        .unwrap()
+        .into_iter()
+        .map(|schema| schema.name)
+        .collect();
+    assert_eq!(names, ["apples", "idx_apples_color", "idx_apples_name"]);
+    assert!(fixture
+        .run("EXPLAIN QUERY PLAN SELECT id FROM apples WHERE name = 'Apple 00001'")
+        .unwrap()
+        .contains("USING COVERING INDEX idx_apples_name"));
+    assert!(database
+        .commit()
+        .unwrap_err()
+        .to_string()
+        .contains("cannot commit - no transaction is active"));
+}
@@ -0,0 +1,108 @@
+//! VACUUM and VACUUM INTO, which rebuild a database file
+
+// These run the shell, which needs the file system
+#![cfg(feature = "fs")]
+
+mod fixtures;
+
+use fixtures::{check_apples, query, with_apples, DatabaseBuilder, Fixture};
+use sqlite_starter_rust::{database::Database, value::Value};
+use std::{fs, path::PathBuf};
+
+#[test]
+fn test_vacuum() {
+    let mut bytes = with_apples(DatabaseBuilder::new(1024), 1000).build();
+    // Three free pages at the end: a freelist trunk page listing two leaves
+    let page_count = u32::from_be_bytes(bytes[28..32].try_into().unwrap());
+    let mut trunk = vec![0; 1024];
+    trunk[4..8].copy_from_slice(&2u32.to_be_bytes());
+    trunk[8..12].copy_from_slice(&(page_count + 2).to_be_bytes());
+    trunk[12..16].copy_from_slice(&(page_count + 3).to_be_bytes());
+    bytes.extend(trunk);
+    bytes.extend(vec![0; 2048]);
+    bytes[28..32].copy_from_slice(&(page_count + 3).to_be_bytes());
+    bytes[32..36].copy_from_slice(&(page_count + 1).to_be_bytes());
+    bytes[36..40].copy_from_slice(&3u32.to_be_bytes());
+    let fixture = Fixture::new("vacuum", &bytes);
+    // Opened before the VACUUM, and still reading the same file after it
+    let mut other = Database::open_filename(fixture.path.to_str().unwrap()).unwrap();
+
+    assert!(fixture
+        .run("BEGIN; VACUUM;")
+        .unwrap_err()
+        .contains("cannot VACUUM from within a transaction"));
+    // A WAL that hasn't been checkpointed would be replayed over the rebuilt database
+    let mut wal_path = fixture.path.clone().into_os_string();
+    wal_path.push("-wal");
+    fs::write(&wal_path, b"not checkpointed").unwrap();
+    assert!(fixture
+        .run("VACUUM")
+        .unwrap_err()
+        .contains("with a WAL file"));
+    fs::remove_file(&wal_path).unwrap();
+    // A rebuilt database left by a VACUUM that was cut short is replaced
+    let mut vacuum_path = fixture.path.clone().into_os_string();
+    vacuum_path.push("-vacuum");
+    fs::write(&vacuum_path, b"left over").unwrap();
+    assert_eq!(fixture.run("VACUUM").unwrap(), "");
+
+    let database = Database::open_filename(fixture.path.to_str().unwrap()).unwrap();
+    let header = database.header().unwrap();
+    assert_eq!(
+        (header.first_freelist_trunk_page, header.freelist_page_count),
+        (0, 0)
+    );
+    // Cells are packed tighter than the fixture's, so there may be fewer pages than before too
+    assert!(database.page_count <= page_count);
+    assert_eq!(
+        fs::metadata(&fixture.path).unwrap().len(),
+        database.page_count as u64 * 1024
+    );
+    let mut journal_path = fixture.path.clone().into_os_string();
+    journal_path.push("-journal");
+    assert!(!PathBuf::from(vacuum_path).exists());
+    assert!(!PathBuf::from(journal_path).exists());
+    other.refresh().unwrap();
+    assert_eq!(other.page_count, database.page_count);
+    assert_eq!(other.header().unwrap().freelist_page_count, 0);
+    check_apples("vacuumed", &fs::read(&fixture.path).unwrap(), 1000, 1024);
+    assert_eq!(
+        query(
+            &database,
+            "SELECT name FROM apples WHERE color = 'Red' LIMIT 2"
+        )
+        .unwrap(),
+        [
+            [Value::Text("Apple 00001".to_string())],
+            [Value::Text("Apple 00005".to_string())]
+        ]
+    );
+
+    let copy = Fixture::new("vacuum-into", b"");
+    fs::remove_file(&copy.path).unwrap();
+    let sql = format!("VACUUM INTO '{}'", copy.path.display());
+    assert_eq!(fixture.run(&sql).unwrap(), "");
+    assert_eq!(
+        fs::read(&copy.path).unwrap().len(),
+        database.page_count as usize * 1024
+    );
+    assert!(fixture.run(&sql).is_err());
+
+    // A copy that can't be made isn't left behind, half written, to be in the way of the next
+    let mut auto_vacuum = bytes.clone();
+    auto_vacuum[52..56].copy_from_slice(&1u32.to_be_bytes());
+    let auto_vacuum = Fixture::new("vacuum-auto", &auto_vacuum);
+    fs::remove_file(&copy.path).unwrap();
+    for _ in 0..2 {
+        let err = auto_vacuum.run(&sql).unwrap_err();
+        assert!(err.contains("Unhandled auto-vacuum database"), "{err}");
+        assert!(!copy.path.exists());
+    }
+
+    let mut database = Database::open_bytes(bytes).unwrap();
+    assert!(database
+        .vacuum()
+        .unwrap_err()
+        .to_string()
+        .contains("Unhandled VACUUM of a database that isn't a file"));
+}